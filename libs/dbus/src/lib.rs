@@ -0,0 +1,188 @@
+//! A minimal D-Bus client, enough to own a name on the system bus, answer
+//! method calls and emit signals.
+
+use std::collections::VecDeque;
+use std::env;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+
+pub use message::{Message, MessageType, ALLOW_INTERACTIVE_AUTHORIZATION, NO_REPLY_EXPECTED};
+pub use value::Value;
+
+mod message;
+mod value;
+
+const DEFAULT_SYSTEM_BUS_ADDRESS: &str = "unix:path=/var/run/dbus/system_bus_socket";
+
+extern "C" {
+    fn geteuid() -> u32;
+}
+
+pub struct Connection {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+    last_serial: u32,
+    unique_name: String,
+    /// Messages that arrived while waiting for a reply.
+    queue: VecDeque<Message>,
+}
+
+impl Connection {
+    /// Connects to the bus at `DBUS_SYSTEM_BUS_ADDRESS`, or the default one.
+    pub fn system() -> io::Result<Self> {
+        let address = env::var("DBUS_SYSTEM_BUS_ADDRESS")
+            .unwrap_or_else(|_| DEFAULT_SYSTEM_BUS_ADDRESS.to_owned());
+        Self::open(&address)
+    }
+
+    /// Connects to the first `unix:path=` entry of the bus `address`.
+    pub fn open(address: &str) -> io::Result<Self> {
+        let path = address
+            .split(';')
+            .filter_map(|entry| entry.strip_prefix("unix:"))
+            .flat_map(|options| options.split(','))
+            .find_map(|option| option.strip_prefix("path="))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    format!("No supported transport in D-Bus address '{address}'."),
+                )
+            })?;
+
+        let stream = UnixStream::connect(path)?;
+        let mut connection = Self {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+            last_serial: 0,
+            unique_name: String::new(),
+            queue: VecDeque::new(),
+        };
+
+        connection.authenticate()?;
+
+        let hello = Message::method_call(
+            "org.freedesktop.DBus",
+            "/org/freedesktop/DBus",
+            "org.freedesktop.DBus",
+            "Hello",
+        );
+        let reply = connection.call(hello)?;
+        connection.unique_name = reply
+            .body
+            .first()
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_owned();
+
+        Ok(connection)
+    }
+
+    /// `EXTERNAL` authentication, the bus checks the credentials of the socket.
+    fn authenticate(&mut self) -> io::Result<()> {
+        // SAFETY: `geteuid` has no preconditions and can't fail.
+        #[allow(unsafe_code)]
+        let uid = unsafe { geteuid() };
+        let hex_uid: String = uid
+            .to_string()
+            .bytes()
+            .map(|b| format!("{b:02x}"))
+            .collect();
+
+        self.writer.write_all(b"\0")?;
+        self.writer
+            .write_all(format!("AUTH EXTERNAL {hex_uid}\r\n").as_bytes())?;
+
+        let mut line = String::new();
+        self.reader.read_line(&mut line)?;
+        if !line.starts_with("OK ") {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("D-Bus authentication failed: {}", line.trim_end()),
+            ));
+        }
+
+        self.writer.write_all(b"BEGIN\r\n")
+    }
+
+    /// Name the bus assigned to this connection.
+    pub fn unique_name(&self) -> &str {
+        &self.unique_name
+    }
+
+    /// Sends `message` and returns its serial.
+    pub fn send(&mut self, mut message: Message) -> io::Result<u32> {
+        self.last_serial += 1;
+        message.serial = self.last_serial;
+        self.writer.write_all(&message.encode())?;
+        Ok(message.serial)
+    }
+
+    /// Sends `message` and waits for its reply, error replies are returned as `Err`.
+    pub fn call(&mut self, message: Message) -> io::Result<Message> {
+        let serial = self.send(message)?;
+        loop {
+            let reply = Message::read_from(&mut self.reader)?;
+            if reply.reply_serial != Some(serial) {
+                self.queue.push_back(reply);
+                continue;
+            }
+
+            if reply.message_type == MessageType::Error {
+                return Err(io::Error::new(io::ErrorKind::Other, reply.error_text()));
+            }
+
+            return Ok(reply);
+        }
+    }
+
+    /// Blocks until the next incoming message.
+    pub fn receive(&mut self) -> io::Result<Message> {
+        match self.queue.pop_front() {
+            Some(message) => Ok(message),
+            None => Message::read_from(&mut self.reader),
+        }
+    }
+
+    /// Takes the well-known `name`, fails if another connection owns it.
+    pub fn request_name(&mut self, name: &str) -> io::Result<()> {
+        const DO_NOT_QUEUE: u32 = 0x4;
+        const PRIMARY_OWNER: u32 = 1;
+
+        let request = Message::method_call(
+            "org.freedesktop.DBus",
+            "/org/freedesktop/DBus",
+            "org.freedesktop.DBus",
+            "RequestName",
+        )
+        .with_body(vec![Value::from(name), Value::UInt32(DO_NOT_QUEUE)]);
+
+        let reply = self.call(request)?;
+        if reply.body.first().and_then(Value::as_u32) != Some(PRIMARY_OWNER) {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("D-Bus name '{name}' is already owned."),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Uid of the process that owns the bus name `sender`.
+    pub fn unix_user(&mut self, sender: &str) -> io::Result<u32> {
+        let request = Message::method_call(
+            "org.freedesktop.DBus",
+            "/org/freedesktop/DBus",
+            "org.freedesktop.DBus",
+            "GetConnectionUnixUser",
+        )
+        .with_body(vec![Value::from(sender)]);
+
+        let reply = self.call(request)?;
+        reply.body.first().and_then(Value::as_u32).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("No uid was returned for '{sender}'."),
+            )
+        })
+    }
+}
@@ -0,0 +1,271 @@
+use crate::value::{Decoder, Encoder, Value};
+use std::io::{self, Read};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MessageType {
+    MethodCall = 1,
+    MethodReturn = 2,
+    Error = 3,
+    Signal = 4,
+}
+
+/// The caller doesn't wait for a reply, so none should be sent.
+pub const NO_REPLY_EXPECTED: u8 = 0x1;
+/// Lets the callee ask the user for credentials(i.e. through polkit).
+pub const ALLOW_INTERACTIVE_AUTHORIZATION: u8 = 0x4;
+
+const PATH: u8 = 1;
+const INTERFACE: u8 = 2;
+const MEMBER: u8 = 3;
+const ERROR_NAME: u8 = 4;
+const REPLY_SERIAL: u8 = 5;
+const DESTINATION: u8 = 6;
+const SENDER: u8 = 7;
+const SIGNATURE: u8 = 8;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub message_type: MessageType,
+    pub flags: u8,
+    /// Assigned by the connection when the message is sent.
+    pub serial: u32,
+    pub path: Option<String>,
+    pub interface: Option<String>,
+    pub member: Option<String>,
+    pub error_name: Option<String>,
+    pub reply_serial: Option<u32>,
+    pub destination: Option<String>,
+    pub sender: Option<String>,
+    pub body: Vec<Value>,
+}
+
+impl Message {
+    fn new(message_type: MessageType) -> Self {
+        Self {
+            message_type,
+            flags: 0,
+            serial: 0,
+            path: None,
+            interface: None,
+            member: None,
+            error_name: None,
+            reply_serial: None,
+            destination: None,
+            sender: None,
+            body: Vec::new(),
+        }
+    }
+
+    pub fn method_call(destination: &str, path: &str, interface: &str, member: &str) -> Self {
+        Self {
+            destination: Some(destination.to_owned()),
+            path: Some(path.to_owned()),
+            interface: Some(interface.to_owned()),
+            member: Some(member.to_owned()),
+            ..Self::new(MessageType::MethodCall)
+        }
+    }
+
+    pub fn signal(path: &str, interface: &str, member: &str) -> Self {
+        Self {
+            path: Some(path.to_owned()),
+            interface: Some(interface.to_owned()),
+            member: Some(member.to_owned()),
+            ..Self::new(MessageType::Signal)
+        }
+    }
+
+    pub fn method_return(call: &Message) -> Self {
+        Self {
+            reply_serial: Some(call.serial),
+            destination: call.sender.clone(),
+            ..Self::new(MessageType::MethodReturn)
+        }
+    }
+
+    pub fn error(call: &Message, name: &str, text: &str) -> Self {
+        Self {
+            error_name: Some(name.to_owned()),
+            reply_serial: Some(call.serial),
+            destination: call.sender.clone(),
+            body: vec![Value::from(text)],
+            ..Self::new(MessageType::Error)
+        }
+    }
+
+    pub fn with_body(mut self, body: Vec<Value>) -> Self {
+        self.body = body;
+        self
+    }
+
+    pub fn with_flags(mut self, flags: u8) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    /// Text of error replies.
+    pub fn error_text(&self) -> String {
+        let name = self.error_name.as_deref().unwrap_or_default();
+        match self.body.first().and_then(Value::as_str) {
+            Some(text) => format!("{name}: {text}"),
+            None => name.to_owned(),
+        }
+    }
+
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut body = Encoder::new();
+        for value in &self.body {
+            body.put(value);
+        }
+
+        let mut fields = Vec::new();
+        let mut field = |code, value| {
+            fields.push(Value::Struct(vec![
+                Value::Byte(code),
+                Value::Variant(Box::new(value)),
+            ]))
+        };
+        if let Some(path) = &self.path {
+            field(PATH, Value::ObjectPath(path.clone()));
+        }
+        if let Some(interface) = &self.interface {
+            field(INTERFACE, Value::from(interface.as_str()));
+        }
+        if let Some(member) = &self.member {
+            field(MEMBER, Value::from(member.as_str()));
+        }
+        if let Some(error_name) = &self.error_name {
+            field(ERROR_NAME, Value::from(error_name.as_str()));
+        }
+        if let Some(reply_serial) = self.reply_serial {
+            field(REPLY_SERIAL, Value::UInt32(reply_serial));
+        }
+        if let Some(destination) = &self.destination {
+            field(DESTINATION, Value::from(destination.as_str()));
+        }
+        if !self.body.is_empty() {
+            let signature = self.body.iter().map(Value::signature).collect();
+            field(SIGNATURE, Value::Signature(signature));
+        }
+
+        let mut header = Encoder::new();
+        header
+            .buf
+            .extend_from_slice(&[b'l', self.message_type as u8, self.flags, 1]);
+        header.put(&Value::UInt32(body.buf.len() as u32));
+        header.put(&Value::UInt32(self.serial));
+        header.put(&Value::Array(String::from("(yv)"), fields));
+        header.pad(8);
+
+        header.buf.extend_from_slice(&body.buf);
+        header.buf
+    }
+
+    pub(crate) fn read_from(reader: &mut impl Read) -> io::Result<Self> {
+        // Fixed part of the header and the length of the header fields.
+        let mut buf = vec![0; 16];
+        reader.read_exact(&mut buf)?;
+
+        let big_endian = match buf[0] {
+            b'l' => false,
+            b'B' => true,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Invalid D-Bus message endianness.",
+                ))
+            }
+        };
+        let read_u32 = |bytes: &[u8]| {
+            let bytes: [u8; 4] = bytes.try_into().unwrap();
+            if big_endian {
+                u32::from_be_bytes(bytes)
+            } else {
+                u32::from_le_bytes(bytes)
+            }
+        };
+
+        let message_type = match buf[1] {
+            1 => MessageType::MethodCall,
+            2 => MessageType::MethodReturn,
+            3 => MessageType::Error,
+            4 => MessageType::Signal,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Unknown D-Bus message type.",
+                ))
+            }
+        };
+        let body_len = read_u32(&buf[4..8]) as usize;
+        let fields_len = read_u32(&buf[12..16]) as usize;
+        let body_start = (16 + fields_len + 7) / 8 * 8;
+
+        buf.resize(body_start + body_len, 0);
+        reader.read_exact(&mut buf[16..])?;
+
+        let mut message = Self::new(message_type);
+        message.flags = buf[2];
+        message.serial = read_u32(&buf[8..12]);
+
+        let mut decoder = Decoder::new(&buf[..16 + fields_len], big_endian);
+        decoder.get_all("yyyyuu")?;
+        let mut signature = String::new();
+        if let Value::Array(_, fields) = decoder.get("a(yv)")? {
+            for field in fields {
+                let Value::Struct(field) = field else {
+                    continue;
+                };
+                let (Some(Value::Byte(code)), Some(Value::Variant(value))) =
+                    (field.first(), field.get(1))
+                else {
+                    continue;
+                };
+
+                let text = value.as_str().map(str::to_owned);
+                match *code {
+                    PATH => message.path = text,
+                    INTERFACE => message.interface = text,
+                    MEMBER => message.member = text,
+                    ERROR_NAME => message.error_name = text,
+                    REPLY_SERIAL => message.reply_serial = value.as_u32(),
+                    DESTINATION => message.destination = text,
+                    SENDER => message.sender = text,
+                    SIGNATURE => signature = text.unwrap_or_default(),
+                    _ => {}
+                }
+            }
+        }
+
+        message.body = Decoder::new(&buf[body_start..], big_endian).get_all(&signature)?;
+
+        Ok(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_and_read() {
+        let mut call = Message::method_call(
+            "org.lpm.PackageManager",
+            "/org/lpm/PackageManager",
+            "org.lpm.PackageManager1",
+            "Install",
+        )
+        .with_body(vec![Value::string_array(["htop", "vim"])]);
+        call.serial = 3;
+
+        let bytes = call.encode();
+        assert_eq!(Message::read_from(&mut bytes.as_slice()).unwrap(), call);
+
+        let mut reply = Message::error(&call, "org.lpm.Error", "Package not found");
+        reply.serial = 4;
+        let reply = Message::read_from(&mut reply.encode().as_slice()).unwrap();
+        assert_eq!(reply.message_type, MessageType::Error);
+        assert_eq!(reply.reply_serial, Some(3));
+        assert_eq!(reply.error_text(), "org.lpm.Error: Package not found");
+    }
+}
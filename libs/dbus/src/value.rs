@@ -0,0 +1,425 @@
+use std::io;
+
+/// A value in the D-Bus wire format.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Byte(u8),
+    Bool(bool),
+    Int16(i16),
+    UInt16(u16),
+    Int32(i32),
+    UInt32(u32),
+    Int64(i64),
+    UInt64(u64),
+    Double(f64),
+    Str(String),
+    ObjectPath(String),
+    Signature(String),
+    /// Signature of the elements and the elements.
+    Array(String, Vec<Value>),
+    Struct(Vec<Value>),
+    DictEntry(Box<Value>, Box<Value>),
+    Variant(Box<Value>),
+}
+
+impl Value {
+    pub fn signature(&self) -> String {
+        match self {
+            Value::Byte(_) => String::from("y"),
+            Value::Bool(_) => String::from("b"),
+            Value::Int16(_) => String::from("n"),
+            Value::UInt16(_) => String::from("q"),
+            Value::Int32(_) => String::from("i"),
+            Value::UInt32(_) => String::from("u"),
+            Value::Int64(_) => String::from("x"),
+            Value::UInt64(_) => String::from("t"),
+            Value::Double(_) => String::from("d"),
+            Value::Str(_) => String::from("s"),
+            Value::ObjectPath(_) => String::from("o"),
+            Value::Signature(_) => String::from("g"),
+            Value::Array(signature, _) => format!("a{signature}"),
+            Value::Struct(fields) => format!(
+                "({})",
+                fields.iter().map(Value::signature).collect::<String>()
+            ),
+            Value::DictEntry(key, value) => format!("{{{}{}}}", key.signature(), value.signature()),
+            Value::Variant(_) => String::from("v"),
+        }
+    }
+
+    /// Array of strings, the most common argument type of lpm's methods.
+    pub fn string_array<S: Into<String>>(values: impl IntoIterator<Item = S>) -> Self {
+        Value::Array(
+            String::from("s"),
+            values.into_iter().map(|t| Value::Str(t.into())).collect(),
+        )
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::Str(t) | Value::ObjectPath(t) | Value::Signature(t) => Some(t),
+            _ => None,
+        }
+    }
+
+    pub fn as_u32(&self) -> Option<u32> {
+        match self {
+            Value::UInt32(t) => Some(*t),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(t) => Some(*t),
+            _ => None,
+        }
+    }
+
+    /// Elements of arrays and fields of structs.
+    pub fn as_slice(&self) -> Option<&[Value]> {
+        match self {
+            Value::Array(_, values) | Value::Struct(values) => Some(values),
+            _ => None,
+        }
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::Str(value.to_owned())
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::Str(value)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Bool(value)
+    }
+}
+
+impl From<u32> for Value {
+    fn from(value: u32) -> Self {
+        Value::UInt32(value)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Value::Int64(value)
+    }
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Splits the first complete type off `signature`.
+pub(crate) fn split_type(signature: &str) -> io::Result<(&str, &str)> {
+    let bytes = signature.as_bytes();
+    let mut end = 0;
+    // Array markers are prefixes of the element type.
+    while bytes.get(end) == Some(&b'a') {
+        end += 1;
+    }
+
+    match bytes.get(end) {
+        Some(b'(') | Some(b'{') => {
+            let mut depth = 0;
+            for (i, c) in bytes.iter().enumerate().skip(end) {
+                match c {
+                    b'(' | b'{' => depth += 1,
+                    b')' | b'}' => depth -= 1,
+                    _ => {}
+                }
+                if depth == 0 {
+                    return Ok(signature.split_at(i + 1));
+                }
+            }
+            Err(invalid_data("Unbalanced D-Bus signature."))
+        }
+        Some(_) => Ok(signature.split_at(end + 1)),
+        None => Err(invalid_data("Incomplete D-Bus signature.")),
+    }
+}
+
+fn alignment(signature: &str) -> usize {
+    match signature.as_bytes().first() {
+        Some(b'n') | Some(b'q') => 2,
+        Some(b'b') | Some(b'i') | Some(b'u') | Some(b's') | Some(b'o') | Some(b'a') => 4,
+        Some(b'x') | Some(b't') | Some(b'd') | Some(b'(') | Some(b'{') => 8,
+        _ => 1,
+    }
+}
+
+/// Writes values with the padding of the wire format, which is relative to
+/// the start of the buffer.
+pub(crate) struct Encoder {
+    pub(crate) buf: Vec<u8>,
+}
+
+impl Encoder {
+    pub(crate) fn new() -> Self {
+        Self { buf: Vec::new() }
+    }
+
+    pub(crate) fn pad(&mut self, align: usize) {
+        while self.buf.len() % align != 0 {
+            self.buf.push(0);
+        }
+    }
+
+    fn put_u32(&mut self, value: u32) {
+        self.pad(4);
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn put_str(&mut self, value: &str) {
+        self.put_u32(value.len() as u32);
+        self.buf.extend_from_slice(value.as_bytes());
+        self.buf.push(0);
+    }
+
+    fn put_signature(&mut self, value: &str) {
+        self.buf.push(value.len() as u8);
+        self.buf.extend_from_slice(value.as_bytes());
+        self.buf.push(0);
+    }
+
+    pub(crate) fn put(&mut self, value: &Value) {
+        match value {
+            Value::Byte(t) => self.buf.push(*t),
+            Value::Bool(t) => self.put_u32(*t as u32),
+            Value::Int16(t) => {
+                self.pad(2);
+                self.buf.extend_from_slice(&t.to_le_bytes());
+            }
+            Value::UInt16(t) => {
+                self.pad(2);
+                self.buf.extend_from_slice(&t.to_le_bytes());
+            }
+            Value::Int32(t) => {
+                self.pad(4);
+                self.buf.extend_from_slice(&t.to_le_bytes());
+            }
+            Value::UInt32(t) => self.put_u32(*t),
+            Value::Int64(t) => {
+                self.pad(8);
+                self.buf.extend_from_slice(&t.to_le_bytes());
+            }
+            Value::UInt64(t) => {
+                self.pad(8);
+                self.buf.extend_from_slice(&t.to_le_bytes());
+            }
+            Value::Double(t) => {
+                self.pad(8);
+                self.buf.extend_from_slice(&t.to_le_bytes());
+            }
+            Value::Str(t) | Value::ObjectPath(t) => self.put_str(t),
+            Value::Signature(t) => self.put_signature(t),
+            Value::Array(signature, values) => {
+                self.put_u32(0);
+                let len_at = self.buf.len() - 4;
+                // The length doesn't include the padding before the first element.
+                self.pad(alignment(signature));
+                let start = self.buf.len();
+                for value in values {
+                    self.put(value);
+                }
+                let len = (self.buf.len() - start) as u32;
+                self.buf[len_at..len_at + 4].copy_from_slice(&len.to_le_bytes());
+            }
+            Value::Struct(fields) => {
+                self.pad(8);
+                for field in fields {
+                    self.put(field);
+                }
+            }
+            Value::DictEntry(key, value) => {
+                self.pad(8);
+                self.put(key);
+                self.put(value);
+            }
+            Value::Variant(value) => {
+                self.put_signature(&value.signature());
+                self.put(value);
+            }
+        }
+    }
+}
+
+pub(crate) struct Decoder<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    big_endian: bool,
+}
+
+macro_rules! get_number {
+    ($self: expr, $ty: ty) => {{
+        const SIZE: usize = std::mem::size_of::<$ty>();
+        $self.align(SIZE)?;
+        let bytes: [u8; SIZE] = $self.take(SIZE)?.try_into().unwrap();
+        if $self.big_endian {
+            <$ty>::from_be_bytes(bytes)
+        } else {
+            <$ty>::from_le_bytes(bytes)
+        }
+    }};
+}
+
+impl<'a> Decoder<'a> {
+    pub(crate) fn new(buf: &'a [u8], big_endian: bool) -> Self {
+        Self {
+            buf,
+            pos: 0,
+            big_endian,
+        }
+    }
+
+    pub(crate) fn align(&mut self, align: usize) -> io::Result<()> {
+        let pos = (self.pos + align - 1) / align * align;
+        if pos > self.buf.len() {
+            return Err(invalid_data("Truncated D-Bus message."));
+        }
+        self.pos = pos;
+        Ok(())
+    }
+
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        let bytes = self
+            .buf
+            .get(self.pos..self.pos + len)
+            .ok_or_else(|| invalid_data("Truncated D-Bus message."))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn get_string(&mut self, len: usize) -> io::Result<String> {
+        let bytes = self.take(len + 1)?;
+        String::from_utf8(bytes[..len].to_vec()).map_err(|_| invalid_data("Invalid UTF-8 string."))
+    }
+
+    /// Reads one value of the complete type `signature`.
+    pub(crate) fn get(&mut self, signature: &str) -> io::Result<Value> {
+        let value = match signature.as_bytes().first() {
+            Some(b'y') => Value::Byte(self.take(1)?[0]),
+            Some(b'b') => Value::Bool(get_number!(self, u32) != 0),
+            Some(b'n') => Value::Int16(get_number!(self, i16)),
+            Some(b'q') => Value::UInt16(get_number!(self, u16)),
+            Some(b'i') => Value::Int32(get_number!(self, i32)),
+            Some(b'u') => Value::UInt32(get_number!(self, u32)),
+            Some(b'x') => Value::Int64(get_number!(self, i64)),
+            Some(b't') => Value::UInt64(get_number!(self, u64)),
+            Some(b'd') => Value::Double(get_number!(self, f64)),
+            Some(b's') => {
+                let len = get_number!(self, u32) as usize;
+                Value::Str(self.get_string(len)?)
+            }
+            Some(b'o') => {
+                let len = get_number!(self, u32) as usize;
+                Value::ObjectPath(self.get_string(len)?)
+            }
+            Some(b'g') => {
+                let len = self.take(1)?[0] as usize;
+                Value::Signature(self.get_string(len)?)
+            }
+            Some(b'v') => {
+                let len = self.take(1)?[0] as usize;
+                let signature = self.get_string(len)?;
+                let (inner, rest) = split_type(&signature)?;
+                if !rest.is_empty() {
+                    return Err(invalid_data("Variant holds more than one value."));
+                }
+                Value::Variant(Box::new(self.get(inner)?))
+            }
+            Some(b'a') => {
+                let len = get_number!(self, u32) as usize;
+                let element = &signature[1..];
+                self.align(alignment(element))?;
+                let end = self.pos + len;
+                let mut values = Vec::new();
+                while self.pos < end {
+                    values.push(self.get(element)?);
+                }
+                Value::Array(element.to_owned(), values)
+            }
+            Some(b'(') => {
+                self.align(8)?;
+                let mut fields = Vec::new();
+                let mut rest = &signature[1..signature.len() - 1];
+                while !rest.is_empty() {
+                    let (field, tail) = split_type(rest)?;
+                    fields.push(self.get(field)?);
+                    rest = tail;
+                }
+                Value::Struct(fields)
+            }
+            Some(b'{') => {
+                self.align(8)?;
+                let (key, rest) = split_type(&signature[1..signature.len() - 1])?;
+                Value::DictEntry(Box::new(self.get(key)?), Box::new(self.get(rest)?))
+            }
+            _ => return Err(invalid_data("Unsupported D-Bus type.")),
+        };
+
+        Ok(value)
+    }
+
+    /// Reads consecutive values of all the types in `signature`.
+    pub(crate) fn get_all(&mut self, mut signature: &str) -> io::Result<Vec<Value>> {
+        let mut values = Vec::new();
+        while !signature.is_empty() {
+            let (current, rest) = split_type(signature)?;
+            values.push(self.get(current)?);
+            signature = rest;
+        }
+        Ok(values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let values = vec![
+            Value::Byte(7),
+            Value::Str(String::from("htop")),
+            Value::Int64(-1),
+            Value::string_array(["a", "bc"]),
+            Value::Array(
+                String::from("{sv}"),
+                vec![Value::DictEntry(
+                    Box::new(Value::from("name")),
+                    Box::new(Value::Variant(Box::new(Value::from(":1.42")))),
+                )],
+            ),
+            Value::Struct(vec![Value::Bool(true), Value::UInt32(3)]),
+        ];
+
+        let mut encoder = Encoder::new();
+        for value in &values {
+            encoder.put(value);
+        }
+
+        let signature: String = values.iter().map(Value::signature).collect();
+        assert_eq!(signature, "ysxasa{sv}(bu)");
+
+        let mut decoder = Decoder::new(&encoder.buf, false);
+        assert_eq!(decoder.get_all(&signature).unwrap(), values);
+    }
+
+    #[test]
+    fn test_split_type() {
+        assert_eq!(split_type("a(ss)u").unwrap(), ("a(ss)", "u"));
+        assert_eq!(split_type("a{sv}").unwrap(), ("a{sv}", ""));
+        assert_eq!(split_type("sb").unwrap(), ("s", "b"));
+        assert!(split_type("(ss").is_err());
+    }
+}
@@ -0,0 +1,504 @@
+//! Ed25519 signature verification (RFC 8032) without dependencies, for the
+//! repository indexes signed with the trusted keys of lpm.
+//!
+//! Only verification is here, lpm never holds a secret key. Nothing that is
+//! verified is secret either, so the arithmetic doesn't try to run in
+//! constant time.
+
+use hash::sha512;
+
+pub const PUBLIC_KEY_LEN: usize = 32;
+pub const SIGNATURE_LEN: usize = 64;
+
+const MASK: u64 = (1 << 51) - 1;
+
+/// Element of the field of integers modulo 2^255 - 19, as five 51 bit limbs.
+#[derive(Clone, Copy)]
+struct Fe([u64; 5]);
+
+const ZERO: Fe = Fe([0, 0, 0, 0, 0]);
+const ONE: Fe = Fe([1, 0, 0, 0, 0]);
+/// `2 * d`, `d` being the constant of the curve equation.
+const D2: Fe = Fe([
+    0x69b9426b2f159,
+    0x35050762add7a,
+    0x3cf44c0038052,
+    0x6738cc7407977,
+    0x2406d9dc56dff,
+]);
+const D: Fe = Fe([
+    0x34dca135978a3,
+    0x1a8283b156ebd,
+    0x5e7a26001c029,
+    0x739c663a03cbb,
+    0x52036cee2b6ff,
+]);
+/// A square root of -1.
+const SQRT_M1: Fe = Fe([
+    0x61b274a0ea0b0,
+    0xd5a5fc8f189d,
+    0x7ef5e9cbd0c60,
+    0x78595a6804c9e,
+    0x2b8324804fc1d,
+]);
+/// p - 2, the exponent of the inverse.
+const P_MINUS_2: [u8; 32] = {
+    let mut bytes = [0xff; 32];
+    bytes[0] = 0xeb;
+    bytes[31] = 0x7f;
+    bytes
+};
+/// (p - 5) / 8, the exponent of the square root.
+const P_MINUS_5_DIV_8: [u8; 32] = {
+    let mut bytes = [0xff; 32];
+    bytes[0] = 0xfd;
+    bytes[31] = 0x0f;
+    bytes
+};
+
+/// Order of the base point, little endian.
+const L: [u8; 32] = [
+    0xed, 0xd3, 0xf5, 0x5c, 0x1a, 0x63, 0x12, 0x58, 0xd6, 0x9c, 0xf7, 0xa2, 0xde, 0xf9, 0xde, 0x14,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x10,
+];
+
+impl Fe {
+    /// Ignores the top bit, which is the sign of `x` in encoded points.
+    fn from_bytes(bytes: &[u8; 32]) -> Fe {
+        let load = |i: usize| {
+            let mut word = [0; 8];
+            word.copy_from_slice(&bytes[i..i + 8]);
+            u64::from_le_bytes(word)
+        };
+
+        Fe([
+            load(0) & MASK,
+            (load(6) >> 3) & MASK,
+            (load(12) >> 6) & MASK,
+            (load(19) >> 1) & MASK,
+            (load(24) >> 12) & MASK,
+        ])
+    }
+
+    /// The canonical encoding, fully reduced.
+    fn to_bytes(self) -> [u8; 32] {
+        let mut limbs = self.carry().0;
+
+        // Adds 19 to find out whether the value is at least p, which is the
+        // case if that carries into bit 255.
+        let mut q = (limbs[0] + 19) >> 51;
+        for limb in &limbs[1..] {
+            q = (limb + q) >> 51;
+        }
+        limbs[0] += 19 * q;
+        for i in 0..4 {
+            limbs[i + 1] += limbs[i] >> 51;
+            limbs[i] &= MASK;
+        }
+        limbs[4] &= MASK;
+
+        let mut bytes = [0; 32];
+        let (mut acc, mut bits, mut i) = (0u128, 0, 0);
+        for limb in limbs {
+            acc |= (limb as u128) << bits;
+            bits += 51;
+            while bits >= 8 {
+                bytes[i] = acc as u8;
+                acc >>= 8;
+                bits -= 8;
+                i += 1;
+            }
+        }
+        bytes[i] = acc as u8;
+        bytes
+    }
+
+    /// Brings the limbs back to 51 bits, plus a small carry on the first.
+    fn carry(self) -> Fe {
+        let l = self.0;
+        let carries = [l[0] >> 51, l[1] >> 51, l[2] >> 51, l[3] >> 51, l[4] >> 51];
+        Fe([
+            (l[0] & MASK) + carries[4] * 19,
+            (l[1] & MASK) + carries[0],
+            (l[2] & MASK) + carries[1],
+            (l[3] & MASK) + carries[2],
+            (l[4] & MASK) + carries[3],
+        ])
+    }
+
+    fn add(self, other: Fe) -> Fe {
+        let (a, b) = (self.0, other.0);
+        Fe([
+            a[0] + b[0],
+            a[1] + b[1],
+            a[2] + b[2],
+            a[3] + b[3],
+            a[4] + b[4],
+        ])
+        .carry()
+    }
+
+    /// Adds 16 * p first, so that the limbs can't underflow.
+    fn sub(self, other: Fe) -> Fe {
+        const P16_FIRST: u64 = 16 * ((1 << 51) - 19);
+        const P16: u64 = 16 * MASK;
+
+        let (a, b) = (self.0, other.0);
+        Fe([
+            a[0] + P16_FIRST - b[0],
+            a[1] + P16 - b[1],
+            a[2] + P16 - b[2],
+            a[3] + P16 - b[3],
+            a[4] + P16 - b[4],
+        ])
+        .carry()
+    }
+
+    fn neg(self) -> Fe {
+        ZERO.sub(self)
+    }
+
+    fn mul(self, other: Fe) -> Fe {
+        let a = self.0.map(|t| t as u128);
+        let b = other.0.map(|t| t as u128);
+        // 2^255 = 19, so the limbs that overflow wrap around times 19.
+        let b19 = b.map(|t| t * 19);
+
+        let mut c = [
+            a[0] * b[0] + a[4] * b19[1] + a[3] * b19[2] + a[2] * b19[3] + a[1] * b19[4],
+            a[1] * b[0] + a[0] * b[1] + a[4] * b19[2] + a[3] * b19[3] + a[2] * b19[4],
+            a[2] * b[0] + a[1] * b[1] + a[0] * b[2] + a[4] * b19[3] + a[3] * b19[4],
+            a[3] * b[0] + a[2] * b[1] + a[1] * b[2] + a[0] * b[3] + a[4] * b19[4],
+            a[4] * b[0] + a[3] * b[1] + a[2] * b[2] + a[1] * b[3] + a[0] * b[4],
+        ];
+        for i in 0..4 {
+            c[i + 1] += c[i] >> 51;
+            c[i] &= MASK as u128;
+        }
+        c[0] += (c[4] >> 51) * 19;
+        c[4] &= MASK as u128;
+        c[1] += c[0] >> 51;
+        c[0] &= MASK as u128;
+
+        Fe(c.map(|t| t as u64))
+    }
+
+    fn square(self) -> Fe {
+        self.mul(self)
+    }
+
+    /// `self` to the power of the little endian `exponent`.
+    fn pow(self, exponent: &[u8; 32]) -> Fe {
+        let mut result = ONE;
+        for i in (0..256).rev() {
+            result = result.square();
+            if (exponent[i / 8] >> (i % 8)) & 1 == 1 {
+                result = result.mul(self);
+            }
+        }
+        result
+    }
+
+    fn invert(self) -> Fe {
+        self.pow(&P_MINUS_2)
+    }
+
+    fn is_negative(self) -> bool {
+        self.to_bytes()[0] & 1 == 1
+    }
+
+    fn equals(self, other: Fe) -> bool {
+        self.to_bytes() == other.to_bytes()
+    }
+}
+
+/// Point of the curve in extended coordinates, x = X/Z, y = Y/Z, xy = T/Z.
+#[derive(Clone, Copy)]
+struct Point {
+    x: Fe,
+    y: Fe,
+    z: Fe,
+    t: Fe,
+}
+
+const IDENTITY: Point = Point {
+    x: ZERO,
+    y: ONE,
+    z: ONE,
+    t: ZERO,
+};
+
+const BASE: Point = Point {
+    x: Fe([
+        0x62d608f25d51a,
+        0x412a4b4f6592a,
+        0x75b7171a4b31d,
+        0x1ff60527118fe,
+        0x216936d3cd6e5,
+    ]),
+    y: Fe([
+        0x6666666666658,
+        0x4cccccccccccc,
+        0x1999999999999,
+        0x3333333333333,
+        0x6666666666666,
+    ]),
+    z: ONE,
+    t: Fe([
+        0x68ab3a5b7dda3,
+        0xeea2a5eadbb,
+        0x2af8df483c27e,
+        0x332b375274732,
+        0x67875f0fd78b7,
+    ]),
+};
+
+impl Point {
+    /// Decodes a point as described in RFC 8032 5.1.3, `None` if `bytes`
+    /// isn't the canonical encoding of one.
+    fn decode(bytes: &[u8; 32]) -> Option<Point> {
+        let y = Fe::from_bytes(bytes);
+        let x_sign = bytes[31] >> 7 == 1;
+        let mut y_bytes = *bytes;
+        y_bytes[31] &= 0x7f;
+        if y.to_bytes() != y_bytes {
+            return None;
+        }
+
+        // x^2 = (y^2 - 1) / (d y^2 + 1)
+        let y2 = y.square();
+        let u = y2.sub(ONE);
+        let v = D.mul(y2).add(ONE);
+        let v3 = v.square().mul(v);
+        let v7 = v3.square().mul(v);
+        let mut x = u.mul(v3).mul(u.mul(v7).pow(&P_MINUS_5_DIV_8));
+
+        let vx2 = v.mul(x.square());
+        if !vx2.equals(u) {
+            if !vx2.equals(u.neg()) {
+                return None;
+            }
+            x = x.mul(SQRT_M1);
+        }
+
+        if x_sign && x.equals(ZERO) {
+            return None;
+        }
+        if x.is_negative() != x_sign {
+            x = x.neg();
+        }
+
+        Some(Point {
+            x,
+            y,
+            z: ONE,
+            t: x.mul(y),
+        })
+    }
+
+    fn encode(&self) -> [u8; 32] {
+        let z_inverse = self.z.invert();
+        let x = self.x.mul(z_inverse);
+        let mut bytes = self.y.mul(z_inverse).to_bytes();
+        bytes[31] |= (x.is_negative() as u8) << 7;
+        bytes
+    }
+
+    /// Complete for this curve, so it doubles as well.
+    fn add(&self, other: &Point) -> Point {
+        let a = self.y.sub(self.x).mul(other.y.sub(other.x));
+        let b = self.y.add(self.x).mul(other.y.add(other.x));
+        let c = self.t.mul(D2).mul(other.t);
+        let d = self.z.add(self.z).mul(other.z);
+        let (e, f, g, h) = (b.sub(a), d.sub(c), d.add(c), b.add(a));
+
+        Point {
+            x: e.mul(f),
+            y: g.mul(h),
+            z: f.mul(g),
+            t: e.mul(h),
+        }
+    }
+
+    fn neg(&self) -> Point {
+        Point {
+            x: self.x.neg(),
+            t: self.t.neg(),
+            ..*self
+        }
+    }
+
+    /// `self` times the little endian `scalar`.
+    fn mul(&self, scalar: &[u8; 32]) -> Point {
+        let mut result = IDENTITY;
+        for i in (0..256).rev() {
+            result = result.add(&result);
+            if (scalar[i / 8] >> (i % 8)) & 1 == 1 {
+                result = result.add(self);
+            }
+        }
+        result
+    }
+}
+
+/// Whether the little endian `scalar` is below `L`.
+fn is_canonical_scalar(scalar: &[u8; 32]) -> bool {
+    for (s, l) in scalar.iter().zip(L).rev() {
+        if *s != l {
+            return *s < l;
+        }
+    }
+    false
+}
+
+/// The little endian `input` modulo `L`, reduced a bit at a time.
+fn reduce_scalar(input: &[u8; 64]) -> [u8; 32] {
+    let l: [u64; 4] = limbs_of(&L);
+    let mut r = [0u64; 4];
+
+    for i in (0..512).rev() {
+        // r = 2r + bit, which stays below 2^254 as r < L.
+        let mut carry = (input[i / 8] >> (i % 8)) as u64 & 1;
+        for limb in &mut r {
+            let next = *limb >> 63;
+            *limb = (*limb << 1) | carry;
+            carry = next;
+        }
+
+        let is_at_least_l = r.iter().rev().zip(l.iter().rev()).find(|(a, b)| a != b);
+        if is_at_least_l.map_or(true, |(a, b)| a > b) {
+            let mut borrow = 0;
+            for (limb, l) in r.iter_mut().zip(l) {
+                let (value, first) = limb.overflowing_sub(l);
+                let (value, second) = value.overflowing_sub(borrow);
+                *limb = value;
+                borrow = (first || second) as u64;
+            }
+        }
+    }
+
+    let mut bytes = [0; 32];
+    for (chunk, limb) in bytes.chunks_exact_mut(8).zip(r) {
+        chunk.copy_from_slice(&limb.to_le_bytes());
+    }
+    bytes
+}
+
+fn limbs_of(bytes: &[u8; 32]) -> [u64; 4] {
+    let mut limbs = [0; 4];
+    for (limb, chunk) in limbs.iter_mut().zip(bytes.chunks_exact(8)) {
+        let mut word = [0; 8];
+        word.copy_from_slice(chunk);
+        *limb = u64::from_le_bytes(word);
+    }
+    limbs
+}
+
+/// Whether `signature` is a valid signature of `message` by `public_key`.
+pub fn verify(
+    public_key: &[u8; PUBLIC_KEY_LEN],
+    message: &[u8],
+    signature: &[u8; SIGNATURE_LEN],
+) -> bool {
+    let mut r = [0; 32];
+    r.copy_from_slice(&signature[..32]);
+    let mut s = [0; 32];
+    s.copy_from_slice(&signature[32..]);
+
+    if !is_canonical_scalar(&s) {
+        return false;
+    }
+    let Some(a) = Point::decode(public_key) else {
+        return false;
+    };
+
+    let mut input = Vec::with_capacity(64 + message.len());
+    input.extend_from_slice(&r);
+    input.extend_from_slice(public_key);
+    input.extend_from_slice(message);
+    let h = reduce_scalar(&sha512::digest(&input));
+
+    // R = [s]B - [h]A
+    BASE.mul(&s).add(&a.neg().mul(&h)).encode() == r
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from_hex<const N: usize>(hex: &str) -> [u8; N] {
+        let mut bytes = [0; N];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).unwrap();
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_verify() {
+        // Test vectors 1 and 2 of RFC 8032.
+        let public_key =
+            from_hex("d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a");
+        let signature = from_hex("e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e065224901555fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b");
+        assert!(verify(&public_key, b"", &signature));
+        assert!(!verify(&public_key, b"\0", &signature));
+
+        let public_key =
+            from_hex("3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c");
+        let signature = from_hex("92a009a9f0d4cab8720e820b5f642540a2b27b5416503f8fb3762223ebdb69da085ac1e43e15996e458f3613d0f11d8c387b2eaeb4302aeeb00d291612bb0c00");
+        assert!(verify(&public_key, b"\x72", &signature));
+
+        let mut tampered = signature;
+        tampered[0] ^= 1;
+        assert!(!verify(&public_key, b"\x72", &tampered));
+
+        // Same signature with L added to s, which is malleable unless
+        // rejected.
+        let mut s = [0u16; 32];
+        let mut carry = 0;
+        for (i, t) in s.iter_mut().enumerate() {
+            let sum = signature[32 + i] as u16 + L[i] as u16 + carry;
+            *t = sum & 0xff;
+            carry = sum >> 8;
+        }
+        let mut malleated = signature;
+        for (i, t) in s.iter().enumerate() {
+            malleated[32 + i] = *t as u8;
+        }
+        assert!(!verify(&public_key, b"\x72", &malleated));
+    }
+
+    #[test]
+    fn test_reduce_scalar() {
+        let mut l_plus_5 = [0; 64];
+        l_plus_5[..32].copy_from_slice(&L);
+        l_plus_5[0] += 5;
+        let mut five = [0; 32];
+        five[0] = 5;
+        assert_eq!(reduce_scalar(&l_plus_5), five);
+
+        assert!(is_canonical_scalar(&five));
+        assert!(!is_canonical_scalar(&L));
+    }
+
+    #[test]
+    fn test_point_encoding() {
+        let base = BASE.encode();
+        assert_eq!(
+            base,
+            from_hex::<32>("5866666666666666666666666666666666666666666666666666666666666666")
+        );
+        assert_eq!(Point::decode(&base).unwrap().encode(), base);
+
+        let mut l = L;
+        assert_eq!(BASE.mul(&l).encode(), IDENTITY.encode());
+        l[0] -= 1;
+        assert_eq!(BASE.mul(&l).encode(), BASE.neg().encode());
+
+        // y = p, not canonical.
+        let mut non_canonical = [0xff; 32];
+        non_canonical[0] = 0xed;
+        non_canonical[31] = 0x7f;
+        assert!(Point::decode(&non_canonical).is_none());
+    }
+}
@@ -0,0 +1,351 @@
+//! Minimal ELF reader. Only parses what lpm needs: the file header and the
+//! `DT_NEEDED`/`DT_SONAME` entries of the dynamic section.
+
+use std::{
+    fs::File,
+    io::{self, Read},
+    path::Path,
+};
+
+const MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
+
+const SHT_DYNAMIC: u32 = 6;
+
+const DT_NULL: u64 = 0;
+const DT_NEEDED: u64 = 1;
+const DT_SONAME: u64 = 14;
+const DT_RPATH: u64 = 15;
+const DT_RUNPATH: u64 = 29;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Class {
+    Elf32,
+    Elf64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Endian {
+    Little,
+    Big,
+}
+
+#[derive(Debug)]
+pub struct Elf {
+    pub class: Class,
+    pub endian: Endian,
+    /// `e_machine` of the header.
+    pub machine: u16,
+    /// Libraries required by the file(`DT_NEEDED`).
+    pub needed: Vec<String>,
+    /// Name the library is linked with(`DT_SONAME`).
+    pub soname: Option<String>,
+    /// Library search paths embedded in the file(`DT_RUNPATH` or `DT_RPATH`).
+    pub runpath: Vec<String>,
+}
+
+pub fn is_elf(data: &[u8]) -> bool {
+    data.starts_with(&MAGIC)
+}
+
+impl Elf {
+    /// Reads the file at `path`, returns `None` if it's not an ELF file.
+    pub fn read(path: &Path) -> io::Result<Option<Self>> {
+        let mut file = File::open(path)?;
+
+        let mut magic = [0; 4];
+        if file.read_exact(&mut magic).is_err() || !is_elf(&magic) {
+            return Ok(None);
+        }
+
+        let mut data = magic.to_vec();
+        file.read_to_end(&mut data)?;
+
+        Self::parse(&data).map(Some)
+    }
+
+    pub fn parse(data: &[u8]) -> io::Result<Self> {
+        if !is_elf(data) {
+            return Err(invalid_data("Not an ELF file."));
+        }
+
+        let class = match data.get(4) {
+            Some(1) => Class::Elf32,
+            Some(2) => Class::Elf64,
+            _ => return Err(invalid_data("Invalid ELF class.")),
+        };
+
+        let endian = match data.get(5) {
+            Some(1) => Endian::Little,
+            Some(2) => Endian::Big,
+            _ => return Err(invalid_data("Invalid ELF data encoding.")),
+        };
+
+        let reader = Reader {
+            data,
+            class,
+            endian,
+        };
+
+        let mut elf = Self {
+            class,
+            endian,
+            machine: reader.u16(18)?,
+            needed: Vec::new(),
+            soname: None,
+            runpath: Vec::new(),
+        };
+
+        let (sh_offset, sh_entry_size, sh_count) = match class {
+            Class::Elf32 => (
+                reader.u32(0x20)? as u64,
+                reader.u16(0x2e)?,
+                reader.u16(0x30)?,
+            ),
+            Class::Elf64 => (reader.u64(0x28)?, reader.u16(0x3a)?, reader.u16(0x3c)?),
+        };
+
+        for i in 0..sh_count as u64 {
+            let section = reader.section(entry_offset(sh_offset, i, sh_entry_size as u64)?)?;
+            if section.kind != SHT_DYNAMIC {
+                continue;
+            }
+
+            let strtab = reader.section(entry_offset(
+                sh_offset,
+                section.link as u64,
+                sh_entry_size as u64,
+            )?)?;
+            elf.read_dynamic_section(&reader, &section, &strtab)?;
+        }
+
+        Ok(elf)
+    }
+
+    fn read_dynamic_section(
+        &mut self,
+        reader: &Reader,
+        dynamic: &Section,
+        strtab: &Section,
+    ) -> io::Result<()> {
+        let entry_size = match self.class {
+            Class::Elf32 => 8,
+            Class::Elf64 => 16,
+        };
+
+        for i in 0..dynamic.size / entry_size {
+            let offset = entry_offset(dynamic.offset, i, entry_size)?;
+            let (tag, value) = match self.class {
+                Class::Elf32 => (
+                    reader.u32(offset)? as u64,
+                    reader.u32(add_offset(offset, 4)?)? as u64,
+                ),
+                Class::Elf64 => (reader.u64(offset)?, reader.u64(add_offset(offset, 8)?)?),
+            };
+
+            match tag {
+                DT_NULL => break,
+                DT_NEEDED => self
+                    .needed
+                    .push(reader.string(add_offset(strtab.offset, value)?)?),
+                DT_SONAME => self.soname = Some(reader.string(add_offset(strtab.offset, value)?)?),
+                DT_RPATH | DT_RUNPATH => self.runpath.extend(
+                    reader
+                        .string(add_offset(strtab.offset, value)?)?
+                        .split(':')
+                        .filter(|t| !t.is_empty())
+                        .map(String::from),
+                ),
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+struct Section {
+    kind: u32,
+    offset: u64,
+    size: u64,
+    link: u32,
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    class: Class,
+    endian: Endian,
+}
+
+impl Reader<'_> {
+    fn bytes<const N: usize>(&self, offset: u64) -> io::Result<[u8; N]> {
+        let start = usize::try_from(offset).map_err(|_| out_of_bounds())?;
+        self.data
+            .get(start..start.checked_add(N).ok_or_else(out_of_bounds)?)
+            .map(|t| t.try_into().unwrap())
+            .ok_or_else(out_of_bounds)
+    }
+
+    fn u16(&self, offset: u64) -> io::Result<u16> {
+        let bytes = self.bytes(offset)?;
+        Ok(match self.endian {
+            Endian::Little => u16::from_le_bytes(bytes),
+            Endian::Big => u16::from_be_bytes(bytes),
+        })
+    }
+
+    fn u32(&self, offset: u64) -> io::Result<u32> {
+        let bytes = self.bytes(offset)?;
+        Ok(match self.endian {
+            Endian::Little => u32::from_le_bytes(bytes),
+            Endian::Big => u32::from_be_bytes(bytes),
+        })
+    }
+
+    fn u64(&self, offset: u64) -> io::Result<u64> {
+        let bytes = self.bytes(offset)?;
+        Ok(match self.endian {
+            Endian::Little => u64::from_le_bytes(bytes),
+            Endian::Big => u64::from_be_bytes(bytes),
+        })
+    }
+
+    /// Reads the NUL terminated string at `offset`.
+    fn string(&self, offset: u64) -> io::Result<String> {
+        let start = usize::try_from(offset).map_err(|_| out_of_bounds())?;
+        let rest = self.data.get(start..).ok_or_else(out_of_bounds)?;
+        let end = rest
+            .iter()
+            .position(|t| *t == 0)
+            .ok_or_else(out_of_bounds)?;
+
+        Ok(String::from_utf8_lossy(&rest[..end]).into_owned())
+    }
+
+    fn section(&self, offset: u64) -> io::Result<Section> {
+        let field = |delta| add_offset(offset, delta);
+        Ok(match self.class {
+            Class::Elf32 => Section {
+                kind: self.u32(field(0x4)?)?,
+                offset: self.u32(field(0x10)?)? as u64,
+                size: self.u32(field(0x14)?)? as u64,
+                link: self.u32(field(0x18)?)?,
+            },
+            Class::Elf64 => Section {
+                kind: self.u32(field(0x4)?)?,
+                offset: self.u64(field(0x18)?)?,
+                size: self.u64(field(0x20)?)?,
+                link: self.u32(field(0x28)?)?,
+            },
+        })
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn out_of_bounds() -> io::Error {
+    invalid_data("ELF offset is out of bounds.")
+}
+
+/// Offsets come from the file, so they may overflow.
+fn add_offset(offset: u64, delta: u64) -> io::Result<u64> {
+    offset.checked_add(delta).ok_or_else(out_of_bounds)
+}
+
+/// Offset of the `index`th entry of a table at `offset`.
+fn entry_offset(offset: u64, index: u64, entry_size: u64) -> io::Result<u64> {
+    index
+        .checked_mul(entry_size)
+        .and_then(|t| offset.checked_add(t))
+        .ok_or_else(out_of_bounds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a little endian ELF64 file with a dynamic section that
+    /// references a string table.
+    fn build_elf64(machine: u16, dynamic: &[(u64, &str)]) -> Vec<u8> {
+        let mut strtab = vec![0];
+        let mut entries = Vec::new();
+        for (tag, value) in dynamic {
+            entries.push((*tag, strtab.len() as u64));
+            strtab.extend_from_slice(value.as_bytes());
+            strtab.push(0);
+        }
+
+        let mut dynamic = Vec::new();
+        for (tag, value) in entries {
+            dynamic.extend_from_slice(&tag.to_le_bytes());
+            dynamic.extend_from_slice(&value.to_le_bytes());
+        }
+        dynamic.extend_from_slice(&[0; 16]);
+
+        let strtab_offset = 64;
+        let dynamic_offset = strtab_offset + strtab.len();
+        let sh_offset = dynamic_offset + dynamic.len();
+
+        let mut data = vec![0; 64];
+        data[..4].copy_from_slice(&MAGIC);
+        data[4] = 2;
+        data[5] = 1;
+        data[18..20].copy_from_slice(&machine.to_le_bytes());
+        data[0x28..0x30].copy_from_slice(&(sh_offset as u64).to_le_bytes());
+        data[0x3a..0x3c].copy_from_slice(&64u16.to_le_bytes());
+        data[0x3c..0x3e].copy_from_slice(&3u16.to_le_bytes());
+
+        data.extend_from_slice(&strtab);
+        data.extend_from_slice(&dynamic);
+
+        let section = |kind: u32, offset: usize, size: usize, link: u32| {
+            let mut header = vec![0; 64];
+            header[0x4..0x8].copy_from_slice(&kind.to_le_bytes());
+            header[0x18..0x20].copy_from_slice(&(offset as u64).to_le_bytes());
+            header[0x20..0x28].copy_from_slice(&(size as u64).to_le_bytes());
+            header[0x28..0x2c].copy_from_slice(&link.to_le_bytes());
+            header
+        };
+
+        data.extend(section(0, 0, 0, 0));
+        data.extend(section(3, strtab_offset, strtab.len(), 0));
+        data.extend(section(SHT_DYNAMIC, dynamic_offset, dynamic.len(), 1));
+
+        data
+    }
+
+    #[test]
+    fn test_parse_dynamic_section() {
+        let data = build_elf64(
+            62,
+            &[
+                (DT_SONAME, "libfoo.so.1"),
+                (DT_NEEDED, "libc.so.6"),
+                (DT_NEEDED, "libm.so.6"),
+                (DT_RUNPATH, "$ORIGIN/../lib:/opt/foo/lib"),
+            ],
+        );
+
+        let elf = Elf::parse(&data).unwrap();
+        assert_eq!(elf.class, Class::Elf64);
+        assert_eq!(elf.endian, Endian::Little);
+        assert_eq!(elf.machine, 62);
+        assert_eq!(elf.soname.as_deref(), Some("libfoo.so.1"));
+        assert_eq!(elf.needed, vec!["libc.so.6", "libm.so.6"]);
+        assert_eq!(elf.runpath, vec!["$ORIGIN/../lib", "/opt/foo/lib"]);
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!(Elf::parse(b"#!/bin/sh").is_err());
+
+        let data = build_elf64(62, &[(DT_NEEDED, "libc.so.6")]);
+        assert!(Elf::parse(&data[..100]).is_err());
+
+        // Section header offset that overflows once the entries are added.
+        let mut data = build_elf64(62, &[(DT_NEEDED, "libc.so.6")]);
+        data[0x28..0x30].copy_from_slice(&(u64::MAX - 2).to_le_bytes());
+        assert!(Elf::parse(&data).is_err());
+    }
+}
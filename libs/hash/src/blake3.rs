@@ -0,0 +1,351 @@
+const OUT_LEN: usize = 32;
+const BLOCK_LEN: usize = 64;
+const CHUNK_LEN: usize = 1024;
+/// Enough for 2^54 chunks, far more than any input has.
+const MAX_DEPTH: usize = 54;
+
+const CHUNK_START: u32 = 1 << 0;
+const CHUNK_END: u32 = 1 << 1;
+const PARENT: u32 = 1 << 2;
+const ROOT: u32 = 1 << 3;
+
+const IV: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+const MSG_PERMUTATION: [usize; 16] = [2, 6, 3, 10, 7, 0, 4, 13, 1, 11, 12, 5, 9, 14, 15, 8];
+
+fn g(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize, mx: u32, my: u32) {
+    state[a] = state[a].wrapping_add(state[b]).wrapping_add(mx);
+    state[d] = (state[d] ^ state[a]).rotate_right(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_right(12);
+    state[a] = state[a].wrapping_add(state[b]).wrapping_add(my);
+    state[d] = (state[d] ^ state[a]).rotate_right(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_right(7);
+}
+
+fn round(state: &mut [u32; 16], m: &[u32; 16]) {
+    // Columns
+    g(state, 0, 4, 8, 12, m[0], m[1]);
+    g(state, 1, 5, 9, 13, m[2], m[3]);
+    g(state, 2, 6, 10, 14, m[4], m[5]);
+    g(state, 3, 7, 11, 15, m[6], m[7]);
+    // Diagonals
+    g(state, 0, 5, 10, 15, m[8], m[9]);
+    g(state, 1, 6, 11, 12, m[10], m[11]);
+    g(state, 2, 7, 8, 13, m[12], m[13]);
+    g(state, 3, 4, 9, 14, m[14], m[15]);
+}
+
+fn compress(
+    chaining_value: &[u32; 8],
+    block_words: &[u32; 16],
+    counter: u64,
+    block_len: u32,
+    flags: u32,
+) -> [u32; 16] {
+    let mut state = [
+        chaining_value[0],
+        chaining_value[1],
+        chaining_value[2],
+        chaining_value[3],
+        chaining_value[4],
+        chaining_value[5],
+        chaining_value[6],
+        chaining_value[7],
+        IV[0],
+        IV[1],
+        IV[2],
+        IV[3],
+        counter as u32,
+        (counter >> 32) as u32,
+        block_len,
+        flags,
+    ];
+
+    let mut block = *block_words;
+    for i in 0..7 {
+        round(&mut state, &block);
+        if i < 6 {
+            let mut permuted = [0; 16];
+            for (word, index) in permuted.iter_mut().zip(MSG_PERMUTATION) {
+                *word = block[index];
+            }
+            block = permuted;
+        }
+    }
+
+    for i in 0..8 {
+        state[i] ^= state[i + 8];
+        state[i + 8] ^= chaining_value[i];
+    }
+
+    state
+}
+
+fn first_8_words(compression_output: [u32; 16]) -> [u32; 8] {
+    let mut words = [0; 8];
+    words.copy_from_slice(&compression_output[..8]);
+    words
+}
+
+/// Little endian words of `block`, zero padded to a whole block.
+fn words_of(block: &[u8]) -> [u32; 16] {
+    let mut padded = [0; BLOCK_LEN];
+    padded[..block.len()].copy_from_slice(block);
+
+    let mut words = [0; 16];
+    for (word, bytes) in words.iter_mut().zip(padded.chunks_exact(4)) {
+        *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    }
+    words
+}
+
+/// Last compression of a node, which is done with `ROOT` for the root node
+/// and without it for the others.
+struct Output {
+    input_chaining_value: [u32; 8],
+    block_words: [u32; 16],
+    counter: u64,
+    block_len: u32,
+    flags: u32,
+}
+
+impl Output {
+    fn chaining_value(&self) -> [u32; 8] {
+        first_8_words(compress(
+            &self.input_chaining_value,
+            &self.block_words,
+            self.counter,
+            self.block_len,
+            self.flags,
+        ))
+    }
+
+    fn root_hash(&self) -> [u8; OUT_LEN] {
+        let words = compress(
+            &self.input_chaining_value,
+            &self.block_words,
+            0,
+            self.block_len,
+            self.flags | ROOT,
+        );
+
+        let mut hash = [0; OUT_LEN];
+        for (bytes, word) in hash.chunks_exact_mut(4).zip(words) {
+            bytes.copy_from_slice(&word.to_le_bytes());
+        }
+        hash
+    }
+}
+
+/// `chunk` up to its last block, which is left to the returned output.
+fn chunk_output(chunk: &[u8], chunk_counter: u64) -> Output {
+    let mut chaining_value = IV;
+    let mut blocks = chunk.chunks(BLOCK_LEN).peekable();
+    let mut start_flag = CHUNK_START;
+
+    // Empty inputs are a single empty block.
+    let mut last_block: &[u8] = &[];
+    while let Some(block) = blocks.next() {
+        if blocks.peek().is_none() {
+            last_block = block;
+            break;
+        }
+
+        chaining_value = first_8_words(compress(
+            &chaining_value,
+            &words_of(block),
+            chunk_counter,
+            BLOCK_LEN as u32,
+            start_flag,
+        ));
+        start_flag = 0;
+    }
+
+    Output {
+        input_chaining_value: chaining_value,
+        block_words: words_of(last_block),
+        counter: chunk_counter,
+        block_len: last_block.len() as u32,
+        flags: start_flag | CHUNK_END,
+    }
+}
+
+fn parent_output(left_child_cv: &[u32; 8], right_child_cv: &[u32; 8]) -> Output {
+    let mut block_words = [0; 16];
+    block_words[..8].copy_from_slice(left_child_cv);
+    block_words[8..].copy_from_slice(right_child_cv);
+
+    Output {
+        input_chaining_value: IV,
+        block_words,
+        counter: 0,
+        block_len: BLOCK_LEN as u32,
+        flags: PARENT,
+    }
+}
+
+/// Hash of `input`, the default 32 bytes of it.
+pub fn digest(input: &[u8]) -> [u8; OUT_LEN] {
+    // Chaining values of the complete subtrees on the left, whose sizes are
+    // the set bits of the number of chunks so far.
+    let mut cv_stack = [[0; 8]; MAX_DEPTH];
+    let mut cv_stack_len = 0;
+
+    let mut chunks = input.chunks(CHUNK_LEN).peekable();
+    let mut chunk_counter = 0;
+    let mut output = chunk_output(&[], 0);
+    while let Some(chunk) = chunks.next() {
+        output = chunk_output(chunk, chunk_counter);
+        if chunks.peek().is_none() {
+            break;
+        }
+
+        // Merges the subtrees that this chunk completes.
+        let mut cv = output.chaining_value();
+        chunk_counter += 1;
+        let mut total_chunks = chunk_counter;
+        while total_chunks & 1 == 0 {
+            cv_stack_len -= 1;
+            cv = parent_output(&cv_stack[cv_stack_len], &cv).chaining_value();
+            total_chunks >>= 1;
+        }
+        cv_stack[cv_stack_len] = cv;
+        cv_stack_len += 1;
+    }
+
+    while cv_stack_len > 0 {
+        cv_stack_len -= 1;
+        output = parent_output(&cv_stack[cv_stack_len], &output.chaining_value());
+    }
+
+    output.root_hash()
+}
+
+/// Same as `digest`, for inputs that are fed in pieces instead of being held in
+/// memory at once.
+pub struct Hasher {
+    cv_stack: [[u32; 8]; MAX_DEPTH],
+    cv_stack_len: usize,
+    chunk: [u8; CHUNK_LEN],
+    chunk_len: usize,
+    chunk_counter: u64,
+}
+
+impl Default for Hasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Hasher {
+    pub const fn new() -> Self {
+        Self {
+            cv_stack: [[0; 8]; MAX_DEPTH],
+            cv_stack_len: 0,
+            chunk: [0; CHUNK_LEN],
+            chunk_len: 0,
+            chunk_counter: 0,
+        }
+    }
+
+    pub fn update(&mut self, mut input: &[u8]) {
+        while !input.is_empty() {
+            // A full chunk is only merged once more input shows it's not the
+            // last one, which is finalized as the root instead.
+            if self.chunk_len == CHUNK_LEN {
+                let mut cv = chunk_output(&self.chunk, self.chunk_counter).chaining_value();
+                self.chunk_counter += 1;
+                let mut total_chunks = self.chunk_counter;
+                while total_chunks & 1 == 0 {
+                    self.cv_stack_len -= 1;
+                    cv = parent_output(&self.cv_stack[self.cv_stack_len], &cv).chaining_value();
+                    total_chunks >>= 1;
+                }
+                self.cv_stack[self.cv_stack_len] = cv;
+                self.cv_stack_len += 1;
+                self.chunk_len = 0;
+            }
+
+            let taken = input.len().min(CHUNK_LEN - self.chunk_len);
+            self.chunk[self.chunk_len..self.chunk_len + taken].copy_from_slice(&input[..taken]);
+            self.chunk_len += taken;
+            input = &input[taken..];
+        }
+    }
+
+    pub fn finalize(mut self) -> [u8; OUT_LEN] {
+        let mut output = chunk_output(&self.chunk[..self.chunk_len], self.chunk_counter);
+        while self.cv_stack_len > 0 {
+            self.cv_stack_len -= 1;
+            output = parent_output(&self.cv_stack[self.cv_stack_len], &output.chaining_value());
+        }
+
+        output.root_hash()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{digest, Hasher};
+    use crate::digest_to_hex_string;
+
+    use alloc::vec::Vec;
+
+    #[test]
+    fn test_digest_and_hex() {
+        assert_eq!(
+            digest_to_hex_string(&digest(b"")),
+            "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262"
+        );
+        assert_eq!(
+            digest_to_hex_string(&digest(b"abc")),
+            "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"
+        );
+
+        // Inputs of the official test vectors, which repeat 0..251.
+        let input = |len: usize| (0..len).map(|t| (t % 251) as u8).collect::<Vec<_>>();
+        for (len, expected) in [
+            (
+                1,
+                "2d3adedff11b61f14c886e35afa036736dcd87a74d27b5c1510225d0f592e213",
+            ),
+            (
+                1024,
+                "42214739f095a406f3fc83deb889744ac00df831c10daa55189b5d121c855af7",
+            ),
+            (
+                1025,
+                "d00278ae47eb27b34faecf67b4fe263f82d5412916c1ffd97c8cb7fb814b8444",
+            ),
+            (
+                3073,
+                "7124b49501012f81cc7f11ca069ec9226cecb8a2c850cfe644e327d22d3e1cd3",
+            ),
+            (
+                102400,
+                "bc3e3d41a1146b069abffad3c0d44860cf664390afce4d9661f7902e7943e085",
+            ),
+        ] {
+            assert_eq!(digest_to_hex_string(&digest(&input(len))), expected);
+        }
+    }
+
+    #[test]
+    fn test_hasher() {
+        let input: Vec<u8> = (0..5000).map(|t| (t % 251) as u8).collect();
+
+        // Chunk boundaries and merges of the tree, fed whole and in pieces.
+        for len in [0, 1, 1023, 1024, 1025, 2048, 3073, 4096, 5000] {
+            for chunk_size in [1, 7, 1024, 1025, 5000] {
+                let mut hasher = Hasher::new();
+                for chunk in input[..len].chunks(chunk_size) {
+                    hasher.update(chunk);
+                }
+                assert_eq!(hasher.finalize(), digest(&input[..len]));
+            }
+        }
+    }
+}
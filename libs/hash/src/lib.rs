@@ -3,6 +3,7 @@
 use alloc::{format, string::String, vec::Vec};
 extern crate alloc;
 
+pub mod blake3;
 pub mod md5;
 pub mod sha256;
 pub mod sha512;
@@ -281,9 +281,86 @@ pub const fn digest(input: &[u8]) -> [u8; RESULT_SIZE] {
     ]
 }
 
+/// Same as `digest`, for inputs that are fed in pieces instead of being held in
+/// memory at once.
+pub struct Hasher {
+    state: [u64; STATE_SIZE],
+    buffer: [u8; BLOCK_SIZE],
+    buffered: usize,
+    len: u64,
+}
+
+impl Default for Hasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Hasher {
+    pub const fn new() -> Self {
+        Self {
+            state: INIT_STATE,
+            buffer: [0; BLOCK_SIZE],
+            buffered: 0,
+            len: 0,
+        }
+    }
+
+    pub fn update(&mut self, mut input: &[u8]) {
+        self.len = self.len.wrapping_add(input.len() as u64);
+
+        if self.buffered > 0 {
+            let taken = input.len().min(BLOCK_SIZE - self.buffered);
+            self.buffer[self.buffered..self.buffered + taken].copy_from_slice(&input[..taken]);
+            self.buffered += taken;
+            input = &input[taken..];
+
+            if self.buffered < BLOCK_SIZE {
+                return;
+            }
+            self.state = sha512_transform(self.state, 0, &self.buffer);
+            self.buffered = 0;
+        }
+
+        let mut cursor = 0;
+        while cursor + BLOCK_SIZE <= input.len() {
+            self.state = sha512_transform(self.state, cursor, input);
+            cursor += BLOCK_SIZE;
+        }
+
+        let rest = &input[cursor..];
+        self.buffer[..rest.len()].copy_from_slice(rest);
+        self.buffered = rest.len();
+    }
+
+    pub fn finalize(mut self) -> [u8; RESULT_SIZE] {
+        // The length in bits takes 128 bits here.
+        let len_pos = BLOCK_SIZE - 2 * core::mem::size_of::<u64>();
+
+        self.buffer[self.buffered] = 0x80;
+        self.buffer[self.buffered + 1..].fill(0);
+        // No room left for the length, it goes in a block of its own.
+        if self.buffered + 1 > len_pos {
+            self.state = sha512_transform(self.state, 0, &self.buffer);
+            self.buffer.fill(0);
+        }
+        self.buffer[len_pos..len_pos + 8]
+            .copy_from_slice(&self.len.wrapping_shr(64 - 3).to_be_bytes());
+        self.buffer[len_pos + 8..].copy_from_slice(&self.len.wrapping_shl(3).to_be_bytes());
+        self.state = sha512_transform(self.state, 0, &self.buffer);
+
+        let mut result = [0; RESULT_SIZE];
+        for (bytes, word) in result.chunks_exact_mut(8).zip(self.state) {
+            bytes.copy_from_slice(&word.to_be_bytes());
+        }
+
+        result
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::digest;
+    use super::{digest, Hasher};
     use crate::digest_to_hex_string;
 
     use alloc::string::String;
@@ -338,4 +415,20 @@ mod tests {
         assert!(digest(t) == t_byte_array);
         assert!(digest_to_hex_string(&digest(t)) == t_sha512_str);
     }
+
+    #[test]
+    fn test_hasher() {
+        let input: alloc::vec::Vec<u8> = (0..300).map(|t| t as u8).collect();
+
+        // Every padding case, fed whole and in pieces that straddle blocks.
+        for len in 0..input.len() {
+            for chunk_size in [1, 7, 128, 129, 300] {
+                let mut hasher = Hasher::new();
+                for chunk in input[..len].chunks(chunk_size) {
+                    hasher.update(chunk);
+                }
+                assert!(hasher.finalize() == digest(&input[..len]));
+            }
+        }
+    }
 }
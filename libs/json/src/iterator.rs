@@ -188,9 +188,19 @@ pub(crate) fn iterate_tokens(
                         Some(JsonValue::Null) => {
                             return Err("Parent node can not be `JsonValue::Null`".to_string());
                         }
-                        None => {
-                            return Err("Parent node can not be empty".to_string());
-                        }
+                        // Single char values(e.g. `{ "retries": 3 }`) of the root object
+                        // are not saved before reaching the closer.
+                        None => match root_object {
+                            JsonValue::Object(object) => {
+                                object.insert(key, JsonValue::Plain(value.clone()));
+                            }
+                            JsonValue::Array(array) => {
+                                array.push(JsonValue::Plain(value.clone()));
+                            }
+                            _ => {
+                                return Err("Parent node can not be empty".to_string());
+                            }
+                        },
                     };
                 }
 
@@ -763,4 +763,15 @@ mod tests {
             assert_eq!(expected_object, actual_object);
         }
     }
+
+    #[test]
+    fn test_single_char_values() {
+        let json_value = Json::new(r#"{ "a": "x", "b": 5 }"#).parse().unwrap();
+        assert_eq!(json_value["a"], JsonValue::Plain("x".to_string()));
+        assert_eq!(json_value["b"], JsonValue::Plain("5".to_string()));
+
+        let json_value = Json::new(r#"{ "b": 5, "c": 0 }"#).parse().unwrap();
+        assert_eq!(json_value["b"], JsonValue::Plain("5".to_string()));
+        assert_eq!(json_value["c"], JsonValue::Plain("0".to_string()));
+    }
 }
@@ -2,8 +2,10 @@ mod deserializer;
 mod iterator;
 mod json;
 mod json_value;
+mod serializer;
 
 pub use crate::json::Json;
 
 pub use deserializer::Deserialize;
 pub use json_value::JsonValue;
+pub use serializer::{to_json_object, Serialize};
@@ -0,0 +1,104 @@
+/// Converts values into JSON text.
+pub trait Serialize {
+    fn to_json(&self) -> String;
+}
+
+impl Serialize for str {
+    fn to_json(&self) -> String {
+        let mut json = String::with_capacity(self.len() + 2);
+        json.push('"');
+        for c in self.chars() {
+            match c {
+                '"' => json.push_str("\\\""),
+                '\\' => json.push_str("\\\\"),
+                '\n' => json.push_str("\\n"),
+                '\r' => json.push_str("\\r"),
+                '\t' => json.push_str("\\t"),
+                c if c.is_control() => json.push_str(&format!("\\u{:04x}", c as u32)),
+                c => json.push(c),
+            }
+        }
+        json.push('"');
+        json
+    }
+}
+
+impl Serialize for String {
+    fn to_json(&self) -> String {
+        self.as_str().to_json()
+    }
+}
+
+macro_rules! impl_serialize_as_display {
+    ($($type: ty),*) => {
+        $(
+            impl Serialize for $type {
+                fn to_json(&self) -> String {
+                    self.to_string()
+                }
+            }
+        )*
+    };
+}
+
+impl_serialize_as_display!(bool, i32, i64, u32, u64, usize);
+
+impl<T: Serialize> Serialize for Option<T> {
+    fn to_json(&self) -> String {
+        match self {
+            Some(value) => value.to_json(),
+            None => String::from("null"),
+        }
+    }
+}
+
+impl<T: Serialize> Serialize for [T] {
+    fn to_json(&self) -> String {
+        let items: Vec<String> = self.iter().map(Serialize::to_json).collect();
+        format!("[{}]", items.join(","))
+    }
+}
+
+impl<T: Serialize> Serialize for Vec<T> {
+    fn to_json(&self) -> String {
+        self.as_slice().to_json()
+    }
+}
+
+impl<T: Serialize + ?Sized> Serialize for &T {
+    fn to_json(&self) -> String {
+        (**self).to_json()
+    }
+}
+
+/// Builds a JSON object out of `fields`, whose values are already serialized.
+pub fn to_json_object(fields: &[(&str, String)]) -> String {
+    let fields: Vec<String> = fields
+        .iter()
+        .map(|(key, value)| format!("{}:{}", key.to_json(), value))
+        .collect();
+    format!("{{{}}}", fields.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Json, JsonValue};
+
+    #[test]
+    fn test_serialize() {
+        assert_eq!("say \"hi\"\n".to_json(), r#""say \"hi\"\n""#);
+        assert_eq!(vec![Some(1), None].to_json(), "[1,null]");
+
+        let object = to_json_object(&[
+            ("name", "htop".to_json()),
+            ("size", 42.to_json()),
+            ("tags", vec!["a", "b"].to_json()),
+        ]);
+        assert_eq!(object, r#"{"name":"htop","size":42,"tags":["a","b"]}"#);
+
+        let parsed = Json::new(&object).parse().unwrap();
+        assert_eq!(parsed["name"], JsonValue::Plain(String::from("htop")));
+        assert_eq!(parsed["size"].as_u64(), Some(42));
+    }
+}
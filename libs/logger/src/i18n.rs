@@ -0,0 +1,416 @@
+//! Translations of the user-facing messages into the language of the locale
+//! (`LC_ALL`, `LC_MESSAGES` or `LANG`), English is the fallback.
+//!
+//! Catalogs are `<LOCALE_DIR>/<language>.po` files(e.g. `pt_BR.po`, then
+//! `pt.po`) with the English messages as `msgid`s. Placeholders of a `msgid`
+//! like `{name}` or `{}` match any text of the formatted message, and are put
+//! in the place of the same placeholders in the `msgstr`, so the catalogs can
+//! use the format strings of the sources as they are. `{}` placeholders go by
+//! order, `{0}`, `{1}` etc. point to them in another one.
+
+use std::{borrow::Cow, collections::HashMap, env, fs, path::Path, sync::OnceLock};
+
+pub const LOCALE_DIR: &str = "/usr/share/lpm/locale";
+
+#[derive(Debug, PartialEq)]
+enum Segment {
+    Text(String),
+    Named(String),
+    Positional(Option<usize>),
+}
+
+#[derive(Debug, Default)]
+struct Catalog {
+    exact: HashMap<String, String>,
+    /// Entries with placeholders in their `msgid`.
+    templates: Vec<(Vec<Segment>, Vec<Segment>)>,
+}
+
+impl Catalog {
+    fn new(entries: Vec<(String, String)>) -> Self {
+        let mut catalog = Self::default();
+        for (msgid, msgstr) in entries {
+            let pattern = parse_template(&msgid);
+            if pattern.iter().all(|t| matches!(t, Segment::Text(_))) {
+                catalog.exact.insert(msgid, msgstr);
+            } else {
+                catalog.templates.push((pattern, parse_template(&msgstr)));
+            }
+        }
+
+        catalog
+    }
+
+    fn translate(&self, message: &str) -> Option<String> {
+        if let Some(translation) = self.exact.get(message) {
+            return Some(translation.clone());
+        }
+
+        self.templates.iter().find_map(|(pattern, translation)| {
+            let captures = match_template(pattern, message)?;
+            Some(fill_template(pattern, translation, &captures))
+        })
+    }
+}
+
+/// `message` in the language of the locale, or as it is if it has no
+/// translation.
+pub fn translate(message: &str) -> Cow<'_, str> {
+    static CATALOG: OnceLock<Option<Catalog>> = OnceLock::new();
+
+    let catalog = CATALOG.get_or_init(|| load_catalog(Path::new(LOCALE_DIR)));
+    match catalog.as_ref().and_then(|t| t.translate(message)) {
+        Some(translation) => Cow::Owned(translation),
+        None => Cow::Borrowed(message),
+    }
+}
+
+/// Translates `text` line by line, keeping the indentation. Meant for help
+/// texts, so that a change in one line doesn't invalidate the translations
+/// of the others.
+pub fn translate_lines(text: &str) -> String {
+    text.split('\n')
+        .map(|line| {
+            let content = line.trim_start();
+            if content.is_empty() {
+                return line.to_owned();
+            }
+
+            let indentation = &line[..line.len() - content.len()];
+            format!("{indentation}{}", translate(content))
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn load_catalog(dir: &Path) -> Option<Catalog> {
+    let locale = ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .find_map(|t| env::var(t).ok().filter(|t| !t.is_empty()))?;
+
+    languages(&locale)
+        .iter()
+        .find_map(|language| fs::read_to_string(dir.join(format!("{language}.po"))).ok())
+        .map(|content| Catalog::new(parse_po(&content)))
+}
+
+/// Catalog names to try for `locale` in order, e.g. `pt_BR` and `pt` for
+/// `pt_BR.UTF-8`.
+fn languages(locale: &str) -> Vec<String> {
+    let language = locale
+        .split(['.', '@'])
+        .next()
+        .unwrap_or_default()
+        .to_owned();
+    if language.is_empty() || language == "C" || language == "POSIX" {
+        return Vec::new();
+    }
+
+    let mut languages = vec![language.clone()];
+    if let Some((base, _)) = language.split_once('_') {
+        languages.push(base.to_owned());
+    }
+
+    languages
+}
+
+/// `(msgid, msgstr)` pairs of a `.po` file, leaving out the header and the
+/// untranslated and fuzzy entries, the latter being guesses that nobody
+/// reviewed yet.
+fn parse_po(content: &str) -> Vec<(String, String)> {
+    #[derive(PartialEq)]
+    enum Field {
+        None,
+        Id,
+        Str,
+    }
+
+    let mut entries = Vec::new();
+    let (mut msgid, mut msgstr, mut field) = (String::new(), String::new(), Field::None);
+    // Flags come before the `msgid` of the entry they belong to.
+    let (mut is_fuzzy, mut is_next_fuzzy) = (false, false);
+    let mut flush = |msgid: &mut String, msgstr: &mut String, is_fuzzy: bool| {
+        if !msgid.is_empty() && !msgstr.is_empty() && !is_fuzzy {
+            entries.push((std::mem::take(msgid), std::mem::take(msgstr)));
+        }
+        msgid.clear();
+        msgstr.clear();
+    };
+
+    for line in content.lines().map(str::trim) {
+        if let Some(rest) = line.strip_prefix("msgid ") {
+            flush(&mut msgid, &mut msgstr, is_fuzzy);
+            is_fuzzy = std::mem::take(&mut is_next_fuzzy);
+            msgid = unquote(rest);
+            field = Field::Id;
+        } else if let Some(rest) = line.strip_prefix("msgstr ") {
+            msgstr = unquote(rest);
+            field = Field::Str;
+        } else if line.starts_with('"') {
+            match field {
+                Field::Id => msgid.push_str(&unquote(line)),
+                Field::Str => msgstr.push_str(&unquote(line)),
+                Field::None => {}
+            }
+        } else if line.is_empty() || line.starts_with('#') {
+            if let Some(flags) = line.strip_prefix("#,") {
+                is_next_fuzzy |= flags.split(',').any(|t| t.trim() == "fuzzy");
+            }
+            field = Field::None;
+        }
+    }
+    flush(&mut msgid, &mut msgstr, is_fuzzy);
+
+    entries
+}
+
+fn unquote(quoted: &str) -> String {
+    let inner = quoted
+        .strip_prefix('"')
+        .and_then(|t| t.strip_suffix('"'))
+        .unwrap_or(quoted);
+
+    let mut unquoted = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unquoted.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => unquoted.push('\n'),
+            Some('t') => unquoted.push('\t'),
+            Some(c) => unquoted.push(c),
+            None => {}
+        }
+    }
+
+    unquoted
+}
+
+/// Splits a format string into text and placeholders, `{{` and `}}` are
+/// literal braces and format specs(e.g. `{:?}`) are ignored.
+fn parse_template(template: &str) -> Vec<Segment> {
+    let mut segments = Vec::new();
+    let mut text = String::new();
+    let mut chars = template.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                text.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                text.push('}');
+            }
+            '{' => {
+                let mut placeholder = String::new();
+                for c in chars.by_ref() {
+                    if c == '}' {
+                        break;
+                    }
+                    placeholder.push(c);
+                }
+
+                if !text.is_empty() {
+                    segments.push(Segment::Text(std::mem::take(&mut text)));
+                }
+
+                let name = placeholder.split(':').next().unwrap_or_default();
+                segments.push(if name.is_empty() {
+                    Segment::Positional(None)
+                } else if let Ok(index) = name.parse() {
+                    Segment::Positional(Some(index))
+                } else {
+                    Segment::Named(name.to_owned())
+                });
+            }
+            c => text.push(c),
+        }
+    }
+
+    if !text.is_empty() {
+        segments.push(Segment::Text(text));
+    }
+
+    segments
+}
+
+/// Values of the placeholders of `pattern` in `message`, in order.
+fn match_template<'a>(pattern: &[Segment], message: &'a str) -> Option<Vec<&'a str>> {
+    match pattern.split_first() {
+        None => message.is_empty().then(Vec::new),
+        Some((Segment::Text(text), rest)) => match_template(rest, message.strip_prefix(text)?),
+        Some((_, rest)) => {
+            let Some(Segment::Text(next)) = rest.first() else {
+                // The last placeholder takes the rest, of adjacent ones only
+                // the last one gets anything.
+                if rest.is_empty() {
+                    return Some(vec![message]);
+                }
+                let mut captures = match_template(rest, message)?;
+                captures.insert(0, "");
+                return Some(captures);
+            };
+
+            message.match_indices(next.as_str()).find_map(|(i, _)| {
+                let mut captures = match_template(rest, &message[i..])?;
+                captures.insert(0, &message[..i]);
+                Some(captures)
+            })
+        }
+    }
+}
+
+fn fill_template(pattern: &[Segment], translation: &[Segment], captures: &[&str]) -> String {
+    let placeholders: Vec<&Segment> = pattern
+        .iter()
+        .filter(|t| !matches!(t, Segment::Text(_)))
+        .collect();
+    let positional: Vec<&str> = placeholders
+        .iter()
+        .zip(captures)
+        .filter(|(t, _)| matches!(t, Segment::Positional(_)))
+        .map(|(_, capture)| *capture)
+        .collect();
+
+    let mut next_position = 0;
+    translation
+        .iter()
+        .map(|segment| match segment {
+            Segment::Text(text) => text.as_str(),
+            Segment::Named(name) => placeholders
+                .iter()
+                .position(|t| matches!(t, Segment::Named(t) if t == name))
+                .map_or("", |i| captures[i]),
+            Segment::Positional(index) => {
+                let index = index.unwrap_or_else(|| {
+                    next_position += 1;
+                    next_position - 1
+                });
+                positional.get(index).copied().unwrap_or_default()
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_languages() {
+        assert_eq!(languages("pt_BR.UTF-8"), ["pt_BR", "pt"]);
+        assert_eq!(languages("de_DE@euro"), ["de_DE", "de"]);
+        assert_eq!(languages("tr"), ["tr"]);
+        assert!(languages("C.UTF-8").is_empty());
+        assert!(languages("POSIX").is_empty());
+    }
+
+    #[test]
+    fn test_parse_po() {
+        let content = r#"
+# Turkish translations of lpm.
+msgid ""
+msgstr ""
+"Language: tr\n"
+
+msgid "No problems found."
+msgstr "Sorun bulunamadı."
+
+msgid "Line one\n"
+"line \"two\""
+msgstr "Birinci satır\n"
+"\"ikinci\" satır"
+
+#, fuzzy, c-format
+msgid "Checking {name}.."
+msgstr "{name} denetleniyor.."
+
+#, c-format
+msgid "{} files are missing."
+msgstr "{} dosya eksik."
+
+msgid "Not translated yet."
+msgstr ""
+"#;
+
+        assert_eq!(
+            parse_po(content),
+            [
+                (
+                    String::from("No problems found."),
+                    String::from("Sorun bulunamadı.")
+                ),
+                (
+                    String::from("Line one\nline \"two\""),
+                    String::from("Birinci satır\n\"ikinci\" satır")
+                ),
+                (
+                    String::from("{} files are missing."),
+                    String::from("{} dosya eksik.")
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_translate() {
+        let catalog = Catalog::new(vec![
+            (
+                String::from("No problems found."),
+                String::from("Sorun bulunamadı."),
+            ),
+            (
+                String::from("Checking {name}.."),
+                String::from("{name} kontrol ediliyor.."),
+            ),
+            (
+                String::from("{} files of '{name}' are missing, e.g. {}"),
+                String::from("'{name}' paketinin {0} dosyası eksik, örneğin {1}"),
+            ),
+            (
+                String::from("Skipping {:?}; already exists: '{}'"),
+                String::from("'{1}' zaten var, {0} atlanıyor"),
+            ),
+        ]);
+
+        let translate = |message| catalog.translate(message);
+        assert_eq!(
+            translate("No problems found.").as_deref(),
+            Some("Sorun bulunamadı.")
+        );
+        assert_eq!(
+            translate("Checking package files..").as_deref(),
+            Some("package files kontrol ediliyor..")
+        );
+        assert_eq!(
+            translate("2 files of 'htop' are missing, e.g. /usr/bin/htop").as_deref(),
+            Some("'htop' paketinin 2 dosyası eksik, örneğin /usr/bin/htop")
+        );
+        assert_eq!(
+            translate("Skipping \"htop.lod\"; already exists: '/tmp/lpm/htop.lod'").as_deref(),
+            Some("'/tmp/lpm/htop.lod' zaten var, \"htop.lod\" atlanıyor")
+        );
+        assert_eq!(translate("Checking package files"), None);
+        assert_eq!(translate("Something else."), None);
+    }
+
+    #[test]
+    fn test_parse_template() {
+        assert_eq!(
+            parse_template("{{literal}} {name:?} {} {1}"),
+            [
+                Segment::Text(String::from("{literal} ")),
+                Segment::Named(String::from("name")),
+                Segment::Text(String::from(" ")),
+                Segment::Positional(None),
+                Segment::Text(String::from(" ")),
+                Segment::Positional(Some(1)),
+            ]
+        );
+    }
+}
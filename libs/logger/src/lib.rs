@@ -1,7 +1,27 @@
+pub mod i18n;
+
 use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
 
 const LOGGER_NAME: &str = "lpm";
 
+static VERBOSE: AtomicBool = AtomicBool::new(false);
+static LOGS_TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+/// Enables the `verbose!` logs, which are hidden by default.
+pub fn set_verbose(enabled: bool) {
+    VERBOSE.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_verbose() -> bool {
+    VERBOSE.load(Ordering::Relaxed)
+}
+
+/// Writes all of the logs to stderr, keeping stdout for machine-readable output.
+pub fn set_logs_to_stderr(enabled: bool) {
+    LOGS_TO_STDERR.store(enabled, Ordering::Relaxed);
+}
+
 pub enum OutputMode {
     SUCCESS,
     INFO,
@@ -95,6 +115,10 @@ pub fn log_to_stderr(log: &[u8]) {
 }
 
 pub fn log_to_stdout(log: &[u8]) {
+    if LOGS_TO_STDERR.load(Ordering::Relaxed) {
+        return log_to_stderr(log);
+    }
+
     if io::stdout().write_all(log).is_err() {
         panic!("writing to stderr failed");
     }
@@ -119,46 +143,61 @@ macro_rules! debug {
     ($log: expr) => {};
 }
 
+/// Unlike `debug!`, available in release builds too when `set_verbose` is enabled.
+#[macro_export]
+macro_rules! verbose {
+    ($log: expr, $($args: tt)+) => {
+        if logger::is_verbose() {
+            logger::log_to_stdout(logger::build_log_ln(logger::OutputMode::DEBUG, &format!($log, $($args)+)).as_bytes());
+        }
+    };
+    ($log: expr) => {
+        if logger::is_verbose() {
+            logger::log_to_stdout(logger::build_log_ln(logger::OutputMode::DEBUG, &format!($log)).as_bytes());
+        }
+    }
+}
+
 #[macro_export]
 macro_rules! success {
     ($log: expr, $($args: tt)+) => {
-        logger::log_to_stdout(logger::build_log_ln(logger::OutputMode::SUCCESS, &format!($log, $($args)+)).as_bytes());
+        logger::log_to_stdout(logger::build_log_ln(logger::OutputMode::SUCCESS, &logger::i18n::translate(&format!($log, $($args)+))).as_bytes());
 
     };
     ($log: expr) => {
-        logger::log_to_stdout(logger::build_log_ln(logger::OutputMode::SUCCESS, &format!($log)).as_bytes());
+        logger::log_to_stdout(logger::build_log_ln(logger::OutputMode::SUCCESS, &logger::i18n::translate(&format!($log))).as_bytes());
     }
 }
 
 #[macro_export]
 macro_rules! info {
     ($log: expr, $($args: tt)+) => {
-        logger::log_to_stdout(logger::build_log_ln(logger::OutputMode::INFO, &format!($log, $($args)+)).as_bytes());
+        logger::log_to_stdout(logger::build_log_ln(logger::OutputMode::INFO, &logger::i18n::translate(&format!($log, $($args)+))).as_bytes());
 
     };
     ($log: expr) => {
-        logger::log_to_stdout(logger::build_log_ln(logger::OutputMode::INFO, &format!($log)).as_bytes());
+        logger::log_to_stdout(logger::build_log_ln(logger::OutputMode::INFO, &logger::i18n::translate(&format!($log))).as_bytes());
     }
 }
 
 #[macro_export]
 macro_rules! error {
     ($log: expr, $($args: tt)+) => {
-        logger::log_to_stderr(logger::build_log_ln(logger::OutputMode::ERROR, &format!($log, $($args)+)).as_bytes());
+        logger::log_to_stderr(logger::build_log_ln(logger::OutputMode::ERROR, &logger::i18n::translate(&format!($log, $($args)+))).as_bytes());
 
     };
     ($log: expr) => {
-        logger::log_to_stderr(logger::build_log_ln(logger::OutputMode::ERROR, &format!($log)).as_bytes());
+        logger::log_to_stderr(logger::build_log_ln(logger::OutputMode::ERROR, &logger::i18n::translate(&format!($log))).as_bytes());
     }
 }
 
 #[macro_export]
 macro_rules! warning {
     ($log: expr, $($args: tt)+) => {
-        logger::log_to_stdout(logger::build_log_ln(logger::OutputMode::WARNING, &format!($log, $($args)+)).as_bytes());
+        logger::log_to_stdout(logger::build_log_ln(logger::OutputMode::WARNING, &logger::i18n::translate(&format!($log, $($args)+))).as_bytes());
 
     };
     ($log: expr) => {
-        logger::log_to_stdout(logger::build_log_ln(logger::OutputMode::WARNING, &format!($log)).as_bytes());
+        logger::log_to_stdout(logger::build_log_ln(logger::OutputMode::WARNING, &logger::i18n::translate(&format!($log))).as_bytes());
     }
 }
@@ -0,0 +1,94 @@
+use std::rc::Rc;
+
+pub(crate) type Block = Vec<Stat>;
+
+#[derive(Debug)]
+pub(crate) struct Stat {
+    pub(crate) kind: StatKind,
+    /// Where runtime errors of the statement are reported.
+    pub(crate) line: usize,
+}
+
+#[derive(Debug)]
+pub(crate) enum StatKind {
+    Local(Vec<String>, Vec<Expr>),
+    Assign(Vec<Expr>, Vec<Expr>),
+    Call(Expr),
+    Do(Block),
+    While(Expr, Block),
+    Repeat(Block, Expr),
+    If(Vec<(Expr, Block)>, Option<Block>),
+    NumericFor {
+        var: String,
+        start: Expr,
+        limit: Expr,
+        step: Option<Expr>,
+        body: Block,
+    },
+    GenericFor(Vec<String>, Vec<Expr>, Block),
+    LocalFunction(String, Rc<FunctionDef>),
+    Return(Vec<Expr>),
+    Break,
+}
+
+#[derive(Debug)]
+pub(crate) struct FunctionDef {
+    pub(crate) params: Vec<String>,
+    pub(crate) is_vararg: bool,
+    pub(crate) body: Block,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Pow,
+    Concat,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum UnOp {
+    Neg,
+    Not,
+    Len,
+}
+
+#[derive(Debug)]
+pub(crate) enum Expr {
+    Nil,
+    Boolean(bool),
+    Number(f64),
+    Str(Rc<str>),
+    Vararg,
+    Function(Rc<FunctionDef>),
+    /// Positional items and keyed fields, in the order they are written.
+    Table(Vec<(Option<Expr>, Expr)>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+    Unary(UnOp, Box<Expr>),
+    Name(String),
+    Index(Box<Expr>, Box<Expr>),
+    Call(Box<Expr>, Vec<Expr>),
+    /// `object:name(args)`
+    Method(Box<Expr>, String, Vec<Expr>),
+    /// Parenthesized, which truncates multiple results to one.
+    Paren(Box<Expr>),
+}
+
+impl Expr {
+    /// Whether the expression can produce any number of values, which are
+    /// all kept at the end of a list.
+    pub(crate) fn is_multi(&self) -> bool {
+        matches!(self, Expr::Call(..) | Expr::Method(..) | Expr::Vararg)
+    }
+}
@@ -0,0 +1,593 @@
+use crate::{
+    ast::{BinOp, Block, Expr, FunctionDef, StatKind, UnOp},
+    parser::parse,
+    stdlib,
+    value::{Args, Function, FunctionKind, Table, Value},
+    Error,
+};
+use std::{cell::RefCell, cmp::Ordering, rc::Rc};
+
+/// Statements and loop iterations a chunk may run, so that a script that
+/// never ends fails instead of hanging its caller.
+const MAX_STEPS: u64 = 10_000_000;
+const MAX_CALL_DEPTH: usize = 100;
+/// Name of the local that keeps the extra arguments of vararg functions,
+/// which no identifier can shadow.
+const VARARGS: &str = "...";
+
+/// A local variable and the ones declared before it, closures keep the chain
+/// they are defined in.
+pub(crate) struct Scope {
+    name: String,
+    value: RefCell<Value>,
+    parent: Option<Rc<Scope>>,
+}
+
+fn declare(parent: &Option<Rc<Scope>>, name: &str, value: Value) -> Option<Rc<Scope>> {
+    Some(Rc::new(Scope {
+        name: name.to_owned(),
+        value: RefCell::new(value),
+        parent: parent.clone(),
+    }))
+}
+
+fn lookup<'a>(scope: &'a Option<Rc<Scope>>, name: &str) -> Option<&'a Scope> {
+    let mut current = scope.as_deref();
+    while let Some(scope) = current {
+        if scope.name == name {
+            return Some(scope);
+        }
+        current = scope.parent.as_deref();
+    }
+
+    None
+}
+
+enum Flow {
+    Normal,
+    Break,
+    Return(Vec<Value>),
+}
+
+/// Interpreter state: the globals, and what `print` wrote.
+pub struct Lua {
+    globals: Table,
+    output: String,
+    steps: u64,
+    depth: usize,
+}
+
+impl Default for Lua {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Lua {
+    /// An interpreter with the restricted standard library.
+    pub fn new() -> Self {
+        let lua = Self {
+            globals: Table::new(),
+            output: String::new(),
+            steps: 0,
+            depth: 0,
+        };
+        stdlib::open(&lua.globals);
+
+        lua
+    }
+
+    pub fn globals(&self) -> &Table {
+        &self.globals
+    }
+
+    pub fn set_global(&self, name: &str, value: impl Into<Value>) {
+        // Only fails for nil and NaN keys.
+        let _ = self.globals.set(name, value);
+    }
+
+    /// Runs `source`, returns what its main chunk returns.
+    pub fn exec(&mut self, source: &str) -> Result<Vec<Value>, Error> {
+        let block = parse(source)?;
+        self.steps = 0;
+
+        match self.exec_block(&block, &None)? {
+            Flow::Return(values) => Ok(values),
+            Flow::Normal | Flow::Break => Ok(Vec::new()),
+        }
+    }
+
+    /// What `print` wrote since the last call.
+    pub fn take_output(&mut self) -> String {
+        std::mem::take(&mut self.output)
+    }
+
+    pub(crate) fn write_output(&mut self, text: &str) {
+        self.output.push_str(text);
+    }
+
+    pub fn call(&mut self, function: &Value, args: Vec<Value>) -> Result<Vec<Value>, Error> {
+        let Value::Function(Function(function)) = function else {
+            return Err(Error::new(format!(
+                "attempt to call a {} value",
+                function.type_name()
+            )));
+        };
+        if self.depth >= MAX_CALL_DEPTH {
+            return Err(Error::new("stack overflow"));
+        }
+
+        let function = function.clone();
+        self.depth += 1;
+        let result = match &*function {
+            FunctionKind::Builtin { name, f } => f(
+                self,
+                Args {
+                    function: name.clone(),
+                    values: args,
+                },
+            ),
+            FunctionKind::Lua { def, scope } => self.call_lua(def, scope, args),
+        };
+        self.depth -= 1;
+
+        result
+    }
+
+    fn call_lua(
+        &mut self,
+        def: &FunctionDef,
+        scope: &Option<Rc<Scope>>,
+        args: Vec<Value>,
+    ) -> Result<Vec<Value>, Error> {
+        let mut scope = scope.clone();
+        for (index, param) in def.params.iter().enumerate() {
+            scope = declare(&scope, param, args.get(index).cloned().unwrap_or_default());
+        }
+        if def.is_vararg {
+            let extra = args.into_iter().skip(def.params.len());
+            scope = declare(&scope, VARARGS, Table::from_list(extra).into());
+        }
+
+        match self.exec_stats(&def.body, &mut scope)? {
+            Flow::Return(values) => Ok(values),
+            Flow::Normal | Flow::Break => Ok(Vec::new()),
+        }
+    }
+
+    fn step(&mut self) -> Result<(), Error> {
+        self.steps += 1;
+        match self.steps > MAX_STEPS {
+            true => Err(Error::new("script runs for too long")),
+            false => Ok(()),
+        }
+    }
+
+    /// Runs `block` in a scope of its own.
+    fn exec_block(&mut self, block: &Block, scope: &Option<Rc<Scope>>) -> Result<Flow, Error> {
+        self.exec_stats(block, &mut scope.clone())
+    }
+
+    /// Runs `block` in `scope`, which gets the locals it declares.
+    fn exec_stats(&mut self, block: &Block, scope: &mut Option<Rc<Scope>>) -> Result<Flow, Error> {
+        for stat in block {
+            let flow = self
+                .step()
+                .and_then(|_| self.exec_stat(&stat.kind, scope))
+                .map_err(|e| e.at_line(stat.line))?;
+            if !matches!(flow, Flow::Normal) {
+                return Ok(flow);
+            }
+        }
+
+        Ok(Flow::Normal)
+    }
+
+    fn exec_stat(&mut self, stat: &StatKind, scope: &mut Option<Rc<Scope>>) -> Result<Flow, Error> {
+        match stat {
+            StatKind::Local(names, exprs) => {
+                let values = self.eval_list(exprs, scope)?;
+                for (index, name) in names.iter().enumerate() {
+                    *scope = declare(scope, name, values.get(index).cloned().unwrap_or_default());
+                }
+            }
+            StatKind::Assign(targets, exprs) => {
+                let values = self.eval_list(exprs, scope)?;
+                for (index, target) in targets.iter().enumerate() {
+                    self.assign(
+                        target,
+                        values.get(index).cloned().unwrap_or_default(),
+                        scope,
+                    )?;
+                }
+            }
+            StatKind::Call(expr) => {
+                self.eval_multi(expr, scope)?;
+            }
+            StatKind::Do(block) => return self.exec_block(block, scope),
+            StatKind::While(condition, block) => {
+                while self.eval(condition, scope)?.is_truthy() {
+                    self.step()?;
+                    match self.exec_block(block, scope)? {
+                        Flow::Break => break,
+                        Flow::Return(values) => return Ok(Flow::Return(values)),
+                        Flow::Normal => {}
+                    }
+                }
+            }
+            StatKind::Repeat(block, condition) => loop {
+                self.step()?;
+                // The condition sees the locals of the block.
+                let mut inner = scope.clone();
+                match self.exec_stats(block, &mut inner)? {
+                    Flow::Break => break,
+                    Flow::Return(values) => return Ok(Flow::Return(values)),
+                    Flow::Normal => {}
+                }
+                if self.eval(condition, &inner)?.is_truthy() {
+                    break;
+                }
+            },
+            StatKind::If(branches, otherwise) => {
+                for (condition, block) in branches {
+                    if self.eval(condition, scope)?.is_truthy() {
+                        return self.exec_block(block, scope);
+                    }
+                }
+                if let Some(block) = otherwise {
+                    return self.exec_block(block, scope);
+                }
+            }
+            StatKind::NumericFor {
+                var,
+                start,
+                limit,
+                step,
+                body,
+            } => {
+                let number = |lua: &mut Self, expr: &Expr, what: &str| {
+                    lua.eval(expr, scope)?
+                        .to_number()
+                        .ok_or_else(|| Error::new(format!("'for' {what} must be a number")))
+                };
+                let start = number(self, start, "initial value")?;
+                let limit = number(self, limit, "limit")?;
+                let step = match step {
+                    Some(step) => number(self, step, "step")?,
+                    None => 1.0,
+                };
+                if step == 0.0 {
+                    return Err(Error::new("'for' step is zero"));
+                }
+
+                let mut value = start;
+                while (step > 0.0 && value <= limit) || (step < 0.0 && value >= limit) {
+                    self.step()?;
+                    match self.exec_block(body, &declare(scope, var, value.into()))? {
+                        Flow::Break => break,
+                        Flow::Return(values) => return Ok(Flow::Return(values)),
+                        Flow::Normal => {}
+                    }
+                    value += step;
+                }
+            }
+            StatKind::GenericFor(names, exprs, body) => {
+                let mut values = self.eval_list(exprs, scope)?.into_iter();
+                let iterator = values.next().unwrap_or_default();
+                let state = values.next().unwrap_or_default();
+                let mut control = values.next().unwrap_or_default();
+
+                loop {
+                    self.step()?;
+                    let results = self.call(&iterator, vec![state.clone(), control.clone()])?;
+                    control = results.first().cloned().unwrap_or_default();
+                    if matches!(control, Value::Nil) {
+                        break;
+                    }
+
+                    let mut inner = scope.clone();
+                    for (index, name) in names.iter().enumerate() {
+                        inner = declare(
+                            &inner,
+                            name,
+                            results.get(index).cloned().unwrap_or_default(),
+                        );
+                    }
+                    match self.exec_block(body, &inner)? {
+                        Flow::Break => break,
+                        Flow::Return(values) => return Ok(Flow::Return(values)),
+                        Flow::Normal => {}
+                    }
+                }
+            }
+            StatKind::LocalFunction(name, def) => {
+                // Declared first, so that the function can call itself.
+                *scope = declare(scope, name, Value::Nil);
+                let function = closure(def, scope);
+                if let Some(local) = scope {
+                    local.value.replace(function);
+                }
+            }
+            StatKind::Return(exprs) => return Ok(Flow::Return(self.eval_list(exprs, scope)?)),
+            StatKind::Break => return Ok(Flow::Break),
+        }
+
+        Ok(Flow::Normal)
+    }
+
+    fn assign(
+        &mut self,
+        target: &Expr,
+        value: Value,
+        scope: &Option<Rc<Scope>>,
+    ) -> Result<(), Error> {
+        match target {
+            Expr::Name(name) => match lookup(scope, name) {
+                Some(local) => {
+                    local.value.replace(value);
+                    Ok(())
+                }
+                None => self.globals.set(name.as_str(), value),
+            },
+            Expr::Index(object, key) => match self.eval(object, scope)? {
+                Value::Table(table) => table.set(self.eval(key, scope)?, value),
+                other => Err(Error::new(format!(
+                    "attempt to index a {} value{}",
+                    other.type_name(),
+                    describe(object, scope)
+                ))),
+            },
+            _ => Err(Error::new("cannot assign to this expression")),
+        }
+    }
+
+    /// Values of `exprs`, the last one expanded to all of its values.
+    fn eval_list(
+        &mut self,
+        exprs: &[Expr],
+        scope: &Option<Rc<Scope>>,
+    ) -> Result<Vec<Value>, Error> {
+        let mut values = Vec::with_capacity(exprs.len());
+        for (index, expr) in exprs.iter().enumerate() {
+            if index == exprs.len() - 1 && expr.is_multi() {
+                values.extend(self.eval_multi(expr, scope)?);
+            } else {
+                values.push(self.eval(expr, scope)?);
+            }
+        }
+
+        Ok(values)
+    }
+
+    fn eval_multi(&mut self, expr: &Expr, scope: &Option<Rc<Scope>>) -> Result<Vec<Value>, Error> {
+        match expr {
+            Expr::Call(function, args) => {
+                let value = self.eval(function, scope)?;
+                let args = self.eval_list(args, scope)?;
+                if !matches!(value, Value::Function(_)) {
+                    return Err(Error::new(format!(
+                        "attempt to call a {} value{}",
+                        value.type_name(),
+                        describe(function, scope)
+                    )));
+                }
+                self.call(&value, args)
+            }
+            Expr::Method(object, name, args) => {
+                let object = self.eval(object, scope)?;
+                let method = self.index(&object, &Value::from(name.as_str()))?;
+                if !matches!(method, Value::Function(_)) {
+                    return Err(Error::new(format!(
+                        "attempt to call a {} value (method '{name}')",
+                        method.type_name()
+                    )));
+                }
+                let mut values = vec![object];
+                values.extend(self.eval_list(args, scope)?);
+                self.call(&method, values)
+            }
+            Expr::Vararg => Ok(match lookup(scope, VARARGS) {
+                Some(local) => match &*local.value.borrow() {
+                    Value::Table(table) => table.list(),
+                    _ => Vec::new(),
+                },
+                None => Vec::new(),
+            }),
+            _ => Ok(vec![self.eval(expr, scope)?]),
+        }
+    }
+
+    fn eval(&mut self, expr: &Expr, scope: &Option<Rc<Scope>>) -> Result<Value, Error> {
+        Ok(match expr {
+            Expr::Nil => Value::Nil,
+            Expr::Boolean(value) => Value::Boolean(*value),
+            Expr::Number(number) => Value::Number(*number),
+            Expr::Str(value) => Value::Str(value.clone()),
+            Expr::Function(def) => closure(def, scope),
+            Expr::Table(fields) => {
+                let table = Table::new();
+                let mut position = 1.0;
+                for (index, (key, value)) in fields.iter().enumerate() {
+                    match key {
+                        Some(key) => {
+                            let key = self.eval(key, scope)?;
+                            table.set(key, self.eval(value, scope)?)?;
+                        }
+                        None if index == fields.len() - 1 && value.is_multi() => {
+                            for value in self.eval_multi(value, scope)? {
+                                table.set(position, value)?;
+                                position += 1.0;
+                            }
+                        }
+                        None => {
+                            table.set(position, self.eval(value, scope)?)?;
+                            position += 1.0;
+                        }
+                    }
+                }
+                Value::Table(table)
+            }
+            Expr::Binary(BinOp::And, lhs, rhs) => {
+                let lhs = self.eval(lhs, scope)?;
+                match lhs.is_truthy() {
+                    true => self.eval(rhs, scope)?,
+                    false => lhs,
+                }
+            }
+            Expr::Binary(BinOp::Or, lhs, rhs) => {
+                let lhs = self.eval(lhs, scope)?;
+                match lhs.is_truthy() {
+                    true => lhs,
+                    false => self.eval(rhs, scope)?,
+                }
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                let lhs = self.eval(lhs, scope)?;
+                let rhs = self.eval(rhs, scope)?;
+                binary(*op, &lhs, &rhs)?
+            }
+            Expr::Unary(op, operand) => {
+                let value = self.eval(operand, scope)?;
+                match op {
+                    UnOp::Not => Value::Boolean(!value.is_truthy()),
+                    UnOp::Neg => Value::Number(-arithmetic_operand(&value)?),
+                    UnOp::Len => match &value {
+                        Value::Str(value) => Value::Number(value.len() as f64),
+                        Value::Table(table) => Value::Number(table.len() as f64),
+                        _ => {
+                            return Err(Error::new(format!(
+                                "attempt to get length of a {} value{}",
+                                value.type_name(),
+                                describe(operand, scope)
+                            )))
+                        }
+                    },
+                }
+            }
+            Expr::Name(name) => match lookup(scope, name) {
+                Some(local) => local.value.borrow().clone(),
+                None => self.globals.get(name.as_str()),
+            },
+            Expr::Index(object, key) => {
+                let value = self.eval(object, scope)?;
+                let key = self.eval(key, scope)?;
+                self.index(&value, &key).map_err(|e| match &value {
+                    Value::Table(_) | Value::Str(_) => e,
+                    _ => Error::new(format!("{}{}", e.message, describe(object, scope))),
+                })?
+            }
+            Expr::Call(..) | Expr::Method(..) | Expr::Vararg => self
+                .eval_multi(expr, scope)?
+                .into_iter()
+                .next()
+                .unwrap_or_default(),
+            Expr::Paren(expr) => self.eval(expr, scope)?,
+        })
+    }
+
+    /// Strings are indexed through the `string` library, which makes
+    /// `s:upper()` work.
+    fn index(&self, value: &Value, key: &Value) -> Result<Value, Error> {
+        match value {
+            Value::Table(table) => Ok(table.get(key.clone())),
+            Value::Str(_) => Ok(match self.globals.get("string") {
+                Value::Table(string) => string.get(key.clone()),
+                _ => Value::Nil,
+            }),
+            _ => Err(Error::new(format!(
+                "attempt to index a {} value",
+                value.type_name()
+            ))),
+        }
+    }
+}
+
+fn closure(def: &Rc<FunctionDef>, scope: &Option<Rc<Scope>>) -> Value {
+    Value::Function(Function(Rc::new(FunctionKind::Lua {
+        def: def.clone(),
+        scope: scope.clone(),
+    })))
+}
+
+/// What `expr` is to the messages of the errors it causes, e.g.
+/// ` (global 'x')`.
+fn describe(expr: &Expr, scope: &Option<Rc<Scope>>) -> String {
+    match expr {
+        Expr::Name(name) if lookup(scope, name).is_some() => format!(" (local '{name}')"),
+        Expr::Name(name) => format!(" (global '{name}')"),
+        Expr::Index(_, key) => match &**key {
+            Expr::Str(key) => format!(" (field '{key}')"),
+            _ => String::new(),
+        },
+        _ => String::new(),
+    }
+}
+
+fn arithmetic_operand(value: &Value) -> Result<f64, Error> {
+    value.to_number().ok_or_else(|| {
+        Error::new(format!(
+            "attempt to perform arithmetic on a {} value",
+            value.type_name()
+        ))
+    })
+}
+
+fn compare(lhs: &Value, rhs: &Value) -> Result<Option<Ordering>, Error> {
+    match (lhs, rhs) {
+        (Value::Number(a), Value::Number(b)) => Ok(a.partial_cmp(b)),
+        (Value::Str(a), Value::Str(b)) => Ok(Some(a.cmp(b))),
+        _ if lhs.type_name() == rhs.type_name() => Err(Error::new(format!(
+            "attempt to compare two {} values",
+            lhs.type_name()
+        ))),
+        _ => Err(Error::new(format!(
+            "attempt to compare {} with {}",
+            lhs.type_name(),
+            rhs.type_name()
+        ))),
+    }
+}
+
+pub(crate) fn binary(op: BinOp, lhs: &Value, rhs: &Value) -> Result<Value, Error> {
+    let arithmetic = |f: fn(f64, f64) -> f64| -> Result<Value, Error> {
+        Ok(Value::Number(f(
+            arithmetic_operand(lhs)?,
+            arithmetic_operand(rhs)?,
+        )))
+    };
+
+    match op {
+        BinOp::Add => arithmetic(|a, b| a + b),
+        BinOp::Sub => arithmetic(|a, b| a - b),
+        BinOp::Mul => arithmetic(|a, b| a * b),
+        BinOp::Div => arithmetic(|a, b| a / b),
+        BinOp::Mod => arithmetic(|a, b| a - (a / b).floor() * b),
+        BinOp::Pow => arithmetic(f64::powf),
+        BinOp::Concat => {
+            let operand = |value: &Value| {
+                value.to_str().ok_or_else(|| {
+                    Error::new(format!(
+                        "attempt to concatenate a {} value",
+                        value.type_name()
+                    ))
+                })
+            };
+            Ok(Value::from(format!("{}{}", operand(lhs)?, operand(rhs)?)))
+        }
+        BinOp::Eq => Ok(Value::Boolean(lhs.raw_equals(rhs))),
+        BinOp::Ne => Ok(Value::Boolean(!lhs.raw_equals(rhs))),
+        BinOp::Lt => Ok(Value::Boolean(compare(lhs, rhs)? == Some(Ordering::Less))),
+        BinOp::Le => Ok(Value::Boolean(matches!(
+            compare(lhs, rhs)?,
+            Some(Ordering::Less | Ordering::Equal)
+        ))),
+        BinOp::Gt => Ok(Value::Boolean(
+            compare(lhs, rhs)? == Some(Ordering::Greater),
+        )),
+        BinOp::Ge => Ok(Value::Boolean(matches!(
+            compare(lhs, rhs)?,
+            Some(Ordering::Greater | Ordering::Equal)
+        ))),
+        BinOp::And | BinOp::Or => unreachable!("short-circuited in `eval`"),
+    }
+}
@@ -0,0 +1,297 @@
+use crate::Error;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Token {
+    Name(String),
+    Number(f64),
+    Str(String),
+    /// Keywords and operators.
+    Symbol(&'static str),
+    Eof,
+}
+
+const KEYWORDS: &[&str] = &[
+    "and", "break", "do", "else", "elseif", "end", "false", "for", "function", "if", "in", "local",
+    "nil", "not", "or", "repeat", "return", "then", "true", "until", "while",
+];
+
+/// Longest first, so that `..` isn't read as two `.`.
+const SYMBOLS: &[&str] = &[
+    "...", "..", "==", "~=", "<=", ">=", "+", "-", "*", "/", "%", "^", "#", "<", ">", "=", "(",
+    ")", "{", "}", "[", "]", ";", ":", ",", ".",
+];
+
+/// Tokens of `source` with the line each starts on.
+pub(crate) fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, Error> {
+    let mut lexer = Lexer {
+        chars: source.chars().collect(),
+        pos: 0,
+        line: 1,
+    };
+
+    // A shebang is how the script picks its interpreter, not Lua.
+    if source.starts_with("#!") {
+        while lexer.peek(0).map_or(false, |t| t != '\n') {
+            lexer.pos += 1;
+        }
+    }
+
+    let mut tokens = Vec::new();
+    loop {
+        lexer.skip_whitespace_and_comments()?;
+        let line = lexer.line;
+        let token = lexer.next_token()?;
+        let is_eof = token == Token::Eof;
+        tokens.push((token, line));
+        if is_eof {
+            return Ok(tokens);
+        }
+    }
+}
+
+struct Lexer {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+}
+
+impl Lexer {
+    fn peek(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.pos + offset).copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek(0)?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn error(&self, message: impl Into<String>) -> Error {
+        Error::new(message).at_line(self.line)
+    }
+
+    fn skip_whitespace_and_comments(&mut self) -> Result<(), Error> {
+        loop {
+            match self.peek(0) {
+                Some(c) if c.is_whitespace() => {
+                    self.bump();
+                }
+                Some('-') if self.peek(1) == Some('-') => {
+                    self.pos += 2;
+                    if self.peek(0) == Some('[') && self.long_bracket_level().is_some() {
+                        self.long_string()?;
+                    } else {
+                        while self.peek(0).map_or(false, |t| t != '\n') {
+                            self.pos += 1;
+                        }
+                    }
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    /// Level of the `[[` or `[==[` at the current position.
+    fn long_bracket_level(&self) -> Option<usize> {
+        let mut level = 0;
+        while self.peek(1 + level) == Some('=') {
+            level += 1;
+        }
+
+        (self.peek(1 + level) == Some('[')).then_some(level)
+    }
+
+    fn long_string(&mut self) -> Result<String, Error> {
+        let level = self.long_bracket_level().unwrap_or_default();
+        self.pos += level + 2;
+        // A newline right after the opening bracket isn't part of the string.
+        if self.peek(0) == Some('\n') {
+            self.bump();
+        }
+
+        let closing: Vec<char> = std::iter::once(']')
+            .chain(std::iter::repeat('=').take(level))
+            .chain(std::iter::once(']'))
+            .collect();
+
+        let mut value = String::new();
+        loop {
+            if self.chars[self.pos..].starts_with(&closing) {
+                self.pos += closing.len();
+                return Ok(value);
+            }
+            match self.bump() {
+                Some(c) => value.push(c),
+                None => return Err(self.error("unfinished long string")),
+            }
+        }
+    }
+
+    fn next_token(&mut self) -> Result<Token, Error> {
+        let Some(c) = self.peek(0) else {
+            return Ok(Token::Eof);
+        };
+
+        if c.is_ascii_alphabetic() || c == '_' {
+            let start = self.pos;
+            while self
+                .peek(0)
+                .map_or(false, |t| t.is_ascii_alphanumeric() || t == '_')
+            {
+                self.pos += 1;
+            }
+            let name: String = self.chars[start..self.pos].iter().collect();
+
+            return Ok(match KEYWORDS.iter().find(|t| **t == name) {
+                Some(keyword) => Token::Symbol(keyword),
+                None => Token::Name(name),
+            });
+        }
+
+        if c.is_ascii_digit() || (c == '.' && self.peek(1).map_or(false, |t| t.is_ascii_digit())) {
+            return self.number();
+        }
+
+        if c == '"' || c == '\'' {
+            return self.quoted_string(c);
+        }
+
+        if c == '[' && self.long_bracket_level().is_some() {
+            return Ok(Token::Str(self.long_string()?));
+        }
+
+        for symbol in SYMBOLS {
+            if self.chars[self.pos..].starts_with(&symbol.chars().collect::<Vec<_>>()) {
+                self.pos += symbol.len();
+                return Ok(Token::Symbol(symbol));
+            }
+        }
+
+        Err(self.error(format!("unexpected symbol '{c}'")))
+    }
+
+    fn number(&mut self) -> Result<Token, Error> {
+        let start = self.pos;
+
+        if self.peek(0) == Some('0') && matches!(self.peek(1), Some('x' | 'X')) {
+            self.pos += 2;
+            while self.peek(0).map_or(false, |t| t.is_ascii_hexdigit()) {
+                self.pos += 1;
+            }
+            let digits: String = self.chars[start + 2..self.pos].iter().collect();
+            return u64::from_str_radix(&digits, 16)
+                .map(|t| Token::Number(t as f64))
+                .map_err(|_| self.error(format!("malformed number '0x{digits}'")));
+        }
+
+        while let Some(c) = self.peek(0) {
+            let is_exponent_sign =
+                matches!(c, '+' | '-') && matches!(self.chars.get(self.pos - 1), Some('e' | 'E'));
+            if c.is_ascii_alphanumeric() || c == '.' || is_exponent_sign {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+
+        let text: String = self.chars[start..self.pos].iter().collect();
+        text.parse()
+            .map(Token::Number)
+            .map_err(|_| self.error(format!("malformed number '{text}'")))
+    }
+
+    fn quoted_string(&mut self, quote: char) -> Result<Token, Error> {
+        self.pos += 1;
+
+        let mut value = String::new();
+        loop {
+            match self.peek(0) {
+                None | Some('\n') => return Err(self.error("unfinished string")),
+                Some(c) if c == quote => {
+                    self.pos += 1;
+                    return Ok(Token::Str(value));
+                }
+                Some('\\') => {
+                    self.pos += 1;
+                    value.push(self.escape()?);
+                }
+                Some(c) => {
+                    self.pos += 1;
+                    value.push(c);
+                }
+            }
+        }
+    }
+
+    fn escape(&mut self) -> Result<char, Error> {
+        let c = self.bump().ok_or_else(|| self.error("unfinished string"))?;
+
+        Ok(match c {
+            'n' => '\n',
+            't' => '\t',
+            'r' => '\r',
+            'a' => '\x07',
+            'b' => '\x08',
+            'f' => '\x0c',
+            'v' => '\x0b',
+            '\\' | '"' | '\'' | '\n' => c,
+            '0'..='9' => {
+                let mut code = c.to_digit(10).unwrap_or_default();
+                for _ in 0..2 {
+                    match self.peek(0).and_then(|t| t.to_digit(10)) {
+                        Some(digit) => {
+                            code = code * 10 + digit;
+                            self.pos += 1;
+                        }
+                        None => break,
+                    }
+                }
+                char::from_u32(code)
+                    .filter(|_| code <= 255)
+                    .ok_or_else(|| self.error("escape sequence too large"))?
+            }
+            _ => return Err(self.error(format!("invalid escape sequence '\\{c}'"))),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokenize() {
+        let tokens: Vec<Token> =
+            tokenize("#!lpm-lua\nlocal s = 'a\\n' .. [[\nb]] -- comment\nx = 0x10 + 1.5e2 ~= ...")
+                .unwrap()
+                .into_iter()
+                .map(|t| t.0)
+                .collect();
+
+        assert_eq!(
+            tokens,
+            [
+                Token::Symbol("local"),
+                Token::Name(String::from("s")),
+                Token::Symbol("="),
+                Token::Str(String::from("a\n")),
+                Token::Symbol(".."),
+                Token::Str(String::from("b")),
+                Token::Name(String::from("x")),
+                Token::Symbol("="),
+                Token::Number(16.0),
+                Token::Symbol("+"),
+                Token::Number(150.0),
+                Token::Symbol("~="),
+                Token::Symbol("..."),
+                Token::Eof,
+            ]
+        );
+
+        let error = tokenize("x = 'open\n").unwrap_err();
+        assert_eq!(error.to_string(), "line 1: unfinished string");
+    }
+}
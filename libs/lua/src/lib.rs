@@ -0,0 +1,216 @@
+//! A small interpreter of Lua 5.1 without dependencies, for package scripts
+//! that run inside lpm instead of through a shell.
+//!
+//! Numbers are doubles and there are no metatables, coroutines or string
+//! patterns: `string.find` and `string.gsub` only search for plain text. Of
+//! the standard library, only the functions that work on values are there,
+//! the host adds what else a script may do with `Lua::set_global`.
+
+mod ast;
+mod interpreter;
+mod lexer;
+mod parser;
+mod stdlib;
+mod value;
+
+pub use interpreter::Lua;
+pub use value::{Args, Builtin, Function, Table, Value};
+
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Error {
+    pub(crate) message: String,
+    /// Line of the script where it happened, unknown for the errors of the
+    /// host functions until they reach the statement that called them.
+    pub(crate) line: Option<usize>,
+}
+
+impl Error {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            line: None,
+        }
+    }
+
+    /// Sets the line, unless it's known already.
+    pub(crate) fn at_line(mut self, line: usize) -> Self {
+        self.line.get_or_insert(line);
+        self
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {line}: {}", self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(source: &str) -> String {
+        let mut lua = Lua::new();
+        lua.exec(source).unwrap();
+        lua.take_output()
+    }
+
+    fn run_err(source: &str) -> String {
+        Lua::new().exec(source).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn test_exec() {
+        assert_eq!(
+            run(r##"
+                local function fib(n)
+                  if n < 2 then return n end
+                  return fib(n - 1) + fib(n - 2)
+                end
+
+                local t = {}
+                for i = 1, 10 do t[#t + 1] = fib(i) end
+                print(table.concat(t, ","), 7 / 2, 2 ^ 10, -7 % 3, "10" + 1)
+
+                local counter = 0
+                local function inc() counter = counter + 1; return counter end
+                inc(); inc()
+                print(counter, select("#", 1, nil, 3), select(2, "a", "b", "c"))
+
+                local sum = 0
+                for _, v in ipairs({3, 4, 5}) do sum = sum + v end
+                local keys = {}
+                for k in pairs({b = 1, a = 2, [1] = 0}) do keys[#keys + 1] = tostring(k) end
+                print(sum, table.concat(keys, " "))
+
+                local i = 0
+                repeat local j = i; i = i + 1 until j >= 2
+                while true do if i > 5 then break end i = i + 1 end
+                print(i, nil == false, 1 == 1.0, "a" < "b", not nil and "yes" or "no")
+            "##),
+            "1,1,2,3,5,8,13,21,34,55\t3.5\t1024\t2\t11\n\
+             2\t3\tb\tc\n\
+             12\t1 a b\n\
+             6\tfalse\ttrue\ttrue\tyes\n"
+        );
+    }
+
+    #[test]
+    fn test_closures_and_varargs() {
+        assert_eq!(
+            run(r##"
+                local adders = {}
+                for i = 1, 3 do adders[i] = function(x) return x + i end end
+                local function pack(...) return {n = select("#", ...), ...} end
+                local p = pack(adders[1](10), adders[3](10))
+                print(p.n, p[1], p[2], unpack({1, 2}))
+
+                local obj = {name = "htop"}
+                function obj:greet(greeting) return greeting .. ", " .. self.name end
+                print(obj:greet("hi"), ("abc"):upper(), #"abc")
+            "##),
+            "2\t11\t13\t1\t2\nhi, htop\tABC\t3\n"
+        );
+    }
+
+    #[test]
+    fn test_string_library() {
+        assert_eq!(
+            run(r##"
+                print(string.format("%5d|%-4s|%.2f|%x|%q|%g", 42, "ab", 3.14159, 255, 'a"b', 0.5))
+                print(("hello world"):sub(1, 5), ("hello"):sub(-3), ("a.b.c"):find(".", 1, true))
+                print(string.rep("ab", 3, "-"), string.gsub("a=1\nb=1", "=1", "=2"))
+                print(string.byte("A"), string.char(72, 105), tonumber("0x10"), tonumber("z", 36))
+            "##),
+            "   42|ab  |3.14|ff|\"a\\\"b\"|0.5\n\
+             hello\tllo\t2\t2\n\
+             ab-ab-ab\ta=2\nb=2\t2\n\
+             65\tHi\t16\t35\n"
+        );
+
+        assert!(run_err(r#"string.find("a.b", ".")"#).contains("patterns are not supported"));
+    }
+
+    #[test]
+    fn test_table_library() {
+        assert_eq!(
+            run(r##"
+                local t = {5, 2, 8, 1}
+                table.sort(t)
+                table.insert(t, 1, 0)
+                table.insert(t, 9)
+                print(table.concat(t, " "), table.remove(t), table.remove(t, 1), #t)
+                table.sort(t, function(a, b) return a > b end)
+                print(table.concat(t, " "))
+            "##),
+            "0 1 2 5 8 9\t9\t0\t4\n8 5 2 1\n"
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            run_err("local x = 1\nlocal y = x + nil"),
+            "line 2: attempt to perform arithmetic on a nil value"
+        );
+        assert_eq!(
+            run_err("\n\nmissing()"),
+            "line 3: attempt to call a nil value (global 'missing')"
+        );
+        assert_eq!(
+            run_err("local t = {}\nprint(t.a.b)"),
+            "line 2: attempt to index a nil value (field 'a')"
+        );
+        assert_eq!(
+            run_err("local function f()\n  error('failed')\nend\nf()"),
+            "line 2: failed"
+        );
+        assert_eq!(
+            run_err("string.sub()"),
+            "line 1: bad argument #1 to 'sub' (string expected, got no value)"
+        );
+        assert_eq!(
+            run("print(pcall(function() error('oops') end))\nprint(pcall(tostring, 1))"),
+            "false\tline 1: oops\ntrue\t1\n"
+        );
+
+        assert_eq!(
+            run_err("local function f() return f() + 1 end\nf()"),
+            "line 1: stack overflow"
+        );
+        assert_eq!(
+            run_err("while true do end"),
+            "line 1: script runs for too long"
+        );
+    }
+
+    #[test]
+    fn test_host_functions() {
+        let mut lua = Lua::new();
+        let pkg = Table::new();
+        pkg.set("name", "htop").unwrap();
+        lua.set_global("pkg", pkg);
+        lua.set_global(
+            "double",
+            Value::function("double", |_, args| Ok(vec![(args.number(0)? * 2.0).into()])),
+        );
+
+        let results = lua.exec("return double(21), pkg.name, io, os").unwrap();
+        assert_eq!(
+            results,
+            [
+                Value::from(42.0),
+                Value::from("htop"),
+                Value::Nil,
+                Value::Nil
+            ]
+        );
+    }
+}
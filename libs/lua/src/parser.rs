@@ -0,0 +1,561 @@
+use crate::{
+    ast::{BinOp, Block, Expr, FunctionDef, Stat, StatKind, UnOp},
+    lexer::{tokenize, Token},
+    Error,
+};
+use std::rc::Rc;
+
+/// Priority of the unary operators, between `*` and `^`.
+const UNARY_PRIORITY: u8 = 8;
+/// Blocks and expressions that can be nested in each other, as the parser
+/// and the interpreter recurse into them.
+const MAX_NESTING: usize = 100;
+
+pub(crate) fn parse(source: &str) -> Result<Block, Error> {
+    let mut parser = Parser {
+        tokens: tokenize(source)?,
+        pos: 0,
+        loop_depth: 0,
+        nesting: 0,
+        // The arguments of a chunk are `...`.
+        is_vararg: true,
+    };
+
+    let block = parser.block()?;
+    if parser.peek() != &Token::Eof {
+        return Err(parser.unexpected("'<eof>'"));
+    }
+
+    Ok(block)
+}
+
+/// Left and right priorities of the binary operator `symbol`, the right one
+/// is lower for the right associative ones.
+fn binary_op(symbol: &str) -> Option<(BinOp, u8, u8)> {
+    Some(match symbol {
+        "or" => (BinOp::Or, 1, 1),
+        "and" => (BinOp::And, 2, 2),
+        "<" => (BinOp::Lt, 3, 3),
+        ">" => (BinOp::Gt, 3, 3),
+        "<=" => (BinOp::Le, 3, 3),
+        ">=" => (BinOp::Ge, 3, 3),
+        "~=" => (BinOp::Ne, 3, 3),
+        "==" => (BinOp::Eq, 3, 3),
+        ".." => (BinOp::Concat, 5, 4),
+        "+" => (BinOp::Add, 6, 6),
+        "-" => (BinOp::Sub, 6, 6),
+        "*" => (BinOp::Mul, 7, 7),
+        "/" => (BinOp::Div, 7, 7),
+        "%" => (BinOp::Mod, 7, 7),
+        "^" => (BinOp::Pow, 10, 9),
+        _ => return None,
+    })
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+    /// Loops around the current position within its function, which `break`
+    /// needs one of.
+    loop_depth: usize,
+    /// Whether the current function can use `...`.
+    is_vararg: bool,
+    nesting: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos].0
+    }
+
+    fn line(&self) -> usize {
+        self.tokens[self.pos].1
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.tokens[self.pos].0.clone();
+        if token != Token::Eof {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn is_symbol(&self, symbol: &str) -> bool {
+        matches!(self.peek(), Token::Symbol(t) if *t == symbol)
+    }
+
+    fn accept(&mut self, symbol: &str) -> bool {
+        let is_symbol = self.is_symbol(symbol);
+        if is_symbol {
+            self.pos += 1;
+        }
+        is_symbol
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), Error> {
+        match self.accept(symbol) {
+            true => Ok(()),
+            false => Err(self.unexpected(&format!("'{symbol}'"))),
+        }
+    }
+
+    fn near(&self) -> String {
+        match self.peek() {
+            Token::Name(name) => format!("'{name}'"),
+            Token::Number(number) => format!("'{number}'"),
+            Token::Str(_) => String::from("string"),
+            Token::Symbol(symbol) => format!("'{symbol}'"),
+            Token::Eof => String::from("'<eof>'"),
+        }
+    }
+
+    fn unexpected(&self, expected: &str) -> Error {
+        Error::new(format!("{expected} expected near {}", self.near())).at_line(self.line())
+    }
+
+    fn name(&mut self) -> Result<String, Error> {
+        match self.peek() {
+            Token::Name(_) => match self.next() {
+                Token::Name(name) => Ok(name),
+                _ => unreachable!(),
+            },
+            _ => Err(self.unexpected("name")),
+        }
+    }
+
+    fn is_block_end(&self) -> bool {
+        matches!(
+            self.peek(),
+            Token::Eof | Token::Symbol("else" | "elseif" | "end" | "until")
+        )
+    }
+
+    fn enter(&mut self) -> Result<(), Error> {
+        self.nesting += 1;
+        match self.nesting > MAX_NESTING {
+            true => Err(Error::new("chunk has too many syntax levels").at_line(self.line())),
+            false => Ok(()),
+        }
+    }
+
+    fn block(&mut self) -> Result<Block, Error> {
+        self.enter()?;
+        let block = self.statements();
+        self.nesting -= 1;
+        block
+    }
+
+    fn statements(&mut self) -> Result<Block, Error> {
+        let mut block = Vec::new();
+
+        while !self.is_block_end() {
+            if self.accept(";") {
+                continue;
+            }
+
+            let line = self.line();
+            if self.accept("return") {
+                let values = match self.is_block_end() || self.is_symbol(";") {
+                    true => Vec::new(),
+                    false => self.expr_list()?,
+                };
+                self.accept(";");
+                block.push(Stat {
+                    kind: StatKind::Return(values),
+                    line,
+                });
+                if !self.is_block_end() {
+                    return Err(self.unexpected("'<eof>'"));
+                }
+                break;
+            }
+
+            let kind = self.statement()?;
+            block.push(Stat { kind, line });
+        }
+
+        Ok(block)
+    }
+
+    fn loop_body(&mut self) -> Result<Block, Error> {
+        self.loop_depth += 1;
+        let block = self.block();
+        self.loop_depth -= 1;
+
+        block
+    }
+
+    fn statement(&mut self) -> Result<StatKind, Error> {
+        if self.is_symbol("break") {
+            if self.loop_depth == 0 {
+                return Err(Error::new("no loop to break").at_line(self.line()));
+            }
+            self.next();
+            return Ok(StatKind::Break);
+        }
+
+        if self.accept("do") {
+            let block = self.block()?;
+            self.expect("end")?;
+            return Ok(StatKind::Do(block));
+        }
+
+        if self.accept("while") {
+            let condition = self.expr()?;
+            self.expect("do")?;
+            let block = self.loop_body()?;
+            self.expect("end")?;
+            return Ok(StatKind::While(condition, block));
+        }
+
+        if self.accept("repeat") {
+            let block = self.loop_body()?;
+            self.expect("until")?;
+            return Ok(StatKind::Repeat(block, self.expr()?));
+        }
+
+        if self.accept("if") {
+            let mut branches = Vec::new();
+            loop {
+                let condition = self.expr()?;
+                self.expect("then")?;
+                branches.push((condition, self.block()?));
+                if !self.accept("elseif") {
+                    break;
+                }
+            }
+            let otherwise = match self.accept("else") {
+                true => Some(self.block()?),
+                false => None,
+            };
+            self.expect("end")?;
+            return Ok(StatKind::If(branches, otherwise));
+        }
+
+        if self.accept("for") {
+            return self.for_statement();
+        }
+
+        if self.accept("function") {
+            let mut target = Expr::Name(self.name()?);
+            while self.accept(".") {
+                target = Expr::Index(Box::new(target), Box::new(Expr::Str(self.name()?.into())));
+            }
+            let is_method = self.accept(":");
+            if is_method {
+                target = Expr::Index(Box::new(target), Box::new(Expr::Str(self.name()?.into())));
+            }
+
+            let function = self.function_body(is_method)?;
+            return Ok(StatKind::Assign(
+                vec![target],
+                vec![Expr::Function(function)],
+            ));
+        }
+
+        if self.accept("local") {
+            if self.accept("function") {
+                let name = self.name()?;
+                return Ok(StatKind::LocalFunction(name, self.function_body(false)?));
+            }
+
+            let mut names = vec![self.name()?];
+            while self.accept(",") {
+                names.push(self.name()?);
+            }
+            let values = match self.accept("=") {
+                true => self.expr_list()?,
+                false => Vec::new(),
+            };
+            return Ok(StatKind::Local(names, values));
+        }
+
+        let expr = self.suffixed_expr()?;
+        if self.is_symbol("=") || self.is_symbol(",") {
+            let mut targets = vec![expr];
+            while self.accept(",") {
+                targets.push(self.suffixed_expr()?);
+            }
+            if targets
+                .iter()
+                .any(|t| !matches!(t, Expr::Name(_) | Expr::Index(..)))
+            {
+                return Err(Error::new("cannot assign to this expression").at_line(self.line()));
+            }
+            self.expect("=")?;
+            return Ok(StatKind::Assign(targets, self.expr_list()?));
+        }
+
+        match expr {
+            Expr::Call(..) | Expr::Method(..) => Ok(StatKind::Call(expr)),
+            _ => Err(self.unexpected("'='")),
+        }
+    }
+
+    fn for_statement(&mut self) -> Result<StatKind, Error> {
+        let first = self.name()?;
+
+        if self.accept("=") {
+            let start = self.expr()?;
+            self.expect(",")?;
+            let limit = self.expr()?;
+            let step = match self.accept(",") {
+                true => Some(self.expr()?),
+                false => None,
+            };
+            self.expect("do")?;
+            let body = self.loop_body()?;
+            self.expect("end")?;
+            return Ok(StatKind::NumericFor {
+                var: first,
+                start,
+                limit,
+                step,
+                body,
+            });
+        }
+
+        let mut names = vec![first];
+        while self.accept(",") {
+            names.push(self.name()?);
+        }
+        self.expect("in")?;
+        let values = self.expr_list()?;
+        self.expect("do")?;
+        let body = self.loop_body()?;
+        self.expect("end")?;
+
+        Ok(StatKind::GenericFor(names, values, body))
+    }
+
+    fn function_body(&mut self, is_method: bool) -> Result<Rc<FunctionDef>, Error> {
+        let mut params = Vec::new();
+        if is_method {
+            params.push(String::from("self"));
+        }
+
+        let mut is_vararg = false;
+        self.expect("(")?;
+        if !self.is_symbol(")") {
+            loop {
+                if self.accept("...") {
+                    is_vararg = true;
+                    break;
+                }
+                params.push(self.name()?);
+                if !self.accept(",") {
+                    break;
+                }
+            }
+        }
+        self.expect(")")?;
+
+        let outer = (self.loop_depth, self.is_vararg);
+        (self.loop_depth, self.is_vararg) = (0, is_vararg);
+        let body = self.block();
+        (self.loop_depth, self.is_vararg) = outer;
+        let body = body?;
+        self.expect("end")?;
+
+        Ok(Rc::new(FunctionDef {
+            params,
+            is_vararg,
+            body,
+        }))
+    }
+
+    fn expr_list(&mut self) -> Result<Vec<Expr>, Error> {
+        let mut exprs = vec![self.expr()?];
+        while self.accept(",") {
+            exprs.push(self.expr()?);
+        }
+        Ok(exprs)
+    }
+
+    fn expr(&mut self) -> Result<Expr, Error> {
+        self.sub_expr(0)
+    }
+
+    fn sub_expr(&mut self, limit: u8) -> Result<Expr, Error> {
+        self.enter()?;
+        let expr = self.operators(limit);
+        self.nesting -= 1;
+        expr
+    }
+
+    fn operators(&mut self, limit: u8) -> Result<Expr, Error> {
+        let unary = match self.peek() {
+            Token::Symbol("not") => Some(UnOp::Not),
+            Token::Symbol("-") => Some(UnOp::Neg),
+            Token::Symbol("#") => Some(UnOp::Len),
+            _ => None,
+        };
+
+        let mut expr = match unary {
+            Some(op) => {
+                self.next();
+                Expr::Unary(op, Box::new(self.sub_expr(UNARY_PRIORITY)?))
+            }
+            None => self.simple_expr()?,
+        };
+
+        while let Token::Symbol(symbol) = self.peek() {
+            let Some((op, left, right)) = binary_op(symbol) else {
+                break;
+            };
+            if left <= limit {
+                break;
+            }
+
+            self.next();
+            let rhs = self.sub_expr(right)?;
+            expr = Expr::Binary(op, Box::new(expr), Box::new(rhs));
+        }
+
+        Ok(expr)
+    }
+
+    fn simple_expr(&mut self) -> Result<Expr, Error> {
+        let expr = match self.peek() {
+            Token::Number(number) => Expr::Number(*number),
+            Token::Str(value) => Expr::Str(value.as_str().into()),
+            Token::Symbol("nil") => Expr::Nil,
+            Token::Symbol("true") => Expr::Boolean(true),
+            Token::Symbol("false") => Expr::Boolean(false),
+            Token::Symbol("...") if self.is_vararg => Expr::Vararg,
+            Token::Symbol("...") => {
+                return Err(
+                    Error::new("cannot use '...' outside a vararg function").at_line(self.line())
+                )
+            }
+            Token::Symbol("{") => return self.table(),
+            Token::Symbol("function") => {
+                self.next();
+                return Ok(Expr::Function(self.function_body(false)?));
+            }
+            _ => return self.suffixed_expr(),
+        };
+
+        self.next();
+        Ok(expr)
+    }
+
+    fn primary_expr(&mut self) -> Result<Expr, Error> {
+        if self.accept("(") {
+            let expr = self.expr()?;
+            self.expect(")")?;
+            return Ok(Expr::Paren(Box::new(expr)));
+        }
+
+        match self.peek() {
+            Token::Name(_) => Ok(Expr::Name(self.name()?)),
+            _ => Err(
+                Error::new(format!("unexpected symbol near {}", self.near())).at_line(self.line()),
+            ),
+        }
+    }
+
+    fn suffixed_expr(&mut self) -> Result<Expr, Error> {
+        let mut expr = self.primary_expr()?;
+
+        loop {
+            if self.accept(".") {
+                let key = Expr::Str(self.name()?.into());
+                expr = Expr::Index(Box::new(expr), Box::new(key));
+            } else if self.accept("[") {
+                let key = self.expr()?;
+                self.expect("]")?;
+                expr = Expr::Index(Box::new(expr), Box::new(key));
+            } else if self.accept(":") {
+                let name = self.name()?;
+                let args = self.call_args()?;
+                expr = Expr::Method(Box::new(expr), name, args);
+            } else if matches!(self.peek(), Token::Str(_) | Token::Symbol("(" | "{")) {
+                let args = self.call_args()?;
+                expr = Expr::Call(Box::new(expr), args);
+            } else {
+                return Ok(expr);
+            }
+        }
+    }
+
+    fn call_args(&mut self) -> Result<Vec<Expr>, Error> {
+        match self.peek() {
+            Token::Str(_) => match self.next() {
+                Token::Str(value) => Ok(vec![Expr::Str(value.into())]),
+                _ => unreachable!(),
+            },
+            Token::Symbol("{") => Ok(vec![self.table()?]),
+            _ => {
+                self.expect("(")?;
+                if self.accept(")") {
+                    return Ok(Vec::new());
+                }
+                let args = self.expr_list()?;
+                self.expect(")")?;
+                Ok(args)
+            }
+        }
+    }
+
+    fn table(&mut self) -> Result<Expr, Error> {
+        self.expect("{")?;
+
+        let mut fields = Vec::new();
+        while !self.accept("}") {
+            if self.accept("[") {
+                let key = self.expr()?;
+                self.expect("]")?;
+                self.expect("=")?;
+                fields.push((Some(key), self.expr()?));
+            } else if matches!(self.peek(), Token::Name(_))
+                && matches!(self.tokens.get(self.pos + 1), Some((Token::Symbol("="), _)))
+            {
+                let key = Expr::Str(self.name()?.into());
+                self.next();
+                fields.push((Some(key), self.expr()?));
+            } else {
+                fields.push((None, self.expr()?));
+            }
+
+            if !self.accept(",") && !self.accept(";") {
+                self.expect("}")?;
+                break;
+            }
+        }
+
+        Ok(Expr::Table(fields))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_errors() {
+        let error = |source: &str| parse(source).unwrap_err().to_string();
+
+        assert_eq!(error("x = "), "line 1: unexpected symbol near '<eof>'");
+        assert_eq!(
+            error("if x then\n  y = 1\n"),
+            "line 3: 'end' expected near '<eof>'"
+        );
+        assert_eq!(error("f() = 1"), "line 1: cannot assign to this expression");
+        assert_eq!(
+            error("return 1\nx = 2"),
+            "line 2: '<eof>' expected near 'x'"
+        );
+        assert_eq!(error("if x then break end"), "line 1: no loop to break");
+        assert_eq!(
+            error("function f() return ... end"),
+            "line 1: cannot use '...' outside a vararg function"
+        );
+        assert_eq!(
+            error(&format!("x = {}1{}", "(".repeat(200), ")".repeat(200))),
+            "line 1: chunk has too many syntax levels"
+        );
+        assert!(parse("while x do local f = function(...) return ... end break end").is_ok());
+        assert!(parse("local t = {1, 2; x = 3, ['y'] = 4,}\nt.x:f 'a' {b = 1}").is_ok());
+    }
+}
@@ -0,0 +1,545 @@
+//! The parts of the standard library that only work on values: nothing here
+//! reaches files, processes or the environment.
+
+use crate::{
+    ast::BinOp,
+    interpreter::binary,
+    value::{format_general, Args, Table, Value},
+    Error, Lua,
+};
+
+/// Longest string `string.rep` builds.
+const MAX_STRING_LEN: usize = 64 * 1024 * 1024;
+/// Characters that make a pattern more than plain text.
+const PATTERN_SPECIALS: &str = "^$()%.[]*+-?";
+
+pub(crate) fn open(globals: &Table) {
+    let set = |table: &Table, name: &str, value: Value| {
+        // Only fails for nil and NaN keys.
+        let _ = table.set(name, value);
+    };
+    let function =
+        |table: &Table, name: &str, f: fn(&mut Lua, Args) -> Result<Vec<Value>, Error>| {
+            set(table, name, Value::function(name, f));
+        };
+
+    function(globals, "print", print);
+    function(globals, "type", |_, args| {
+        if args.is_empty() {
+            return Err(Error::new("bad argument #1 to 'type' (value expected)"));
+        }
+        Ok(vec![Value::from(args.get(0).type_name())])
+    });
+    function(globals, "tostring", |_, args| {
+        Ok(vec![Value::from(args.get(0).to_string())])
+    });
+    function(globals, "tonumber", tonumber);
+    function(globals, "error", |_, args| {
+        Err(Error::new(args.get(0).to_string()))
+    });
+    function(globals, "assert", |_, args| {
+        if args.get(0).is_truthy() {
+            return Ok(args.into_values());
+        }
+        Err(Error::new(match args.get(1) {
+            Value::Nil => String::from("assertion failed!"),
+            message => message.to_string(),
+        }))
+    });
+    function(globals, "pcall", |lua, args| {
+        let mut values = args.into_values().into_iter();
+        let function = values.next().unwrap_or_default();
+        Ok(match lua.call(&function, values.collect()) {
+            Ok(results) => std::iter::once(Value::Boolean(true))
+                .chain(results)
+                .collect(),
+            Err(e) => vec![Value::Boolean(false), Value::from(e.to_string())],
+        })
+    });
+    function(globals, "select", select);
+    function(globals, "unpack", unpack);
+
+    let next = Value::function("next", |_, args| {
+        Ok(match args.table(0)?.next(&args.get(1))? {
+            Some((key, value)) => vec![key, value],
+            None => vec![Value::Nil],
+        })
+    });
+    set(globals, "next", next.clone());
+    set(
+        globals,
+        "pairs",
+        Value::function("pairs", move |_, args| {
+            Ok(vec![next.clone(), args.table(0)?.into(), Value::Nil])
+        }),
+    );
+    let ipairs_next = Value::function("ipairs", |_, args| {
+        let index = args.integer(1)? + 1;
+        Ok(match args.table(0)?.get(index) {
+            Value::Nil => vec![Value::Nil],
+            value => vec![index.into(), value],
+        })
+    });
+    set(
+        globals,
+        "ipairs",
+        Value::function("ipairs", move |_, args| {
+            Ok(vec![ipairs_next.clone(), args.table(0)?.into(), 0.into()])
+        }),
+    );
+
+    let string = Table::new();
+    function(&string, "len", |_, args| {
+        Ok(vec![(args.string(0)?.len() as i64).into()])
+    });
+    function(&string, "sub", |_, args| {
+        let value = args.string(0)?;
+        let (start, end) = byte_range(
+            value.len(),
+            args.optional_integer(1)?.unwrap_or(1),
+            args.optional_integer(2)?.unwrap_or(-1),
+        );
+        Ok(vec![String::from_utf8_lossy(&value.as_bytes()[start..end])
+            .into_owned()
+            .into()])
+    });
+    function(&string, "upper", |_, args| {
+        Ok(vec![args.string(0)?.to_uppercase().into()])
+    });
+    function(&string, "lower", |_, args| {
+        Ok(vec![args.string(0)?.to_lowercase().into()])
+    });
+    function(&string, "rep", |_, args| {
+        let value = args.string(0)?;
+        let count = args.integer(1)?.max(0) as usize;
+        let separator = args.optional_string(2)?.unwrap_or_else(|| "".into());
+        if (value.len() + separator.len()).saturating_mul(count) > MAX_STRING_LEN {
+            return Err(Error::new("resulting string too large"));
+        }
+        Ok(vec![vec![&*value; count].join(&separator).into()])
+    });
+    function(&string, "byte", |_, args| {
+        let value = args.string(0)?;
+        let start = args.optional_integer(1)?.unwrap_or(1);
+        let end = args.optional_integer(2)?.unwrap_or(start);
+        let (start, end) = byte_range(value.len(), start, end);
+        Ok(value.as_bytes()[start..end]
+            .iter()
+            .map(|t| i64::from(*t).into())
+            .collect())
+    });
+    function(&string, "char", |_, args| {
+        let bytes = (0..args.len())
+            .map(|index| {
+                u8::try_from(args.integer(index)?).map_err(|_| {
+                    Error::new(format!(
+                        "bad argument #{} to 'char' (value out of range)",
+                        index + 1
+                    ))
+                })
+            })
+            .collect::<Result<Vec<u8>, Error>>()?;
+        Ok(vec![String::from_utf8_lossy(&bytes).into_owned().into()])
+    });
+    function(&string, "format", format);
+    function(&string, "find", find);
+    function(&string, "gsub", gsub);
+    set(globals, "string", string.into());
+
+    let table = Table::new();
+    function(&table, "insert", |_, args| {
+        let table = args.table(0)?;
+        match args.len() {
+            2 => table.push(args.get(1)),
+            3 => {
+                let index = args.integer(1)?;
+                if index < 1 || index as usize > table.len() + 1 {
+                    return Err(Error::new(
+                        "bad argument #2 to 'insert' (position out of bounds)",
+                    ));
+                }
+                table.insert(index as usize, args.get(2));
+            }
+            _ => return Err(Error::new("wrong number of arguments to 'insert'")),
+        }
+        Ok(Vec::new())
+    });
+    function(&table, "remove", |_, args| {
+        let table = args.table(0)?;
+        let index = args.optional_integer(1)?.unwrap_or(table.len() as i64);
+        Ok(vec![match index < 1 {
+            true => Value::Nil,
+            false => table.remove(index as usize),
+        }])
+    });
+    function(&table, "concat", concat);
+    function(&table, "sort", sort);
+    set(globals, "table", table.into());
+
+    let math = Table::new();
+    function(&math, "floor", |_, args| {
+        Ok(vec![args.number(0)?.floor().into()])
+    });
+    function(&math, "ceil", |_, args| {
+        Ok(vec![args.number(0)?.ceil().into()])
+    });
+    function(&math, "abs", |_, args| {
+        Ok(vec![args.number(0)?.abs().into()])
+    });
+    function(&math, "max", |_, args| {
+        let mut max = args.number(0)?;
+        for index in 1..args.len() {
+            max = max.max(args.number(index)?);
+        }
+        Ok(vec![max.into()])
+    });
+    function(&math, "min", |_, args| {
+        let mut min = args.number(0)?;
+        for index in 1..args.len() {
+            min = min.min(args.number(index)?);
+        }
+        Ok(vec![min.into()])
+    });
+    set(&math, "huge", f64::INFINITY.into());
+    set(&math, "pi", std::f64::consts::PI.into());
+    set(globals, "math", math.into());
+}
+
+fn print(lua: &mut Lua, args: Args) -> Result<Vec<Value>, Error> {
+    let line: Vec<String> = args.into_values().iter().map(Value::to_string).collect();
+    lua.write_output(&format!("{}\n", line.join("\t")));
+
+    Ok(Vec::new())
+}
+
+fn tonumber(_: &mut Lua, args: Args) -> Result<Vec<Value>, Error> {
+    let Some(base) = args.optional_integer(1)? else {
+        return Ok(vec![args.get(0).to_number().into()]);
+    };
+    if !(2..=36).contains(&base) {
+        return Err(Error::new(
+            "bad argument #2 to 'tonumber' (base out of range)",
+        ));
+    }
+
+    let value = args.string(0)?;
+    Ok(vec![i64::from_str_radix(value.trim(), base as u32)
+        .ok()
+        .into()])
+}
+
+fn select(_: &mut Lua, args: Args) -> Result<Vec<Value>, Error> {
+    let count = args.len() as i64 - 1;
+    if args.get(0).to_str().as_deref() == Some("#") {
+        return Ok(vec![count.into()]);
+    }
+
+    let index = args.integer(0)?;
+    let skip = match index {
+        _ if index < 0 && -index <= count => count + index,
+        _ if index > 0 => index - 1,
+        _ => {
+            return Err(Error::new(
+                "bad argument #1 to 'select' (index out of range)",
+            ))
+        }
+    };
+    Ok(args
+        .into_values()
+        .into_iter()
+        .skip(1 + skip as usize)
+        .collect())
+}
+
+fn unpack(_: &mut Lua, args: Args) -> Result<Vec<Value>, Error> {
+    let table = args.table(0)?;
+    let start = args.optional_integer(1)?.unwrap_or(1);
+    let end = args.optional_integer(2)?.unwrap_or(table.len() as i64);
+
+    Ok((start..=end).map(|index| table.get(index)).collect())
+}
+
+/// Byte offsets of the Lua string indices `start..=end`, which count from
+/// the end if negative.
+fn byte_range(len: usize, start: i64, end: i64) -> (usize, usize) {
+    let len = len as i64;
+    let absolute = |index: i64| if index < 0 { len + index + 1 } else { index };
+    let start = absolute(start).max(1);
+    let end = absolute(end).min(len);
+
+    match start > end {
+        true => (0, 0),
+        false => (start as usize - 1, end as usize),
+    }
+}
+
+fn check_plain(function: &str, pattern: &str) -> Result<(), Error> {
+    match pattern.contains(|t| PATTERN_SPECIALS.contains(t)) {
+        true => Err(Error::new(format!(
+            "bad argument #2 to '{function}' (patterns are not supported, \
+             only plain text can be searched for)"
+        ))),
+        false => Ok(()),
+    }
+}
+
+/// `string.find` of plain text, which a true 4th argument asks for. Patterns
+/// without special characters are the same as plain text.
+fn find(_: &mut Lua, args: Args) -> Result<Vec<Value>, Error> {
+    let value = args.string(0)?;
+    let needle = args.string(1)?;
+    if !args.get(3).is_truthy() {
+        check_plain("find", &needle)?;
+    }
+
+    let (start, _) = byte_range(value.len(), args.optional_integer(2)?.unwrap_or(1), -1);
+    let found = value
+        .get(start..)
+        .and_then(|rest| rest.find(&*needle))
+        .map(|offset| start + offset);
+
+    Ok(match found {
+        Some(offset) => vec![
+            ((offset + 1) as i64).into(),
+            ((offset + needle.len()) as i64).into(),
+        ],
+        None => vec![Value::Nil],
+    })
+}
+
+/// `string.gsub` of plain text, with a string replacement in which `%%` is
+/// the only escape.
+fn gsub(_: &mut Lua, args: Args) -> Result<Vec<Value>, Error> {
+    let value = args.string(0)?;
+    let needle = args.string(1)?;
+    let replacement = args.string(2)?;
+    let limit = args.optional_integer(3)?;
+    check_plain("gsub", &needle)?;
+    if replacement.replace("%%", "").contains('%') {
+        return Err(Error::new(
+            "bad argument #3 to 'gsub' (captures are not supported, use '%%' for '%')",
+        ));
+    }
+    let replacement = replacement.replace("%%", "%");
+
+    if needle.is_empty() {
+        return Ok(vec![Value::Str(value), 0.into()]);
+    }
+
+    let mut result = String::new();
+    let mut count = 0;
+    let mut rest = &*value;
+    while let Some(offset) = rest.find(&*needle) {
+        if limit.map_or(false, |limit| count >= limit) {
+            break;
+        }
+        result.push_str(&rest[..offset]);
+        result.push_str(&replacement);
+        rest = &rest[offset + needle.len()..];
+        count += 1;
+    }
+    result.push_str(rest);
+
+    Ok(vec![result.into(), count.into()])
+}
+
+fn pad(text: String, width: usize, left: bool, zero: bool) -> String {
+    if text.len() >= width {
+        return text;
+    }
+
+    let fill = width - text.len();
+    match (left, zero) {
+        (true, _) => format!("{text}{}", " ".repeat(fill)),
+        (false, true) => match text.strip_prefix('-') {
+            Some(digits) => format!("-{}{digits}", "0".repeat(fill)),
+            None => format!("{}{text}", "0".repeat(fill)),
+        },
+        (false, false) => format!("{}{text}", " ".repeat(fill)),
+    }
+}
+
+/// `string.format` with the `-`, `0`, width and precision options of the
+/// `d`, `i`, `x`, `X`, `f`, `e`, `g`, `s`, `q` and `c` conversions.
+fn format(_: &mut Lua, args: Args) -> Result<Vec<Value>, Error> {
+    let template = args.string(0)?;
+    let mut chars = template.chars().peekable();
+    let mut result = String::new();
+    let mut next_arg = 1;
+
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            result.push(c);
+            continue;
+        }
+        if chars.peek() == Some(&'%') {
+            chars.next();
+            result.push('%');
+            continue;
+        }
+
+        let (mut left, mut zero, mut plus) = (false, false, false);
+        while let Some(flag) = chars.next_if(|t| "-0+ #".contains(*t)) {
+            match flag {
+                '-' => left = true,
+                '0' => zero = true,
+                '+' => plus = true,
+                _ => {}
+            }
+        }
+        let mut width = 0;
+        while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+            width = width * 10 + digit.to_digit(10).unwrap_or_default() as usize;
+        }
+        let precision = match chars.next_if_eq(&'.') {
+            Some(_) => {
+                let mut precision = 0;
+                while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+                    precision = precision * 10 + digit.to_digit(10).unwrap_or_default() as usize;
+                }
+                Some(precision)
+            }
+            None => None,
+        };
+
+        let index = next_arg;
+        next_arg += 1;
+        let sign = |text: String, number: f64| match plus && number >= 0.0 {
+            true => format!("+{text}"),
+            false => text,
+        };
+
+        let text = match chars.next() {
+            Some('d' | 'i') => {
+                let number = args.integer(index)?;
+                sign(number.to_string(), number as f64)
+            }
+            Some('x') => format!("{:x}", args.integer(index)?),
+            Some('X') => format!("{:X}", args.integer(index)?),
+            Some('c') => {
+                let code = args.integer(index)?;
+                char::from_u32(code as u32).unwrap_or_default().to_string()
+            }
+            Some('f') => {
+                let number = args.number(index)?;
+                sign(format!("{:.*}", precision.unwrap_or(6), number), number)
+            }
+            Some('e') => {
+                let number = args.number(index)?;
+                let text = format!("{:.*e}", precision.unwrap_or(6), number);
+                let (mantissa, exponent) = text.split_once('e').unwrap_or((&text, "0"));
+                let exponent: i32 = exponent.parse().unwrap_or_default();
+                let exponent_sign = if exponent < 0 { '-' } else { '+' };
+                sign(
+                    format!("{mantissa}e{exponent_sign}{:02}", exponent.abs()),
+                    number,
+                )
+            }
+            Some('g') => {
+                let number = args.number(index)?;
+                sign(format_general(number, precision.unwrap_or(6)), number)
+            }
+            Some('s') => {
+                let text = args.get(index).to_string();
+                match precision {
+                    Some(precision) => text.chars().take(precision).collect(),
+                    None => text,
+                }
+            }
+            Some('q') => {
+                let text = args.string(index)?;
+                let mut quoted = String::from("\"");
+                for c in text.chars() {
+                    match c {
+                        '"' => quoted.push_str("\\\""),
+                        '\\' => quoted.push_str("\\\\"),
+                        '\n' => quoted.push_str("\\n"),
+                        '\r' => quoted.push_str("\\r"),
+                        '\0' => quoted.push_str("\\0"),
+                        c => quoted.push(c),
+                    }
+                }
+                quoted.push('"');
+                quoted
+            }
+            other => {
+                return Err(Error::new(format!(
+                    "invalid conversion '%{}' to 'format'",
+                    other.map(String::from).unwrap_or_default()
+                )))
+            }
+        };
+
+        result.push_str(&pad(text, width, left, zero));
+    }
+
+    Ok(vec![result.into()])
+}
+
+fn concat(_: &mut Lua, args: Args) -> Result<Vec<Value>, Error> {
+    let table = args.table(0)?;
+    let separator = args.optional_string(1)?.unwrap_or_else(|| "".into());
+    let start = args.optional_integer(2)?.unwrap_or(1);
+    let end = args.optional_integer(3)?.unwrap_or(table.len() as i64);
+
+    let mut items = Vec::new();
+    for index in start..=end {
+        let item = table.get(index).to_str().ok_or_else(|| {
+            Error::new(format!(
+                "invalid value (at index {index}) in table for 'concat'"
+            ))
+        })?;
+        items.push(item);
+    }
+
+    Ok(vec![items.join(&separator).into()])
+}
+
+fn sort(lua: &mut Lua, args: Args) -> Result<Vec<Value>, Error> {
+    let table = args.table(0)?;
+    let comparator = args.get(1);
+
+    let mut less = |a: &Value, b: &Value| -> Result<bool, Error> {
+        match &comparator {
+            Value::Nil => Ok(binary(BinOp::Lt, a, b)?.is_truthy()),
+            comparator => Ok(lua
+                .call(comparator, vec![a.clone(), b.clone()])?
+                .first()
+                .map_or(false, Value::is_truthy)),
+        }
+    };
+
+    let sorted = merge_sort(table.list(), &mut less)?;
+    table.set_list(sorted);
+
+    Ok(Vec::new())
+}
+
+/// Sorts with a comparator that may fail, or may not be consistent.
+fn merge_sort(
+    mut values: Vec<Value>,
+    less: &mut dyn FnMut(&Value, &Value) -> Result<bool, Error>,
+) -> Result<Vec<Value>, Error> {
+    if values.len() <= 1 {
+        return Ok(values);
+    }
+
+    let right = values.split_off(values.len() / 2);
+    let left = merge_sort(values, less)?;
+    let right = merge_sort(right, less)?;
+
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    let (mut left, mut right) = (left.into_iter().peekable(), right.into_iter().peekable());
+    while let (Some(a), Some(b)) = (left.peek(), right.peek()) {
+        // Equal values keep their order.
+        let next = match less(b, a)? {
+            true => right.next(),
+            false => left.next(),
+        };
+        merged.extend(next);
+    }
+    merged.extend(left);
+    merged.extend(right);
+
+    Ok(merged)
+}
@@ -0,0 +1,616 @@
+use crate::{ast::FunctionDef, interpreter::Scope, Error, Lua};
+use std::{cell::RefCell, cmp::Ordering, collections::BTreeMap, fmt, rc::Rc};
+
+pub type Builtin = dyn Fn(&mut Lua, Args) -> Result<Vec<Value>, Error>;
+
+#[derive(Clone, Default)]
+pub enum Value {
+    #[default]
+    Nil,
+    Boolean(bool),
+    Number(f64),
+    Str(Rc<str>),
+    Table(Table),
+    Function(Function),
+}
+
+impl Value {
+    /// Wraps `f` as a function named `name`, which is how the errors of its
+    /// arguments refer to it.
+    pub fn function(
+        name: &str,
+        f: impl Fn(&mut Lua, Args) -> Result<Vec<Value>, Error> + 'static,
+    ) -> Self {
+        Value::Function(Function(Rc::new(FunctionKind::Builtin {
+            name: name.to_owned(),
+            f: Box::new(f),
+        })))
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Nil => "nil",
+            Value::Boolean(_) => "boolean",
+            Value::Number(_) => "number",
+            Value::Str(_) => "string",
+            Value::Table(_) => "table",
+            Value::Function(_) => "function",
+        }
+    }
+
+    /// Only `nil` and `false` are false.
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Value::Nil | Value::Boolean(false))
+    }
+
+    /// The number, or the string converted to one.
+    pub fn to_number(&self) -> Option<f64> {
+        match self {
+            Value::Number(number) => Some(*number),
+            Value::Str(value) => parse_number(value),
+            _ => None,
+        }
+    }
+
+    /// The string, or the number converted to one.
+    pub fn to_str(&self) -> Option<Rc<str>> {
+        match self {
+            Value::Str(value) => Some(value.clone()),
+            Value::Number(number) => Some(format_number(*number).into()),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn raw_equals(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Boolean(a), Value::Boolean(b)) => a == b,
+            (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::Str(a), Value::Str(b)) => a == b,
+            (Value::Table(a), Value::Table(b)) => Rc::ptr_eq(&a.0, &b.0),
+            (Value::Function(a), Value::Function(b)) => Rc::ptr_eq(&a.0, &b.0),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Nil => write!(f, "nil"),
+            Value::Boolean(value) => write!(f, "{value}"),
+            Value::Number(number) => write!(f, "{}", format_number(*number)),
+            Value::Str(value) => write!(f, "{value}"),
+            Value::Table(table) => write!(f, "table: {:p}", Rc::as_ptr(&table.0)),
+            Value::Function(function) => write!(f, "function: {:p}", Rc::as_ptr(&function.0)),
+        }
+    }
+}
+
+impl fmt::Debug for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Str(value) => write!(f, "{value:?}"),
+            _ => write!(f, "{self}"),
+        }
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        self.raw_equals(other)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Boolean(value)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Self {
+        Value::Number(value)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Value::Number(value as f64)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::Str(value.into())
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::Str(value.into())
+    }
+}
+
+impl From<Table> for Value {
+    fn from(table: Table) -> Self {
+        Value::Table(table)
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Value::Nil, Into::into)
+    }
+}
+
+/// Integers are shown without a fraction, the others as `%.14g`, like Lua
+/// does.
+fn format_number(number: f64) -> String {
+    if number.fract() == 0.0 && number.abs() < 1e15 {
+        format!("{}", number as i64)
+    } else {
+        format_general(number, 14)
+    }
+}
+
+/// `number` as `%.<precision>g` of C: `precision` significant digits, in
+/// the exponent form if it would be too long otherwise.
+pub(crate) fn format_general(number: f64, precision: usize) -> String {
+    if number.is_nan() {
+        return String::from("nan");
+    }
+    if number.is_infinite() {
+        return String::from(if number > 0.0 { "inf" } else { "-inf" });
+    }
+
+    let precision = precision.max(1);
+    let scientific = format!("{:.*e}", precision - 1, number);
+    let (mantissa, exponent) = scientific.split_once('e').unwrap_or((&scientific, "0"));
+    let exponent: i32 = exponent.parse().unwrap_or_default();
+
+    let trim = |digits: &str| -> String {
+        match digits.contains('.') {
+            true => digits
+                .trim_end_matches('0')
+                .trim_end_matches('.')
+                .to_owned(),
+            false => digits.to_owned(),
+        }
+    };
+
+    if exponent < -4 || exponent >= precision as i32 {
+        let sign = if exponent < 0 { '-' } else { '+' };
+        format!("{}e{sign}{:02}", trim(mantissa), exponent.abs())
+    } else {
+        let decimals = (precision as i32 - 1 - exponent).max(0) as usize;
+        trim(&format!("{number:.decimals$}"))
+    }
+}
+
+pub(crate) fn parse_number(value: &str) -> Option<f64> {
+    let value = value.trim();
+    let (negative, digits) = match value.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, value),
+    };
+
+    let number = match digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        Some(hex) => u64::from_str_radix(hex, 16).ok()? as f64,
+        // Rust accepts these, Lua doesn't.
+        None if digits
+            .chars()
+            .any(|t| t.is_ascii_alphabetic() && !"eE".contains(t)) =>
+        {
+            return None
+        }
+        None => digits.parse().ok()?,
+    };
+
+    Some(if negative { -number } else { number })
+}
+
+pub struct Function(pub(crate) Rc<FunctionKind>);
+
+impl Clone for Function {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+pub(crate) enum FunctionKind {
+    Lua {
+        def: Rc<FunctionDef>,
+        /// The locals it sees where it's defined.
+        scope: Option<Rc<Scope>>,
+    },
+    Builtin {
+        name: String,
+        f: Box<Builtin>,
+    },
+}
+
+/// Table keys, ordered so that `pairs` goes through them the same way every
+/// time.
+#[derive(Clone)]
+enum Key {
+    Boolean(bool),
+    Number(f64),
+    Str(Rc<str>),
+    /// Tables and functions, compared by their address.
+    Reference(usize, Value),
+}
+
+impl Key {
+    fn new(value: &Value) -> Option<Self> {
+        Some(match value {
+            Value::Nil => return None,
+            Value::Number(number) if number.is_nan() => return None,
+            Value::Boolean(value) => Key::Boolean(*value),
+            // -0 and 0 are the same key.
+            Value::Number(number) => Key::Number(number + 0.0),
+            Value::Str(value) => Key::Str(value.clone()),
+            Value::Table(table) => Key::Reference(Rc::as_ptr(&table.0) as usize, value.clone()),
+            Value::Function(function) => {
+                Key::Reference(Rc::as_ptr(&function.0) as usize, value.clone())
+            }
+        })
+    }
+
+    fn to_value(&self) -> Value {
+        match self {
+            Key::Boolean(value) => Value::Boolean(*value),
+            Key::Number(number) => Value::Number(*number),
+            Key::Str(value) => Value::Str(value.clone()),
+            Key::Reference(_, value) => value.clone(),
+        }
+    }
+
+    fn rank(&self) -> u8 {
+        match self {
+            Key::Boolean(_) => 0,
+            Key::Number(_) => 1,
+            Key::Str(_) => 2,
+            Key::Reference(..) => 3,
+        }
+    }
+}
+
+impl Ord for Key {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Key::Boolean(a), Key::Boolean(b)) => a.cmp(b),
+            (Key::Number(a), Key::Number(b)) => a.total_cmp(b),
+            (Key::Str(a), Key::Str(b)) => a.cmp(b),
+            (Key::Reference(a, _), Key::Reference(b, _)) => a.cmp(b),
+            _ => self.rank().cmp(&other.rank()),
+        }
+    }
+}
+
+impl PartialOrd for Key {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Key {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Key {}
+
+#[derive(Default)]
+struct TableData {
+    /// Values of the keys `1..=n`.
+    array: Vec<Value>,
+    hash: BTreeMap<Key, Value>,
+}
+
+impl TableData {
+    /// Moves the keys right after the array part into it.
+    fn extend_array(&mut self) {
+        while let Some(next) = self
+            .hash
+            .remove(&Key::Number((self.array.len() + 1) as f64))
+        {
+            self.array.push(next);
+        }
+    }
+}
+
+/// A reference to a table, clones share the content.
+#[derive(Clone, Default)]
+pub struct Table(Rc<RefCell<TableData>>);
+
+/// `1..=len` of the array part, if `number` is an integer in there or right
+/// after it.
+fn array_index(number: f64, len: usize) -> Option<usize> {
+    match number.fract() == 0.0 && number >= 1.0 && number <= (len + 1) as f64 {
+        true => Some(number as usize - 1),
+        false => None,
+    }
+}
+
+impl Table {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A table of `values` at the keys `1..=n`.
+    pub fn from_list(values: impl IntoIterator<Item = Value>) -> Self {
+        let table = Self::new();
+        for value in values {
+            table.push(value);
+        }
+        table
+    }
+
+    pub fn get(&self, key: impl Into<Value>) -> Value {
+        let key = key.into();
+        let data = self.0.borrow();
+
+        if let Value::Number(number) = key {
+            if let Some(value) =
+                array_index(number, data.array.len()).and_then(|index| data.array.get(index))
+            {
+                return value.clone();
+            }
+        }
+
+        Key::new(&key)
+            .and_then(|key| data.hash.get(&key).cloned())
+            .unwrap_or_default()
+    }
+
+    /// Setting a key to `nil` removes it.
+    pub fn set(&self, key: impl Into<Value>, value: impl Into<Value>) -> Result<(), Error> {
+        let (key, value) = (key.into(), value.into());
+        let mut data = self.0.borrow_mut();
+
+        if let Value::Number(number) = key {
+            if let Some(index) = array_index(number, data.array.len()) {
+                if index < data.array.len() {
+                    data.array[index] = value;
+                    while matches!(data.array.last(), Some(Value::Nil)) {
+                        data.array.pop();
+                    }
+                } else {
+                    data.hash.remove(&Key::Number(number));
+                    if !matches!(value, Value::Nil) {
+                        data.array.push(value);
+                        data.extend_array();
+                    }
+                }
+                return Ok(());
+            }
+        }
+
+        let Some(key) = Key::new(&key) else {
+            return Err(Error::new(match key {
+                Value::Nil => "table index is nil",
+                _ => "table index is NaN",
+            }));
+        };
+        match value {
+            Value::Nil => data.hash.remove(&key),
+            value => data.hash.insert(key, value),
+        };
+
+        Ok(())
+    }
+
+    /// Appends `value` at `len() + 1`.
+    pub fn push(&self, value: impl Into<Value>) {
+        let len = self.len();
+        // Only fails for nil and NaN keys.
+        let _ = self.set((len + 1) as f64, value);
+    }
+
+    /// The border of the table, `n` for the lists `1..=n`.
+    pub fn len(&self) -> usize {
+        self.0.borrow().array.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0 && self.0.borrow().hash.is_empty()
+    }
+
+    /// The entry after `key`, the first one for `nil`. Entries are visited in
+    /// the same order as long as the table isn't changed.
+    pub fn next(&self, key: &Value) -> Result<Option<(Value, Value)>, Error> {
+        let data = self.0.borrow();
+
+        let array_start = match key {
+            Value::Nil => Some(0),
+            Value::Number(number) => array_index(*number, data.array.len())
+                .filter(|index| *index < data.array.len())
+                .map(|index| index + 1),
+            _ => None,
+        };
+        if let Some(start) = array_start {
+            for (index, value) in data.array.iter().enumerate().skip(start) {
+                if !matches!(value, Value::Nil) {
+                    return Ok(Some((Value::Number((index + 1) as f64), value.clone())));
+                }
+            }
+            return Ok(data
+                .hash
+                .iter()
+                .next()
+                .map(|(key, value)| (key.to_value(), value.clone())));
+        }
+
+        let key = Key::new(key).ok_or_else(|| Error::new("invalid key to 'next'"))?;
+        if !data.hash.contains_key(&key) {
+            return Err(Error::new("invalid key to 'next'"));
+        }
+
+        Ok(data
+            .hash
+            .range(key..)
+            .nth(1)
+            .map(|(key, value)| (key.to_value(), value.clone())))
+    }
+
+    /// Values of `1..=len()`.
+    pub fn list(&self) -> Vec<Value> {
+        self.0.borrow().array.clone()
+    }
+
+    /// Removes and returns the value at `index`, moving the ones after it
+    /// down.
+    pub(crate) fn remove(&self, index: usize) -> Value {
+        let mut data = self.0.borrow_mut();
+        match index >= 1 && index <= data.array.len() {
+            true => data.array.remove(index - 1),
+            false => Value::Nil,
+        }
+    }
+
+    /// Inserts `value` at `index`, moving the ones from there up.
+    pub(crate) fn insert(&self, index: usize, value: Value) {
+        let mut data = self.0.borrow_mut();
+        let index = index.clamp(1, data.array.len() + 1);
+        data.array.insert(index - 1, value);
+        data.extend_array();
+    }
+
+    /// Replaces the values of `1..=len()`, which `values` must not change the
+    /// number of.
+    pub(crate) fn set_list(&self, values: Vec<Value>) {
+        self.0.borrow_mut().array = values;
+    }
+}
+
+/// Arguments of a builtin function, which checks their types through these.
+pub struct Args {
+    pub(crate) function: String,
+    pub(crate) values: Vec<Value>,
+}
+
+impl Args {
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// The argument at `index`, counting from 0, `nil` if it's missing.
+    pub fn get(&self, index: usize) -> Value {
+        self.values.get(index).cloned().unwrap_or_default()
+    }
+
+    pub fn into_values(self) -> Vec<Value> {
+        self.values
+    }
+
+    fn bad_argument(&self, index: usize, expected: &str) -> Error {
+        Error::new(format!(
+            "bad argument #{} to '{}' ({expected} expected, got {})",
+            index + 1,
+            self.function,
+            match self.values.get(index) {
+                Some(value) => value.type_name(),
+                None => "no value",
+            }
+        ))
+    }
+
+    pub fn string(&self, index: usize) -> Result<Rc<str>, Error> {
+        self.get(index)
+            .to_str()
+            .ok_or_else(|| self.bad_argument(index, "string"))
+    }
+
+    pub fn number(&self, index: usize) -> Result<f64, Error> {
+        self.get(index)
+            .to_number()
+            .ok_or_else(|| self.bad_argument(index, "number"))
+    }
+
+    pub fn integer(&self, index: usize) -> Result<i64, Error> {
+        let number = self.number(index)?;
+        match number.fract() == 0.0 {
+            true => Ok(number as i64),
+            false => Err(Error::new(format!(
+                "bad argument #{} to '{}' (number has no integer representation)",
+                index + 1,
+                self.function
+            ))),
+        }
+    }
+
+    pub fn table(&self, index: usize) -> Result<Table, Error> {
+        match self.get(index) {
+            Value::Table(table) => Ok(table),
+            _ => Err(self.bad_argument(index, "table")),
+        }
+    }
+
+    pub fn optional_integer(&self, index: usize) -> Result<Option<i64>, Error> {
+        match self.get(index) {
+            Value::Nil => Ok(None),
+            _ => self.integer(index).map(Some),
+        }
+    }
+
+    pub fn optional_string(&self, index: usize) -> Result<Option<Rc<str>>, Error> {
+        match self.get(index) {
+            Value::Nil => Ok(None),
+            _ => self.string(index).map(Some),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_table() {
+        let table = Table::new();
+        table.set(1.0, "a").unwrap();
+        table.set(3.0, "c").unwrap();
+        assert_eq!(table.len(), 1);
+
+        // Fills the gap, so `3` moves into the array part too.
+        table.set(2.0, "b").unwrap();
+        assert_eq!(table.len(), 3);
+        table.set("x", true).unwrap();
+        table.set(3.0, Value::Nil).unwrap();
+        assert_eq!(table.len(), 2);
+
+        let mut key = Value::Nil;
+        let mut entries = Vec::new();
+        while let Some((next, value)) = table.next(&key).unwrap() {
+            entries.push(format!("{next}={value}"));
+            key = next;
+        }
+        assert_eq!(entries, ["1=a", "2=b", "x=true"]);
+
+        assert!(table.set(Value::Nil, 1.0).is_err());
+        assert!(table.set(f64::NAN, 1.0).is_err());
+        assert_eq!(table.get(-0.0), Value::Nil);
+    }
+
+    #[test]
+    fn test_number_conversions() {
+        assert_eq!(format_number(3.0), "3");
+        assert_eq!(format_number(-0.5), "-0.5");
+        assert_eq!(format_number(1e300), "1e+300");
+        assert_eq!(format_number(1.0 / 3.0), "0.33333333333333");
+        assert_eq!(format_number(0.00001), "1e-05");
+        assert_eq!(parse_number(" 0x1F "), Some(31.0));
+        assert_eq!(parse_number("-2.5e1"), Some(-25.0));
+        assert_eq!(parse_number("inf"), None);
+        assert_eq!(parse_number("1 2"), None);
+    }
+}
@@ -1,9 +1,27 @@
 use std::io::{self, BufRead, BufReader, Read, Write};
-use std::net::TcpStream;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub use proxy::Proxy;
+pub use runtime::{block_on, block_on_all, sleep};
+pub use tls::TlsConfig;
+
+mod proxy;
+mod runtime;
+mod tls;
 
 pub struct Rekuest {
     host: String,
+    path: String,
+    https: bool,
+    /// Header lines, each one prefixed with `\r\n`.
     request_data: String,
+    rate_limit: Option<u64>,
+    proxy: Option<Proxy>,
+    timeout: Option<Duration>,
+    tls_config: Option<TlsConfig>,
 }
 
 pub struct HttpResponse {
@@ -14,22 +32,19 @@ pub struct HttpResponse {
 
 impl Rekuest {
     pub fn new(url: &str) -> io::Result<Self> {
-        let (host, path) = parse_url(url).ok_or_else(|| {
-            io::Error::new(
-                io::ErrorKind::InvalidInput,
-                format!("Couldn't parse {}", url),
-            )
-        })?;
+        let (host, path, https) = parse_url(url)?;
 
         let mut rekuest = Self {
             host,
+            path,
+            https,
             request_data: String::new(),
+            rate_limit: None,
+            proxy: None,
+            timeout: None,
+            tls_config: None,
         };
 
-        rekuest
-            .request_data
-            .push_str(&format!("GET /{} HTTP/1.1", path));
-
         rekuest.add_header("Host", &rekuest.host.to_string());
         rekuest.add_header("Connection", "close");
 
@@ -41,52 +56,304 @@ impl Rekuest {
         self.request_data.push_str(&format!("{}: {}", key, value));
     }
 
-    pub fn get(self) -> io::Result<HttpResponse> {
-        let mut stream = TcpStream::connect(&self.host)?;
+    /// Limits the speed of reading the response body to `bytes_per_second`.
+    pub fn set_rate_limit(&mut self, bytes_per_second: u64) {
+        self.rate_limit = Some(bytes_per_second);
+    }
+
+    /// Sends the request through `proxy` instead of connecting to the host directly.
+    pub fn set_proxy(&mut self, proxy: Proxy) {
+        self.proxy = Some(proxy);
+    }
+
+    /// Fails the request if connecting, or any single read/write takes longer than `timeout`.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = Some(timeout);
+    }
+
+    /// Verifies `https://` servers and authenticates against them with
+    /// `config`, instead of only trusting the system certificates.
+    pub fn set_tls_config(&mut self, config: TlsConfig) {
+        self.tls_config = Some(config);
+    }
+
+    /// Connects to the host, or the proxy if set, and returns the connection
+    /// with the target to put in the request line. The TLS handshake for
+    /// `https://` urls is done here as well.
+    fn open(&self) -> io::Result<(Connection, String)> {
+        let (stream, request_target) = match &self.proxy {
+            None => (
+                connect(&self.host, self.timeout)?,
+                format!("/{}", self.path),
+            ),
+            // The proxy can't see inside TLS, so it only tunnels the connection.
+            Some(Proxy::Http(addr)) if self.https => (
+                proxy::connect_http_tunnel(connect(addr, self.timeout)?, &self.host)?,
+                format!("/{}", self.path),
+            ),
+            // HTTP proxies expect the absolute form of the url.
+            Some(Proxy::Http(addr)) => (
+                connect(addr, self.timeout)?,
+                format!("http://{}/{}", self.host, self.path),
+            ),
+            Some(Proxy::Socks5(addr, auth)) => (
+                proxy::connect_socks5(connect(addr, self.timeout)?, auth, &self.host)?,
+                format!("/{}", self.path),
+            ),
+        };
         stream.set_nodelay(true)?;
 
-        let mut request_data = self.request_data;
+        if !self.https {
+            return Ok((Connection::Plain(stream), request_target));
+        }
+
+        let config = match &self.tls_config {
+            Some(config) => config.clone(),
+            None => TlsConfig::system()?,
+        };
+        let stream = tls::TlsStream::connect(stream, &self.host, &config)?;
+
+        Ok((Connection::Tls(stream), request_target))
+    }
+
+    fn request_data(&self, request_target: &str) -> String {
+        self.request_data_with_method("GET", request_target)
+    }
+
+    fn request_data_with_method(&self, method: &str, request_target: &str) -> String {
+        let mut request_data = format!("{} {} HTTP/1.1", method, request_target);
+        request_data.push_str(&self.request_data);
         request_data.push_str("\r\n");
         request_data.push_str("\r\n");
+        request_data
+    }
+
+    pub fn get(self) -> io::Result<HttpResponse> {
+        let (mut stream, request_target) = self.open()?;
+        stream.write_all(self.request_data(&request_target).as_bytes())?;
+
+        self.read_response(&mut stream)
+    }
 
+    /// Sends `body` with a POST request, the response is read like in `get`.
+    pub fn post(mut self, content_type: &str, body: &[u8]) -> io::Result<HttpResponse> {
+        self.add_header("Content-Type", content_type);
+        self.add_header("Content-Length", &body.len().to_string());
+
+        let (mut stream, request_target) = self.open()?;
+        let request_data = self.request_data_with_method("POST", &request_target);
         stream.write_all(request_data.as_bytes())?;
+        stream.write_all(body)?;
 
-        let mut response = HttpResponse {
-            headers: Vec::new(),
-            body: Vec::new(),
-            status_code: 0,
-        };
+        self.read_response(&mut stream)
+    }
 
+    fn read_response(&self, stream: &mut Connection) -> io::Result<HttpResponse> {
         let mut headers: Vec<u8> = Vec::new();
 
-        let mut reader = BufReader::new(&stream);
+        let mut reader = BufReader::new(stream);
         read_until_nrt(&mut reader, &mut headers)?;
 
         // ignore '\n'
         reader.consume(1);
 
-        let headers = String::from_utf8(headers)
-            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}", e)))?;
+        let mut response = parse_head(&headers)?;
 
-        let mut lines = headers.lines();
-        if let Some(status_line) = lines.next() {
-            let status_code = parse_status_code(status_line)?;
-            response.status_code = status_code;
+        let mut body = Vec::new();
+        match self.rate_limit {
+            Some(bytes_per_second) => {
+                read_to_end_throttled(&mut reader, &mut body, bytes_per_second)?
+            }
+            None => {
+                reader.read_to_end(&mut body)?;
+            }
         }
 
-        for line in lines {
-            if let Some((header_name, header_value)) = parse_header(line) {
-                response.headers.push((header_name, header_value));
+        response.body = body;
+
+        Ok(response)
+    }
+
+    /// Like `get`, but the socket is read and written without blocking, so
+    /// the request can run next to others in `block_on_all`. Connecting (and
+    /// the proxy and TLS handshakes) still blocks.
+    pub async fn get_async(self) -> io::Result<HttpResponse> {
+        self.get_async_with_progress(|_, _| Ok(())).await
+    }
+
+    /// Like `get_async`, calls `on_progress` with the received and the total
+    /// (from `Content-Length`, if sent) byte count of the body after each read.
+    /// An error from `on_progress` aborts the request with it.
+    pub async fn get_async_with_progress(
+        self,
+        on_progress: impl Fn(u64, Option<u64>) -> io::Result<()>,
+    ) -> io::Result<HttpResponse> {
+        let (mut stream, request_target) = self.open()?;
+        stream.tcp().set_nonblocking(true)?;
+
+        let request_data = self.request_data(&request_target);
+        runtime::write_all(&mut stream, request_data.as_bytes(), self.timeout).await?;
+
+        let chunk_size = self.rate_limit.map_or(8192, |t| t.clamp(1, 8192));
+        let mut chunk = vec![0; chunk_size as usize];
+        let mut data = Vec::new();
+        let started_at = Instant::now();
+        // Body offset and length, once the head is complete.
+        let mut body_start: Option<(usize, Option<u64>)> = None;
+
+        loop {
+            let n = runtime::read(&mut stream, &mut chunk, self.timeout).await?;
+            if n == 0 {
+                break;
+            }
+
+            data.extend_from_slice(&chunk[..n]);
+
+            if body_start.is_none() {
+                if let Some(head_end) = data.windows(4).position(|t| t == b"\r\n\r\n") {
+                    let content_length = parse_head(&data[..head_end])?
+                        .get_header_value("Content-Length")
+                        .and_then(|t| t.parse().ok());
+                    body_start = Some((head_end + 4, content_length));
+                }
+            }
+            if let Some((start, total)) = body_start {
+                on_progress((data.len() - start) as u64, total)?;
+            }
+
+            // Same throttling as `read_to_end_throttled`.
+            if let Some(bytes_per_second) = self.rate_limit {
+                let expected =
+                    Duration::from_secs_f64(data.len() as f64 / bytes_per_second.max(1) as f64);
+                let elapsed = started_at.elapsed();
+                if expected > elapsed {
+                    runtime::sleep(expected - elapsed).await;
+                }
             }
         }
 
-        let mut body = Vec::new();
-        reader.read_to_end(&mut body)?;
+        parse_response(data)
+    }
+}
 
-        response.body = body;
+/// The socket of a request, encrypted for `https://` urls.
+enum Connection {
+    Plain(TcpStream),
+    Tls(tls::TlsStream),
+}
 
-        Ok(response)
+impl Connection {
+    fn tcp(&self) -> &TcpStream {
+        match self {
+            Self::Plain(stream) => stream,
+            Self::Tls(stream) => stream.get_ref(),
+        }
+    }
+
+    /// Whether the last operation that failed with `WouldBlock` waits for the
+    /// socket to become writable. TLS may have to write while reading, and
+    /// the other way around.
+    fn wants_write(&self, writing: bool) -> bool {
+        match self {
+            Self::Plain(_) => writing,
+            Self::Tls(stream) => stream.wants_write(),
+        }
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Plain(stream) => stream.read(buf),
+            Self::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Plain(stream) => stream.write(buf),
+            Self::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Plain(stream) => stream.flush(),
+            Self::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+impl AsRawFd for Connection {
+    fn as_raw_fd(&self) -> RawFd {
+        self.tcp().as_raw_fd()
+    }
+}
+
+/// Parses the status line and headers of a response.
+fn parse_head(head: &[u8]) -> io::Result<HttpResponse> {
+    let head = std::str::from_utf8(head)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}", e)))?;
+
+    let mut response = HttpResponse {
+        headers: Vec::new(),
+        body: Vec::new(),
+        status_code: 0,
+    };
+
+    let mut lines = head.lines();
+    if let Some(status_line) = lines.next() {
+        response.status_code = parse_status_code(status_line)?;
+    }
+
+    for line in lines {
+        if let Some((header_name, header_value)) = parse_header(line) {
+            response.headers.push((header_name, header_value));
+        }
+    }
+
+    Ok(response)
+}
+
+/// Splits a complete response into its head and body.
+fn parse_response(mut data: Vec<u8>) -> io::Result<HttpResponse> {
+    let head_end = data
+        .windows(4)
+        .position(|t| t == b"\r\n\r\n")
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Incomplete response head"))?;
+
+    let body = data.split_off(head_end + 4);
+    let mut response = parse_head(&data[..head_end])?;
+    response.body = body;
+
+    Ok(response)
+}
+
+fn connect(addr: &str, timeout: Option<Duration>) -> io::Result<TcpStream> {
+    let Some(timeout) = timeout else {
+        return TcpStream::connect(addr);
+    };
+
+    let mut last_error = None;
+    for socket_addr in addr.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&socket_addr, timeout) {
+            Ok(stream) => {
+                stream.set_read_timeout(Some(timeout))?;
+                stream.set_write_timeout(Some(timeout))?;
+                return Ok(stream);
+            }
+            Err(e) => last_error = Some(e),
+        }
     }
+
+    Err(last_error.unwrap_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Couldn't resolve {addr}"),
+        )
+    }))
 }
 
 impl HttpResponse {
@@ -100,7 +367,22 @@ impl HttpResponse {
     }
 }
 
-fn parse_url(url: &str) -> Option<(String, String)> {
+/// Splits `url` into `host:port` and the path, and tells whether it's an `https://` url.
+fn parse_url(url: &str) -> io::Result<(String, String, bool)> {
+    let parsed = match url.strip_prefix("https://") {
+        Some(without_prefix) => parse_plain_url(without_prefix, 443).map(|(h, p)| (h, p, true)),
+        None => parse_plain_url(url, 80).map(|(h, p)| (h, p, false)),
+    };
+
+    parsed.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Couldn't parse {}", url),
+        )
+    })
+}
+
+fn parse_plain_url(url: &str, default_port: u16) -> Option<(String, String)> {
     let url = if let Some(without_prefix) = url.strip_prefix("http://") {
         without_prefix
     } else {
@@ -113,7 +395,10 @@ fn parse_url(url: &str) -> Option<(String, String)> {
 
     let mut host_parts = host_port.splitn(2, ':');
     let host = host_parts.next()?;
-    let port = host_parts.next().and_then(|p| p.parse().ok()).unwrap_or(80);
+    let port = host_parts
+        .next()
+        .and_then(|p| p.parse().ok())
+        .unwrap_or(default_port);
 
     Some((format!("{}:{}", host, port), path.to_owned()))
 }
@@ -155,6 +440,36 @@ fn read_until_nrt<R: BufRead>(reader: &mut R, buf: &mut Vec<u8>) -> io::Result<(
     Ok(())
 }
 
+fn read_to_end_throttled<R: Read>(
+    reader: &mut R,
+    buf: &mut Vec<u8>,
+    bytes_per_second: u64,
+) -> io::Result<()> {
+    let bytes_per_second = bytes_per_second.max(1);
+    let mut chunk = vec![0; bytes_per_second.min(8192) as usize];
+    let started_at = Instant::now();
+
+    loop {
+        let n = match reader.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+
+        buf.extend_from_slice(&chunk[..n]);
+
+        // Sleep until the average speed falls back under the limit.
+        let expected = Duration::from_secs_f64(buf.len() as f64 / bytes_per_second as f64);
+        let elapsed = started_at.elapsed();
+        if expected > elapsed {
+            thread::sleep(expected - elapsed);
+        }
+    }
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -164,17 +479,20 @@ mod tests {
         // with default port and with http prefix
         {
             let url = "http://linux-amd64-default.lpm.lodosgroup.org";
-            let (host, path) = parse_url(url).unwrap();
+            let (host, path, https) = parse_url(url).unwrap();
+            assert!(!https);
             assert_eq!(host, "linux-amd64-default.lpm.lodosgroup.org:80");
             assert_eq!(path, "");
 
             let url = "http://linux-amd64-default.lpm.lodosgroup.org/index-tracker";
-            let (host, path) = parse_url(url).unwrap();
+            let (host, path, https) = parse_url(url).unwrap();
+            assert!(!https);
             assert_eq!(host, "linux-amd64-default.lpm.lodosgroup.org:80");
             assert_eq!(path, "index-tracker");
 
             let url = "http://linux-amd64-default.lpm.lodosgroup.org/index-tracker/health";
-            let (host, path) = parse_url(url).unwrap();
+            let (host, path, https) = parse_url(url).unwrap();
+            assert!(!https);
             assert_eq!(host, "linux-amd64-default.lpm.lodosgroup.org:80");
             assert_eq!(path, "index-tracker/health");
         }
@@ -182,20 +500,38 @@ mod tests {
         // with custom port and without http prefix
         {
             let url = "linux-amd64-default.lpm.lodosgroup.org:6150";
-            let (host, path) = parse_url(url).unwrap();
+            let (host, path, https) = parse_url(url).unwrap();
+            assert!(!https);
             assert_eq!(host, "linux-amd64-default.lpm.lodosgroup.org:6150");
             assert_eq!(path, "");
 
             let url = "linux-amd64-default.lpm.lodosgroup.org:6150/index-tracker";
-            let (host, path) = parse_url(url).unwrap();
+            let (host, path, https) = parse_url(url).unwrap();
+            assert!(!https);
             assert_eq!(host, "linux-amd64-default.lpm.lodosgroup.org:6150");
             assert_eq!(path, "index-tracker");
 
             let url = "linux-amd64-default.lpm.lodosgroup.org:6150/index-tracker/health";
-            let (host, path) = parse_url(url).unwrap();
+            let (host, path, https) = parse_url(url).unwrap();
+            assert!(!https);
             assert_eq!(host, "linux-amd64-default.lpm.lodosgroup.org:6150");
             assert_eq!(path, "index-tracker/health");
         }
+
+        // with https prefix
+        {
+            let url = "https://linux-amd64-default.lpm.lodosgroup.org/index-tracker";
+            let (host, path, https) = parse_url(url).unwrap();
+            assert!(https);
+            assert_eq!(host, "linux-amd64-default.lpm.lodosgroup.org:443");
+            assert_eq!(path, "index-tracker");
+
+            let url = "https://linux-amd64-default.lpm.lodosgroup.org:6150";
+            let (host, path, https) = parse_url(url).unwrap();
+            assert!(https);
+            assert_eq!(host, "linux-amd64-default.lpm.lodosgroup.org:6150");
+            assert_eq!(path, "");
+        }
     }
 
     #[test]
@@ -242,4 +578,135 @@ mod tests {
         let expected_output = b"Header1: Value1\r\nHeader2: Value2";
         assert_eq!(buf, expected_output);
     }
+
+    #[test]
+    fn test_parse_response() {
+        let data = b"HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\n\r\nBody\r\n\r\n".to_vec();
+        let response = parse_response(data).unwrap();
+        assert_eq!(response.status_code, 200);
+        assert_eq!(
+            response.get_header_value("Content-Type"),
+            Some("text/plain")
+        );
+        assert_eq!(response.body, b"Body\r\n\r\n");
+
+        assert!(parse_response(b"HTTP/1.1 200 OK\r\n".to_vec()).is_err());
+    }
+
+    #[test]
+    fn test_post() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = thread::spawn(move || {
+            let mut stream = listener.accept().unwrap().0;
+            let mut request = Vec::new();
+            while !request.ends_with(b"{\"ok\":true}") {
+                let mut chunk = [0; 1024];
+                let n = stream.read(&mut chunk).unwrap();
+                request.extend_from_slice(&chunk[..n]);
+            }
+            write!(stream, "HTTP/1.1 204 No Content\r\n\r\n").unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        let response = Rekuest::new(&format!("http://{addr}/hooks"))
+            .unwrap()
+            .post("application/json", b"{\"ok\":true}")
+            .unwrap();
+        assert_eq!(response.status_code, 204);
+
+        let request = server.join().unwrap();
+        assert!(request.starts_with("POST /hooks HTTP/1.1\r\n"));
+        assert!(request.contains("\r\nContent-Type: application/json\r\n"));
+        assert!(request.contains("\r\nContent-Length: 11\r\n"));
+    }
+
+    #[test]
+    fn test_get_async() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = thread::spawn(move || {
+            let mut streams: Vec<TcpStream> =
+                (0..2).map(|_| listener.accept().unwrap().0).collect();
+
+            // Answer the second request first, the first one has to wait
+            // without blocking it.
+            for (i, stream) in streams.iter_mut().enumerate().rev() {
+                let mut request = [0; 1024];
+                let _ = stream.read(&mut request).unwrap();
+                write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nresponse {i}"
+                )
+                .unwrap();
+            }
+        });
+
+        let progress = std::cell::RefCell::new(Vec::new());
+        let requests = (0..2)
+            .map(|i| {
+                let progress = &progress;
+                Rekuest::new(&format!("http://{addr}/{i}"))
+                    .unwrap()
+                    .get_async_with_progress(move |received, total| {
+                        progress.borrow_mut().push((i, received, total));
+                        Ok(())
+                    })
+            })
+            .collect();
+        let responses = block_on_all(requests);
+        server.join().unwrap();
+
+        // Each response arrives in a single read.
+        let mut progress = progress.into_inner();
+        progress.sort();
+        assert_eq!(progress, vec![(0, 10, Some(10)), (1, 10, Some(10))]);
+
+        for (i, response) in responses.into_iter().enumerate() {
+            let response = response.unwrap();
+            assert_eq!(response.status_code, 200);
+            assert_eq!(response.body, format!("response {i}").as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_get_https() {
+        let certs = tls::tests::write_certs("get-https");
+        let config = TlsConfig::new(Some(&certs.join("ca.pem")), None).unwrap();
+        let data = b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello";
+
+        let (addr, server) = tls::tests::serve(&certs, false, data);
+        let mut rekuest = Rekuest::new(&format!("https://{addr}/index")).unwrap();
+        rekuest.set_tls_config(config.clone());
+        let response = rekuest.get().unwrap();
+        server.join().unwrap().unwrap();
+        assert_eq!(response.status_code, 200);
+        assert_eq!(response.body, b"hello");
+
+        let (addr, server) = tls::tests::serve(&certs, false, data);
+        let mut rekuest = Rekuest::new(&format!("https://{addr}/index")).unwrap();
+        rekuest.set_tls_config(config);
+        let response = block_on(rekuest.get_async()).unwrap();
+        server.join().unwrap().unwrap();
+        assert_eq!(response.status_code, 200);
+        assert_eq!(response.body, b"hello");
+
+        std::fs::remove_dir_all(&certs).unwrap();
+    }
+
+    #[test]
+    fn test_read_to_end_throttled() {
+        let input = vec![7u8; 2048];
+        let mut reader = io::Cursor::new(&input);
+        let mut buf = Vec::new();
+
+        let started_at = Instant::now();
+        read_to_end_throttled(&mut reader, &mut buf, 8192).unwrap();
+
+        assert_eq!(buf, input);
+        // 2048 bytes at 8 KiB/s should take about 250ms.
+        assert!(started_at.elapsed() >= Duration::from_millis(200));
+    }
 }
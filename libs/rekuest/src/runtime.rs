@@ -0,0 +1,395 @@
+//! A small single-threaded executor which multiplexes the socket I/O of many
+//! requests with `poll(2)`, so the rest of lpm can stay synchronous and only
+//! bridge into it through `block_on` and `block_on_all`.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io::{self, Read, Write};
+use std::os::raw::{c_int, c_short, c_ulong};
+use std::os::unix::io::{AsRawFd, RawFd};
+
+use crate::Connection;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+use std::time::{Duration, Instant};
+
+const POLLIN: c_short = 0x1;
+const POLLOUT: c_short = 0x4;
+
+#[repr(C)]
+struct PollFd {
+    fd: c_int,
+    events: c_short,
+    revents: c_short,
+}
+
+extern "C" {
+    fn poll(fds: *mut PollFd, nfds: c_ulong, timeout: c_int) -> c_int;
+}
+
+/// Sockets and timers that the pending tasks are waiting for, by the id of
+/// the future that registered them.
+#[derive(Default)]
+struct Reactor {
+    next_id: usize,
+    io: Vec<(usize, RawFd, c_short, Waker)>,
+    timers: Vec<(usize, Instant, Waker)>,
+}
+
+thread_local! {
+    static REACTOR: RefCell<Reactor> = RefCell::new(Reactor::default());
+}
+
+impl Reactor {
+    fn next_id() -> usize {
+        REACTOR.with(|r| {
+            let mut r = r.borrow_mut();
+            r.next_id = r.next_id.wrapping_add(1);
+            r.next_id
+        })
+    }
+
+    /// Replaces what `id` waits for with `fd` being ready for `events`(if
+    /// any) or `deadline` passing(if any), whichever comes first.
+    fn register(id: usize, io: Option<(RawFd, c_short)>, deadline: Option<Instant>, waker: &Waker) {
+        Self::deregister(id);
+        REACTOR.with(|r| {
+            let mut r = r.borrow_mut();
+            if let Some((fd, events)) = io {
+                r.io.push((id, fd, events, waker.clone()));
+            }
+            if let Some(deadline) = deadline {
+                r.timers.push((id, deadline, waker.clone()));
+            }
+        });
+    }
+
+    /// Forgets what `id` waits for, once its future resolves or is dropped,
+    /// so the timers don't pile up over the waits of a long transfer.
+    fn deregister(id: usize) {
+        // Futures can be dropped along with the thread-locals.
+        let _ = REACTOR.try_with(|r| {
+            let mut r = r.borrow_mut();
+            r.io.retain(|t| t.0 != id);
+            r.timers.retain(|t| t.0 != id);
+        });
+    }
+
+    /// Blocks until one of the registered sockets is ready or a timer expires,
+    /// then wakes the tasks waiting for them.
+    fn wait() -> io::Result<()> {
+        let (io, timers) = REACTOR.with(|r| {
+            let mut r = r.borrow_mut();
+            (std::mem::take(&mut r.io), std::mem::take(&mut r.timers))
+        });
+
+        if io.is_empty() && timers.is_empty() {
+            panic!("INTERNAL: Tasks are pending without waiting for any event.");
+        }
+
+        let timeout = timers
+            .iter()
+            .map(|(_, deadline, _)| deadline.saturating_duration_since(Instant::now()))
+            .min()
+            // Round up, so the timer has expired once `poll` returns.
+            .map(|t| ((t.as_micros() + 999) / 1000).min(c_int::MAX as u128) as c_int)
+            .unwrap_or(-1);
+
+        let mut fds: Vec<PollFd> = io
+            .iter()
+            .map(|(_, fd, events, _)| PollFd {
+                fd: *fd,
+                events: *events,
+                revents: 0,
+            })
+            .collect();
+
+        // SAFETY: `fds` is a valid array of `fds.len()` elements.
+        #[allow(unsafe_code)]
+        let result = unsafe { poll(fds.as_mut_ptr(), fds.len() as c_ulong, timeout) };
+        if result < 0 {
+            let error = io::Error::last_os_error();
+            if error.kind() != io::ErrorKind::Interrupted {
+                return Err(error);
+            }
+        }
+
+        let mut pending = Reactor::default();
+        for (pollfd, (id, fd, events, waker)) in fds.iter().zip(io) {
+            if pollfd.revents != 0 {
+                waker.wake();
+            } else {
+                pending.io.push((id, fd, events, waker));
+            }
+        }
+
+        let now = Instant::now();
+        for (id, deadline, waker) in timers {
+            if deadline <= now {
+                waker.wake();
+            } else {
+                pending.timers.push((id, deadline, waker));
+            }
+        }
+
+        REACTOR.with(|r| {
+            let mut r = r.borrow_mut();
+            r.io.append(&mut pending.io);
+            r.timers.append(&mut pending.timers);
+        });
+
+        Ok(())
+    }
+}
+
+struct TaskWaker {
+    id: usize,
+    ready: Arc<Mutex<VecDeque<usize>>>,
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.ready.lock().unwrap().push_back(self.id);
+    }
+}
+
+/// Runs `future` to completion on the current thread.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    block_on_all(vec![future]).pop().unwrap()
+}
+
+/// Runs all of `futures` concurrently on the current thread and returns their
+/// outputs in the same order.
+pub fn block_on_all<F: Future>(futures: Vec<F>) -> Vec<F::Output> {
+    let ready = Arc::new(Mutex::new((0..futures.len()).collect::<VecDeque<_>>()));
+    let mut tasks: Vec<Option<Pin<Box<F>>>> =
+        futures.into_iter().map(|f| Some(Box::pin(f))).collect();
+    let mut outputs: Vec<Option<F::Output>> = tasks.iter().map(|_| None).collect();
+    let mut remaining = tasks.len();
+
+    while remaining > 0 {
+        let next = ready.lock().unwrap().pop_front();
+        let Some(id) = next else {
+            Reactor::wait().expect("Waiting for socket events failed.");
+            continue;
+        };
+
+        // Tasks can be woken more than once before getting polled.
+        let Some(task) = &mut tasks[id] else {
+            continue;
+        };
+
+        let waker = Waker::from(Arc::new(TaskWaker {
+            id,
+            ready: ready.clone(),
+        }));
+        if let Poll::Ready(output) = task.as_mut().poll(&mut Context::from_waker(&waker)) {
+            outputs[id] = Some(output);
+            tasks[id] = None;
+            remaining -= 1;
+        }
+    }
+
+    outputs.into_iter().map(Option::unwrap).collect()
+}
+
+/// Resolves once `fd` is ready for `events`, fails if `deadline` passes first.
+struct Readiness {
+    fd: RawFd,
+    events: c_short,
+    deadline: Option<Instant>,
+    /// Set once the events are registered.
+    id: Option<usize>,
+}
+
+impl Future for Readiness {
+    type Output = io::Result<()>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if matches!(self.deadline, Some(deadline) if deadline <= Instant::now()) {
+            return Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "Socket operation timed out.",
+            )));
+        }
+
+        // Woken up, the caller retries the operation and waits again if needed.
+        if self.id.is_some() {
+            return Poll::Ready(Ok(()));
+        }
+
+        let id = Reactor::next_id();
+        Reactor::register(id, Some((self.fd, self.events)), self.deadline, cx.waker());
+        self.id = Some(id);
+
+        Poll::Pending
+    }
+}
+
+impl Drop for Readiness {
+    fn drop(&mut self) {
+        if let Some(id) = self.id {
+            Reactor::deregister(id);
+        }
+    }
+}
+
+fn readiness(fd: RawFd, events: c_short, deadline: Option<Instant>) -> Readiness {
+    Readiness {
+        fd,
+        events,
+        deadline,
+        id: None,
+    }
+}
+
+/// Resolves once `duration` has passed.
+pub struct Sleep {
+    until: Instant,
+    id: usize,
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if self.until <= Instant::now() {
+            return Poll::Ready(());
+        }
+
+        Reactor::register(self.id, None, Some(self.until), cx.waker());
+
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        Reactor::deregister(self.id);
+    }
+}
+
+pub fn sleep(duration: Duration) -> Sleep {
+    Sleep {
+        until: Instant::now() + duration,
+        id: Reactor::next_id(),
+    }
+}
+
+/// Reads from the non-blocking `stream`, failing if no data arrives within `timeout`.
+pub(crate) async fn read(
+    stream: &mut Connection,
+    buf: &mut [u8],
+    timeout: Option<Duration>,
+) -> io::Result<usize> {
+    let deadline = timeout.map(|t| Instant::now() + t);
+    loop {
+        match stream.read(buf) {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                let events = if stream.wants_write(false) {
+                    POLLOUT
+                } else {
+                    POLLIN
+                };
+                readiness(stream.as_raw_fd(), events, deadline).await?
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            result => return result,
+        }
+    }
+}
+
+/// Writes all of `buf` into the non-blocking `stream`, failing if a single
+/// write can't make progress within `timeout`.
+pub(crate) async fn write_all(
+    stream: &mut Connection,
+    mut buf: &[u8],
+    timeout: Option<Duration>,
+) -> io::Result<()> {
+    let mut deadline = timeout.map(|t| Instant::now() + t);
+    while !buf.is_empty() {
+        match stream.write(buf) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(n) => {
+                buf = &buf[n..];
+                deadline = timeout.map(|t| Instant::now() + t);
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                let events = if stream.wants_write(true) {
+                    POLLOUT
+                } else {
+                    POLLIN
+                };
+                readiness(stream.as_raw_fd(), events, deadline).await?
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixStream;
+
+    #[test]
+    fn test_block_on_all() {
+        async fn delayed(value: u64) -> u64 {
+            sleep(Duration::from_millis(value * 50)).await;
+            value
+        }
+
+        let started_at = Instant::now();
+        let outputs = block_on_all(vec![delayed(3), delayed(1), delayed(2)]);
+
+        assert_eq!(outputs, vec![3, 1, 2]);
+        // The sleeps run at the same time instead of one after another.
+        let elapsed = started_at.elapsed();
+        assert!(elapsed >= Duration::from_millis(150));
+        assert!(elapsed < Duration::from_millis(300));
+        assert_eq!(registrations(), (0, 0));
+    }
+
+    fn registrations() -> (usize, usize) {
+        REACTOR.with(|r| {
+            let r = r.borrow();
+            (r.io.len(), r.timers.len())
+        })
+    }
+
+    #[test]
+    fn test_readiness_deregisters() {
+        let (reader, mut writer) = UnixStream::pair().unwrap();
+        let deadline = |t| Some(Instant::now() + Duration::from_millis(t));
+
+        // Ready before the deadline, the timer goes away with it.
+        writer.write_all(b"ready").unwrap();
+        block_on(readiness(reader.as_raw_fd(), POLLIN, deadline(60_000))).unwrap();
+        assert_eq!(registrations(), (0, 0));
+
+        // Timed out, the socket goes away with it.
+        let (idle, _peer) = UnixStream::pair().unwrap();
+        let error = block_on(readiness(idle.as_raw_fd(), POLLIN, deadline(20))).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+        assert_eq!(registrations(), (0, 0));
+
+        // Dropped while pending.
+        let mut pending = Box::pin(readiness(idle.as_raw_fd(), POLLIN, deadline(60_000)));
+        let waker = Waker::from(Arc::new(TaskWaker {
+            id: 0,
+            ready: Default::default(),
+        }));
+        assert!(pending
+            .as_mut()
+            .poll(&mut Context::from_waker(&waker))
+            .is_pending());
+        assert_eq!(registrations(), (1, 1));
+        drop(pending);
+        assert_eq!(registrations(), (0, 0));
+    }
+}
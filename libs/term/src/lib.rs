@@ -1,2 +1,3 @@
 pub mod controller;
 pub mod progress_bar;
+pub mod raw_mode;
@@ -0,0 +1,177 @@
+use crate::controller::STDIN_FD;
+
+use std::io::{self, Read};
+use std::os::raw::{c_int, c_uchar, c_uint};
+
+const NCCS: usize = 32;
+const ISIG: c_uint = 0o0000001;
+const ICANON: c_uint = 0o0000002;
+const ECHO: c_uint = 0o0000010;
+const IEXTEN: c_uint = 0o0100000;
+const VTIME: usize = 5;
+const VMIN: usize = 6;
+const TCSAFLUSH: c_int = 2;
+
+/// termios port from C
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Termios {
+    c_iflag: c_uint,
+    c_oflag: c_uint,
+    c_cflag: c_uint,
+    c_lflag: c_uint,
+    c_line: c_uchar,
+    c_cc: [c_uchar; NCCS],
+    c_ispeed: c_uint,
+    c_ospeed: c_uint,
+}
+
+extern "C" {
+    fn tcgetattr(fd: c_int, termios: *mut Termios) -> c_int;
+    fn tcsetattr(fd: c_int, optional_actions: c_int, termios: *const Termios) -> c_int;
+}
+
+/// Switches the terminal into non-canonical mode without echo, so keys can be
+/// read one by one. The previous mode is restored on drop.
+///
+/// Reads time out after 100ms, so a lone `Esc` can be told apart from the
+/// escape sequences of the other keys.
+pub struct RawMode {
+    original: Termios,
+}
+
+impl RawMode {
+    pub fn enable() -> io::Result<Self> {
+        let mut original = Termios {
+            c_iflag: 0,
+            c_oflag: 0,
+            c_cflag: 0,
+            c_lflag: 0,
+            c_line: 0,
+            c_cc: [0; NCCS],
+            c_ispeed: 0,
+            c_ospeed: 0,
+        };
+
+        #[allow(unsafe_code)]
+        if unsafe { tcgetattr(STDIN_FD, &mut original) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut raw = original;
+        raw.c_lflag &= !(ECHO | ICANON | ISIG | IEXTEN);
+        raw.c_cc[VMIN] = 0;
+        raw.c_cc[VTIME] = 1;
+
+        #[allow(unsafe_code)]
+        if unsafe { tcsetattr(STDIN_FD, TCSAFLUSH, &raw) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self { original })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        #[allow(unsafe_code)]
+        unsafe {
+            tcsetattr(STDIN_FD, TCSAFLUSH, &self.original);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Key {
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    Enter,
+    Backspace,
+    Esc,
+    /// Ctrl-C, since signals are disabled in raw mode.
+    Interrupt,
+    Char(char),
+}
+
+fn read_byte(input: &mut impl Read) -> io::Result<Option<u8>> {
+    let mut byte = [0];
+    match input.read(&mut byte)? {
+        0 => Ok(None),
+        _ => Ok(Some(byte[0])),
+    }
+}
+
+/// Waits for the next key on `input`, unknown escape sequences are skipped.
+pub fn read_key(input: &mut impl Read) -> io::Result<Key> {
+    loop {
+        let Some(byte) = read_byte(input)? else {
+            continue;
+        };
+
+        let key = match byte {
+            b'\r' | b'\n' => Key::Enter,
+            0x7f | 0x08 => Key::Backspace,
+            0x03 => Key::Interrupt,
+            0x1b => match read_byte(input)? {
+                None => Key::Esc,
+                Some(b'[') => match read_byte(input)? {
+                    Some(b'A') => Key::Up,
+                    Some(b'B') => Key::Down,
+                    Some(b'5') if read_byte(input)? == Some(b'~') => Key::PageUp,
+                    Some(b'6') if read_byte(input)? == Some(b'~') => Key::PageDown,
+                    _ => continue,
+                },
+                Some(_) => continue,
+            },
+            byte => {
+                // Multi byte UTF-8 characters.
+                let length = match byte {
+                    0xc0..=0xdf => 2,
+                    0xe0..=0xef => 3,
+                    0xf0..=0xf7 => 4,
+                    _ => 1,
+                };
+                let mut bytes = vec![byte];
+                for _ in 1..length {
+                    bytes.extend(read_byte(input)?);
+                }
+
+                match std::str::from_utf8(&bytes)
+                    .ok()
+                    .and_then(|t| t.chars().next())
+                {
+                    Some(c) if !c.is_control() => Key::Char(c),
+                    _ => continue,
+                }
+            }
+        };
+
+        return Ok(key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_key() {
+        let mut input: &[u8] = b"\x1b[Aq\x1b[6~\x1b[Z\r\x7f\xc3\xa7\x1b";
+        let keys: Vec<Key> = (0..7).map(|_| read_key(&mut input).unwrap()).collect();
+
+        assert_eq!(
+            keys,
+            [
+                Key::Up,
+                Key::Char('q'),
+                Key::PageDown,
+                Key::Enter,
+                Key::Backspace,
+                Key::Char('ç'),
+                Key::Esc,
+            ]
+        );
+    }
+}
@@ -0,0 +1,41 @@
+#[derive(Debug, PartialEq)]
+pub enum AlternativesSubcommand<'a> {
+    List(Option<&'a str>),
+    Set(&'a str, &'a str),
+    Auto(&'a str),
+    Help,
+    None,
+}
+
+impl<'a> AlternativesSubcommand<'a> {
+    pub(crate) fn parse(iter: &mut dyn Iterator<Item = &'a String>) -> Self {
+        let Some(arg) = iter.next() else {
+            return Self::None;
+        };
+
+        match arg.as_str() {
+            "--list" | "-l" => Self::List(iter.next().map(|t| t.as_str())),
+            "--set" | "-s" => match (iter.next(), iter.next()) {
+                (Some(name), Some(path)) => Self::Set(name, path),
+                _ => Self::None,
+            },
+            "--auto" | "-a" => match iter.next() {
+                Some(name) => Self::Auto(name),
+                None => Self::None,
+            },
+            "--help" | "-h" => Self::Help,
+            _ => Self::None,
+        }
+    }
+
+    pub(crate) fn help() -> &'static str {
+        "Usage: lpm --alternatives [OPTION]
+
+Options:
+    -l, --list        [<Name>]                                List the providers of alternatives, '*' marks the active one
+    -s, --set         <Name> <Path>                           Use the provider at the given path
+    -a, --auto        <Name>                                  Use the provider with the highest priority
+    -h, --help                                                Print help
+"
+    }
+}
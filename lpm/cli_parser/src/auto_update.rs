@@ -0,0 +1,36 @@
+#[derive(Debug, PartialEq)]
+pub enum AutoUpdateSubcommand<'a> {
+    Run,
+    InstallUnits(Option<&'a str>),
+    Help,
+    None,
+}
+
+impl<'a> AutoUpdateSubcommand<'a> {
+    pub(crate) fn parse(iter: &mut dyn Iterator<Item = &'a String>) -> Self {
+        if let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--install-units" => match (iter.next(), iter.next()) {
+                    (unit_dir, None) => Self::InstallUnits(unit_dir.map(|t| t.as_str())),
+                    _ => Self::None,
+                },
+                "--help" | "-h" => Self::Help,
+                _ => Self::None,
+            }
+        } else {
+            Self::Run
+        }
+    }
+
+    pub(crate) fn help() -> &'static str {
+        "Usage: lpm --auto-update [OPTION]
+
+Updates the packages as allowed by the 'auto_update' policy of the config, every run is
+recorded in /var/log/lpm/auto-update.log.
+
+Options:
+    --install-units [DIR]                                     Write the systemd service and timer running it (default: /etc/systemd/system)
+    -h, --help                                                Print help
+"
+    }
+}
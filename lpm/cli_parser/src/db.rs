@@ -0,0 +1,75 @@
+#[derive(Debug, PartialEq)]
+pub enum DbSubcommand<'a> {
+    Check {
+        verify_size: bool,
+        verify_store: bool,
+    },
+    Revert(&'a str),
+    Status,
+    /// Maintainers and signing keys of the installed packages.
+    Maintainers,
+    RunPendingScripts,
+    /// Re-hash the installed files with the checksum algorithm `to`.
+    Rehash {
+        to: &'a str,
+    },
+    Help,
+    None,
+}
+
+impl<'a> DbSubcommand<'a> {
+    pub(crate) fn parse(iter: &mut dyn Iterator<Item = &'a String>) -> Self {
+        if let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "check" => {
+                    let (mut verify_size, mut verify_store) = (false, false);
+                    for arg in iter {
+                        match arg.as_str() {
+                            "--verify-size" => verify_size = true,
+                            "--verify-store" => verify_store = true,
+                            _ => return Self::None,
+                        }
+                    }
+
+                    Self::Check {
+                        verify_size,
+                        verify_store,
+                    }
+                }
+                "status" => Self::Status,
+                "maintainers" => Self::Maintainers,
+                "run-pending-scripts" => Self::RunPendingScripts,
+                "rehash" => match (iter.next().map(String::as_str), iter.next(), iter.next()) {
+                    (Some("--to"), Some(algorithm), None) => Self::Rehash { to: algorithm },
+                    _ => Self::None,
+                },
+                "revert" => match (iter.next(), iter.next()) {
+                    (Some(name), None) => Self::Revert(name),
+                    _ => Self::None,
+                },
+                "--help" | "-h" => Self::Help,
+                _ => Self::None,
+            }
+        } else {
+            Self::None
+        }
+    }
+
+    pub(crate) fn help() -> &'static str {
+        "Usage: lpm --db [OPTION] [FLAGS]
+
+Options:
+    check                                                     Check the integrity of the package database
+    status                                                    Print the applied and pending migrations
+    revert <MIGRATION>                                        Revert a migration and the ones applied after it
+    maintainers                                               List the maintainers and signing keys of the installed packages
+    run-pending-scripts                                       Run the scripts deferred by foreign architecture installations
+    rehash --to <ALGORITHM>                                   Re-hash the installed files with md5, sha256, sha512 or blake3
+    -h, --help                                                Print help
+
+Flags:
+    --verify-size                                             Also compare recorded package sizes with the files on disk(for check)
+    --verify-store                                            Also check that the installed files are the ones in the content store(for check)
+"
+    }
+}
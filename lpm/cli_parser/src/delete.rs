@@ -4,24 +4,49 @@ use std::collections::HashSet;
 pub struct DeleteArgs<'a> {
     pub packages: HashSet<&'a str>,
     pub print_help: bool,
+    /// File listing package names, one per line.
+    pub from_file: Option<&'a str>,
+    /// Allows deleting packages that are marked as essential.
+    pub force_essential: bool,
+    /// Also deletes the dependencies that were installed along with the packages.
+    pub recursive: bool,
+    /// Also deletes the packages that depend on the given ones.
+    pub cascade: bool,
+    /// Also deletes the config files, which are kept otherwise.
+    pub purge: bool,
 }
 
 impl<'a> DeleteArgs<'a> {
     pub(crate) fn parse(iter: &mut dyn Iterator<Item = &'a String>) -> Self {
         let mut args = DeleteArgs::default();
 
-        for arg in iter {
+        while let Some(arg) = iter.next() {
             match arg.as_str() {
                 "--help" | "-h" => {
                     args.print_help = true;
                 }
+                "--from-file" => {
+                    args.from_file = iter.next().map(|t| t.as_str());
+                }
+                "--force-essential" => {
+                    args.force_essential = true;
+                }
+                "--recursive" | "-r" => {
+                    args.recursive = true;
+                }
+                "--cascade" => {
+                    args.cascade = true;
+                }
+                "--purge" => {
+                    args.purge = true;
+                }
                 _ => {
                     args.packages.insert(arg);
                 }
             }
         }
 
-        if args.packages.is_empty() {
+        if args.packages.is_empty() && args.from_file.is_none() {
             args.print_help = true;
         }
 
@@ -29,13 +54,18 @@ impl<'a> DeleteArgs<'a> {
     }
 
     pub(crate) fn help() -> &'static str {
-        "Usage: lpm --deete [FLAGS] <List of package names>/[OPTION]
+        "Usage: lpm --delete [FLAGS] <List of package names>/[OPTION]
 
 Options:
     -h, --help                                                Print help
+    --from-file <PATH>                                        Read package names from a file, one per line
 
 Flags:
     -y, --yes                                                 Preaccept the confirmation prompts
+    --force-essential                                         Allow deleting essential packages
+    -r, --recursive                                           Also delete the dependencies that nothing else needs
+    --cascade                                                 Also delete the packages that depend on the given ones
+    --purge                                                   Also delete config files, including the ones kept by earlier deletions
 "
     }
 }
@@ -0,0 +1,44 @@
+#[derive(Debug, Default, PartialEq)]
+pub struct DuArgs<'a> {
+    pub package: Option<&'a str>,
+    /// Measure the files on disk instead of using the recorded sizes.
+    pub stat: bool,
+    pub print_help: bool,
+}
+
+impl<'a> DuArgs<'a> {
+    pub(crate) fn parse(iter: &mut dyn Iterator<Item = &'a String>) -> Self {
+        let mut args = DuArgs::default();
+
+        for arg in iter {
+            match arg.as_str() {
+                "--help" | "-h" => {
+                    args.print_help = true;
+                }
+                "--stat" => {
+                    args.stat = true;
+                }
+                _ => {
+                    args.package = Some(arg);
+                }
+            }
+        }
+
+        if args.package.is_none() {
+            args.print_help = true;
+        }
+
+        args
+    }
+
+    pub(crate) fn help() -> &'static str {
+        "Usage: lpm --du [FLAGS] <Package name>/[OPTION]
+
+Options:
+    -h, --help                                                Print help
+
+Flags:
+    --stat                                                    Measure the files on disk instead of using the recorded sizes
+"
+    }
+}
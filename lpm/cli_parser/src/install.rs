@@ -5,6 +5,19 @@ pub struct InstallArgs<'a> {
     pub packages: HashSet<&'a str>,
     pub from_local_package: bool,
     pub print_help: bool,
+    pub root: Option<&'a str>,
+    pub target_arch: Option<&'a str>,
+    /// File listing package names, one per line.
+    pub from_file: Option<&'a str>,
+    /// Glob patterns of package paths to skip, `--exclude` can be repeated.
+    pub exclude: Vec<&'a str>,
+    pub no_docs: bool,
+    /// Comma separated list of locales to keep.
+    pub select_locales: Option<&'a str>,
+    /// Fail instead of warning about missing shared libraries.
+    pub strict: bool,
+    /// Script interpreters allowed in addition to the ones of the config.
+    pub allow_interpreters: Vec<&'a str>,
     // TODO:
     // install_temporary: bool,
     // repository: Option<String>,
@@ -15,11 +28,39 @@ impl<'a> InstallArgs<'a> {
     pub(crate) fn parse(iter: &mut dyn Iterator<Item = &'a String>) -> Self {
         let mut args = InstallArgs::default();
 
-        for arg in iter {
+        while let Some(arg) = iter.next() {
             match arg.as_str() {
                 "--local" | "-L" => {
                     args.from_local_package = true;
                 }
+                "--root" => {
+                    args.root = iter.next().map(|t| t.as_str());
+                }
+                "--target-arch" => {
+                    args.target_arch = iter.next().map(|t| t.as_str());
+                }
+                "--from-file" => {
+                    args.from_file = iter.next().map(|t| t.as_str());
+                }
+                "--no-docs" => {
+                    args.no_docs = true;
+                }
+                "--strict" => {
+                    args.strict = true;
+                }
+                "--select-locales" => {
+                    args.select_locales = iter.next().map(|t| t.as_str());
+                }
+                "--allow-interpreter" => {
+                    if let Some(interpreter) = iter.next() {
+                        args.allow_interpreters.push(interpreter);
+                    }
+                }
+                "--exclude" => {
+                    if let Some(pattern) = iter.next() {
+                        args.exclude.push(pattern);
+                    }
+                }
                 "--help" | "-h" => {
                     args.print_help = true;
                 }
@@ -29,7 +70,7 @@ impl<'a> InstallArgs<'a> {
             }
         }
 
-        if args.packages.is_empty() {
+        if args.packages.is_empty() && args.from_file.is_none() {
             args.print_help = true;
         }
 
@@ -41,10 +82,18 @@ impl<'a> InstallArgs<'a> {
 
 Options:
     -h, --help                                                Print help
+    --root <PATH>                                             Install into an alternate root filesystem
+    --target-arch <ARCH>                                      Architecture of the alternate root(requires --root)
+    --from-file <PATH>                                        Read package names from a file, one per line
+    --exclude <GLOB>                                          Skip package paths matching the pattern(e.g. 'usr/share/doc/*')
+    --select-locales <LIST>                                   Only install the translations of the given locales(e.g. en,fr)
+    --allow-interpreter <NAME>                                Let package scripts use an interpreter missing from the config(e.g. python3)
 
 Flags:
-    -l, --local                                               Activate installation from local *.lod file
+    -l, --local                                               Activate installation from local *.lod file('-' reads it from stdin)
     -y, --yes                                                 Preaccept the confirmation prompts
+    --no-docs                                                 Skip documentation(doc, man and info pages)
+    --strict                                                  Fail if installed binaries need shared libraries missing on the system
 "
     }
 }
@@ -1,10 +1,18 @@
+pub use alternatives::AlternativesSubcommand;
+pub use auto_update::AutoUpdateSubcommand;
+pub use db::DbSubcommand;
 pub use delete::DeleteArgs;
+pub use du::DuArgs;
 pub use install::InstallArgs;
 pub use module::ModuleSubcommand;
 pub use repository::RepositorySubcommand;
 pub use update::UpdateSubcommand;
 
+mod alternatives;
+mod auto_update;
+mod db;
 mod delete;
+mod du;
 mod install;
 mod module;
 mod repository;
@@ -17,6 +25,34 @@ pub enum Command<'a> {
     Delete(DeleteArgs<'a>),
     Module(ModuleSubcommand<'a>),
     Repository(RepositorySubcommand<'a>),
+    Db(DbSubcommand<'a>),
+    DiskUsage(DuArgs<'a>),
+    Alternatives(AlternativesSubcommand<'a>),
+    /// Serve the package operations on the system bus.
+    Daemon,
+    /// Serve the package operations as JSON-RPC on a Unix socket.
+    RpcDaemon(Option<&'a str>),
+    /// Apply a transaction plan printed by `--print-plan json`.
+    ApplyPlan(&'a str),
+    /// Compare the installed packages with a manifest, `apply` converges
+    /// them to it.
+    DiffManifest {
+        path: &'a str,
+        apply: bool,
+    },
+    /// Show the meta data of a `.lod` file.
+    Inspect(&'a str),
+    /// Show an installed package, along with where it came from.
+    Info(&'a str),
+    /// Browse and mark packages interactively.
+    Tui,
+    /// Run queries and stage a transaction from a prompt.
+    Shell,
+    /// Print the available updates without applying them.
+    CheckUpdates,
+    AutoUpdate(AutoUpdateSubcommand<'a>),
+    /// Look for problems left on the system, with their fixes.
+    Doctor,
     Version,
     Help,
 }
@@ -25,33 +61,45 @@ pub enum Command<'a> {
 pub struct CliParser<'a> {
     pub commands: Vec<Command<'a>>,
     pub force_yes: bool,
+    pub offline: bool,
+    pub limit_rate: Option<&'a str>,
+    pub verbose: bool,
+    /// `Some(true)` for `--allow-downgrade`, `Some(false)` for `--no-downgrade`
+    /// and `None` to ask.
+    pub allow_downgrade: Option<bool>,
+    /// Hash the installed files on updates instead of trusting their size
+    /// and modification time.
+    pub paranoid: bool,
+    /// Script interpreters allowed in addition to the ones of the config,
+    /// `--allow-interpreter` can be repeated.
+    pub allow_interpreters: Vec<&'a str>,
+    /// Format of `--print-plan`, which shows the transactions without applying them.
+    pub print_plan: Option<&'a str>,
 }
 
 impl Command<'_> {
     pub fn print_help(&self) {
-        match self {
-            Command::Install(_subcommand) => {
-                println!("{}", InstallArgs::help());
-            }
+        let help = match self {
+            Command::Install(_subcommand) => InstallArgs::help(),
 
-            Command::Update(_pkg_name, _subcommands) => {
-                println!("{}", UpdateSubcommand::help());
-            }
+            Command::Update(_pkg_name, _subcommands) => UpdateSubcommand::help(),
 
-            Command::Delete(_pkg_name) => {
-                println!("{}", DeleteArgs::help());
-            }
+            Command::Delete(_pkg_name) => DeleteArgs::help(),
 
-            Command::Module(_subcommand) => {
-                println!("{}", ModuleSubcommand::help());
-            }
+            Command::Module(_subcommand) => ModuleSubcommand::help(),
 
-            Command::Repository(_subcommand) => {
-                println!("{}", RepositorySubcommand::help());
-            }
+            Command::Repository(_subcommand) => RepositorySubcommand::help(),
+
+            Command::Db(_subcommand) => DbSubcommand::help(),
+
+            Command::DiskUsage(_args) => DuArgs::help(),
+
+            Command::Alternatives(_subcommand) => AlternativesSubcommand::help(),
+
+            Command::AutoUpdate(_subcommand) => AutoUpdateSubcommand::help(),
 
             Command::Help => {
-                let help = "Lod Package Manager Command Line Interface
+                "Lod Package Manager Command Line Interface
 
 Usage: lpm [SUBCOMMAND] [SUBCOMMAND FLAGS] [SUBCOMMAND OPTIONS]
 
@@ -59,16 +107,51 @@ Subcommands:
     -i, --install                                             Install package to system from remote repository or filesystem
     -d, --delete                                              Delete package from system
     -u, --update                                              Update operations(packages, repository index, lpm database migrations)
+    --check-updates                                           Sync the repository indexes and list available updates, exits with 100 if there are any
+    --auto-update                                             Apply the updates allowed by the 'auto_update' policy of the config
     -r, --repository                                          Remote repository operations (add, delete, list)
     -m, --module                                              Dynamic module operations (add, delete, list, run)
+    --db                                                      Package database maintenance (check)
+    --doctor                                                  Check the database, package files, repositories and modules for problems
+    --du                                                      Show the disk usage of an installed package
+    --alternatives                                            Commands provided by several packages (list, set, auto)
+    --inspect <PATH>                                          Show the meta data of a package file without installing it
+    --info <NAME>                                             Show an installed package and the repository it came from
+    --tui                                                     Browse, search and mark packages to install or delete interactively
+    --shell                                                   Run queries and stage a transaction from an interactive prompt
+    --daemon                                                  Serve package operations on the D-Bus system bus
+    --rpc-daemon [SOCKET PATH]                                Serve package operations as JSON-RPC on a Unix socket
+    --apply-plan <PATH>                                       Apply a transaction plan created with '--print-plan json'
+    --diff-manifest <PATH> [--apply]                          Show (or apply) the changes that make the installed packages match a manifest, exits with 1 if they differ
+
+Flags:
+    -y, --yes                                                 Preaccept the confirmation prompts
+    --offline                                                 Forbid network access, use local caches and repositories only
+    --limit-rate <SIZE>                                       Limit download speed per second (e.g. 512K, 2M)
+    --verbose                                                 Print detailed logs (e.g. network retry attempts)
+    --print-plan <FORMAT>                                     Print the transaction (text, json) instead of applying it
+    --allow-interpreter <NAME>                                Let package scripts use an interpreter missing from the config (e.g. python3)
 
 For more specific help, go for `lpm [SUBCOMMAND] --help`
-";
-                println!("{}", help);
+"
             }
 
-            Command::Version => panic!("This should never happen. Seems like a bug."),
-        }
+            Command::Daemon
+            | Command::RpcDaemon(_)
+            | Command::ApplyPlan(_)
+            | Command::DiffManifest { .. }
+            | Command::Inspect(_)
+            | Command::Info(_)
+            | Command::Tui
+            | Command::Shell
+            | Command::CheckUpdates
+            | Command::Doctor
+            | Command::Version => {
+                panic!("This should never happen. Seems like a bug.")
+            }
+        };
+
+        println!("{}", logger::i18n::translate_lines(help));
     }
 }
 
@@ -93,8 +176,24 @@ impl CliParser<'_> {
                             pkg_name = iter.next();
                         };
                     }
-                    while iter.peek().is_some() {
-                        subcommands.push(UpdateSubcommand::parse(&mut iter));
+                    while let Some(value) = iter.peek() {
+                        match value.as_str() {
+                            "--allow-downgrade" => {
+                                cli_parser.allow_downgrade = Some(true);
+                                iter.next();
+                            }
+                            "--no-downgrade" => {
+                                cli_parser.allow_downgrade = Some(false);
+                                iter.next();
+                            }
+                            "--allow-interpreter" => {
+                                iter.next();
+                                if let Some(interpreter) = iter.next() {
+                                    cli_parser.allow_interpreters.push(interpreter);
+                                }
+                            }
+                            _ => subcommands.push(UpdateSubcommand::parse(&mut iter)),
+                        }
                     }
 
                     cli_parser
@@ -116,9 +215,101 @@ impl CliParser<'_> {
                         .commands
                         .push(Command::Repository(RepositorySubcommand::parse(&mut iter)));
                 }
+                "--db" => {
+                    cli_parser
+                        .commands
+                        .push(Command::Db(DbSubcommand::parse(&mut iter)));
+                }
+                "--du" => {
+                    cli_parser
+                        .commands
+                        .push(Command::DiskUsage(DuArgs::parse(&mut iter)));
+                }
+                "--alternatives" => {
+                    cli_parser
+                        .commands
+                        .push(Command::Alternatives(AlternativesSubcommand::parse(
+                            &mut iter,
+                        )));
+                }
+                "--daemon" => {
+                    cli_parser.commands.push(Command::Daemon);
+                }
+                "--rpc-daemon" => {
+                    let socket_path = iter.next_if(|value| !value.starts_with('-'));
+                    cli_parser
+                        .commands
+                        .push(Command::RpcDaemon(socket_path.map(|t| t.as_str())));
+                }
+                "--apply-plan" => {
+                    if let Some(path) = iter.next() {
+                        cli_parser.commands.push(Command::ApplyPlan(path));
+                    }
+                }
+                "--diff-manifest" => {
+                    if let Some(path) = iter.next() {
+                        let apply = iter.next_if(|value| *value == "--apply").is_some();
+                        cli_parser
+                            .commands
+                            .push(Command::DiffManifest { path, apply });
+                    }
+                }
+                "--inspect" => {
+                    if let Some(path) = iter.next() {
+                        cli_parser.commands.push(Command::Inspect(path));
+                    }
+                }
+                "--info" => {
+                    if let Some(name) = iter.next() {
+                        cli_parser.commands.push(Command::Info(name));
+                    }
+                }
+                "--tui" => {
+                    cli_parser.commands.push(Command::Tui);
+                }
+                "--shell" => {
+                    cli_parser.commands.push(Command::Shell);
+                }
+                "--check-updates" => {
+                    cli_parser.commands.push(Command::CheckUpdates);
+                }
+                "--doctor" => {
+                    cli_parser.commands.push(Command::Doctor);
+                }
+                "--auto-update" => {
+                    cli_parser
+                        .commands
+                        .push(Command::AutoUpdate(AutoUpdateSubcommand::parse(&mut iter)));
+                }
+                "--print-plan" => {
+                    cli_parser.print_plan = iter.next().map(|t| t.as_str());
+                }
                 "--yes" | "-y" => {
                     cli_parser.force_yes = true;
                 }
+                "--offline" => {
+                    cli_parser.offline = true;
+                }
+                "--limit-rate" => {
+                    cli_parser.limit_rate = iter.next().map(|t| t.as_str());
+                }
+                "--verbose" => {
+                    cli_parser.verbose = true;
+                }
+                "--allow-downgrade" => {
+                    cli_parser.allow_downgrade = Some(true);
+                }
+                "--no-downgrade" => {
+                    cli_parser.allow_downgrade = Some(false);
+                }
+                "--allow-interpreter" => {
+                    if let Some(interpreter) = iter.next() {
+                        cli_parser.allow_interpreters.push(interpreter);
+                    }
+                }
+                "--paranoid" => {
+                    cli_parser.paranoid = true;
+                }
                 "--version" | "-v" => {
                     cli_parser.commands.push(Command::Version);
                 }
@@ -201,6 +392,212 @@ mod tests {
 
             assert!(cli_parser.commands.contains(&Command::Install(args)));
         }
+
+        {
+            let args = vec![
+                String::from("--install"),
+                String::from("package_name"),
+                String::from("--root"),
+                String::from("/mnt/rootfs"),
+                String::from("--target-arch"),
+                String::from("arm64"),
+            ];
+            let cli_parser = CliParser::parse_args(&args);
+            assert_eq!(cli_parser.commands.len(), 1);
+
+            let mut args = InstallArgs::default();
+            args.packages = HashSet::from(["package_name"]);
+            args.root = Some("/mnt/rootfs");
+            args.target_arch = Some("arm64");
+
+            assert_eq!(cli_parser.commands[0], Command::Install(args));
+        }
+        {
+            let args = vec![
+                String::from("--install"),
+                String::from("--from-file"),
+                String::from("pkgs.txt"),
+            ];
+            let cli_parser = CliParser::parse_args(&args);
+
+            let mut args = InstallArgs::default();
+            args.from_file = Some("pkgs.txt");
+
+            assert_eq!(cli_parser.commands[0], Command::Install(args));
+        }
+        {
+            let args = vec![
+                String::from("--install"),
+                String::from("package_name"),
+                String::from("--allow-interpreter"),
+                String::from("python3"),
+            ];
+            let cli_parser = CliParser::parse_args(&args);
+
+            let mut args = InstallArgs::default();
+            args.packages = HashSet::from(["package_name"]);
+            args.allow_interpreters = vec!["python3"];
+
+            assert_eq!(cli_parser.commands[0], Command::Install(args));
+        }
+    }
+
+    #[test]
+    fn test_parse_global_flags() {
+        let args = vec![
+            String::from("--yes"),
+            String::from("--offline"),
+            String::from("--limit-rate"),
+            String::from("512K"),
+            String::from("--verbose"),
+            String::from("--print-plan"),
+            String::from("json"),
+            String::from("--allow-interpreter"),
+            String::from("perl"),
+            String::from("--paranoid"),
+            String::from("--install"),
+            String::from("package_name"),
+        ];
+        let cli_parser = CliParser::parse_args(&args);
+
+        assert!(cli_parser.force_yes);
+        assert!(cli_parser.offline);
+        assert_eq!(cli_parser.limit_rate, Some("512K"));
+        assert!(cli_parser.verbose);
+        assert_eq!(cli_parser.print_plan, Some("json"));
+        assert_eq!(cli_parser.allow_interpreters, ["perl"]);
+        assert!(cli_parser.paranoid);
+    }
+
+    #[test]
+    fn test_parse_apply_plan() {
+        let args = vec![String::from("--apply-plan"), String::from("plan.json")];
+        let cli_parser = CliParser::parse_args(&args);
+
+        assert_eq!(cli_parser.commands, vec![Command::ApplyPlan("plan.json")]);
+    }
+
+    #[test]
+    fn test_parse_diff_manifest() {
+        let args = vec![
+            String::from("--diff-manifest"),
+            String::from("manifest.txt"),
+        ];
+        let cli_parser = CliParser::parse_args(&args);
+        assert_eq!(
+            cli_parser.commands,
+            vec![Command::DiffManifest {
+                path: "manifest.txt",
+                apply: false
+            }]
+        );
+
+        let args = vec![
+            String::from("--diff-manifest"),
+            String::from("manifest.txt"),
+            String::from("--apply"),
+            String::from("--yes"),
+        ];
+        let cli_parser = CliParser::parse_args(&args);
+        assert_eq!(
+            cli_parser.commands,
+            vec![Command::DiffManifest {
+                path: "manifest.txt",
+                apply: true
+            }]
+        );
+        assert!(cli_parser.force_yes);
+    }
+
+    #[test]
+    fn test_parse_interactive_modes() {
+        let args = vec![String::from("--yes"), String::from("--tui")];
+        let cli_parser = CliParser::parse_args(&args);
+
+        assert_eq!(cli_parser.commands, vec![Command::Tui]);
+        assert!(cli_parser.force_yes);
+
+        let args = vec![String::from("--shell")];
+        let cli_parser = CliParser::parse_args(&args);
+        assert_eq!(cli_parser.commands, vec![Command::Shell]);
+    }
+
+    #[test]
+    fn test_parse_check_updates() {
+        let args = vec![String::from("--offline"), String::from("--check-updates")];
+        let cli_parser = CliParser::parse_args(&args);
+
+        assert_eq!(cli_parser.commands, vec![Command::CheckUpdates]);
+        assert!(cli_parser.offline);
+    }
+
+    #[test]
+    fn test_parse_doctor() {
+        let args = vec![String::from("--doctor")];
+        let cli_parser = CliParser::parse_args(&args);
+
+        assert_eq!(cli_parser.commands, vec![Command::Doctor]);
+    }
+
+    #[test]
+    fn test_parse_auto_update() {
+        let args = vec![String::from("--auto-update")];
+        let cli_parser = CliParser::parse_args(&args);
+        assert_eq!(
+            cli_parser.commands,
+            vec![Command::AutoUpdate(AutoUpdateSubcommand::Run)]
+        );
+
+        let args = vec![
+            String::from("--auto-update"),
+            String::from("--install-units"),
+            String::from("/tmp/units"),
+        ];
+        let cli_parser = CliParser::parse_args(&args);
+        assert_eq!(
+            cli_parser.commands,
+            vec![Command::AutoUpdate(AutoUpdateSubcommand::InstallUnits(
+                Some("/tmp/units")
+            ))]
+        );
+
+        let args = vec![String::from("--auto-update"), String::from("now")];
+        let cli_parser = CliParser::parse_args(&args);
+        assert_eq!(
+            cli_parser.commands,
+            vec![Command::AutoUpdate(AutoUpdateSubcommand::None)]
+        );
+    }
+
+    #[test]
+    fn test_parse_inspect() {
+        let args = vec![String::from("--inspect"), String::from("htop.lod")];
+        let cli_parser = CliParser::parse_args(&args);
+
+        assert_eq!(cli_parser.commands, vec![Command::Inspect("htop.lod")]);
+    }
+
+    #[test]
+    fn test_parse_info() {
+        let args = vec![String::from("--info"), String::from("htop")];
+        let cli_parser = CliParser::parse_args(&args);
+
+        assert_eq!(cli_parser.commands, vec![Command::Info("htop")]);
+    }
+
+    #[test]
+    fn test_parse_daemon() {
+        let args = vec![String::from("--yes"), String::from("--daemon")];
+        let cli_parser = CliParser::parse_args(&args);
+
+        assert_eq!(cli_parser.commands, vec![Command::Daemon]);
+
+        let args = vec![String::from("--rpc-daemon"), String::from("/tmp/lpm.sock")];
+        let cli_parser = CliParser::parse_args(&args);
+        assert_eq!(
+            cli_parser.commands,
+            vec![Command::RpcDaemon(Some("/tmp/lpm.sock"))]
+        );
     }
 
     #[test]
@@ -228,6 +625,45 @@ mod tests {
                 vec![UpdateSubcommand::Local("./path/to/package_name.lod")]
             )));
         }
+        {
+            let args = vec![String::from("--update"), String::from("--stage")];
+            let cli_parser = CliParser::parse_args(&args);
+            assert_eq!(
+                cli_parser.commands,
+                vec![Command::Update(None, vec![UpdateSubcommand::Stage])]
+            );
+        }
+        {
+            let args = vec![String::from("--update"), String::from("--ab")];
+            let cli_parser = CliParser::parse_args(&args);
+            assert_eq!(
+                cli_parser.commands,
+                vec![Command::Update(None, vec![UpdateSubcommand::Ab])]
+            );
+        }
+        {
+            let args = vec![
+                String::from("--update"),
+                String::from("package_name"),
+                String::from("--local"),
+                String::from("./path/to/package_name.lod"),
+                String::from("--allow-downgrade"),
+            ];
+            let cli_parser = CliParser::parse_args(&args);
+            assert_eq!(cli_parser.allow_downgrade, Some(true));
+            assert!(cli_parser.commands.contains(&Command::Update(
+                Some("package_name"),
+                vec![UpdateSubcommand::Local("./path/to/package_name.lod")]
+            )));
+
+            let args = vec![
+                String::from("--no-downgrade"),
+                String::from("--update"),
+                String::from("package_name"),
+            ];
+            let cli_parser = CliParser::parse_args(&args);
+            assert_eq!(cli_parser.allow_downgrade, Some(false));
+        }
     }
 
     #[test]
@@ -258,6 +694,178 @@ mod tests {
 
             assert!(cli_parser.commands.contains(&Command::Delete(args)));
         }
+
+        {
+            let args = vec![
+                String::from("--delete"),
+                String::from("--force-essential"),
+                String::from("--recursive"),
+                String::from("--cascade"),
+                String::from("--purge"),
+                String::from("package_name"),
+            ];
+            let cli_parser = CliParser::parse_args(&args);
+            assert_eq!(cli_parser.commands.len(), 1);
+
+            let mut args = DeleteArgs::default();
+            args.packages = HashSet::from(["package_name"]);
+            args.force_essential = true;
+            args.recursive = true;
+            args.cascade = true;
+            args.purge = true;
+
+            assert!(cli_parser.commands.contains(&Command::Delete(args)));
+        }
+    }
+
+    #[test]
+    fn test_parse_db() {
+        let args = vec![
+            String::from("--db"),
+            String::from("check"),
+            String::from("--verify-size"),
+        ];
+        let cli_parser = CliParser::parse_args(&args);
+        assert_eq!(
+            cli_parser.commands,
+            vec![Command::Db(DbSubcommand::Check {
+                verify_size: true,
+                verify_store: false
+            })]
+        );
+
+        let args = vec![
+            String::from("--db"),
+            String::from("check"),
+            String::from("--verify-store"),
+        ];
+        let cli_parser = CliParser::parse_args(&args);
+        assert_eq!(
+            cli_parser.commands,
+            vec![Command::Db(DbSubcommand::Check {
+                verify_size: false,
+                verify_store: true
+            })]
+        );
+
+        let args = vec![String::from("--db"), String::from("check")];
+        let cli_parser = CliParser::parse_args(&args);
+        assert_eq!(
+            cli_parser.commands,
+            vec![Command::Db(DbSubcommand::Check {
+                verify_size: false,
+                verify_store: false
+            })]
+        );
+
+        let args = vec![
+            String::from("--db"),
+            String::from("revert"),
+            String::from("add_file_sizes"),
+        ];
+        let cli_parser = CliParser::parse_args(&args);
+        assert_eq!(
+            cli_parser.commands,
+            vec![Command::Db(DbSubcommand::Revert("add_file_sizes"))]
+        );
+
+        let args = vec![String::from("--db"), String::from("status")];
+        let cli_parser = CliParser::parse_args(&args);
+        assert_eq!(cli_parser.commands, vec![Command::Db(DbSubcommand::Status)]);
+
+        let args = vec![String::from("--db"), String::from("maintainers")];
+        let cli_parser = CliParser::parse_args(&args);
+        assert_eq!(
+            cli_parser.commands,
+            vec![Command::Db(DbSubcommand::Maintainers)]
+        );
+
+        let args = vec![String::from("--db"), String::from("run-pending-scripts")];
+        let cli_parser = CliParser::parse_args(&args);
+        assert_eq!(
+            cli_parser.commands,
+            vec![Command::Db(DbSubcommand::RunPendingScripts)]
+        );
+
+        let args = vec![
+            String::from("--db"),
+            String::from("rehash"),
+            String::from("--to"),
+            String::from("blake3"),
+        ];
+        let cli_parser = CliParser::parse_args(&args);
+        assert_eq!(
+            cli_parser.commands,
+            vec![Command::Db(DbSubcommand::Rehash { to: "blake3" })]
+        );
+
+        let args = vec![String::from("--db"), String::from("rehash")];
+        let cli_parser = CliParser::parse_args(&args);
+        assert_eq!(cli_parser.commands, vec![Command::Db(DbSubcommand::None)]);
+
+        let args = vec![String::from("--db"), String::from("revert")];
+        let cli_parser = CliParser::parse_args(&args);
+        assert_eq!(cli_parser.commands, vec![Command::Db(DbSubcommand::None)]);
+    }
+
+    #[test]
+    fn test_parse_alternatives() {
+        let args = vec![
+            String::from("--alternatives"),
+            String::from("--set"),
+            String::from("vi"),
+            String::from("/usr/bin/nvi"),
+        ];
+        let cli_parser = CliParser::parse_args(&args);
+        assert_eq!(
+            cli_parser.commands,
+            vec![Command::Alternatives(AlternativesSubcommand::Set(
+                "vi",
+                "/usr/bin/nvi"
+            ))]
+        );
+
+        let args = vec![String::from("--alternatives"), String::from("--list")];
+        let cli_parser = CliParser::parse_args(&args);
+        assert_eq!(
+            cli_parser.commands,
+            vec![Command::Alternatives(AlternativesSubcommand::List(None))]
+        );
+
+        let args = vec![String::from("--alternatives"), String::from("--auto")];
+        let cli_parser = CliParser::parse_args(&args);
+        assert_eq!(
+            cli_parser.commands,
+            vec![Command::Alternatives(AlternativesSubcommand::None)]
+        );
+    }
+
+    #[test]
+    fn test_parse_du() {
+        let args = vec![
+            String::from("--du"),
+            String::from("htop"),
+            String::from("--stat"),
+        ];
+        let cli_parser = CliParser::parse_args(&args);
+        assert_eq!(
+            cli_parser.commands,
+            vec![Command::DiskUsage(DuArgs {
+                package: Some("htop"),
+                stat: true,
+                print_help: false,
+            })]
+        );
+
+        let args = vec![String::from("--du")];
+        let cli_parser = CliParser::parse_args(&args);
+        assert_eq!(
+            cli_parser.commands,
+            vec![Command::DiskUsage(DuArgs {
+                print_help: true,
+                ..Default::default()
+            })]
+        );
     }
 
     #[test]
@@ -272,9 +880,27 @@ mod tests {
             ];
             let cli_parser = CliParser::parse_args(&args);
             assert_eq!(cli_parser.commands.len(), 1);
-            let expected_command =
-                Command::Module(ModuleSubcommand::Add(vec!["arg1", "arg2", "arg3"]));
+            let expected_command = Command::Module(ModuleSubcommand::Add {
+                arguments: vec!["arg1", "arg2", "arg3"],
+                allow_db_write: false,
+            });
             assert!(cli_parser.commands.contains(&expected_command));
+
+            let args = vec![
+                String::from("--module"),
+                String::from("--add"),
+                String::from("arg1"),
+                String::from("--allow-db-write"),
+                String::from("arg2"),
+            ];
+            let cli_parser = CliParser::parse_args(&args);
+            assert_eq!(
+                cli_parser.commands,
+                vec![Command::Module(ModuleSubcommand::Add {
+                    arguments: vec!["arg1", "arg2"],
+                    allow_db_write: true,
+                })]
+            );
         }
 
         {
@@ -299,6 +925,26 @@ mod tests {
             let expected_command = Command::Module(ModuleSubcommand::List);
             assert!(cli_parser.commands.contains(&expected_command));
         }
+
+        {
+            let args = vec![
+                String::from("--module"),
+                String::from("--help"),
+                String::from("module-name"),
+            ];
+            let cli_parser = CliParser::parse_args(&args);
+            assert_eq!(
+                cli_parser.commands,
+                vec![Command::Module(ModuleSubcommand::Help(Some("module-name")))]
+            );
+
+            let args = vec![String::from("--module"), String::from("--help")];
+            let cli_parser = CliParser::parse_args(&args);
+            assert_eq!(
+                cli_parser.commands,
+                vec![Command::Module(ModuleSubcommand::Help(None))]
+            );
+        }
     }
     #[test]
 
@@ -312,13 +958,31 @@ mod tests {
             ];
             let cli_parser = CliParser::parse_args(&args);
             assert_eq!(cli_parser.commands.len(), 1);
-            let expected_command = Command::Repository(RepositorySubcommand::Add(vec![
-                "repository-name",
-                "http://example.address",
-            ]));
+            let expected_command = Command::Repository(RepositorySubcommand::Add {
+                arguments: vec!["repository-name", "http://example.address"],
+                skip_check: false,
+            });
             assert!(cli_parser.commands.contains(&expected_command));
         }
 
+        {
+            let args = vec![
+                String::from("--repository"),
+                String::from("--add"),
+                String::from("repository-name"),
+                String::from("--skip-check"),
+                String::from("/srv/repository"),
+            ];
+            let cli_parser = CliParser::parse_args(&args);
+            assert_eq!(
+                cli_parser.commands,
+                vec![Command::Repository(RepositorySubcommand::Add {
+                    arguments: vec!["repository-name", "/srv/repository"],
+                    skip_check: true,
+                })]
+            );
+        }
+
         {
             let args = vec![
                 String::from("--repository"),
@@ -344,6 +1008,100 @@ mod tests {
             let expected_command = Command::Repository(RepositorySubcommand::List);
             assert!(cli_parser.commands.contains(&expected_command));
         }
+
+        {
+            let args = vec![
+                String::from("--repository"),
+                String::from("--rank-mirrors"),
+                String::from("repository-name"),
+            ];
+            let cli_parser = CliParser::parse_args(&args);
+            assert_eq!(
+                cli_parser.commands,
+                vec![Command::Repository(RepositorySubcommand::RankMirrors(
+                    vec!["repository-name"]
+                ))]
+            );
+        }
+
+        {
+            let args = vec![String::from("--repository"), String::from("--keys")];
+            let cli_parser = CliParser::parse_args(&args);
+            assert_eq!(
+                cli_parser.commands,
+                vec![Command::Repository(RepositorySubcommand::Keys)]
+            );
+        }
+
+        {
+            let args = vec![
+                String::from("--repository"),
+                String::from("--trust"),
+                String::from("release-2024"),
+                String::from("d75a98"),
+            ];
+            let cli_parser = CliParser::parse_args(&args);
+            assert_eq!(
+                cli_parser.commands,
+                vec![Command::Repository(RepositorySubcommand::Trust {
+                    key_id: Some("release-2024"),
+                    public_key: Some("d75a98"),
+                })]
+            );
+
+            let args = vec![
+                String::from("--repository"),
+                String::from("--revoke"),
+                String::from("release-2019"),
+            ];
+            let cli_parser = CliParser::parse_args(&args);
+            assert_eq!(
+                cli_parser.commands,
+                vec![Command::Repository(RepositorySubcommand::Revoke(Some(
+                    "release-2019"
+                )))]
+            );
+        }
+
+        {
+            let args = vec![
+                String::from("--repository"),
+                String::from("--list-packages"),
+                String::from("repository-name"),
+                String::from("--sort"),
+                String::from("size"),
+                String::from("--page"),
+                String::from("2"),
+            ];
+            let cli_parser = CliParser::parse_args(&args);
+            assert_eq!(
+                cli_parser.commands,
+                vec![Command::Repository(RepositorySubcommand::ListPackages {
+                    repository: Some("repository-name"),
+                    sort: Some("size"),
+                    page: Some("2"),
+                    page_size: None,
+                })]
+            );
+        }
+
+        {
+            let args = vec![
+                String::from("--repository"),
+                String::from("--configure"),
+                String::from("repository-name"),
+                String::from("exclude=*-devel,*-docs"),
+                String::from("bandwidth=512K"),
+            ];
+            let cli_parser = CliParser::parse_args(&args);
+            assert_eq!(cli_parser.commands.len(), 1);
+            let expected_command = Command::Repository(RepositorySubcommand::Configure(vec![
+                "repository-name",
+                "exclude=*-devel,*-docs",
+                "bandwidth=512K",
+            ]));
+            assert!(cli_parser.commands.contains(&expected_command));
+        }
     }
 
     #[test]
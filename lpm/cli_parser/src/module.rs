@@ -1,9 +1,14 @@
 #[derive(Debug, PartialEq)]
 pub enum ModuleSubcommand<'a> {
-    Add(Vec<&'a str>),
+    Add {
+        arguments: Vec<&'a str>,
+        /// Lets the module write to the core database through the host.
+        allow_db_write: bool,
+    },
     Delete(Vec<&'a str>),
     List,
-    Help,
+    /// Help of `lpm --module`, or of the given module.
+    Help(Option<&'a str>),
     None,
 }
 
@@ -12,11 +17,20 @@ impl<'a> ModuleSubcommand<'a> {
         if let Some(arg) = iter.next() {
             match arg.as_str() {
                 "--add" | "-a" => {
-                    let arguments: Vec<&str> = iter
-                        .take_while(|&arg| !arg.starts_with('-'))
-                        .map(|arg| arg.as_str())
-                        .collect();
-                    Self::Add(arguments)
+                    let mut arguments = vec![];
+                    let mut allow_db_write = false;
+                    for arg in iter {
+                        match arg.as_str() {
+                            "--allow-db-write" => allow_db_write = true,
+                            _ if arg.starts_with('-') => break,
+                            _ => arguments.push(arg.as_str()),
+                        }
+                    }
+
+                    Self::Add {
+                        arguments,
+                        allow_db_write,
+                    }
                 }
                 "--delete" | "-d" => {
                     let arguments: Vec<&str> = iter
@@ -26,7 +40,13 @@ impl<'a> ModuleSubcommand<'a> {
                     Self::Delete(arguments)
                 }
                 "--list" | "-l" => Self::List,
-                "--help" | "-h" => Self::Help,
+                "--help" | "-h" => {
+                    let module_name = iter
+                        .take_while(|&arg| !arg.starts_with('-'))
+                        .map(|arg| arg.as_str())
+                        .next();
+                    Self::Help(module_name)
+                }
                 _ => Self::None,
             }
         } else {
@@ -41,9 +61,10 @@ Options:
     -a, --add         <Module Name> <Dylib Path>              Add dynamic module
     -d, --delete      [<Module Name>]                         Delete list of dynamic modules
     -l, --list                                                List usable dynamic modules on system
-    -h, --help                                                Print help
+    -h, --help        [<Module Name>]                         Print help, or the usage of the given module
 
 Flags:
+    --allow-db-write                                          Let the added module write to the core database
     -y, --yes                                                 Preaccept the confirmation prompts
 "
     }
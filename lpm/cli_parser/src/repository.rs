@@ -1,8 +1,26 @@
 #[derive(Debug, PartialEq)]
 pub enum RepositorySubcommand<'a> {
-    Add(Vec<&'a str>),
+    Add {
+        arguments: Vec<&'a str>,
+        skip_check: bool,
+    },
     Delete(Vec<&'a str>),
+    Configure(Vec<&'a str>),
+    RankMirrors(Vec<&'a str>),
     List,
+    /// Browse the packages offered by a repository, a page at a time.
+    ListPackages {
+        repository: Option<&'a str>,
+        sort: Option<&'a str>,
+        page: Option<&'a str>,
+        page_size: Option<&'a str>,
+    },
+    Keys,
+    Trust {
+        key_id: Option<&'a str>,
+        public_key: Option<&'a str>,
+    },
+    Revoke(Option<&'a str>),
     Help,
     None,
 }
@@ -12,20 +30,69 @@ impl<'a> RepositorySubcommand<'a> {
         if let Some(arg) = iter.next() {
             match arg.as_str() {
                 "--add" | "-a" => {
+                    let mut arguments = vec![];
+                    let mut skip_check = false;
+                    for arg in iter {
+                        match arg.as_str() {
+                            "--skip-check" => skip_check = true,
+                            _ if arg.starts_with('-') => break,
+                            _ => arguments.push(arg.as_str()),
+                        }
+                    }
+
+                    Self::Add {
+                        arguments,
+                        skip_check,
+                    }
+                }
+                "--delete" | "-d" => {
                     let arguments: Vec<&str> = iter
                         .take_while(|&arg| !arg.starts_with('-'))
                         .map(|arg| arg.as_str())
                         .collect();
-                    Self::Add(arguments)
+                    Self::Delete(arguments)
                 }
-                "--delete" | "-d" => {
+                "--configure" | "-c" => {
                     let arguments: Vec<&str> = iter
                         .take_while(|&arg| !arg.starts_with('-'))
                         .map(|arg| arg.as_str())
                         .collect();
-                    Self::Delete(arguments)
+                    Self::Configure(arguments)
+                }
+                "--rank-mirrors" => {
+                    let arguments: Vec<&str> = iter
+                        .take_while(|&arg| !arg.starts_with('-'))
+                        .map(|arg| arg.as_str())
+                        .collect();
+                    Self::RankMirrors(arguments)
                 }
                 "--list" | "-l" => Self::List,
+                "--list-packages" => {
+                    let mut repository = None;
+                    let (mut sort, mut page, mut page_size) = (None, None, None);
+                    while let Some(arg) = iter.next() {
+                        match arg.as_str() {
+                            "--sort" => sort = iter.next().map(|t| t.as_str()),
+                            "--page" => page = iter.next().map(|t| t.as_str()),
+                            "--page-size" => page_size = iter.next().map(|t| t.as_str()),
+                            _ if arg.starts_with('-') => break,
+                            _ => repository = Some(arg.as_str()),
+                        }
+                    }
+
+                    Self::ListPackages {
+                        repository,
+                        sort,
+                        page,
+                        page_size,
+                    }
+                }
+                "--keys" => Self::Keys,
+                "--trust" => Self::Trust {
+                    key_id: iter.next().map(|t| t.as_str()),
+                    public_key: iter.next().map(|t| t.as_str()),
+                },
+                "--revoke" => Self::Revoke(iter.next().map(|t| t.as_str())),
                 "--help" | "-h" => Self::Help,
                 _ => Self::None,
             }
@@ -40,10 +107,20 @@ impl<'a> RepositorySubcommand<'a> {
 Options:
     -a, --add         <Repository Name> <Repository URL>      Add package repository
     -d, --delete      [<Repository Name>]                     Delete list of package repositories
+    -c, --configure   <Repository Name> [<Key=Value>]         Set repository options (arch, include, exclude, signature, bandwidth, proxy, mirrors, ca, cert, key)
+    --rank-mirrors    [<Repository Name>]                     Measure the mirrors of repositories and have downloads try the fastest first
     -l, --list                                                List active package repositories on system
+    --list-packages   <Repository Name>                       List the packages offered by a repository
+    --keys                                                    List trusted signing keys, from the database and /etc/lpm/trusted.keys.d
+    --trust           <Key ID> <Public Key>                   Trust a hex encoded Ed25519 signing key
+    --revoke          <Key ID>                                Revoke a signing key
     -h, --help                                                Print help
 
 Flags:
+    --skip-check                                              Add the repository without checking that it's reachable(for add)
+    --sort <name|size>                                        Order of the listed packages, by name by default(for list-packages)
+    --page <N>                                                Page of the listed packages to print, from 1(for list-packages)
+    --page-size <N>                                           Number of packages per page, 50 by default(for list-packages)
     -y, --yes                                                 Preaccept the confirmation prompts
 "
     }
@@ -5,6 +5,14 @@ pub enum UpdateSubcommand<'a> {
     Db,
     Packages,
     All,
+    /// Download the updates now and apply them on the next boot.
+    Stage,
+    /// Apply the staged updates, run on boot.
+    ApplyStaged,
+    /// Rebuild the inactive slot of an image-based system and boot it next.
+    Ab,
+    /// Settle the last A/B update, run on boot.
+    AbConfirm,
     Help,
     None,
 }
@@ -24,6 +32,10 @@ impl<'a> UpdateSubcommand<'a> {
                 "--packages" | "-p" => Self::Packages,
                 "--index" | "-i" => Self::Index,
                 "--db" | "-d" => Self::Db,
+                "--stage" => Self::Stage,
+                "--apply-staged" => Self::ApplyStaged,
+                "--ab" => Self::Ab,
+                "--ab-confirm" => Self::AbConfirm,
                 "--help" | "-h" => Self::Help,
                 _ => Self::None,
             }
@@ -40,11 +52,19 @@ Options:
     -p, --packages                                            Update all the installed packages
     -i, --index                                               Update repository index from remote
     -d, --db                                                  Update lpm database(by applying remote migrations)
+    --stage                                                   Download and verify the package updates, apply them on the next boot
+    --apply-staged                                            Apply the staged updates(run on boot by lpm-offline-update.service)
+    --ab                                                      Rebuild the inactive A/B slot with the latest packages and boot it next
+    --ab-confirm                                              Bless the booted A/B slot or record the rollback(run on boot by lpm-ab-confirm.service)
     -h, --help                                                Print help
 
 Flags:
     -l, --local                                               Activate updates from local *.lod file
     -y, --yes                                                 Preaccept the confirmation prompts
+    --allow-downgrade                                         Apply packages older than the installed ones without asking
+    --no-downgrade                                            Skip packages older than the installed ones
+    --allow-interpreter <NAME>                                Let package scripts use an interpreter missing from the config
+    --paranoid                                                Hash every installed file instead of trusting unchanged sizes and modification times
 "
     }
 }
@@ -0,0 +1,183 @@
+use crate::{NO_ARCH, SYSTEM_ARCH};
+
+use elf::{Class, Elf, Endian};
+
+/// Known alternative spellings of the architectures, mapped to the names lpm
+/// uses internally.
+const ARCH_ALIASES: &[(&str, &[&str])] = &[
+    ("amd64", &["x86_64", "x86-64", "x64"]),
+    ("arm64", &["aarch64", "armv8"]),
+    ("arm", &["armhf", "armv7", "armv7l", "armv7hl"]),
+    ("i386", &["i486", "i586", "i686", "x86"]),
+    (NO_ARCH, &["noarch", "any", "all"]),
+];
+
+/// Architectures whose binaries can run next to the native ones(multilib).
+const COMPATIBLE_ARCHS: &[(&str, &[&str])] = &[("amd64", &["i386"]), ("arm64", &["arm"])];
+
+/// Returns the canonical name of the given architecture, or the lowercased
+/// input if it's not a known alias.
+pub fn normalize(arch: &str) -> String {
+    let arch = arch.trim().to_lowercase();
+
+    for (canonical, aliases) in ARCH_ALIASES {
+        if arch == *canonical || aliases.contains(&arch.as_str()) {
+            return canonical.to_string();
+        }
+    }
+
+    arch
+}
+
+/// Compares two architectures by their canonical names.
+pub fn is_same(a: &str, b: &str) -> bool {
+    normalize(a) == normalize(b)
+}
+
+/// Returns true if `arch` is neither the native architecture nor `no-arch`.
+pub fn is_foreign(arch: &str) -> bool {
+    !is_same(arch, NO_ARCH) && !is_same(arch, SYSTEM_ARCH)
+}
+
+/// Returns true if packages built for `arch` can be installed on this system,
+/// either natively or as a multilib variant.
+pub fn is_supported_by_system(arch: &str) -> bool {
+    is_supported_by(SYSTEM_ARCH, arch)
+}
+
+/// Returns true if packages built for `arch` can be installed on a `target`
+/// system, either natively or as a multilib variant.
+pub fn is_supported_by(target: &str, arch: &str) -> bool {
+    let (target, arch) = (normalize(target), normalize(arch));
+    if arch == NO_ARCH || arch == target {
+        return true;
+    }
+
+    COMPATIBLE_ARCHS
+        .iter()
+        .any(|(native, compatibles)| *native == target && compatibles.contains(&arch.as_str()))
+}
+
+/// Returns the canonical name of `arch` followed by its known aliases, which
+/// indexes may use for it.
+pub fn spellings(arch: &str) -> Vec<String> {
+    let arch = normalize(arch);
+
+    match ARCH_ALIASES
+        .iter()
+        .find(|(canonical, _)| *canonical == arch)
+    {
+        Some((canonical, aliases)) => std::iter::once(canonical)
+            .chain(aliases.iter())
+            .map(|t| t.to_string())
+            .collect(),
+        None => vec![arch],
+    }
+}
+
+/// Returns the canonical names of the architectures that a `target` system
+/// can install packages of, `no-arch` included.
+pub fn supported_archs(target: &str) -> Vec<String> {
+    let target = normalize(target);
+    let mut archs = vec![target.clone(), NO_ARCH.to_owned()];
+
+    if let Some((_, compatibles)) = COMPATIBLE_ARCHS.iter().find(|(t, _)| *t == target) {
+        archs.extend(compatibles.iter().map(|t| t.to_string()));
+    }
+
+    archs
+}
+
+/// Returns the architecture an ELF file is built for, `None` if lpm doesn't
+/// know its machine type.
+pub fn of_elf(elf: &Elf) -> Option<&'static str> {
+    let arch = match (elf.machine, elf.class, elf.endian) {
+        (3, Class::Elf32, _) => "i386",
+        (62, Class::Elf64, _) => "amd64",
+        (40, Class::Elf32, _) => "arm",
+        (183, Class::Elf64, _) => "arm64",
+        (243, Class::Elf64, _) => "riscv64",
+        (21, Class::Elf64, Endian::Little) => "ppc64le",
+        (22, Class::Elf64, _) => "s390x",
+        _ => return None,
+    };
+
+    Some(arch)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_arch() {
+        assert_eq!(normalize("amd64"), "amd64");
+        assert_eq!(normalize("x86_64"), "amd64");
+        assert_eq!(normalize("X86_64"), "amd64");
+        assert_eq!(normalize("aarch64"), "arm64");
+        assert_eq!(normalize("armv7l"), "arm");
+        assert_eq!(normalize("i686"), "i386");
+        assert_eq!(normalize("noarch"), NO_ARCH);
+        assert_eq!(normalize(" riscv64 "), "riscv64");
+    }
+
+    #[test]
+    fn test_arch_comparison() {
+        assert!(is_same("x86_64", "amd64"));
+        assert!(is_same("arm64", "aarch64"));
+        assert!(!is_same("arm", "arm64"));
+
+        assert!(is_supported_by_system(NO_ARCH));
+        assert!(is_supported_by_system("any"));
+        assert!(is_supported_by_system(SYSTEM_ARCH));
+        assert!(!is_foreign(SYSTEM_ARCH));
+        assert!(!is_foreign("noarch"));
+        assert!(!is_supported_by_system("unknown-arch"));
+    }
+
+    #[test]
+    fn test_arch_support_of_target() {
+        assert!(is_supported_by("aarch64", "arm64"));
+        assert!(is_supported_by("arm64", "armhf"));
+        assert!(is_supported_by("arm64", "noarch"));
+        assert!(!is_supported_by("arm64", "amd64"));
+        assert!(!is_supported_by("arm", "arm64"));
+    }
+
+    #[test]
+    fn test_arch_spellings() {
+        assert_eq!(spellings("aarch64"), ["arm64", "aarch64", "armv8"]);
+        assert_eq!(spellings("any"), [NO_ARCH, "noarch", "any", "all"]);
+        assert_eq!(spellings("RISCV64"), ["riscv64"]);
+
+        assert_eq!(supported_archs("x86_64"), ["amd64", NO_ARCH, "i386"]);
+        assert_eq!(supported_archs("arm"), ["arm", NO_ARCH]);
+        assert_eq!(supported_archs("riscv64"), ["riscv64", NO_ARCH]);
+    }
+
+    #[test]
+    fn test_arch_of_elf() {
+        let elf = |machine, class| Elf {
+            class,
+            endian: Endian::Little,
+            machine,
+            needed: Vec::new(),
+            soname: None,
+            runpath: Vec::new(),
+        };
+
+        assert_eq!(of_elf(&elf(62, Class::Elf64)), Some("amd64"));
+        assert_eq!(of_elf(&elf(3, Class::Elf32)), Some("i386"));
+        assert_eq!(of_elf(&elf(183, Class::Elf64)), Some("arm64"));
+        assert_eq!(of_elf(&elf(62, Class::Elf32)), None);
+        assert_eq!(of_elf(&elf(0xffff, Class::Elf64)), None);
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_multilib_arch_support() {
+        assert!(is_foreign("i686"));
+        assert!(is_supported_by_system("i686"));
+        assert!(!is_supported_by_system("arm64"));
+    }
+}
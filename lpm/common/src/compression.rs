@@ -0,0 +1,149 @@
+//! Compression of the `.lod` archives, given to the package builder as
+//! `<algorithm>[:<level>]`, e.g. `lz4:12` or `none`, and recorded in the
+//! `compression` field of `meta.json`.
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Algorithm {
+    Lz4,
+    /// Plain tar archives.
+    None,
+}
+
+impl Algorithm {
+    pub const ALL: [Algorithm; 2] = [Algorithm::Lz4, Algorithm::None];
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|t| t.as_str() == name)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Algorithm::Lz4 => "lz4",
+            Algorithm::None => "none",
+        }
+    }
+
+    /// Levels the algorithm accepts and the one used when none is given,
+    /// `None` if it has no levels.
+    fn levels(&self) -> Option<(u32, u32, u32)> {
+        match self {
+            Algorithm::Lz4 => Some((1, 12, 9)),
+            Algorithm::None => None,
+        }
+    }
+
+    /// Algorithm of the stream starting with `magic`, streams without a known
+    /// magic number are taken as plain tar archives.
+    pub fn detect(magic: &[u8]) -> Self {
+        if magic.starts_with(&[0x04, 0x22, 0x4d, 0x18]) {
+            Algorithm::Lz4
+        } else {
+            Algorithm::None
+        }
+    }
+}
+
+/// Longest magic number that `Algorithm::detect` looks for.
+pub const MAGIC_SIZE: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Compression {
+    pub algorithm: Algorithm,
+    /// `None` for `Algorithm::None` only.
+    pub level: Option<u32>,
+}
+
+impl Compression {
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let (name, level) = match spec.split_once(':') {
+            Some((name, level)) => (name, Some(level)),
+            None => (spec, None),
+        };
+
+        let algorithm = Algorithm::parse(name).ok_or_else(|| {
+            let names: Vec<&str> = Algorithm::ALL.iter().map(Algorithm::as_str).collect();
+            format!(
+                "Unknown compression '{name}', expected one of: {}.",
+                names.join(", ")
+            )
+        })?;
+
+        let level = match (algorithm.levels(), level) {
+            (None, None) => None,
+            (None, Some(_)) => {
+                return Err(format!("Compression '{name}' doesn't take a level."));
+            }
+            (Some((_, _, default)), None) => Some(default),
+            (Some((min, max, _)), Some(level)) => {
+                let level = level
+                    .parse()
+                    .ok()
+                    .filter(|t| (min..=max).contains(t))
+                    .ok_or_else(|| {
+                        format!("Level of '{name}' must be between {min} and {max}, got '{level}'.")
+                    })?;
+                Some(level)
+            }
+        };
+
+        Ok(Self { algorithm, level })
+    }
+}
+
+/// LZ4 at its default level, which is what the packages that predate the
+/// `compression` field use.
+impl Default for Compression {
+    fn default() -> Self {
+        Self {
+            algorithm: Algorithm::Lz4,
+            level: Algorithm::Lz4.levels().map(|(_, _, default)| default),
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.level {
+            Some(level) => write!(f, "{}:{level}", self.algorithm.as_str()),
+            None => f.write_str(self.algorithm.as_str()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_compression() {
+        let parse = |spec| Compression::parse(spec).map(|t| t.to_string());
+
+        assert_eq!(parse("lz4:12").as_deref(), Ok("lz4:12"));
+        assert_eq!(parse("lz4").as_deref(), Ok("lz4:9"));
+        assert_eq!(parse("none").as_deref(), Ok("none"));
+        assert_eq!(Compression::default().to_string(), "lz4:9");
+
+        assert!(parse("lz4:13").is_err());
+        assert!(parse("lz4:fast").is_err());
+        assert!(parse("none:1").is_err());
+        assert_eq!(
+            parse("zstd:19"),
+            Err(String::from(
+                "Unknown compression 'zstd', expected one of: lz4, none."
+            ))
+        );
+        assert!(parse("xz").is_err());
+    }
+
+    #[test]
+    fn test_detect_algorithm() {
+        assert_eq!(
+            Algorithm::detect(&[0x04, 0x22, 0x4d, 0x18, 0x60]),
+            Algorithm::Lz4
+        );
+        assert_eq!(Algorithm::detect(b"meta/"), Algorithm::None);
+        assert_eq!(Algorithm::detect(&[]), Algorithm::None);
+    }
+}
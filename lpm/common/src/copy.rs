@@ -0,0 +1,347 @@
+//! File copies for installing package payloads.
+//!
+//! On copy-on-write filesystems like btrfs and XFS the copies share the data
+//! blocks of the source(reflinks), so installing large packages takes neither
+//! the time nor the space of a full copy. Holes of sparse files(e.g. disk
+//! images) are kept as holes instead of being written out as zeros.
+//!
+//! The modes and modification times that packages record for their files and
+//! directories are applied with `set_attributes`, so installed trees don't
+//! depend on the time of installation. Owners that packages declare for them
+//! are applied with `set_ownership`, looked up in the users and groups of the
+//! installation root.
+
+use std::{
+    ffi::CString,
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom},
+    os::{
+        raw::{c_char, c_int, c_long, c_ulong},
+        unix::{
+            ffi::OsStrExt,
+            fs::{MetadataExt, PermissionsExt},
+            io::AsRawFd,
+        },
+    },
+    path::Path,
+};
+
+/// `_IOW(0x94, 9, int)` from `linux/fs.h`
+const FICLONE: c_ulong = 0x4004_9409;
+const SEEK_DATA: c_int = 3;
+const SEEK_HOLE: c_int = 4;
+const ENXIO: i32 = 6;
+const AT_FDCWD: c_int = -100;
+const UTIME_OMIT: c_long = (1 << 30) - 2;
+
+/// timespec port from C
+#[repr(C)]
+struct Timespec {
+    tv_sec: i64,
+    tv_nsec: c_long,
+}
+
+extern "C" {
+    fn ioctl(fd: c_int, request: c_ulong, ...) -> c_int;
+    fn lseek(fd: c_int, offset: i64, whence: c_int) -> i64;
+    fn lchown(pathname: *const c_char, owner: u32, group: u32) -> c_int;
+    fn utimensat(
+        dirfd: c_int,
+        pathname: *const c_char,
+        times: *const Timespec,
+        flags: c_int,
+    ) -> c_int;
+}
+
+/// Copies `from` to `to` along with its permissions, like `fs::copy`.
+///
+/// The copy is a reflink when both are on the same filesystem and it supports
+/// them. Otherwise sparse files are copied region by region, and the others
+/// go through `fs::copy`(which still lets the kernel optimize it with
+/// `copy_file_range`).
+pub fn copy_file(from: &Path, to: &Path) -> io::Result<u64> {
+    let source = File::open(from)?;
+    let metadata = source.metadata()?;
+    if !metadata.is_file() {
+        return fs::copy(from, to);
+    }
+
+    let mut destination = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(to)?;
+
+    let is_copied = clone_file(&source, &destination)
+        || (is_sparse(&metadata) && copy_data_regions(&source, &mut destination, metadata.len())?);
+
+    if is_copied {
+        destination.set_permissions(metadata.permissions())?;
+        return Ok(metadata.len());
+    }
+
+    drop(destination);
+    fs::copy(from, to)
+}
+
+/// Copies `from` to `to` along with its permissions only if the copy can be a
+/// reflink, returns whether it could. `to` is left alone otherwise.
+pub fn reflink_file(from: &Path, to: &Path) -> io::Result<bool> {
+    let source = File::open(from)?;
+    let metadata = source.metadata()?;
+    if !metadata.is_file() {
+        return Ok(false);
+    }
+
+    let destination = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(to)?;
+    if !clone_file(&source, &destination) {
+        drop(destination);
+        fs::remove_file(to)?;
+        return Ok(false);
+    }
+
+    destination.set_permissions(metadata.permissions())?;
+    Ok(true)
+}
+
+/// Shares the data blocks of `source` with the empty `destination`, fails
+/// with e.g. EXDEV across filesystems, EOPNOTSUPP on ext4 or tmpfs.
+fn clone_file(source: &File, destination: &File) -> bool {
+    #[allow(unsafe_code)]
+    let status = unsafe { ioctl(destination.as_raw_fd(), FICLONE, source.as_raw_fd()) };
+    status == 0
+}
+
+/// Sets the permission bits and the modification time(in seconds since the
+/// epoch) of `path`, leaving out the ones that are `None`.
+pub fn set_attributes(path: &Path, mode: Option<u32>, mtime: Option<i64>) -> io::Result<()> {
+    if let Some(mode) = mode {
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    }
+
+    if let Some(mtime) = mtime {
+        let c_path = CString::new(path.as_os_str().as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let times = [
+            // Access time
+            Timespec {
+                tv_sec: 0,
+                tv_nsec: UTIME_OMIT,
+            },
+            Timespec {
+                tv_sec: mtime,
+                tv_nsec: 0,
+            },
+        ];
+
+        #[allow(unsafe_code)]
+        if unsafe { utimensat(AT_FDCWD, c_path.as_ptr(), times.as_ptr(), 0) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+/// Changes the owner and group of `path`(not following symlinks) to the
+/// declared names or ids. The names are looked up in `etc/passwd` and
+/// `etc/group` of `root`, and root is used for the ones that are `None`, so
+/// the ownership of the extracted files never carries over.
+///
+/// Must come before `set_attributes`, as changing the owner clears the setuid
+/// and setgid bits.
+pub fn set_ownership(
+    root: &Path,
+    path: &Path,
+    owner: Option<&str>,
+    group: Option<&str>,
+) -> io::Result<()> {
+    let uid = match owner {
+        Some(owner) => resolve_id(&root.join("etc/passwd"), owner)?,
+        None => 0,
+    };
+    let gid = match group {
+        Some(group) => resolve_id(&root.join("etc/group"), group)?,
+        None => 0,
+    };
+
+    let metadata = fs::symlink_metadata(path)?;
+    if (metadata.uid(), metadata.gid()) == (uid, gid) {
+        return Ok(());
+    }
+
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    #[allow(unsafe_code)]
+    if unsafe { lchown(c_path.as_ptr(), uid, gid) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Id of `name` in a `passwd` or `group` file, or `name` itself if it's
+/// numeric.
+fn resolve_id(database: &Path, name: &str) -> io::Result<u32> {
+    if let Ok(id) = name.parse() {
+        return Ok(id);
+    }
+
+    find_id(&fs::read_to_string(database)?, name).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("'{name}' is not found in {}", database.display()),
+        )
+    })
+}
+
+fn find_id(database: &str, name: &str) -> Option<u32> {
+    database.lines().find_map(|line| {
+        let mut fields = line.split(':');
+        if fields.next()? != name {
+            return None;
+        }
+        fields.nth(1)?.parse().ok()
+    })
+}
+
+/// Whether fewer blocks are allocated than the size needs.
+fn is_sparse(metadata: &fs::Metadata) -> bool {
+    metadata.blocks() * 512 < metadata.len()
+}
+
+/// Copies the data regions of `source`, seeking over the holes between them.
+/// Returns false if the filesystem can't report the holes.
+fn copy_data_regions(mut source: &File, destination: &mut File, len: u64) -> io::Result<bool> {
+    let seek = |offset: i64, whence: c_int| -> io::Result<Option<i64>> {
+        #[allow(unsafe_code)]
+        let position = unsafe { lseek(source.as_raw_fd(), offset, whence) };
+        if position >= 0 {
+            return Ok(Some(position));
+        }
+
+        let error = io::Error::last_os_error();
+        // No data after `offset`.
+        if error.raw_os_error() == Some(ENXIO) {
+            Ok(None)
+        } else {
+            Err(error)
+        }
+    };
+
+    let mut offset = 0;
+    loop {
+        let data_start = match seek(offset, SEEK_DATA) {
+            Ok(Some(position)) => position,
+            Ok(None) => break,
+            Err(_) if offset == 0 => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        // There is always an implicit hole at the end of the file.
+        let data_end = seek(data_start, SEEK_HOLE)?.unwrap_or(len as i64);
+
+        source.seek(SeekFrom::Start(data_start as u64))?;
+        destination.seek(SeekFrom::Start(data_start as u64))?;
+        let region_len = (data_end - data_start) as u64;
+        if io::copy(&mut source.take(region_len), destination)? != region_len {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "file got shorter while copying it",
+            ));
+        }
+
+        offset = data_end;
+    }
+
+    destination.set_len(len)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestDir;
+    use std::io::Write;
+
+    #[test]
+    fn test_copy_file() {
+        let dir = TestDir::new("copy-file");
+
+        let (from, to) = (dir.join("from"), dir.join("to"));
+        fs::write(&from, "#!/bin/sh\necho lpm\n").unwrap();
+        fs::set_permissions(&from, fs::Permissions::from_mode(0o755)).unwrap();
+        // Longer than the source, to make sure it gets truncated.
+        fs::write(&to, "x".repeat(64)).unwrap();
+
+        assert_eq!(copy_file(&from, &to).unwrap(), 19);
+        assert_eq!(fs::read(&to).unwrap(), fs::read(&from).unwrap());
+        assert_eq!(
+            fs::metadata(&to).unwrap().permissions().mode() & 0o777,
+            0o755
+        );
+    }
+
+    #[test]
+    fn test_copy_sparse_file() {
+        let dir = TestDir::new("copy-sparse");
+
+        let (from, to) = (dir.join("disk.img"), dir.join("copy.img"));
+        let mut file = File::create(&from).unwrap();
+        file.write_all(b"header").unwrap();
+        file.seek(SeekFrom::Start(4 << 20)).unwrap();
+        file.write_all(b"footer").unwrap();
+        file.set_len(8 << 20).unwrap();
+        drop(file);
+
+        assert_eq!(copy_file(&from, &to).unwrap(), 8 << 20);
+        assert_eq!(fs::read(&to).unwrap(), fs::read(&from).unwrap());
+
+        // Only meaningful where the filesystem supports holes to begin with.
+        if is_sparse(&fs::metadata(&from).unwrap()) {
+            assert!(is_sparse(&fs::metadata(&to).unwrap()));
+        }
+    }
+
+    #[test]
+    fn test_reflink_file() {
+        let dir = TestDir::new("copy-reflink");
+
+        let (from, to) = (dir.join("from"), dir.join("to"));
+        fs::write(&from, "lpm").unwrap();
+
+        // Depends on the filesystem of the temporary directory.
+        if reflink_file(&from, &to).unwrap() {
+            assert_eq!(fs::read(&to).unwrap(), b"lpm");
+        } else {
+            assert!(!to.exists());
+        }
+    }
+
+    #[test]
+    fn test_set_attributes() {
+        let dir = TestDir::new("copy-attributes");
+
+        set_attributes(&dir, Some(0o750), Some(1_700_000_000)).unwrap();
+        let metadata = fs::metadata(&dir).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o750);
+        assert_eq!(metadata.mtime(), 1_700_000_000);
+
+        // Nothing changes without values.
+        set_attributes(&dir, None, None).unwrap();
+        assert_eq!(fs::metadata(&dir).unwrap().mtime(), 1_700_000_000);
+    }
+
+    #[test]
+    fn test_find_id() {
+        let group = "root:x:0:\nwheel:x:10:alice\nhello:x:1001:\n";
+        assert_eq!(find_id(group, "hello"), Some(1001));
+        assert_eq!(find_id(group, "wheel"), Some(10));
+        assert_eq!(find_id(group, "hell"), None);
+        assert_eq!(resolve_id(Path::new("/nonexistent"), "42").unwrap(), 42);
+    }
+}
@@ -0,0 +1,386 @@
+//! rsync-style delta updates of large files.
+//!
+//! The installed file is split into `BLOCK_SIZE` blocks, each with a weak
+//! rolling checksum and an md5 digest(its `Signature`). A rolling window over
+//! the new version finds the blocks that it still has anywhere, the rest is
+//! literal data(`compute_delta`). `update_in_place` then writes only the
+//! literal data and the blocks that moved, the blocks at the same offset stay
+//! untouched on disk.
+
+use hash::md5;
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    os::unix::fs::FileExt,
+    path::Path,
+};
+
+pub const BLOCK_SIZE: usize = 64 * 1024;
+
+/// Smaller files are cheaper to copy than to diff.
+pub const MIN_FILE_SIZE: u64 = 8 * 1024 * 1024;
+
+/// Upper limit of the blocks that `update_in_place` keeps in memory, as they
+/// move to offsets that are written before they are read.
+const MAX_MOVED_BYTES: u64 = 16 * 1024 * 1024;
+
+/// Literal data is flushed in ops of at most this size.
+const MAX_LITERAL_LEN: usize = 1024 * 1024;
+
+/// Checksum of rsync, which can be rolled over a window one byte at a time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct RollingChecksum {
+    a: u16,
+    b: u16,
+    len: usize,
+}
+
+impl RollingChecksum {
+    fn new(data: &[u8]) -> Self {
+        let (mut a, mut b) = (0_u16, 0_u16);
+        for (i, byte) in data.iter().enumerate() {
+            a = a.wrapping_add(*byte as u16);
+            b = b.wrapping_add(((data.len() - i) as u16).wrapping_mul(*byte as u16));
+        }
+
+        Self {
+            a,
+            b,
+            len: data.len(),
+        }
+    }
+
+    /// Moves the window one byte forward, `out` leaves it and `into` joins it.
+    fn roll(&mut self, out: u8, into: u8) {
+        self.a = self.a.wrapping_sub(out as u16).wrapping_add(into as u16);
+        self.b = self
+            .b
+            .wrapping_sub((self.len as u16).wrapping_mul(out as u16))
+            .wrapping_add(self.a);
+    }
+
+    fn value(&self) -> u32 {
+        self.a as u32 | (self.b as u32) << 16
+    }
+}
+
+/// Checksums of the blocks of a file, the last one can be shorter.
+#[derive(Debug)]
+pub struct Signature {
+    block_size: usize,
+    blocks: Vec<(u32, [u8; 16], usize)>,
+    /// Blocks by their weak checksum.
+    lookup: HashMap<u32, Vec<usize>>,
+}
+
+impl Signature {
+    pub fn new(mut reader: impl Read, block_size: usize) -> io::Result<Self> {
+        let mut blocks = Vec::new();
+        let mut lookup: HashMap<u32, Vec<usize>> = HashMap::new();
+        let mut block = vec![0; block_size];
+
+        loop {
+            let len = read_full(&mut reader, &mut block)?;
+            if len == 0 {
+                break;
+            }
+
+            let weak = RollingChecksum::new(&block[..len]).value();
+            lookup.entry(weak).or_default().push(blocks.len());
+            blocks.push((weak, md5::digest(&block[..len]), len));
+
+            if len < block_size {
+                break;
+            }
+        }
+
+        Ok(Self {
+            block_size,
+            blocks,
+            lookup,
+        })
+    }
+
+    /// Block of the signature with the same content as `data`.
+    fn find(&self, weak: u32, data: &[u8]) -> Option<usize> {
+        let candidates = self.lookup.get(&weak)?;
+        let mut strong = None;
+        candidates.iter().copied().find(|&index| {
+            let (_, digest, len) = self.blocks[index];
+            len == data.len() && *strong.get_or_insert_with(|| md5::digest(data)) == digest
+        })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum DeltaOp {
+    /// Block of the old file, by index.
+    Copy(usize),
+    Data(Vec<u8>),
+}
+
+/// Ops that build the content of `reader` from the file of `signature`.
+pub fn compute_delta(signature: &Signature, mut reader: impl Read) -> io::Result<Vec<DeltaOp>> {
+    let block_size = signature.block_size;
+    let mut ops = Vec::new();
+    let mut buffer = Vec::new();
+    // Window is `buffer[position..position + block_size]`, literal data
+    // starts at 0.
+    let mut position = 0;
+    let mut checksum: Option<RollingChecksum> = None;
+    let mut is_eof = false;
+
+    loop {
+        if !is_eof && buffer.len() < position + block_size + 1 {
+            let (len, requested) = (buffer.len(), block_size.max(MAX_LITERAL_LEN / 4));
+            buffer.resize(len + requested, 0);
+            let read = read_full(&mut reader, &mut buffer[len..])?;
+            buffer.truncate(len + read);
+            is_eof = read < requested;
+        }
+
+        // The window can't be rolled any further, what's left can only end
+        // with the last block if that's a short one.
+        if buffer.len() < position + block_size + 1 && is_eof {
+            if buffer.len() >= position + block_size {
+                let window = &buffer[position..];
+                let weak = checksum.unwrap_or_else(|| RollingChecksum::new(window));
+                if let Some(index) = signature.find(weak.value(), window) {
+                    if position > 0 {
+                        ops.push(DeltaOp::Data(buffer[..position].to_vec()));
+                    }
+                    ops.push(DeltaOp::Copy(index));
+                    break;
+                }
+            }
+
+            push_tail(signature, &mut ops, buffer, position);
+            break;
+        }
+
+        let window_end = position + block_size;
+        let window = &buffer[position..window_end];
+        let weak = *checksum.get_or_insert_with(|| RollingChecksum::new(window));
+        if let Some(index) = signature.find(weak.value(), window) {
+            if position > 0 {
+                ops.push(DeltaOp::Data(buffer[..position].to_vec()));
+            }
+            ops.push(DeltaOp::Copy(index));
+
+            buffer.drain(..window_end);
+            position = 0;
+            checksum = None;
+            continue;
+        }
+
+        checksum
+            .as_mut()
+            .unwrap()
+            .roll(buffer[position], buffer[window_end]);
+        position += 1;
+
+        if position >= MAX_LITERAL_LEN {
+            ops.push(DeltaOp::Data(buffer.drain(..position).collect()));
+            position = 0;
+        }
+    }
+
+    Ok(ops)
+}
+
+/// Ops for the rest of `buffer` after `position`, which is shorter than a
+/// block and can only be the last block of the old file.
+fn push_tail(signature: &Signature, ops: &mut Vec<DeltaOp>, mut buffer: Vec<u8>, position: usize) {
+    let last_len = signature.blocks.last().map_or(0, |t| t.2);
+    if last_len < signature.block_size && last_len > 0 && buffer.len() >= position + last_len {
+        let tail_start = buffer.len() - last_len;
+        let tail = &buffer[tail_start..];
+        if let Some(index) = signature.find(RollingChecksum::new(tail).value(), tail) {
+            buffer.truncate(tail_start);
+            if !buffer.is_empty() {
+                ops.push(DeltaOp::Data(buffer));
+            }
+            ops.push(DeltaOp::Copy(index));
+            return;
+        }
+    }
+
+    if !buffer.is_empty() {
+        ops.push(DeltaOp::Data(buffer));
+    }
+}
+
+/// Rewrites `target` to the content of `source` by writing only the parts
+/// that changed. Returns the number of bytes written, or `None` without
+/// touching `target` if too many blocks moved for an update in place.
+pub fn update_in_place(target: &Path, source: &Path) -> io::Result<Option<u64>> {
+    let signature = Signature::new(File::open(target)?, BLOCK_SIZE)?;
+    let ops = compute_delta(&signature, File::open(source)?)?;
+
+    // Blocks that end up somewhere else are read before anything is written,
+    // as their old offset may get overwritten first.
+    let mut moved = HashSet::new();
+    let mut moved_bytes = 0;
+    let mut offset = 0;
+    for op in &ops {
+        match op {
+            DeltaOp::Copy(index) => {
+                let len = signature.blocks[*index].2;
+                if index * BLOCK_SIZE != offset {
+                    moved_bytes += len as u64;
+                    moved.insert(*index);
+                }
+                offset += len;
+            }
+            DeltaOp::Data(data) => offset += data.len(),
+        }
+    }
+
+    if moved_bytes > MAX_MOVED_BYTES {
+        return Ok(None);
+    }
+
+    let mut file = OpenOptions::new().read(true).write(true).open(target)?;
+    let mut moved_blocks = HashMap::with_capacity(moved.len());
+    for index in moved {
+        let mut block = vec![0; signature.blocks[index].2];
+        file.read_exact_at(&mut block, (index * BLOCK_SIZE) as u64)?;
+        moved_blocks.insert(index, block);
+    }
+
+    let (mut offset, mut written) = (0, 0);
+    for op in &ops {
+        let data = match op {
+            DeltaOp::Copy(index) if index * BLOCK_SIZE == offset => {
+                offset += signature.blocks[*index].2;
+                continue;
+            }
+            DeltaOp::Copy(index) => &moved_blocks[index],
+            DeltaOp::Data(data) => data,
+        };
+
+        file.seek(SeekFrom::Start(offset as u64))?;
+        file.write_all(data)?;
+        offset += data.len();
+        written += data.len() as u64;
+    }
+
+    file.set_len(offset as u64)?;
+    Ok(Some(written))
+}
+
+/// Like `read_exact`, but returns how much it read before the end of file.
+fn read_full(reader: &mut impl Read, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut len = 0;
+    while len < buffer.len() {
+        match reader.read(&mut buffer[len..]) {
+            Ok(0) => break,
+            Ok(read) => len += read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestDir;
+    use std::fs;
+
+    /// Rebuilds the content from the ops and the old content.
+    fn apply(old: &[u8], block_size: usize, ops: &[DeltaOp]) -> Vec<u8> {
+        let mut content = Vec::new();
+        for op in ops {
+            match op {
+                DeltaOp::Copy(index) => {
+                    let start = index * block_size;
+                    content.extend(&old[start..(start + block_size).min(old.len())]);
+                }
+                DeltaOp::Data(data) => content.extend(data),
+            }
+        }
+
+        content
+    }
+
+    fn pseudo_random(len: usize, seed: u32) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (state >> 16) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_rolling_checksum() {
+        let data = pseudo_random(100, 1);
+        let mut checksum = RollingChecksum::new(&data[..16]);
+        for i in 0..84 {
+            checksum.roll(data[i], data[i + 16]);
+            assert_eq!(checksum, RollingChecksum::new(&data[i + 1..i + 17]));
+        }
+    }
+
+    #[test]
+    fn test_compute_delta() {
+        let old = pseudo_random(1000, 2);
+        let signature = Signature::new(&old[..], 64).unwrap();
+
+        assert_eq!(
+            compute_delta(&signature, &old[..]).unwrap(),
+            (0..16).map(DeltaOp::Copy).collect::<Vec<_>>()
+        );
+
+        // A few bytes inserted at the start shift every block.
+        let mut new = b"inserted".to_vec();
+        new.extend(&old[..500]);
+        new.extend(pseudo_random(30, 3));
+        new.extend(&old[520..]);
+
+        let ops = compute_delta(&signature, &new[..]).unwrap();
+        assert_eq!(apply(&old, 64, &ops), new);
+        assert_eq!(ops.first(), Some(&DeltaOp::Data(b"inserted".to_vec())));
+        assert_eq!(ops.last(), Some(&DeltaOp::Copy(15)));
+        assert_eq!(
+            ops.iter().filter(|t| matches!(t, DeltaOp::Copy(_))).count(),
+            14
+        );
+
+        assert_eq!(compute_delta(&signature, &[][..]).unwrap(), []);
+    }
+
+    #[test]
+    fn test_update_in_place() {
+        let dir = TestDir::new("delta");
+        let (target, source) = (dir.join("target"), dir.join("source"));
+
+        let old = pseudo_random(BLOCK_SIZE * 4 + 100, 4);
+        let mut new = old.clone();
+        new[BLOCK_SIZE + 10] ^= 0xff;
+        new.truncate(BLOCK_SIZE * 3 + 50);
+        fs::write(&target, &old).unwrap();
+        fs::write(&source, &new).unwrap();
+
+        // Only the changed block and the new tail are written.
+        let written = update_in_place(&target, &source).unwrap().unwrap();
+        assert_eq!(written, BLOCK_SIZE as u64 + 50);
+        assert_eq!(fs::read(&target).unwrap(), new);
+
+        // Moved blocks are read before they are overwritten.
+        let mut moved = old[BLOCK_SIZE..BLOCK_SIZE * 2].to_vec();
+        moved.extend(&old[..BLOCK_SIZE]);
+        fs::write(&target, &old).unwrap();
+        fs::write(&source, &moved).unwrap();
+        assert_eq!(
+            update_in_place(&target, &source).unwrap(),
+            Some(BLOCK_SIZE as u64 * 2)
+        );
+        assert_eq!(fs::read(&target).unwrap(), moved);
+    }
+}
@@ -0,0 +1,53 @@
+//! Progress reporting of the package operations.
+
+use crate::pkg::ScriptPhase;
+
+use std::path::Path;
+
+/// Receives the progress of downloads and installations. The CLI logs them
+/// through `LogEvents`, GUIs and daemons can set their own sink on the `Ctx`.
+///
+/// Every method does nothing by default. They can be called from the worker
+/// threads of an operation, and for several packages at the same time.
+pub trait EventSink: Send + Sync {
+    fn download_started(&self, _file_name: &str) {}
+    /// `total` is `None` if the server didn't send the size.
+    fn download_progress(&self, _file_name: &str, _downloaded: u64, _total: Option<u64>) {}
+    fn download_finished(&self, _file_name: &str) {}
+    /// Progress in bytes of the `.lod` archive at `pkg_path`.
+    fn extraction_progress(&self, _pkg_path: &Path, _extracted: u64, _total: u64) {}
+    fn script_started(&self, _pkg_name: &str, _phase: ScriptPhase) {}
+    /// Script of a custom phase that the package declares.
+    fn custom_script_started(&self, _pkg_name: &str, _phase: &str) {}
+    fn file_installed(&self, _pkg_name: &str, _path: &Path) {}
+}
+
+/// Ignores every event.
+pub struct NoEvents;
+
+impl EventSink for NoEvents {}
+
+/// Sink of the CLI, which logs the events.
+pub struct LogEvents;
+
+impl EventSink for LogEvents {
+    fn download_started(&self, file_name: &str) {
+        logger::info!("Downloading {file_name}..");
+    }
+
+    fn download_finished(&self, file_name: &str) {
+        logger::debug!("Download of {file_name} was successful");
+    }
+
+    fn script_started(&self, pkg_name: &str, phase: ScriptPhase) {
+        logger::info!("Running {} script of {pkg_name}..", phase.as_str());
+    }
+
+    fn custom_script_started(&self, pkg_name: &str, phase: &str) {
+        logger::info!("Running {phase} script of {pkg_name}..");
+    }
+
+    fn file_installed(&self, pkg_name: &str, path: &Path) {
+        logger::debug!("Installed {} of {pkg_name}", path.display());
+    }
+}
@@ -0,0 +1,74 @@
+/// Matches `input` against a shell-style glob `pattern`.
+///
+/// Supports `*` (any sequence of characters, including none) and `?`
+/// (exactly one character). Every other character is matched literally.
+pub fn matches(pattern: &str, input: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let input: Vec<char> = input.chars().collect();
+
+    let (mut p, mut i) = (0, 0);
+    // Position of the last seen '*' in pattern and the input position it
+    // was matched against, used for backtracking.
+    let mut star: Option<(usize, usize)> = None;
+
+    while i < input.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == input[i]) {
+            p += 1;
+            i += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, i));
+            p += 1;
+        } else if let Some((star_p, star_i)) = star {
+            p = star_p + 1;
+            i = star_i + 1;
+            star = Some((star_p, star_i + 1));
+        } else {
+            return false;
+        }
+    }
+
+    while p < pattern.len() && pattern[p] == '*' {
+        p += 1;
+    }
+
+    p == pattern.len()
+}
+
+/// Returns true if `input` matches at least one of the `patterns`.
+pub fn matches_any(patterns: &[String], input: &str) -> bool {
+    patterns.iter().any(|pattern| matches(pattern, input))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_matches() {
+        assert!(matches("htop", "htop"));
+        assert!(!matches("htop", "htop2"));
+
+        assert!(matches("*", ""));
+        assert!(matches("*", "anything"));
+        assert!(matches("lib*", "libc"));
+        assert!(matches("*-devel", "gc-devel"));
+        assert!(!matches("*-devel", "gc-devel-docs"));
+        assert!(matches("*devel*", "gc-devel-docs"));
+        assert!(matches("usr/share/doc/*", "usr/share/doc/htop/README"));
+
+        assert!(matches("lz?p", "lzip"));
+        assert!(!matches("lz?p", "lzp"));
+        assert!(matches("a*b*c", "aXXbYYc"));
+        assert!(!matches("a*b*c", "aXXbYY"));
+    }
+
+    #[test]
+    fn test_glob_matches_any() {
+        let patterns = vec![String::from("lib*"), String::from("*-devel")];
+
+        assert!(matches_any(&patterns, "libc"));
+        assert!(matches_any(&patterns, "gc-devel"));
+        assert!(!matches_any(&patterns, "htop"));
+        assert!(!matches_any(&[], "htop"));
+    }
+}
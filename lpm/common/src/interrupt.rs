@@ -0,0 +1,88 @@
+//! SIGINT and SIGTERM handling during transactions.
+//!
+//! The handler only records the signal. The long running steps(downloads,
+//! extraction and file copies) call `check` between files, which fails with
+//! `io::ErrorKind::Interrupted`, so an interruption takes the same path as any
+//! other error: the database transaction is rolled back and the failure is
+//! reported. A second signal terminates lpm right away.
+
+use std::{
+    io,
+    os::raw::c_int,
+    sync::{
+        atomic::{AtomicI32, Ordering},
+        Once,
+    },
+};
+
+const SIGINT: c_int = 2;
+const SIGTERM: c_int = 15;
+const SIG_DFL: usize = 0;
+
+static RECEIVED_SIGNAL: AtomicI32 = AtomicI32::new(0);
+
+extern "C" {
+    fn signal(signum: c_int, handler: usize) -> usize;
+    fn raise(sig: c_int) -> c_int;
+}
+
+extern "C" fn handle_signal(signum: c_int) {
+    if RECEIVED_SIGNAL.swap(signum, Ordering::SeqCst) != 0 {
+        #[allow(unsafe_code)]
+        unsafe {
+            signal(signum, SIG_DFL);
+            raise(signum);
+        }
+    }
+}
+
+/// Installs the handlers, once per process.
+pub fn install_handlers() {
+    static INSTALLED: Once = Once::new();
+
+    INSTALLED.call_once(|| {
+        for signum in [SIGINT, SIGTERM] {
+            #[allow(unsafe_code)]
+            unsafe {
+                signal(signum, handle_signal as usize);
+            }
+        }
+    });
+}
+
+pub fn is_interrupted() -> bool {
+    RECEIVED_SIGNAL.load(Ordering::SeqCst) != 0
+}
+
+/// Fails if a signal was received since the handlers are installed.
+pub fn check() -> io::Result<()> {
+    let name = match RECEIVED_SIGNAL.load(Ordering::SeqCst) {
+        0 => return Ok(()),
+        SIGINT => "SIGINT",
+        SIGTERM => "SIGTERM",
+        _ => "a signal",
+    };
+
+    Err(io::Error::new(
+        io::ErrorKind::Interrupted,
+        format!("Interrupted by {name}."),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        assert!(check().is_ok());
+
+        RECEIVED_SIGNAL.store(SIGTERM, Ordering::SeqCst);
+        let error = check().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::Interrupted);
+        assert!(error.to_string().contains("SIGTERM"));
+        assert!(is_interrupted());
+
+        RECEIVED_SIGNAL.store(0, Ordering::SeqCst);
+    }
+}
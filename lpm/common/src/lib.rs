@@ -1,24 +1,47 @@
+pub mod arch;
+pub mod compression;
+pub mod config;
+pub mod copy;
+pub mod delta;
+pub mod event;
+pub mod glob;
+pub mod interrupt;
 pub mod meta;
 pub mod pkg;
+pub mod pkg_path;
+mod privsep;
+pub mod size;
+pub mod soname;
+pub mod stats;
 pub mod system;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod testing;
 pub mod version;
 
 // re-exports
 pub use meta::Files;
+pub use rekuest::TlsConfig;
 
-use rekuest::Rekuest;
-use std::{fs, io, path::Path};
+use event::EventSink;
+use rekuest::{HttpResponse, Proxy, Rekuest};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 pub trait ParserTasks {
-    fn deserialize(path: &str) -> Self;
+    fn deserialize(path: &Path) -> Self;
 }
 
 // For non-binary packages
 pub const NO_ARCH: &str = "no-arch";
 
-// Supported CPU architectures
+// Supported CPU architectures, see `arch` module for their aliases
 #[cfg(target_arch = "x86_64")]
 pub const SYSTEM_ARCH: &str = "amd64";
+#[cfg(target_arch = "aarch64")]
+pub const SYSTEM_ARCH: &str = "arm64";
 #[cfg(target_arch = "arm")]
 pub const SYSTEM_ARCH: &str = "arm";
 
@@ -54,26 +77,300 @@ macro_rules! some_or_error {
     }
 }
 
-pub fn download_file(url: &str, output_path: &Path) -> std::io::Result<()> {
+/// Returns the filesystem path of repository addresses like `file:///srv/repo`
+/// or `/srv/repo`, `None` for remote ones.
+pub fn local_address_path(address: &str) -> Option<&Path> {
+    if let Some(path) = address.strip_prefix("file://") {
+        return Some(Path::new(path));
+    }
+
+    if address.starts_with('/') {
+        return Some(Path::new(address));
+    }
+
+    None
+}
+
+/// Network settings applied to every request made for a repository.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DownloadOptions {
+    /// Rate limit in bytes per second.
+    pub rate_limit: Option<u64>,
+    /// Proxy url, `http://` or `socks5://`.
+    pub proxy: Option<String>,
+    pub retries: u32,
+    pub retry_backoff: Duration,
+    pub timeout: Option<Duration>,
+    /// Unprivileged user that sends the requests when lpm runs as root.
+    pub download_user: Option<String>,
+    /// Certificates for `https://` addresses, the system ones are used if not
+    /// set. Loaded up front, since the `download_user` usually can't read the
+    /// client key.
+    pub tls: Option<TlsConfig>,
+}
+
+impl DownloadOptions {
+    /// Global options from the config file, for requests not tied to a repository.
+    pub fn from_config(config: &config::Config) -> Self {
+        Self {
+            rate_limit: config.limit_rate,
+            proxy: config.proxy.clone(),
+            retries: config.retries,
+            retry_backoff: config.retry_backoff,
+            timeout: config.timeout,
+            download_user: config.download_user.clone(),
+            tls: None,
+        }
+    }
+}
+
+fn new_request(url: &str, options: &DownloadOptions) -> io::Result<Rekuest> {
+    let mut rekuest = Rekuest::new(url)?;
+    if let Some(bytes_per_second) = options.rate_limit {
+        rekuest.set_rate_limit(bytes_per_second);
+    }
+    if let Some(proxy) = &options.proxy {
+        rekuest.set_proxy(Proxy::parse(proxy)?);
+    }
+    if let Some(timeout) = options.timeout {
+        rekuest.set_timeout(timeout);
+    }
+    if let Some(tls) = &options.tls {
+        rekuest.set_tls_config(tls.clone());
+    }
+
+    Ok(rekuest)
+}
+
+/// Requests whose responses are returned rather than written into a file.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Request {
+    /// GET request, see `fetch`.
+    Get(String),
+    /// GET request of the first bytes only, see `fetch_range`.
+    Range(String, u64),
+    /// POST request with a JSON body, see `post_json`.
+    PostJson(String, String),
+}
+
+/// Sends a GET request to `url`, retrying failed attempts according to `options`.
+///
+/// Server errors(5xx) are retried too; the last response is returned as is.
+pub fn fetch(url: &str, options: &DownloadOptions) -> io::Result<HttpResponse> {
+    send_request(Request::Get(url.to_owned()), options)
+}
+
+/// Sends `body` as JSON to `url` with a POST request. It is not retried, since
+/// the receiver may have handled a request that failed on the way back.
+pub fn post_json(url: &str, body: &str, options: &DownloadOptions) -> io::Result<HttpResponse> {
+    send_request(Request::PostJson(url.to_owned(), body.to_owned()), options)
+}
+
+/// Fetches the first `len` bytes of `url` without retrying, for probing the
+/// speed of a server. Servers that don't support ranges send all of it.
+pub fn fetch_range(url: &str, len: u64, options: &DownloadOptions) -> io::Result<HttpResponse> {
+    send_request(Request::Range(url.to_owned(), len), options)
+}
+
+fn send_request(request: Request, options: &DownloadOptions) -> io::Result<HttpResponse> {
+    send_requests(&[(request, options.clone())])?.pop().unwrap()
+}
+
+/// Sends each `(request, options)` of `requests` at the same time, and returns
+/// their responses in the same order. Like the downloads, they are sent by the
+/// `download_user` of the options when lpm runs as root.
+pub fn send_requests(
+    requests: &[(Request, DownloadOptions)],
+) -> io::Result<Vec<io::Result<HttpResponse>>> {
+    match privsep::request_user(requests.iter().map(|(_, options)| options)) {
+        Some(user) => privsep::send_requests_as(user, requests),
+        None => Ok(send_requests_in_process(requests)),
+    }
+}
+
+fn send_requests_in_process(
+    requests: &[(Request, DownloadOptions)],
+) -> Vec<io::Result<HttpResponse>> {
+    let futures = requests
+        .iter()
+        .map(|(request, options)| send_request_async(request, options))
+        .collect();
+
+    rekuest::block_on_all(futures)
+}
+
+async fn send_request_async(
+    request: &Request,
+    options: &DownloadOptions,
+) -> io::Result<HttpResponse> {
+    match request {
+        Request::Get(url) => fetch_with_progress(url, options, &|_, _| Ok(())).await,
+        Request::Range(url, len) => {
+            let mut request = new_request(url, options)?;
+            request.add_header("Range", &format!("bytes=0-{}", len.saturating_sub(1)));
+            request.get_async().await
+        }
+        Request::PostJson(url, body) => {
+            new_request(url, options)?.post("application/json", body.as_bytes())
+        }
+    }
+}
+
+/// `on_progress` starts over on each retry.
+async fn fetch_with_progress(
+    url: &str,
+    options: &DownloadOptions,
+    on_progress: &dyn Fn(u64, Option<u64>) -> io::Result<()>,
+) -> io::Result<HttpResponse> {
+    let mut attempt = 0;
+    loop {
+        let request = new_request(url, options)?;
+        let failure = match request.get_async_with_progress(on_progress).await {
+            Ok(response) if response.status_code < 500 || attempt >= options.retries => {
+                return Ok(response)
+            }
+            Ok(response) => format!("server responded with {}", response.status_code),
+            Err(e) if !is_retryable(&e) || attempt >= options.retries => return Err(e),
+            Err(e) => e.to_string(),
+        };
+
+        let delay = retry_delay(options.retry_backoff, attempt);
+        attempt += 1;
+        logger::verbose!(
+            "Request to '{url}' failed ({failure}), retrying in {delay:?} (attempt {attempt}/{}).",
+            options.retries
+        );
+        rekuest::sleep(delay).await;
+    }
+}
+
+/// Errors caused by the request itself, which would fail the same way again.
+fn is_retryable(error: &io::Error) -> bool {
+    !matches!(
+        error.kind(),
+        io::ErrorKind::InvalidInput | io::ErrorKind::Unsupported
+    )
+}
+
+/// Exponential backoff, `base` doubled for each previous attempt.
+fn retry_delay(base: Duration, attempt: u32) -> Duration {
+    base.saturating_mul(2_u32.saturating_pow(attempt))
+}
+
+/// Downloads the file from the first of `urls`(mirrors of the same file)
+/// that works.
+pub fn download_file(
+    urls: &[String],
+    output_path: &Path,
+    options: &DownloadOptions,
+    events: &dyn EventSink,
+) -> std::io::Result<()> {
+    if options.download_user.is_some() {
+        return download_files(
+            &[(urls.to_vec(), output_path.to_owned(), options.clone())],
+            events,
+        );
+    }
+
+    rekuest::block_on(download_from_mirrors_async(
+        urls,
+        output_path,
+        options,
+        events,
+    ))
+}
+
+/// Downloads each `(urls, output path, options)` of `downloads` at the same
+/// time, returns the first error once all of them are finished.
+pub fn download_files(
+    downloads: &[(Vec<String>, PathBuf, DownloadOptions)],
+    events: &dyn EventSink,
+) -> io::Result<()> {
+    match privsep::request_user(downloads.iter().map(|(_, _, options)| options)) {
+        Some(user) => privsep::download_files_as(user, downloads, events),
+        None => download_files_in_process(downloads, events),
+    }
+}
+
+fn download_files_in_process(
+    downloads: &[(Vec<String>, PathBuf, DownloadOptions)],
+    events: &dyn EventSink,
+) -> io::Result<()> {
+    let futures = downloads
+        .iter()
+        .map(|(urls, output_path, options)| {
+            download_from_mirrors_async(urls, output_path, options, events)
+        })
+        .collect();
+
+    rekuest::block_on_all(futures).into_iter().collect()
+}
+
+async fn download_from_mirrors_async(
+    urls: &[String],
+    output_path: &Path,
+    options: &DownloadOptions,
+    events: &dyn EventSink,
+) -> io::Result<()> {
+    let mut last_error = None;
+    for url in urls {
+        match download_file_async(url, output_path, options, events).await {
+            Ok(()) => return Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => return Err(e),
+            Err(e) => {
+                logger::warning!("Download from '{url}' failed ({e}).");
+                last_error = Some(e);
+            }
+        }
+    }
+
+    Err(last_error
+        .unwrap_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no url to download from")))
+}
+
+async fn download_file_async(
+    url: &str,
+    output_path: &Path,
+    options: &DownloadOptions,
+    events: &dyn EventSink,
+) -> io::Result<()> {
     let pkg_filename = output_path.file_name().unwrap();
-    // TODO
-    // We should check if user wants to force re-downloading.
-    if output_path.exists() {
+    let file_name = pkg_filename.to_string_lossy();
+    if skip_existing(output_path) {
+        return Ok(());
+    }
+
+    if let Some(source_path) = local_address_path(url) {
         logger::info!(
-            "Skipping package download for {:?}; already exists: '{}'",
+            "Copying {:?} into '{}'",
             pkg_filename,
             output_path.display()
         );
 
+        fs::create_dir_all(some_or_error!(
+            output_path.parent(),
+            "Failed creating parent directories of '{}'",
+            output_path.display()
+        ))?;
+        fs::copy(source_path, output_path)?;
+
         return Ok(());
     }
 
-    logger::info!(
-        "Downloading {:?} into '{}'",
-        pkg_filename,
-        output_path.display()
-    );
-    let response = Rekuest::new(url)?.get()?;
+    events.download_started(&file_name);
+    // Checked after each read, so a signal doesn't wait for the whole body.
+    let on_progress = |downloaded, total| {
+        events.download_progress(&file_name, downloaded, total);
+        interrupt::check()
+    };
+    let response = fetch_with_progress(url, options, &on_progress).await?;
+    interrupt::check()?;
+    if response.status_code >= 400 {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("server responded with {}", response.status_code),
+        ));
+    }
 
     fs::create_dir_all(some_or_error!(
         output_path.parent(),
@@ -84,12 +381,31 @@ pub fn download_file(url: &str, output_path: &Path) -> std::io::Result<()> {
     let mut file = fs::File::create(output_path)?;
     io::Write::write_all(&mut file, &response.body)?;
     io::Write::flush(&mut file)?;
+    stats::record_download(response.body.len() as u64);
 
-    logger::debug!("Download of {:?} was successful", pkg_filename);
+    events.download_finished(&file_name);
 
     Ok(())
 }
 
+/// Whether `output_path` was already downloaded.
+fn skip_existing(output_path: &Path) -> bool {
+    // TODO
+    // We should check if user wants to force re-downloading.
+    if !output_path.exists() {
+        return false;
+    }
+
+    logger::info!(
+        "Skipping package download for {:?}; already exists: '{}'",
+        output_path.file_name().unwrap_or_default(),
+        output_path.display()
+    );
+    stats::record_cache_hit();
+
+    true
+}
+
 #[macro_export]
 macro_rules! ctx_confirmation_check {
     ($ctx: expr) => {
@@ -98,3 +414,27 @@ macro_rules! ctx_confirmation_check {
         }
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_delay() {
+        let base = Duration::from_millis(500);
+        assert_eq!(retry_delay(base, 0), Duration::from_millis(500));
+        assert_eq!(retry_delay(base, 1), Duration::from_secs(1));
+        assert_eq!(retry_delay(base, 3), Duration::from_secs(4));
+        assert_eq!(retry_delay(base, 64), base * u32::MAX);
+    }
+
+    #[test]
+    fn test_is_retryable() {
+        assert!(is_retryable(&io::Error::from(io::ErrorKind::TimedOut)));
+        assert!(is_retryable(&io::Error::from(
+            io::ErrorKind::ConnectionRefused
+        )));
+        assert!(!is_retryable(&io::Error::from(io::ErrorKind::Unsupported)));
+        assert!(!is_retryable(&io::Error::from(io::ErrorKind::InvalidInput)));
+    }
+}
@@ -1,40 +1,188 @@
+use crate::compression::Compression;
+use crate::pkg_path::PkgPath;
+use crate::size::disk_usage;
 use crate::version::VersionStruct;
-use crate::{de_required_field, ParserTasks};
+use crate::{de_required_field, glob, ParserTasks};
 
-use json::{Deserialize, JsonValue};
-use std::fs;
+use json::{to_json_object, Deserialize, JsonValue, Serialize};
+use std::{fs, io, path::Path};
+
+/// Latest version of the `meta.json` format understood by this lpm.
+///
+/// Files without a `schema_version` predate it and are read as version 1,
+/// which ignores unknown fields. Starting with version 2 unknown fields are
+/// rejected, and versions newer than this one can't be read at all.
+pub const META_SCHEMA_VERSION: u32 = 2;
+
+const META_FIELDS: &[&str] = &[
+    "schema_version",
+    "name",
+    "arch",
+    "slot",
+    "essential",
+    "kind",
+    "maintainer",
+    "signing_key",
+    "installed_size",
+    "version",
+    "dependencies",
+    "suggestions",
+    "provides",
+    "conflicts",
+    "needs_sonames",
+    "provides_sonames",
+    "alternatives",
+    "config_files",
+    "directories",
+    "scripts",
+    "compression",
+];
 
 #[derive(Debug, Clone)]
 pub struct Meta {
     pub name: String,
     pub arch: String, // TODO: use enums
+    /// Slot-aware packages can have one installation per slot(e.g. `3.11` and
+    /// `3.12` of `python3`), as long as their files don't overlap.
+    pub slot: Option<String>,
+    /// Essential packages can't be deleted without `--force-essential`.
+    pub essential: bool,
+    /// `None` for the packages that predate kinds or don't set one.
+    pub kind: Option<PackageKind>,
+    /// Who builds the package, e.g. `Jane Doe <jane@example.org>`.
+    pub maintainer: Option<String>,
+    /// Id of the key that the maintainer signs the package with, as the
+    /// package declares it. Installed packages record the key that verified
+    /// them instead, see `PkgOrigin::signing_key`.
+    pub signing_key: Option<String>,
     pub installed_size: i64,
     pub version: VersionStruct,
     pub dependencies: Vec<DependencyStruct>,
     pub suggestions: Vec<SuggestionStruct>,
+    /// Virtual packages or capabilities that the package satisfies.
+    pub provides: Vec<SuggestionStruct>,
+    /// Packages that can't be installed along with this one.
+    pub conflicts: Vec<SuggestionStruct>,
+    /// Shared libraries needed by the package, generated at build time.
+    pub needs_sonames: Vec<String>,
+    pub provides_sonames: Vec<String>,
+    pub alternatives: Vec<AlternativeStruct>,
+    /// Files that are kept on deletion unless the package is purged.
+    pub config_files: Vec<String>,
+    /// Directories to create with a given mode and modification time, the
+    /// others are created with the defaults.
+    pub directories: Vec<DirectoryStruct>,
+    /// Checksums of the scripts in `scripts/`, checked right before they run.
+    /// Required from schema version 2, `None` for the packages that predate
+    /// them.
+    pub scripts: Option<Vec<ScriptStruct>>,
+    /// Compression the archive was built with, e.g. `lz4:12`. `None` for
+    /// the packages that predate it, which are LZ4 compressed.
+    pub compression: Option<Compression>,
 }
 
 impl Meta {
     pub fn get_group_id(&self) -> String {
         format!("{}@{}", self.name, self.version.readable_format)
     }
+
+    /// Like `deserialize`, but fails instead of panicking.
+    pub fn read(path: &Path) -> Result<Self, String> {
+        let data_as_str = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let json = json::Json::new(&data_as_str).parse()?;
+        Self::from_json_object(&json)
+    }
 }
 
 impl json::Deserialize for Meta {
     type Error = String;
 
     fn from_json_object(json: &json::JsonValue) -> Result<Self, Self::Error> {
-        let version = VersionStruct::from_json_object(&json["version"])?;
-        let dependencies = DependencyStruct::from_json_array(&json["dependencies"])?;
-        let suggestions = SuggestionStruct::from_json_array(&json["suggestions"])?;
+        let JsonValue::Object(fields) = json else {
+            return Err(String::from("Meta data must be a JSON object."));
+        };
+
+        let schema_version = match &json["schema_version"] {
+            JsonValue::Null => 1,
+            value => value
+                .as_u32()
+                .filter(|t| *t > 0)
+                .ok_or("Field 'schema_version' must be a positive integer.")?,
+        };
+        if schema_version > META_SCHEMA_VERSION {
+            return Err(format!(
+                "Meta schema version {schema_version} is not supported, this lpm reads up to version {META_SCHEMA_VERSION}. Update lpm to install this package."
+            ));
+        }
+
+        let unknown_fields: Vec<&str> = fields
+            .keys()
+            .map(String::as_str)
+            .filter(|field| !META_FIELDS.contains(field))
+            .collect();
+        if !unknown_fields.is_empty() {
+            if schema_version >= 2 {
+                return Err(format!(
+                    "Unknown field(s) in meta data: '{}'.",
+                    unknown_fields.join("', '")
+                ));
+            }
+
+            logger::verbose!(
+                "Ignoring unknown meta field(s): {}",
+                unknown_fields.join(", ")
+            );
+        }
+
+        let version = match &json["version"] {
+            JsonValue::Null => return Err(missing_field("version")),
+            value => VersionStruct::from_json_object(value).map_err(|e| format!("version: {e}"))?,
+        };
 
         Ok(Self {
-            name: de_required_field!(json["name"].to_string(), "name"),
-            arch: de_required_field!(json["arch"].to_string(), "arch"),
-            installed_size: de_required_field!(json["installed_size"].as_i64(), "installed_size"),
+            name: de_field(json, "name", "a string", JsonValue::to_string)?,
+            arch: de_field(json, "arch", "a string", JsonValue::to_string)?,
+            slot: json["slot"].to_string().filter(|t| !t.is_empty()),
+            essential: match &json["essential"] {
+                JsonValue::Null => false,
+                _ => de_field(json, "essential", "a boolean", JsonValue::as_bool)?,
+            },
+            kind: match &json["kind"] {
+                JsonValue::Null => None,
+                value => {
+                    let kinds = PackageKind::ALL.map(|t| t.as_str());
+                    let kind = value.to_string().as_deref().and_then(PackageKind::parse);
+                    Some(kind.ok_or_else(|| {
+                        format!("Field 'kind' must be one of '{}'.", kinds.join("', '"))
+                    })?)
+                }
+            },
+            maintainer: json["maintainer"].to_string().filter(|t| !t.is_empty()),
+            signing_key: json["signing_key"].to_string().filter(|t| !t.is_empty()),
+            installed_size: de_field(json, "installed_size", "an integer", JsonValue::as_i64)?,
             version,
-            dependencies,
-            suggestions,
+            dependencies: de_array(json, "dependencies", true)?,
+            suggestions: de_array(json, "suggestions", true)?,
+            provides: de_array(json, "provides", false)?,
+            conflicts: de_array(json, "conflicts", false)?,
+            needs_sonames: de_string_array(&json["needs_sonames"], "needs_sonames")?,
+            provides_sonames: de_string_array(&json["provides_sonames"], "provides_sonames")?,
+            alternatives: de_array(json, "alternatives", false)?,
+            config_files: de_string_array(&json["config_files"], "config_files")?,
+            directories: de_array(json, "directories", false)?,
+            scripts: match &json["scripts"] {
+                JsonValue::Null if schema_version < 2 => None,
+                _ => Some(de_array(json, "scripts", true)?),
+            },
+            compression: match &json["compression"] {
+                JsonValue::Null => None,
+                value => {
+                    let spec = value
+                        .to_string()
+                        .ok_or("Field 'compression' must be a string.")?;
+                    Some(Compression::parse(&spec).map_err(|e| format!("compression: {e}"))?)
+                }
+            },
         })
     }
 
@@ -54,9 +202,261 @@ impl json::Deserialize for Meta {
     }
 }
 
+fn missing_field(field: &str) -> String {
+    format!("Field '{field}' is required and must be provided.")
+}
+
+/// Required `field` of `json`, `expected` describes its type in the errors.
+fn de_field<T>(
+    json: &JsonValue,
+    field: &str,
+    expected: &str,
+    get: impl Fn(&JsonValue) -> Option<T>,
+) -> Result<T, String> {
+    match &json[field] {
+        JsonValue::Null => Err(missing_field(field)),
+        value => get(value).ok_or_else(|| format!("Field '{field}' must be {expected}.")),
+    }
+}
+
+/// Array of objects, empty if the field is missing and not `required`.
+/// Errors point to the invalid item, e.g. `dependencies[1]`.
+fn de_array<T: Deserialize<Error = String>>(
+    json: &JsonValue,
+    field: &str,
+    required: bool,
+) -> Result<Vec<T>, String> {
+    match &json[field] {
+        JsonValue::Null if required => Err(missing_field(field)),
+        JsonValue::Null => Ok(Vec::new()),
+        JsonValue::Array(array) => array
+            .iter()
+            .enumerate()
+            .map(|(i, item)| {
+                if !item.is_object() {
+                    return Err(format!("{field}[{i}]: Must be an object."));
+                }
+                T::from_json_object(item).map_err(|e| format!("{field}[{i}]: {e}"))
+            })
+            .collect(),
+        _ => Err(format!("Field '{field}' must be an array.")),
+    }
+}
+
+/// Optional array of strings, empty if the field is missing.
+fn de_string_array(json: &JsonValue, field: &str) -> Result<Vec<String>, String> {
+    let error = || format!("Field '{field}' must be an array of strings.");
+    match json {
+        JsonValue::Null => Ok(Vec::new()),
+        JsonValue::Array(array) => array
+            .iter()
+            .map(|item| item.to_string().ok_or_else(error))
+            .collect(),
+        _ => Err(error()),
+    }
+}
+
+/// Documentation directories, relative to the root.
+pub const DOC_DIRS: [&str; 4] = [
+    "usr/share/doc",
+    "usr/share/man",
+    "usr/share/info",
+    "usr/share/gtk-doc",
+];
+
+/// Directories of executables, relative to the root.
+const BINARY_DIRS: [&str; 6] = [
+    "bin",
+    "sbin",
+    "usr/bin",
+    "usr/sbin",
+    "usr/local/bin",
+    "usr/local/sbin",
+];
+
+/// Directories of license texts, relative to the root.
+const LICENSE_DIRS: [&str; 1] = ["usr/share/licenses"];
+
+/// Name prefixes of license texts elsewhere, e.g. `usr/share/doc/htop/COPYING`.
+const LICENSE_FILE_NAMES: [&str; 4] = ["LICENSE", "LICENCE", "COPYING", "COPYRIGHT"];
+
+/// What a package is, recorded in its meta and used to filter the package
+/// lists.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PackageKind {
+    Application,
+    Library,
+    Kernel,
+    /// Kernel modules, built against a kernel package.
+    Module,
+    /// No files of its own, only pulls in its dependencies.
+    Meta,
+}
+
+impl PackageKind {
+    pub const ALL: [PackageKind; 5] = [
+        PackageKind::Application,
+        PackageKind::Library,
+        PackageKind::Kernel,
+        PackageKind::Module,
+        PackageKind::Meta,
+    ];
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|t| t.as_str() == name)
+    }
+
+    /// Name of the kind in `meta.json` and in the `package_kinds` table.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PackageKind::Application => "application",
+            PackageKind::Library => "library",
+            PackageKind::Kernel => "kernel",
+            PackageKind::Module => "module",
+            PackageKind::Meta => "meta",
+        }
+    }
+}
+
+/// Class of a file, recorded by the package builder or inferred from the path
+/// for the packages that predate it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FileKind {
+    /// Listed in `config_files` of the package.
+    Config,
+    Doc,
+    /// Kept by `--no-docs`, as distributions are often required to ship them.
+    License,
+    /// In one of the executable directories, or has an executable mode.
+    Binary,
+    Other,
+}
+
+impl FileKind {
+    pub const ALL: [FileKind; 5] = [
+        FileKind::Config,
+        FileKind::Doc,
+        FileKind::License,
+        FileKind::Binary,
+        FileKind::Other,
+    ];
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|t| t.as_str() == name)
+    }
+
+    /// Name of the class in `files.json` and in the database.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FileKind::Config => "config",
+            FileKind::Doc => "doc",
+            FileKind::License => "license",
+            FileKind::Binary => "binary",
+            FileKind::Other => "other",
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Files(pub Vec<FileStruct>);
 
+impl Files {
+    /// Index of the file at `path`, in either form of `PkgPath`.
+    pub fn position(&self, path: &str) -> Option<usize> {
+        let path = PkgPath::new(path).ok()?;
+        self.0
+            .iter()
+            .position(|t| t.pkg_path().map_or(false, |t| t == path))
+    }
+
+    pub fn find(&self, path: &str) -> Option<&FileStruct> {
+        self.position(path).map(|t| &self.0[t])
+    }
+
+    /// Files whose path matches the glob `pattern`, see `glob::matches`.
+    pub fn matching<'a>(&'a self, pattern: &'a str) -> impl Iterator<Item = &'a FileStruct> {
+        let pattern = PkgPath::new(pattern).ok();
+        self.0
+            .iter()
+            .filter(move |t| match (&pattern, t.pkg_path()) {
+                (Some(pattern), Ok(path)) => {
+                    glob::matches(pattern.as_relative(), path.as_relative())
+                }
+                _ => false,
+            })
+    }
+
+    /// Files below `prefix`, see `PkgPath::starts_with`.
+    pub fn under<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item = &'a FileStruct> {
+        let prefix = PkgPath::new(prefix).ok();
+        self.0
+            .iter()
+            .filter(move |t| match (&prefix, t.pkg_path()) {
+                (Some(prefix), Ok(path)) => path.starts_with(prefix),
+                _ => false,
+            })
+    }
+
+    /// Files of `kind`, `config_files` are the ones of the package meta data.
+    pub fn of_kind<'a>(
+        &'a self,
+        kind: FileKind,
+        config_files: &'a [String],
+    ) -> impl Iterator<Item = &'a FileStruct> {
+        self.0.iter().filter(move |t| t.kind(config_files) == kind)
+    }
+
+    /// `config_files` of the package meta data, along with the files that are
+    /// classed as config without being listed there.
+    pub fn config_paths(&self, config_files: &[String]) -> Vec<String> {
+        let mut paths = config_files.to_vec();
+        paths.extend(
+            self.of_kind(FileKind::Config, config_files)
+                .filter_map(|t| t.pkg_path().ok())
+                .filter(|path| {
+                    !config_files
+                        .iter()
+                        .any(|t| PkgPath::new(t).map_or(false, |t| t == *path))
+                })
+                .map(|path| path.as_relative().to_owned()),
+        );
+
+        paths
+    }
+
+    /// Sums the disk space used by the files installed under `root`, skipping
+    /// the ones that don't exist.
+    pub fn disk_usage(&self, root: &Path) -> io::Result<u64> {
+        let mut total = 0;
+        for file in &self.0 {
+            match disk_usage(&file.pkg_path()?.under(root)) {
+                Ok(usage) => total += usage,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(total)
+    }
+
+    /// Like `disk_usage`, but also records the size of each file.
+    pub fn record_sizes(&mut self, root: &Path) -> io::Result<u64> {
+        let mut total = 0;
+        for file in &mut self.0 {
+            match disk_usage(&file.pkg_path()?.under(root)) {
+                Ok(usage) => {
+                    file.size = Some(usage);
+                    total += usage;
+                }
+                Err(e) if e.kind() == io::ErrorKind::NotFound => file.size = None,
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(total)
+    }
+}
+
 impl json::Deserialize for Files {
     type Error = String;
 
@@ -161,24 +561,129 @@ impl json::Deserialize for SuggestionStruct {
     }
 }
 
+/// A command that several packages can provide, e.g. `vi`. The `link` points to
+/// the `path` of the provider with the highest priority, unless the user selects
+/// another one.
+#[derive(Debug, Clone)]
+pub struct AlternativeStruct {
+    pub name: String,
+    pub link: String,
+    pub path: String,
+    pub priority: i64,
+}
+
+impl json::Deserialize for AlternativeStruct {
+    type Error = String;
+
+    fn from_json_object(json: &json::JsonValue) -> Result<Self, Self::Error> {
+        Ok(Self {
+            name: de_required_field!(json["name"].to_string(), "name"),
+            link: de_required_field!(json["link"].to_string(), "link"),
+            path: de_required_field!(json["path"].to_string(), "path"),
+            priority: de_required_field!(json["priority"].as_i64(), "priority"),
+        })
+    }
+
+    fn from_json_array(json: &json::JsonValue) -> Result<Vec<Self>, Self::Error> {
+        let mut object_array = vec![];
+        match json {
+            JsonValue::Array(array) => {
+                for item in array {
+                    let object = Self::from_json_object(item)?;
+                    object_array.push(object);
+                }
+            }
+            _ => return Err("Wrong input, expected an array".to_string()),
+        };
+
+        Ok(object_array)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct FileStruct {
     pub path: String,
     pub checksum_algorithm: String,
     pub checksum: String,
+    /// Disk space used by the installed file, `None` if it's not known.
+    pub size: Option<u64>,
+    /// Permission bits, `None` keeps the ones of the archive.
+    pub mode: Option<u32>,
+    /// Modification time in seconds since the epoch, `None` for the time of
+    /// installation.
+    pub mtime: Option<i64>,
+    /// User and group names or ids, `None` for root. Declared by the package,
+    /// so the ownership of the build files doesn't matter.
+    pub owner: Option<String>,
+    pub group: Option<String>,
+    /// Set when the package was built, `None` for the older packages.
+    pub class: Option<FileKind>,
+}
+
+impl FileStruct {
+    pub fn pkg_path(&self) -> io::Result<PkgPath> {
+        PkgPath::new(&self.path)
+    }
+
+    /// The recorded class, otherwise the one the path suggests.
+    /// `config_files` are the ones of the package meta data.
+    pub fn kind(&self, config_files: &[String]) -> FileKind {
+        if let Some(class) = self.class {
+            return class;
+        }
+
+        // Never the case for the files of a parsed meta.
+        let Ok(path) = self.pkg_path() else {
+            return FileKind::Other;
+        };
+        let is_under = |dir: &&str| PkgPath::new(dir).map_or(false, |t| path.starts_with(&t));
+        if config_files
+            .iter()
+            .any(|t| PkgPath::new(t).map_or(false, |t| t == path))
+        {
+            FileKind::Config
+        } else if LICENSE_DIRS.iter().any(is_under)
+            || LICENSE_FILE_NAMES
+                .iter()
+                .any(|t| path.file_name().to_uppercase().starts_with(t))
+        {
+            FileKind::License
+        } else if DOC_DIRS.iter().any(is_under) {
+            FileKind::Doc
+        } else if BINARY_DIRS.iter().any(is_under) || self.mode.map_or(false, |t| t & 0o111 != 0) {
+            FileKind::Binary
+        } else {
+            FileKind::Other
+        }
+    }
 }
 
 impl json::Deserialize for FileStruct {
     type Error = String;
 
     fn from_json_object(json: &json::JsonValue) -> Result<Self, Self::Error> {
+        let path = de_required_field!(json["path"].to_string(), "path");
+        PkgPath::new(&path).map_err(|e| e.to_string())?;
+
         Ok(Self {
-            path: de_required_field!(json["path"].to_string(), "path"),
+            path,
             checksum_algorithm: de_required_field!(
                 json["checksum_algorithm"].to_string(),
                 "checksum_algorithm"
             ),
             checksum: de_required_field!(json["checksum"].to_string(), "checksum"),
+            size: json["size"].as_u64(),
+            mode: json["mode"].as_u32(),
+            mtime: json["mtime"].as_i64(),
+            owner: json["owner"].to_string(),
+            group: json["group"].to_string(),
+            class: match json["class"].to_string() {
+                Some(class) => Some(
+                    FileKind::parse(&class)
+                        .ok_or_else(|| format!("'{class}' is not a file class"))?,
+                ),
+                None => None,
+            },
         })
     }
 
@@ -198,10 +703,194 @@ impl json::Deserialize for FileStruct {
     }
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub struct DirectoryStruct {
+    pub path: String,
+    /// Permission bits, `None` for the default ones.
+    pub mode: Option<u32>,
+    /// Modification time in seconds since the epoch.
+    pub mtime: Option<i64>,
+    /// User and group names or ids, `None` for root.
+    pub owner: Option<String>,
+    pub group: Option<String>,
+}
+
+impl json::Deserialize for DirectoryStruct {
+    type Error = String;
+
+    fn from_json_object(json: &json::JsonValue) -> Result<Self, Self::Error> {
+        let path = de_field(json, "path", "a string", JsonValue::to_string)?;
+        PkgPath::new(&path).map_err(|e| e.to_string())?;
+
+        Ok(Self {
+            path,
+            mode: json["mode"].as_u32(),
+            mtime: json["mtime"].as_i64(),
+            owner: json["owner"].to_string(),
+            group: json["group"].to_string(),
+        })
+    }
+
+    fn from_json_array(json: &json::JsonValue) -> Result<Vec<Self>, Self::Error> {
+        let mut object_array = vec![];
+        match json {
+            JsonValue::Array(array) => {
+                for item in array {
+                    let object = Self::from_json_object(item)?;
+                    object_array.push(object);
+                }
+            }
+            _ => return Err("Wrong input, expected an array".to_string()),
+        };
+
+        Ok(object_array)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScriptStruct {
+    /// File name in `scripts/`, the phase or the custom phase it's for.
+    pub name: String,
+    /// sha256 of the content.
+    pub checksum: String,
+}
+
+impl json::Deserialize for ScriptStruct {
+    type Error = String;
+
+    fn from_json_object(json: &json::JsonValue) -> Result<Self, Self::Error> {
+        Ok(Self {
+            name: de_field(json, "name", "a string", JsonValue::to_string)?,
+            checksum: de_field(json, "checksum", "a string", JsonValue::to_string)?,
+        })
+    }
+
+    fn from_json_array(json: &json::JsonValue) -> Result<Vec<Self>, Self::Error> {
+        let mut object_array = vec![];
+        match json {
+            JsonValue::Array(array) => {
+                for item in array {
+                    let object = Self::from_json_object(item)?;
+                    object_array.push(object);
+                }
+            }
+            _ => return Err("Wrong input, expected an array".to_string()),
+        };
+
+        Ok(object_array)
+    }
+}
+
+impl Serialize for Meta {
+    fn to_json(&self) -> String {
+        to_json_object(&[
+            ("schema_version", META_SCHEMA_VERSION.to_json()),
+            ("name", self.name.to_json()),
+            ("arch", self.arch.to_json()),
+            ("slot", self.slot.to_json()),
+            ("essential", self.essential.to_json()),
+            ("kind", self.kind.map(|t| t.as_str().to_owned()).to_json()),
+            ("maintainer", self.maintainer.to_json()),
+            ("signing_key", self.signing_key.to_json()),
+            ("installed_size", self.installed_size.to_json()),
+            ("version", self.version.to_json()),
+            ("dependencies", self.dependencies.to_json()),
+            ("suggestions", self.suggestions.to_json()),
+            ("provides", self.provides.to_json()),
+            ("conflicts", self.conflicts.to_json()),
+            ("needs_sonames", self.needs_sonames.to_json()),
+            ("provides_sonames", self.provides_sonames.to_json()),
+            ("alternatives", self.alternatives.to_json()),
+            ("config_files", self.config_files.to_json()),
+            ("directories", self.directories.to_json()),
+            (
+                "scripts",
+                self.scripts.as_deref().unwrap_or_default().to_json(),
+            ),
+            (
+                "compression",
+                self.compression.map(|t| t.to_string()).to_json(),
+            ),
+        ])
+    }
+}
+
+impl Serialize for Files {
+    fn to_json(&self) -> String {
+        self.0.to_json()
+    }
+}
+
+impl Serialize for FileStruct {
+    fn to_json(&self) -> String {
+        to_json_object(&[
+            ("path", self.path.to_json()),
+            ("checksum_algorithm", self.checksum_algorithm.to_json()),
+            ("checksum", self.checksum.to_json()),
+            ("size", self.size.to_json()),
+            ("mode", self.mode.to_json()),
+            ("mtime", self.mtime.to_json()),
+            ("owner", self.owner.to_json()),
+            ("group", self.group.to_json()),
+            ("class", self.class.map(|t| t.as_str()).to_json()),
+        ])
+    }
+}
+
+impl Serialize for DependencyStruct {
+    fn to_json(&self) -> String {
+        to_json_object(&[
+            ("name", self.name.to_json()),
+            ("version", self.version.to_json()),
+        ])
+    }
+}
+
+impl Serialize for SuggestionStruct {
+    fn to_json(&self) -> String {
+        to_json_object(&[
+            ("name", self.name.to_json()),
+            ("version", self.version.to_json()),
+        ])
+    }
+}
+
+impl Serialize for AlternativeStruct {
+    fn to_json(&self) -> String {
+        to_json_object(&[
+            ("name", self.name.to_json()),
+            ("link", self.link.to_json()),
+            ("path", self.path.to_json()),
+            ("priority", self.priority.to_json()),
+        ])
+    }
+}
+
+impl Serialize for DirectoryStruct {
+    fn to_json(&self) -> String {
+        to_json_object(&[
+            ("path", self.path.to_json()),
+            ("mode", self.mode.to_json()),
+            ("mtime", self.mtime.to_json()),
+            ("owner", self.owner.to_json()),
+            ("group", self.group.to_json()),
+        ])
+    }
+}
+
+impl Serialize for ScriptStruct {
+    fn to_json(&self) -> String {
+        to_json_object(&[
+            ("name", self.name.to_json()),
+            ("checksum", self.checksum.to_json()),
+        ])
+    }
+}
+
 impl ParserTasks for Meta {
-    fn deserialize(path: &str) -> Self {
+    fn deserialize(path: &Path) -> Self {
         let data_as_str = fs::read_to_string(path).unwrap_or_else(|_| {
-            panic!("{} could not found.", path);
+            panic!("{} could not found.", path.display());
         });
 
         let json = json::Json::new(&data_as_str)
@@ -218,9 +907,9 @@ impl ParserTasks for Meta {
 }
 
 impl ParserTasks for Files {
-    fn deserialize(path: &str) -> Self {
+    fn deserialize(path: &Path) -> Self {
         let data_as_str = fs::read_to_string(path).unwrap_or_else(|_| {
-            panic!("{} could not found.", path);
+            panic!("{} could not found.", path.display());
         });
 
         let json = json::Json::new(&data_as_str)
@@ -236,3 +925,225 @@ impl ParserTasks for Files {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(content: &str) -> Result<Meta, String> {
+        Meta::from_json_object(&json::Json::new(content).parse().unwrap())
+    }
+
+    const VERSION: &str =
+        r#"{"readable_format": "1.0.0", "major": 1, "minor": 0, "patch": 0, "tag": null}"#;
+
+    fn meta_json(extra_fields: &str) -> String {
+        format!(
+            r#"{{"name": "htop", "arch": "amd64", "installed_size": 1024, "version": {VERSION}, "dependencies": [], "suggestions": []{extra_fields}}}"#
+        )
+    }
+
+    #[test]
+    fn test_meta_schema_version() {
+        assert!(parse(&meta_json("")).is_ok());
+        assert!(parse(&meta_json(r#", "schema_version": 2, "scripts": []"#)).is_ok());
+
+        let error = parse(&meta_json(r#", "schema_version": 3"#)).unwrap_err();
+        assert!(error.contains("reads up to version 2"));
+
+        let error = parse(&meta_json(r#", "schema_version": "latest""#)).unwrap_err();
+        assert_eq!(error, "Field 'schema_version' must be a positive integer.");
+    }
+
+    #[test]
+    fn test_meta_unknown_fields() {
+        // Ignored by the version 1 meta files, which predate the validation.
+        assert!(parse(&meta_json(r#", "homepage": "htop.dev""#)).is_ok());
+
+        let error = parse(&meta_json(
+            r#", "schema_version": 2, "scripts": [], "homepage": "htop.dev""#,
+        ))
+        .unwrap_err();
+        assert_eq!(error, "Unknown field(s) in meta data: 'homepage'.");
+    }
+
+    #[test]
+    fn test_meta_field_errors() {
+        let error = parse(&format!(r#"{{"name": "htop", "version": {VERSION}}}"#)).unwrap_err();
+        assert_eq!(error, "Field 'arch' is required and must be provided.");
+
+        let error = parse(&meta_json(r#", "essential": "yes""#)).unwrap_err();
+        assert_eq!(error, "Field 'essential' must be a boolean.");
+
+        let error = parse(&meta_json(r#", "kind": "game""#)).unwrap_err();
+        assert_eq!(
+            error,
+            "Field 'kind' must be one of 'application', 'library', 'kernel', 'module', 'meta'."
+        );
+
+        let error = parse(&meta_json(r#", "provides": {"name": "top"}"#)).unwrap_err();
+        assert_eq!(error, "Field 'provides' must be an array.");
+
+        let error = parse(&meta_json(
+            r#", "conflicts": [{"name": "top"}, {"version": null}]"#,
+        ))
+        .unwrap_err();
+        assert_eq!(
+            error,
+            "conflicts[1]: Field 'name' is required and must be provided."
+        );
+    }
+
+    #[test]
+    fn test_meta_directories() {
+        let meta = parse(&meta_json(
+            r#", "schema_version": 2, "scripts": [], "directories": [{"path": "var/lib/htop", "mode": 448, "mtime": 1700000000, "owner": "htop", "group": "htop"}, {"path": "etc/htop"}]"#,
+        ))
+        .unwrap();
+        assert_eq!(
+            meta.directories,
+            vec![
+                DirectoryStruct {
+                    path: String::from("var/lib/htop"),
+                    mode: Some(0o700),
+                    mtime: Some(1700000000),
+                    owner: Some(String::from("htop")),
+                    group: Some(String::from("htop")),
+                },
+                DirectoryStruct {
+                    path: String::from("etc/htop"),
+                    mode: None,
+                    mtime: None,
+                    owner: None,
+                    group: None,
+                },
+            ]
+        );
+
+        let error = parse(&meta_json(r#", "directories": [{"mode": 448}]"#)).unwrap_err();
+        assert_eq!(
+            error,
+            "directories[0]: Field 'path' is required and must be provided."
+        );
+
+        let error = parse(&meta_json(r#", "directories": [{"path": "../etc"}]"#)).unwrap_err();
+        assert_eq!(
+            error,
+            "directories[0]: Package path '../etc' must not contain '..'."
+        );
+    }
+
+    #[test]
+    fn test_meta_scripts() {
+        let meta = parse(&meta_json(
+            r#", "schema_version": 2, "scripts": [{"name": "post_install", "checksum": "ab12"}]"#,
+        ))
+        .unwrap();
+        assert_eq!(
+            meta.scripts,
+            Some(vec![ScriptStruct {
+                name: String::from("post_install"),
+                checksum: String::from("ab12"),
+            }])
+        );
+        assert_eq!(parse(&meta_json("")).unwrap().scripts, None);
+
+        // Scripts of the newer packages can't go unchecked.
+        let error = parse(&meta_json(r#", "schema_version": 2"#)).unwrap_err();
+        assert_eq!(error, "Field 'scripts' is required and must be provided.");
+
+        let error = parse(&meta_json(r#", "scripts": [{"name": "post_install"}]"#)).unwrap_err();
+        assert_eq!(
+            error,
+            "scripts[0]: Field 'checksum' is required and must be provided."
+        );
+    }
+
+    #[test]
+    fn test_meta_compression() {
+        let meta = parse(&meta_json(r#", "compression": "lz4:12""#)).unwrap();
+        assert_eq!(
+            meta.compression.map(|t| t.to_string()).as_deref(),
+            Some("lz4:12")
+        );
+        assert_eq!(parse(&meta_json("")).unwrap().compression, None);
+
+        let error = parse(&meta_json(r#", "compression": "lz4:30""#)).unwrap_err();
+        assert_eq!(
+            error,
+            "compression: Level of 'lz4' must be between 1 and 12, got '30'."
+        );
+        assert!(parse(&meta_json(r#", "compression": "zstd:19""#)).is_err());
+    }
+
+    fn files(paths: &[(&str, Option<u32>)]) -> Files {
+        Files(
+            paths
+                .iter()
+                .map(|(path, mode)| FileStruct {
+                    path: path.to_string(),
+                    checksum_algorithm: String::from("sha256"),
+                    checksum: String::new(),
+                    size: None,
+                    mode: *mode,
+                    mtime: None,
+                    owner: None,
+                    group: None,
+                    class: None,
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_files_queries() {
+        let mut files = files(&[
+            ("/usr/bin/htop", None),
+            ("/usr/share/doc/htop/README", None),
+            ("/usr/share/doc2/htop", None),
+            ("/etc/htoprc", None),
+            ("/opt/htop/run.sh", Some(0o755)),
+            ("/opt/htop/LICENSE.txt", None),
+        ]);
+
+        assert_eq!(files.position("usr/bin/htop"), Some(0));
+        assert_eq!(files.position("//usr/bin/htop"), Some(0));
+        assert!(files.find("/usr/bin/top").is_none());
+
+        let paths = |files: Vec<&FileStruct>| -> Vec<String> {
+            files.into_iter().map(|t| t.path.clone()).collect()
+        };
+        assert_eq!(
+            paths(files.matching("usr/share/*/htop*").collect()),
+            ["/usr/share/doc/htop/README", "/usr/share/doc2/htop"]
+        );
+        assert_eq!(
+            paths(files.under("/usr/share/doc").collect()),
+            ["/usr/share/doc/htop/README"]
+        );
+
+        let config_files = [String::from("etc/htoprc")];
+        assert_eq!(files.0[3].kind(&config_files), FileKind::Config);
+        assert_eq!(files.0[3].kind(&[]), FileKind::Other);
+        assert_eq!(
+            paths(files.of_kind(FileKind::Binary, &config_files).collect()),
+            ["/usr/bin/htop", "/opt/htop/run.sh"]
+        );
+        assert_eq!(
+            paths(files.of_kind(FileKind::Doc, &config_files).collect()),
+            ["/usr/share/doc/htop/README"]
+        );
+        assert_eq!(files.0[5].kind(&[]), FileKind::License);
+
+        // Recorded classes win over the paths.
+        files.0[2].class = Some(FileKind::Config);
+        files.0[3].class = Some(FileKind::Other);
+        assert_eq!(files.0[3].kind(&config_files), FileKind::Other);
+        assert_eq!(
+            files.config_paths(&config_files),
+            ["etc/htoprc", "usr/share/doc2/htop"]
+        );
+        assert_eq!(FileKind::parse("license"), Some(FileKind::License));
+        assert_eq!(FileKind::parse("docs"), None);
+    }
+}
@@ -1,23 +1,49 @@
 use super::ParserTasks;
 use crate::{
-    meta::{Files, Meta},
+    compression::Compression,
+    meta::{
+        DependencyStruct, FileStruct, Files, Meta, PackageKind, ScriptStruct, SuggestionStruct,
+    },
     system::System,
     version::{Condition, VersionStruct},
+    NO_ARCH,
 };
 
-use std::path::{Path, PathBuf};
+use hash::sha256;
+use json::Serialize;
+use std::{
+    fs, io,
+    os::unix::fs::PermissionsExt,
+    path::{Component, Path, PathBuf},
+};
 
 pub struct PkgDataFromFs {
     pub path: PathBuf,
     pub meta_dir: MetaDir,
     pub scripts: Vec<Stage1Script>,
     pub system: System,
+    /// Set for the packages downloaded from a repository, recorded along
+    /// with them in the database.
+    pub origin: Option<PkgOrigin>,
 }
 
 pub struct PkgDataFromDb {
     pub pkg_id: i64,
     pub group_id: String,
     pub meta_fields: MetaDir,
+    /// `None` for local `.lod` files.
+    pub origin: Option<PkgOrigin>,
+}
+
+/// Where an installed package was resolved from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PkgOrigin {
+    pub repository: String,
+    /// Sync timestamp of the repository index at the time.
+    pub index_timestamp: u32,
+    /// Id of the key that signed the index, which vouches for the archive
+    /// through its checksum. `None` for unsigned indexes.
+    pub signing_key: Option<String>,
 }
 
 pub struct MetaDir {
@@ -26,7 +52,7 @@ pub struct MetaDir {
     pub files: Files,
 }
 
-#[derive(PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ScriptPhase {
     PreInstall,
     PostInstall,
@@ -38,20 +64,408 @@ pub enum ScriptPhase {
     PostUpgrade,
 }
 
+impl ScriptPhase {
+    pub const ALL: [ScriptPhase; 8] = [
+        ScriptPhase::PreInstall,
+        ScriptPhase::PostInstall,
+        ScriptPhase::PreDelete,
+        ScriptPhase::PostDelete,
+        ScriptPhase::PreDowngrade,
+        ScriptPhase::PostDowngrade,
+        ScriptPhase::PreUpgrade,
+        ScriptPhase::PostUpgrade,
+    ];
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|t| t.as_str() == name)
+    }
+
+    /// Name of the script file in the package.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ScriptPhase::PreInstall => "pre_install",
+            ScriptPhase::PostInstall => "post_install",
+            ScriptPhase::PreDelete => "pre_delete",
+            ScriptPhase::PostDelete => "post_delete",
+            ScriptPhase::PreDowngrade => "pre_downgrade",
+            ScriptPhase::PostDowngrade => "post_downgrade",
+            ScriptPhase::PreUpgrade => "pre_upgrade",
+            ScriptPhase::PostUpgrade => "post_upgrade",
+        }
+    }
+}
+
+/// Manifest of the custom phases in the `scripts/` of a package.
+pub const PHASES_MANIFEST: &str = "phases";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PhaseOrder {
+    Before,
+    After,
+}
+
+/// Phase that a package adds to the built in ones, e.g. for steps that the
+/// policy of a distribution requires. Its script runs right before or after
+/// the script of `anchor`, whether the package has one for it or not.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CustomPhase {
+    /// Also the name of its script file.
+    pub name: String,
+    pub order: PhaseOrder,
+    pub anchor: ScriptPhase,
+}
+
+impl CustomPhase {
+    /// Parses the `PHASES_MANIFEST` of a package, a `<name> before|after
+    /// <phase>` line for each custom phase. Phases with the same anchor and
+    /// order run in the order of the manifest.
+    pub fn parse_manifest(content: &str) -> Result<Vec<Self>, String> {
+        let mut phases: Vec<Self> = Vec::new();
+        for line in content.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let [name, order, anchor] = fields[..] else {
+                return Err(format!("'{line}' is not a '<name> before|after <phase>' line"));
+            };
+
+            let is_valid_name = name
+                .chars()
+                .all(|t| t.is_ascii_lowercase() || t.is_ascii_digit() || t == '_');
+            if !is_valid_name || name == PHASES_MANIFEST || ScriptPhase::parse(name).is_some() {
+                return Err(format!("'{name}' can't be the name of a custom phase"));
+            }
+            if phases.iter().any(|t| t.name == name) {
+                return Err(format!("phase '{name}' is declared more than once"));
+            }
+
+            let order = match order {
+                "before" => PhaseOrder::Before,
+                "after" => PhaseOrder::After,
+                _ => return Err(format!("'{order}' must be either 'before' or 'after'")),
+            };
+            let anchor = ScriptPhase::parse(anchor)
+                .ok_or_else(|| format!("'{anchor}' is not a script phase"))?;
+
+            phases.push(Self {
+                name: name.to_owned(),
+                order,
+                anchor,
+            });
+        }
+
+        Ok(phases)
+    }
+}
+
 pub struct Stage1Script {
     pub contents: String,
     pub path: PathBuf,
+    /// The anchor for the scripts of custom phases.
     pub phase: ScriptPhase,
+    pub custom_phase: Option<CustomPhase>,
+    /// sha256 that the meta data records for `contents`, `None` for the
+    /// packages that predate script checksums.
+    pub checksum: Option<String>,
 }
 
 impl MetaDir {
-    pub fn new(dir: &Path) -> Self {
-        Self {
+    /// Fails with the reason if `meta.json` doesn't match its schema.
+    pub fn new(dir: &Path) -> Result<Self, String> {
+        let meta_path = dir.join("meta.json");
+        let meta = Meta::read(&meta_path).map_err(|e| format!("'{}': {e}", meta_path.display()))?;
+
+        Ok(Self {
             path: dir.to_owned(),
-            meta: Meta::deserialize(&dir.join("meta.json").to_string_lossy()),
-            files: Files::deserialize(&dir.join("files.json").to_string_lossy()),
+            meta,
+            files: Files::deserialize(&dir.join("files.json")),
+        })
+    }
+}
+
+impl PkgDataFromFs {
+    /// Starts building the data of a package, see `PkgDataBuilder`.
+    pub fn builder(name: &str, version: VersionStruct) -> PkgDataBuilder {
+        PkgDataBuilder::new(name, version)
+    }
+}
+
+/// Builds packages programmatically, so that tools creating them don't have
+/// to write the JSON of the package data themselves.
+///
+/// `write_to` lays the package out the way extracted archives are: the data
+/// in `meta/` and `system.json`, the files in `program/` and the scripts in
+/// `scripts/`.
+pub struct PkgDataBuilder {
+    meta: Meta,
+    files: Vec<(FileStruct, Vec<u8>)>,
+    scripts: Vec<(ScriptPhase, String)>,
+    system: System,
+    /// Paths given to `owner` that no file was added for.
+    unknown_owned_paths: Vec<String>,
+}
+
+impl PkgDataBuilder {
+    fn new(name: &str, version: VersionStruct) -> Self {
+        let any_version = VersionStruct {
+            readable_format: String::from("0.0.0"),
+            ..Default::default()
+        };
+
+        Self {
+            meta: Meta {
+                name: name.to_owned(),
+                arch: NO_ARCH.to_owned(),
+                slot: None,
+                essential: false,
+                kind: None,
+                maintainer: None,
+                signing_key: None,
+                installed_size: 0,
+                version,
+                dependencies: Vec::new(),
+                suggestions: Vec::new(),
+                provides: Vec::new(),
+                conflicts: Vec::new(),
+                needs_sonames: Vec::new(),
+                provides_sonames: Vec::new(),
+                alternatives: Vec::new(),
+                config_files: Vec::new(),
+                directories: Vec::new(),
+                scripts: Some(Vec::new()),
+                compression: None,
+            },
+            files: Vec::new(),
+            scripts: Vec::new(),
+            system: System {
+                builder_version: any_version.clone(),
+                min_supported_lpm_version: any_version,
+            },
+            unknown_owned_paths: Vec::new(),
         }
     }
+
+    /// `no-arch` unless it's set.
+    pub fn arch(mut self, arch: &str) -> Self {
+        self.meta.arch = arch.to_owned();
+        self
+    }
+
+    pub fn slot(mut self, slot: &str) -> Self {
+        self.meta.slot = Some(slot.to_owned());
+        self
+    }
+
+    pub fn essential(mut self, essential: bool) -> Self {
+        self.meta.essential = essential;
+        self
+    }
+
+    pub fn kind(mut self, kind: PackageKind) -> Self {
+        self.meta.kind = Some(kind);
+        self
+    }
+
+    pub fn maintainer(mut self, maintainer: &str, signing_key: Option<&str>) -> Self {
+        self.meta.maintainer = Some(maintainer.to_owned());
+        self.meta.signing_key = signing_key.map(str::to_owned);
+        self
+    }
+
+    pub fn dependency(mut self, name: &str, version: VersionStruct) -> Self {
+        self.meta.dependencies.push(DependencyStruct {
+            name: name.to_owned(),
+            version,
+        });
+        self
+    }
+
+    pub fn suggestion(mut self, name: &str, version: Option<VersionStruct>) -> Self {
+        self.meta.suggestions.push(SuggestionStruct {
+            name: name.to_owned(),
+            version,
+        });
+        self
+    }
+
+    pub fn provides(mut self, name: &str, version: Option<VersionStruct>) -> Self {
+        self.meta.provides.push(SuggestionStruct {
+            name: name.to_owned(),
+            version,
+        });
+        self
+    }
+
+    pub fn conflict(mut self, name: &str, version: Option<VersionStruct>) -> Self {
+        self.meta.conflicts.push(SuggestionStruct {
+            name: name.to_owned(),
+            version,
+        });
+        self
+    }
+
+    pub fn config_file(mut self, path: &str) -> Self {
+        self.meta.config_files.push(path.to_owned());
+        self
+    }
+
+    /// Adds a file installed at `path`, relative to the root, with the sha256
+    /// checksum of `contents`.
+    pub fn file(mut self, path: &str, contents: impl Into<Vec<u8>>, mode: Option<u32>) -> Self {
+        let contents = contents.into();
+        let file = FileStruct {
+            path: path.to_owned(),
+            checksum_algorithm: String::from("sha256"),
+            checksum: hash::digest_to_hex_string(&sha256::digest(&contents)),
+            size: Some(contents.len() as u64),
+            mode,
+            mtime: None,
+            owner: None,
+            group: None,
+            class: None,
+        };
+
+        self.meta.installed_size += contents.len() as i64;
+        self.files.push((file, contents));
+        self
+    }
+
+    /// Installs the file added at `path` as `owner` and `group`(names or
+    /// ids) instead of root. Only recorded in the package data, so packages
+    /// can be built without root.
+    pub fn owner(mut self, path: &str, owner: &str, group: &str) -> Self {
+        match self.files.iter_mut().find(|(t, _)| t.path == path) {
+            Some((file, _)) => {
+                file.owner = Some(owner.to_owned());
+                file.group = Some(group.to_owned());
+            }
+            None => self.unknown_owned_paths.push(path.to_owned()),
+        }
+        self
+    }
+
+    pub fn script(mut self, phase: ScriptPhase, contents: &str) -> Self {
+        self.scripts.retain(|(t, _)| *t != phase);
+        self.scripts.push((phase, contents.to_owned()));
+        self
+    }
+
+    /// Versions of the tool building the package and of the oldest lpm that
+    /// can install it, `0.0.0` unless they are set.
+    pub fn system(
+        mut self,
+        builder_version: VersionStruct,
+        min_lpm_version: VersionStruct,
+    ) -> Self {
+        self.system = System {
+            builder_version,
+            min_supported_lpm_version: min_lpm_version,
+        };
+        self
+    }
+
+    /// Compression of the archive, recorded in the meta data. Packages
+    /// without it are LZ4 compressed.
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.meta.compression = Some(compression);
+        self
+    }
+
+    /// Writes the package into `dir`, which should be empty.
+    pub fn write_to(mut self, dir: &Path) -> io::Result<PkgDataFromFs> {
+        let invalid = |reason: String| io::Error::new(io::ErrorKind::InvalidInput, reason);
+        if self.meta.name.is_empty() {
+            return Err(invalid(String::from("package name is empty")));
+        }
+
+        if let Some(path) = self.unknown_owned_paths.first() {
+            return Err(invalid(format!(
+                "owner is set for '{path}', which is not added"
+            )));
+        }
+
+        for (i, (file, _)) in self.files.iter().enumerate() {
+            let path = Path::new(&file.path);
+            let is_escaping = path
+                .components()
+                .any(|t| !matches!(t, Component::Normal(_) | Component::CurDir));
+            if file.path.is_empty() || is_escaping {
+                return Err(invalid(format!(
+                    "'{}' must be a relative path inside the package",
+                    file.path
+                )));
+            }
+
+            if self.files[..i].iter().any(|(t, _)| t.path == file.path) {
+                return Err(invalid(format!("'{}' is added more than once", file.path)));
+            }
+        }
+
+        let meta_path = dir.join("meta");
+        let program_path = dir.join("program");
+        fs::create_dir_all(&meta_path)?;
+        fs::create_dir_all(&program_path)?;
+
+        let mut files = Vec::with_capacity(self.files.len());
+        for (mut file, contents) in self.files {
+            file.class = Some(file.kind(&self.meta.config_files));
+
+            let path = program_path.join(&file.path);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(&path, contents)?;
+            if let Some(mode) = file.mode {
+                fs::set_permissions(&path, fs::Permissions::from_mode(mode))?;
+            }
+
+            files.push(file);
+        }
+        let files = Files(files);
+
+        let mut scripts = Vec::with_capacity(self.scripts.len());
+        if !self.scripts.is_empty() {
+            fs::create_dir_all(dir.join("scripts"))?;
+        }
+        for (phase, contents) in self.scripts {
+            let path = dir.join("scripts").join(phase.as_str());
+            fs::write(&path, &contents)?;
+
+            let checksum = hash::digest_to_hex_string(&sha256::digest(contents.as_bytes()));
+            self.meta
+                .scripts
+                .get_or_insert_with(Vec::new)
+                .push(ScriptStruct {
+                    name: phase.as_str().to_owned(),
+                    checksum: checksum.clone(),
+                });
+            scripts.push(Stage1Script {
+                contents,
+                path,
+                phase,
+                custom_phase: None,
+                checksum: Some(checksum),
+            });
+        }
+
+        fs::write(meta_path.join("meta.json"), self.meta.to_json())?;
+        fs::write(meta_path.join("files.json"), files.to_json())?;
+        fs::write(dir.join("system.json"), self.system.to_json())?;
+
+        Ok(PkgDataFromFs {
+            path: dir.to_owned(),
+            meta_dir: MetaDir {
+                path: meta_path,
+                meta: self.meta,
+                files,
+            },
+            scripts,
+            system: self.system,
+            origin: None,
+        })
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -62,6 +476,8 @@ pub struct PkgToQuery {
     pub patch: Option<u16>,
     pub tag: Option<String>,
     pub condition: Condition,
+    /// Slot of a slot-aware package, any of them if `None`.
+    pub slot: Option<String>,
 }
 
 impl PkgToQuery {
@@ -107,6 +523,7 @@ impl PkgToQuery {
                 minor,
                 patch,
                 tag,
+                slot: None,
             })
         } else {
             Some(Self {
@@ -116,6 +533,7 @@ impl PkgToQuery {
                 minor: None,
                 patch: None,
                 tag: None,
+                slot: None,
             })
         }
     }
@@ -179,6 +597,7 @@ impl ToString for PkgToQuery {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{meta::FileKind, testing::TestDir};
 
     #[test]
     fn test_pkg_to_query_with_version() {
@@ -191,6 +610,7 @@ mod tests {
             minor: Some(3),
             patch: Some(5),
             tag: Some(String::from("beta")),
+            slot: None,
             condition: Condition::default(),
         };
 
@@ -209,6 +629,7 @@ mod tests {
                 minor: Some(3),
                 patch: Some(5),
                 tag: Some(String::from("beta")),
+                slot: None,
                 condition: Condition::Equal,
             };
 
@@ -225,6 +646,7 @@ mod tests {
                 minor: Some(3),
                 patch: Some(5),
                 tag: Some(String::from("beta")),
+                slot: None,
                 condition: Condition::Less,
             };
 
@@ -241,6 +663,7 @@ mod tests {
                 minor: Some(3),
                 patch: Some(5),
                 tag: Some(String::from("beta")),
+                slot: None,
                 condition: Condition::Greater,
             };
 
@@ -257,6 +680,7 @@ mod tests {
                 minor: Some(3),
                 patch: Some(5),
                 tag: Some(String::from("beta")),
+                slot: None,
                 condition: Condition::LessOrEqual,
             };
 
@@ -273,6 +697,7 @@ mod tests {
                 minor: Some(3),
                 patch: Some(5),
                 tag: Some(String::from("beta")),
+                slot: None,
                 condition: Condition::GreaterOrEqual,
             };
 
@@ -311,4 +736,124 @@ mod tests {
         assert_eq!(package.patch, None);
         assert_eq!(package.tag, None);
     }
+
+    #[test]
+    fn test_pkg_data_builder() {
+        let dir = TestDir::new("pkg-builder");
+        let version = |readable_format: &str, major| VersionStruct {
+            readable_format: readable_format.to_owned(),
+            major,
+            ..Default::default()
+        };
+
+        let pkg = PkgDataFromFs::builder("hello", version("1.0.0", 1))
+            .arch("amd64")
+            .maintainer("Jane Doe <jane@example.org>", Some("jane-2024"))
+            .dependency("libc", version("2.0.0", 2))
+            .conflict("hello-legacy", None)
+            .config_file("etc/hello.conf")
+            .file("usr/bin/hello", "#!/bin/sh\necho hello\n", Some(0o755))
+            .file("etc/hello.conf", "greeting=hello\n", None)
+            .owner("etc/hello.conf", "hello", "0")
+            .script(ScriptPhase::PostInstall, "echo installed")
+            .compression(Compression::parse("lz4:12").unwrap())
+            .write_to(&dir)
+            .unwrap();
+
+        assert_eq!(pkg.meta_dir.meta.installed_size, 36);
+        assert_eq!(pkg.scripts[0].path, dir.join("scripts/post_install"));
+        assert_eq!(
+            fs::metadata(dir.join("program/usr/bin/hello"))
+                .unwrap()
+                .permissions()
+                .mode()
+                & 0o777,
+            0o755
+        );
+
+        // What lpm reads from extracted packages.
+        let meta_dir = MetaDir::new(&dir.join("meta")).unwrap();
+        assert_eq!(meta_dir.meta.get_group_id(), "hello@1.0.0");
+        assert_eq!(meta_dir.meta.arch, "amd64");
+        assert_eq!(
+            meta_dir.meta.maintainer.as_deref(),
+            Some("Jane Doe <jane@example.org>")
+        );
+        assert_eq!(meta_dir.meta.signing_key.as_deref(), Some("jane-2024"));
+        assert_eq!(meta_dir.meta.dependencies[0].version.major, 2);
+        assert_eq!(meta_dir.meta.conflicts[0].name, "hello-legacy");
+        assert_eq!(meta_dir.meta.config_files, ["etc/hello.conf"]);
+        assert_eq!(
+            meta_dir.meta.scripts.as_ref().unwrap()[0].name,
+            "post_install"
+        );
+        assert_eq!(
+            meta_dir.meta.compression.map(|t| t.to_string()).as_deref(),
+            Some("lz4:12")
+        );
+        assert_eq!(
+            Some(&meta_dir.meta.scripts.as_ref().unwrap()[0].checksum),
+            pkg.scripts[0].checksum.as_ref()
+        );
+        assert_eq!(meta_dir.files.0.len(), 2);
+        assert_eq!(
+            meta_dir.files.0[1].checksum,
+            hash::digest_to_hex_string(&sha256::digest(b"greeting=hello\n"))
+        );
+        assert_eq!(meta_dir.files.0[0].owner, None);
+        assert_eq!(meta_dir.files.0[0].class, Some(FileKind::Binary));
+        assert_eq!(meta_dir.files.0[1].class, Some(FileKind::Config));
+        assert_eq!(meta_dir.files.0[1].owner.as_deref(), Some("hello"));
+        assert_eq!(meta_dir.files.0[1].group.as_deref(), Some("0"));
+        let system = System::deserialize(&dir.join("system.json"));
+        assert_eq!(system.min_supported_lpm_version.readable_format, "0.0.0");
+
+        let error = PkgDataFromFs::builder("hello", version("1.0.0", 1))
+            .file("../etc/passwd", "", None)
+            .write_to(&dir)
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+
+        let error = PkgDataFromFs::builder("hello", version("1.0.0", 1))
+            .owner("etc/hello.conf", "hello", "hello")
+            .write_to(&dir)
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_parse_phases_manifest() {
+        let manifest = "
+            # Refreshed for every package that installs icons.
+            update_icon_cache   after   post_install
+            stop_service        before  pre_delete
+        ";
+        assert_eq!(
+            CustomPhase::parse_manifest(manifest).unwrap(),
+            [
+                CustomPhase {
+                    name: String::from("update_icon_cache"),
+                    order: PhaseOrder::After,
+                    anchor: ScriptPhase::PostInstall,
+                },
+                CustomPhase {
+                    name: String::from("stop_service"),
+                    order: PhaseOrder::Before,
+                    anchor: ScriptPhase::PreDelete,
+                },
+            ]
+        );
+
+        assert!(CustomPhase::parse_manifest("post_install after pre_install").is_err());
+        assert!(CustomPhase::parse_manifest("../cache after post_install").is_err());
+        assert!(CustomPhase::parse_manifest("cache during post_install").is_err());
+        assert!(CustomPhase::parse_manifest("cache after post_build").is_err());
+        assert!(CustomPhase::parse_manifest("cache after").is_err());
+        assert!(
+            CustomPhase::parse_manifest("cache after post_install\ncache before pre_delete")
+                .is_err()
+        );
+    }
 }
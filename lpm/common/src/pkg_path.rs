@@ -0,0 +1,127 @@
+//! Paths of package files, which are relative to the root in the packages and
+//! absolute in the database. Both forms, along with repeated slashes and `.`
+//! components, are normalized away by `PkgPath` before the paths are compared
+//! or placed under a root.
+
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+};
+
+/// A normalized package path, kept relative to the root. `/` is the empty
+/// path.
+///
+/// `..` components are rejected, as resolving them needs the filesystem and
+/// could lead outside of the root.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PkgPath(String);
+
+impl PkgPath {
+    /// Fails with `InvalidData` on `..` components.
+    pub fn new(path: &str) -> io::Result<Self> {
+        let components: Vec<&str> = path
+            .split('/')
+            .filter(|t| !t.is_empty() && *t != ".")
+            .collect();
+        if components.contains(&"..") {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Package path '{path}' must not contain '..'."),
+            ));
+        }
+
+        Ok(Self(components.join("/")))
+    }
+
+    /// E.g. `usr/bin/htop`, the form of the paths in `files.json`.
+    pub fn as_relative(&self) -> &str {
+        &self.0
+    }
+
+    /// E.g. `/usr/bin/htop`, the form of the paths in the database.
+    pub fn to_absolute(&self) -> String {
+        format!("/{}", self.0)
+    }
+
+    /// The path below `root`, e.g. `/mnt/usr/bin/htop` for `/mnt`.
+    pub fn under(&self, root: &Path) -> PathBuf {
+        if self.0.is_empty() {
+            return root.to_owned();
+        }
+
+        root.join(&self.0)
+    }
+
+    /// Whether the path is `prefix` or below it, e.g. `/usr/share/doc`
+    /// contains `usr/share/doc/htop` but not `usr/share/doc2`.
+    pub fn starts_with(&self, prefix: &PkgPath) -> bool {
+        prefix.0.is_empty()
+            || self
+                .0
+                .strip_prefix(&prefix.0)
+                .map_or(false, |rest| rest.is_empty() || rest.starts_with('/'))
+    }
+
+    /// The last component, empty for `/`.
+    pub fn file_name(&self) -> &str {
+        self.0.rsplit('/').next().unwrap_or_default()
+    }
+}
+
+/// Shown in the absolute form.
+impl fmt::Display for PkgPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "/{}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pkg_path() {
+        let path = PkgPath::new("usr/bin/htop").unwrap();
+        for other in ["/usr/bin/htop", "//usr//bin/./htop", "usr/bin/htop/"] {
+            assert_eq!(PkgPath::new(other).unwrap(), path);
+        }
+        assert_eq!(path.as_relative(), "usr/bin/htop");
+        assert_eq!(path.to_absolute(), "/usr/bin/htop");
+        assert_eq!(path.to_string(), "/usr/bin/htop");
+        assert_eq!(path.file_name(), "htop");
+        assert_eq!(
+            path.under(Path::new("/mnt")),
+            PathBuf::from("/mnt/usr/bin/htop")
+        );
+        assert_eq!(
+            PkgPath::new("/").unwrap().under(Path::new("/mnt")),
+            Path::new("/mnt")
+        );
+        assert_eq!(
+            PkgPath::new("usr/../etc").unwrap_err().to_string(),
+            "Package path 'usr/../etc' must not contain '..'."
+        );
+        assert!(PkgPath::new("/..").is_err());
+        assert_eq!(
+            PkgPath::new("usr/..bin").unwrap().as_relative(),
+            "usr/..bin"
+        );
+    }
+
+    #[test]
+    fn test_starts_with() {
+        let starts_with = |path: &str, prefix: &str| {
+            PkgPath::new(path)
+                .unwrap()
+                .starts_with(&PkgPath::new(prefix).unwrap())
+        };
+
+        assert!(starts_with("/usr/share/doc/htop", "usr/share/doc"));
+        assert!(starts_with("usr/share/doc/htop", "/usr/share/doc/"));
+        assert!(starts_with("/usr/share/doc", "/usr/share/doc"));
+        assert!(starts_with("usr//share/./doc/htop", "/usr/share/doc"));
+        assert!(!starts_with("/usr/share/doc2/htop", "/usr/share/doc"));
+        assert!(!starts_with("/usr/share", "/usr/share/doc"));
+        assert!(starts_with("/etc/htoprc", "/"));
+    }
+}
@@ -0,0 +1,631 @@
+//! Network requests in a child process running as the `download_user` of the
+//! config, so the HTTP parsing never runs as root.
+//!
+//! For downloads, the child can only write into a staging directory next to
+//! each output. Its progress events and the responses of the other requests
+//! come back through a socket. Once it exits, the parent moves the files into
+//! place and takes their ownership back; they are verified by the callers as
+//! usual before anything gets installed.
+//!
+//! The child is forked without `exec`, which is only sound while no other
+//! thread runs in the process. This is checked before forking, so programs
+//! embedding lpm have to send its requests while they don't run other
+//! threads either.
+
+use crate::{
+    download_files_in_process,
+    event::{EventSink, NoEvents},
+    interrupt, local_address_path, send_requests_in_process, skip_existing, stats, DownloadOptions,
+    Request,
+};
+
+use rekuest::HttpResponse;
+use std::{
+    collections::BTreeMap,
+    ffi::CString,
+    fs,
+    io::{self, BufRead, BufReader, Read, Write},
+    os::{
+        raw::{c_char, c_int},
+        unix::{
+            ffi::OsStrExt,
+            fs::{MetadataExt, PermissionsExt},
+            net::UnixStream,
+        },
+    },
+    path::{Path, PathBuf},
+    ptr,
+    sync::Mutex,
+};
+
+/// Directory of the unprivileged downloads, inside the one of their outputs.
+const STAGING_DIR: &str = ".unprivileged";
+
+extern "C" {
+    fn getuid() -> u32;
+    fn fork() -> c_int;
+    fn setgroups(size: usize, list: *const u32) -> c_int;
+    fn setgid(gid: u32) -> c_int;
+    fn setuid(uid: u32) -> c_int;
+    fn lchown(path: *const c_char, owner: u32, group: u32) -> c_int;
+    fn waitpid(pid: c_int, status: *mut c_int, options: c_int) -> c_int;
+    fn _exit(status: c_int) -> !;
+}
+
+/// Lines sent from the child to the parent.
+#[derive(Debug, PartialEq)]
+enum ChildMessage {
+    Started(String),
+    Progress(String, u64, Option<u64>),
+    Finished(String),
+    Failed(String),
+    /// Response to the request at `index`, followed by `headers` lines of
+    /// headers and `body_len` bytes of body.
+    Response {
+        index: usize,
+        status_code: u16,
+        headers: usize,
+        body_len: u64,
+    },
+    RequestFailed(usize, String),
+}
+
+impl ChildMessage {
+    /// The file name or the error goes last, as it may contain tabs.
+    fn encode(&self) -> String {
+        match self {
+            ChildMessage::Started(file_name) => format!("started\t{file_name}"),
+            ChildMessage::Progress(file_name, downloaded, total) => {
+                let total = total.map(|t| t.to_string()).unwrap_or_default();
+                format!("progress\t{downloaded}\t{total}\t{file_name}")
+            }
+            ChildMessage::Finished(file_name) => format!("finished\t{file_name}"),
+            // Kept on one line.
+            ChildMessage::Failed(error) => format!("failed\t{}", error.replace('\n', " ")),
+            ChildMessage::Response {
+                index,
+                status_code,
+                headers,
+                body_len,
+            } => format!("response\t{index}\t{status_code}\t{headers}\t{body_len}"),
+            ChildMessage::RequestFailed(index, error) => {
+                format!("request_failed\t{index}\t{}", error.replace('\n', " "))
+            }
+        }
+    }
+
+    fn decode(line: &str) -> Option<Self> {
+        let (kind, rest) = line.split_once('\t')?;
+        match kind {
+            "started" => Some(ChildMessage::Started(rest.to_owned())),
+            "progress" => {
+                let mut fields = rest.splitn(3, '\t');
+                let downloaded = fields.next()?.parse().ok()?;
+                let total = match fields.next()? {
+                    "" => None,
+                    total => Some(total.parse().ok()?),
+                };
+                Some(ChildMessage::Progress(
+                    fields.next()?.to_owned(),
+                    downloaded,
+                    total,
+                ))
+            }
+            "finished" => Some(ChildMessage::Finished(rest.to_owned())),
+            "failed" => Some(ChildMessage::Failed(rest.to_owned())),
+            "response" => {
+                let mut fields = rest.split('\t');
+                let message = ChildMessage::Response {
+                    index: fields.next()?.parse().ok()?,
+                    status_code: fields.next()?.parse().ok()?,
+                    headers: fields.next()?.parse().ok()?,
+                    body_len: fields.next()?.parse().ok()?,
+                };
+                fields.next().is_none().then_some(message)
+            }
+            "request_failed" => {
+                let (index, error) = rest.split_once('\t')?;
+                Some(ChildMessage::RequestFailed(
+                    index.parse().ok()?,
+                    error.to_owned(),
+                ))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Sink of the child, which forwards the events to the parent.
+struct ForwardEvents(Mutex<UnixStream>);
+
+impl ForwardEvents {
+    fn send(&self, message: ChildMessage) {
+        if let Ok(mut stream) = self.0.lock() {
+            // The parent is gone if this fails, and the download with it.
+            let _ = writeln!(stream, "{}", message.encode());
+        }
+    }
+
+    fn send_response(&self, index: usize, response: io::Result<HttpResponse>) {
+        let response = match response {
+            Ok(response) => response,
+            Err(e) => return self.send(ChildMessage::RequestFailed(index, e.to_string())),
+        };
+
+        let message = ChildMessage::Response {
+            index,
+            status_code: response.status_code,
+            headers: response.headers.len(),
+            body_len: response.body.len() as u64,
+        };
+        if let Ok(mut stream) = self.0.lock() {
+            let mut send = || -> io::Result<()> {
+                writeln!(stream, "{}", message.encode())?;
+                for (name, value) in &response.headers {
+                    writeln!(stream, "{name}\t{value}")?;
+                }
+                stream.write_all(&response.body)
+            };
+            // The parent is gone if this fails, and the request with it.
+            let _ = send();
+        }
+    }
+}
+
+impl EventSink for ForwardEvents {
+    fn download_started(&self, file_name: &str) {
+        self.send(ChildMessage::Started(file_name.to_owned()));
+    }
+
+    fn download_progress(&self, file_name: &str, downloaded: u64, total: Option<u64>) {
+        self.send(ChildMessage::Progress(
+            file_name.to_owned(),
+            downloaded,
+            total,
+        ));
+    }
+
+    fn download_finished(&self, file_name: &str) {
+        self.send(ChildMessage::Finished(file_name.to_owned()));
+    }
+}
+
+/// User of the first request that asks for one, if lpm runs as root.
+pub(crate) fn request_user<'a>(
+    options: impl IntoIterator<Item = &'a DownloadOptions>,
+) -> Option<&'a str> {
+    // SAFETY: `getuid` has no preconditions and can't fail.
+    #[allow(unsafe_code)]
+    let uid = unsafe { getuid() };
+    if uid != 0 {
+        return None;
+    }
+
+    options
+        .into_iter()
+        .find_map(|options| options.download_user.as_deref())
+}
+
+/// Downloads `downloads` as `user`. Outputs that already exist and copies from
+/// local repositories are handled in this process, since they don't involve
+/// any network traffic.
+pub(crate) fn download_files_as(
+    user: &str,
+    downloads: &[(Vec<String>, PathBuf, DownloadOptions)],
+    events: &dyn EventSink,
+) -> io::Result<()> {
+    let (uid, gid) = lookup_user(user)?;
+
+    let (local, remote): (Vec<_>, Vec<_>) = downloads
+        .iter()
+        .filter(|(_, output_path, _)| !skip_existing(output_path))
+        .cloned()
+        .partition(|(urls, _, _)| urls.iter().all(|t| local_address_path(t).is_some()));
+    download_files_in_process(&local, events)?;
+    if remote.is_empty() {
+        return Ok(());
+    }
+
+    let mut staged = Vec::with_capacity(remote.len());
+    for (urls, output_path, options) in &remote {
+        let staging_path = staging_path(output_path)?;
+        prepare_staging_dir(staging_path.parent().unwrap(), uid, gid)?;
+        match fs::remove_file(&staging_path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        staged.push((urls.clone(), staging_path, options.clone()));
+    }
+
+    run_child(uid, gid, events, |child_events| {
+        download_files_in_process(&staged, child_events)
+    })?;
+
+    for ((_, output_path, _), (_, staging_path, _)) in remote.iter().zip(&staged) {
+        move_into_place(staging_path, output_path)?;
+    }
+
+    Ok(())
+}
+
+/// Sends `requests` as `user`, and returns their responses in the same order.
+pub(crate) fn send_requests_as(
+    user: &str,
+    requests: &[(Request, DownloadOptions)],
+) -> io::Result<Vec<io::Result<HttpResponse>>> {
+    let (uid, gid) = lookup_user(user)?;
+
+    let mut report = run_child(uid, gid, &NoEvents, |child_events| {
+        for (index, response) in send_requests_in_process(requests).into_iter().enumerate() {
+            child_events.send_response(index, response);
+        }
+        Ok(())
+    })?;
+
+    Ok((0..requests.len())
+        .map(|index| {
+            report.responses.remove(&index).unwrap_or_else(|| {
+                Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    "request process sent no response",
+                ))
+            })
+        })
+        .collect())
+}
+
+/// What the child sent besides the events.
+#[derive(Default)]
+struct ChildReport {
+    /// Error that the child failed with.
+    failure: Option<String>,
+    /// Responses by the index of their request.
+    responses: BTreeMap<usize, io::Result<HttpResponse>>,
+}
+
+/// Runs `work` in a child process as `uid` and `gid`, and passes the events
+/// that it sends on to `events`.
+fn run_child(
+    uid: u32,
+    gid: u32,
+    events: &dyn EventSink,
+    work: impl FnOnce(&ForwardEvents) -> io::Result<()>,
+) -> io::Result<ChildReport> {
+    ensure_single_threaded()?;
+    let (parent_end, child_end) = UnixStream::pair()?;
+
+    // SAFETY: No other thread runs in this process, as checked above, so the
+    // child can't inherit a lock held by one.
+    #[allow(unsafe_code)]
+    let pid = unsafe { fork() };
+    if pid < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    if pid == 0 {
+        drop(parent_end);
+        let child_events = ForwardEvents(Mutex::new(child_end));
+        let result = drop_privileges(uid, gid).and_then(|()| work(&child_events));
+        if let Err(e) = &result {
+            child_events.send(ChildMessage::Failed(e.to_string()));
+        }
+
+        // SAFETY: Leaves without running the exit handlers of the parent,
+        // e.g. flushing its buffers a second time.
+        #[allow(unsafe_code)]
+        unsafe {
+            _exit(i32::from(result.is_err()))
+        }
+    }
+
+    drop(child_end);
+    let report = read_child_messages(parent_end, events);
+    let status = wait_for(pid)?;
+
+    interrupt::check()?;
+    let report = report?;
+    if let Some(error) = &report.failure {
+        return Err(io::Error::new(io::ErrorKind::Other, error.clone()));
+    }
+    if status != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("request process exited with status {status}"),
+        ));
+    }
+
+    Ok(report)
+}
+
+/// Fails if other threads run in this process, since the child is forked
+/// without `exec` and could inherit a lock that one of them holds, e.g. the
+/// one of the allocator, and hang on it.
+fn ensure_single_threaded() -> io::Result<()> {
+    let threads = fs::read_dir("/proc/self/task")?.count();
+    if threads > 1 {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!(
+                "'download_user' needs requests to be sent while no other thread runs, but {threads} threads are running"
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Uid and gid of `user` in `/etc/passwd`.
+fn lookup_user(user: &str) -> io::Result<(u32, u32)> {
+    find_user(&fs::read_to_string("/etc/passwd")?, user).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("download user '{user}' doesn't exist"),
+        )
+    })
+}
+
+fn find_user(passwd: &str, user: &str) -> Option<(u32, u32)> {
+    passwd.lines().find_map(|line| {
+        let mut fields = line.split(':');
+        if fields.next()? != user {
+            return None;
+        }
+        let mut ids = fields.skip(1);
+        Some((ids.next()?.parse().ok()?, ids.next()?.parse().ok()?))
+    })
+}
+
+fn staging_path(output_path: &Path) -> io::Result<PathBuf> {
+    match (output_path.parent(), output_path.file_name()) {
+        (Some(parent), Some(file_name)) => Ok(parent.join(STAGING_DIR).join(file_name)),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid download path '{}'", output_path.display()),
+        )),
+    }
+}
+
+/// Creates `dir` so that only `uid` can use it.
+fn prepare_staging_dir(dir: &Path, uid: u32, gid: u32) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    if !fs::symlink_metadata(dir)?.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("'{}' is not a directory", dir.display()),
+        ));
+    }
+
+    change_owner(dir, uid, gid)?;
+    fs::set_permissions(dir, fs::Permissions::from_mode(0o700))
+}
+
+/// Doesn't follow symlinks.
+fn change_owner(path: &Path, uid: u32, gid: u32) -> io::Result<()> {
+    let path = CString::new(path.as_os_str().as_bytes())?;
+
+    // SAFETY: `path` is NUL terminated and outlives the call.
+    #[allow(unsafe_code)]
+    let result = unsafe { lchown(path.as_ptr(), uid, gid) };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+fn drop_privileges(uid: u32, gid: u32) -> io::Result<()> {
+    // SAFETY: These only take plain integers, and a null list with a size
+    // of 0 for `setgroups`.
+    #[allow(unsafe_code)]
+    let failed = unsafe { setgroups(0, ptr::null()) != 0 || setgid(gid) != 0 || setuid(uid) != 0 };
+    if failed {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Passes the events of the child to `events` until it closes the socket,
+/// and collects the rest of what it sends.
+fn read_child_messages(stream: UnixStream, events: &dyn EventSink) -> io::Result<ChildReport> {
+    let mut report = ChildReport::default();
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            break;
+        }
+
+        match ChildMessage::decode(line.trim_end_matches('\n')) {
+            Some(ChildMessage::Started(file_name)) => events.download_started(&file_name),
+            Some(ChildMessage::Progress(file_name, downloaded, total)) => {
+                events.download_progress(&file_name, downloaded, total)
+            }
+            Some(ChildMessage::Finished(file_name)) => events.download_finished(&file_name),
+            Some(ChildMessage::Failed(error)) => report.failure = Some(error),
+            Some(ChildMessage::Response {
+                index,
+                status_code,
+                headers,
+                body_len,
+            }) => {
+                let response = read_response(&mut reader, status_code, headers, body_len)?;
+                report.responses.insert(index, Ok(response));
+            }
+            Some(ChildMessage::RequestFailed(index, error)) => {
+                let error = io::Error::new(io::ErrorKind::Other, error);
+                report.responses.insert(index, Err(error));
+            }
+            None => {}
+        }
+    }
+
+    Ok(report)
+}
+
+/// Reads the headers and the body that follow a `ChildMessage::Response`.
+/// The body is read as it comes, so a wrong length can't make the parent
+/// allocate more than the child sent.
+fn read_response(
+    reader: &mut impl BufRead,
+    status_code: u16,
+    headers: usize,
+    body_len: u64,
+) -> io::Result<HttpResponse> {
+    let mut response = HttpResponse {
+        headers: Vec::new(),
+        body: Vec::new(),
+        status_code,
+    };
+
+    let mut line = String::new();
+    for _ in 0..headers {
+        line.clear();
+        reader.read_line(&mut line)?;
+        if let Some((name, value)) = line.trim_end_matches('\n').split_once('\t') {
+            response.headers.push((name.to_owned(), value.to_owned()));
+        }
+    }
+
+    reader.take(body_len).read_to_end(&mut response.body)?;
+    if response.body.len() as u64 != body_len {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "request process sent a truncated response",
+        ));
+    }
+
+    Ok(response)
+}
+
+/// Exit status of the child, or the signal that killed it plus 128.
+fn wait_for(pid: c_int) -> io::Result<i32> {
+    let mut status = 0;
+    loop {
+        // SAFETY: `status` outlives the call.
+        #[allow(unsafe_code)]
+        let result = unsafe { waitpid(pid, &mut status, 0) };
+        if result >= 0 {
+            break;
+        }
+
+        let error = io::Error::last_os_error();
+        if error.kind() != io::ErrorKind::Interrupted {
+            return Err(error);
+        }
+    }
+
+    Ok(match status & 0x7f {
+        0 => (status >> 8) & 0xff,
+        signal => 128 + signal,
+    })
+}
+
+/// Moves a finished download to `output_path` and gives it back to root, if
+/// the child left a regular file there.
+fn move_into_place(staging_path: &Path, output_path: &Path) -> io::Result<()> {
+    let invalid = || {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "download process left no regular file at '{}'",
+                staging_path.display()
+            ),
+        )
+    };
+
+    if !fs::symlink_metadata(staging_path)?.is_file() {
+        return Err(invalid());
+    }
+    fs::rename(staging_path, output_path)?;
+
+    // Checked again, it could have been replaced in the meantime.
+    let metadata = fs::symlink_metadata(output_path)?;
+    if !metadata.is_file() || metadata.nlink() != 1 {
+        fs::remove_file(output_path)?;
+        return Err(invalid());
+    }
+    change_owner(output_path, 0, 0)?;
+    fs::set_permissions(output_path, fs::Permissions::from_mode(0o644))?;
+    stats::record_download(metadata.len());
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_child_messages() {
+        let messages = [
+            ChildMessage::Started(String::from("htop.lod")),
+            ChildMessage::Progress(String::from("with\ttab.lod"), 512, Some(2048)),
+            ChildMessage::Progress(String::from("htop.lod"), 512, None),
+            ChildMessage::Finished(String::from("htop.lod")),
+            ChildMessage::Response {
+                index: 1,
+                status_code: 200,
+                headers: 2,
+                body_len: 4096,
+            },
+            ChildMessage::RequestFailed(0, String::from("connection\trefused")),
+        ];
+        for message in messages {
+            assert_eq!(ChildMessage::decode(&message.encode()), Some(message));
+        }
+
+        assert_eq!(
+            ChildMessage::decode(&ChildMessage::Failed(String::from("a\nb")).encode()),
+            Some(ChildMessage::Failed(String::from("a b")))
+        );
+        assert_eq!(ChildMessage::decode("progress\tmany\t\thtop.lod"), None);
+        assert_eq!(ChildMessage::decode("response\t1\t200\t2"), None);
+        assert_eq!(ChildMessage::decode("unknown"), None);
+    }
+
+    #[test]
+    fn test_forwarded_responses() {
+        let (parent_end, child_end) = UnixStream::pair().unwrap();
+        let child_events = ForwardEvents(Mutex::new(child_end));
+        child_events.download_started("htop.lod");
+        child_events.send_response(
+            1,
+            Ok(HttpResponse {
+                headers: vec![(String::from("Content-Length"), String::from("7"))],
+                body: b"binary\n".to_vec(),
+                status_code: 200,
+            }),
+        );
+        child_events.send_response(
+            0,
+            Err(io::Error::new(io::ErrorKind::Other, "connection refused")),
+        );
+        drop(child_events);
+
+        let mut report = read_child_messages(parent_end, &NoEvents).unwrap();
+        let response = report.responses.remove(&1).unwrap().unwrap();
+        assert_eq!(response.status_code, 200);
+        assert_eq!(response.get_header_value("Content-Length"), Some("7"));
+        assert_eq!(response.body, b"binary\n");
+        match report.responses.remove(&0).unwrap() {
+            Err(e) => assert_eq!(e.to_string(), "connection refused"),
+            Ok(_) => panic!("the request failed in the child"),
+        }
+        assert!(report.failure.is_none());
+    }
+
+    #[test]
+    fn test_find_user() {
+        let passwd = "root:x:0:0:root:/root:/bin/bash\n\
+                      lpm-download:x:990:985::/var/empty:/usr/bin/nologin\n";
+        assert_eq!(find_user(passwd, "lpm-download"), Some((990, 985)));
+        assert_eq!(find_user(passwd, "lpm"), None);
+
+        assert_eq!(
+            staging_path(Path::new("/var/cache/lpm/htop.lod")).unwrap(),
+            Path::new("/var/cache/lpm/.unprivileged/htop.lod")
+        );
+    }
+}
@@ -0,0 +1,96 @@
+use std::{io, os::unix::fs::MetadataExt, path::Path};
+
+/// Returns the disk space allocated for `path`, which is usually more than
+/// its length because of the filesystem block size.
+pub fn disk_usage(path: &Path) -> io::Result<u64> {
+    // `st_blocks` is always in 512 byte units.
+    Ok(path.symlink_metadata()?.blocks() * 512)
+}
+
+/// Parses sizes like `512`, `512K`, `2M` or `1G` into bytes.
+///
+/// Suffixes are case-insensitive and 1024 based; an optional trailing `B`
+/// (e.g. `2MB`) is accepted as well.
+pub fn parse_byte_size(input: &str) -> Option<u64> {
+    let input = input.trim().to_uppercase();
+    let input = input.strip_suffix('B').unwrap_or(&input);
+
+    let (number, multiplier) = match input.chars().last()? {
+        'K' => (&input[..input.len() - 1], 1024),
+        'M' => (&input[..input.len() - 1], 1024 * 1024),
+        'G' => (&input[..input.len() - 1], 1024 * 1024 * 1024),
+        _ => (input, 1),
+    };
+
+    number.parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+/// Formats `bytes` for humans, e.g. `512 B`, `1.5 KiB` or `2.0 MiB`.
+pub fn format_byte_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    format!("{size:.1} {}", UNITS[unit])
+}
+
+/// Same as `format_byte_size`, with a leading `+` or `-` sign.
+pub fn format_byte_size_change(bytes: i64) -> String {
+    let sign = if bytes < 0 { '-' } else { '+' };
+    format!("{sign}{}", format_byte_size(bytes.unsigned_abs()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestDir;
+
+    #[test]
+    fn test_parse_byte_size() {
+        assert_eq!(parse_byte_size("512"), Some(512));
+        assert_eq!(parse_byte_size("1k"), Some(1024));
+        assert_eq!(parse_byte_size("2M"), Some(2 * 1024 * 1024));
+        assert_eq!(parse_byte_size("2MB"), Some(2 * 1024 * 1024));
+        assert_eq!(parse_byte_size("1G"), Some(1024 * 1024 * 1024));
+
+        assert_eq!(parse_byte_size(""), None);
+        assert_eq!(parse_byte_size("M"), None);
+        assert_eq!(parse_byte_size("abc"), None);
+        assert_eq!(parse_byte_size("-1K"), None);
+    }
+
+    #[test]
+    fn test_disk_usage() {
+        let dir = TestDir::new("disk-usage");
+        let path = dir.join("file");
+        std::fs::write(&path, [0; 100]).unwrap();
+
+        let usage = disk_usage(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(usage % 512, 0);
+        assert!(disk_usage(&path).is_err());
+    }
+
+    #[test]
+    fn test_format_byte_size() {
+        assert_eq!(format_byte_size(0), "0 B");
+        assert_eq!(format_byte_size(512), "512 B");
+        assert_eq!(format_byte_size(1536), "1.5 KiB");
+        assert_eq!(format_byte_size(2 * 1024 * 1024), "2.0 MiB");
+        assert_eq!(format_byte_size(u64::MAX), "16777216.0 TiB");
+
+        assert_eq!(format_byte_size_change(1024), "+1.0 KiB");
+        assert_eq!(format_byte_size_change(-512), "-512 B");
+        assert_eq!(format_byte_size_change(0), "+0 B");
+    }
+}
@@ -0,0 +1,109 @@
+use elf::Elf;
+use std::{
+    collections::BTreeSet,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Shared-library dependencies of a package, generated from its ELF files.
+#[derive(Debug, Default, PartialEq)]
+pub struct Sonames {
+    /// Sonames required by the package and not provided by itself.
+    pub needed: Vec<String>,
+    pub provided: Vec<String>,
+}
+
+/// Scans the ELF files under `dir`(e.g. the `program` directory of a
+/// package that is being built) and collects their sonames.
+pub fn collect_sonames(dir: &Path) -> io::Result<Sonames> {
+    let mut elfs = Vec::new();
+    for path in walk_files(dir)? {
+        // Unparsable ELFs are not fatal, they just won't contribute any sonames.
+        match Elf::read(&path) {
+            Ok(Some(elf)) => elfs.push(elf),
+            Ok(None) => {}
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                logger::warning!("Skipping '{}': {e}", path.display());
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(merge_sonames(&elfs))
+}
+
+fn merge_sonames(elfs: &[Elf]) -> Sonames {
+    let provided: BTreeSet<&String> = elfs.iter().filter_map(|t| t.soname.as_ref()).collect();
+    let needed: BTreeSet<&String> = elfs
+        .iter()
+        .flat_map(|t| &t.needed)
+        .filter(|t| !provided.contains(t))
+        .collect();
+
+    Sonames {
+        needed: needed.into_iter().cloned().collect(),
+        provided: provided.into_iter().cloned().collect(),
+    }
+}
+
+/// Regular files under `dir`, symlinks are not followed.
+fn walk_files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                dirs.push(entry.path());
+            } else if file_type.is_file() {
+                files.push(entry.path());
+            }
+        }
+    }
+
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestDir;
+    use elf::{Class, Endian};
+
+    fn elf(soname: Option<&str>, needed: &[&str]) -> Elf {
+        Elf {
+            class: Class::Elf64,
+            endian: Endian::Little,
+            machine: 62,
+            needed: needed.iter().map(|t| t.to_string()).collect(),
+            soname: soname.map(String::from),
+            runpath: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_merge_sonames() {
+        let elfs = [
+            elf(None, &["libfoo.so.1", "libc.so.6"]),
+            elf(Some("libfoo.so.1"), &["libm.so.6", "libc.so.6"]),
+        ];
+
+        assert_eq!(
+            merge_sonames(&elfs),
+            Sonames {
+                needed: vec![String::from("libc.so.6"), String::from("libm.so.6")],
+                provided: vec![String::from("libfoo.so.1")],
+            }
+        );
+    }
+
+    #[test]
+    fn test_collect_sonames_skips_other_files() {
+        let dir = TestDir::new("soname");
+        fs::create_dir_all(dir.join("usr/bin")).unwrap();
+        fs::write(dir.join("usr/bin/script"), "#!/bin/sh\n").unwrap();
+
+        assert_eq!(collect_sonames(&dir).unwrap(), Sonames::default());
+    }
+}
@@ -0,0 +1,122 @@
+//! Counters of the work done during a transaction(downloads, file copies and
+//! removals), reported and recorded once it's finished.
+//!
+//! The counters are process wide like the ones of `interrupt`, the steps that
+//! do the work bump them and `take` collects them.
+
+use crate::size::format_byte_size;
+
+use json::{to_json_object, Serialize};
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+static DOWNLOADED_BYTES: AtomicU64 = AtomicU64::new(0);
+static WRITTEN_BYTES: AtomicU64 = AtomicU64::new(0);
+static FILES_INSTALLED: AtomicU64 = AtomicU64::new(0);
+static FILES_REMOVED: AtomicU64 = AtomicU64::new(0);
+static CACHE_HITS: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct TransactionStats {
+    pub elapsed: Duration,
+    pub downloaded_bytes: u64,
+    pub written_bytes: u64,
+    pub files_installed: u64,
+    pub files_removed: u64,
+    /// Archives that were already downloaded.
+    pub cache_hits: u64,
+}
+
+impl TransactionStats {
+    /// One line summary for the end of the operation.
+    pub fn summary(&self) -> String {
+        format!(
+            "Took {:.1}s: {} downloaded, {} written, {} files installed, {} files removed, {} cache hits.",
+            self.elapsed.as_secs_f64(),
+            format_byte_size(self.downloaded_bytes),
+            format_byte_size(self.written_bytes),
+            self.files_installed,
+            self.files_removed,
+            self.cache_hits
+        )
+    }
+}
+
+impl Serialize for TransactionStats {
+    fn to_json(&self) -> String {
+        to_json_object(&[
+            ("elapsed_ms", (self.elapsed.as_millis() as u64).to_json()),
+            ("downloaded_bytes", self.downloaded_bytes.to_json()),
+            ("written_bytes", self.written_bytes.to_json()),
+            ("files_installed", self.files_installed.to_json()),
+            ("files_removed", self.files_removed.to_json()),
+            ("cache_hits", self.cache_hits.to_json()),
+        ])
+    }
+}
+
+pub fn record_download(bytes: u64) {
+    DOWNLOADED_BYTES.fetch_add(bytes, Ordering::Relaxed);
+}
+
+/// A file of `bytes` copied into the system.
+pub fn record_installed_file(bytes: u64) {
+    FILES_INSTALLED.fetch_add(1, Ordering::Relaxed);
+    WRITTEN_BYTES.fetch_add(bytes, Ordering::Relaxed);
+}
+
+pub fn record_removed_file() {
+    FILES_REMOVED.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn record_cache_hit() {
+    CACHE_HITS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Counters since the previous call, which are reset.
+pub fn take(elapsed: Duration) -> TransactionStats {
+    TransactionStats {
+        elapsed,
+        downloaded_bytes: DOWNLOADED_BYTES.swap(0, Ordering::Relaxed),
+        written_bytes: WRITTEN_BYTES.swap(0, Ordering::Relaxed),
+        files_installed: FILES_INSTALLED.swap(0, Ordering::Relaxed),
+        files_removed: FILES_REMOVED.swap(0, Ordering::Relaxed),
+        cache_hits: CACHE_HITS.swap(0, Ordering::Relaxed),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_take() {
+        take(Duration::ZERO);
+
+        record_download(2048);
+        record_installed_file(100);
+        record_installed_file(24);
+        record_removed_file();
+        record_cache_hit();
+
+        let stats = take(Duration::from_millis(1500));
+        assert_eq!(
+            stats,
+            TransactionStats {
+                elapsed: Duration::from_millis(1500),
+                downloaded_bytes: 2048,
+                written_bytes: 124,
+                files_installed: 2,
+                files_removed: 1,
+                cache_hits: 1,
+            }
+        );
+        assert_eq!(
+            stats.summary(),
+            "Took 1.5s: 2.0 KiB downloaded, 124 B written, 2 files installed, 1 files removed, 1 cache hits."
+        );
+        assert_eq!(take(Duration::ZERO), TransactionStats::default());
+    }
+}
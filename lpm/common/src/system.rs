@@ -1,8 +1,8 @@
 use super::ParserTasks;
 use crate::version::VersionStruct;
 
-use json::{Deserialize, JsonValue};
-use std::fs;
+use json::{to_json_object, Deserialize, JsonValue, Serialize};
+use std::{fs, path::Path};
 
 #[derive(Debug, Clone)]
 pub struct System {
@@ -10,6 +10,18 @@ pub struct System {
     pub min_supported_lpm_version: VersionStruct,
 }
 
+impl Serialize for System {
+    fn to_json(&self) -> String {
+        to_json_object(&[
+            ("builder_version", self.builder_version.to_json()),
+            (
+                "min_supported_lpm_version",
+                self.min_supported_lpm_version.to_json(),
+            ),
+        ])
+    }
+}
+
 impl json::Deserialize for System {
     type Error = String;
 
@@ -39,9 +51,9 @@ impl json::Deserialize for System {
 }
 
 impl ParserTasks for System {
-    fn deserialize(path: &str) -> Self {
+    fn deserialize(path: &Path) -> Self {
         let data_as_str = fs::read_to_string(path).unwrap_or_else(|_| {
-            panic!("{} could not found.", path);
+            panic!("{} could not found.", path.display());
         });
 
         let json = json::Json::new(&data_as_str)
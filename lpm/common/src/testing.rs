@@ -0,0 +1,65 @@
+//! Helpers shared by the tests of the lpm crates, built for the others with
+//! the `test-utils` feature.
+
+use std::{
+    fs,
+    ops::Deref,
+    path::{Path, PathBuf},
+};
+
+/// Empty directory of a test under the system temporary directory, removed
+/// once it goes out of scope, even if the test panics.
+pub struct TestDir(PathBuf);
+
+impl TestDir {
+    /// Creates `lpm-<name>-<pid>`, so `name` must be unique among the tests
+    /// of the crate, while the test binaries of different runs don't collide.
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("lpm-{name}-{}", std::process::id()));
+        // Left behind by an aborted run that had the same pid.
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+
+        Self(path)
+    }
+}
+
+impl Deref for TestDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TestDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dir_is_removed_on_panic() {
+        let path = std::panic::catch_unwind(|| {
+            let dir = TestDir::new("testing");
+            fs::write(dir.join("file"), "").unwrap();
+            std::panic::panic_any(dir.to_path_buf());
+        })
+        .unwrap_err()
+        .downcast::<PathBuf>()
+        .unwrap();
+
+        assert!(path.ends_with(format!("lpm-testing-{}", std::process::id())));
+        assert!(!path.exists());
+    }
+}
@@ -1,6 +1,6 @@
 use crate::de_required_field;
 
-use json::JsonValue;
+use json::{to_json_object, JsonValue, Serialize};
 use std::cmp::Ordering;
 
 #[derive(Clone, Debug, Default)]
@@ -75,6 +75,19 @@ impl VersionStruct {
     }
 }
 
+impl Serialize for VersionStruct {
+    fn to_json(&self) -> String {
+        to_json_object(&[
+            ("readable_format", self.readable_format.to_json()),
+            ("major", u32::from(self.major).to_json()),
+            ("minor", u32::from(self.minor).to_json()),
+            ("patch", u32::from(self.patch).to_json()),
+            ("tag", self.tag.to_json()),
+            ("condition", self.condition.to_str_operator().to_json()),
+        ])
+    }
+}
+
 impl json::Deserialize for VersionStruct {
     type Error = String;
 
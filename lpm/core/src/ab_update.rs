@@ -0,0 +1,431 @@
+//! A/B updates of image-based systems, laid out by `ab_update` in the config.
+//!
+//! The system has two roots, slot `a` and `b`, and the booted one is named
+//! by `lpm.slot=` on the kernel command line. `lpm --update --ab` downloads
+//! the latest versions of the packages explicitly installed on the booted
+//! one, then erases the other root and installs them into it, copies its
+//! kernel to the ESP and adds a boot entry for it.
+//!
+//! The entries follow the Boot Loader Specification with boot counting, e.g.
+//! `lpm-b+3.conf`. The newest one is booted by default, and once its tries
+//! are used up without the boot being blessed, the bootloader sorts it last
+//! and falls back to the other slot. `lpm-ab-confirm.service` runs `lpm
+//! --update --ab-confirm` after `boot-complete.target`, which blesses the
+//! entry of the upgraded slot, or removes it if the other slot got booted
+//! instead. The slot waiting for its first boot is kept in `STATE_FILE` on
+//! the ESP, since it's the only place both slots share.
+
+use crate::{
+    api::installed_packages,
+    auto_update::SYSTEMD_UNIT_DIR,
+    filter::PathFilter,
+    install::{fetch_from_repository, install_from_repository},
+    notify::run_transaction,
+    Ctx, Operation,
+};
+
+use common::config::{AbUpdateLayout, CONFIG_PATH};
+use ehandle::{lpm::LpmError, MainError};
+use json::{to_json_object, Json, Serialize};
+use logger::{info, success, warning};
+use std::{
+    collections::HashSet,
+    fs, io,
+    os::unix::fs::symlink,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const SLOTS: [&str; 2] = ["a", "b"];
+const STATE_FILE: &str = "lpm/ab-state.json";
+const ENTRIES_DIR: &str = "loader/entries";
+const UNIT_NAME: &str = "lpm-ab-confirm.service";
+
+/// Slot layout at the time of the last `lpm --update --ab`.
+#[derive(Debug, Clone, PartialEq)]
+struct AbState {
+    /// Slot that was booted while the other one was upgraded.
+    active: String,
+    /// Upgraded slot waiting for its first successful boot.
+    pending: Option<String>,
+    built_at: i64,
+}
+
+impl Serialize for AbState {
+    fn to_json(&self) -> String {
+        to_json_object(&[
+            ("active", self.active.to_json()),
+            ("pending", self.pending.to_json()),
+            ("built_at", self.built_at.to_json()),
+        ])
+    }
+}
+
+fn parse_state(content: &str) -> Result<AbState, String> {
+    let state = Json::new(content).parse()?;
+    let active = state["active"]
+        .to_string()
+        .ok_or("'active' is missing from the A/B state.")?;
+
+    Ok(AbState {
+        active,
+        pending: state["pending"].to_string(),
+        built_at: state["built_at"].as_i64().unwrap_or_default(),
+    })
+}
+
+fn read_state(esp: &Path) -> io::Result<Option<AbState>> {
+    match fs::read_to_string(esp.join(STATE_FILE)) {
+        Ok(content) => parse_state(&content)
+            .map(Some)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn write_state(esp: &Path, state: &AbState) -> io::Result<()> {
+    let path = esp.join(STATE_FILE);
+    fs::create_dir_all(path.parent().unwrap())?;
+
+    let tmp_path = path.with_extension("json.tmp");
+    fs::write(&tmp_path, state.to_json())?;
+    fs::rename(tmp_path, path)
+}
+
+/// Slot named by `lpm.slot=` in the kernel command line.
+fn booted_slot(cmdline: &str) -> Option<&str> {
+    cmdline
+        .split_whitespace()
+        .find_map(|t| t.strip_prefix("lpm.slot="))
+        .filter(|t| SLOTS.contains(t))
+}
+
+fn read_booted_slot() -> Result<String, LpmError<MainError>> {
+    let cmdline = fs::read_to_string("/proc/cmdline")?;
+    match booted_slot(&cmdline) {
+        Some(slot) => Ok(slot.to_owned()),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "The system isn't booted from an A/B slot, 'lpm.slot' is missing from the kernel command line.",
+        ))?,
+    }
+}
+
+/// Slot of a boot entry file name, `lpm-b+2-1.conf` belongs to `b`.
+fn entry_slot(file_name: &str) -> Option<&str> {
+    let name = file_name.strip_prefix("lpm-")?.strip_suffix(".conf")?;
+    let slot = name.split_once('+').map_or(name, |(slot, _)| slot);
+    SLOTS.contains(&slot).then_some(slot)
+}
+
+fn boot_entry(slot: &str, built_at: i64, options: &str) -> String {
+    // Entries of the same sort key are sorted by version, newest first.
+    format!(
+        "title lpm (slot {slot})
+sort-key lpm
+version {built_at}
+linux /lpm/{slot}/vmlinuz
+initrd /lpm/{slot}/initrd.img
+options {} lpm.slot={slot}
+",
+        options.replace("{slot}", slot)
+    )
+}
+
+fn entry_paths(esp: &Path, slot: &str) -> io::Result<Vec<PathBuf>> {
+    let entries_dir = esp.join(ENTRIES_DIR);
+    if !entries_dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(entries_dir)? {
+        let entry = entry?;
+        if entry_slot(&entry.file_name().to_string_lossy()) == Some(slot) {
+            paths.push(entry.path());
+        }
+    }
+
+    Ok(paths)
+}
+
+/// Drops the boot counter from the entry of `slot`, so it's no longer
+/// falling back. `systemd-bless-boot` may have done it already.
+fn bless_entry(esp: &Path, slot: &str) -> io::Result<()> {
+    let blessed_path = esp.join(ENTRIES_DIR).join(format!("lpm-{slot}.conf"));
+    for path in entry_paths(esp, slot)? {
+        if path != blessed_path {
+            fs::rename(path, &blessed_path)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn remove_entries(esp: &Path, slot: &str) -> io::Result<()> {
+    for path in entry_paths(esp, slot)? {
+        fs::remove_file(path)?;
+    }
+
+    Ok(())
+}
+
+/// Removes everything in `root`, but not `root` itself which is usually a
+/// mount point.
+fn clear_root(root: &Path) -> io::Result<()> {
+    if fs::canonicalize(root)? == Path::new("/") {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Refusing to erase '{}', it's the running root.",
+                root.display()
+            ),
+        ));
+    }
+
+    for entry in fs::read_dir(root)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            fs::remove_dir_all(entry.path())?;
+        } else {
+            fs::remove_file(entry.path())?;
+        }
+    }
+
+    Ok(())
+}
+
+fn unit(lpm_path: &Path) -> String {
+    format!(
+        "[Unit]
+Description=Confirm the A/B update slot booted by lpm
+Requires=boot-complete.target
+After=boot-complete.target
+
+[Service]
+Type=oneshot
+ExecStart={} --update --ab-confirm
+
+[Install]
+WantedBy=multi-user.target
+",
+        lpm_path.display()
+    )
+}
+
+/// Same as `systemctl enable` for the root at `root`, which isn't the
+/// running one for the upgraded slot.
+fn install_unit(root: &Path) -> io::Result<()> {
+    let unit_dir = root.join(SYSTEMD_UNIT_DIR.trim_start_matches('/'));
+    let unit_path = unit_dir.join(UNIT_NAME);
+    fs::create_dir_all(&unit_dir)?;
+    fs::write(unit_path, unit(&std::env::current_exe()?))?;
+
+    let wants_dir = unit_dir.join("multi-user.target.wants");
+    fs::create_dir_all(&wants_dir)?;
+    let wants_link = wants_dir.join(UNIT_NAME);
+    if fs::symlink_metadata(&wants_link).is_err() {
+        symlink(Path::new(SYSTEMD_UNIT_DIR).join(UNIT_NAME), wants_link)?;
+    }
+
+    Ok(())
+}
+
+/// Copies the kernel and initrd of the root of `slot` to the ESP, where its
+/// boot entry expects them.
+fn install_kernel(layout: &AbUpdateLayout, slot: &str, root: &Path) -> io::Result<()> {
+    let kernel_dir = Path::new(&layout.esp).join("lpm").join(slot);
+    fs::create_dir_all(&kernel_dir)?;
+
+    for (source, file_name) in [(&layout.kernel, "vmlinuz"), (&layout.initrd, "initrd.img")] {
+        let source = root.join(source.trim_start_matches('/'));
+        if !source.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "'{}' doesn't exist, no package of the slot provides it.",
+                    source.display()
+                ),
+            ));
+        }
+
+        fs::copy(source, kernel_dir.join(file_name))?;
+    }
+
+    Ok(())
+}
+
+fn layout(ctx: &Ctx) -> Option<AbUpdateLayout> {
+    let layout = ctx.config.ab_update.clone();
+    if layout.is_none() {
+        info!("A/B updates are disabled, set 'ab_update' in {CONFIG_PATH} to enable them.");
+    }
+
+    layout
+}
+
+/// Rebuilds the slot that isn't booted with the latest versions of the
+/// packages explicitly installed on the booted one, and makes it the default
+/// boot entry.
+pub fn assemble_ab_update(mut ctx: Ctx) -> Result<(), LpmError<MainError>> {
+    let Some(layout) = layout(&ctx) else {
+        return Ok(());
+    };
+
+    let active = read_booted_slot()?;
+    let target_index = usize::from(active == SLOTS[0]);
+    let (target, target_root) = (
+        SLOTS[target_index],
+        PathBuf::from(&layout.roots[target_index]),
+    );
+    let esp = Path::new(&layout.esp);
+
+    let pkg_names: Vec<String> = installed_packages(&ctx)?
+        .into_iter()
+        .filter(|pkg| !pkg.is_dependency)
+        .map(|pkg| pkg.name)
+        .collect();
+
+    warning!(
+        "Slot {target} at '{}' will be erased and rebuilt with the latest versions of {} packages.",
+        target_root.display(),
+        pkg_names.len()
+    );
+    common::ctx_confirmation_check!(ctx);
+    common::interrupt::install_handlers();
+
+    // Everything is downloaded while the slot is still intact, so that e.g. a
+    // network failure doesn't leave it erased.
+    let pkg_names: HashSet<&str> = pkg_names.iter().map(String::as_str).collect();
+    fetch_from_repository(&ctx, &pkg_names)?;
+
+    // The slot is inconsistent from here on, so it must not be booted.
+    remove_entries(esp, target)?;
+    if read_state(esp)?.and_then(|t| t.pending).as_deref() == Some(target) {
+        fs::remove_file(esp.join(STATE_FILE))?;
+    }
+    clear_root(&target_root)?;
+
+    // Already confirmed, and the erased slot can't be left at a printed plan.
+    ctx.force_yes = true;
+    ctx.plan_format = None;
+    ctx.set_install_root(&target_root, None)?;
+
+    let filter = PathFilter::new(&ctx.config.no_extract)?;
+    run_transaction(&ctx, Operation::Update, |ctx| {
+        install_from_repository(ctx, &pkg_names, &filter, false)
+    })?;
+
+    install_kernel(&layout, target, &target_root)?;
+    install_unit(Path::new("/"))?;
+    install_unit(&target_root)?;
+
+    let built_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|t| t.as_secs() as i64)
+        .unwrap_or_default();
+    write_state(
+        esp,
+        &AbState {
+            active: active.clone(),
+            pending: Some(target.to_owned()),
+            built_at,
+        },
+    )?;
+
+    // The entry goes last, the slot isn't booted without it.
+    let entries_dir = esp.join(ENTRIES_DIR);
+    fs::create_dir_all(&entries_dir)?;
+    fs::write(
+        entries_dir.join(format!("lpm-{target}+{}.conf", layout.tries)),
+        boot_entry(target, built_at, &layout.options),
+    )?;
+
+    success!(
+        "Slot {target} is ready, reboot to switch to it. Slot {active} is booted instead if it fails to boot {} times.",
+        layout.tries
+    );
+
+    Ok(())
+}
+
+/// Settles the outcome of the last `lpm --update --ab` once the system has
+/// booted, run by `lpm-ab-confirm.service`.
+pub fn confirm_ab_boot(ctx: &Ctx) -> Result<(), LpmError<MainError>> {
+    let Some(layout) = layout(ctx) else {
+        return Ok(());
+    };
+
+    let esp = Path::new(&layout.esp);
+    let Some(mut state) = read_state(esp)? else {
+        info!("No A/B update is waiting for confirmation.");
+        return Ok(());
+    };
+    let Some(pending) = state.pending.clone() else {
+        info!("No A/B update is waiting for confirmation.");
+        return Ok(());
+    };
+
+    let booted = read_booted_slot()?;
+    if booted == pending {
+        bless_entry(esp, &pending)?;
+        success!("Slot {pending} booted successfully, it's the active one now.");
+        state.active = pending;
+    } else {
+        // The upgraded slot ran out of tries, or was never booted.
+        remove_entries(esp, &pending)?;
+        warning!("Slot {pending} failed to boot, rolled back to slot {booted}.");
+        state.active = booted;
+    }
+
+    state.pending = None;
+    write_state(esp, &state)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_boot_entries() {
+        assert_eq!(booted_slot("quiet lpm.slot=b rw"), Some("b"));
+        assert_eq!(booted_slot("quiet lpm.slot=c"), None);
+        assert_eq!(booted_slot("quiet rw"), None);
+
+        assert_eq!(entry_slot("lpm-a.conf"), Some("a"));
+        assert_eq!(entry_slot("lpm-b+3.conf"), Some("b"));
+        assert_eq!(entry_slot("lpm-b+0-3.conf"), Some("b"));
+        assert_eq!(entry_slot("lpm-c+3.conf"), None);
+        assert_eq!(entry_slot("arch.conf"), None);
+
+        let entry = boot_entry(
+            "b",
+            1700000000,
+            "root=LABEL=system rootflags=subvol=@{slot}",
+        );
+        assert!(entry.contains("\nversion 1700000000\n"));
+        assert!(entry.contains("\nlinux /lpm/b/vmlinuz\n"));
+        assert!(entry.ends_with("\noptions root=LABEL=system rootflags=subvol=@b lpm.slot=b\n"));
+    }
+
+    #[test]
+    fn test_state() {
+        let state = AbState {
+            active: String::from("a"),
+            pending: Some(String::from("b")),
+            built_at: 1700000000,
+        };
+        assert_eq!(parse_state(&state.to_json()).unwrap(), state);
+
+        let state = AbState {
+            pending: None,
+            ..state
+        };
+        assert_eq!(parse_state(&state.to_json()).unwrap(), state);
+
+        assert!(parse_state("{}").is_err());
+    }
+}
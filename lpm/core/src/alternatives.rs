@@ -0,0 +1,166 @@
+use crate::Ctx;
+
+use common::{meta::AlternativeStruct, pkg_path::PkgPath};
+use db::{get_alternatives, get_selected_alternative, select_alternative, Alternative};
+use ehandle::{lpm::LpmError, pkg::PackageErrorKind, ErrorCommons, MainError};
+use logger::{info, warning};
+use min_sqlite3_sys::prelude::Database;
+use std::{fs, io, os::unix, path::Path};
+
+/// Points the links of the given (name, link) alternatives to their active
+/// providers, and removes the links of the ones without any providers left.
+pub(crate) fn refresh_alternatives(
+    root: &Path,
+    core_db: &Database,
+    alternatives: &[(String, String)],
+) -> Result<(), LpmError<MainError>> {
+    for (name, link) in alternatives {
+        let providers = get_alternatives(core_db, Some(name))?;
+        let selected = get_selected_alternative(core_db, name)?;
+
+        let link_path = PkgPath::new(link)?.under(root);
+        match fs::symlink_metadata(&link_path) {
+            Ok(metadata) if !metadata.file_type().is_symlink() => {
+                warning!(
+                    "'{}' is not a symlink, leaving alternative '{name}' untouched.",
+                    link_path.display()
+                );
+                continue;
+            }
+            Ok(_) => fs::remove_file(&link_path)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e)?,
+        }
+
+        if let Some(active) = active_provider(&providers, selected.as_deref()) {
+            if let Some(parent) = link_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+
+            info!(
+                "Linking alternative '{name}' {} -> {}",
+                active.link, active.path
+            );
+            unix::fs::symlink(&active.path, &link_path)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// (name, link) pairs of the alternatives, as `refresh_alternatives` takes them.
+pub(crate) fn alternative_links(alternatives: &[AlternativeStruct]) -> Vec<(String, String)> {
+    alternatives
+        .iter()
+        .map(|t| (t.name.clone(), t.link.clone()))
+        .collect()
+}
+
+/// The provider selected by the user if it's still installed, otherwise the one
+/// with the highest priority. `providers` are sorted by descending priority.
+fn active_provider<'a>(
+    providers: &'a [Alternative],
+    selected: Option<&str>,
+) -> Option<&'a Alternative> {
+    selected
+        .and_then(|path| providers.iter().find(|t| t.path == path))
+        .or_else(|| providers.first())
+}
+
+pub fn print_alternatives(ctx: Ctx, name: Option<&str>) -> Result<(), LpmError<MainError>> {
+    let core_db = ctx.pkgs_db();
+    let alternatives = get_alternatives(core_db, name)?;
+
+    if let Some(name) = name {
+        if alternatives.is_empty() {
+            return Err(PackageErrorKind::UnknownAlternative(name.to_owned()).to_lpm_err())?;
+        }
+    }
+
+    let mut names: Vec<&str> = alternatives.iter().map(|t| t.name.as_str()).collect();
+    names.dedup();
+
+    for name in names {
+        let providers: Vec<Alternative> = alternatives
+            .iter()
+            .filter(|t| t.name == name)
+            .cloned()
+            .collect();
+        let selected = get_selected_alternative(core_db, name)?;
+        let active = active_provider(&providers, selected.as_deref());
+        let mode = if selected.is_some() { "manual" } else { "auto" };
+
+        println!("{name} ({}, {mode})", providers[0].link);
+        for provider in &providers {
+            let marker = if Some(provider) == active { '*' } else { ' ' };
+            println!(
+                "  {marker} {}  priority {}  ({})",
+                provider.path, provider.priority, provider.package
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Makes `path` the provider of `name`, or restores the automatic selection if
+/// `path` is `None`.
+pub fn set_alternative(
+    ctx: Ctx,
+    name: &str,
+    path: Option<&str>,
+) -> Result<(), LpmError<MainError>> {
+    let core_db = ctx.pkgs_db();
+    let providers = get_alternatives(core_db, Some(name))?;
+
+    if providers.is_empty() {
+        return Err(PackageErrorKind::UnknownAlternative(name.to_owned()).to_lpm_err())?;
+    }
+
+    if let Some(path) = path {
+        if !providers.iter().any(|t| t.path == path) {
+            return Err(
+                PackageErrorKind::UnknownAlternative(format!("{name} -> {path}")).to_lpm_err(),
+            )?;
+        }
+    }
+
+    select_alternative(core_db, name, path)?;
+    refresh_alternatives(
+        ctx.root_path(),
+        core_db,
+        &[(name.to_owned(), providers[0].link.clone())],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn provider(path: &str, priority: i64) -> Alternative {
+        Alternative {
+            name: String::from("vi"),
+            link: String::from("/usr/bin/vi"),
+            path: path.to_owned(),
+            priority,
+            package: String::from("vim"),
+        }
+    }
+
+    #[test]
+    fn test_active_provider() {
+        let providers = [provider("/usr/bin/vim", 50), provider("/usr/bin/nvi", 20)];
+
+        assert_eq!(active_provider(&providers, None), Some(&providers[0]));
+        assert_eq!(
+            active_provider(&providers, Some("/usr/bin/nvi")),
+            Some(&providers[1])
+        );
+        // Falls back to the priorities once the selected provider is gone.
+        assert_eq!(
+            active_provider(&providers, Some("/usr/bin/elvis")),
+            Some(&providers[0])
+        );
+        assert_eq!(active_provider(&[], None), None);
+    }
+}
@@ -0,0 +1,382 @@
+//! Rust API for embedding lpm into other programs(installers, provisioning
+//! tools, GUIs), without going through the CLI or parsing its logs.
+//!
+//! ```no_run
+//! # fn main() -> Result<(), ehandle::lpm::LpmError<ehandle::MainError>> {
+//! let mut lpm = core::Lpm::new()?;
+//! lpm.set_event_callback(|event| println!("{event:?}"));
+//!
+//! let changes = lpm.install(&["htop"])?;
+//! for pkg in &changes.installed {
+//!     println!("{} {}", pkg.name, pkg.version);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::{
+    delete_packages,
+    install::PkgInstallTasks,
+    install_package,
+    notify::run_transaction,
+    repository::{is_index_trusted, search_pkg_indexes},
+    update::{find_available_updates, find_pkg_update},
+    update_pkg_from_repository, update_pkgs_from_repository, Ctx, Initiator,
+};
+
+use cli_parser::{DeleteArgs, InstallArgs};
+use common::event::EventSink;
+use common::meta::PackageKind;
+use common::pkg::{PkgDataFromDb, PkgDataFromFs, PkgToQuery};
+use db::pkg::DbOpsForInstalledPkg;
+use ehandle::{lpm::LpmError, pkg::PackageErrorKind, ErrorCommons, MainError};
+use std::{collections::HashSet, sync::Arc};
+
+/// An installed package.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PackageInfo {
+    pub name: String,
+    pub version: String,
+    pub arch: String,
+    pub slot: Option<String>,
+    pub kind: Option<PackageKind>,
+    pub installed_size: i64,
+    /// Installed as a dependency of another package.
+    pub is_dependency: bool,
+}
+
+impl From<&PkgDataFromDb> for PackageInfo {
+    fn from(pkg: &PkgDataFromDb) -> Self {
+        let meta = &pkg.meta_fields.meta;
+        Self {
+            name: meta.name.clone(),
+            version: meta.version.readable_format.clone(),
+            arch: meta.arch.clone(),
+            slot: meta.slot.clone(),
+            kind: meta.kind,
+            installed_size: meta.installed_size,
+            is_dependency: pkg.group_id != meta.get_group_id(),
+        }
+    }
+}
+
+impl PackageInfo {
+    /// Installations of the same package share the key across versions.
+    fn key(&self) -> (&str, &str, Option<&str>) {
+        (&self.name, &self.arch, self.slot.as_deref())
+    }
+}
+
+/// A package that can be installed from the repositories.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AvailablePackage {
+    pub name: String,
+    pub version: String,
+    pub repository: String,
+    /// Size of the `.lod` archive in bytes, if the index provides it.
+    pub archive_size: Option<i64>,
+    /// Installed size in bytes, if the index provides it.
+    pub installed_size: Option<i64>,
+    /// Kind of the package, if the index provides it.
+    pub kind: Option<PackageKind>,
+}
+
+/// What an operation changed on the system.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Changes {
+    pub installed: Vec<PackageInfo>,
+    /// Old and new installation of each updated package.
+    pub updated: Vec<(PackageInfo, PackageInfo)>,
+    pub deleted: Vec<PackageInfo>,
+}
+
+impl Changes {
+    pub fn is_empty(&self) -> bool {
+        self.installed.is_empty() && self.updated.is_empty() && self.deleted.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operation {
+    Install,
+    Update,
+    Delete,
+}
+
+impl Operation {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Operation::Install => "install",
+            Operation::Update => "update",
+            Operation::Delete => "delete",
+        }
+    }
+}
+
+/// Progress of the operations, passed to the callback set with
+/// `Lpm::set_event_callback`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    Started(Operation),
+    Installed(PackageInfo),
+    Updated { from: PackageInfo, to: PackageInfo },
+    Deleted(PackageInfo),
+    Finished(Operation),
+}
+
+pub type EventCallback = Box<dyn Fn(&Event)>;
+
+pub struct Lpm {
+    ctx: Ctx,
+    on_event: Option<EventCallback>,
+}
+
+impl Lpm {
+    /// Connects to the package database of the running system.
+    pub fn new() -> Result<Self, LpmError<MainError>> {
+        Ok(Self::from_ctx(Ctx::new()?))
+    }
+
+    /// Uses the settings and connections of `ctx`. Confirmation prompts are
+    /// always accepted, since nobody is there to answer them.
+    pub fn from_ctx(mut ctx: Ctx) -> Self {
+        ctx.force_yes = true;
+        Self {
+            ctx,
+            on_event: None,
+        }
+    }
+
+    pub fn set_event_callback(&mut self, callback: impl Fn(&Event) + 'static) {
+        self.on_event = Some(Box::new(callback));
+    }
+
+    /// Receives the progress within the operations(downloads, scripts,
+    /// files), unlike the callback which only sees their results.
+    pub fn set_event_sink(&mut self, sink: Arc<dyn EventSink>) {
+        self.ctx.events = sink;
+    }
+
+    pub(crate) fn set_initiator(&mut self, initiator: Initiator) {
+        self.ctx.initiator = initiator;
+    }
+
+    /// Limits the following installs and updates to the packages of trusted
+    /// repositories, see `is_from_trusted_repositories`.
+    pub(crate) fn set_trusted_repositories_only(&mut self, trusted_repositories_only: bool) {
+        self.ctx.trusted_repositories_only = trusted_repositories_only;
+    }
+
+    /// Whether installing or updating `packages`(as `install` and `update`
+    /// would) only takes packages, dependencies included, from repositories
+    /// that require signatures and whose indexes are signed.
+    pub(crate) fn is_from_trusted_repositories(
+        &self,
+        operation: Operation,
+        packages: &[&str],
+    ) -> Result<bool, LpmError<MainError>> {
+        let ctx = &self.ctx;
+        let mut indexes = Vec::new();
+        match operation {
+            Operation::Install => {
+                for pkg_name in packages {
+                    let pkg_to_query = PkgToQuery::parse(pkg_name).ok_or_else(|| {
+                        PackageErrorKind::InvalidPackageName(pkg_name.to_string()).to_lpm_err()
+                    })?;
+                    indexes.extend(PkgDataFromFs::get_pkg_stack(
+                        &ctx.core_db,
+                        pkg_to_query,
+                        ctx.target_arch(),
+                    )?);
+                }
+            }
+            Operation::Update if packages.is_empty() => indexes = find_available_updates(ctx)?.1,
+            Operation::Update => {
+                for pkg_name in packages {
+                    indexes.push(find_pkg_update(ctx, pkg_name, None)?.1);
+                }
+            }
+            Operation::Delete => return Ok(false),
+        }
+
+        for index in &indexes {
+            if !is_index_trusted(&ctx.core_db, index)? {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Installs `packages` and their dependencies from the repositories.
+    pub fn install(&mut self, packages: &[&str]) -> Result<Changes, LpmError<MainError>> {
+        let args = InstallArgs {
+            packages: packages.iter().copied().collect(),
+            ..Default::default()
+        };
+
+        self.track(Operation::Install, |ctx| install_package(ctx, &args))
+    }
+
+    /// Installs the `.lod` package at `path`.
+    pub fn install_local(&mut self, path: &str) -> Result<Changes, LpmError<MainError>> {
+        let args = InstallArgs {
+            packages: HashSet::from([path]),
+            from_local_package: true,
+            ..Default::default()
+        };
+
+        self.track(Operation::Install, |ctx| install_package(ctx, &args))
+    }
+
+    /// Updates `packages` from the repositories, all of the installed ones
+    /// if it's empty.
+    pub fn update(&mut self, packages: &[&str]) -> Result<Changes, LpmError<MainError>> {
+        self.track(Operation::Update, |ctx| {
+            if packages.is_empty() {
+                return update_pkgs_from_repository(ctx);
+            }
+
+            for pkg_name in packages {
+                update_pkg_from_repository(ctx, pkg_name)?;
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Deletes `packages`, keeping the dependencies that were installed along
+    /// with them, as `lpm --delete` does without `--recursive`.
+    pub fn delete(&mut self, packages: &[&str]) -> Result<Changes, LpmError<MainError>> {
+        let args = DeleteArgs {
+            packages: packages.iter().copied().collect(),
+            ..Default::default()
+        };
+
+        self.track(Operation::Delete, |ctx| delete_packages(ctx, &args))
+    }
+
+    /// Installed packages, including dependencies.
+    pub fn list(&self) -> Result<Vec<PackageInfo>, LpmError<MainError>> {
+        installed_packages(&self.ctx)
+    }
+
+    /// Packages in the repositories whose name contains `pattern`.
+    pub fn search(&self, pattern: &str) -> Result<Vec<AvailablePackage>, LpmError<MainError>> {
+        let indexes = search_pkg_indexes(&self.ctx.core_db, pattern, self.ctx.target_arch())?;
+        Ok(indexes
+            .into_iter()
+            .map(|index| AvailablePackage {
+                name: index.name,
+                version: index.version.readable_format,
+                repository: index.repository_name,
+                archive_size: index.archive_size,
+                installed_size: index.installed_size,
+                kind: index.kind,
+            })
+            .collect())
+    }
+
+    /// Runs `f` as a transaction, then reports what it changed.
+    fn track(
+        &mut self,
+        operation: Operation,
+        f: impl FnOnce(&mut Ctx) -> Result<(), LpmError<MainError>>,
+    ) -> Result<Changes, LpmError<MainError>> {
+        self.emit(Event::Started(operation));
+
+        let changes = run_transaction(&mut self.ctx, operation, |ctx| f(ctx))?;
+
+        for pkg in &changes.installed {
+            self.emit(Event::Installed(pkg.clone()));
+        }
+        for (from, to) in &changes.updated {
+            self.emit(Event::Updated {
+                from: from.clone(),
+                to: to.clone(),
+            });
+        }
+        for pkg in &changes.deleted {
+            self.emit(Event::Deleted(pkg.clone()));
+        }
+
+        self.emit(Event::Finished(operation));
+
+        Ok(changes)
+    }
+
+    fn emit(&self, event: Event) {
+        if let Some(on_event) = &self.on_event {
+            on_event(&event);
+        }
+    }
+}
+
+pub(crate) fn installed_packages(ctx: &Ctx) -> Result<Vec<PackageInfo>, LpmError<MainError>> {
+    let pkgs = PkgDataFromDb::load_all_packages(ctx.pkgs_db())?;
+    Ok(pkgs.iter().map(PackageInfo::from).collect())
+}
+
+/// What changed between the installed packages `before` and `after`.
+pub(crate) fn diff_packages(before: &[PackageInfo], after: &[PackageInfo]) -> Changes {
+    let mut changes = Changes::default();
+
+    for new in after {
+        match before.iter().find(|old| old.key() == new.key()) {
+            Some(old) if old.version != new.version => {
+                changes.updated.push((old.clone(), new.clone()))
+            }
+            Some(_) => {}
+            None => changes.installed.push(new.clone()),
+        }
+    }
+
+    changes.deleted = before
+        .iter()
+        .filter(|old| !after.iter().any(|new| new.key() == old.key()))
+        .cloned()
+        .collect();
+
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pkg(name: &str, version: &str) -> PackageInfo {
+        PackageInfo {
+            name: name.to_owned(),
+            version: version.to_owned(),
+            arch: String::from("amd64"),
+            slot: None,
+            kind: None,
+            installed_size: 0,
+            is_dependency: false,
+        }
+    }
+
+    #[test]
+    fn test_diff_packages() {
+        let before = vec![
+            pkg("htop", "3.2.1"),
+            pkg("ncurses", "6.3"),
+            pkg("vim", "9.0"),
+        ];
+        let after = vec![
+            pkg("htop", "3.2.1"),
+            pkg("ncurses", "6.4"),
+            pkg("nano", "7.2"),
+        ];
+
+        let changes = diff_packages(&before, &after);
+        assert_eq!(changes.installed, vec![pkg("nano", "7.2")]);
+        assert_eq!(
+            changes.updated,
+            vec![(pkg("ncurses", "6.3"), pkg("ncurses", "6.4"))]
+        );
+        assert_eq!(changes.deleted, vec![pkg("vim", "9.0")]);
+
+        assert!(diff_packages(&before, &before).is_empty());
+    }
+}
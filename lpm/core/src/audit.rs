@@ -0,0 +1,208 @@
+//! Attribution of the transactions to whoever started them, recorded in the
+//! transaction history and, with `audit_syslog` in the config, reported to the
+//! `authpriv` syslog facility:
+//!
+//! ```text
+//! lpm[4242]: operation=install status=success uid=0 user=root sudo_user=alice sudo_uid=1000 tty=/dev/pts/1 installed=htop
+//! ```
+
+use crate::{Changes, Operation};
+
+use json::{to_json_object, Serialize};
+use std::{env, fs, io, os::unix::net::UnixDatagram};
+
+const SYSLOG_SOCKET_PATH: &str = "/dev/log";
+// `authpriv` facility (10) with the `notice` severity (5).
+const SYSLOG_PRIORITY: u32 = 10 * 8 + 5;
+
+extern "C" {
+    fn getuid() -> u32;
+}
+
+/// The user behind a transaction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Initiator {
+    pub uid: u32,
+    pub user: Option<String>,
+    /// Caller of `sudo`, from `SUDO_USER` and `SUDO_UID`.
+    pub sudo_user: Option<String>,
+    pub sudo_uid: Option<u32>,
+    /// Terminal on stdin, `None` for services and pipes.
+    pub tty: Option<String>,
+    /// Set for the transactions requested through `lpm --rpc-daemon` or
+    /// `lpm --daemon`.
+    pub rpc: bool,
+}
+
+impl Initiator {
+    /// Whoever runs the current process.
+    pub fn current() -> Self {
+        // SAFETY: `getuid` has no preconditions and can't fail.
+        #[allow(unsafe_code)]
+        let uid = unsafe { getuid() };
+
+        Self {
+            uid,
+            user: user_name(uid),
+            sudo_user: env::var("SUDO_USER").ok(),
+            sudo_uid: env::var("SUDO_UID").ok().and_then(|t| t.parse().ok()),
+            tty: fs::read_link("/proc/self/fd/0")
+                .ok()
+                .map(|t| t.to_string_lossy().into_owned())
+                .filter(|t| t.starts_with("/dev/pts/") || t.starts_with("/dev/tty")),
+            rpc: false,
+        }
+    }
+
+    /// Peer of a JSON-RPC connection or caller on the system bus, whose
+    /// environment and terminal are unknown.
+    pub(crate) fn rpc_peer(uid: u32) -> Self {
+        Self {
+            uid,
+            user: user_name(uid),
+            sudo_user: None,
+            sudo_uid: None,
+            tty: None,
+            rpc: true,
+        }
+    }
+}
+
+impl Serialize for Initiator {
+    fn to_json(&self) -> String {
+        to_json_object(&[
+            ("uid", self.uid.to_json()),
+            ("user", self.user.to_json()),
+            ("sudo_user", self.sudo_user.to_json()),
+            ("sudo_uid", self.sudo_uid.to_json()),
+            ("tty", self.tty.to_json()),
+            ("rpc", self.rpc.to_json()),
+        ])
+    }
+}
+
+fn user_name(uid: u32) -> Option<String> {
+    let passwd = fs::read_to_string("/etc/passwd").ok()?;
+    find_user_name(&passwd, uid)
+}
+
+fn find_user_name(passwd: &str, uid: u32) -> Option<String> {
+    passwd.lines().find_map(|line| {
+        let mut fields = line.split(':');
+        let name = fields.next()?;
+        (fields.nth(1)?.parse() == Ok(uid)).then(|| name.to_owned())
+    })
+}
+
+fn audit_message(
+    operation: Operation,
+    succeeded: bool,
+    initiator: &Initiator,
+    changes: &Changes,
+) -> String {
+    let mut message = format!(
+        "operation={} status={} uid={}",
+        operation.as_str(),
+        if succeeded { "success" } else { "failure" },
+        initiator.uid
+    );
+
+    let optional_fields = [
+        ("user", initiator.user.clone()),
+        ("sudo_user", initiator.sudo_user.clone()),
+        ("sudo_uid", initiator.sudo_uid.map(|t| t.to_string())),
+        ("tty", initiator.tty.clone()),
+    ];
+    for (key, value) in optional_fields {
+        if let Some(value) = value {
+            message.push_str(&format!(" {key}={value}"));
+        }
+    }
+
+    if initiator.rpc {
+        message.push_str(" via=rpc");
+    }
+
+    let package_lists = [
+        ("installed", changes.installed.iter().collect::<Vec<_>>()),
+        ("updated", changes.updated.iter().map(|(_, t)| t).collect()),
+        ("deleted", changes.deleted.iter().collect()),
+    ];
+    for (key, packages) in package_lists {
+        if !packages.is_empty() {
+            let names: Vec<&str> = packages.iter().map(|t| t.name.as_str()).collect();
+            message.push_str(&format!(" {key}={}", names.join(",")));
+        }
+    }
+
+    message
+}
+
+/// Sends the audit event of a transaction to the local syslog daemon.
+pub(crate) fn send_audit_event(
+    operation: Operation,
+    succeeded: bool,
+    initiator: &Initiator,
+    changes: &Changes,
+) -> io::Result<()> {
+    let message = format!(
+        "<{SYSLOG_PRIORITY}>lpm[{}]: {}",
+        std::process::id(),
+        audit_message(operation, succeeded, initiator, changes)
+    );
+
+    UnixDatagram::unbound()?
+        .send_to(message.as_bytes(), SYSLOG_SOCKET_PATH)
+        .map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PackageInfo;
+
+    #[test]
+    fn test_audit_message() {
+        let passwd = "root:x:0:0:root:/root:/bin/bash\nalice:x:1000:1000::/home/alice:/bin/sh\n";
+        assert_eq!(find_user_name(passwd, 1000).as_deref(), Some("alice"));
+        assert_eq!(find_user_name(passwd, 1001), None);
+
+        let initiator = Initiator {
+            uid: 0,
+            user: Some("root".to_owned()),
+            sudo_user: Some("alice".to_owned()),
+            sudo_uid: Some(1000),
+            tty: Some("/dev/pts/1".to_owned()),
+            rpc: false,
+        };
+        let package = |name: &str| PackageInfo {
+            name: name.to_owned(),
+            version: "1.0.0".to_owned(),
+            arch: "amd64".to_owned(),
+            slot: None,
+            kind: None,
+            installed_size: 0,
+            is_dependency: false,
+        };
+        let changes = Changes {
+            installed: vec![package("htop"), package("ncurses")],
+            updated: Vec::new(),
+            deleted: vec![package("top")],
+        };
+
+        assert_eq!(
+            audit_message(Operation::Install, true, &initiator, &changes),
+            "operation=install status=success uid=0 user=root sudo_user=alice sudo_uid=1000 \
+             tty=/dev/pts/1 installed=htop,ncurses deleted=top"
+        );
+
+        let rpc_peer = Initiator {
+            user: None,
+            ..Initiator::rpc_peer(1000)
+        };
+        assert_eq!(
+            audit_message(Operation::Install, false, &rpc_peer, &Changes::default()),
+            "operation=install status=failure uid=1000 via=rpc"
+        );
+    }
+}
@@ -0,0 +1,306 @@
+//! `lpm --auto-update`, unattended updates governed by the `auto_update`
+//! policy of the config.
+//!
+//! It's meant to be run by the systemd timer from `lpm --auto-update
+//! --install-units`, which fires hourly and leaves checking the maintenance
+//! windows to lpm. Every run that gets to the repositories is appended as a
+//! JSON line to `AUTO_UPDATE_HISTORY_PATH`, with the updates it skipped and why.
+
+use crate::{
+    notify::run_transaction,
+    repository::{ensure_fresh_metadata, get_and_apply_repository_patches},
+    update::{apply_updates, find_available_updates},
+    Changes, Ctx, Operation,
+};
+
+use common::{
+    config::{AutoUpdatePolicy, CONFIG_PATH},
+    glob,
+    pkg::PkgDataFromDb,
+};
+use db::{enable_core_db_wal1, PkgIndex, REPOSITORY_INDEX_DB_DIR};
+use ehandle::{lpm::LpmError, MainError};
+use json::{to_json_object, Serialize};
+use logger::{info, success, warning};
+use min_sqlite3_sys::prelude::*;
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    os::raw::{c_char, c_int, c_long},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+pub const AUTO_UPDATE_HISTORY_PATH: &str = "/var/log/lpm/auto-update.log";
+pub const SYSTEMD_UNIT_DIR: &str = "/etc/systemd/system";
+const UNIT_NAME: &str = "lpm-auto-update";
+
+/// tm port from C
+#[repr(C)]
+struct Tm {
+    tm_sec: c_int,
+    tm_min: c_int,
+    tm_hour: c_int,
+    tm_mday: c_int,
+    tm_mon: c_int,
+    tm_year: c_int,
+    tm_wday: c_int,
+    tm_yday: c_int,
+    tm_isdst: c_int,
+    tm_gmtoff: c_long,
+    tm_zone: *const c_char,
+}
+
+extern "C" {
+    fn localtime_r(timep: *const i64, result: *mut Tm) -> *mut Tm;
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|t| t.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+/// Weekday (0 for Monday) and minutes since midnight of `timestamp` in local time.
+fn local_weekday_and_minute(timestamp: i64) -> io::Result<(u8, u16)> {
+    let mut tm = Tm {
+        tm_sec: 0,
+        tm_min: 0,
+        tm_hour: 0,
+        tm_mday: 0,
+        tm_mon: 0,
+        tm_year: 0,
+        tm_wday: 0,
+        tm_yday: 0,
+        tm_isdst: 0,
+        tm_gmtoff: 0,
+        tm_zone: std::ptr::null(),
+    };
+
+    #[allow(unsafe_code)]
+    if unsafe { localtime_r(&timestamp, &mut tm) }.is_null() {
+        return Err(io::Error::last_os_error());
+    }
+
+    // `tm_wday` starts from Sunday.
+    let weekday = ((tm.tm_wday + 6) % 7) as u8;
+    Ok((weekday, (tm.tm_hour * 60 + tm.tm_min) as u16))
+}
+
+/// Why the policy doesn't allow updating `name`, `None` if it does.
+fn skip_reason(
+    policy: &AutoUpdatePolicy,
+    name: &str,
+    is_security_update: impl FnOnce() -> Result<bool, LpmError<MainError>>,
+) -> Result<Option<&'static str>, LpmError<MainError>> {
+    if glob::matches_any(&policy.exclude, name) {
+        return Ok(Some("excluded by the policy"));
+    }
+
+    if policy.security_only && !is_security_update()? {
+        return Ok(Some("not a security update"));
+    }
+
+    Ok(None)
+}
+
+fn is_security_update(pkg: &PkgDataFromDb, index: &PkgIndex) -> Result<bool, LpmError<MainError>> {
+    let index_db = Database::open(Path::new(REPOSITORY_INDEX_DB_DIR).join(&index.repository_name))?;
+    Ok(PkgIndex::has_security_update(
+        &index_db,
+        &index.name,
+        &pkg.meta_fields.meta.version,
+    )?)
+}
+
+pub fn run_auto_update(mut ctx: Ctx) -> Result<(), LpmError<MainError>> {
+    let Some(policy) = ctx.config.auto_update.clone() else {
+        info!("Automatic updates are disabled, set 'auto_update' in {CONFIG_PATH} to enable them.");
+        return Ok(());
+    };
+
+    let started_at = unix_now();
+    let (weekday, minute) = local_weekday_and_minute(started_at)?;
+    if !policy.windows.is_empty() && !policy.windows.iter().any(|t| t.contains(weekday, minute)) {
+        info!("Outside of the maintenance windows, skipping automatic updates.");
+        return Ok(());
+    }
+
+    // Nobody is there to answer the prompts.
+    ctx.force_yes = true;
+    ctx.allow_downgrade = Some(false);
+    ctx.plan_format = None;
+
+    let mut skipped: Vec<(String, &str)> = Vec::new();
+    let result = run_transaction(&ctx, Operation::Update, |ctx| {
+        enable_core_db_wal1(&ctx.core_db)?;
+        get_and_apply_repository_patches(ctx)?;
+
+        let (mut old_pkgs, mut new_indexes) = (Vec::new(), Vec::new());
+        let (candidates, indexes) = find_available_updates(ctx)?;
+        for (pkg, index) in candidates.into_iter().zip(indexes) {
+            let name = pkg.meta_fields.meta.name.clone();
+            match skip_reason(&policy, &name, || is_security_update(&pkg, &index))? {
+                Some(reason) => {
+                    info!("Skipping the update of {name}, {reason}.");
+                    skipped.push((name, reason));
+                }
+                None => {
+                    old_pkgs.push(pkg);
+                    new_indexes.push(index);
+                }
+            }
+        }
+
+        if old_pkgs.is_empty() {
+            info!("No update to apply.");
+            return Ok(());
+        }
+        ensure_fresh_metadata(ctx, &new_indexes)?;
+
+        apply_updates(ctx, old_pkgs, new_indexes)
+    });
+
+    if let Err(e) = record_history(started_at, &result, &skipped) {
+        warning!("Couldn't record the run in {AUTO_UPDATE_HISTORY_PATH}: {e}");
+    }
+
+    let changes = result?;
+    if !changes.is_empty() {
+        success!("{} packages updated automatically.", changes.updated.len());
+    }
+
+    Ok(())
+}
+
+fn record_history(
+    started_at: i64,
+    result: &Result<Changes, LpmError<MainError>>,
+    skipped: &[(String, &str)],
+) -> io::Result<()> {
+    let skipped: Vec<String> = skipped
+        .iter()
+        .map(|(name, reason)| {
+            to_json_object(&[("name", name.to_json()), ("reason", reason.to_json())])
+        })
+        .collect();
+
+    let record = to_json_object(&[
+        ("started_at", started_at.to_json()),
+        ("finished_at", unix_now().to_json()),
+        (
+            "status",
+            if result.is_ok() { "success" } else { "failure" }.to_json(),
+        ),
+        (
+            "error",
+            result
+                .as_ref()
+                .err()
+                .map(|e| format!("{:?}", e.error_type))
+                .to_json(),
+        ),
+        ("changes", result.as_ref().ok().to_json()),
+        ("skipped", format!("[{}]", skipped.join(","))),
+    ]);
+
+    let path = Path::new(AUTO_UPDATE_HISTORY_PATH);
+    fs::create_dir_all(path.parent().unwrap())?;
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{record}")
+}
+
+fn units(lpm_path: &Path) -> [(String, String); 2] {
+    let service = format!(
+        "[Unit]
+Description=Automatic lpm package updates
+Wants=network-online.target
+After=network-online.target
+
+[Service]
+Type=oneshot
+ExecStart={} --auto-update
+",
+        lpm_path.display()
+    );
+
+    let timer = String::from(
+        "[Unit]
+Description=Run automatic lpm package updates
+
+[Timer]
+OnCalendar=hourly
+RandomizedDelaySec=15min
+Persistent=true
+
+[Install]
+WantedBy=timers.target
+",
+    );
+
+    [
+        (format!("{UNIT_NAME}.service"), service),
+        (format!("{UNIT_NAME}.timer"), timer),
+    ]
+}
+
+/// Writes the systemd service and timer that run `lpm --auto-update` into
+/// `unit_dir`, `SYSTEMD_UNIT_DIR` by default.
+pub fn install_auto_update_units(unit_dir: Option<&str>) -> Result<(), LpmError<MainError>> {
+    let unit_dir = Path::new(unit_dir.unwrap_or(SYSTEMD_UNIT_DIR));
+    fs::create_dir_all(unit_dir)?;
+
+    for (file_name, content) in units(&std::env::current_exe()?) {
+        let path = unit_dir.join(file_name);
+        fs::write(&path, content)?;
+        info!("Wrote {}", path.display());
+    }
+
+    println!(
+        "\nEnable them with `systemctl daemon-reload && systemctl enable --now {UNIT_NAME}.timer`"
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_skip_reason() {
+        let policy = AutoUpdatePolicy {
+            security_only: true,
+            exclude: vec![String::from("linux*")],
+            windows: Vec::new(),
+        };
+
+        let security_fix = || Ok(true);
+        let regular = || Ok(false);
+        assert_eq!(
+            skip_reason(&policy, "linux-lts", security_fix).unwrap(),
+            Some("excluded by the policy")
+        );
+        assert_eq!(
+            skip_reason(&policy, "htop", regular).unwrap(),
+            Some("not a security update")
+        );
+        assert_eq!(skip_reason(&policy, "htop", security_fix).unwrap(), None);
+
+        let policy = AutoUpdatePolicy::default();
+        assert_eq!(
+            skip_reason(&policy, "htop", || panic!("not needed")).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_units() {
+        let [(service_name, service), (timer_name, timer)] = units(Path::new("/usr/bin/lpm"));
+        assert_eq!(service_name, "lpm-auto-update.service");
+        assert!(service.contains("\nExecStart=/usr/bin/lpm --auto-update\n"));
+        assert_eq!(timer_name, "lpm-auto-update.timer");
+        assert!(timer.contains("\nOnCalendar=hourly\n"));
+    }
+}
@@ -0,0 +1,279 @@
+//! Backups of the files that updates replace or remove, kept for the last
+//! `keep_backups` transactions of the config so that they can be restored
+//! without network access.
+//!
+//! File contents are stored once under `objects/<sha256>` of `BACKUPS_DIR`,
+//! and each transaction gets a manifest in `transactions/`:
+//!
+//! ```json
+//! {"created_at": 1700000000, "files": [{"path": "/usr/bin/htop", "checksum": "…", "mode": 493}]}
+//! ```
+
+use hash::sha256;
+use json::{to_json_object, Json, JsonValue, Serialize};
+use logger::{debug, warning};
+use std::{
+    collections::HashSet,
+    fs::{self, DirBuilder, File, OpenOptions},
+    io::{self, Read, Write},
+    os::unix::fs::{DirBuilderExt, OpenOptionsExt, PermissionsExt},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+pub const BACKUPS_DIR: &str = "/var/lib/lpm/backups";
+
+struct BackedUpFile {
+    path: String,
+    checksum: String,
+    mode: u32,
+}
+
+impl Serialize for BackedUpFile {
+    fn to_json(&self) -> String {
+        to_json_object(&[
+            ("path", self.path.to_json()),
+            ("checksum", self.checksum.to_json()),
+            ("mode", self.mode.to_json()),
+        ])
+    }
+}
+
+/// Backups of a single transaction, recorded with `finish`.
+pub(crate) struct Backups {
+    dir: PathBuf,
+    keep: u32,
+    files: Vec<BackedUpFile>,
+}
+
+impl Backups {
+    /// Does nothing if `keep` is 0.
+    pub(crate) fn new(keep: u32) -> Self {
+        Self::new_in(Path::new(BACKUPS_DIR), keep)
+    }
+
+    fn new_in(dir: &Path, keep: u32) -> Self {
+        Self {
+            dir: dir.to_owned(),
+            keep,
+            files: Vec::new(),
+        }
+    }
+
+    /// Stores the content of `path` before it's replaced or removed. Only
+    /// regular files are backed up.
+    pub(crate) fn save(&mut self, path: &Path) -> io::Result<()> {
+        if self.keep == 0 {
+            return Ok(());
+        }
+
+        let metadata = fs::symlink_metadata(path)?;
+        if !metadata.is_file() {
+            return Ok(());
+        }
+
+        // Backups may be of files that only root can read, so nobody else
+        // gets to read them here either.
+        let objects_dir = self.dir.join("objects");
+        DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(&objects_dir)?;
+        fs::set_permissions(&self.dir, fs::Permissions::from_mode(0o700))?;
+
+        // Renamed into place once the checksum is known, so that interrupted
+        // writes never leave an object with the wrong content.
+        let partial_path = objects_dir.join(format!("partial-{}", std::process::id()));
+        let checksum = match copy_hashed(path, &partial_path) {
+            Ok(checksum) => checksum,
+            Err(e) => {
+                let _ = fs::remove_file(&partial_path);
+                return Err(e);
+            }
+        };
+        let object_path = objects_dir.join(&checksum);
+        if object_path.exists() {
+            fs::remove_file(&partial_path)?;
+        } else {
+            fs::rename(&partial_path, &object_path)?;
+        }
+
+        debug!("Backed up {} as {checksum}", path.display());
+        self.files.push(BackedUpFile {
+            path: path.to_string_lossy().into_owned(),
+            checksum,
+            mode: metadata.permissions().mode() & 0o7777,
+        });
+
+        Ok(())
+    }
+
+    /// Records the backups of the transaction, whether it succeeded or not,
+    /// as the files are replaced either way. Failures are only warned about.
+    pub(crate) fn finish(self) {
+        if let Err(e) = self.record() {
+            warning!("Couldn't record the backups in {}: {e}", self.dir.display());
+        }
+    }
+
+    /// Writes the manifest of the transaction, then drops the backups of the
+    /// transactions older than the `keep` most recent ones.
+    fn record(&self) -> io::Result<()> {
+        if self.files.is_empty() {
+            return Ok(());
+        }
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let manifest = to_json_object(&[
+            ("created_at", (now.as_secs() as i64).to_json()),
+            ("files", self.files.to_json()),
+        ]);
+
+        let transactions_dir = self.dir.join("transactions");
+        fs::create_dir_all(&transactions_dir)?;
+        // Zero padded, so the names sort by time.
+        fs::write(
+            transactions_dir.join(format!("{:020}.json", now.as_nanos())),
+            manifest,
+        )?;
+
+        prune(&self.dir, self.keep as usize)
+    }
+}
+
+/// Copies `from` to a new file at `to` that only its owner can read, returns
+/// the SHA-256 of the content.
+fn copy_hashed(from: &Path, to: &Path) -> io::Result<String> {
+    let mut reader = File::open(from)?;
+    let mut writer = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(to)?;
+
+    let mut hasher = sha256::Hasher::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let read = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        hasher.update(&buffer[..read]);
+        writer.write_all(&buffer[..read])?;
+    }
+    writer.sync_all()?;
+
+    Ok(hash::digest_to_hex_string(&hasher.finalize()))
+}
+
+/// Manifests in `transactions_dir`, the oldest first.
+fn transaction_manifests(transactions_dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut manifests: Vec<PathBuf> = fs::read_dir(transactions_dir)?
+        .map(|t| t.map(|t| t.path()))
+        .collect::<io::Result<_>>()?;
+    manifests.retain(|t| t.extension().map_or(false, |t| t == "json"));
+    manifests.sort();
+
+    Ok(manifests)
+}
+
+fn prune(dir: &Path, keep: usize) -> io::Result<()> {
+    let manifests = transaction_manifests(&dir.join("transactions"))?;
+    let (expired, kept) = manifests.split_at(manifests.len().saturating_sub(keep));
+    if expired.is_empty() {
+        return Ok(());
+    }
+
+    for manifest in expired {
+        fs::remove_file(manifest)?;
+    }
+
+    let mut referenced = HashSet::new();
+    for manifest in kept {
+        let content = fs::read_to_string(manifest)?;
+        let json = Json::new(&content).parse().map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("'{}': {e}", manifest.display()),
+            )
+        })?;
+
+        if let JsonValue::Array(files) = &json["files"] {
+            referenced.extend(files.iter().filter_map(|t| t["checksum"].to_string()));
+        }
+    }
+
+    for object in fs::read_dir(dir.join("objects"))? {
+        let object = object?;
+        if !referenced.contains(&*object.file_name().to_string_lossy()) {
+            fs::remove_file(object.path())?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::testing::TestDir;
+
+    #[test]
+    fn test_backups() {
+        let dir = TestDir::new("backups");
+        let backups_dir = dir.join("backups");
+        let (first, second) = (dir.join("first"), dir.join("second"));
+
+        let objects = || {
+            let mut objects: Vec<String> = fs::read_dir(backups_dir.join("objects"))
+                .unwrap()
+                .map(|t| t.unwrap().file_name().to_string_lossy().into_owned())
+                .collect();
+            objects.sort();
+            objects
+        };
+        let checksum = |content: &[u8]| hash::digest_to_hex_string(&sha256::digest(content));
+
+        let mut disabled = Backups::new_in(&backups_dir, 0);
+        fs::write(&first, "one").unwrap();
+        disabled.save(&first).unwrap();
+        disabled.record().unwrap();
+        assert!(!backups_dir.exists());
+
+        // Same content is stored once.
+        let mut backups = Backups::new_in(&backups_dir, 1);
+        fs::write(&second, "one").unwrap();
+        backups.save(&first).unwrap();
+        backups.save(&second).unwrap();
+        backups.record().unwrap();
+        assert_eq!(objects(), [checksum(b"one")]);
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&backups_dir), 0o700);
+        assert_eq!(
+            mode(&backups_dir.join("objects").join(checksum(b"one"))),
+            0o600
+        );
+
+        // Objects of the dropped transactions go with them.
+        let mut backups = Backups::new_in(&backups_dir, 1);
+        fs::write(&first, "two").unwrap();
+        backups.save(&first).unwrap();
+        backups.record().unwrap();
+        assert_eq!(objects(), [checksum(b"two")]);
+
+        let manifests = transaction_manifests(&backups_dir.join("transactions")).unwrap();
+        assert_eq!(manifests.len(), 1);
+        let manifest = Json::new(&fs::read_to_string(&manifests[0]).unwrap())
+            .parse()
+            .unwrap();
+        assert_eq!(
+            manifest["files"][0]["path"].to_string().as_deref(),
+            Some(&*first.to_string_lossy())
+        );
+    }
+}
@@ -0,0 +1,91 @@
+use crate::{store::ContentStore, Ctx};
+
+use common::{meta::FileKind, pkg::PkgDataFromDb, size::format_byte_size};
+use db::pkg::DbOpsForInstalledPkg;
+use ehandle::{db::SqlErrorKind, lpm::LpmError, ErrorCommons, MainError};
+use logger::{info, success};
+use std::path::Path;
+
+/// Differences below this are just noise from the block sizes.
+const SIZE_TOLERANCE: u64 = 64 * 1024;
+
+/// Checks the integrity of the package database, and optionally compares the
+/// recorded package sizes with the files on disk, or checks that the installed
+/// files are the ones in the content store.
+pub fn check_database(
+    ctx: Ctx,
+    verify_size: bool,
+    verify_store: bool,
+) -> Result<(), LpmError<MainError>> {
+    info!("Checking database integrity..");
+    let mut problems = db::check_integrity(&ctx.core_db)?;
+
+    if verify_size {
+        info!("Comparing recorded package sizes with the files on disk..");
+        for pkg in PkgDataFromDb::load_all_packages(&ctx.core_db)? {
+            let recorded = pkg.meta_fields.meta.installed_size.max(0) as u64;
+            let actual = pkg.meta_fields.files.disk_usage(Path::new("/"))?;
+
+            if is_size_diverging(recorded, actual) {
+                problems.push(format!(
+                    "'{}' is recorded as {} but uses {} on disk.",
+                    pkg.meta_fields.meta.get_group_id(),
+                    format_byte_size(recorded),
+                    format_byte_size(actual)
+                ));
+            }
+        }
+    }
+
+    if verify_store {
+        info!("Verifying the installed files against the content store..");
+        let pkgs = PkgDataFromDb::load_all_packages(&ctx.core_db)?;
+        let files: Vec<_> = pkgs
+            .iter()
+            .flat_map(|pkg| {
+                let config_files = &pkg.meta_fields.meta.config_files;
+                pkg.meta_fields
+                    .files
+                    .0
+                    .iter()
+                    .filter(move |file| file.kind(config_files) != FileKind::Config)
+            })
+            .collect();
+        problems.extend(ContentStore::new(Path::new("/")).verify(&files)?);
+    }
+
+    if !problems.is_empty() {
+        return Err(SqlErrorKind::CheckFailed(problems).to_lpm_err())?;
+    }
+
+    success!("No problems found.");
+    Ok(())
+}
+
+/// True if one of the sizes is more than twice the other one, ignoring small
+/// differences.
+fn is_size_diverging(recorded: u64, actual: u64) -> bool {
+    let (smaller, larger) = if recorded < actual {
+        (recorded, actual)
+    } else {
+        (actual, recorded)
+    };
+
+    larger - smaller > SIZE_TOLERANCE && larger > smaller.saturating_mul(2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_size_diverging() {
+        assert!(!is_size_diverging(0, 0));
+        assert!(!is_size_diverging(4096, 12288));
+        assert!(!is_size_diverging(1024 * 1024, 1536 * 1024));
+
+        assert!(is_size_diverging(1024 * 1024, 3 * 1024 * 1024));
+        assert!(is_size_diverging(3 * 1024 * 1024, 1024 * 1024));
+        assert!(is_size_diverging(0, 1024 * 1024));
+    }
+}
@@ -0,0 +1,312 @@
+//! Mounts that package scripts need while they run chrooted into an alternate
+//! installation root: `/proc`, `/sys` and `/dev` of the host, and the
+//! extracted packages so that `PKG_ROOT` points to the same place inside.
+//!
+//! Scripts of foreign architectures run the same way, through the qemu-user
+//! handlers registered in `binfmt_misc`.
+
+use common::arch;
+use logger::warning;
+use std::{
+    ffi::CString,
+    fs, io,
+    os::{
+        raw::{c_char, c_int, c_ulong, c_void},
+        unix::ffi::OsStrExt,
+    },
+    path::{Path, PathBuf},
+    ptr,
+    sync::{Mutex, MutexGuard, PoisonError},
+};
+
+const BINFMT_MISC_DIR: &str = "/proc/sys/fs/binfmt_misc";
+
+/// Names that qemu uses for the architectures, in `qemu-<name>`.
+const QEMU_ARCHS: &[(&str, &str)] = &[
+    ("amd64", "x86_64"),
+    ("arm64", "aarch64"),
+    ("arm", "arm"),
+    ("i386", "i386"),
+];
+
+const MS_BIND: c_ulong = 4096;
+const MS_REC: c_ulong = 16384;
+const MS_SLAVE: c_ulong = 1 << 19;
+const MNT_DETACH: c_int = 2;
+
+extern "C" {
+    fn mount(
+        source: *const c_char,
+        target: *const c_char,
+        filesystem_type: *const c_char,
+        flags: c_ulong,
+        data: *const c_void,
+    ) -> c_int;
+    fn umount2(target: *const c_char, flags: c_int) -> c_int;
+}
+
+/// Packages of a transaction are installed in parallel, their chrooted
+/// scripts take turns so they don't mount over each other.
+static CHROOT_LOCK: Mutex<()> = Mutex::new(());
+
+/// Mounts for the scripts chrooted into a root, torn down on drop along with
+/// the directories created for them.
+pub(crate) struct ChrootMounts {
+    mounted: Vec<PathBuf>,
+    created: Vec<PathBuf>,
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl ChrootMounts {
+    pub(crate) fn new(root: &Path) -> io::Result<Self> {
+        let mut mounts = Self {
+            mounted: Vec::new(),
+            created: Vec::new(),
+            _lock: CHROOT_LOCK.lock().unwrap_or_else(PoisonError::into_inner),
+        };
+
+        // Whatever got mounted before a failure is dropped along with `mounts`.
+        mounts.mount(root, "proc", "proc", Some("proc"), 0)?;
+        mounts.mount(root, "sysfs", "sys", Some("sysfs"), 0)?;
+        mounts.mount(root, "/dev", "dev", None, MS_BIND | MS_REC)?;
+        mounts.mount(
+            root,
+            super::EXTRACTION_OUTPUT_PATH,
+            super::EXTRACTION_OUTPUT_PATH.trim_start_matches('/'),
+            None,
+            MS_BIND,
+        )?;
+
+        Ok(mounts)
+    }
+
+    fn mount(
+        &mut self,
+        root: &Path,
+        source: &str,
+        target: &str,
+        filesystem_type: Option<&str>,
+        flags: c_ulong,
+    ) -> io::Result<()> {
+        let target = root.join(target);
+        let missing = missing_dirs(&target);
+        fs::create_dir_all(&target)?;
+        self.created.extend(missing);
+
+        // Symlinks of the root must not redirect the mounts onto the host.
+        if !fs::canonicalize(&target)?.starts_with(fs::canonicalize(root)?) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("'{}' points outside of the root", target.display()),
+            ));
+        }
+
+        let c_source = CString::new(source)?;
+        let c_target = CString::new(target.as_os_str().as_bytes())?;
+        let c_filesystem_type = filesystem_type.map(CString::new).transpose()?;
+
+        // SAFETY: The strings are NUL terminated and outlive the call, the
+        // filesystem type and data may be null.
+        #[allow(unsafe_code)]
+        let result = unsafe {
+            mount(
+                c_source.as_ptr(),
+                c_target.as_ptr(),
+                c_filesystem_type
+                    .as_ref()
+                    .map_or(ptr::null(), |t| t.as_ptr()),
+                flags,
+                ptr::null(),
+            )
+        };
+        if result != 0 {
+            let error = io::Error::last_os_error();
+            return Err(io::Error::new(
+                error.kind(),
+                format!(
+                    "Couldn't mount '{source}' on '{}': {error}",
+                    target.display()
+                ),
+            ));
+        }
+        self.mounted.push(target);
+
+        // Bind mounts share their propagation with the host, unmounting the
+        // `/dev` submounts at drop would unmount them on the host too.
+        if flags & MS_BIND != 0 {
+            // SAFETY: `c_target` is NUL terminated and outlives the call, the
+            // source, filesystem type and data are ignored for MS_SLAVE.
+            #[allow(unsafe_code)]
+            let result = unsafe {
+                mount(
+                    ptr::null(),
+                    c_target.as_ptr(),
+                    ptr::null(),
+                    MS_SLAVE | MS_REC,
+                    ptr::null(),
+                )
+            };
+            if result != 0 {
+                let error = io::Error::last_os_error();
+                return Err(io::Error::new(
+                    error.kind(),
+                    format!("Couldn't make '{source}' a slave mount: {error}"),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for ChrootMounts {
+    fn drop(&mut self) {
+        for target in self.mounted.iter().rev() {
+            let Ok(c_target) = CString::new(target.as_os_str().as_bytes()) else {
+                continue;
+            };
+
+            // SAFETY: `c_target` is NUL terminated and outlives the call.
+            #[allow(unsafe_code)]
+            let result = unsafe { umount2(c_target.as_ptr(), MNT_DETACH) };
+            if result != 0 {
+                warning!(
+                    "Couldn't unmount '{}': {}",
+                    target.display(),
+                    io::Error::last_os_error()
+                );
+            }
+        }
+
+        // Deepest first, and only if nothing was left in them.
+        for dir in self.created.iter().rev() {
+            let _ = fs::remove_dir(dir);
+        }
+    }
+}
+
+/// A `binfmt_misc` handler.
+#[derive(Debug, PartialEq)]
+struct BinfmtHandler {
+    interpreter: PathBuf,
+    /// The `F` flag, the interpreter is opened on registration and doesn't
+    /// need to exist inside the chroot then.
+    fix_binary: bool,
+}
+
+/// Parses a handler of `binfmt_misc`, `None` if it is disabled.
+fn parse_binfmt_handler(content: &str) -> Option<BinfmtHandler> {
+    let mut lines = content.lines();
+    if lines.next()? != "enabled" {
+        return None;
+    }
+
+    let (mut interpreter, mut fix_binary) = (None, false);
+    for line in lines {
+        if let Some(path) = line.strip_prefix("interpreter ") {
+            interpreter = Some(PathBuf::from(path));
+        } else if let Some(flags) = line.strip_prefix("flags: ") {
+            fix_binary = flags.contains('F');
+        }
+    }
+
+    Some(BinfmtHandler {
+        interpreter: interpreter?,
+        fix_binary,
+    })
+}
+
+/// Whether binaries of `target_arch` can run chrooted into `root`, through an
+/// enabled qemu-user handler.
+pub(crate) fn can_emulate(root: &Path, target_arch: &str) -> bool {
+    let target_arch = arch::normalize(target_arch);
+    let Some((_, qemu_arch)) = QEMU_ARCHS.iter().find(|(arch, _)| *arch == target_arch) else {
+        return false;
+    };
+
+    let binfmt_misc = Path::new(BINFMT_MISC_DIR);
+    match fs::read_to_string(binfmt_misc.join("status")) {
+        Ok(status) if status.trim() == "enabled" => {}
+        _ => return false,
+    }
+
+    let Ok(handlers) = fs::read_dir(binfmt_misc) else {
+        return false;
+    };
+
+    // `qemu-aarch64` and `qemu-aarch64-static` are both common.
+    let interpreter_name = format!("qemu-{qemu_arch}");
+    handlers
+        .filter_map(|t| fs::read_to_string(t.ok()?.path()).ok())
+        .filter_map(|t| parse_binfmt_handler(&t))
+        .any(|handler| {
+            let matches = handler.interpreter.file_name().map_or(false, |name| {
+                let name = name.to_string_lossy();
+                name == interpreter_name || name == format!("{interpreter_name}-static")
+            });
+
+            matches
+                && (handler.fix_binary
+                    || root
+                        .join(
+                            handler
+                                .interpreter
+                                .strip_prefix("/")
+                                .unwrap_or(&handler.interpreter),
+                        )
+                        .exists())
+        })
+}
+
+/// Directories that creating `dir` would add, the outermost first.
+fn missing_dirs(dir: &Path) -> Vec<PathBuf> {
+    let mut missing: Vec<PathBuf> = dir
+        .ancestors()
+        .take_while(|t| !t.exists())
+        .map(Path::to_path_buf)
+        .collect();
+    missing.reverse();
+
+    missing
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::testing::TestDir;
+
+    #[test]
+    fn test_parse_binfmt_handler() {
+        let handler = "enabled\ninterpreter /usr/bin/qemu-aarch64-static\nflags: OCF\noffset 0\n\
+                       magic 7f454c460201010000000000000000000200b700\n";
+        assert_eq!(
+            parse_binfmt_handler(handler),
+            Some(BinfmtHandler {
+                interpreter: PathBuf::from("/usr/bin/qemu-aarch64-static"),
+                fix_binary: true,
+            })
+        );
+
+        assert_eq!(
+            parse_binfmt_handler("enabled\ninterpreter /usr/bin/qemu-arm\nflags: \n")
+                .map(|t| t.fix_binary),
+            Some(false)
+        );
+        assert_eq!(
+            parse_binfmt_handler("disabled\ninterpreter /usr/bin/qemu-arm\n"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_missing_dirs() {
+        let dir = TestDir::new("chroot");
+        fs::create_dir_all(dir.join("proc")).unwrap();
+
+        assert!(missing_dirs(&dir.join("proc")).is_empty());
+        assert_eq!(
+            missing_dirs(&dir.join("tmp/lpm")),
+            [dir.join("tmp"), dir.join("tmp/lpm")]
+        );
+    }
+}
@@ -1,14 +1,64 @@
-use crate::open_core_db_connection;
+use crate::{
+    open_core_db_connection, open_core_db_connection_at, open_core_db_connection_read_only,
+    plan::PlanFormat, store::ContentStore, Initiator,
+};
 
 use cli_parser::CliParser;
+use common::{
+    arch,
+    config::Config,
+    event::{EventSink, LogEvents},
+    size::parse_byte_size,
+    SYSTEM_ARCH,
+};
 use db::SQL_NO_CALLBACK_FN;
 use ehandle::{lpm::LpmError, MainError};
+use json::JsonValue;
 use min_sqlite3_sys::prelude::{Database, Operations};
-use std::io::{self, Write};
+use std::{
+    fs,
+    io::{self, BufRead, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 pub struct Ctx {
     pub core_db: Database,
     pub force_yes: bool,
+    /// Forbids network access when set.
+    pub offline: bool,
+    /// Settings from the config file, overridden by the CLI flags.
+    pub config: Config,
+    /// Alternate installation root, `None` means the running system.
+    pub install_root: Option<InstallRoot>,
+    /// Whether packages can be replaced with older versions, `None` asks the user.
+    pub allow_downgrade: Option<bool>,
+    /// Hash the installed files on updates instead of trusting their size
+    /// and modification time.
+    pub paranoid: bool,
+    /// Set when stdin carried the package data, prompts read from `/dev/tty` then.
+    pub stdin_consumed: bool,
+    /// Progress of the operations, logged by default.
+    pub events: Arc<dyn EventSink>,
+    /// Print the transactions in this format instead of applying them.
+    pub plan_format: Option<PlanFormat>,
+    /// Path and content of the plan given to `--apply-plan`.
+    pub(crate) approved_plan: Option<(String, JsonValue)>,
+    /// Who the transactions are attributed to in the history.
+    pub initiator: Initiator,
+    /// Only lets packages from trusted repositories be installed or updated,
+    /// see `repository::is_index_trusted`.
+    pub(crate) trusted_repositories_only: bool,
+}
+
+/// A root filesystem other than `/` (e.g. an image being assembled) with its
+/// own package database.
+///
+/// Repositories are still resolved through the host database.
+pub struct InstallRoot {
+    pub path: PathBuf,
+    pub arch: String,
+    pub core_db: Database,
 }
 
 impl Ctx {
@@ -16,32 +66,155 @@ impl Ctx {
         Ok(Self {
             core_db: open_core_db_connection()?,
             force_yes: false,
+            offline: false,
+            config: Config::load()?,
+            install_root: None,
+            allow_downgrade: None,
+            paranoid: false,
+            stdin_consumed: false,
+            events: Arc::new(LogEvents),
+            plan_format: None,
+            approved_plan: None,
+            initiator: Initiator::current(),
+            trusted_repositories_only: false,
         })
     }
 
     pub fn new_from_cli_parser(cli_parser: &CliParser) -> Result<Self, LpmError<MainError>> {
+        Self::from_cli_parser(cli_parser, open_core_db_connection)
+    }
+
+    /// For the commands that only run queries, which unprivileged users can
+    /// run too.
+    pub fn new_read_only_from_cli_parser(
+        cli_parser: &CliParser,
+    ) -> Result<Self, LpmError<MainError>> {
+        Self::from_cli_parser(cli_parser, open_core_db_connection_read_only)
+    }
+
+    fn from_cli_parser(
+        cli_parser: &CliParser,
+        open_core_db: fn() -> Result<Database, LpmError<MainError>>,
+    ) -> Result<Self, LpmError<MainError>> {
+        let mut config = Config::load()?;
+
+        if let Some(value) = cli_parser.limit_rate {
+            config.limit_rate = Some(parse_byte_size(value).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Invalid '--limit-rate' value '{value}'."),
+                )
+            })?);
+        }
+
+        config
+            .allowed_script_interpreters
+            .extend(cli_parser.allow_interpreters.iter().map(|t| t.to_string()));
+
+        let plan_format = match cli_parser.print_plan {
+            Some(value) => Some(PlanFormat::parse(value).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Invalid '--print-plan' value '{value}', expected 'text' or 'json'."),
+                )
+            })?),
+            None => None,
+        };
+
         Ok(Self {
-            core_db: open_core_db_connection()?,
+            core_db: open_core_db()?,
             force_yes: cli_parser.force_yes,
+            offline: cli_parser.offline,
+            config,
+            install_root: None,
+            allow_downgrade: cli_parser.allow_downgrade,
+            paranoid: cli_parser.paranoid,
+            stdin_consumed: false,
+            events: Arc::new(LogEvents),
+            plan_format,
+            approved_plan: None,
+            initiator: Initiator::current(),
+            trusted_repositories_only: false,
         })
     }
 
+    /// Redirects package installations into `path`, optionally for a
+    /// different architecture than the host.
+    pub fn set_install_root(
+        &mut self,
+        path: &Path,
+        target_arch: Option<&str>,
+    ) -> Result<(), LpmError<MainError>> {
+        self.install_root = Some(InstallRoot {
+            path: path.to_path_buf(),
+            arch: arch::normalize(target_arch.unwrap_or(SYSTEM_ARCH)),
+            core_db: open_core_db_connection_at(path)?,
+        });
+
+        Ok(())
+    }
+
+    /// Database which holds the installed packages of the target root.
+    pub fn pkgs_db(&self) -> &Database {
+        match &self.install_root {
+            Some(root) => &root.core_db,
+            None => &self.core_db,
+        }
+    }
+
+    pub fn root_path(&self) -> &Path {
+        match &self.install_root {
+            Some(root) => &root.path,
+            None => Path::new("/"),
+        }
+    }
+
+    pub fn target_arch(&self) -> &str {
+        match &self.install_root {
+            Some(root) => &root.arch,
+            None => SYSTEM_ARCH,
+        }
+    }
+
+    /// Store of the target root, `None` unless `content_store` is set.
+    pub(crate) fn content_store(&self) -> Option<ContentStore> {
+        self.config
+            .content_store
+            .then(|| ContentStore::new(self.root_path()))
+    }
+
     pub fn ask_for_confirmation(&self, q: &str) -> Result<bool, LpmError<MainError>> {
         if self.force_yes {
             return Ok(true);
         }
 
+        let mut tty = if self.stdin_consumed {
+            Some(io::BufReader::new(fs::File::open("/dev/tty")?))
+        } else {
+            None
+        };
+
         loop {
             let mut input = String::new();
 
             print!(
                 "{} [Y/n]: ",
-                logger::build_log(logger::OutputMode::QUESTION, q)
+                logger::build_log(logger::OutputMode::QUESTION, &logger::i18n::translate(q))
             );
 
             io::stdout().flush()?;
 
-            io::stdin().read_line(&mut input)?;
+            let read = match &mut tty {
+                Some(tty) => tty.read_line(&mut input)?,
+                None => io::stdin().read_line(&mut input)?,
+            };
+
+            if read == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "No answer for the confirmation prompt, use '--yes' to preaccept it.",
+                ))?;
+            }
 
             // Expect next char to be new line, so anything other than Y-y/N-n
             // will fail.
@@ -69,5 +242,15 @@ impl Drop for Ctx {
                 SQL_NO_CALLBACK_FN,
             )
             .unwrap();
+
+        if let Some(root) = &self.install_root {
+            #[allow(clippy::disallowed_methods)]
+            root.core_db
+                .execute(
+                    String::from("PRAGMA journal_mode = DELETE;"),
+                    SQL_NO_CALLBACK_FN,
+                )
+                .unwrap();
+        }
     }
 }
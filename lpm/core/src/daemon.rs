@@ -0,0 +1,390 @@
+//! `lpm --daemon`, which serves the operations of `Lpm` on the system bus so
+//! software centers and unprivileged users can drive them.
+//!
+//! The daemon needs the bus policy in `data/dbus/` to own its name, and
+//! transactions are authorized with the polkit actions in `data/polkit/`.
+//! Installs and updates that only take packages from trusted repositories,
+//! which require signatures and have signed indexes, need
+//! `INSTALL_TRUSTED_ACTION_ID`, which local sessions are granted without
+//! authentication. The transaction is then limited to those repositories, in
+//! case the indexes change in the meantime. Other installs and updates need
+//! `INSTALL_ACTION_ID`, whose administrator authentication local sessions
+//! keep for a while, and deletions need `DELETE_ACTION_ID`, which always asks
+//! for it. The package names are passed to polkit as the `packages` detail,
+//! so rules can narrow the actions down further.
+
+use crate::{Changes, Ctx, Event, Initiator, Lpm, Operation, PackageInfo};
+
+use dbus::{Connection, Message, MessageType, Value, ALLOW_INTERACTIVE_AUTHORIZATION};
+use ehandle::{lpm::LpmError, MainError};
+use std::{cell::RefCell, rc::Rc};
+
+const BUS_NAME: &str = "org.lpm.PackageManager";
+const OBJECT_PATH: &str = "/org/lpm/PackageManager";
+const INTERFACE: &str = "org.lpm.PackageManager1";
+const INSTALL_TRUSTED_ACTION_ID: &str = "org.lpm.PackageManager.install-trusted";
+const INSTALL_ACTION_ID: &str = "org.lpm.PackageManager.install";
+const DELETE_ACTION_ID: &str = "org.lpm.PackageManager.delete";
+
+const ERROR_FAILED: &str = "org.lpm.PackageManager1.Error.Failed";
+const ERROR_ACCESS_DENIED: &str = "org.freedesktop.DBus.Error.AccessDenied";
+const ERROR_INVALID_ARGS: &str = "org.freedesktop.DBus.Error.InvalidArgs";
+const ERROR_UNKNOWN_METHOD: &str = "org.freedesktop.DBus.Error.UnknownMethod";
+
+const INTROSPECTION: &str = r#"<!DOCTYPE node PUBLIC "-//freedesktop//DTD D-BUS Object Introspection 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/introspect.dtd">
+<node>
+  <interface name="org.lpm.PackageManager1">
+    <!-- name, version, arch, slot, installed size, is dependency -->
+    <method name="ListPackages">
+      <arg name="packages" type="a(ssssxb)" direction="out"/>
+    </method>
+    <!-- name, version, repository, archive size, installed size(-1 if unknown) -->
+    <method name="Search">
+      <arg name="pattern" type="s" direction="in"/>
+      <arg name="packages" type="a(sssxx)" direction="out"/>
+    </method>
+    <method name="Install">
+      <arg name="names" type="as" direction="in"/>
+      <arg name="installed" type="a(ss)" direction="out"/>
+      <arg name="updated" type="a(sss)" direction="out"/>
+      <arg name="deleted" type="a(ss)" direction="out"/>
+    </method>
+    <!-- Updates all of the installed packages if `names` is empty. -->
+    <method name="Update">
+      <arg name="names" type="as" direction="in"/>
+      <arg name="installed" type="a(ss)" direction="out"/>
+      <arg name="updated" type="a(sss)" direction="out"/>
+      <arg name="deleted" type="a(ss)" direction="out"/>
+    </method>
+    <method name="Delete">
+      <arg name="names" type="as" direction="in"/>
+      <arg name="installed" type="a(ss)" direction="out"/>
+      <arg name="updated" type="a(sss)" direction="out"/>
+      <arg name="deleted" type="a(ss)" direction="out"/>
+    </method>
+    <signal name="Started">
+      <arg name="operation" type="s"/>
+    </signal>
+    <signal name="Installed">
+      <arg name="name" type="s"/>
+      <arg name="version" type="s"/>
+    </signal>
+    <signal name="Updated">
+      <arg name="name" type="s"/>
+      <arg name="from_version" type="s"/>
+      <arg name="to_version" type="s"/>
+    </signal>
+    <signal name="Deleted">
+      <arg name="name" type="s"/>
+      <arg name="version" type="s"/>
+    </signal>
+    <signal name="Finished">
+      <arg name="operation" type="s"/>
+    </signal>
+  </interface>
+  <interface name="org.freedesktop.DBus.Introspectable">
+    <method name="Introspect">
+      <arg name="xml_data" type="s" direction="out"/>
+    </method>
+  </interface>
+</node>
+"#;
+
+/// Error name and text of failed method calls.
+type MethodResult = Result<Vec<Value>, (&'static str, String)>;
+
+/// Serves method calls until the connection to the bus is lost.
+pub fn run_daemon(ctx: Ctx) -> Result<(), LpmError<MainError>> {
+    let connection = Rc::new(RefCell::new(Connection::system()?));
+    connection.borrow_mut().request_name(BUS_NAME)?;
+    logger::info!("Serving '{BUS_NAME}' on the system bus.");
+
+    let mut lpm = Lpm::from_ctx(ctx);
+    let signals = connection.clone();
+    lpm.set_event_callback(move |event| {
+        if let Err(err) = signals.borrow_mut().send(event_signal(event)) {
+            logger::warning!("Failed to emit D-Bus signal: {err}");
+        }
+    });
+
+    loop {
+        let call = connection.borrow_mut().receive()?;
+        if call.message_type != MessageType::MethodCall {
+            continue;
+        }
+
+        let reply = match handle_call(&connection, &mut lpm, &call) {
+            Ok(body) => Message::method_return(&call).with_body(body),
+            Err((name, text)) => Message::error(&call, name, &text),
+        };
+
+        if call.flags & dbus::NO_REPLY_EXPECTED == 0 {
+            connection.borrow_mut().send(reply)?;
+        }
+    }
+}
+
+fn handle_call(connection: &RefCell<Connection>, lpm: &mut Lpm, call: &Message) -> MethodResult {
+    let member = call.member.as_deref().unwrap_or_default();
+
+    match call.interface.as_deref() {
+        Some("org.freedesktop.DBus.Introspectable") if member == "Introspect" => {
+            return Ok(vec![Value::from(INTROSPECTION)]);
+        }
+        Some(INTERFACE) | None if call.path.as_deref() == Some(OBJECT_PATH) => {}
+        _ => return Err(unknown_method(member)),
+    }
+
+    let failed = |err: LpmError<MainError>| (ERROR_FAILED, format!("{:?}", err.error_type));
+
+    match member {
+        "ListPackages" => {
+            let pkgs = lpm.list().map_err(failed)?;
+            Ok(vec![Value::Array(
+                String::from("(ssssxb)"),
+                pkgs.iter()
+                    .map(|pkg| {
+                        Value::Struct(vec![
+                            Value::from(pkg.name.as_str()),
+                            Value::from(pkg.version.as_str()),
+                            Value::from(pkg.arch.as_str()),
+                            Value::from(pkg.slot.as_deref().unwrap_or_default()),
+                            Value::Int64(pkg.installed_size),
+                            Value::Bool(pkg.is_dependency),
+                        ])
+                    })
+                    .collect(),
+            )])
+        }
+
+        "Search" => {
+            let pattern = call.body.first().and_then(Value::as_str).ok_or((
+                ERROR_INVALID_ARGS,
+                String::from("Expected a search pattern."),
+            ))?;
+            let pkgs = lpm.search(pattern).map_err(failed)?;
+            Ok(vec![Value::Array(
+                String::from("(sssxx)"),
+                pkgs.iter()
+                    .map(|pkg| {
+                        Value::Struct(vec![
+                            Value::from(pkg.name.as_str()),
+                            Value::from(pkg.version.as_str()),
+                            Value::from(pkg.repository.as_str()),
+                            Value::Int64(pkg.archive_size.unwrap_or(-1)),
+                            Value::Int64(pkg.installed_size.unwrap_or(-1)),
+                        ])
+                    })
+                    .collect(),
+            )])
+        }
+
+        "Install" | "Update" | "Delete" => {
+            let names = package_names(call)?;
+            let pkg_names: Vec<&str> = names.iter().map(String::as_str).collect();
+            let operation = match member {
+                "Install" => Operation::Install,
+                "Update" => Operation::Update,
+                _ => Operation::Delete,
+            };
+
+            // Packages that can't be resolved go through the administrator,
+            // and the transaction reports why.
+            let is_trusted = lpm
+                .is_from_trusted_repositories(operation, &pkg_names)
+                .unwrap_or(false);
+            let action_id = match operation {
+                Operation::Delete => DELETE_ACTION_ID,
+                _ if is_trusted => INSTALL_TRUSTED_ACTION_ID,
+                _ => INSTALL_ACTION_ID,
+            };
+            authorize(connection, call, action_id, &names)?;
+            lpm.set_initiator(Initiator::rpc_peer(caller_uid(connection, call)?));
+            lpm.set_trusted_repositories_only(is_trusted);
+
+            let changes = match operation {
+                Operation::Install => lpm.install(&pkg_names),
+                Operation::Update => lpm.update(&pkg_names),
+                Operation::Delete => lpm.delete(&pkg_names),
+            }
+            .map_err(failed)?;
+
+            Ok(changes_body(&changes))
+        }
+
+        _ => Err(unknown_method(member)),
+    }
+}
+
+fn unknown_method(member: &str) -> (&'static str, String) {
+    (
+        ERROR_UNKNOWN_METHOD,
+        format!("No method '{member}' on '{OBJECT_PATH}'."),
+    )
+}
+
+fn package_names(call: &Message) -> Result<Vec<String>, (&'static str, String)> {
+    call.body
+        .first()
+        .and_then(Value::as_slice)
+        .and_then(|names| {
+            names
+                .iter()
+                .map(|name| name.as_str().map(str::to_owned))
+                .collect()
+        })
+        .ok_or((
+            ERROR_INVALID_ARGS,
+            String::from("Expected an array of package names."),
+        ))
+}
+
+/// Uid of the caller, which the transactions are audited as.
+fn caller_uid(
+    connection: &RefCell<Connection>,
+    call: &Message,
+) -> Result<u32, (&'static str, String)> {
+    let sender = call.sender.as_deref().unwrap_or_default();
+    connection
+        .borrow_mut()
+        .unix_user(sender)
+        .map_err(|err| (ERROR_FAILED, format!("Caller can't be identified: {err}")))
+}
+
+/// Asks polkit whether the caller may run `action_id` on `names`, letting it
+/// prompt for a password if the caller allows interactive authorization.
+fn authorize(
+    connection: &RefCell<Connection>,
+    call: &Message,
+    action_id: &str,
+    names: &[String],
+) -> Result<(), (&'static str, String)> {
+    const ALLOW_USER_INTERACTION: u32 = 0x1;
+
+    let sender = call.sender.clone().unwrap_or_default();
+    let subject = Value::Struct(vec![
+        Value::from("system-bus-name"),
+        Value::Array(
+            String::from("{sv}"),
+            vec![Value::DictEntry(
+                Box::new(Value::from("name")),
+                Box::new(Value::Variant(Box::new(Value::from(sender)))),
+            )],
+        ),
+    ]);
+    let flags = if call.flags & ALLOW_INTERACTIVE_AUTHORIZATION != 0 {
+        ALLOW_USER_INTERACTION
+    } else {
+        0
+    };
+
+    let check = Message::method_call(
+        "org.freedesktop.PolicyKit1",
+        "/org/freedesktop/PolicyKit1/Authority",
+        "org.freedesktop.PolicyKit1.Authority",
+        "CheckAuthorization",
+    )
+    .with_body(vec![
+        subject,
+        Value::from(action_id),
+        Value::Array(
+            String::from("{ss}"),
+            vec![Value::DictEntry(
+                Box::new(Value::from("packages")),
+                Box::new(Value::from(names.join(","))),
+            )],
+        ),
+        Value::UInt32(flags),
+        Value::from(""),
+    ]);
+
+    let reply = connection.borrow_mut().call(check).map_err(|err| {
+        (
+            ERROR_ACCESS_DENIED,
+            format!("Authorization check failed: {err}"),
+        )
+    })?;
+
+    // (is_authorized, is_challenge, details)
+    let is_authorized = reply
+        .body
+        .first()
+        .and_then(Value::as_slice)
+        .and_then(|result| result.first())
+        .and_then(Value::as_bool);
+
+    if is_authorized != Some(true) {
+        return Err((
+            ERROR_ACCESS_DENIED,
+            format!("Not authorized for '{action_id}'."),
+        ));
+    }
+
+    Ok(())
+}
+
+fn changes_body(changes: &Changes) -> Vec<Value> {
+    let name_and_version = |pkg: &PackageInfo| {
+        Value::Struct(vec![
+            Value::from(pkg.name.as_str()),
+            Value::from(pkg.version.as_str()),
+        ])
+    };
+
+    vec![
+        Value::Array(
+            String::from("(ss)"),
+            changes.installed.iter().map(name_and_version).collect(),
+        ),
+        Value::Array(
+            String::from("(sss)"),
+            changes
+                .updated
+                .iter()
+                .map(|(from, to)| {
+                    Value::Struct(vec![
+                        Value::from(to.name.as_str()),
+                        Value::from(from.version.as_str()),
+                        Value::from(to.version.as_str()),
+                    ])
+                })
+                .collect(),
+        ),
+        Value::Array(
+            String::from("(ss)"),
+            changes.deleted.iter().map(name_and_version).collect(),
+        ),
+    ]
+}
+
+fn event_signal(event: &Event) -> Message {
+    let (member, body) = match event {
+        Event::Started(operation) => ("Started", vec![Value::from(operation.as_str())]),
+        Event::Installed(pkg) => (
+            "Installed",
+            vec![
+                Value::from(pkg.name.as_str()),
+                Value::from(pkg.version.as_str()),
+            ],
+        ),
+        Event::Updated { from, to } => (
+            "Updated",
+            vec![
+                Value::from(to.name.as_str()),
+                Value::from(from.version.as_str()),
+                Value::from(to.version.as_str()),
+            ],
+        ),
+        Event::Deleted(pkg) => (
+            "Deleted",
+            vec![
+                Value::from(pkg.name.as_str()),
+                Value::from(pkg.version.as_str()),
+            ],
+        ),
+        Event::Finished(operation) => ("Finished", vec![Value::from(operation.as_str())]),
+    };
+
+    Message::signal(OBJECT_PATH, INTERFACE, member).with_body(body)
+}
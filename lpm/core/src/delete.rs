@@ -1,81 +1,316 @@
 use crate::{
-    stage1::{get_scripts, Stage1Tasks, PKG_SCRIPTS_DIR},
+    alternatives::refresh_alternatives,
+    in_transaction,
+    protect::ProtectedPaths,
+    read_package_list,
+    stage1::{get_installed_scripts, pkg_lib_dir, Stage1Tasks},
     Ctx,
 };
 
 use cli_parser::DeleteArgs;
 use common::{
     ctx_confirmation_check,
+    event::EventSink,
+    interrupt,
+    meta::FileKind,
     pkg::{PkgDataFromDb, ScriptPhase},
+    stats,
 };
 use db::{
-    enable_core_db_wal1, enable_foreign_keys, pkg::DbOpsForInstalledPkg, transaction_op,
-    Transaction,
+    delete_retained_config_files, enable_core_db_wal1, enable_foreign_keys, get_dependents,
+    get_package_alternatives, get_retained_config_files,
+    pkg::{load_group, set_group_id, DbOpsForInstalledPkg},
 };
 use ehandle::{lpm::LpmError, pkg::PackageErrorKind, ErrorCommons, MainError};
 use logger::{info, warning};
 use min_sqlite3_sys::prelude::Database;
-use std::{fs, path::Path, sync::Arc, thread};
+use std::{collections::HashSet, fs, path::Path};
 
 trait PkgDeleteTasks {
-    fn start_delete_task(&self, core_db: &Database) -> Result<(), LpmError<MainError>>;
+    fn delete_files_from_system(&self, purge: bool) -> Result<(), LpmError<MainError>>;
 }
 
 impl PkgDeleteTasks for PkgDataFromDb {
-    fn start_delete_task(&self, core_db: &Database) -> Result<(), LpmError<MainError>> {
-        // Enable constraits to remove records that are related with package
-        enable_foreign_keys(core_db)?;
+    /// Config files are kept unless `purge` is set.
+    fn delete_files_from_system(&self, purge: bool) -> Result<(), LpmError<MainError>> {
+        for file in &self.meta_fields.files.0 {
+            if !purge && file.kind(&self.meta_fields.meta.config_files) == FileKind::Config {
+                info!(
+                    "Keeping config file {} of '{}', use '--purge' to delete it.",
+                    file.path, self.meta_fields.meta.name
+                );
+                continue;
+            }
 
-        transaction_op(core_db, Transaction::Begin)?;
+            let pkg_path = file.pkg_path()?;
+            let path = pkg_path.under(Path::new("/"));
+            if path.exists() {
+                fs::remove_file(&path)?;
+                stats::record_removed_file();
+            } else {
+                warning!("Path -> {pkg_path} <- is not exists");
+            }
+        }
 
-        let pkg_lib_dir = Path::new(PKG_SCRIPTS_DIR).join(&self.meta_fields.meta.name);
-        let scripts = get_scripts(&pkg_lib_dir.join("scripts"))?;
+        let pkg_lib_dir = pkg_lib_dir(Path::new("/"), &self.meta_fields.meta);
+        if pkg_lib_dir.exists() {
+            fs::remove_dir_all(pkg_lib_dir)?;
+        }
+
+        Ok(())
+    }
+}
 
-        if let Err(err) = scripts.execute_script(vec![], ScriptPhase::PreDelete) {
-            transaction_op(core_db, Transaction::Rollback)?;
-            return Err(err);
+/// Removes config files that were kept from an earlier deletion of `pkg_name`.
+fn purge_retained_config_files(
+    core_db: &Database,
+    pkg_name: &str,
+    paths: &[String],
+) -> Result<(), LpmError<MainError>> {
+    for path in paths {
+        if Path::new(path).exists() {
+            fs::remove_file(path)?;
+            stats::record_removed_file();
         }
+    }
+
+    delete_retained_config_files(core_db, pkg_name)?;
+
+    Ok(())
+}
 
-        info!("Syncing with package database..");
-        if self.delete_from_db(core_db).is_err() {
-            transaction_op(core_db, Transaction::Rollback)?;
+/// Deletes all of `pkgs` within the transaction that the caller began. Files
+/// are removed only after every package is gone from the database, so a
+/// failing package leaves the system untouched up to that point.
+fn delete_in_transaction(
+    core_db: &Database,
+    pkgs: &[PkgDataFromDb],
+    retained: &[(String, Vec<String>)],
+    purge: bool,
+    events: &dyn EventSink,
+) -> Result<(), LpmError<MainError>> {
+    let mut scripts = Vec::with_capacity(pkgs.len());
+    for pkg in pkgs {
+        scripts.push(get_installed_scripts(&pkg.meta_fields.meta)?);
+    }
+
+    for (pkg, pkg_scripts) in pkgs.iter().zip(&scripts) {
+        pkg_scripts.execute_script(
+            Path::new("/"),
+            vec![],
+            ScriptPhase::PreDelete,
+            &pkg.meta_fields.meta.name,
+            events,
+        )?;
+    }
 
+    info!("Syncing with package database..");
+    let mut links = vec![];
+    for pkg in pkgs {
+        links.extend(get_package_alternatives(core_db, pkg.pkg_id)?);
+
+        if pkg.delete_from_db(core_db).is_err() {
             return Err(
-                PackageErrorKind::DeletionFailed(self.meta_fields.meta.name.clone()).to_lpm_err(),
+                PackageErrorKind::DeletionFailed(pkg.meta_fields.meta.name.clone()).to_lpm_err(),
             )?;
-        };
-
-        info!("Deleting package files from system..");
-        for file in &self.meta_fields.files.0 {
-            if Path::new(&file.path).exists() {
-                fs::remove_file(&file.path)?;
-            } else {
-                warning!("Path -> {} <- is not exists", file.path);
-            }
         }
+    }
 
-        if Path::new(&pkg_lib_dir).exists() {
-            fs::remove_dir_all(pkg_lib_dir)?;
-        }
+    info!("Deleting package files from system..");
+    for pkg in pkgs {
+        pkg.delete_files_from_system(purge)?;
 
-        if let Err(err) = scripts.execute_script(vec![], ScriptPhase::PostDelete) {
-            transaction_op(core_db, Transaction::Rollback)?;
-            return Err(err);
+        if purge {
+            delete_retained_config_files(core_db, &pkg.meta_fields.meta.name)?;
         }
+    }
 
-        transaction_op(core_db, Transaction::Commit)?;
-        info!("Deletion transaction completed.");
+    for (pkg_name, paths) in retained {
+        purge_retained_config_files(core_db, pkg_name, paths)?;
+    }
 
-        Ok(())
+    links.sort();
+    links.dedup();
+    refresh_alternatives(Path::new("/"), core_db, &links)?;
+
+    for (pkg, pkg_scripts) in pkgs.iter().zip(&scripts) {
+        pkg_scripts.execute_script(
+            Path::new("/"),
+            vec![],
+            ScriptPhase::PostDelete,
+            &pkg.meta_fields.meta.name,
+            events,
+        )?;
     }
+
+    Ok(())
 }
 
-pub fn delete_packages(ctx: Ctx, args: &DeleteArgs) -> Result<(), LpmError<MainError>> {
+pub fn delete_packages(ctx: &Ctx, args: &DeleteArgs) -> Result<(), LpmError<MainError>> {
     enable_core_db_wal1(&ctx.core_db)?;
 
+    let listed_pkgs = match args.from_file {
+        Some(path) => read_package_list(path)?,
+        None => Vec::new(),
+    };
+
+    let mut pkg_names = args.packages.clone();
+    pkg_names.extend(listed_pkgs.iter().map(String::as_str));
+
+    // All the packages are deleted in a single transaction, so the same one
+    // must not be listed twice (e.g. both on the command line and in a file).
     let mut pkgs = vec![];
-    for pkg_name in &args.packages {
-        pkgs.push(PkgDataFromDb::load(&ctx.core_db, pkg_name)?);
+    let mut pkg_ids = HashSet::new();
+    // Packages that are already deleted but still have config files to purge.
+    let mut retained = vec![];
+    for pkg_name in &pkg_names {
+        let pkg = match PkgDataFromDb::load(&ctx.core_db, pkg_name) {
+            Ok(pkg) => pkg,
+            Err(err) => {
+                let paths = if args.purge {
+                    get_retained_config_files(&ctx.core_db, pkg_name)?
+                } else {
+                    Vec::new()
+                };
+
+                if paths.is_empty() {
+                    return Err(err)?;
+                }
+
+                retained.push((pkg_name.to_string(), paths));
+                continue;
+            }
+        };
+
+        if pkg_ids.insert(pkg.pkg_id) {
+            pkgs.push(pkg);
+        }
+    }
+
+    // The packages depending on the deleted ones go too, and so do the ones
+    // depending on those, until nothing else is pulled in. Dependencies belong
+    // to the group of the package that pulled them in, so its main package is
+    // one depending on them.
+    if args.cascade {
+        loop {
+            let ids: Vec<i64> = pkgs.iter().map(|pkg| pkg.pkg_id).collect();
+            let mut dependents = vec![];
+            for pkg in &pkgs {
+                if pkg.meta_fields.meta.get_group_id() != pkg.group_id {
+                    dependents.extend(
+                        load_group(&ctx.core_db, &pkg.group_id)?
+                            .into_iter()
+                            .filter(|t| t.meta_fields.meta.get_group_id() == t.group_id),
+                    );
+                }
+
+                for (pkg_id, group_id) in get_dependents(&ctx.core_db, pkg.pkg_id, &ids)? {
+                    dependents.extend(
+                        load_group(&ctx.core_db, &group_id)?
+                            .into_iter()
+                            .filter(|t| t.pkg_id == pkg_id),
+                    );
+                }
+            }
+
+            let count = pkgs.len();
+            for pkg in dependents {
+                if pkg_ids.insert(pkg.pkg_id) {
+                    pkgs.push(pkg);
+                }
+            }
+            if pkgs.len() == count {
+                break;
+            }
+        }
+    }
+
+    // Group ids of the main packages being deleted, their dependencies can go
+    // along with them.
+    let deleted_groups: HashSet<String> = pkgs
+        .iter()
+        .filter(|pkg| pkg.meta_fields.meta.get_group_id() == pkg.group_id)
+        .map(|pkg| pkg.group_id.clone())
+        .collect();
+
+    // Dependencies kept for other packages, moved to the group of one of them.
+    let mut regrouped = vec![];
+    if args.recursive {
+        let mut dependency_ids = HashSet::new();
+        for group_id in &deleted_groups {
+            for pkg in load_group(&ctx.core_db, group_id)? {
+                if pkg_ids.insert(pkg.pkg_id) {
+                    dependency_ids.insert(pkg.pkg_id);
+                    pkgs.push(pkg);
+                }
+            }
+        }
+
+        // Keeping one of them may keep the ones it depends on as well.
+        loop {
+            let ids: Vec<i64> = pkgs.iter().map(|pkg| pkg.pkg_id).collect();
+            let mut kept = None;
+            for (i, pkg) in pkgs.iter().enumerate() {
+                if !dependency_ids.contains(&pkg.pkg_id) {
+                    continue;
+                }
+                if let Some((_, group_id)) = get_dependents(&ctx.core_db, pkg.pkg_id, &ids)?
+                    .into_iter()
+                    .next()
+                {
+                    kept = Some((i, group_id));
+                    break;
+                }
+            }
+
+            let Some((i, group_id)) = kept else {
+                break;
+            };
+            let pkg = pkgs.remove(i);
+            pkg_ids.remove(&pkg.pkg_id);
+            info!(
+                "Keeping {}, {group_id} depends on it.",
+                pkg.meta_fields.meta.get_group_id()
+            );
+            regrouped.push((pkg.pkg_id, group_id));
+        }
+    }
+
+    if let Some(pkg) = pkgs
+        .iter()
+        .find(|pkg| !deleted_groups.contains(&pkg.group_id))
+    {
+        return Err(PackageErrorKind::DependencyOfAnotherPackage {
+            package: pkg.meta_fields.meta.name.clone(),
+            depends_on: pkg.group_id.clone(),
+        }
+        .to_lpm_err())?;
+    }
+
+    if !args.force_essential {
+        if let Some(pkg) = pkgs.iter().find(|pkg| {
+            let name = &pkg.meta_fields.meta.name;
+            pkg.meta_fields.meta.essential || ctx.config.essential_packages.contains(name)
+        }) {
+            return Err(
+                PackageErrorKind::EssentialPackage(pkg.meta_fields.meta.name.clone()).to_lpm_err(),
+            )?;
+        }
+    }
+
+    let protected = ProtectedPaths::new(&ctx.config.protected_paths)?;
+    for pkg in &pkgs {
+        protected.check(
+            &pkg.meta_fields.meta.name,
+            pkg.meta_fields
+                .files
+                .0
+                .iter()
+                .map(|file| file.path.as_str()),
+        )?;
+    }
+    for (pkg_name, paths) in &retained {
+        protected.check(pkg_name, paths.iter().map(String::as_str))?;
     }
 
     {
@@ -85,34 +320,39 @@ pub fn delete_packages(ctx: Ctx, args: &DeleteArgs) -> Result<(), LpmError<MainE
         // use colors
         println!("\nPackage list to be deleted:");
         pkgs.iter().for_each(|pkg| {
-            println!("  - {}", pkg.meta_fields.meta.get_group_id());
+            let group_id = pkg.meta_fields.meta.get_group_id();
+            if group_id == pkg.group_id {
+                println!("  - {group_id}");
+            } else {
+                println!("  - {group_id} (dependency of {})", pkg.group_id);
+            }
+        });
+        retained.iter().for_each(|(pkg_name, _)| {
+            println!("  - {pkg_name} (config files only)");
         });
         println!();
     }
 
     ctx_confirmation_check!(ctx);
+    interrupt::install_handlers();
 
-    thread::scope(|s| -> Result<(), LpmError<MainError>> {
-        pkgs.iter().for_each(|pkg| {
-            let core_db = Arc::new(&ctx.core_db);
-            s.spawn(move || -> Result<(), LpmError<MainError>> {
-                if pkg.meta_fields.meta.get_group_id() != pkg.group_id {
-                    return Err(PackageErrorKind::DependencyOfAnotherPackage {
-                        package: pkg.meta_fields.meta.name.clone(),
-                        depends_on: pkg.group_id.clone(),
-                    }
-                    .to_lpm_err())?;
-                };
-
-                info!("Package deletion started for {}", pkg.meta_fields.meta.name);
-                pkg.start_delete_task(&core_db)?;
+    // Enable constraits to remove records that are related with package
+    enable_foreign_keys(&ctx.core_db)?;
 
-                Ok(())
-            });
-        });
+    in_transaction(&ctx.core_db, || {
+        for (pkg_id, group_id) in &regrouped {
+            set_group_id(&ctx.core_db, *pkg_id, group_id)?;
+        }
 
-        Ok(())
+        delete_in_transaction(
+            &ctx.core_db,
+            &pkgs,
+            &retained,
+            args.purge,
+            ctx.events.as_ref(),
+        )
     })?;
+    info!("Deletion transaction completed.");
 
     Ok(())
 }
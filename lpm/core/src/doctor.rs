@@ -0,0 +1,236 @@
+//! `lpm --doctor`, a health check of everything lpm leaves on the system,
+//! with a suggested fix for each problem it finds.
+
+use crate::{
+    extract::extracted_packages, offline_update::SYSTEM_UPDATE_LINK, Ctx, AUTO_UPDATE_HISTORY_PATH,
+    DEFAULT_RPC_SOCKET_PATH, EXTRACTION_OUTPUT_PATH, STAGED_UPDATES_DIR,
+};
+
+use common::{meta::FileKind, pkg::PkgDataFromDb};
+use db::{pkg::DbOpsForInstalledPkg, REPOSITORY_INDEX_DB_DIR};
+use ehandle::{lpm::LpmError, MainError};
+use json::Json;
+use logger::{info, success, warning};
+use min_sqlite3_sys::prelude::*;
+use std::{fs, io, os::unix::net::UnixStream, path::Path};
+
+#[derive(Debug, PartialEq)]
+struct Finding {
+    problem: String,
+    fix: String,
+}
+
+impl Finding {
+    fn new(problem: String, fix: String) -> Self {
+        Self { problem, fix }
+    }
+}
+
+type Check = fn(&Ctx) -> Result<Vec<Finding>, LpmError<MainError>>;
+
+const CHECKS: [(&str, Check); 7] = [
+    ("database integrity", check_database_integrity),
+    ("package files", check_package_files),
+    ("interrupted transactions", check_interrupted_transactions),
+    ("stale sockets", check_stale_sockets),
+    ("temporary files", check_temporary_files),
+    ("repositories", check_repositories),
+    ("modules", check_modules),
+];
+
+/// Runs all the checks, returns whether any of them found a problem.
+pub fn run_doctor(ctx: &Ctx) -> Result<bool, LpmError<MainError>> {
+    let mut count = 0;
+    for (name, check) in CHECKS {
+        info!("Checking {name}..");
+        for finding in check(ctx)? {
+            warning!("{}", finding.problem);
+            println!("  Fix: {}", finding.fix);
+            count += 1;
+        }
+    }
+
+    if count == 0 {
+        success!("No problems found.");
+    } else {
+        warning!("{count} problems found.");
+    }
+
+    Ok(count > 0)
+}
+
+fn check_database_integrity(ctx: &Ctx) -> Result<Vec<Finding>, LpmError<MainError>> {
+    Ok(db::check_integrity(&ctx.core_db)?
+        .into_iter()
+        .map(|problem| {
+            Finding::new(
+                problem,
+                String::from("Restore the package database from a backup."),
+            )
+        })
+        .collect())
+}
+
+fn check_package_files(ctx: &Ctx) -> Result<Vec<Finding>, LpmError<MainError>> {
+    let mut findings = Vec::new();
+    for pkg in PkgDataFromDb::load_all_packages(&ctx.core_db)? {
+        // Documentation and licenses are often removed on purpose, e.g. from
+        // minimal images, so they don't need a reinstall.
+        let config_files = &pkg.meta_fields.meta.config_files;
+        let missing: Vec<&str> = pkg
+            .meta_fields
+            .files
+            .0
+            .iter()
+            .filter(|t| !matches!(t.kind(config_files), FileKind::Doc | FileKind::License))
+            .map(|t| t.path.as_str())
+            .filter(|t| fs::symlink_metadata(t).is_err())
+            .collect();
+
+        if let Some(first) = missing.first() {
+            let name = &pkg.meta_fields.meta.name;
+            findings.push(Finding::new(
+                format!(
+                    "{} files of '{name}' are missing, e.g. {first}",
+                    missing.len()
+                ),
+                format!("Reinstall '{name}' with `lpm --delete {name} && lpm --install {name}`."),
+            ));
+        }
+    }
+
+    Ok(findings)
+}
+
+fn check_interrupted_transactions(_ctx: &Ctx) -> Result<Vec<Finding>, LpmError<MainError>> {
+    let mut findings = Vec::new();
+
+    // The link is removed before the staged updates are applied, and the
+    // updates once they are.
+    let staged_dir = Path::new(STAGED_UPDATES_DIR);
+    if staged_dir.exists() && fs::symlink_metadata(SYSTEM_UPDATE_LINK).is_err() {
+        findings.push(Finding::new(
+            format!("The offline updates in {STAGED_UPDATES_DIR} were not applied completely."),
+            format!(
+                "Stage them again with `lpm --update --stage`, or remove {STAGED_UPDATES_DIR}."
+            ),
+        ));
+    }
+
+    match fs::read_to_string(AUTO_UPDATE_HISTORY_PATH) {
+        Ok(history) => findings.extend(last_auto_update_failure(&history)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e)?,
+    }
+
+    Ok(findings)
+}
+
+/// The last run of `lpm --auto-update` in its history, if it failed.
+fn last_auto_update_failure(history: &str) -> Option<Finding> {
+    let last_run = Json::new(history.lines().last()?).parse().ok()?;
+    if last_run["status"].to_string().as_deref() != Some("failure") {
+        return None;
+    }
+
+    Some(Finding::new(
+        format!(
+            "The last automatic update failed: {}",
+            last_run["error"].to_string().unwrap_or_default()
+        ),
+        format!("See {AUTO_UPDATE_HISTORY_PATH}, then run `lpm --auto-update` again."),
+    ))
+}
+
+fn check_stale_sockets(_ctx: &Ctx) -> Result<Vec<Finding>, LpmError<MainError>> {
+    let socket_path = Path::new(DEFAULT_RPC_SOCKET_PATH);
+    if !socket_path.exists() {
+        return Ok(Vec::new());
+    }
+
+    match UnixStream::connect(socket_path) {
+        Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => Ok(vec![Finding::new(
+            format!("{DEFAULT_RPC_SOCKET_PATH} is left from a stopped 'lpm --rpc-daemon'."),
+            format!("Remove {DEFAULT_RPC_SOCKET_PATH}."),
+        )]),
+        _ => Ok(Vec::new()),
+    }
+}
+
+fn check_temporary_files(_ctx: &Ctx) -> Result<Vec<Finding>, LpmError<MainError>> {
+    Ok(extracted_packages(Path::new(EXTRACTION_OUTPUT_PATH))?
+        .into_iter()
+        .map(|path| {
+            Finding::new(
+                format!("{} is left from an earlier transaction.", path.display()),
+                format!(
+                    "Remove it with `rm -r {}` when no lpm is running.",
+                    path.display()
+                ),
+            )
+        })
+        .collect())
+}
+
+fn check_repositories(ctx: &Ctx) -> Result<Vec<Finding>, LpmError<MainError>> {
+    let mut findings = Vec::new();
+    for (name, _) in db::get_repositories(&ctx.core_db)? {
+        let index_db_path = Path::new(REPOSITORY_INDEX_DB_DIR).join(&name);
+        let problem = if !index_db_path.exists() {
+            Some(String::from("has no index"))
+        } else {
+            match Database::open(&index_db_path).map(|t| db::check_integrity(&t)) {
+                Ok(Ok(problems)) if problems.is_empty() => None,
+                Ok(Ok(problems)) => Some(format!("has a corrupt index: {}", problems.join(", "))),
+                Ok(Err(e)) => Some(format!("has an unreadable index: {:?}", e.error_type)),
+                Err(e) => Some(format!("has an unreadable index: {e:?}")),
+            }
+        };
+
+        if let Some(problem) = problem {
+            findings.push(Finding::new(
+                format!("Repository '{name}' {problem}"),
+                format!(
+                    "Sync it with `lpm --update --index`, or remove {}.",
+                    index_db_path.display()
+                ),
+            ));
+        }
+    }
+
+    Ok(findings)
+}
+
+fn check_modules(ctx: &Ctx) -> Result<Vec<Finding>, LpmError<MainError>> {
+    Ok(db::get_modules(&ctx.core_db)?
+        .into_iter()
+        .filter(|(_, dylib_path)| !Path::new(dylib_path).exists())
+        .map(|(name, dylib_path)| {
+            Finding::new(
+                format!("The library of module '{name}', {dylib_path}, is missing."),
+                format!("Add it again with `lpm --module --add {name} <Dylib Path>`, or remove it with `lpm --module --delete {name}`."),
+            )
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_last_auto_update_failure() {
+        assert_eq!(last_auto_update_failure(""), None);
+
+        let success = r#"{"started_at": 1, "status": "success", "error": null}"#;
+        let failure = r#"{"started_at": 2, "status": "failure", "error": "timed out"}"#;
+        assert_eq!(
+            last_auto_update_failure(&format!("{success}\n{failure}\n")).map(|t| t.problem),
+            Some(String::from("The last automatic update failed: timed out"))
+        );
+        assert_eq!(
+            last_auto_update_failure(&format!("{failure}\n{success}\n")),
+            None
+        );
+    }
+}
@@ -0,0 +1,81 @@
+use crate::Ctx;
+
+use common::{
+    pkg::PkgDataFromDb,
+    size::{disk_usage, format_byte_size},
+};
+use db::pkg::DbOpsForInstalledPkg;
+use ehandle::{lpm::LpmError, MainError};
+use std::{collections::BTreeMap, io, path::Path};
+
+/// Prints the disk usage of `pkg_name` grouped by directories. Uses the sizes
+/// recorded on installation unless `stat` is set, or a size is missing.
+pub fn print_disk_usage(ctx: Ctx, pkg_name: &str, stat: bool) -> Result<(), LpmError<MainError>> {
+    let pkg = PkgDataFromDb::load(&ctx.core_db, pkg_name)?;
+
+    let mut usage: BTreeMap<String, u64> = BTreeMap::new();
+    let mut missing = 0;
+    for file in &pkg.meta_fields.files.0 {
+        let size = match file.size {
+            Some(size) if !stat => size,
+            _ => match disk_usage(&file.pkg_path()?.under(Path::new("/"))) {
+                Ok(size) => size,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    missing += 1;
+                    continue;
+                }
+                Err(e) => return Err(e)?,
+            },
+        };
+
+        *usage.entry(top_level_dir(&file.path)).or_default() += size;
+    }
+
+    let width = usage.keys().map(|t| t.len()).max().unwrap_or(0);
+    for (dir, size) in &usage {
+        println!("  {dir:<width$}  {:>10}", format_byte_size(*size));
+    }
+
+    let total: u64 = usage.values().sum();
+    println!(
+        "\n  {} ({} files): {}",
+        pkg.meta_fields.meta.get_group_id(),
+        pkg.meta_fields.files.0.len(),
+        format_byte_size(total)
+    );
+
+    if missing > 0 {
+        logger::warning!("{missing} file(s) of '{pkg_name}' are missing on the filesystem.");
+    }
+
+    Ok(())
+}
+
+/// Parent directory of `path`, cut to its first two components
+/// (e.g. `/usr/share/doc/htop/README` -> `/usr/share`).
+fn top_level_dir(path: &str) -> String {
+    let parent = Path::new(path)
+        .parent()
+        .and_then(|t| t.to_str())
+        .unwrap_or_default();
+
+    let dir: Vec<&str> = parent
+        .split('/')
+        .filter(|t| !t.is_empty())
+        .take(2)
+        .collect();
+    format!("/{}", dir.join("/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_top_level_dir() {
+        assert_eq!(top_level_dir("/usr/share/doc/htop/README"), "/usr/share");
+        assert_eq!(top_level_dir("/usr/bin/htop"), "/usr/bin");
+        assert_eq!(top_level_dir("/etc/htoprc"), "/etc");
+        assert_eq!(top_level_dir("/htop"), "/");
+    }
+}
@@ -1,45 +1,125 @@
-use crate::stage1::get_scripts;
+use crate::{
+    lod::{read_layout, section_decoder, FrameDecoder, Layout},
+    stage1::get_scripts,
+};
 
 use common::{
+    compression::{Algorithm, Compression},
+    config::ExtractionLimits,
+    event::EventSink,
+    interrupt,
     pkg::{MetaDir, PkgDataFromFs},
+    size::format_byte_size,
     system::System,
     ParserTasks,
 };
-use ehandle::lpm::LpmError;
+use ehandle::{lpm::LpmError, pkg::PackageErrorKind, ErrorCommons, MainError};
+use json::{Json, JsonValue};
 use logger::debug;
 use std::{
-    fs::File,
-    io,
-    path::{Path, PathBuf},
+    borrow::Cow,
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom},
+    path::{Component, Path, PathBuf},
 };
+use untar::EntryType;
 
 pub(crate) trait PkgExtractTasks {
-    fn start_extract_task(pkg_path: &Path) -> Result<Self, LpmError<io::Error>>
+    fn start_extract_task(
+        pkg_path: &Path,
+        limits: &ExtractionLimits,
+        events: &dyn EventSink,
+    ) -> Result<Self, LpmError<MainError>>
     where
         Self: Sized;
-    fn unpack_and_decompress(pkg_path: &Path) -> Result<(), LpmError<io::Error>>;
+    fn unpack_and_decompress(
+        pkg_path: &Path,
+        limits: &ExtractionLimits,
+        events: &dyn EventSink,
+    ) -> Result<(), LpmError<MainError>>;
     fn read_pkg_data(pkg_path: &Path) -> Result<PkgDataFromFs, LpmError<io::Error>>;
 }
 
+/// Reports how much of the archive has been read, the decoder and the
+/// unpacking don't know the uncompressed size ahead.
+struct ProgressReader<'a, R> {
+    file: R,
+    pkg_path: &'a Path,
+    read: u64,
+    total: u64,
+    events: &'a dyn EventSink,
+}
+
+impl<R: Read> Read for ProgressReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.file.read(buf)?;
+        self.read += n as u64;
+        self.events
+            .extraction_progress(self.pkg_path, self.read, self.total);
+        Ok(n)
+    }
+}
+
+impl<R: Seek> Seek for ProgressReader<'_, R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.read = self.file.seek(pos)?;
+        Ok(self.read)
+    }
+}
+
 impl PkgExtractTasks for PkgDataFromFs {
-    fn start_extract_task(pkg_path: &Path) -> Result<Self, LpmError<io::Error>>
+    fn start_extract_task(
+        pkg_path: &Path,
+        limits: &ExtractionLimits,
+        events: &dyn EventSink,
+    ) -> Result<Self, LpmError<MainError>>
     where
         Self: Sized,
     {
-        PkgDataFromFs::unpack_and_decompress(pkg_path)?;
+        PkgDataFromFs::unpack_and_decompress(pkg_path, limits, events)?;
         let pkg_data = PkgDataFromFs::read_pkg_data(pkg_path)?;
 
         Ok(pkg_data)
     }
 
-    fn unpack_and_decompress(pkg_path: &Path) -> Result<(), LpmError<io::Error>> {
-        let compressed_pkg_file = File::open(pkg_path)?;
-        let mut archive =
-            untar::Archive::new(tiny_lz4_decoder_sys::Decoder::new(compressed_pkg_file)?);
+    fn unpack_and_decompress(
+        pkg_path: &Path,
+        limits: &ExtractionLimits,
+        events: &dyn EventSink,
+    ) -> Result<(), LpmError<MainError>> {
+        let mut file = File::open(pkg_path)?;
+        let total = file.metadata()?.len();
         let tmp_dir = get_pkg_tmp_output_path(pkg_path);
+        let mut budget = ExtractionBudget::new(limits);
 
         debug!("Extracting {} -> {}", pkg_path.display(), tmp_dir.display());
-        archive.unpack(&tmp_dir)?;
+        match read_layout(&mut file)? {
+            Layout::V1 => {
+                file.rewind()?;
+                let compressed_pkg_file = ProgressReader {
+                    file,
+                    pkg_path,
+                    read: 0,
+                    total,
+                    events,
+                };
+                let decoder = FrameDecoder::new(compressed_pkg_file)?;
+                unpack_archive(decoder, pkg_path, &tmp_dir, &mut budget)?;
+            }
+            Layout::V2(sections) => {
+                for section in &sections {
+                    let compressed_section = ProgressReader {
+                        file: &file,
+                        pkg_path,
+                        read: 0,
+                        total,
+                        events,
+                    };
+                    let decoder = section_decoder(compressed_section, section)?;
+                    unpack_archive(decoder, pkg_path, &tmp_dir, &mut budget)?;
+                }
+            }
+        }
 
         Ok(())
     }
@@ -55,25 +135,444 @@ impl PkgExtractTasks for PkgDataFromFs {
             meta_dir.display(),
             meta_dir.display()
         );
-        let meta_dir = MetaDir::new(&meta_dir);
+        let meta_dir = MetaDir::new(&meta_dir).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Package has invalid meta data, {e}"),
+            )
+        })?;
 
         debug!("Getting stage1 scripts");
-        let scripts = get_scripts(&pkg_tmp_output_dir.join("scripts"))?;
+        let scripts = get_scripts(
+            &pkg_tmp_output_dir.join("scripts"),
+            meta_dir.meta.scripts.as_deref(),
+        )?;
 
         debug!("Reading system data from {}", system_json.display());
-        let system = System::deserialize(&system_json.to_string_lossy());
+        let system = System::deserialize(&system_json);
 
         Ok(PkgDataFromFs {
             path: pkg_path.to_path_buf(),
             meta_dir,
             scripts,
             system,
+            origin: None,
+        })
+    }
+}
+
+/// How much of the `ExtractionLimits` the entries of a package used up.
+struct ExtractionBudget<'a> {
+    limits: &'a ExtractionLimits,
+    size: u64,
+    files: u64,
+    program_size: u64,
+    /// `installed_size` of `meta/meta.json`, once it's extracted.
+    declared_size: Option<u64>,
+}
+
+impl<'a> ExtractionBudget<'a> {
+    fn new(limits: &'a ExtractionLimits) -> Self {
+        Self {
+            limits,
+            size: 0,
+            files: 0,
+            program_size: 0,
+            declared_size: None,
+        }
+    }
+
+    /// Accounts for the entry at `path`, returns why the package is over the
+    /// limits if it is.
+    fn spend(&mut self, path: &Path, size: u64) -> Option<String> {
+        self.files += 1;
+        self.size = self.size.saturating_add(size);
+        if path.starts_with("program") {
+            self.program_size = self.program_size.saturating_add(size);
+        }
+
+        if size > self.limits.max_file_size {
+            return Some(format!(
+                "'{}' is larger than the {} limit of a single file",
+                path.display(),
+                format_byte_size(self.limits.max_file_size)
+            ));
+        }
+
+        if self.files > self.limits.max_files {
+            return Some(format!(
+                "it has more than {} entries",
+                self.limits.max_files
+            ));
+        }
+
+        if self.size > self.limits.max_size {
+            return Some(format!(
+                "it's larger than the {} limit",
+                format_byte_size(self.limits.max_size)
+            ));
+        }
+
+        self.check_declared_size()
+    }
+
+    fn check_declared_size(&self) -> Option<String> {
+        let declared_size = self.declared_size?;
+        (self.program_size > declared_size).then(|| {
+            format!(
+                "its files are larger than the installed size of {} it declares",
+                format_byte_size(declared_size)
+            )
         })
     }
 }
 
+/// Same as `untar::Archive::unpack`, except that the package is rejected
+/// instead of having its entries skipped or written anywhere outside of `dst`.
+fn unpack_archive<R: Read>(
+    decoder: FrameDecoder<R>,
+    pkg_path: &Path,
+    dst: &Path,
+    budget: &mut ExtractionBudget,
+) -> Result<(), LpmError<MainError>> {
+    let algorithm = decoder.algorithm();
+    let mut archive = untar::Archive::new(decoder);
+    fs::create_dir_all(dst)?;
+    let root = dst.canonicalize()?;
+
+    // Directories go last, so their permissions can't keep the entries
+    // inside of them from being written.
+    let mut directories = Vec::new();
+    for entry in archive.entries()? {
+        let entry = entry?;
+        if entry.header().entry_type().is_dir() {
+            directories.push(entry);
+        } else {
+            unpack_entry(entry, pkg_path, &root, algorithm, budget)?;
+        }
+    }
+
+    for entry in directories {
+        unpack_entry(entry, pkg_path, &root, algorithm, budget)?;
+    }
+
+    Ok(())
+}
+
+fn unpack_entry<R: Read>(
+    mut entry: untar::Entry<'_, R>,
+    pkg_path: &Path,
+    root: &Path,
+    algorithm: Algorithm,
+    budget: &mut ExtractionBudget,
+) -> Result<(), LpmError<MainError>> {
+    interrupt::check()?;
+
+    let path: PathBuf = entry
+        .path()?
+        .components()
+        .filter(|t| *t != Component::CurDir)
+        .collect();
+    let kind = entry.header().entry_type();
+    let link_name = entry.link_name()?.map(Cow::into_owned);
+
+    let mut reason = unsafe_entry_reason(&path, kind, link_name.as_deref());
+    if reason.is_none() && crosses_symlink(root, &path)? {
+        reason = Some("would be written through a symlink");
+    }
+    if let (None, Some(link_name)) = (reason, link_name.as_deref()) {
+        if kind.is_hard_link() && crosses_symlink(root, link_name)? {
+            reason = Some("is a hard link through a symlink");
+        }
+    }
+
+    if let Some(reason) = reason {
+        return Err(PackageErrorKind::UnsafeArchiveEntry {
+            package: pkg_path.display().to_string(),
+            path: path.display().to_string(),
+            reason: reason.to_owned(),
+        }
+        .to_lpm_err())?;
+    }
+
+    let limit_exceeded = |reason| {
+        PackageErrorKind::ArchiveLimitExceeded {
+            package: pkg_path.display().to_string(),
+            reason,
+        }
+        .to_lpm_err()
+    };
+
+    if let Some(reason) = budget.spend(&path, entry.size()) {
+        return Err(limit_exceeded(reason))?;
+    }
+
+    entry.unpack_in(root)?;
+
+    if path == Path::new("meta/meta.json") {
+        // Invalid meta data is reported once all of it is extracted.
+        let meta = Json::new(&fs::read_to_string(root.join(&path))?)
+            .parse()
+            .ok();
+        budget.declared_size = meta
+            .as_ref()
+            .and_then(|t| t["installed_size"].as_i64())
+            .and_then(|t| u64::try_from(t).ok());
+
+        if let Some(reason) = budget.check_declared_size() {
+            return Err(limit_exceeded(reason))?;
+        }
+
+        let declared_algorithm = meta.as_ref().and_then(|t| match &t["compression"] {
+            JsonValue::Null => Some(Compression::default().algorithm),
+            value => Compression::parse(&value.to_string()?)
+                .ok()
+                .map(|t| t.algorithm),
+        });
+        if let Some(declared_algorithm) = declared_algorithm.filter(|t| *t != algorithm) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Package declares {} compression in its meta data, but it's {}.",
+                    declared_algorithm.as_str(),
+                    algorithm.as_str()
+                ),
+            ))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Why the entry at `path` can't be extracted safely, judged from the archive
+/// alone, `None` if it can. `link_name` is the target of links.
+fn unsafe_entry_reason(
+    path: &Path,
+    kind: EntryType,
+    link_name: Option<&Path>,
+) -> Option<&'static str> {
+    if path.has_root() {
+        return Some("is an absolute path");
+    }
+
+    if path.components().any(|t| t == Component::ParentDir) {
+        return Some("contains '..'");
+    }
+
+    let link_name = link_name?;
+    if kind.is_hard_link()
+        && (link_name.has_root() || link_name.components().any(|t| t == Component::ParentDir))
+    {
+        return Some("is a hard link to outside of the package");
+    }
+
+    if kind.is_symlink() {
+        if link_name.has_root() {
+            return Some("is a symlink with an absolute target");
+        }
+
+        // Leading `..` only go through the real directories of the symlink's
+        // own path, one after a symlinked directory would go anywhere.
+        if link_name
+            .components()
+            .skip_while(|t| *t == Component::ParentDir)
+            .any(|t| t == Component::ParentDir)
+        {
+            return Some("is a symlink with '..' in the middle of its target");
+        }
+
+        // How deep the symlink target is, starting from the directory of the
+        // symlink.
+        let mut depth = path
+            .components()
+            .filter(|t| matches!(t, Component::Normal(_)))
+            .count() as isize
+            - 1;
+        for component in link_name.components() {
+            match component {
+                Component::ParentDir => depth -= 1,
+                Component::Normal(_) => depth += 1,
+                _ => {}
+            }
+
+            if depth < 0 {
+                return Some("is a symlink to outside of the package");
+            }
+        }
+    }
+
+    None
+}
+
+/// Whether any of the directories that lead to `path` inside of `root` is a
+/// symlink that was already extracted, which would redirect the entry
+/// wherever it points. `path` itself is replaced rather than followed.
+fn crosses_symlink(root: &Path, path: &Path) -> io::Result<bool> {
+    let mut dir = root.to_owned();
+    let Some(parent) = path.parent() else {
+        return Ok(false);
+    };
+
+    for component in parent.components() {
+        dir.push(component);
+        match fs::symlink_metadata(&dir) {
+            Ok(metadata) if metadata.file_type().is_symlink() => return Ok(true),
+            Ok(_) => {}
+            // Created as a directory by the extraction.
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(false)
+}
+
+/// Trees that packages were extracted into under `dir`, without the
+/// downloaded archives next to them.
+pub(crate) fn extracted_packages(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut paths = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            paths.push(entry.path());
+        }
+    }
+    paths.sort();
+
+    Ok(paths)
+}
+
+/// Removes the extracted trees of `EXTRACTION_OUTPUT_PATH`, but keeps the
+/// downloaded archives.
+pub(crate) fn remove_extracted_packages() -> io::Result<()> {
+    for path in extracted_packages(Path::new(super::EXTRACTION_OUTPUT_PATH))? {
+        fs::remove_dir_all(path)?;
+    }
+
+    Ok(())
+}
+
 #[inline]
 pub(crate) fn get_pkg_tmp_output_path(pkg_path: &Path) -> PathBuf {
-    PathBuf::from(super::EXTRACTION_OUTPUT_PATH.to_string())
-        .join(pkg_path.file_stem().unwrap().to_str().unwrap())
+    Path::new(super::EXTRACTION_OUTPUT_PATH).join(pkg_path.file_stem().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::testing::TestDir;
+    use std::os::unix::fs::symlink;
+
+    #[test]
+    fn test_unsafe_entry_reason() {
+        let check = |path: &str, kind, link_name: Option<&str>| {
+            unsafe_entry_reason(Path::new(path), kind, link_name.map(Path::new))
+        };
+
+        assert_eq!(
+            check("program/usr/bin/htop", EntryType::Regular, None),
+            None
+        );
+        assert!(check("/etc/passwd", EntryType::Regular, None).is_some());
+        assert!(check("program/../../etc/passwd", EntryType::Regular, None).is_some());
+
+        let symlink = EntryType::Symlink;
+        assert_eq!(
+            check("program/usr/lib/libz.so", symlink, Some("libz.so.1")),
+            None
+        );
+        assert_eq!(
+            check("program/usr/bin/vi", symlink, Some("../../usr/bin/vim")),
+            None
+        );
+        assert!(check("program/usr/lib/libz.so", symlink, Some("/etc/passwd")).is_some());
+        assert!(check("program/etc", symlink, Some("../../../etc")).is_some());
+        assert!(check("program/x", symlink, Some("a/../../../y")).is_some());
+        assert!(check("program/usr/x", symlink, Some("lib/../y")).is_some());
+
+        let hard_link = EntryType::Link;
+        assert_eq!(
+            check("program/usr/bin/vi", hard_link, Some("program/usr/bin/vim")),
+            None
+        );
+        assert!(check("program/passwd", hard_link, Some("/etc/passwd")).is_some());
+    }
+
+    #[test]
+    fn test_get_pkg_tmp_output_path() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        let pkg_path = Path::new(OsStr::from_bytes(b"/tmp/caf\xe9-1.0.0.lod"));
+        assert_eq!(
+            get_pkg_tmp_output_path(pkg_path),
+            Path::new(crate::EXTRACTION_OUTPUT_PATH).join(OsStr::from_bytes(b"caf\xe9-1.0.0"))
+        );
+    }
+
+    #[test]
+    fn test_extracted_packages() {
+        let dir = TestDir::new("extracted");
+        fs::create_dir_all(dir.join("htop-3.2.2")).unwrap();
+        fs::write(dir.join("htop-3.2.2.lod"), "").unwrap();
+
+        assert_eq!(
+            extracted_packages(&dir).unwrap(),
+            vec![dir.join("htop-3.2.2")]
+        );
+        assert!(extracted_packages(&dir.join("missing")).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_extraction_budget() {
+        let limits = ExtractionLimits {
+            max_size: 100,
+            max_file_size: 60,
+            max_files: 3,
+        };
+
+        let mut budget = ExtractionBudget::new(&limits);
+        assert!(budget.spend(Path::new("program/big"), 61).is_some());
+
+        let mut budget = ExtractionBudget::new(&limits);
+        assert_eq!(budget.spend(Path::new("program/a"), 50), None);
+        assert_eq!(budget.spend(Path::new("scripts/b"), 40), None);
+        assert!(budget.spend(Path::new("program/c"), 20).is_some());
+
+        let mut budget = ExtractionBudget::new(&limits);
+        for _ in 0..3 {
+            assert_eq!(budget.spend(Path::new("program/dir"), 0), None);
+        }
+        assert!(budget.spend(Path::new("program/dir"), 0).is_some());
+
+        let mut budget = ExtractionBudget::new(&limits);
+        assert_eq!(budget.spend(Path::new("program/a"), 30), None);
+        budget.declared_size = Some(40);
+        assert_eq!(budget.check_declared_size(), None);
+        // Only the files of `program/` are installed.
+        assert_eq!(budget.spend(Path::new("meta/files.json"), 20), None);
+        assert!(budget.spend(Path::new("program/b"), 20).is_some());
+    }
+
+    #[test]
+    fn test_crosses_symlink() {
+        let root = TestDir::new("extract");
+        fs::create_dir_all(root.join("program/inside")).unwrap();
+        let root = root.canonicalize().unwrap();
+
+        symlink("inside", root.join("program/link")).unwrap();
+        symlink("/etc", root.join("program/escape")).unwrap();
+
+        assert!(!crosses_symlink(&root, Path::new("program/inside/file")).unwrap());
+        assert!(!crosses_symlink(&root, Path::new("program/new/dir/file")).unwrap());
+        assert!(!crosses_symlink(&root, Path::new("program/link")).unwrap());
+        // Even when the symlink stays inside of the package.
+        assert!(crosses_symlink(&root, Path::new("program/link/file")).unwrap());
+        assert!(crosses_symlink(&root, Path::new("program/escape/passwd")).unwrap());
+    }
 }
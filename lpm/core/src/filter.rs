@@ -0,0 +1,206 @@
+use common::{glob, meta::FileKind, pkg_path::PkgPath};
+use json::{to_json_object, Json, JsonValue, Serialize};
+use std::io;
+
+/// Decides which package paths get installed, built from the `no_extract`
+/// config, `--exclude` patterns and the install profiles.
+#[derive(Debug, Default)]
+pub(crate) struct PathFilter {
+    patterns: Vec<String>,
+    /// Locales to keep, `None` keeps all of them.
+    locales: Option<Vec<String>>,
+    /// Set by `--no-docs`.
+    no_docs: bool,
+}
+
+impl PathFilter {
+    pub(crate) fn new(patterns: &[String]) -> io::Result<Self> {
+        let mut filter = Self::default();
+        for pattern in patterns {
+            filter.add_pattern(pattern)?;
+        }
+
+        Ok(filter)
+    }
+
+    pub(crate) fn add_patterns<T: AsRef<str>>(&mut self, patterns: &[T]) -> io::Result<()> {
+        for pattern in patterns {
+            self.add_pattern(pattern.as_ref())?;
+        }
+
+        Ok(())
+    }
+
+    /// Parses a filter stored with `to_json`.
+    pub(crate) fn parse(content: &str) -> io::Result<Self> {
+        let invalid = |e: String| io::Error::new(io::ErrorKind::InvalidData, e);
+        let strings = |value: &JsonValue| -> io::Result<Vec<String>> {
+            match value {
+                JsonValue::Array(items) => items
+                    .iter()
+                    .map(|item| {
+                        item.to_string()
+                            .ok_or_else(|| invalid(String::from("Expected a string.")))
+                    })
+                    .collect(),
+                _ => Err(invalid(String::from("Expected an array of strings."))),
+            }
+        };
+
+        let json = Json::new(content).parse().map_err(invalid)?;
+        let mut filter = Self::new(&strings(&json["patterns"])?)?;
+        if !json["locales"].is_null() {
+            filter.select_locales(strings(&json["locales"])?);
+        }
+        filter.no_docs = json["no_docs"].as_bool().unwrap_or_default();
+
+        Ok(filter)
+    }
+
+    fn add_pattern(&mut self, pattern: &str) -> io::Result<()> {
+        let pattern = PkgPath::new(pattern)?;
+        self.patterns.push(pattern.as_relative().to_owned());
+
+        Ok(())
+    }
+
+    /// Skips the files classed as documentation, which keeps the licenses
+    /// that are shipped along with it.
+    pub(crate) fn exclude_docs(&mut self) {
+        self.no_docs = true;
+    }
+
+    /// Keeps only the translations of `locales`, e.g. `en` keeps `en`,
+    /// `en_US` and `en@quot` but drops `fr`.
+    pub(crate) fn select_locales(&mut self, locales: Vec<String>) {
+        self.locales = Some(locales);
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.patterns.is_empty() && self.locales.is_none() && !self.no_docs
+    }
+
+    /// Like `is_excluded`, for a file of the given class.
+    pub(crate) fn is_file_excluded(&self, path: &str, kind: FileKind) -> bool {
+        (self.no_docs && kind == FileKind::Doc) || self.is_excluded(path)
+    }
+
+    /// Paths with `..` are always excluded, they can't be installed anyway.
+    pub(crate) fn is_excluded(&self, path: &str) -> bool {
+        let Ok(path) = PkgPath::new(path) else {
+            return true;
+        };
+        let path = path.as_relative();
+
+        if glob::matches_any(&self.patterns, path) {
+            return true;
+        }
+
+        match (&self.locales, locale_of(path)) {
+            (Some(locales), Some(locale)) => !locales.iter().any(|selected| {
+                locale == selected
+                    || locale
+                        .strip_prefix(selected.as_str())
+                        .map_or(false, |rest| rest.starts_with(['_', '.', '@']))
+            }),
+            _ => false,
+        }
+    }
+}
+
+impl Serialize for PathFilter {
+    fn to_json(&self) -> String {
+        to_json_object(&[
+            ("patterns", self.patterns.to_json()),
+            ("locales", self.locales.to_json()),
+            ("no_docs", self.no_docs.to_json()),
+        ])
+    }
+}
+
+/// Returns the locale that `path` belongs to, if it's a translation.
+fn locale_of(path: &str) -> Option<&str> {
+    if let Some(rest) = path.strip_prefix("usr/share/locale/") {
+        let (locale, _) = rest.split_once('/')?;
+        return Some(locale);
+    }
+
+    if let Some(rest) = path.strip_prefix("usr/share/man/") {
+        let (locale, _) = rest.split_once('/')?;
+        // `man1`, `man8` etc. are the untranslated sections.
+        if !locale.starts_with("man") {
+            return Some(locale);
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exclude_patterns() {
+        let filter = PathFilter::new(&[String::from("/usr/share/doc/*")]).unwrap();
+        assert!(filter.is_excluded("usr/share/doc/htop/README"));
+        assert!(filter.is_excluded("/usr/share/doc/htop/README"));
+        assert!(!filter.is_excluded("usr/bin/htop"));
+
+        assert!(PathFilter::new(&[]).unwrap().is_empty());
+        assert!(!PathFilter::new(&[])
+            .unwrap()
+            .is_excluded("usr/share/doc/htop/README"));
+
+        assert!(PathFilter::new(&[String::from("usr/../etc/*")]).is_err());
+    }
+
+    #[test]
+    fn test_exclude_docs() {
+        let mut filter = PathFilter::new(&[]).unwrap();
+        filter.exclude_docs();
+        assert!(!filter.is_empty());
+
+        assert!(filter.is_file_excluded("usr/share/man/man1/htop.1.gz", FileKind::Doc));
+        assert!(filter.is_file_excluded("usr/share/info/htop.info", FileKind::Doc));
+        assert!(
+            !filter.is_file_excluded("usr/share/locale/fr/LC_MESSAGES/htop.mo", FileKind::Other)
+        );
+        assert!(!filter.is_file_excluded("usr/share/doc/htop/COPYING", FileKind::License));
+    }
+
+    #[test]
+    fn test_parse() {
+        let mut filter = PathFilter::new(&[String::from("usr/share/doc/*")]).unwrap();
+        filter.exclude_docs();
+        filter.select_locales(vec![String::from("en")]);
+
+        let parsed = PathFilter::parse(&filter.to_json()).unwrap();
+        assert_eq!(parsed.patterns, filter.patterns);
+        assert_eq!(parsed.locales, filter.locales);
+        assert!(parsed.no_docs);
+
+        let parsed = PathFilter::parse(&PathFilter::default().to_json()).unwrap();
+        assert!(parsed.is_empty());
+
+        assert!(PathFilter::parse(r#"{ "patterns": "usr/share/doc/*" }"#).is_err());
+    }
+
+    #[test]
+    fn test_select_locales() {
+        let mut filter = PathFilter::new(&[]).unwrap();
+        filter.select_locales(vec![String::from("en"), String::from("pt_BR")]);
+
+        assert!(!filter.is_excluded("usr/share/locale/en/LC_MESSAGES/htop.mo"));
+        assert!(!filter.is_excluded("usr/share/locale/en_GB/LC_MESSAGES/htop.mo"));
+        assert!(!filter.is_excluded("usr/share/locale/en@quot/LC_MESSAGES/htop.mo"));
+        assert!(!filter.is_excluded("usr/share/locale/pt_BR/LC_MESSAGES/htop.mo"));
+        assert!(filter.is_excluded("usr/share/locale/pt/LC_MESSAGES/htop.mo"));
+        assert!(filter.is_excluded("usr/share/locale/fr/LC_MESSAGES/htop.mo"));
+        assert!(filter.is_excluded("usr/share/man/fr/man1/htop.1.gz"));
+
+        assert!(!filter.is_excluded("usr/share/locale/locale.alias"));
+        assert!(!filter.is_excluded("usr/share/man/man1/htop.1.gz"));
+        assert!(!filter.is_excluded("usr/bin/htop"));
+    }
+}
@@ -0,0 +1,63 @@
+//! History of the transactions, each one is appended as a JSON line to
+//! `TRANSACTION_HISTORY_PATH` with what it changed and its statistics, so
+//! regressions in the time or the traffic of the operations can be tracked,
+//! and who started it.
+//!
+//! ```json
+//! {"finished_at": 1700000000, "operation": "install", "status": "success", "error": null,
+//!  "initiator": {"uid": 0, "user": "root", "sudo_user": "alice", "sudo_uid": 1000,
+//!  "tty": "/dev/pts/1", "rpc": false},
+//!  "changes": {...}, "stats": {"elapsed_ms": 1520, "downloaded_bytes": 2048, ...}}
+//! ```
+
+use crate::{Changes, Initiator, Operation};
+
+use common::stats::TransactionStats;
+use ehandle::{lpm::LpmError, MainError};
+use json::{to_json_object, Serialize};
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+pub const TRANSACTION_HISTORY_PATH: &str = "/var/log/lpm/transactions.log";
+
+pub(crate) fn record_transaction(
+    operation: Operation,
+    result: &Result<(), LpmError<MainError>>,
+    initiator: &Initiator,
+    changes: &Changes,
+    stats: &TransactionStats,
+) -> io::Result<()> {
+    let finished_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|t| t.as_secs() as i64)
+        .unwrap_or_default();
+
+    let record = to_json_object(&[
+        ("finished_at", finished_at.to_json()),
+        ("operation", operation.as_str().to_json()),
+        (
+            "status",
+            if result.is_ok() { "success" } else { "failure" }.to_json(),
+        ),
+        (
+            "error",
+            result
+                .as_ref()
+                .err()
+                .map(|e| format!("{:?}", e.error_type))
+                .to_json(),
+        ),
+        ("initiator", initiator.to_json()),
+        ("changes", changes.to_json()),
+        ("stats", stats.to_json()),
+    ]);
+
+    let path = Path::new(TRANSACTION_HISTORY_PATH);
+    fs::create_dir_all(path.parent().unwrap())?;
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{record}")
+}
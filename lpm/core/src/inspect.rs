@@ -0,0 +1,125 @@
+use crate::{lod::read_lod_meta, Ctx};
+
+use common::{
+    meta::{FileKind, Files},
+    pkg::PkgDataFromDb,
+    size::format_byte_size,
+};
+use db::pkg::DbOpsForInstalledPkg;
+use ehandle::{lpm::LpmError, MainError};
+use std::path::Path;
+
+/// Prints the meta data of the `.lod` package at `pkg_path`, without
+/// extracting or installing it.
+pub fn print_package_info(pkg_path: &str) -> Result<(), LpmError<MainError>> {
+    let lod = read_lod_meta(Path::new(pkg_path))?;
+    let meta = &lod.meta;
+
+    let dependencies: Vec<String> = meta
+        .dependencies
+        .iter()
+        .map(|t| format!("{}@{}", t.name, t.version.readable_format))
+        .collect();
+
+    let rows = [
+        ("Name", meta.name.clone()),
+        ("Version", meta.version.readable_format.clone()),
+        ("Architecture", meta.arch.clone()),
+        (
+            "Slot",
+            meta.slot.clone().unwrap_or_else(|| String::from("-")),
+        ),
+        (
+            "Installed size",
+            format_byte_size(meta.installed_size.max(0) as u64),
+        ),
+        (
+            "Files",
+            match lod.files.0.len() {
+                0 => String::from("0"),
+                count => format!("{count} ({})", file_classes(&lod.files, &meta.config_files)),
+            },
+        ),
+        (
+            "Dependencies",
+            if dependencies.is_empty() {
+                String::from("-")
+            } else {
+                dependencies.join(", ")
+            },
+        ),
+        ("Package format", format!("v{}", lod.format_version)),
+    ];
+
+    for (field, value) in rows {
+        println!("  {:<16}{value}", format!("{field}:"));
+    }
+
+    Ok(())
+}
+
+/// Prints the installed package `pkg_name`, along with the repository it was
+/// installed or last updated from.
+pub fn print_installed_package_info(ctx: Ctx, pkg_name: &str) -> Result<(), LpmError<MainError>> {
+    let pkg = PkgDataFromDb::load(&ctx.core_db, pkg_name)?;
+    let meta = &pkg.meta_fields.meta;
+    let files = &pkg.meta_fields.files;
+
+    let rows = [
+        ("Name", meta.name.clone()),
+        ("Version", meta.version.readable_format.clone()),
+        ("Architecture", meta.arch.clone()),
+        (
+            "Slot",
+            meta.slot.clone().unwrap_or_else(|| String::from("-")),
+        ),
+        ("Kind", meta.kind.map_or("-", |t| t.as_str()).to_owned()),
+        (
+            "Installed size",
+            format_byte_size(meta.installed_size.max(0) as u64),
+        ),
+        (
+            "Files",
+            match files.0.len() {
+                0 => String::from("0"),
+                count => format!("{count} ({})", file_classes(files, &meta.config_files)),
+            },
+        ),
+        (
+            "Installed for",
+            if pkg.group_id == meta.get_group_id() {
+                String::from("itself")
+            } else {
+                pkg.group_id.clone()
+            },
+        ),
+        (
+            "Origin",
+            match &pkg.origin {
+                Some(origin) => format!(
+                    "{} repository (index {})",
+                    origin.repository, origin.index_timestamp
+                ),
+                None => String::from("-"),
+            },
+        ),
+    ];
+
+    for (field, value) in rows {
+        println!("  {:<16}{value}", format!("{field}:"));
+    }
+
+    Ok(())
+}
+
+/// Number of files per class, e.g. `1 config, 3 doc, 2 binary`.
+fn file_classes(files: &Files, config_files: &[String]) -> String {
+    let counts: Vec<String> = FileKind::ALL
+        .into_iter()
+        .map(|kind| (kind, files.of_kind(kind, config_files).count()))
+        .filter(|(_, count)| *count > 0)
+        .map(|(kind, count)| format!("{count} {}", kind.as_str()))
+        .collect();
+
+    counts.join(", ")
+}
@@ -1,46 +1,87 @@
 use crate::{
+    alternatives::{alternative_links, refresh_alternatives},
+    chroot::can_emulate,
     extract::{get_pkg_tmp_output_path, PkgExtractTasks},
-    repository::find_pkg_index,
-    stage1::{Stage1Tasks, PKG_SCRIPTS_DIR},
-    validate::PkgValidateTasks,
+    filter::PathFilter,
+    in_transaction,
+    plan::{confirm_plan, Plan, PlanEntry},
+    protect::ProtectedPaths,
+    read_package_list,
+    repository::{
+        ensure_available_offline, ensure_fresh_metadata, ensure_trusted_origin, find_pkg_index,
+        index_origin, is_index_verified, is_repository_usable,
+    },
+    shlib::check_shared_libraries,
+    stage1::{check_interpreters, defer_install_scripts, pkg_lib_dir, Stage1Tasks},
+    store::ContentStore,
+    validate::{verify_archive, PkgValidateTasks},
     Ctx,
 };
 
 use cli_parser::InstallArgs;
 use common::{
-    ctx_confirmation_check, download_file,
-    pkg::{PkgDataFromFs, PkgToQuery, ScriptPhase},
-    some_or_error,
+    arch,
+    config::ExtractionLimits,
+    copy::{copy_file, set_attributes, set_ownership},
+    download_files,
+    event::EventSink,
+    interrupt,
+    meta::{DirectoryStruct, FileKind},
+    pkg::{PkgDataFromFs, PkgToQuery, ScriptPhase, PHASES_MANIFEST},
+    pkg_path::PkgPath,
+    some_or_error, stats, Files, NO_ARCH,
 };
 use db::{
     enable_core_db_wal1,
-    pkg::{is_package_exists, DbOpsForBuildFile},
+    pkg::{get_file_owner, is_package_exists, DbOpsForBuildFile},
     PkgIndex,
 };
 use ehandle::{
     lpm::LpmError, pkg::PackageErrorKind, repository::RepositoryErrorKind, ErrorCommons, MainError,
 };
+use json::Serialize;
 use logger::{debug, info, warning};
 use min_sqlite3_sys::prelude::*;
 use std::{
     collections::HashSet,
     fs::{self, create_dir_all},
+    io,
     path::{Path, PathBuf},
-    sync::Arc,
+    sync::{Arc, Mutex},
     thread,
 };
 
-trait PkgInstallTasks {
+pub(crate) trait PkgInstallTasks {
     fn get_pkg_stack(
         core_db: &Database,
         pkg_to_query: PkgToQuery,
+        target_arch: &str,
     ) -> Result<Vec<PkgIndex>, LpmError<MainError>>;
-    fn pre_install_task(path: &Path) -> Result<Self, LpmError<MainError>>
+    fn pre_install_task(
+        path: &Path,
+        target_arch: &str,
+        limits: &ExtractionLimits,
+        events: &dyn EventSink,
+    ) -> Result<Self, LpmError<MainError>>
     where
         Self: Sized;
-    fn install_files(&self) -> Result<(), LpmError<MainError>>;
-    fn copy_programs(&self) -> Result<(), LpmError<MainError>>;
-    fn copy_scripts(&self) -> Result<(), LpmError<MainError>>;
+    fn install_files(
+        &mut self,
+        root: &Path,
+        target_arch: &str,
+        store: Option<&ContentStore>,
+        events: &dyn EventSink,
+    ) -> Result<(), LpmError<MainError>>;
+    fn copy_programs(
+        &self,
+        root: &Path,
+        store: Option<&ContentStore>,
+        events: &dyn EventSink,
+    ) -> Result<(), LpmError<MainError>>;
+    fn copy_scripts(&self, root: &Path) -> Result<(), LpmError<MainError>>;
+    fn exclude_files(&mut self, filter: &PathFilter) -> Result<(), LpmError<MainError>>;
+    fn check_slot_conflicts(&self, core_db: &Database) -> Result<(), LpmError<MainError>>;
+    fn check_protected_paths(&self, protected: &ProtectedPaths) -> Result<(), LpmError<MainError>>;
 }
 
 impl PkgInstallTasks for PkgDataFromFs {
@@ -48,6 +89,7 @@ impl PkgInstallTasks for PkgDataFromFs {
     fn get_pkg_stack(
         core_db: &Database,
         pkg_to_query: PkgToQuery,
+        target_arch: &str,
     ) -> Result<Vec<PkgIndex>, LpmError<MainError>> {
         let index_db_list = db::get_repositories(core_db)?;
         if index_db_list.is_empty() {
@@ -55,10 +97,16 @@ impl PkgInstallTasks for PkgDataFromFs {
             return Err(RepositoryErrorKind::PackageNotFound(pkg_to_query.name).to_lpm_err())?;
         }
 
-        let index = find_pkg_index(&index_db_list, &pkg_to_query)?;
+        let index = find_pkg_index(core_db, &index_db_list, &pkg_to_query, target_arch)?;
 
         let mut pkg_stack = vec![index];
         for (name, repository_address) in index_db_list {
+            let options = db::get_repository_options(core_db, &name)?;
+            if !is_repository_usable(&name, &options) {
+                continue;
+            }
+            let archs = options.index_archs(target_arch);
+
             let repository_db_path = Path::new(db::REPOSITORY_INDEX_DB_DIR).join(&name);
             let db_file = fs::metadata(&repository_db_path)?;
             let index_db = Database::open(Path::new(&repository_db_path))?;
@@ -68,6 +116,9 @@ impl PkgInstallTasks for PkgDataFromFs {
                 warning!("{name} repository is not initialized");
                 continue;
             }
+            if !is_index_verified(&name, &options, &index_db)? {
+                continue;
+            }
 
             let mut i = 0;
             loop {
@@ -88,23 +139,34 @@ impl PkgInstallTasks for PkgDataFromFs {
                     "Failed resolving package name '{pkg_name}'"
                 );
 
-                let new_pkgs: Vec<PkgIndex> =
-                    db::PkgIndex::get_mandatory_dependencies(&index_db, &pkg_to_query)?
+                let mut new_pkgs: Vec<PkgIndex> =
+                    db::PkgIndex::get_mandatory_dependencies(&index_db, &pkg_to_query, &archs)?
                         .iter()
                         .map(|pkg_name| {
-                            let pkg_to_query = some_or_error!(
+                            some_or_error!(
                                 PkgToQuery::parse(pkg_name),
                                 "Failed resolving package name '{pkg_name}'"
-                            );
-
-                            PkgIndex {
-                                name: pkg_to_query.name.clone(),
-                                repository_address: repository_address.clone(),
-                                version: pkg_to_query.version_struct(),
-                            }
+                            )
+                        })
+                        .filter(|pkg_to_query| options.is_pkg_allowed(&pkg_to_query.name))
+                        .map(|pkg_to_query| PkgIndex {
+                            name: pkg_to_query.name.clone(),
+                            repository_name: name.clone(),
+                            repository_address: repository_address.clone(),
+                            version: pkg_to_query.version_struct(),
+                            archive_checksum: None,
+                            archive_size: None,
+                            installed_size: None,
+                            kind: None,
+                            description: None,
+                            signing_key: None,
                         })
                         .collect();
 
+                for new_pkg in &mut new_pkgs {
+                    new_pkg.load_archive_info(&index_db, &archs)?;
+                }
+
                 pkg_stack.extend(new_pkgs);
 
                 i += 1;
@@ -118,54 +180,177 @@ impl PkgInstallTasks for PkgDataFromFs {
         Ok(pkg_stack)
     }
 
-    fn pre_install_task(path: &Path) -> Result<Self, LpmError<MainError>> {
+    fn pre_install_task(
+        path: &Path,
+        target_arch: &str,
+        limits: &ExtractionLimits,
+        events: &dyn EventSink,
+    ) -> Result<Self, LpmError<MainError>> {
         info!("Extracting..");
-        let pkg = PkgDataFromFs::start_extract_task(path)?;
+        let pkg = PkgDataFromFs::start_extract_task(path, limits, events)?;
 
         info!("Validating files..");
-        pkg.start_validate_task()?;
+        pkg.start_validate_task(target_arch)?;
 
         Ok(pkg)
     }
 
-    fn install_files(&self) -> Result<(), LpmError<MainError>> {
-        let pkg_output_root = get_pkg_tmp_output_path(&self.path);
-        let script_env = vec![("PKG_ROOT", pkg_output_root.to_str().unwrap())];
+    /// Drops the files excluded by `filter`, so they are neither installed nor recorded
+    /// in the database. `installed_size` is reduced by their sizes.
+    fn exclude_files(&mut self, filter: &PathFilter) -> Result<(), LpmError<MainError>> {
+        if filter.is_empty() {
+            return Ok(());
+        }
+
+        let source_path = get_pkg_tmp_output_path(&self.path).join("program");
+        let mut excluded_size = 0;
+
+        let config_files = &self.meta_dir.meta.config_files;
+        let (excluded, kept) = std::mem::take(&mut self.meta_dir.files.0)
+            .into_iter()
+            .partition(|file| filter.is_file_excluded(&file.path, file.kind(config_files)));
+        self.meta_dir.files.0 = kept;
+
+        for file in &excluded {
+            debug!("Excluding {}", file.path);
+            excluded_size += fs::symlink_metadata(source_path.join(&file.path))?.len() as i64;
+        }
 
-        self.scripts
-            .execute_script(script_env.clone(), ScriptPhase::PreInstall)?;
+        let meta = &mut self.meta_dir.meta;
+        meta.installed_size = (meta.installed_size - excluded_size).max(0);
+
+        Ok(())
+    }
 
-        info!("Installing package files into system..");
-        self.copy_scripts()?;
-        self.copy_programs()?;
+    /// Slots of the same package are installed side by side, so they must not
+    /// share any paths.
+    fn check_slot_conflicts(&self, core_db: &Database) -> Result<(), LpmError<MainError>> {
+        if self.meta_dir.meta.slot.is_none() {
+            return Ok(());
+        }
 
-        self.scripts
-            .execute_script(script_env, ScriptPhase::PostInstall)?;
+        for file in &self.meta_dir.files.0 {
+            let path = file.pkg_path()?.to_absolute();
+            if let Some(owner) = get_file_owner(core_db, &path)? {
+                return Err(PackageErrorKind::FileConflict { path, owner }.to_lpm_err())?;
+            }
+        }
 
         Ok(())
     }
 
-    fn copy_programs(&self) -> Result<(), LpmError<MainError>> {
+    fn check_protected_paths(&self, protected: &ProtectedPaths) -> Result<(), LpmError<MainError>> {
+        protected.check(
+            &self.meta_dir.meta.name,
+            self.meta_dir.files.0.iter().map(|file| file.path.as_str()),
+        )
+    }
+
+    /// Also replaces `installed_size` with the disk space that the installed files use.
+    fn install_files(
+        &mut self,
+        root: &Path,
+        target_arch: &str,
+        store: Option<&ContentStore>,
+        events: &dyn EventSink,
+    ) -> Result<(), LpmError<MainError>> {
+        let pkg_name = &self.meta_dir.meta.name;
+        let pkg_output_root = get_pkg_tmp_output_path(&self.path);
+        let script_env = vec![
+            ("PKG_ROOT", pkg_output_root.as_os_str()),
+            ("LPM_ROOT", root.as_os_str()),
+        ];
+
+        // Scripts of packages built for another architecture can only be
+        // executed through qemu-user, they are left to the target system
+        // otherwise.
+        let run_scripts =
+            arch::is_supported_by_system(target_arch) || can_emulate(root, target_arch);
+        let defer_scripts = !run_scripts && !self.scripts.is_empty();
+        if defer_scripts {
+            warning!(
+                "Deferring scripts of '{}', target architecture '{}' can not run on this host. \
+                 Run 'lpm --db run-pending-scripts' on the target system.",
+                self.meta_dir.meta.name,
+                target_arch
+            );
+        }
+
+        if run_scripts {
+            self.scripts.execute_script(
+                root,
+                script_env.clone(),
+                ScriptPhase::PreInstall,
+                pkg_name,
+                events,
+            )?;
+        }
+
+        info!("Installing package files into {}..", root.display());
+        self.copy_scripts(root)?;
+        if defer_scripts {
+            defer_install_scripts(root, &self.meta_dir.meta)?;
+        }
+        self.copy_programs(root, store, events)?;
+        self.meta_dir.meta.installed_size = self.meta_dir.files.record_sizes(root)? as i64;
+
+        if run_scripts {
+            self.scripts.execute_script(
+                root,
+                script_env,
+                ScriptPhase::PostInstall,
+                pkg_name,
+                events,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn copy_programs(
+        &self,
+        root: &Path,
+        store: Option<&ContentStore>,
+        events: &dyn EventSink,
+    ) -> Result<(), LpmError<MainError>> {
         let source_path = get_pkg_tmp_output_path(&self.path).join("program");
+        let config_files = &self.meta_dir.meta.config_files;
 
         for file in &self.meta_dir.files.0 {
-            let destination = Path::new("/").join(&file.path);
+            interrupt::check()?;
+
+            let pkg_path = file.pkg_path()?;
+            let destination = pkg_path.under(root);
             create_dir_all(destination.parent().unwrap())?;
 
-            let from = source_path.join(&file.path);
+            let from = pkg_path.under(&source_path);
 
             debug!("Copying {} -> {}", from.display(), destination.display());
 
-            fs::copy(from, destination)?;
+            let size = match store {
+                Some(store) if file.kind(config_files) != FileKind::Config => {
+                    store.install(&from, &destination, file)?
+                }
+                _ => copy_file(&from, &destination)?,
+            };
+            stats::record_installed_file(size);
+            set_ownership(
+                root,
+                &destination,
+                file.owner.as_deref(),
+                file.group.as_deref(),
+            )?;
+            set_attributes(&destination, file.mode, file.mtime)?;
+            events.file_installed(&self.meta_dir.meta.name, &destination);
         }
 
+        create_directories(root, &self.meta_dir.meta.directories)?;
+
         Ok(())
     }
 
-    fn copy_scripts(&self) -> Result<(), LpmError<MainError>> {
-        let pkg_scripts_path = Path::new(PKG_SCRIPTS_DIR)
-            .join(&self.meta_dir.meta.name)
-            .join("scripts");
+    fn copy_scripts(&self, root: &Path) -> Result<(), LpmError<MainError>> {
+        let pkg_scripts_path = pkg_lib_dir(root, &self.meta_dir.meta).join("scripts");
 
         std::fs::create_dir_all(&pkg_scripts_path)?;
 
@@ -178,15 +363,126 @@ impl PkgInstallTasks for PkgDataFromFs {
                 destination.display()
             );
 
-            fs::copy(&script.path, destination)?;
+            // What was read and checked, not whatever the file holds now.
+            fs::write(destination, &script.contents)?;
+        }
+
+        let manifest_path = pkg_scripts_path.join(PHASES_MANIFEST);
+        match self.scripts.iter().find(|t| t.custom_phase.is_some()) {
+            Some(script) => {
+                fs::copy(script.path.with_file_name(PHASES_MANIFEST), manifest_path)?;
+            }
+            // Left from an earlier version of the package.
+            None if manifest_path.exists() => fs::remove_file(manifest_path)?,
+            None => {}
         }
 
+        // Kept for the migrations that need data of the installed packages.
+        fs::copy(
+            self.meta_dir.path.join("meta.json"),
+            pkg_scripts_path.with_file_name("meta.json"),
+        )?;
+
         Ok(())
     }
 }
 
-fn install_from_repository(ctx: Ctx, pkg_names: &HashSet<&str>) -> Result<(), LpmError<MainError>> {
+/// Creates the `directories` of a package under `root` with their recorded
+/// owners, modes and modification times, after its files are in place so
+/// adding them doesn't change the times again.
+pub(crate) fn create_directories(
+    root: &Path,
+    directories: &[DirectoryStruct],
+) -> Result<(), LpmError<MainError>> {
+    for directory in directories {
+        let path = PkgPath::new(&directory.path)?.under(root);
+        create_dir_all(&path)?;
+        set_ownership(
+            root,
+            &path,
+            directory.owner.as_deref(),
+            directory.group.as_deref(),
+        )?;
+        set_attributes(&path, directory.mode, directory.mtime)?;
+    }
+
+    Ok(())
+}
+
+/// Records `filter` for the package `pkg_id`, so that its updates skip the
+/// same paths.
+fn set_path_filter(
+    core_db: &Database,
+    pkg_id: i64,
+    filter: &PathFilter,
+) -> Result<(), LpmError<MainError>> {
+    let path_filter = (!filter.is_empty()).then(|| filter.to_json());
+    db::pkg::set_path_filter(core_db, pkg_id, path_filter.as_deref())?;
+
+    Ok(())
+}
+
+/// Downloads the archives of `indexes` from the mirrors of their
+/// repositories, skipping the ones that are already downloaded.
+fn download_packages<'a>(
+    ctx: &Ctx,
+    indexes: impl Iterator<Item = &'a PkgIndex>,
+) -> Result<(), LpmError<MainError>> {
+    let mut downloads = Vec::new();
+    for item in indexes {
+        let options = db::get_repository_options(&ctx.core_db, &item.repository_name)?;
+        downloads.push((
+            item.pkg_urls(&options.mirrors),
+            item.pkg_output_path(super::EXTRACTION_OUTPUT_PATH),
+            options.download_options(&ctx.config)?,
+        ));
+    }
+    download_files(&downloads, ctx.events.as_ref())?;
+
+    Ok(())
+}
+
+/// Resolves `pkg_names` along with their dependencies and downloads and
+/// verifies their archives, without installing anything, so that a later
+/// `install_from_repository` of them doesn't need the network.
+pub(crate) fn fetch_from_repository(
+    ctx: &Ctx,
+    pkg_names: &HashSet<&str>,
+) -> Result<(), LpmError<MainError>> {
+    let mut indexes = vec![];
+    for pkg_name in pkg_names {
+        let pkg_to_query = PkgToQuery::parse(pkg_name).ok_or_else(|| {
+            PackageErrorKind::InvalidPackageName(pkg_name.to_string()).to_lpm_err()
+        })?;
+        indexes.extend(PkgDataFromFs::get_pkg_stack(
+            &ctx.core_db,
+            pkg_to_query,
+            ctx.target_arch(),
+        )?);
+    }
+    ensure_fresh_metadata(ctx, &indexes)?;
+
+    if ctx.offline {
+        let indexes: Vec<&PkgIndex> = indexes.iter().collect();
+        ensure_available_offline(&indexes, super::EXTRACTION_OUTPUT_PATH)?;
+    }
+
+    download_packages(ctx, indexes.iter())?;
+    for index in &indexes {
+        verify_archive(index, &index.pkg_output_path(super::EXTRACTION_OUTPUT_PATH))?;
+    }
+
+    Ok(())
+}
+
+pub(crate) fn install_from_repository(
+    ctx: &Ctx,
+    pkg_names: &HashSet<&str>,
+    filter: &PathFilter,
+    strict: bool,
+) -> Result<(), LpmError<MainError>> {
     enable_core_db_wal1(&ctx.core_db)?;
+    enable_core_db_wal1(ctx.pkgs_db())?;
 
     let mut pkg_stacks = vec![];
 
@@ -195,7 +491,12 @@ fn install_from_repository(ctx: Ctx, pkg_names: &HashSet<&str>) -> Result<(), Lp
             PackageErrorKind::InvalidPackageName(pkg_name.to_string()).to_lpm_err()
         })?;
 
-        if is_package_exists(&ctx.core_db, &pkg_to_query.name)? {
+        if is_package_exists(
+            ctx.pkgs_db(),
+            &pkg_to_query.name,
+            &[ctx.target_arch(), NO_ARCH],
+            Some(""),
+        )? {
             logger::info!(
                 "Package '{}' already installed on your machine.",
                 pkg_to_query.to_string()
@@ -203,42 +504,79 @@ fn install_from_repository(ctx: Ctx, pkg_names: &HashSet<&str>) -> Result<(), Lp
             return Ok(());
         }
 
-        pkg_stacks.push(PkgDataFromFs::get_pkg_stack(&ctx.core_db, pkg_to_query)?);
+        pkg_stacks.push(PkgDataFromFs::get_pkg_stack(
+            &ctx.core_db,
+            pkg_to_query,
+            ctx.target_arch(),
+        )?);
     }
+    ensure_fresh_metadata(ctx, pkg_stacks.iter().flatten())?;
+    ensure_trusted_origin(ctx, pkg_stacks.iter().flatten())?;
 
-    {
-        // TODO
-        // package size is missing
-        // total installation size is missing
-        // use colors
-        println!("\nPackage list to be installed:");
-        pkg_stacks.iter().for_each(|pkg_stack| {
-            pkg_stack.iter().for_each(|index| {
-                println!("  - {}", index.get_group_id());
-            });
-        });
-        println!();
+    if ctx.offline {
+        let indexes: Vec<&PkgIndex> = pkg_stacks.iter().flatten().collect();
+        ensure_available_offline(&indexes, super::EXTRACTION_OUTPUT_PATH)?;
     }
 
-    ctx_confirmation_check!(ctx);
+    let mut requested: Vec<String> = pkg_names.iter().map(|t| t.to_string()).collect();
+    requested.sort();
+    let plan = Plan {
+        operation: "install",
+        requested,
+        entries: pkg_stacks
+            .iter()
+            .flatten()
+            .map(|index| PlanEntry::from_index(index, None))
+            .collect(),
+    };
+    if !confirm_plan(ctx, "Package list to be installed:", &plan)? {
+        return Ok(());
+    }
+
+    download_packages(ctx, pkg_stacks.iter().flatten())?;
 
-    let core_db = Arc::new(&ctx.core_db);
+    let pkgs_db = Arc::new(ctx.pkgs_db());
+    let (root, target_arch) = (ctx.root_path(), ctx.target_arch());
+    let store = &ctx.content_store();
+    let protected = &ProtectedPaths::new(&ctx.config.protected_paths)?;
+    let (limits, events) = (&ctx.config.extraction_limits, ctx.events.as_ref());
+    let interpreters = &ctx.config.allowed_script_interpreters;
+    let installed = Mutex::new(Vec::new());
     thread::scope(|s| -> Result<(), LpmError<MainError>> {
         pkg_stacks.iter().for_each(|pkg_stack| {
             for item in pkg_stack {
-                let core_db = core_db.clone();
+                let pkgs_db = pkgs_db.clone();
                 let pkg_path = item.pkg_output_path(super::EXTRACTION_OUTPUT_PATH);
                 let group_id = pkg_stack[0].get_group_id();
+                let installed = &installed;
 
                 s.spawn(move || -> Result<(), LpmError<MainError>> {
-                    download_file(&item.pkg_url(), &pkg_path)?;
-                    let pkg = PkgDataFromFs::pre_install_task(&pkg_path)?;
+                    verify_archive(item, &pkg_path)?;
+                    let mut pkg =
+                        PkgDataFromFs::pre_install_task(&pkg_path, target_arch, limits, events)?;
+                    pkg.origin = Some(index_origin(item)?);
+                    if let Some(slot) = &pkg.meta_dir.meta.slot {
+                        if is_package_exists(
+                            &pkgs_db,
+                            &pkg.meta_dir.meta.name,
+                            &[&pkg.meta_dir.meta.arch],
+                            Some(slot),
+                        )? {
+                            logger::info!(
+                                "Slot '{slot}' of '{}' already installed on your machine.",
+                                pkg.meta_dir.meta.name
+                            );
+                            return Ok(());
+                        }
+                    }
+                    pkg.exclude_files(filter)?;
+                    pkg.check_protected_paths(protected)?;
+                    pkg.check_slot_conflicts(&pkgs_db)?;
+                    check_interpreters(&pkg.meta_dir.meta.name, &pkg.scripts, interpreters)?;
 
                     info!("Package installation started for {}", pkg_path.display());
-                    pkg.install_files()?;
-
-                    info!("Syncing with package database..");
-                    let _id = pkg.insert_to_db(&core_db, group_id)?;
+                    pkg.install_files(root, target_arch, store.as_ref(), events)?;
+                    installed.lock().unwrap().push((pkg, group_id));
 
                     Ok(())
                 });
@@ -248,47 +586,155 @@ fn install_from_repository(ctx: Ctx, pkg_names: &HashSet<&str>) -> Result<(), Lp
         Ok(())
     })?;
 
-    Ok(())
-}
-
-/// Local installations ignores the sub-packages(dependencies) for now.
-fn install_from_lod_file(ctx: Ctx, pkg_path: &str) -> Result<(), LpmError<MainError>> {
-    enable_core_db_wal1(&ctx.core_db)?;
+    // Packages of the same transaction can provide each other's libraries and
+    // alternatives, so these have to wait until all of them are installed.
+    let installed = installed.into_inner().unwrap();
+    in_transaction(&pkgs_db, || {
+        info!("Syncing with package database..");
+        for (pkg, group_id) in &installed {
+            let pkg_id = pkg.insert_to_db(&pkgs_db, group_id.clone())?;
+            set_path_filter(&pkgs_db, pkg_id, filter)?;
+        }
 
-    info!("Package installation started for {}", pkg_path);
+        let links: Vec<(String, String)> = installed
+            .iter()
+            .flat_map(|(pkg, _)| alternative_links(&pkg.meta_dir.meta.alternatives))
+            .collect();
+        refresh_alternatives(root, &pkgs_db, &links)
+    })?;
 
-    let pkg_path = PathBuf::from(pkg_path);
-    let pkg = PkgDataFromFs::pre_install_task(&pkg_path)?;
+    let files: Vec<&Files> = installed
+        .iter()
+        .map(|(pkg, _)| &pkg.meta_dir.files)
+        .collect();
+    check_shared_libraries(root, &files, strict)
+}
 
-    if is_package_exists(&ctx.core_db, &pkg.meta_dir.meta.name)? {
+/// Local installations ignores the sub-packages(dependencies) for now.
+fn install_from_lod_file(
+    ctx: &Ctx,
+    pkg_path: &Path,
+    filter: &PathFilter,
+    strict: bool,
+) -> Result<(), LpmError<MainError>> {
+    enable_core_db_wal1(ctx.pkgs_db())?;
+
+    info!("Package installation started for {}", pkg_path.display());
+
+    let mut pkg = PkgDataFromFs::pre_install_task(
+        pkg_path,
+        ctx.target_arch(),
+        &ctx.config.extraction_limits,
+        ctx.events.as_ref(),
+    )?;
+    pkg.exclude_files(filter)?;
+    pkg.check_protected_paths(&ProtectedPaths::new(&ctx.config.protected_paths)?)?;
+
+    let slot = pkg.meta_dir.meta.slot.as_deref();
+    if is_package_exists(
+        ctx.pkgs_db(),
+        &pkg.meta_dir.meta.name,
+        &[&pkg.meta_dir.meta.arch],
+        Some(slot.unwrap_or_default()),
+    )? {
         logger::info!(
-            "Package '{}' already installed on your machine.",
-            pkg.meta_dir.meta.name
+            "Package '{}{}' ({}) already installed on your machine.",
+            pkg.meta_dir.meta.name,
+            slot.map(|t| format!("/{t}")).unwrap_or_default(),
+            pkg.meta_dir.meta.arch
         );
         return Ok(());
     }
-
-    {
-        // TODO
-        // package size is missing
-        // total installation size is missing
-        // use colors
-        println!("\nPackage list to be installed:");
-        println!("  - {}", pkg.meta_dir.meta.get_group_id());
-        println!();
+    pkg.check_slot_conflicts(ctx.pkgs_db())?;
+    check_interpreters(
+        &pkg.meta_dir.meta.name,
+        &pkg.scripts,
+        &ctx.config.allowed_script_interpreters,
+    )?;
+
+    let plan = Plan {
+        operation: "install_local",
+        requested: vec![pkg_path.display().to_string()],
+        entries: vec![PlanEntry::from_meta(&pkg.meta_dir.meta, None)],
+    };
+    if !confirm_plan(ctx, "Package list to be installed:", &plan)? {
+        return Ok(());
     }
 
-    ctx_confirmation_check!(ctx);
+    pkg.install_files(
+        ctx.root_path(),
+        ctx.target_arch(),
+        ctx.content_store().as_ref(),
+        ctx.events.as_ref(),
+    )?;
+
+    in_transaction(ctx.pkgs_db(), || {
+        info!("Syncing with package database..");
+        let pkg_id = pkg.insert_to_db(ctx.pkgs_db(), pkg.meta_dir.meta.get_group_id())?;
+        set_path_filter(ctx.pkgs_db(), pkg_id, filter)?;
+
+        refresh_alternatives(
+            ctx.root_path(),
+            ctx.pkgs_db(),
+            &alternative_links(&pkg.meta_dir.meta.alternatives),
+        )
+    })?;
 
-    pkg.install_files()?;
+    check_shared_libraries(ctx.root_path(), &[&pkg.meta_dir.files], strict)
+}
 
-    info!("Syncing with package database..");
-    let _ = pkg.insert_to_db(&ctx.core_db, pkg.meta_dir.meta.get_group_id())?;
+/// Saves the package piped into stdin, so it can be extracted like any other `.lod` file.
+fn buffer_stdin_to_file() -> Result<PathBuf, LpmError<MainError>> {
+    fs::create_dir_all(super::EXTRACTION_OUTPUT_PATH)?;
+    let pkg_path =
+        Path::new(super::EXTRACTION_OUTPUT_PATH).join(format!("stdin-{}.lod", std::process::id()));
+
+    let mut file = fs::File::create(&pkg_path)?;
+    let size = io::copy(&mut io::stdin().lock(), &mut file)?;
+
+    if size == 0 {
+        fs::remove_file(&pkg_path)?;
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "No package data received from stdin.",
+        ))?;
+    }
 
-    Ok(())
+    debug!(
+        "Buffered {size} bytes from stdin into '{}'",
+        pkg_path.display()
+    );
+
+    Ok(pkg_path)
 }
 
-pub fn install_package(ctx: Ctx, args: &InstallArgs) -> Result<(), LpmError<MainError>> {
+pub fn install_package(ctx: &mut Ctx, args: &InstallArgs) -> Result<(), LpmError<MainError>> {
+    ctx.config
+        .allowed_script_interpreters
+        .extend(args.allow_interpreters.iter().map(|t| t.to_string()));
+
+    let mut filter = PathFilter::new(&ctx.config.no_extract)?;
+    filter.add_patterns(&args.exclude)?;
+    if args.no_docs {
+        filter.exclude_docs();
+    }
+    if let Some(locales) = args.select_locales {
+        filter.select_locales(
+            locales
+                .split(',')
+                .map(|t| t.trim().to_owned())
+                .filter(|t| !t.is_empty())
+                .collect(),
+        );
+    }
+
+    if let Some(root) = args.root {
+        ctx.set_install_root(Path::new(root), args.target_arch)?;
+    } else if args.target_arch.is_some() {
+        logger::error!("'--target-arch' can only be used together with '--root'.");
+        std::process::exit(101);
+    }
+
     if args.from_local_package {
         if args.packages.len() != 1 {
             logger::error!(
@@ -298,8 +744,26 @@ pub fn install_package(ctx: Ctx, args: &InstallArgs) -> Result<(), LpmError<Main
             std::process::exit(101);
         }
 
-        install_from_lod_file(ctx, args.packages.iter().next().unwrap())
+        let pkg_path = args.packages.iter().next().unwrap();
+        if *pkg_path == "-" {
+            let stdin_pkg_path = buffer_stdin_to_file()?;
+            ctx.stdin_consumed = true;
+
+            let result = install_from_lod_file(ctx, &stdin_pkg_path, &filter, args.strict);
+            fs::remove_file(&stdin_pkg_path)?;
+            return result;
+        }
+
+        install_from_lod_file(ctx, Path::new(pkg_path), &filter, args.strict)
     } else {
-        install_from_repository(ctx, &args.packages)
+        let listed_pkgs = match args.from_file {
+            Some(path) => read_package_list(path)?,
+            None => Vec::new(),
+        };
+
+        let mut pkg_names = args.packages.clone();
+        pkg_names.extend(listed_pkgs.iter().map(String::as_str));
+
+        install_from_repository(ctx, &pkg_names, &filter, args.strict)
     }
 }
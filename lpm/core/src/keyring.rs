@@ -0,0 +1,375 @@
+//! Trusted signing keys, seeded by provisioning systems through drop-in files
+//! in `TRUSTED_KEYS_DIR`.
+//!
+//! Each `*.keys` file holds one entry per line, empty lines and lines starting
+//! with `#` are ignored:
+//!
+//! ```text
+//! trust <key id> <hex encoded Ed25519 public key>
+//! revoke <key id>
+//! ```
+//!
+//! Keys can be trusted and revoked in the core database as well, with
+//! `lpm --repository --trust <key id> <public key>` and `--revoke <key id>`.
+//!
+//! Files are read in the order of their names, after the database, but the
+//! result doesn't depend on it: a revocation anywhere wins over trusting the
+//! key, and a key id that is trusted with different public keys is distrusted
+//! entirely.
+//!
+//! Signatures are published as `<key id> <hex encoded signature>`, and are
+//! only accepted from the trusted keys.
+
+use crate::Ctx;
+
+use common::ctx_confirmation_check;
+use ehandle::{lpm::LpmError, repository::RepositoryError, MainError};
+use logger::{info, warning};
+use min_sqlite3_sys::prelude::*;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs, io,
+    path::Path,
+};
+
+pub const TRUSTED_KEYS_DIR: &str = "/etc/lpm/trusted.keys.d";
+
+#[derive(Debug, Clone, PartialEq)]
+enum KeyEntry {
+    Trust { id: String, public_key: String },
+    Revoke(String),
+}
+
+/// Where a key id ended up after merging all of the files.
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeyState {
+    Trusted(String),
+    Revoked,
+    /// Trusted with more than one public key.
+    Conflicting,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Keyring {
+    pub keys: BTreeMap<String, KeyState>,
+}
+
+impl Keyring {
+    /// Public key of `id`, `None` if it's unknown, revoked or conflicting.
+    pub fn trusted_key(&self, id: &str) -> Option<&str> {
+        match self.keys.get(id) {
+            Some(KeyState::Trusted(public_key)) => Some(public_key),
+            _ => None,
+        }
+    }
+
+    /// Checks `signature`, which is `<key id> <hex encoded signature>`, of
+    /// `message`. Returns the key id if it's valid and the key is trusted,
+    /// the reason otherwise.
+    pub fn verify(&self, signature: &str, message: &[u8]) -> Result<String, String> {
+        let (id, signature) = signature
+            .trim()
+            .split_once(' ')
+            .ok_or_else(|| format!("malformed signature '{signature}'"))?;
+        let signature = decode_hex::<{ ed25519::SIGNATURE_LEN }>(signature.trim())
+            .ok_or_else(|| format!("malformed signature of key '{id}'"))?;
+
+        let public_key = match self.keys.get(id) {
+            Some(KeyState::Trusted(public_key)) => public_key,
+            Some(KeyState::Revoked) => return Err(format!("key '{id}' is revoked")),
+            Some(KeyState::Conflicting) => {
+                return Err(format!("key '{id}' is trusted with different public keys"))
+            }
+            None => return Err(format!("key '{id}' is not trusted")),
+        };
+        // Checked when the keys are read.
+        let public_key = decode_hex(public_key).unwrap();
+
+        if !ed25519::verify(&public_key, message, &signature) {
+            return Err(format!("signature of key '{id}' doesn't match"));
+        }
+
+        Ok(id.to_owned())
+    }
+
+    fn merge(entries: impl IntoIterator<Item = KeyEntry>) -> Self {
+        let mut public_keys: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+        let mut revoked = BTreeSet::new();
+
+        for entry in entries {
+            match entry {
+                KeyEntry::Trust { id, public_key } => {
+                    public_keys.entry(id).or_default().insert(public_key);
+                }
+                KeyEntry::Revoke(id) => {
+                    revoked.insert(id);
+                }
+            }
+        }
+
+        let mut keys: BTreeMap<String, KeyState> = public_keys
+            .into_iter()
+            .map(|(id, mut public_keys)| {
+                let state = if public_keys.len() == 1 {
+                    KeyState::Trusted(public_keys.pop_first().unwrap())
+                } else {
+                    KeyState::Conflicting
+                };
+                (id, state)
+            })
+            .collect();
+
+        for id in revoked {
+            keys.insert(id, KeyState::Revoked);
+        }
+
+        Self { keys }
+    }
+}
+
+fn decode_hex<const N: usize>(hex: &str) -> Option<[u8; N]> {
+    if hex.len() != N * 2 || !hex.is_ascii() {
+        return None;
+    }
+
+    let mut bytes = [0; N];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(bytes)
+}
+
+fn parse_keys_file(content: &str) -> Result<Vec<KeyEntry>, String> {
+    let mut entries = Vec::new();
+
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let is_valid_id = |id: &str| {
+            id.chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+        };
+
+        let entry = match fields.as_slice() {
+            ["trust", id, public_key] if is_valid_id(id) => {
+                if decode_hex::<{ ed25519::PUBLIC_KEY_LEN }>(public_key).is_none() {
+                    return Err(format!(
+                        "Line {}: public key must be a hex encoded Ed25519 key.",
+                        i + 1
+                    ));
+                }
+
+                KeyEntry::Trust {
+                    id: id.to_string(),
+                    public_key: public_key.to_ascii_lowercase(),
+                }
+            }
+            ["revoke", id] if is_valid_id(id) => KeyEntry::Revoke(id.to_string()),
+            _ => {
+                return Err(format!(
+                    "Line {}: expected 'trust <key id> <public key>' or 'revoke <key id>'.",
+                    i + 1
+                ))
+            }
+        };
+
+        entries.push(entry);
+    }
+
+    Ok(entries)
+}
+
+/// Reads the `*.keys` files in `dir`, nothing if it doesn't exist. Invalid
+/// files are skipped with a warning, rather than half applied.
+fn read_keys_dir(dir: &Path) -> io::Result<Vec<KeyEntry>> {
+    let mut paths = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .map(|entry| entry.map(|t| t.path()))
+            .collect::<io::Result<Vec<_>>>()?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    paths.retain(|path| path.extension().map_or(false, |t| t == "keys"));
+    paths.sort();
+
+    let mut entries = Vec::new();
+    for path in paths {
+        match parse_keys_file(&fs::read_to_string(&path)?) {
+            Ok(file_entries) => entries.extend(file_entries),
+            Err(e) => warning!("Skipping '{}', {e}", path.display()),
+        }
+    }
+
+    Ok(entries)
+}
+
+/// Merges the keys of the database with the `*.keys` files in `dir`.
+pub fn load_keyring(core_db: &Database, dir: &Path) -> Result<Keyring, LpmError<RepositoryError>> {
+    let mut entries: Vec<KeyEntry> = db::get_trusted_keys(core_db)?
+        .into_iter()
+        .map(|(id, public_key)| match public_key {
+            Some(public_key) => KeyEntry::Trust { id, public_key },
+            None => KeyEntry::Revoke(id),
+        })
+        .collect();
+    entries.extend(read_keys_dir(dir)?);
+
+    Ok(Keyring::merge(entries))
+}
+
+/// Trusts `public_key` as `key_id` in the database.
+pub fn trust_signing_key(
+    ctx: Ctx,
+    key_id: &str,
+    public_key: &str,
+) -> Result<(), LpmError<MainError>> {
+    let entry = match parse_keys_file(&format!("trust {key_id} {public_key}")) {
+        Ok(mut entries) => entries.remove(0),
+        Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidInput, e))?,
+    };
+
+    println!("\nKey to be trusted:\n  - {key_id}: {public_key}\n");
+    ctx_confirmation_check!(ctx);
+
+    if let KeyEntry::Trust { id, public_key } = entry {
+        info!("Trusting key '{id}'..");
+        db::trust_key(&ctx.core_db, &id, &public_key)?;
+    }
+
+    Ok(())
+}
+
+/// Revokes `key_id` in the database, which wins over trusting it in the
+/// drop-in files.
+pub fn revoke_signing_key(ctx: Ctx, key_id: &str) -> Result<(), LpmError<MainError>> {
+    if let Err(e) = parse_keys_file(&format!("revoke {key_id}")) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    }
+
+    println!("\nKey to be revoked:\n  - {key_id}\n");
+    ctx_confirmation_check!(ctx);
+
+    info!("Revoking key '{key_id}'..");
+    db::revoke_key(&ctx.core_db, key_id)?;
+
+    Ok(())
+}
+
+pub fn print_trusted_keys(core_db: &Database) -> Result<(), LpmError<MainError>> {
+    let keyring = load_keyring(core_db, Path::new(TRUSTED_KEYS_DIR))?;
+
+    if keyring.keys.is_empty() {
+        println!("No trusted key has been found.");
+        return Ok(());
+    }
+
+    for (id, state) in &keyring.keys {
+        match state {
+            KeyState::Trusted(public_key) => println!("  {id}: {public_key}"),
+            KeyState::Revoked => println!("  {id}: revoked"),
+            KeyState::Conflicting => {
+                println!("  {id}: distrusted, it is trusted with different public keys")
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Public key of the first test vector of RFC 8032.
+    const PUBLIC_KEY: &str = "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a";
+
+    #[test]
+    fn test_parse_keys_file() {
+        let content = format!(
+            "# release keys\ntrust release-2024 {}\n\nrevoke release-2019\n",
+            PUBLIC_KEY.to_uppercase()
+        );
+        assert_eq!(
+            parse_keys_file(&content).unwrap(),
+            vec![
+                KeyEntry::Trust {
+                    id: String::from("release-2024"),
+                    public_key: String::from(PUBLIC_KEY)
+                },
+                KeyEntry::Revoke(String::from("release-2019")),
+            ]
+        );
+
+        assert!(parse_keys_file("trust release-2024 xyz").is_err());
+        assert!(parse_keys_file("trust release-2024 a1b2c3d4").is_err());
+        assert!(parse_keys_file("trust release-2024").is_err());
+        assert!(parse_keys_file("distrust release-2024").is_err());
+    }
+
+    #[test]
+    fn test_merge_keyring() {
+        let trust = |id: &str, public_key: &str| KeyEntry::Trust {
+            id: id.to_owned(),
+            public_key: public_key.to_owned(),
+        };
+        let entries = vec![
+            trust("a", "aa"),
+            trust("b", "bb"),
+            KeyEntry::Revoke(String::from("b")),
+            trust("c", "cc"),
+            trust("c", "cd"),
+            trust("d", "dd"),
+            trust("d", "dd"),
+        ];
+
+        let keyring = Keyring::merge(entries.clone());
+        assert_eq!(keyring.trusted_key("a"), Some("aa"));
+        assert_eq!(keyring.keys["b"], KeyState::Revoked);
+        assert_eq!(keyring.keys["c"], KeyState::Conflicting);
+        assert_eq!(keyring.trusted_key("d"), Some("dd"));
+
+        // Same result regardless of the order the files are read in.
+        assert_eq!(Keyring::merge(entries.into_iter().rev()), keyring);
+    }
+
+    #[test]
+    fn test_verify() {
+        let keyring = Keyring::merge([
+            KeyEntry::Trust {
+                id: String::from("release-2024"),
+                public_key: String::from(PUBLIC_KEY),
+            },
+            KeyEntry::Trust {
+                id: String::from("release-2019"),
+                public_key: String::from(PUBLIC_KEY),
+            },
+            KeyEntry::Revoke(String::from("release-2019")),
+        ]);
+        let signature = "aae7db2fa76c0a0b5457f004dc78e5656df1ef8798ec9a1d340a10ac107565072c99703fbd65af764160aa797af24bea00930cc87d2b157a0c3eda8e25318d07";
+
+        assert_eq!(
+            keyring.verify(&format!("release-2024 {signature}"), b"htop"),
+            Ok(String::from("release-2024"))
+        );
+        assert_eq!(
+            keyring.verify(&format!("release-2024 {signature}"), b"htop2"),
+            Err(String::from(
+                "signature of key 'release-2024' doesn't match"
+            ))
+        );
+        assert_eq!(
+            keyring.verify(&format!("release-2019 {signature}"), b"htop"),
+            Err(String::from("key 'release-2019' is revoked"))
+        );
+        assert_eq!(
+            keyring.verify(&format!("jane {signature}"), b"htop"),
+            Err(String::from("key 'jane' is not trusted"))
+        );
+        assert!(keyring.verify("release-2024 abcd", b"htop").is_err());
+        assert!(keyring.verify(signature, b"htop").is_err());
+    }
+}
@@ -1,25 +1,105 @@
+mod ab_update;
+mod alternatives;
+mod api;
+mod audit;
+mod auto_update;
+mod backup;
+mod check;
+mod chroot;
 mod ctx;
+mod daemon;
 mod delete;
+mod doctor;
+mod du;
 mod extract;
+mod filter;
+mod history;
+mod inspect;
 mod install;
+mod keyring;
+mod lod;
+mod maintainer;
+mod manifest;
+mod mirrors;
 mod module;
+mod module_host;
+mod notify;
+mod offline_update;
+mod plan;
+mod protect;
+mod rehash;
 mod repository;
+mod rollback;
+mod rpc;
+mod shell;
+mod shlib;
 mod stage1;
+mod stage1_lua;
+mod store;
+mod tui;
 mod update;
 mod validate;
 
-use db::enable_core_db_pragmas;
-use std::path::Path;
+use common::interrupt;
+use db::{enable_core_db_pragmas, enable_read_only_pragmas};
+use std::{
+    fs,
+    os::{raw::c_int, unix::fs::PermissionsExt},
+    path::Path,
+};
+
+const SQLITE_CONFIG_URI: c_int = 17;
+
+extern "C" {
+    fn sqlite3_config(option: c_int, ...) -> c_int;
+}
 
-pub use ctx::Ctx;
+pub use ab_update::{assemble_ab_update, confirm_ab_boot};
+pub use alternatives::{print_alternatives, set_alternative};
+pub use api::{AvailablePackage, Changes, Event, Lpm, Operation, PackageInfo};
+pub use audit::Initiator;
+pub use auto_update::{install_auto_update_units, run_auto_update, AUTO_UPDATE_HISTORY_PATH};
+pub use backup::BACKUPS_DIR;
+pub use check::check_database;
+pub use common::event::{EventSink, LogEvents, NoEvents};
+pub use common::meta::PackageKind;
+pub use ctx::{Ctx, InstallRoot};
+pub use daemon::run_daemon;
 pub use delete::delete_packages;
+pub use doctor::run_doctor;
+pub use du::print_disk_usage;
 pub(crate) use extract::PkgExtractTasks;
+pub use history::TRANSACTION_HISTORY_PATH;
+pub use inspect::{print_installed_package_info, print_package_info};
 pub use install::install_package;
-pub use module::{add_module, delete_modules, print_modules, trigger_lpm_module};
+pub use keyring::{
+    load_keyring, print_trusted_keys, revoke_signing_key, trust_signing_key, KeyState, Keyring,
+    TRUSTED_KEYS_DIR,
+};
+pub use maintainer::print_maintainers;
+pub use manifest::diff_manifest;
+pub use mirrors::rank_mirrors;
+pub use module::{
+    add_module, delete_modules, print_module_help, print_module_summaries, print_modules,
+    trigger_lpm_module,
+};
+pub use notify::run_transaction;
+pub use offline_update::{apply_staged_updates, stage_updates, STAGED_UPDATES_DIR};
+pub use plan::{apply_plan, PlanFormat};
+pub use rehash::rehash_installed_files;
 pub use repository::get_and_apply_repository_patches;
-pub use repository::{add_repository, delete_repositories, print_repositories};
+pub use repository::{
+    add_repository, configure_repository, delete_repositories, print_repositories,
+    print_repository_packages, PackageSort,
+};
+pub use rpc::{run_rpc_daemon, DEFAULT_RPC_SOCKET_PATH};
+pub use shell::run_shell;
+pub use stage1::run_pending_scripts;
+pub use store::STORE_DIR;
+pub use tui::run_tui;
 pub use update::{
-    update_pkg_from_lod_file, update_pkg_from_repository, update_pkgs_from_repository,
+    check_updates, update_pkg_from_lod_file, update_pkg_from_repository,
+    update_pkgs_from_repository,
 };
 
 use ehandle::{lpm::LpmError, MainError};
@@ -27,18 +107,207 @@ use min_sqlite3_sys::prelude::*;
 
 const EXTRACTION_OUTPUT_PATH: &str = "/tmp/lpm";
 
-pub fn update_database_migrations() -> Result<(), LpmError<MainError>> {
-    std::fs::create_dir_all(std::path::Path::new(db::CORE_DB_PATH).parent().unwrap())?;
+/// Reads package names from `path`, one per line. Empty lines and lines
+/// starting with `#` are ignored.
+pub(crate) fn read_package_list(path: &str) -> std::io::Result<Vec<String>> {
+    Ok(parse_package_list(&std::fs::read_to_string(path)?))
+}
+
+fn parse_package_list(content: &str) -> Vec<String> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect()
+}
+
+pub fn update_database_migrations(core_db: &Database) -> Result<(), LpmError<MainError>> {
     std::fs::create_dir_all(db::REPOSITORY_INDEX_DB_DIR)?;
     std::fs::create_dir_all(stage1::PKG_SCRIPTS_DIR)?;
 
-    db::migrate_database_tables(&open_core_db_connection()?)?;
+    db::migrate_database_tables(core_db)?;
+
+    Ok(())
+}
+
+/// Runs `f` inside a transaction of `core_db`, which is rolled back if `f` fails.
+pub(crate) fn in_transaction<T>(
+    core_db: &Database,
+    f: impl FnOnce() -> Result<T, LpmError<MainError>>,
+) -> Result<T, LpmError<MainError>> {
+    db::transaction_op(core_db, db::Transaction::Begin)?;
+
+    let result = f().and_then(|value| {
+        // A signal during the last step still rolls the transaction back.
+        interrupt::check()?;
+        Ok(value)
+    });
+
+    match result {
+        Ok(value) => {
+            db::transaction_op(core_db, db::Transaction::Commit)?;
+            Ok(value)
+        }
+        Err(err) => {
+            db::transaction_op(core_db, db::Transaction::Rollback)?;
+            Err(err)
+        }
+    }
+}
+
+/// Prints the applied and pending migrations of the database.
+pub fn print_migration_status(ctx: Ctx) -> Result<(), LpmError<MainError>> {
+    let status = db::get_migration_status(&ctx.core_db)?;
+    let (applied, pending): (Vec<_>, Vec<_>) = status
+        .iter()
+        .partition(|migration| migration.applied_at.is_some());
+
+    match applied.last() {
+        Some(last) => println!("Schema version: {} ({})", applied.len(), last.name),
+        None => println!("Schema version: 0"),
+    }
+
+    println!("\nApplied migrations:");
+    for migration in &applied {
+        let applied_at = migration.applied_at.as_deref().unwrap_or_default();
+        if migration.is_known {
+            println!("  - {} ({applied_at})", migration.name);
+        } else {
+            println!(
+                "  - {} ({applied_at}, unknown to this lpm version)",
+                migration.name
+            );
+        }
+    }
+
+    if pending.is_empty() {
+        println!("\nNo pending migrations.");
+    } else {
+        println!("\nPending migrations:");
+        for migration in &pending {
+            println!("  - {}", migration.name);
+        }
+        println!("\nRun 'lpm --update --db' to apply them.");
+    }
+
+    Ok(())
+}
+
+/// Reverts the `name` migration and the ones applied after it.
+pub fn revert_database_migrations(ctx: Ctx, name: &str) -> Result<(), LpmError<MainError>> {
+    logger::warning!(
+        "Reverting '{name}' and the migrations applied after it, the data they hold will be lost."
+    );
+    common::ctx_confirmation_check!(ctx);
+
+    let reverted = db::revert_migrations(&ctx.core_db, name)?;
+    logger::info!("Reverted {} migration(s).", reverted.len());
 
     Ok(())
 }
 
 pub fn open_core_db_connection() -> Result<Database, LpmError<MainError>> {
-    let core_db = Database::open(Path::new(db::CORE_DB_PATH))?;
+    let core_db_path = Path::new(db::CORE_DB_PATH);
+    let is_new = !core_db_path.exists();
+    fs::create_dir_all(core_db_path.parent().unwrap())?;
+    let core_db = Database::open(core_db_path)?;
+    enable_core_db_pragmas(&core_db)?;
+
+    // Readable by everyone regardless of the umask of root, so that the
+    // queries work without root. Databases created before that are fixed up
+    // too, along with the WAL files that SQLite creates with their mode.
+    if is_new {
+        for dir in core_db_path.ancestors().skip(1).take(2) {
+            fs::set_permissions(dir, fs::Permissions::from_mode(0o755))?;
+        }
+    }
+    for path in ["", "-wal", "-shm"].map(|t| format!("{}{t}", db::CORE_DB_PATH)) {
+        match fs::metadata(&path) {
+            Ok(metadata) if metadata.permissions().mode() & 0o7777 != 0o644 => {
+                fs::set_permissions(&path, fs::Permissions::from_mode(0o644))?;
+            }
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    Ok(core_db)
+}
+
+/// Opens the core database for queries only, which works for unprivileged
+/// users too.
+///
+/// Users that can't write to it open it with `mode=ro`. While a writer has
+/// the database open, they read its `-shm` file, which is as readable as the
+/// database, otherwise the WAL is checkpointed and they read the database
+/// itself.
+pub fn open_core_db_connection_read_only() -> Result<Database, LpmError<MainError>> {
+    let core_db_path = Path::new(db::CORE_DB_PATH);
+    if !core_db_path.exists() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!(
+                "'{}' doesn't exist, run lpm as root first.",
+                core_db_path.display()
+            ),
+        )
+        .into());
+    }
+
+    let is_writable = fs::OpenOptions::new()
+        .append(true)
+        .open(core_db_path)
+        .is_ok();
+
+    let core_db = if is_writable {
+        Database::open(core_db_path)?
+    } else {
+        // SAFETY: Takes a single int after `SQLITE_CONFIG_URI`. It fails
+        // harmlessly if SQLite is already initialized.
+        #[allow(unsafe_code)]
+        let uri_enabled = unsafe { sqlite3_config(SQLITE_CONFIG_URI, 1 as c_int) } == 0;
+        if uri_enabled {
+            Database::open(format!("file:{}?mode=ro", db::CORE_DB_PATH))?
+        } else {
+            Database::open(core_db_path)?
+        }
+    };
+    enable_read_only_pragmas(&core_db)?;
+
+    Ok(core_db)
+}
+
+/// Opens (and migrates if needed) the core database of an alternate root.
+pub fn open_core_db_connection_at(root: &Path) -> Result<Database, LpmError<MainError>> {
+    let core_db_path = root.join(db::CORE_DB_PATH.trim_start_matches('/'));
+    std::fs::create_dir_all(core_db_path.parent().unwrap())?;
+    std::fs::create_dir_all(root.join(stage1::PKG_SCRIPTS_DIR.trim_start_matches('/')))?;
+
+    let core_db = Database::open(&core_db_path)?;
     enable_core_db_pragmas(&core_db)?;
+    db::migrate_database_tables(&core_db)?;
+
     Ok(core_db)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_package_list() {
+        let content = "htop\n\n# editors\n  vim  \nncurses>=6.4\n";
+        assert_eq!(
+            parse_package_list(content),
+            vec![
+                String::from("htop"),
+                String::from("vim"),
+                String::from("ncurses>=6.4")
+            ]
+        );
+
+        assert!(parse_package_list("").is_empty());
+    }
+}
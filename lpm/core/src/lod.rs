@@ -0,0 +1,411 @@
+//! Layouts of the `.lod` archives.
+//!
+//! v1 is a single LZ4 frame of a tar archive holding `meta/`, `system.json`,
+//! `scripts/` and `program/`, so even reading the meta data decompresses the
+//! stream up to it.
+//!
+//! v2 splits the same tree into sections, each an LZ4 frame of its own tar
+//! archive, and ends with a table of contents pointing to them:
+//!
+//! ```text
+//! [section "meta": meta/meta.json, meta/files.json, system.json]
+//! [section "data": scripts/, program/]
+//! [toc: per section, name length (u8), name, offset (u64 LE), length (u64 LE)]
+//! [toc length (u64 LE)][magic "LOD\0TOC2"]
+//! ```
+//!
+//! All sections unpack into the same tree as a v1 archive.
+//!
+//! The frames can also be plain tar archives, which is what `none` in the
+//! `compression` of the meta data builds. The algorithm is detected from the
+//! magic number of each frame, as the meta data is only readable after that.
+
+use common::{
+    compression::{Algorithm, MAGIC_SIZE},
+    meta::{Files, Meta},
+};
+use json::{Deserialize, Json};
+use std::{
+    fs::File,
+    io::{self, Chain, Cursor, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+use tiny_lz4_decoder_sys::Decoder;
+
+const TOC_MAGIC: &[u8; 8] = b"LOD\0TOC2";
+const TRAILER_SIZE: u64 = 16;
+/// Section that holds `meta/` and `system.json`.
+const META_SECTION: &str = "meta";
+
+#[derive(Debug, PartialEq)]
+pub(crate) struct Section {
+    pub(crate) name: String,
+    pub(crate) offset: u64,
+    pub(crate) length: u64,
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum Layout {
+    V1,
+    V2(Vec<Section>),
+}
+
+impl Layout {
+    pub(crate) fn format_version(&self) -> u8 {
+        match self {
+            Layout::V1 => 1,
+            Layout::V2(_) => 2,
+        }
+    }
+}
+
+fn invalid_toc(reason: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Invalid table of contents in the package, {reason}"),
+    )
+}
+
+/// Reads the table of contents at the end of `file`, `Layout::V1` if there is
+/// none.
+pub(crate) fn read_layout(file: &mut File) -> io::Result<Layout> {
+    let file_size = file.metadata()?.len();
+    if file_size < TRAILER_SIZE {
+        return Ok(Layout::V1);
+    }
+
+    let mut trailer = [0; TRAILER_SIZE as usize];
+    file.seek(SeekFrom::End(-(TRAILER_SIZE as i64)))?;
+    file.read_exact(&mut trailer)?;
+    if &trailer[8..] != TOC_MAGIC {
+        return Ok(Layout::V1);
+    }
+
+    let toc_length = u64::from_le_bytes(trailer[..8].try_into().unwrap());
+    let toc_end = file_size - TRAILER_SIZE;
+    if toc_length > toc_end {
+        return Err(invalid_toc("it is larger than the archive."));
+    }
+
+    let mut toc = vec![0; toc_length as usize];
+    file.seek(SeekFrom::Start(toc_end - toc_length))?;
+    file.read_exact(&mut toc)?;
+
+    let sections = parse_toc(&toc)?;
+    if sections.iter().any(|t| {
+        t.offset
+            .checked_add(t.length)
+            .map_or(true, |end| end > toc_end - toc_length)
+    }) {
+        return Err(invalid_toc("a section points outside of the archive."));
+    }
+
+    Ok(Layout::V2(sections))
+}
+
+fn parse_toc(mut toc: &[u8]) -> io::Result<Vec<Section>> {
+    let mut sections = Vec::new();
+    while let Some((&name_length, rest)) = toc.split_first() {
+        let name_length = name_length as usize;
+        if rest.len() < name_length + 16 {
+            return Err(invalid_toc("it is truncated."));
+        }
+
+        let name = std::str::from_utf8(&rest[..name_length])
+            .map_err(|_| invalid_toc("a section name is not valid UTF-8."))?;
+        let read_u64 = |at: usize| u64::from_le_bytes(rest[at..at + 8].try_into().unwrap());
+
+        sections.push(Section {
+            name: name.to_owned(),
+            offset: read_u64(name_length),
+            length: read_u64(name_length + 8),
+        });
+        toc = &rest[name_length + 16..];
+    }
+
+    Ok(sections)
+}
+
+enum Frame<R: Read> {
+    Lz4(Decoder<Chain<Cursor<Vec<u8>>, R>>),
+    Tar(Chain<Cursor<Vec<u8>>, R>),
+}
+
+/// Decompressed tar archive of a v1 archive or a v2 section.
+pub(crate) struct FrameDecoder<R: Read> {
+    algorithm: Algorithm,
+    frame: Frame<R>,
+}
+
+impl<R: Read> FrameDecoder<R> {
+    pub(crate) fn new(mut reader: R) -> io::Result<Self> {
+        let mut magic = Vec::with_capacity(MAGIC_SIZE);
+        (&mut reader)
+            .take(MAGIC_SIZE as u64)
+            .read_to_end(&mut magic)?;
+
+        let algorithm = Algorithm::detect(&magic);
+        let reader = Cursor::new(magic).chain(reader);
+        let frame = match algorithm {
+            Algorithm::Lz4 => Frame::Lz4(Decoder::new(reader)?),
+            Algorithm::None => Frame::Tar(reader),
+        };
+
+        Ok(Self { algorithm, frame })
+    }
+
+    pub(crate) fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+}
+
+impl<R: Read> Read for FrameDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match &mut self.frame {
+            Frame::Lz4(decoder) => decoder.read(buf),
+            Frame::Tar(reader) => reader.read(buf),
+        }
+    }
+}
+
+/// Decoder of `section`, read from `file`.
+pub(crate) fn section_decoder<R: Read + Seek>(
+    mut file: R,
+    section: &Section,
+) -> io::Result<FrameDecoder<io::Take<R>>> {
+    file.seek(SeekFrom::Start(section.offset))?;
+    FrameDecoder::new(file.take(section.length))
+}
+
+/// Meta data of a package, read without extracting the archive.
+pub(crate) struct LodMeta {
+    pub(crate) format_version: u8,
+    pub(crate) meta: Meta,
+    pub(crate) files: Files,
+}
+
+/// Reads `meta/meta.json` and `meta/files.json` of the package at `pkg_path`.
+/// Only the meta section of v2 archives is decompressed, v1 archives are
+/// decompressed until both files are found.
+pub(crate) fn read_lod_meta(pkg_path: &Path) -> io::Result<LodMeta> {
+    let mut file = File::open(pkg_path)?;
+    let layout = read_layout(&mut file)?;
+
+    let (meta, files) = match &layout {
+        Layout::V1 => {
+            file.rewind()?;
+            read_meta_entries(untar::Archive::new(FrameDecoder::new(&file)?))?
+        }
+        Layout::V2(sections) => {
+            let section = sections
+                .iter()
+                .find(|t| t.name == META_SECTION)
+                .ok_or_else(|| invalid_toc("the meta section is missing."))?;
+            read_meta_entries(untar::Archive::new(section_decoder(&file, section)?))?
+        }
+    };
+
+    let invalid_data = |file_name: &str, e: String| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Package has invalid meta data, '{file_name}': {e}"),
+        )
+    };
+    let parse = |content: &str| Json::new(content).parse();
+
+    Ok(LodMeta {
+        format_version: layout.format_version(),
+        meta: parse(&meta)
+            .and_then(|t| Meta::from_json_object(&t))
+            .map_err(|e| invalid_data("meta.json", e))?,
+        files: parse(&files)
+            .and_then(|t| Files::from_json_object(&t))
+            .map_err(|e| invalid_data("files.json", e))?,
+    })
+}
+
+/// Contents of `meta/meta.json` and `meta/files.json`, stops reading
+/// `archive` once both are found.
+fn read_meta_entries<R: Read>(mut archive: untar::Archive<R>) -> io::Result<(String, String)> {
+    let (mut meta, mut files) = (None, None);
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?;
+        let path: PathBuf = path.strip_prefix("./").unwrap_or(&path).to_path_buf();
+
+        let target = if path == Path::new("meta/meta.json") {
+            &mut meta
+        } else if path == Path::new("meta/files.json") {
+            &mut files
+        } else {
+            continue;
+        };
+
+        let mut content = String::new();
+        entry.read_to_string(&mut content)?;
+        *target = Some(content);
+
+        if meta.is_some() && files.is_some() {
+            break;
+        }
+    }
+
+    match (meta, files) {
+        (Some(meta), Some(files)) => Ok((meta, files)),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Package is missing 'meta/meta.json' or 'meta/files.json'.",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::testing::TestDir;
+    use std::io::Write;
+
+    /// LZ4 frame of uncompressed blocks, which the decoder accepts as is.
+    fn lz4_frame(data: &[u8]) -> Vec<u8> {
+        // Independent blocks of up to 64 KiB, and the checksum of these flags.
+        let mut frame = vec![0x04, 0x22, 0x4d, 0x18, 0x60, 0x40, 0x82];
+        for block in data.chunks(64 * 1024) {
+            frame.extend((block.len() as u32 | 0x8000_0000).to_le_bytes());
+            frame.extend(block);
+        }
+        frame.extend(0u32.to_le_bytes());
+        frame
+    }
+
+    fn tar(entries: &[(&str, &str)]) -> Vec<u8> {
+        let mut tar = Vec::new();
+        for (path, content) in entries {
+            let mut header = [0u8; 512];
+            header[..path.len()].copy_from_slice(path.as_bytes());
+            header[100..108].copy_from_slice(b"0000644\0");
+            header[108..116].copy_from_slice(b"0000000\0");
+            header[116..124].copy_from_slice(b"0000000\0");
+            header[124..136].copy_from_slice(format!("{:011o}\0", content.len()).as_bytes());
+            header[136..148].copy_from_slice(b"00000000000\0");
+            header[148..156].copy_from_slice(b"        ");
+            header[156] = b'0';
+            header[257..265].copy_from_slice(b"ustar\x0000");
+            let checksum: u32 = header.iter().map(|t| *t as u32).sum();
+            header[148..156].copy_from_slice(format!("{checksum:06o}\0 ").as_bytes());
+
+            tar.extend(header);
+            tar.extend(content.as_bytes());
+            tar.resize((tar.len() + 511) / 512 * 512, 0);
+        }
+        tar.resize(tar.len() + 1024, 0);
+        tar
+    }
+
+    const META: &str = r#"{"name": "htop", "arch": "amd64", "installed_size": 1024, "version": {"readable_format": "3.2.2", "major": 3, "minor": 2, "patch": 2, "tag": null}, "dependencies": [], "suggestions": []}"#;
+    const FILES: &str =
+        r#"[{"path": "usr/bin/htop", "checksum_algorithm": "sha256", "checksum": "00"}]"#;
+
+    /// Writes the sections into a v2 archive at `path`.
+    fn write_v2(path: &Path, sections: &[(&str, Vec<u8>)]) {
+        let (mut archive, mut toc) = (Vec::new(), Vec::new());
+        for (name, content) in sections {
+            toc.push(name.len() as u8);
+            toc.extend(name.as_bytes());
+            toc.extend((archive.len() as u64).to_le_bytes());
+            toc.extend((content.len() as u64).to_le_bytes());
+            archive.extend(content);
+        }
+        archive.extend(&toc);
+        archive.extend((toc.len() as u64).to_le_bytes());
+        archive.extend(TOC_MAGIC);
+
+        File::create(path).unwrap().write_all(&archive).unwrap();
+    }
+
+    #[test]
+    fn test_read_lod_meta_v1() {
+        let dir = TestDir::new("lod-v1");
+        let path = dir.join("htop.lod");
+        let archive = tar(&[
+            ("./program/usr/bin/htop", "binary"),
+            ("./meta/meta.json", META),
+            ("./meta/files.json", FILES),
+        ]);
+        File::create(&path)
+            .unwrap()
+            .write_all(&lz4_frame(&archive))
+            .unwrap();
+
+        let lod = read_lod_meta(&path).unwrap();
+        assert_eq!(lod.format_version, 1);
+        assert_eq!(lod.meta.get_group_id(), "htop@3.2.2");
+        assert_eq!(lod.files.0[0].path, "usr/bin/htop");
+    }
+
+    #[test]
+    fn test_frame_decoder() {
+        let archive = tar(&[("./meta/meta.json", META)]);
+
+        let mut content = Vec::new();
+        let mut decoder = FrameDecoder::new(&archive[..]).unwrap();
+        assert_eq!(decoder.algorithm(), Algorithm::None);
+        decoder.read_to_end(&mut content).unwrap();
+        assert_eq!(content, archive);
+
+        let frame = lz4_frame(&archive);
+        let decoder = FrameDecoder::new(&frame[..]).unwrap();
+        assert_eq!(decoder.algorithm(), Algorithm::Lz4);
+    }
+
+    #[test]
+    fn test_read_lod_meta_v2() {
+        let dir = TestDir::new("lod-v2");
+        let path = dir.join("htop.lod");
+        write_v2(
+            &path,
+            &[
+                // Reading the meta data must not touch this section.
+                ("data", b"not an lz4 frame".to_vec()),
+                (
+                    "meta",
+                    lz4_frame(&tar(&[
+                        ("meta/meta.json", META),
+                        ("meta/files.json", FILES),
+                    ])),
+                ),
+            ],
+        );
+
+        let mut file = File::open(&path).unwrap();
+        let Layout::V2(sections) = read_layout(&mut file).unwrap() else {
+            panic!("expected a v2 layout");
+        };
+        assert_eq!(
+            sections.iter().map(|t| t.name.as_str()).collect::<Vec<_>>(),
+            ["data", "meta"]
+        );
+
+        let lod = read_lod_meta(&path).unwrap();
+        assert_eq!(lod.format_version, 2);
+        assert_eq!(lod.meta.name, "htop");
+    }
+
+    #[test]
+    fn test_parse_toc() {
+        let mut toc = vec![4];
+        toc.extend(b"meta");
+        toc.extend(10u64.to_le_bytes());
+        toc.extend(20u64.to_le_bytes());
+
+        assert_eq!(
+            parse_toc(&toc).unwrap(),
+            vec![Section {
+                name: String::from("meta"),
+                offset: 10,
+                length: 20
+            }]
+        );
+        assert!(parse_toc(&toc[..toc.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,205 @@
+//! Maintainers of the installed packages, as set in their meta data, and the
+//! keys that signed the indexes they were installed from. The key that a
+//! package declares isn't used, since anyone can write it.
+//!
+//! An update that comes from another maintainer or is signed with another
+//! key is applied with a warning, as it's how a takeover of a package would
+//! look like. `lpm --db maintainers` lists who the installed packages are
+//! trusted from.
+
+use crate::Ctx;
+
+use common::{
+    meta::Meta,
+    pkg::{PkgDataFromDb, PkgOrigin},
+};
+use db::pkg::DbOpsForInstalledPkg;
+use ehandle::{lpm::LpmError, MainError};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Descriptions of how the maintainer or the signing key of a package changes
+/// from `old` to `new`, the keys being the ones in their origins. Setting them
+/// for the first time isn't a change.
+pub(crate) fn maintainer_changes(
+    old: &Meta,
+    old_origin: Option<&PkgOrigin>,
+    new: &Meta,
+    new_origin: Option<&PkgOrigin>,
+) -> Vec<String> {
+    let mut changes = Vec::new();
+
+    if let Some(old_maintainer) = &old.maintainer {
+        match &new.maintainer {
+            Some(new_maintainer) if new_maintainer == old_maintainer => {}
+            Some(new_maintainer) => changes.push(format!(
+                "Maintainer of '{}' changes from '{old_maintainer}' to '{new_maintainer}'.",
+                old.name
+            )),
+            None => changes.push(format!(
+                "'{}' no longer names its maintainer, it was '{old_maintainer}'.",
+                old.name
+            )),
+        }
+    }
+
+    if let Some(old_key) = signing_key(old_origin) {
+        match signing_key(new_origin) {
+            Some(new_key) if new_key == old_key => {}
+            Some(new_key) => changes.push(format!(
+                "Signing key of '{}' changes from '{old_key}' to '{new_key}'.",
+                old.name
+            )),
+            None => changes.push(format!(
+                "'{}' is no longer signed, its signing key was '{old_key}'.",
+                old.name
+            )),
+        }
+    }
+
+    changes
+}
+
+fn signing_key(origin: Option<&PkgOrigin>) -> Option<&str> {
+    origin.and_then(|t| t.signing_key.as_deref())
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct MaintainerStats {
+    packages: usize,
+    signing_keys: BTreeSet<String>,
+}
+
+/// Installed packages per maintainer, `None` for the ones that don't set any.
+fn maintainer_stats<'a>(
+    pkgs: impl IntoIterator<Item = (&'a Meta, Option<&'a PkgOrigin>)>,
+) -> BTreeMap<Option<&'a str>, MaintainerStats> {
+    let mut stats: BTreeMap<Option<&str>, MaintainerStats> = BTreeMap::new();
+
+    for (meta, origin) in pkgs {
+        let entry = stats.entry(meta.maintainer.as_deref()).or_default();
+        entry.packages += 1;
+        entry
+            .signing_keys
+            .extend(signing_key(origin).map(str::to_owned));
+    }
+
+    stats
+}
+
+/// Prints the maintainers of the installed packages, with the number of
+/// packages and the signing keys of each.
+pub fn print_maintainers(ctx: Ctx) -> Result<(), LpmError<MainError>> {
+    let pkgs = PkgDataFromDb::load_all_packages(&ctx.core_db)?;
+    let stats = maintainer_stats(
+        pkgs.iter()
+            .map(|t| (&t.meta_fields.meta, t.origin.as_ref())),
+    );
+
+    let mut stats: Vec<_> = stats.into_iter().collect();
+    stats.sort_by(|a, b| b.1.packages.cmp(&a.1.packages).then(a.0.cmp(&b.0)));
+
+    let width = stats
+        .iter()
+        .map(|(maintainer, _)| maintainer.unwrap_or("-").len())
+        .max()
+        .unwrap_or(0);
+
+    println!();
+    for (maintainer, stats) in &stats {
+        let keys = match stats.signing_keys.len() {
+            0 => String::from("no signing key"),
+            _ => stats
+                .signing_keys
+                .iter()
+                .cloned()
+                .collect::<Vec<_>>()
+                .join(", "),
+        };
+        println!(
+            "  {:<width$}  {:>5} packages  {keys}",
+            maintainer.unwrap_or("-"),
+            stats.packages
+        );
+    }
+
+    let multiple_keys: Vec<&str> = stats
+        .iter()
+        .filter(|(_, stats)| stats.signing_keys.len() > 1)
+        .filter_map(|(maintainer, _)| *maintainer)
+        .collect();
+    if !multiple_keys.is_empty() {
+        logger::warning!(
+            "Packages of '{}' are signed with more than one key.",
+            multiple_keys.join("', '")
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use json::{Deserialize, Json, Serialize};
+
+    fn meta(maintainer: Option<&str>) -> Meta {
+        let json = format!(
+            r#"{{"name": "htop", "arch": "amd64", "installed_size": 0, "version": {{"readable_format": "1.0.0", "major": 1, "minor": 0, "patch": 0, "tag": null}}, "dependencies": [], "suggestions": [], "maintainer": {}, "signing_key": "jane-2024"}}"#,
+            maintainer.to_json()
+        );
+        Meta::from_json_object(&Json::new(&json).parse().unwrap()).unwrap()
+    }
+
+    fn origin(signing_key: Option<&str>) -> Option<PkgOrigin> {
+        Some(PkgOrigin {
+            repository: String::from("core"),
+            index_timestamp: 0,
+            signing_key: signing_key.map(str::to_owned),
+        })
+    }
+
+    #[test]
+    fn test_maintainer_changes() {
+        let (jane, jane_origin) = (meta(Some("Jane")), origin(Some("jane-2024")));
+        let changes = |new: &Meta, new_origin: &Option<PkgOrigin>| {
+            maintainer_changes(&jane, jane_origin.as_ref(), new, new_origin.as_ref())
+        };
+
+        assert!(changes(&jane, &jane_origin).is_empty());
+        assert!(maintainer_changes(&meta(None), None, &jane, jane_origin.as_ref()).is_empty());
+        assert_eq!(
+            changes(&meta(Some("Mallory")), &origin(Some("mallory"))),
+            [
+                "Maintainer of 'htop' changes from 'Jane' to 'Mallory'.",
+                "Signing key of 'htop' changes from 'jane-2024' to 'mallory'."
+            ]
+        );
+        // The key that the package declares doesn't count.
+        assert_eq!(
+            changes(&jane, &origin(None)),
+            ["'htop' is no longer signed, its signing key was 'jane-2024'."]
+        );
+        assert_eq!(
+            changes(&jane, &None),
+            ["'htop' is no longer signed, its signing key was 'jane-2024'."]
+        );
+    }
+
+    #[test]
+    fn test_maintainer_stats() {
+        let pkgs = [
+            (meta(Some("Jane")), origin(Some("jane-2024"))),
+            (meta(Some("Jane")), origin(Some("jane-2025"))),
+            (meta(None), None),
+        ];
+        let stats = maintainer_stats(pkgs.iter().map(|(meta, origin)| (meta, origin.as_ref())));
+
+        assert_eq!(stats[&Some("Jane")].packages, 2);
+        assert_eq!(
+            stats[&Some("Jane")].signing_keys,
+            BTreeSet::from([String::from("jane-2024"), String::from("jane-2025")])
+        );
+        assert_eq!(stats[&None].packages, 1);
+    }
+}
@@ -0,0 +1,301 @@
+//! Desired package set of a system, as kept by configuration management.
+//!
+//! A manifest lists the packages that should be installed, one per line, as
+//! `name` or `name@version` with the version conditions of `--install`
+//! (e.g. `htop@>=3.2`). Empty lines and lines starting with `#` are ignored.
+//!
+//! `lpm --diff-manifest` compares it against the explicitly installed
+//! packages; the ones installed as dependencies are left to their
+//! dependents.
+
+use crate::{
+    delete_packages, install_package, notify::run_transaction, parse_package_list,
+    update::update_pkg_to_version, Ctx, Operation,
+};
+
+use cli_parser::{DeleteArgs, InstallArgs};
+use common::{
+    ctx_confirmation_check, interrupt,
+    meta::Meta,
+    pkg::{PkgDataFromDb, PkgToQuery},
+    version::{Condition, VersionStruct},
+};
+use db::pkg::DbOpsForInstalledPkg;
+use ehandle::{lpm::LpmError, MainError};
+use logger::info;
+use std::{
+    cmp::Ordering,
+    collections::{BTreeSet, HashSet},
+    fs, io,
+};
+
+struct ManifestEntry {
+    /// As written in the manifest, which `--install` accepts too.
+    line: String,
+    query: PkgToQuery,
+}
+
+/// A change that brings the installed packages closer to the manifest.
+#[derive(Debug, PartialEq)]
+enum ManifestAction {
+    /// Not installed, as listed in the manifest.
+    Install(String),
+    /// Installed in a version the manifest doesn't allow, which may also
+    /// need a downgrade.
+    Upgrade {
+        name: String,
+        current_version: String,
+        wanted: String,
+    },
+    /// Installed explicitly, but not listed in the manifest.
+    Remove(String),
+}
+
+fn parse_manifest(content: &str) -> Result<Vec<ManifestEntry>, io::Error> {
+    let mut names = HashSet::new();
+    let mut entries = Vec::new();
+
+    for line in parse_package_list(content) {
+        let query = PkgToQuery::parse(&line)
+            .filter(|t| !t.name.is_empty())
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Invalid manifest entry '{line}'."),
+                )
+            })?;
+
+        if !names.insert(query.name.clone()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("'{}' is listed more than once in the manifest.", query.name),
+            ));
+        }
+
+        entries.push(ManifestEntry { line, query });
+    }
+
+    Ok(entries)
+}
+
+/// Whether `version` matches the version conditions of `query`. The parts
+/// left out of the query are taken from `version`, so `htop@>=3.2` compares
+/// as `3.2.<patch>` against the whole version.
+fn satisfies(version: &VersionStruct, query: &PkgToQuery) -> bool {
+    let wanted = VersionStruct {
+        major: query.major.unwrap_or(version.major),
+        minor: query.minor.unwrap_or(version.minor),
+        patch: query.patch.unwrap_or(version.patch),
+        tag: query.tag.clone().or_else(|| version.tag.clone()),
+        ..VersionStruct::default()
+    };
+
+    let ordering = version.compare(&wanted);
+    match query.condition {
+        Condition::Less => ordering == Ordering::Less,
+        Condition::LessOrEqual => ordering != Ordering::Greater,
+        Condition::Equal => ordering == Ordering::Equal,
+        Condition::GreaterOrEqual => ordering != Ordering::Less,
+        Condition::Greater => ordering == Ordering::Greater,
+    }
+}
+
+/// Actions that converge the `installed` packages to the manifest `entries`,
+/// in the order they are applied.
+fn diff_installed(entries: &[ManifestEntry], installed: &[&Meta]) -> Vec<ManifestAction> {
+    let mut installs = Vec::new();
+    let mut upgrades = Vec::new();
+
+    for entry in entries {
+        let installed_versions: Vec<&Meta> = installed
+            .iter()
+            .copied()
+            .filter(|meta| meta.name == entry.query.name)
+            .collect();
+
+        if installed_versions.is_empty() {
+            installs.push(ManifestAction::Install(entry.line.clone()));
+        } else if let Some(meta) = installed_versions
+            .iter()
+            .find(|meta| !satisfies(&meta.version, &entry.query))
+        {
+            upgrades.push(ManifestAction::Upgrade {
+                name: meta.name.clone(),
+                current_version: meta.version.readable_format.clone(),
+                wanted: entry.line.clone(),
+            });
+        }
+    }
+
+    let listed: HashSet<&str> = entries.iter().map(|t| t.query.name.as_str()).collect();
+    let removals = installed
+        .iter()
+        .map(|meta| meta.name.as_str())
+        .filter(|name| !listed.contains(name))
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .map(|name| ManifestAction::Remove(name.to_owned()));
+
+    installs
+        .into_iter()
+        .chain(upgrades)
+        .chain(removals)
+        .collect()
+}
+
+fn format_action(action: &ManifestAction) -> String {
+    match action {
+        ManifestAction::Install(line) => format!("  install  {line}"),
+        ManifestAction::Upgrade {
+            name,
+            current_version,
+            wanted,
+        } => format!("  upgrade  {name} ({current_version} -> {wanted})"),
+        ManifestAction::Remove(name) => format!("  remove   {name}"),
+    }
+}
+
+/// Prints what it takes for the installed packages to match the manifest at
+/// `path`, and applies it if `apply` is set. Returns whether the system is
+/// left different from the manifest.
+pub fn diff_manifest(ctx: &mut Ctx, path: &str, apply: bool) -> Result<bool, LpmError<MainError>> {
+    let entries = parse_manifest(&fs::read_to_string(path)?)?;
+    let pkgs = PkgDataFromDb::load_all_main_packages(&ctx.core_db)?;
+    let installed: Vec<&Meta> = pkgs.iter().map(|t| &t.meta_fields.meta).collect();
+
+    let actions = diff_installed(&entries, &installed);
+    if actions.is_empty() {
+        info!("Installed packages match {path}.");
+        return Ok(false);
+    }
+
+    println!("\nActions to converge to {path}:");
+    for action in &actions {
+        println!("{}", format_action(action));
+    }
+
+    if !apply {
+        return Ok(true);
+    }
+
+    ctx_confirmation_check!(ctx);
+    interrupt::install_handlers();
+    apply_actions(ctx, &entries, &actions)?;
+
+    Ok(false)
+}
+
+fn apply_actions(
+    ctx: &mut Ctx,
+    entries: &[ManifestEntry],
+    actions: &[ManifestAction],
+) -> Result<(), LpmError<MainError>> {
+    let installs: HashSet<&str> = actions
+        .iter()
+        .filter_map(|action| match action {
+            ManifestAction::Install(line) => Some(line.as_str()),
+            _ => None,
+        })
+        .collect();
+    if !installs.is_empty() {
+        run_transaction(&mut *ctx, Operation::Install, |ctx| {
+            install_package(
+                ctx,
+                &InstallArgs {
+                    packages: installs,
+                    ..Default::default()
+                },
+            )
+        })?;
+    }
+
+    for action in actions {
+        if let ManifestAction::Upgrade { name, .. } = action {
+            let entry = entries.iter().find(|t| &t.query.name == name);
+            run_transaction(&*ctx, Operation::Update, |ctx| {
+                update_pkg_to_version(ctx, name, entry.map(|t| &t.query))
+            })?;
+        }
+    }
+
+    let removals: HashSet<&str> = actions
+        .iter()
+        .filter_map(|action| match action {
+            ManifestAction::Remove(name) => Some(name.as_str()),
+            _ => None,
+        })
+        .collect();
+    if !removals.is_empty() {
+        run_transaction(&*ctx, Operation::Delete, |ctx| {
+            delete_packages(
+                ctx,
+                &DeleteArgs {
+                    packages: removals,
+                    ..Default::default()
+                },
+            )
+        })?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use json::{Deserialize, Json};
+
+    fn meta(name: &str, version: &str) -> Meta {
+        let parts: Vec<&str> = version.split('.').collect();
+        let json = format!(
+            r#"{{"name": "{name}", "arch": "amd64", "installed_size": 0, "version": {{"readable_format": "{version}", "major": {}, "minor": {}, "patch": {}, "tag": null}}, "dependencies": [], "suggestions": []}}"#,
+            parts[0], parts[1], parts[2]
+        );
+        Meta::from_json_object(&Json::new(&json).parse().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_parse_manifest() {
+        let entries = parse_manifest("# base\nhtop\n\n  vim@>=9.1\n").unwrap();
+        let lines: Vec<&str> = entries.iter().map(|t| t.line.as_str()).collect();
+        assert_eq!(lines, ["htop", "vim@>=9.1"]);
+        assert_eq!(entries[1].query.name, "vim");
+        assert_eq!(entries[1].query.condition, Condition::GreaterOrEqual);
+
+        assert!(parse_manifest("htop\nhtop@3.2.2\n").is_err());
+        assert!(parse_manifest("htop@3@4\n").is_err());
+    }
+
+    #[test]
+    fn test_diff_installed() {
+        let entries = parse_manifest("htop\nvim@>=9.1\ncurl@=8.0.0\nnano\n").unwrap();
+        let (htop, vim, curl, git) = (
+            meta("htop", "3.2.2"),
+            meta("vim", "9.0.5"),
+            meta("curl", "8.0.0"),
+            meta("git", "2.43.0"),
+        );
+
+        assert_eq!(
+            diff_installed(&entries, &[&htop, &vim, &curl, &git]),
+            [
+                ManifestAction::Install(String::from("nano")),
+                ManifestAction::Upgrade {
+                    name: String::from("vim"),
+                    current_version: String::from("9.0.5"),
+                    wanted: String::from("vim@>=9.1"),
+                },
+                ManifestAction::Remove(String::from("git")),
+            ]
+        );
+
+        let entries = parse_manifest("htop@<4\ncurl@8\n").unwrap();
+        assert!(diff_installed(&entries, &[&htop, &curl]).is_empty());
+
+        // 10.0 is newer than 9.1 although its minor part is lower.
+        let entries = parse_manifest("vim@>=9.1\nhtop@<3.3\n").unwrap();
+        let vim = meta("vim", "10.0.0");
+        assert!(diff_installed(&entries, &[&htop, &vim]).is_empty());
+    }
+}
@@ -0,0 +1,190 @@
+//! `lpm --repository --rank-mirrors`, which measures the mirrors of the
+//! repositories and stores them fastest first, the order downloads try them.
+
+use crate::Ctx;
+
+use common::{fetch_range, local_address_path, size::format_byte_size, DownloadOptions};
+use db::{get_repositories, get_repository_options, update_repository_options};
+use ehandle::{lpm::LpmError, repository::RepositoryErrorKind, ErrorCommons, MainError};
+use logger::{info, warning};
+use std::{
+    io,
+    time::{Duration, Instant},
+};
+
+/// Bytes fetched from each mirror to measure its throughput.
+const THROUGHPUT_PROBE_SIZE: u64 = 256 * 1024;
+/// Mirrors are compared by how long they would take to send this much, so
+/// that neither latency nor throughput alone decide.
+const REFERENCE_DOWNLOAD_SIZE: f64 = 1024.0 * 1024.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Probe {
+    latency: Duration,
+    /// Bytes per second.
+    throughput: f64,
+}
+
+impl Probe {
+    fn estimated_time(&self) -> Duration {
+        self.latency + Duration::from_secs_f64(REFERENCE_DOWNLOAD_SIZE / self.throughput)
+    }
+}
+
+/// Ranks the mirrors of `names`, or of all the repositories that have any if
+/// it's empty. The repository address is ranked along with its mirrors.
+pub fn rank_mirrors(ctx: Ctx, names: &[String]) -> Result<(), LpmError<MainError>> {
+    let repositories = get_repositories(&ctx.core_db)?;
+    for name in names {
+        if !repositories.iter().any(|(t, _)| t == name) {
+            return Err(RepositoryErrorKind::RepositoryNotFound(name.to_owned()).to_lpm_err())?;
+        }
+    }
+
+    for (name, address) in repositories {
+        if !names.is_empty() && !names.contains(&name) {
+            continue;
+        }
+
+        let mut options = get_repository_options(&ctx.core_db, &name)?;
+        if options.mirrors.is_empty() {
+            if !names.is_empty() {
+                warning!("{name} repository has no mirrors, add them with `lpm --repository --configure {name} mirrors=<list>`.");
+            }
+            continue;
+        }
+
+        let mut candidates = options.mirrors.clone();
+        if !candidates.contains(&address) {
+            candidates.push(address);
+        }
+
+        info!(
+            "Ranking {} mirrors of {name} repository..",
+            candidates.len()
+        );
+        let download_options = options.download_options(&ctx.config)?;
+        let probes: Vec<(String, io::Result<Probe>)> = candidates
+            .into_iter()
+            .map(|t| {
+                let probe = probe(&t, &download_options);
+                (t, probe)
+            })
+            .collect();
+
+        println!("\nMirrors of '{name}', fastest first:");
+        let ranked = rank(probes);
+        for (position, (address, probe)) in ranked.iter().enumerate() {
+            match probe {
+                Ok(probe) if probe.throughput.is_infinite() => {
+                    println!("  {}. {address} (local)", position + 1)
+                }
+                Ok(probe) => println!(
+                    "  {}. {address} ({} ms, {}/s)",
+                    position + 1,
+                    probe.latency.as_millis(),
+                    format_byte_size(probe.throughput as u64)
+                ),
+                Err(e) => println!("  {}. {address} (unreachable: {e})", position + 1),
+            }
+        }
+        println!();
+
+        options.mirrors = ranked.into_iter().map(|(address, _)| address).collect();
+        update_repository_options(&ctx.core_db, &name, &options)?;
+    }
+
+    Ok(())
+}
+
+/// Sorts the mirrors by their estimated download time, keeping the order of
+/// the unreachable ones at the end.
+fn rank(mut probes: Vec<(String, io::Result<Probe>)>) -> Vec<(String, io::Result<Probe>)> {
+    probes.sort_by_key(|(_, probe)| match probe {
+        Ok(probe) => (false, probe.estimated_time()),
+        Err(_) => (true, Duration::ZERO),
+    });
+
+    probes
+}
+
+fn probe(address: &str, options: &DownloadOptions) -> io::Result<Probe> {
+    if let Some(path) = local_address_path(address) {
+        if !path.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("'{}' is not a directory", path.display()),
+            ));
+        }
+
+        return Ok(Probe {
+            latency: Duration::ZERO,
+            throughput: f64::INFINITY,
+        });
+    }
+
+    let timed_fetch = |url: String, len: u64| -> io::Result<(Duration, usize)> {
+        let started = Instant::now();
+        let response = fetch_range(&url, len, options)?;
+        if response.status_code >= 400 {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("server responded with {}", response.status_code),
+            ));
+        }
+
+        Ok((started.elapsed(), response.body.len()))
+    };
+
+    let (latency, _) = timed_fetch(format!("{address}/index-tracker/health"), 1)?;
+    let (elapsed, len) = timed_fetch(format!("{address}/index-tracker/0"), THROUGHPUT_PROBE_SIZE)?;
+    let transfer_time = elapsed
+        .saturating_sub(latency)
+        .max(Duration::from_millis(1));
+
+    Ok(Probe {
+        latency,
+        throughput: len.max(1) as f64 / transfer_time.as_secs_f64(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rank() {
+        let probe = |latency_ms, throughput| {
+            Ok(Probe {
+                latency: Duration::from_millis(latency_ms),
+                throughput,
+            })
+        };
+        let unreachable = || Err(io::Error::from(io::ErrorKind::ConnectionRefused));
+
+        let ranked: Vec<String> = rank(vec![
+            (String::from("http://down-1"), unreachable()),
+            (String::from("http://slow"), probe(20, 128.0 * 1024.0)),
+            (
+                String::from("http://far"),
+                probe(300, 16.0 * 1024.0 * 1024.0),
+            ),
+            (String::from("http://down-2"), unreachable()),
+            (String::from("http://near"), probe(5, 8.0 * 1024.0 * 1024.0)),
+        ])
+        .into_iter()
+        .map(|(address, _)| address)
+        .collect();
+
+        assert_eq!(
+            ranked,
+            [
+                "http://near",
+                "http://far",
+                "http://slow",
+                "http://down-1",
+                "http://down-2"
+            ]
+        );
+    }
+}
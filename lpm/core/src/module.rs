@@ -1,15 +1,23 @@
-use crate::Ctx;
+use crate::{
+    module_host::{ModuleDb, ModuleHost},
+    Ctx,
+};
 
 use common::{ctx_confirmation_check, some_or_error};
-use db::{get_dylib_path_by_name, insert_module, is_module_exists, CORE_DB_PATH};
+use db::{
+    get_dylib_path_by_name, has_module_db_write, insert_module, is_module_exists, CORE_DB_PATH,
+};
 use ehandle::{
     lpm::LpmError,
     module::{ModuleError, ModuleErrorKind},
     ErrorCommons, MainError,
 };
-use logger::{debug, info};
+use logger::{debug, info, warning};
 use min_sqlite3_sys::prelude::*;
-use std::ffi::CString;
+use std::{
+    ffi::{CStr, CString},
+    path::Path,
+};
 
 struct ModuleController(*mut std::os::raw::c_void);
 
@@ -34,22 +42,39 @@ extern "C" {
 type ModuleEntrypointFn =
     extern "C" fn(*const std::os::raw::c_char, std::os::raw::c_uint, *const std::os::raw::c_void);
 
+// Preferred over `lpm_entrypoint`, the module queries the core database through the host
+// instead of opening it, see `module_host`.
+type ModuleHostEntrypointFn =
+    extern "C" fn(*const ModuleHost, std::os::raw::c_uint, *const std::os::raw::c_void);
+
+const ENTRYPOINT: &str = "lpm_entrypoint";
+const HOST_ENTRYPOINT: &str = "lpm_module_entrypoint";
+
+// Optional, returns the usage of the module as a NUL terminated string that stays owned by
+// the module. Its first line is recorded when the module is added, and shown as the summary
+// of the module in the general help.
+type ModuleHelpFn = extern "C" fn() -> *const std::os::raw::c_char;
+
 impl ModuleController {
     fn validate(dylib_path: &str) -> Result<(), LpmError<ModuleError>> {
         let mc = Self::load(dylib_path)?;
 
-        let func_name = CString::new("lpm_entrypoint")?;
-
-        #[allow(unsafe_code)]
-        let func_ptr = unsafe { dlsym(mc.0, func_name.as_ptr()) };
-
-        if func_ptr.is_null() {
+        if mc.symbol(HOST_ENTRYPOINT)?.is_null() && mc.symbol(ENTRYPOINT)?.is_null() {
             return Err(ModuleErrorKind::EntrypointFunctionNotFound.to_lpm_err());
         }
 
         Ok(())
     }
 
+    fn symbol(&self, name: &str) -> Result<*mut std::os::raw::c_void, LpmError<ModuleError>> {
+        let func_name = CString::new(name)?;
+
+        #[allow(unsafe_code)]
+        let func_ptr = unsafe { dlsym(self.0, func_name.as_ptr()) };
+
+        Ok(func_ptr)
+    }
+
     fn load(dylib_path: &str) -> Result<Self, LpmError<ModuleError>> {
         let module = CString::new(dylib_path)?;
 
@@ -65,19 +90,21 @@ impl ModuleController {
         Ok(Self(lib_pointer))
     }
 
-    fn run(&self, args: Vec<String>) -> Result<(), LpmError<ModuleError>> {
-        let func_name = CString::new("lpm_entrypoint")?;
-
-        #[allow(unsafe_code)]
-        let func_ptr = unsafe { dlsym(self.0, func_name.as_ptr()) };
-
-        if func_ptr.is_null() {
+    /// Runs the module, giving it the host of `module_db` if it exports
+    /// `lpm_module_entrypoint`.
+    fn run(
+        &self,
+        args: Vec<String>,
+        module_db: &ModuleDb,
+        schema_version: u32,
+    ) -> Result<(), LpmError<ModuleError>> {
+        let host_func_ptr = self.symbol(HOST_ENTRYPOINT)?;
+        let func_ptr = self.symbol(ENTRYPOINT)?;
+
+        if host_func_ptr.is_null() && func_ptr.is_null() {
             return Err(ModuleErrorKind::EntrypointFunctionNotFound.to_lpm_err());
         }
 
-        #[allow(unsafe_code)]
-        let lpm_entrypoint: ModuleEntrypointFn = unsafe { std::mem::transmute(func_ptr) };
-
         let cstrings: Vec<CString> = args
             .iter()
             .map(|s| CString::new(s.as_str()).unwrap())
@@ -86,6 +113,29 @@ impl ModuleController {
             cstrings.iter().map(|s| s.as_ptr()).collect();
         args_ptrs.push(std::ptr::null());
 
+        if !host_func_ptr.is_null() {
+            #[allow(unsafe_code)]
+            let lpm_module_entrypoint: ModuleHostEntrypointFn =
+                unsafe { std::mem::transmute(host_func_ptr) };
+
+            let host = module_db.host(schema_version);
+            lpm_module_entrypoint(
+                &host,
+                (args_ptrs.len() - 1) as std::os::raw::c_uint,
+                args_ptrs.as_ptr() as *const std::os::raw::c_void,
+            );
+
+            return Ok(());
+        }
+
+        warning!(
+            "The module exports '{ENTRYPOINT}' only and opens the database by itself, \
+             it should move to '{HOST_ENTRYPOINT}'."
+        );
+
+        #[allow(unsafe_code)]
+        let lpm_entrypoint: ModuleEntrypointFn = unsafe { std::mem::transmute(func_ptr) };
+
         let db_path = CString::new(CORE_DB_PATH)?;
         lpm_entrypoint(
             db_path.as_ptr(),
@@ -95,6 +145,31 @@ impl ModuleController {
 
         Ok(())
     }
+
+    /// Usage reported by `lpm_module_help`, `None` if the module doesn't export it.
+    fn help(&self) -> Result<Option<String>, LpmError<ModuleError>> {
+        let func_name = CString::new("lpm_module_help")?;
+
+        #[allow(unsafe_code)]
+        let func_ptr = unsafe { dlsym(self.0, func_name.as_ptr()) };
+
+        if func_ptr.is_null() {
+            return Ok(None);
+        }
+
+        #[allow(unsafe_code)]
+        let lpm_module_help: ModuleHelpFn = unsafe { std::mem::transmute(func_ptr) };
+
+        let help = lpm_module_help();
+        if help.is_null() {
+            return Ok(None);
+        }
+
+        #[allow(unsafe_code)]
+        let help = unsafe { CStr::from_ptr(help) };
+
+        Ok(Some(help.to_string_lossy().into_owned()))
+    }
 }
 
 impl Drop for ModuleController {
@@ -121,13 +196,27 @@ pub fn trigger_lpm_module(
     info!("Module '{}' loaded.", module_name);
     let module_controller = ModuleController::load(&dylib_path)?;
 
-    module_controller.run(args.clone())?;
+    let module_db = ModuleDb::open(
+        Path::new(CORE_DB_PATH),
+        has_module_db_write(core_db, module_name)?,
+    )?;
+    let schema_version = db::get_migration_status(core_db)?
+        .iter()
+        .filter(|t| t.applied_at.is_some())
+        .count() as u32;
+
+    module_controller.run(args.clone(), &module_db, schema_version)?;
     info!("Module '{}' finished running.", module_name);
 
     Ok(())
 }
 
-pub fn add_module(ctx: Ctx, name: &str, dylib_path: &str) -> Result<(), LpmError<MainError>> {
+pub fn add_module(
+    ctx: Ctx,
+    name: &str,
+    dylib_path: &str,
+    db_write: bool,
+) -> Result<(), LpmError<MainError>> {
     // read absolute path of the dynamic library
     let dylib_path = std::fs::canonicalize(dylib_path)?;
     let dylib_path = dylib_path.to_string_lossy();
@@ -141,6 +230,9 @@ pub fn add_module(ctx: Ctx, name: &str, dylib_path: &str) -> Result<(), LpmError
         // use colors
         println!("\nModule list to be registered:");
         println!("  - {name}: {dylib_path}");
+        if db_write {
+            println!("    It will be able to write to the core database.");
+        }
         println!();
     }
     ctx_confirmation_check!(ctx);
@@ -148,9 +240,18 @@ pub fn add_module(ctx: Ctx, name: &str, dylib_path: &str) -> Result<(), LpmError
     // validate the module
     debug!("Validating {name} module..");
     ModuleController::validate(&dylib_path)?;
+    let summary = ModuleController::load(&dylib_path)?
+        .help()?
+        .and_then(|t| t.lines().next().map(str::to_owned));
 
     info!("Adding {name} module to the database..");
-    insert_module(&ctx.core_db, name, &dylib_path)?;
+    insert_module(
+        &ctx.core_db,
+        name,
+        &dylib_path,
+        db_write,
+        summary.as_deref(),
+    )?;
 
     Ok(())
 }
@@ -201,3 +302,41 @@ pub fn print_modules(ctx: Ctx) -> Result<(), LpmError<MainError>> {
 
     Ok(())
 }
+
+pub fn print_module_help(core_db: &Database, module_name: &str) -> Result<(), LpmError<MainError>> {
+    let dylib_path = get_dylib_path_by_name(core_db, module_name)?
+        .ok_or_else(|| ModuleErrorKind::ModuleNotFound(module_name.to_owned()).to_lpm_err())?;
+
+    match ModuleController::load(&dylib_path)?.help()? {
+        Some(help) => println!("{}", help.trim_end()),
+        None => println!("Module '{module_name}' doesn't provide any help."),
+    }
+
+    Ok(())
+}
+
+/// Lists the registered modules along with the summaries recorded when they
+/// were added, for the general help output. The modules themselves aren't
+/// loaded. Prints nothing if the core database doesn't exist yet.
+pub fn print_module_summaries() -> Result<(), LpmError<MainError>> {
+    if !Path::new(CORE_DB_PATH).exists() {
+        return Ok(());
+    }
+
+    let core_db = Database::open(Path::new(CORE_DB_PATH))?;
+    // Databases that aren't migrated yet have no summaries.
+    let list = db::get_module_summaries(&core_db).or_else(|_| {
+        db::get_modules(&core_db).map(|t| t.into_iter().map(|(name, _)| (name, None)).collect())
+    })?;
+    if list.is_empty() {
+        return Ok(());
+    }
+
+    println!("Modules:");
+    for (name, summary) in list {
+        println!("    {name:<58}{}", summary.unwrap_or_default());
+    }
+    println!("\nFor the usage of a module, go for `lpm --module --help <Module Name>`\n");
+
+    Ok(())
+}
@@ -0,0 +1,315 @@
+//! Access of the modules to the core database, mediated by lpm so that they
+//! don't open `CORE_DB_PATH` on their own and run into its locks or depend on
+//! how it's opened.
+//!
+//! Modules that export `lpm_module_entrypoint` get a `ModuleHost` instead of
+//! the database path:
+//!
+//! ```c
+//! typedef int (*lpm_row_callback)(void *user_data, int column_count, char **values, char **names);
+//!
+//! struct lpm_module_host {
+//!     unsigned int version;
+//!     /* Number of the applied migrations, to check the tables against. */
+//!     unsigned int schema_version;
+//!     void *handle;
+//!     int (*query)(void *handle, const char *sql, lpm_row_callback callback, void *user_data);
+//!     /* NULL unless the module was added with `--allow-db-write`. */
+//!     int (*execute)(void *handle, const char *sql);
+//! };
+//! ```
+//!
+//! Both functions return an SQLite result code, 0 on success. `query` runs on
+//! a read-only connection, so it fails with `SQLITE_READONLY`(8) on writes.
+
+use logger::error;
+use std::{
+    ffi::{CStr, CString},
+    io,
+    os::{
+        raw::{c_char, c_int, c_uint, c_void},
+        unix::ffi::OsStrExt,
+    },
+    path::Path,
+    ptr,
+    sync::{Mutex, PoisonError},
+};
+
+/// Version of `ModuleHost`, fields are only ever appended to it.
+const MODULE_HOST_VERSION: c_uint = 1;
+
+const SQLITE_OK: c_int = 0;
+const SQLITE_MISUSE: c_int = 21;
+const SQLITE_OPEN_READONLY: c_int = 0x1;
+const SQLITE_OPEN_READWRITE: c_int = 0x2;
+const SQLITE_LIMIT_ATTACHED: c_int = 7;
+/// How long the queries of the modules wait for the locks of lpm.
+const BUSY_TIMEOUT_MS: c_int = 5000;
+
+#[repr(C)]
+struct Sqlite3 {
+    _private: [u8; 0],
+}
+
+pub(crate) type RowCallback =
+    extern "C" fn(*mut c_void, c_int, *mut *mut c_char, *mut *mut c_char) -> c_int;
+
+extern "C" {
+    fn sqlite3_open_v2(
+        filename: *const c_char,
+        db: *mut *mut Sqlite3,
+        flags: c_int,
+        vfs: *const c_char,
+    ) -> c_int;
+    fn sqlite3_close(db: *mut Sqlite3) -> c_int;
+    fn sqlite3_busy_timeout(db: *mut Sqlite3, ms: c_int) -> c_int;
+    fn sqlite3_limit(db: *mut Sqlite3, id: c_int, new_value: c_int) -> c_int;
+    fn sqlite3_exec(
+        db: *mut Sqlite3,
+        sql: *const c_char,
+        callback: Option<RowCallback>,
+        user_data: *mut c_void,
+        error_message: *mut *mut c_char,
+    ) -> c_int;
+    fn sqlite3_free(ptr: *mut c_void);
+}
+
+struct Connection(*mut Sqlite3);
+
+impl Connection {
+    fn open(path: &Path, flags: c_int) -> io::Result<Self> {
+        let c_path = CString::new(path.as_os_str().as_bytes())?;
+        let mut db = ptr::null_mut();
+
+        // SAFETY: `c_path` is NUL terminated and `db` is a valid out pointer,
+        // the default VFS is used for the null one.
+        #[allow(unsafe_code)]
+        let result = unsafe { sqlite3_open_v2(c_path.as_ptr(), &mut db, flags, ptr::null()) };
+        // A handle is returned even when opening fails, and must be closed.
+        let connection = Self(db);
+        if result != SQLITE_OK {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!(
+                    "Couldn't open '{}' for the module, SQLite error {result}",
+                    path.display()
+                ),
+            ));
+        }
+
+        // SAFETY: `connection.0` is an open connection.
+        #[allow(unsafe_code)]
+        unsafe {
+            sqlite3_busy_timeout(connection.0, BUSY_TIMEOUT_MS);
+            // Other databases would be opened with the same flags, but the
+            // modules have no business with them.
+            sqlite3_limit(connection.0, SQLITE_LIMIT_ATTACHED, 0);
+        }
+
+        Ok(connection)
+    }
+
+    fn exec(
+        &self,
+        sql: *const c_char,
+        callback: Option<RowCallback>,
+        user_data: *mut c_void,
+    ) -> c_int {
+        let mut error_message = ptr::null_mut();
+
+        // SAFETY: The connection is open, `sql` is checked to be non-null
+        // and the callback gets `user_data` as the module passed it.
+        #[allow(unsafe_code)]
+        let result = unsafe { sqlite3_exec(self.0, sql, callback, user_data, &mut error_message) };
+        if !error_message.is_null() {
+            // SAFETY: SQLite returns a NUL terminated message, which is freed
+            // with `sqlite3_free` once read.
+            #[allow(unsafe_code)]
+            unsafe {
+                error!(
+                    "Module query failed: {}",
+                    CStr::from_ptr(error_message).to_string_lossy()
+                );
+                sqlite3_free(error_message as *mut c_void);
+            }
+        }
+
+        result
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        // SAFETY: Closing a null handle is a no-op.
+        #[allow(unsafe_code)]
+        unsafe {
+            sqlite3_close(self.0);
+        }
+    }
+}
+
+/// Connections to the core database for a running module. Calls of the
+/// module threads are serialized per connection.
+pub(crate) struct ModuleDb {
+    reader: Mutex<Connection>,
+    /// `None` unless the module was added with `--allow-db-write`.
+    writer: Option<Mutex<Connection>>,
+}
+
+/// Handed over to `lpm_module_entrypoint`, see the module documentation.
+#[repr(C)]
+pub(crate) struct ModuleHost {
+    version: c_uint,
+    schema_version: c_uint,
+    handle: *const c_void,
+    query: extern "C" fn(*const c_void, *const c_char, Option<RowCallback>, *mut c_void) -> c_int,
+    execute: Option<extern "C" fn(*const c_void, *const c_char) -> c_int>,
+}
+
+impl ModuleDb {
+    pub(crate) fn open(path: &Path, db_write: bool) -> io::Result<Self> {
+        Ok(Self {
+            reader: Mutex::new(Connection::open(path, SQLITE_OPEN_READONLY)?),
+            writer: match db_write {
+                true => Some(Mutex::new(Connection::open(path, SQLITE_OPEN_READWRITE)?)),
+                false => None,
+            },
+        })
+    }
+
+    /// Host that borrows `self`, which must outlive the module call.
+    pub(crate) fn host(&self, schema_version: u32) -> ModuleHost {
+        ModuleHost {
+            version: MODULE_HOST_VERSION,
+            schema_version,
+            handle: self as *const Self as *const c_void,
+            query,
+            execute: self.writer.as_ref().map(|_| execute as _),
+        }
+    }
+}
+
+/// `ModuleDb` of a host handle, `None` for null pointers.
+fn module_db<'a>(handle: *const c_void, sql: *const c_char) -> Option<&'a ModuleDb> {
+    if handle.is_null() || sql.is_null() {
+        return None;
+    }
+
+    // SAFETY: Non-null handles come from `ModuleDb::host`, whose `ModuleDb`
+    // outlives the module call.
+    #[allow(unsafe_code)]
+    Some(unsafe { &*(handle as *const ModuleDb) })
+}
+
+extern "C" fn query(
+    handle: *const c_void,
+    sql: *const c_char,
+    callback: Option<RowCallback>,
+    user_data: *mut c_void,
+) -> c_int {
+    let Some(db) = module_db(handle, sql) else {
+        return SQLITE_MISUSE;
+    };
+
+    let reader = db.reader.lock().unwrap_or_else(PoisonError::into_inner);
+    reader.exec(sql, callback, user_data)
+}
+
+extern "C" fn execute(handle: *const c_void, sql: *const c_char) -> c_int {
+    let Some(writer) = module_db(handle, sql).and_then(|t| t.writer.as_ref()) else {
+        return SQLITE_MISUSE;
+    };
+
+    let writer = writer.lock().unwrap_or_else(PoisonError::into_inner);
+    writer.exec(sql, None, ptr::null_mut())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::testing::TestDir;
+    use min_sqlite3_sys::{connection::Connection as _, prelude::Database};
+
+    extern "C" fn collect_rows(
+        user_data: *mut c_void,
+        column_count: c_int,
+        values: *mut *mut c_char,
+        _names: *mut *mut c_char,
+    ) -> c_int {
+        // SAFETY: The tests pass a `Vec<Vec<Option<String>>>`, and SQLite
+        // passes `column_count` values that are NULL or NUL terminated.
+        #[allow(unsafe_code)]
+        unsafe {
+            let rows = &mut *(user_data as *mut Vec<Vec<Option<String>>>);
+            let row = (0..column_count as usize)
+                .map(|i| {
+                    let value = *values.add(i);
+                    (!value.is_null()).then(|| CStr::from_ptr(value).to_string_lossy().into_owned())
+                })
+                .collect();
+            rows.push(row);
+        }
+
+        0
+    }
+
+    #[test]
+    fn test_module_db() {
+        let dir = TestDir::new("module-host");
+        let path = dir.join("core-db");
+        drop(Database::open(&path).unwrap());
+
+        let sql = |sql: &str| CString::new(sql).unwrap();
+        let writable = ModuleDb::open(&path, true).unwrap();
+        let host = writable.host(3);
+        let execute = host.execute.unwrap();
+        assert_eq!(
+            execute(
+                host.handle,
+                sql("CREATE TABLE t (a TEXT, b INTEGER);").as_ptr()
+            ),
+            SQLITE_OK
+        );
+        assert_eq!(
+            execute(
+                host.handle,
+                sql("INSERT INTO t VALUES ('x', 1), (NULL, 2);").as_ptr()
+            ),
+            SQLITE_OK
+        );
+
+        let read_only = ModuleDb::open(&path, false).unwrap();
+        let host = read_only.host(3);
+        assert_eq!(host.schema_version, 3);
+        assert!(host.execute.is_none());
+
+        let mut rows: Vec<Vec<Option<String>>> = Vec::new();
+        let result = (host.query)(
+            host.handle,
+            sql("SELECT a, b FROM t ORDER BY b;").as_ptr(),
+            Some(collect_rows),
+            &mut rows as *mut _ as *mut c_void,
+        );
+        assert_eq!(result, SQLITE_OK);
+        assert_eq!(
+            rows,
+            [
+                vec![Some(String::from("x")), Some(String::from("1"))],
+                vec![None, Some(String::from("2"))],
+            ]
+        );
+
+        let write_through_query = (host.query)(
+            host.handle,
+            sql("DELETE FROM t;").as_ptr(),
+            None,
+            ptr::null_mut(),
+        );
+        // SQLITE_READONLY
+        assert_eq!(write_through_query, 8);
+        assert_eq!(
+            (host.query)(ptr::null(), ptr::null(), None, ptr::null_mut()),
+            SQLITE_MISUSE
+        );
+    }
+}
@@ -0,0 +1,176 @@
+//! Notifications fired after each transaction to the `notify` targets of the
+//! config, so monitoring systems know when a machine changes.
+//!
+//! Every target gets the same summary:
+//!
+//! ```json
+//! {"operation": "install", "status": "success", "error": null, "hostname": "web-1",
+//!  "changes": {"installed": [...], "updated": [{"from": ..., "to": ...}], "deleted": [...]},
+//!  "stats": {"elapsed_ms": 1520, "downloaded_bytes": 2048, ...}}
+//! ```
+//!
+//! Commands also get the operation and status in `LPM_OPERATION` and
+//! `LPM_STATUS`. A failing target is only warned about, it never fails the
+//! transaction.
+
+use crate::{
+    api::diff_packages, api::installed_packages, audit::send_audit_event,
+    extract::remove_extracted_packages, history::record_transaction, Changes, Ctx, Operation,
+    TRANSACTION_HISTORY_PATH,
+};
+
+use common::{config::NotifyTarget, interrupt, post_json, stats, DownloadOptions};
+use ehandle::{lpm::LpmError, MainError};
+use json::{to_json_object, Serialize};
+use logger::{debug, info, warning};
+use std::{
+    borrow::Borrow,
+    fs,
+    io::{self, Write},
+    process::{Command, Stdio},
+    time::{Duration, Instant},
+};
+
+/// Runs the transaction `f`, then notifies the targets, unless it neither
+/// failed nor changed anything(e.g. the plan was only printed).
+///
+/// Once `f` gets the transaction confirmed, SIGINT and SIGTERM make it fail
+/// like any other error, see `common::interrupt`. The partially extracted
+/// packages are removed then.
+///
+/// `ctx` is either `&Ctx` or `&mut Ctx`, whichever `f` needs.
+pub fn run_transaction<C: Borrow<Ctx>>(
+    mut ctx: C,
+    operation: Operation,
+    f: impl FnOnce(&mut C) -> Result<(), LpmError<MainError>>,
+) -> Result<Changes, LpmError<MainError>> {
+    let before = installed_packages(ctx.borrow())?;
+    // Leftovers of whatever ran before, e.g. the index sync.
+    stats::take(Duration::ZERO);
+    let started_at = Instant::now();
+    let result = f(&mut ctx);
+    let stats = stats::take(started_at.elapsed());
+
+    if interrupt::is_interrupted() {
+        if let Err(e) = remove_extracted_packages() {
+            warning!("Couldn't remove the extracted packages: {e}");
+        }
+    }
+
+    let ctx: &Ctx = ctx.borrow();
+    // Objects of the replaced and removed files, whichever way it ended.
+    if let Some(store) = ctx.content_store() {
+        match store.prune() {
+            Ok(removed) => debug!("Removed {removed} unused objects from the store."),
+            Err(e) => warning!("Couldn't remove the unused objects from the store: {e}"),
+        }
+    }
+
+    let changes = match installed_packages(ctx) {
+        Ok(after) => diff_packages(&before, &after),
+        Err(e) => {
+            result?;
+            return Err(e);
+        }
+    };
+
+    if result.is_err() || !changes.is_empty() {
+        info!("{}", stats.summary());
+        if let Err(e) = record_transaction(operation, &result, &ctx.initiator, &changes, &stats) {
+            warning!("Couldn't record the transaction in {TRANSACTION_HISTORY_PATH}: {e}");
+        }
+
+        if ctx.config.audit_syslog {
+            if let Err(e) = send_audit_event(operation, result.is_ok(), &ctx.initiator, &changes) {
+                warning!("Couldn't send the audit event to syslog: {e}");
+            }
+        }
+    }
+
+    if !ctx.config.notify.is_empty() && (result.is_err() || !changes.is_empty()) {
+        let status = if result.is_ok() { "success" } else { "failure" };
+        let error = result.as_ref().err().map(|e| format!("{:?}", e.error_type));
+        let summary = to_json_object(&[
+            ("operation", operation.as_str().to_json()),
+            ("status", status.to_json()),
+            ("error", error.to_json()),
+            ("hostname", hostname().to_json()),
+            ("changes", changes.to_json()),
+            ("stats", stats.to_json()),
+        ]);
+
+        for target in &ctx.config.notify {
+            if let Err(e) = notify(ctx, target, operation, status, &summary) {
+                warning!("Couldn't notify {}: {e}", target_name(target));
+            }
+        }
+    }
+
+    result.map(|_| changes)
+}
+
+fn hostname() -> Option<String> {
+    fs::read_to_string("/proc/sys/kernel/hostname")
+        .ok()
+        .map(|t| t.trim().to_owned())
+}
+
+fn target_name(target: &NotifyTarget) -> String {
+    match target {
+        NotifyTarget::Exec(command) => format!("'{command}'"),
+        NotifyTarget::Webhook(url) => url.clone(),
+    }
+}
+
+fn notify(
+    ctx: &Ctx,
+    target: &NotifyTarget,
+    operation: Operation,
+    status: &str,
+    summary: &str,
+) -> io::Result<()> {
+    debug!("Notifying {}..", target_name(target));
+
+    match target {
+        NotifyTarget::Exec(command) => {
+            let mut child = Command::new("/bin/sh")
+                .arg("-c")
+                .arg(command)
+                .env("LPM_OPERATION", operation.as_str())
+                .env("LPM_STATUS", status)
+                .stdin(Stdio::piped())
+                .spawn()?;
+
+            // The command may exit without reading all of it.
+            if let Some(mut stdin) = child.stdin.take() {
+                let _ = stdin.write_all(summary.as_bytes());
+            }
+
+            let exit_status = child.wait()?;
+            if !exit_status.success() {
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    format!("the command exited with {exit_status}"),
+                ));
+            }
+        }
+        NotifyTarget::Webhook(url) => {
+            if ctx.offline {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    "network access is forbidden by '--offline'",
+                ));
+            }
+
+            let response = post_json(url, summary, &DownloadOptions::from_config(&ctx.config))?;
+            if response.status_code >= 400 {
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    format!("the server responded with {}", response.status_code),
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
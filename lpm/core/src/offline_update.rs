@@ -0,0 +1,394 @@
+//! Offline updates, downloaded and verified by `lpm --update --stage` but
+//! applied early on the next boot, before the services that might be using
+//! the replaced libraries are started.
+//!
+//! This follows the offline update protocol of systemd: `/system-update`
+//! links to `STAGED_UPDATES_DIR`, which makes the next boot go into
+//! `system-update.target`. There `lpm-offline-update.service` runs
+//! `lpm --update --apply-staged`, then reboots into the updated system.
+
+use crate::{
+    auto_update::SYSTEMD_UNIT_DIR,
+    backup::Backups,
+    in_transaction,
+    plan::{confirm_plan, Plan, PlanEntry},
+    protect::ProtectedPaths,
+    repository::{ensure_available_offline, ensure_fresh_metadata, index_origin},
+    rollback::FsJournal,
+    stage1::check_interpreters,
+    update::{find_available_updates, FileUpdateMode, PkgUpdateTasks},
+    validate::verify_archive,
+    Ctx, PkgExtractTasks,
+};
+
+use common::{
+    download_files,
+    pkg::{PkgDataFromDb, PkgDataFromFs, PkgOrigin},
+};
+use db::{enable_core_db_wal1, pkg::DbOpsForInstalledPkg, PkgIndex};
+use ehandle::{lpm::LpmError, pkg::PackageErrorKind, ErrorCommons, MainError};
+use hash::sha256;
+use json::{to_json_object, Json, JsonValue, Serialize};
+use logger::{info, success, warning};
+use std::{
+    fs, io,
+    os::unix::fs::symlink,
+    path::{Path, PathBuf},
+};
+
+pub const STAGED_UPDATES_DIR: &str = "/var/lib/lpm/staged";
+const MANIFEST_FILE: &str = "manifest.json";
+pub(crate) const SYSTEM_UPDATE_LINK: &str = "/system-update";
+const UNIT_NAME: &str = "lpm-offline-update.service";
+
+/// An update waiting in `STAGED_UPDATES_DIR` for the next boot.
+#[derive(Debug, Clone, PartialEq)]
+struct StagedUpdate {
+    name: String,
+    /// Version installed at the time of staging, the update is dropped if
+    /// it's no longer the installed one.
+    from_version: String,
+    to_version: String,
+    file_name: String,
+    /// sha256 of the archive, checked again before it's applied.
+    checksum: String,
+    /// Missing from the manifests staged by older versions.
+    origin: Option<PkgOrigin>,
+}
+
+impl Serialize for StagedUpdate {
+    fn to_json(&self) -> String {
+        to_json_object(&[
+            ("name", self.name.to_json()),
+            ("from_version", self.from_version.to_json()),
+            ("to_version", self.to_version.to_json()),
+            ("file_name", self.file_name.to_json()),
+            ("checksum", self.checksum.to_json()),
+            (
+                "repository",
+                self.origin.as_ref().map(|t| &t.repository).to_json(),
+            ),
+            (
+                "index_timestamp",
+                self.origin.as_ref().map(|t| t.index_timestamp).to_json(),
+            ),
+            (
+                "signing_key",
+                self.origin
+                    .as_ref()
+                    .and_then(|t| t.signing_key.as_ref())
+                    .to_json(),
+            ),
+        ])
+    }
+}
+
+fn parse_manifest(content: &str) -> Result<Vec<StagedUpdate>, String> {
+    let manifest = Json::new(content).parse()?;
+    let JsonValue::Array(items) = &manifest["updates"] else {
+        return Err(String::from("'updates' is missing."));
+    };
+
+    items
+        .iter()
+        .map(|item| {
+            let field = |name: &str| {
+                item[name]
+                    .to_string()
+                    .ok_or_else(|| format!("'{name}' is missing from a staged update."))
+            };
+
+            let update = StagedUpdate {
+                name: field("name")?,
+                from_version: field("from_version")?,
+                to_version: field("to_version")?,
+                file_name: field("file_name")?,
+                checksum: field("checksum")?,
+                origin: item["repository"].to_string().map(|repository| PkgOrigin {
+                    repository,
+                    index_timestamp: item["index_timestamp"].as_u32().unwrap_or(0),
+                    signing_key: item["signing_key"].to_string(),
+                }),
+            };
+
+            // The archives can only come from the staging directory.
+            if update.file_name.contains('/') {
+                return Err(format!("Invalid file name '{}'.", update.file_name));
+            }
+
+            Ok(update)
+        })
+        .collect()
+}
+
+fn file_checksum(path: &Path) -> io::Result<String> {
+    Ok(hash::digest_to_hex_string(&sha256::digest(&fs::read(
+        path,
+    )?)))
+}
+
+fn unit(lpm_path: &Path) -> String {
+    format!(
+        "[Unit]
+Description=Apply the updates staged by lpm
+DefaultDependencies=no
+Requires=sysinit.target
+After=sysinit.target system-update-pre.target
+Before=system-update.target
+ConditionPathExists={STAGED_UPDATES_DIR}/{MANIFEST_FILE}
+
+[Service]
+Type=oneshot
+ExecStart={} --update --apply-staged
+ExecStartPost=/usr/bin/systemctl reboot
+FailureAction=reboot
+",
+        lpm_path.display()
+    )
+}
+
+/// Same as `systemctl enable`, which may not work when staging from an
+/// environment without a running systemd.
+fn install_unit() -> io::Result<()> {
+    let unit_dir = Path::new(SYSTEMD_UNIT_DIR);
+    let unit_path = unit_dir.join(UNIT_NAME);
+    fs::create_dir_all(unit_dir)?;
+    fs::write(&unit_path, unit(&std::env::current_exe()?))?;
+
+    let wants_dir = unit_dir.join("system-update.target.wants");
+    fs::create_dir_all(&wants_dir)?;
+    let wants_link = wants_dir.join(UNIT_NAME);
+    if fs::symlink_metadata(&wants_link).is_err() {
+        symlink(&unit_path, wants_link)?;
+    }
+
+    Ok(())
+}
+
+/// Removes `/system-update` if it's the link to the staged updates, and
+/// leaves it alone if another tool owns it.
+fn remove_system_update_link() -> io::Result<()> {
+    match fs::read_link(SYSTEM_UPDATE_LINK) {
+        Ok(target) if target == Path::new(STAGED_UPDATES_DIR) => {
+            fs::remove_file(SYSTEM_UPDATE_LINK)
+        }
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Downloads and verifies the available updates into `STAGED_UPDATES_DIR`
+/// and schedules them for the next boot, replacing the ones staged before.
+pub fn stage_updates(ctx: &Ctx) -> Result<(), LpmError<MainError>> {
+    let (old_pkgs, new_indexes) = find_available_updates(ctx)?;
+    ensure_fresh_metadata(ctx, &new_indexes)?;
+    if old_pkgs.is_empty() {
+        info!("All packages are already up to date.");
+        return Ok(());
+    }
+
+    let plan = Plan {
+        operation: "update",
+        requested: Vec::new(),
+        entries: old_pkgs
+            .iter()
+            .zip(&new_indexes)
+            .map(|(old_pkg, index)| PlanEntry::from_index(index, Some(&old_pkg.meta_fields.meta)))
+            .collect(),
+    };
+    if !confirm_plan(ctx, "Package list to be updated on the next boot:", &plan)? {
+        return Ok(());
+    }
+
+    if ctx.offline {
+        let indexes: Vec<&PkgIndex> = new_indexes.iter().collect();
+        ensure_available_offline(&indexes, super::EXTRACTION_OUTPUT_PATH)?;
+    }
+
+    let staged_dir = Path::new(STAGED_UPDATES_DIR);
+    if staged_dir.exists() {
+        warning!("Replacing the updates staged before.");
+        remove_system_update_link()?;
+        fs::remove_dir_all(staged_dir)?;
+    }
+    fs::create_dir_all(staged_dir)?;
+
+    let mut downloads = Vec::new();
+    for index in &new_indexes {
+        // Archives left from an earlier update don't need to be downloaded again.
+        let cached_path = index.pkg_output_path(super::EXTRACTION_OUTPUT_PATH);
+        let staged_path = index.pkg_output_path(STAGED_UPDATES_DIR);
+        if cached_path.exists() {
+            fs::copy(&cached_path, &staged_path)?;
+        }
+
+        let options = db::get_repository_options(&ctx.core_db, &index.repository_name)?;
+        downloads.push((
+            index.pkg_urls(&options.mirrors),
+            staged_path,
+            options.download_options(&ctx.config)?,
+        ));
+    }
+    download_files(&downloads, ctx.events.as_ref())?;
+
+    let mut updates = Vec::new();
+    for (old_pkg, index) in old_pkgs.iter().zip(&new_indexes) {
+        let staged_path = index.pkg_output_path(STAGED_UPDATES_DIR);
+        verify_archive(index, &staged_path)?;
+
+        updates.push(StagedUpdate {
+            name: index.name.clone(),
+            from_version: old_pkg.meta_fields.meta.version.readable_format.clone(),
+            to_version: index.version.readable_format.clone(),
+            file_name: index.pkg_filename(),
+            checksum: file_checksum(&staged_path)?,
+            origin: Some(index_origin(index)?),
+        });
+    }
+
+    // The manifest goes last, nothing is applied without it.
+    let manifest_path = staged_dir.join(MANIFEST_FILE);
+    let tmp_path = manifest_path.with_extension("json.tmp");
+    fs::write(&tmp_path, to_json_object(&[("updates", updates.to_json())]))?;
+    fs::rename(tmp_path, manifest_path)?;
+
+    install_unit()?;
+    symlink(STAGED_UPDATES_DIR, SYSTEM_UPDATE_LINK)?;
+
+    success!(
+        "{} updates are staged, they will be applied on the next boot.",
+        updates.len()
+    );
+
+    Ok(())
+}
+
+/// Applies the updates of `STAGED_UPDATES_DIR` in a single transaction, run
+/// by `lpm-offline-update.service` on boot.
+pub fn apply_staged_updates(ctx: &Ctx) -> Result<(), LpmError<MainError>> {
+    // Removed first, so a failing update can't keep the system from booting
+    // normally.
+    remove_system_update_link()?;
+
+    let staged_dir = PathBuf::from(STAGED_UPDATES_DIR);
+    let manifest = match fs::read_to_string(staged_dir.join(MANIFEST_FILE)) {
+        Ok(manifest) => manifest,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            info!("No update is staged.");
+            return Ok(());
+        }
+        Err(e) => return Err(e)?,
+    };
+    let updates =
+        parse_manifest(&manifest).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    enable_core_db_wal1(&ctx.core_db)?;
+
+    let mut extracted = Vec::new();
+    for update in updates {
+        let old_pkg = PkgDataFromDb::load(&ctx.core_db, &update.name)?;
+        let installed_version = &old_pkg.meta_fields.meta.version.readable_format;
+        if *installed_version != update.from_version {
+            warning!(
+                "Skipping '{}', {installed_version} is installed since {} was staged.",
+                update.name,
+                update.from_version
+            );
+            continue;
+        }
+
+        let pkg_path = staged_dir.join(&update.file_name);
+        let checksum = file_checksum(&pkg_path)?;
+        if checksum != update.checksum {
+            return Err(PackageErrorKind::ArchiveVerificationFailed {
+                package: format!("{}@{}", update.name, update.to_version),
+                reason: format!(
+                    "Expected {} checksum, but the staged archive has {checksum}.",
+                    update.checksum
+                ),
+            }
+            .to_lpm_err())?;
+        }
+
+        let mut requested_pkg = PkgDataFromFs::start_extract_task(
+            &pkg_path,
+            &ctx.config.extraction_limits,
+            ctx.events.as_ref(),
+        )?;
+        requested_pkg.origin = update.origin;
+        check_interpreters(
+            &requested_pkg.meta_dir.meta.name,
+            &requested_pkg.scripts,
+            &ctx.config.allowed_script_interpreters,
+        )?;
+        extracted.push((old_pkg, requested_pkg));
+    }
+
+    let protected = ProtectedPaths::new(&ctx.config.protected_paths)?;
+    let store = ctx.content_store();
+    let mut journal = FsJournal::new(Backups::new(ctx.config.keep_backups));
+    let result = in_transaction(&ctx.core_db, || {
+        for (old_pkg, requested_pkg) in &mut extracted {
+            info!(
+                "Package update started for {}",
+                old_pkg.meta_fields.meta.name
+            );
+            old_pkg.start_update_task(
+                &ctx.core_db,
+                requested_pkg,
+                &protected,
+                FileUpdateMode {
+                    store: store.as_ref(),
+                    paranoid: ctx.paranoid,
+                    no_extract: &ctx.config.no_extract,
+                },
+                &mut journal,
+                ctx.events.as_ref(),
+            )?;
+        }
+
+        Ok(())
+    });
+    journal.finish(result.is_ok());
+    result?;
+    info!("Update transaction completed.");
+
+    fs::remove_dir_all(staged_dir)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manifest() {
+        let updates = vec![StagedUpdate {
+            name: String::from("openssl"),
+            from_version: String::from("3.1.0"),
+            to_version: String::from("3.1.1"),
+            file_name: String::from("openssl-3.1.1.lod"),
+            checksum: String::from("ab12"),
+            origin: Some(PkgOrigin {
+                repository: String::from("core"),
+                index_timestamp: 1700000000,
+                signing_key: Some(String::from("core-2024")),
+            }),
+        }];
+
+        let manifest = to_json_object(&[("updates", updates.to_json())]);
+        assert_eq!(parse_manifest(&manifest).unwrap(), updates);
+
+        // Staged by a version that didn't record the origins.
+        let manifest = r#"{"updates": [{"name": "openssl", "from_version": "3.1.0", "to_version": "3.1.1", "file_name": "openssl-3.1.1.lod", "checksum": "ab12"}]}"#;
+        assert_eq!(parse_manifest(manifest).unwrap()[0].origin, None);
+
+        assert!(parse_manifest("{}").is_err());
+        assert!(parse_manifest(
+            r#"{"updates": [{"name": "openssl", "from_version": "3.1.0", "to_version": "3.1.1", "file_name": "../openssl.lod", "checksum": "ab12"}]}"#
+        )
+        .is_err());
+    }
+}
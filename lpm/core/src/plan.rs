@@ -0,0 +1,390 @@
+use crate::{
+    install_package, notify::run_transaction, update_pkg_from_repository,
+    update_pkgs_from_repository, Ctx, Operation,
+};
+
+use cli_parser::InstallArgs;
+
+use common::{
+    ctx_confirmation_check, interrupt,
+    meta::Meta,
+    size::{format_byte_size, format_byte_size_change},
+};
+use db::PkgIndex;
+use ehandle::{lpm::LpmError, pkg::PackageErrorKind, ErrorCommons, MainError};
+use json::{to_json_object, Json, JsonValue, Serialize};
+use std::{cmp::Ordering, fs, io};
+
+/// How `--print-plan` shows the transaction instead of applying it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlanFormat {
+    Text,
+    Json,
+}
+
+impl PlanFormat {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "text" => Some(Self::Text),
+            "json" => Some(Self::Json),
+            _ => None,
+        }
+    }
+}
+
+/// A single package change of a transaction, shown to the user before the
+/// confirmation prompt.
+pub(crate) struct PlanEntry {
+    name: String,
+    /// `install`, `update` or `downgrade`.
+    action: &'static str,
+    current_version: Option<String>,
+    new_version: String,
+    /// `None` for packages installed from the filesystem.
+    repository: Option<String>,
+    url: Option<String>,
+    /// `None` if unknown, or if there is nothing to download.
+    download_size: Option<i64>,
+    /// Net change of the installed size, `None` if unknown.
+    size_change: Option<i64>,
+}
+
+impl PlanEntry {
+    pub(crate) fn from_index(index: &PkgIndex, current: Option<&Meta>) -> Self {
+        Self {
+            name: index.name.clone(),
+            action: action(current, |meta| meta.version.compare(&index.version)),
+            current_version: current.map(|meta| meta.version.readable_format.clone()),
+            new_version: index.version.readable_format.clone(),
+            repository: Some(index.repository_name.clone()),
+            url: Some(index.pkg_url()),
+            download_size: index.archive_size,
+            size_change: index
+                .installed_size
+                .map(|size| size - current.map(|meta| meta.installed_size).unwrap_or(0)),
+        }
+    }
+
+    /// For packages installed from the filesystem, nothing gets downloaded.
+    pub(crate) fn from_meta(meta: &Meta, current: Option<&Meta>) -> Self {
+        Self {
+            name: meta.name.clone(),
+            action: action(current, |current| current.version.compare(&meta.version)),
+            current_version: current.map(|meta| meta.version.readable_format.clone()),
+            new_version: meta.version.readable_format.clone(),
+            repository: None,
+            url: None,
+            download_size: None,
+            size_change: Some(
+                meta.installed_size - current.map(|meta| meta.installed_size).unwrap_or(0),
+            ),
+        }
+    }
+}
+
+fn action(current: Option<&Meta>, compare: impl Fn(&Meta) -> Ordering) -> &'static str {
+    match current {
+        None => "install",
+        Some(meta) if compare(meta) == Ordering::Greater => "downgrade",
+        Some(_) => "update",
+    }
+}
+
+impl Serialize for PlanEntry {
+    fn to_json(&self) -> String {
+        to_json_object(&[
+            ("name", self.name.to_json()),
+            ("action", self.action.to_json()),
+            ("current_version", self.current_version.to_json()),
+            ("version", self.new_version.to_json()),
+            ("repository", self.repository.to_json()),
+            ("url", self.url.to_json()),
+            ("download_size", self.download_size.to_json()),
+            ("size_change", self.size_change.to_json()),
+        ])
+    }
+}
+
+/// A resolved transaction, in the order its actions are applied.
+pub(crate) struct Plan {
+    /// `install`, `update`, `install_local` or `update_local`.
+    pub(crate) operation: &'static str,
+    /// Package names (or the archive path of local operations) as given by
+    /// the user, empty when updating all of the packages.
+    pub(crate) requested: Vec<String>,
+    pub(crate) entries: Vec<PlanEntry>,
+}
+
+impl Serialize for Plan {
+    fn to_json(&self) -> String {
+        to_json_object(&[
+            ("operation", self.operation.to_json()),
+            ("packages", self.requested.to_json()),
+            ("actions", self.entries.to_json()),
+        ])
+    }
+}
+
+/// Shows `plan` the way `ctx` asks for, returns whether the transaction
+/// should be applied.
+///
+/// Once a plan is approved through `--apply-plan`, the transaction only gets
+/// applied if it still resolves to the same actions.
+pub(crate) fn confirm_plan(
+    ctx: &Ctx,
+    title: &str,
+    plan: &Plan,
+) -> Result<bool, LpmError<MainError>> {
+    if let Some((plan_path, approved)) = &ctx.approved_plan {
+        let resolved = parse_plan(&plan.to_json())?;
+        if resolved["actions"] != approved["actions"] {
+            return Err(PackageErrorKind::PlanChanged(plan_path.clone()).to_lpm_err())?;
+        }
+
+        interrupt::install_handlers();
+        return Ok(true);
+    }
+
+    match ctx.plan_format {
+        Some(PlanFormat::Json) => {
+            println!("{}", plan.to_json());
+            Ok(false)
+        }
+        Some(PlanFormat::Text) => {
+            println!("\n{title}\n{}", format_plan(&plan.entries));
+            Ok(false)
+        }
+        None => {
+            println!("\n{title}\n{}", format_plan(&plan.entries));
+            ctx_confirmation_check!(ctx);
+            // Signals only abort the transaction from here, the prompt
+            // itself is left with Ctrl-C as usual.
+            interrupt::install_handlers();
+            Ok(true)
+        }
+    }
+}
+
+pub(crate) fn parse_plan(content: &str) -> Result<JsonValue, LpmError<MainError>> {
+    let plan = Json::new(content)
+        .parse()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    if !plan.is_object() || !plan["actions"].is_array() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Transaction plan is missing its actions.",
+        ))?;
+    }
+
+    Ok(plan)
+}
+
+/// Applies the transaction plan at `path`, as printed by `--print-plan json`.
+pub fn apply_plan(ctx: &mut Ctx, path: &str) -> Result<(), LpmError<MainError>> {
+    let plan = parse_plan(&fs::read_to_string(path)?)?;
+    let operation = plan["operation"].to_string().unwrap_or_default();
+    let packages: Vec<String> = match &plan["packages"] {
+        JsonValue::Array(items) => items.iter().filter_map(JsonValue::to_string).collect(),
+        _ => Vec::new(),
+    };
+    ctx.approved_plan = Some((path.to_owned(), plan));
+
+    let changes = match operation.as_str() {
+        "install" => run_transaction(ctx, Operation::Install, |ctx| {
+            install_package(
+                ctx,
+                &InstallArgs {
+                    packages: packages.iter().map(String::as_str).collect(),
+                    ..Default::default()
+                },
+            )
+        }),
+        "update" if packages.is_empty() => run_transaction(ctx, Operation::Update, |ctx| {
+            update_pkgs_from_repository(ctx)
+        }),
+        "update" => run_transaction(ctx, Operation::Update, |ctx| {
+            for pkg_name in &packages {
+                update_pkg_from_repository(ctx, pkg_name)?;
+            }
+
+            Ok(())
+        }),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "'{operation}' plans can't be applied, only 'install' and 'update' are supported."
+            ),
+        ))?,
+    };
+
+    changes.map(|_| ())
+}
+
+pub(crate) fn format_plan(entries: &[PlanEntry]) -> String {
+    let rows: Vec<[String; 4]> = entries
+        .iter()
+        .map(|entry| {
+            let version = match &entry.current_version {
+                Some(current_version) => format!("{current_version} -> {}", entry.new_version),
+                None => entry.new_version.clone(),
+            };
+
+            let download_size = entry
+                .download_size
+                .map(|size| format_byte_size(size.max(0) as u64))
+                .unwrap_or_else(|| String::from("-"));
+
+            let size_change = entry
+                .size_change
+                .map(format_byte_size_change)
+                .unwrap_or_else(|| String::from("?"));
+
+            [entry.name.clone(), version, download_size, size_change]
+        })
+        .collect();
+
+    let total_download: i64 = entries.iter().filter_map(|t| t.download_size).sum();
+    let total_change: i64 = entries.iter().filter_map(|t| t.size_change).sum();
+
+    let header = [
+        String::from("Package"),
+        String::from("Version"),
+        String::from("Download"),
+        String::from("Size change"),
+    ];
+    let total = [
+        String::from("Total"),
+        String::new(),
+        format_byte_size(total_download.max(0) as u64),
+        format_byte_size_change(total_change),
+    ];
+
+    let mut widths = [0; 4];
+    for row in std::iter::once(&header).chain(&rows).chain([&total]) {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    let format_row = |row: &[String; 4]| {
+        format!(
+            "  {:<w0$}  {:<w1$}  {:>w2$}  {:>w3$}\n",
+            row[0],
+            row[1],
+            row[2],
+            row[3],
+            w0 = widths[0],
+            w1 = widths[1],
+            w2 = widths[2],
+            w3 = widths[3],
+        )
+    };
+
+    let mut output = format_row(&header);
+    rows.iter()
+        .for_each(|row| output.push_str(&format_row(row)));
+    output.push_str(&format_row(&total));
+
+    if entries.iter().any(|t| t.size_change.is_none()) {
+        output
+            .push_str("\n  Installed size of some packages is unknown, the total is incomplete.\n");
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_plan() {
+        let entries = [
+            PlanEntry {
+                name: String::from("htop"),
+                action: "update",
+                current_version: Some(String::from("3.2.1")),
+                new_version: String::from("3.2.2"),
+                repository: Some(String::from("main")),
+                url: Some(String::from("https://example.com/htop-3.2.2.lod")),
+                download_size: Some(150 * 1024),
+                size_change: Some(2048),
+            },
+            PlanEntry {
+                name: String::from("ncurses"),
+                action: "install",
+                current_version: None,
+                new_version: String::from("6.4.0"),
+                repository: Some(String::from("main")),
+                url: Some(String::from("https://example.com/ncurses-6.4.0.lod")),
+                download_size: Some(1024 * 1024),
+                size_change: Some(3 * 1024 * 1024),
+            },
+        ];
+
+        let output = format_plan(&entries);
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("  Package  Version"));
+        assert!(lines[1].contains("3.2.1 -> 3.2.2"));
+        assert!(lines[1].ends_with("+2.0 KiB"));
+        assert!(lines[2].contains("1.0 MiB"));
+        assert!(lines[3].starts_with("  Total"));
+        assert!(lines[3].ends_with("+3.0 MiB"));
+    }
+
+    #[test]
+    fn test_format_plan_with_unknown_sizes() {
+        let entries = [PlanEntry {
+            name: String::from("htop"),
+            action: "install",
+            current_version: None,
+            new_version: String::from("3.2.2"),
+            repository: None,
+            url: None,
+            download_size: None,
+            size_change: None,
+        }];
+
+        let output = format_plan(&entries);
+        assert!(output.lines().nth(1).unwrap().ends_with('?'));
+        assert!(output.contains("the total is incomplete"));
+    }
+
+    #[test]
+    fn test_plan_to_json() {
+        let plan = Plan {
+            operation: "install",
+            requested: vec![String::from("htop")],
+            entries: vec![PlanEntry {
+                name: String::from("htop"),
+                action: "install",
+                current_version: None,
+                new_version: String::from("3.2.2"),
+                repository: Some(String::from("main")),
+                url: Some(String::from("https://example.com/htop-3.2.2.lod")),
+                download_size: Some(1024),
+                size_change: None,
+            }],
+        };
+
+        let parsed = parse_plan(&plan.to_json()).unwrap();
+        assert_eq!(parsed["operation"].to_string().as_deref(), Some("install"));
+        assert_eq!(parsed["packages"][0].to_string().as_deref(), Some("htop"));
+
+        let action = &parsed["actions"][0];
+        assert_eq!(action["action"].to_string().as_deref(), Some("install"));
+        assert_eq!(action["version"].to_string().as_deref(), Some("3.2.2"));
+        assert_eq!(
+            action["url"].to_string().as_deref(),
+            Some("https://example.com/htop-3.2.2.lod")
+        );
+        assert_eq!(action["download_size"].as_i64(), Some(1024));
+        assert!(action["current_version"].is_null());
+        assert!(action["size_change"].is_null());
+
+        assert!(parse_plan("{}").is_err());
+        assert!(parse_plan("not json").is_err());
+    }
+}
@@ -0,0 +1,96 @@
+use common::pkg_path::PkgPath;
+use ehandle::{lpm::LpmError, pkg::PackageErrorKind, ErrorCommons, MainError};
+use std::io;
+
+/// Path prefixes from the `protected_paths` config, packages are never
+/// allowed to modify or remove anything under them.
+#[derive(Debug, Default)]
+pub(crate) struct ProtectedPaths {
+    prefixes: Vec<PkgPath>,
+}
+
+impl ProtectedPaths {
+    pub(crate) fn new(prefixes: &[String]) -> io::Result<Self> {
+        let mut paths = Self::default();
+        for prefix in prefixes {
+            let prefix = PkgPath::new(prefix)?;
+            if !prefix.as_relative().is_empty() {
+                paths.prefixes.push(prefix);
+            }
+        }
+
+        Ok(paths)
+    }
+
+    /// `/boot/efi` protects `/boot/efi` itself and everything below it, but
+    /// not `/boot/efivars`. Paths with `..` could be anywhere, so they count
+    /// as protected as long as anything is.
+    pub(crate) fn is_protected(&self, path: &str) -> bool {
+        match PkgPath::new(path) {
+            Ok(path) => self.prefixes.iter().any(|prefix| path.starts_with(prefix)),
+            Err(_) => !self.prefixes.is_empty(),
+        }
+    }
+
+    /// Fails with all the protected paths that `package` would touch.
+    pub(crate) fn check<'a>(
+        &self,
+        package: &str,
+        paths: impl IntoIterator<Item = &'a str>,
+    ) -> Result<(), LpmError<MainError>> {
+        if self.prefixes.is_empty() {
+            return Ok(());
+        }
+
+        let violations: Vec<String> = paths
+            .into_iter()
+            .filter(|path| self.is_protected(path))
+            .map(|path| PkgPath::new(path).map_or_else(|_| path.to_owned(), |t| t.to_absolute()))
+            .collect();
+
+        if violations.is_empty() {
+            return Ok(());
+        }
+
+        Err(PackageErrorKind::ProtectedPaths {
+            package: package.to_owned(),
+            paths: violations,
+        }
+        .to_lpm_err())?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_protected() {
+        let protected =
+            ProtectedPaths::new(&[String::from("/boot/efi/"), String::from("etc/fstab")]).unwrap();
+
+        assert!(protected.is_protected("/boot/efi"));
+        assert!(protected.is_protected("boot/efi/EFI/BOOT/BOOTX64.EFI"));
+        assert!(protected.is_protected("/etc/fstab"));
+        assert!(!protected.is_protected("/boot/efivars"));
+        assert!(!protected.is_protected("/etc/fstab.d/extra"));
+        assert!(!protected.is_protected("/usr/bin/htop"));
+
+        assert!(!ProtectedPaths::new(&[String::from("/")])
+            .unwrap()
+            .is_protected("/usr/bin/htop"));
+    }
+
+    #[test]
+    fn test_check() {
+        let protected = ProtectedPaths::new(&[String::from("/etc/fstab")]).unwrap();
+
+        assert!(protected.check("htop", ["usr/bin/htop"]).is_ok());
+        assert!(protected
+            .check("htop", ["usr/bin/htop", "etc/fstab"])
+            .is_err());
+        assert!(protected.check("htop", ["usr/../etc/passwd"]).is_err());
+
+        assert!(ProtectedPaths::new(&[String::from("/boot/../etc")]).is_err());
+    }
+}
@@ -0,0 +1,147 @@
+use crate::{
+    in_transaction,
+    store::ContentStore,
+    validate::{check_algorithm, digest},
+    Ctx,
+};
+
+use common::{meta::FileStruct, pkg::PkgDataFromDb};
+use db::pkg::DbOpsForInstalledPkg;
+use ehandle::{lpm::LpmError, MainError};
+use logger::{info, success, warning};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Re-hashes the installed files with `algorithm` and records the new
+/// checksums in one transaction, so that the system moves off an algorithm
+/// without reinstalling its packages.
+///
+/// Only the files that still have the content their packages installed are
+/// re-hashed, the others keep their checksums so that they keep showing up
+/// as changed.
+pub fn rehash_installed_files(ctx: Ctx, algorithm: &str) -> Result<(), LpmError<MainError>> {
+    check_algorithm(algorithm)?;
+    let algorithm = algorithm.to_lowercase();
+    let root = ctx.root_path();
+
+    info!("Re-hashing the installed files with {algorithm}..");
+    let mut rehashed = Vec::new();
+    let mut skipped = 0;
+    for pkg in PkgDataFromDb::load_all_packages(&ctx.core_db)? {
+        for file in &pkg.meta_fields.files.0 {
+            if file.checksum_algorithm.eq_ignore_ascii_case(&algorithm) {
+                continue;
+            }
+
+            let pkg_path = file.pkg_path()?;
+            match rehash(file, &pkg_path.under(root), &algorithm) {
+                Ok(Some(new_file)) => rehashed.push((file.clone(), new_file)),
+                Ok(None) => {}
+                Err(reason) => {
+                    warning!(
+                        "{pkg_path} keeps its {} checksum, {reason}.",
+                        file.checksum_algorithm
+                    );
+                    skipped += 1;
+                }
+            }
+        }
+    }
+
+    // The objects of the content store get their new names before the
+    // database refers to them, and lose the old ones after.
+    let store = ContentStore::new(root);
+    let mut old_objects: Vec<PathBuf> = Vec::new();
+    for (file, new_file) in &rehashed {
+        old_objects.extend(store.relink(file, new_file)?);
+    }
+
+    in_transaction(&ctx.core_db, || {
+        for (_, new_file) in &rehashed {
+            db::pkg::update_file_checksum(
+                &ctx.core_db,
+                &new_file.pkg_path()?.to_absolute(),
+                &new_file.checksum,
+                &new_file.checksum_algorithm,
+            )?;
+        }
+        Ok(())
+    })?;
+
+    for object in old_objects {
+        match fs::remove_file(&object) {
+            // Shared by several files.
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            result => result?,
+        }
+    }
+
+    success!(
+        "Re-hashed {} files with {algorithm}, skipped {skipped}.",
+        rehashed.len()
+    );
+    Ok(())
+}
+
+/// `file` with the `algorithm` checksum of its installed content at `path`,
+/// `None` for the files that aren't regular ones. Fails with the reason if
+/// the content isn't the one that was installed.
+fn rehash(file: &FileStruct, path: &Path, algorithm: &str) -> Result<Option<FileStruct>, String> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if !metadata.is_file() => return Ok(None),
+        Ok(_) => {}
+        Err(e) => return Err(format!("it can't be read: {e}")),
+    }
+
+    let content = fs::read(path).map_err(|e| format!("it can't be read: {e}"))?;
+    let is_intact = digest(&file.checksum_algorithm, &content)
+        .map_or(false, |t| t.eq_ignore_ascii_case(&file.checksum));
+    if !is_intact {
+        return Err(String::from("its content changed since it was installed"));
+    }
+
+    Ok(Some(FileStruct {
+        checksum_algorithm: algorithm.to_owned(),
+        checksum: digest(algorithm, &content).unwrap(),
+        ..file.clone()
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::testing::TestDir;
+
+    #[test]
+    fn test_rehash() {
+        let dir = TestDir::new("rehash");
+        let path = dir.join("htop");
+        fs::write(&path, b"htop").unwrap();
+
+        let file = FileStruct {
+            path: String::from("usr/bin/htop"),
+            checksum_algorithm: String::from("md5"),
+            checksum: digest("md5", b"htop").unwrap(),
+            size: None,
+            mode: None,
+            mtime: None,
+            owner: None,
+            group: None,
+            class: None,
+        };
+
+        let rehashed = rehash(&file, &path, "blake3").unwrap().unwrap();
+        assert_eq!(rehashed.checksum_algorithm, "blake3");
+        assert_eq!(rehashed.checksum, digest("blake3", b"htop").unwrap());
+        assert_eq!(rehashed.path, file.path);
+
+        fs::write(&path, b"changed").unwrap();
+        assert!(rehash(&file, &path, "blake3")
+            .unwrap_err()
+            .contains("content changed"));
+        assert!(rehash(&file, &dir.join("missing"), "blake3").is_err());
+        assert!(rehash(&file, &dir, "blake3").unwrap().is_none());
+    }
+}
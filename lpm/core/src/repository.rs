@@ -1,9 +1,16 @@
-use crate::Ctx;
+use crate::{load_keyring, Ctx, Keyring, TRUSTED_KEYS_DIR};
 
-use common::{ctx_confirmation_check, pkg::PkgToQuery};
+use common::{
+    arch, ctx_confirmation_check, fetch, local_address_path,
+    pkg::{PkgOrigin, PkgToQuery},
+    send_requests,
+    size::{format_byte_size, parse_byte_size},
+    DownloadOptions, Request,
+};
 use db::{
-    get_repositories, insert_repository, is_repository_exists, PkgIndex, REPOSITORY_INDEX_DB_DIR,
-    SQL_NO_CALLBACK_FN,
+    get_repositories, get_repository_options, insert_repository, is_repository_exists,
+    update_repository_options, PkgIndex, RepositoryOptions, SignatureLevel,
+    REPOSITORY_INDEX_DB_DIR, SQL_NO_CALLBACK_FN,
 };
 use ehandle::{
     lpm::LpmError,
@@ -12,16 +19,56 @@ use ehandle::{
 };
 use logger::{debug, info, warning};
 use min_sqlite3_sys::prelude::*;
-use rekuest::Rekuest;
-use std::{fs, path::Path};
+use rekuest::Proxy;
+use std::{
+    collections::BTreeSet,
+    fs, io,
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// First line of the signed index patches, see `verify_patch`.
+const PATCH_SIGNATURE_PREFIX: &str = "-- signature: ";
 
-pub fn add_repository(ctx: Ctx, name: &str, address: &str) -> Result<(), LpmError<MainError>> {
+/// Adds the repository at `address`, which must be reachable unless
+/// `skip_check` is set.
+pub fn add_repository(
+    ctx: Ctx,
+    name: &str,
+    address: &str,
+    skip_check: bool,
+) -> Result<(), LpmError<MainError>> {
     let repository_index_db_path = Path::new(REPOSITORY_INDEX_DB_DIR).join(name);
 
     if is_repository_exists(&ctx.core_db, name)? {
         return Err(RepositoryErrorKind::RepositoryAlreadyExists(name.to_owned()).to_lpm_err())?;
     }
 
+    let address = &normalize_repository_address(address)?;
+    for (existing_name, existing_address) in get_repositories(&ctx.core_db)? {
+        if normalize_repository_address(&existing_address)
+            .ok()
+            .as_ref()
+            == Some(address)
+        {
+            return Err(RepositoryErrorKind::DuplicateRepositoryAddress {
+                address: address.to_owned(),
+                repository: existing_name,
+            }
+            .to_lpm_err())?;
+        }
+    }
+
+    if !skip_check && !ctx.offline {
+        info!("Checking {name} repository at {address}..");
+        ensure_reachable(address, &DownloadOptions::from_config(&ctx.config))?;
+    }
+
     {
         // TODO
         // use colors
@@ -40,33 +87,101 @@ pub fn add_repository(ctx: Ctx, name: &str, address: &str) -> Result<(), LpmErro
         true,
     )?;
 
-    {
-        info!("Getting {name} indexes..");
-        let index_db = Database::open(&repository_index_db_path)?;
-
-        let index_db_file = fs::metadata(&repository_index_db_path)?;
-        let index_timestamp = if index_db_file.len() == 0 {
-            0
-        } else {
-            PkgIndex::latest_timestamp(&index_db)?
-        };
+    if ctx.offline {
+        warning!(
+            "Offline mode is enabled, {name} indexes will be fetched on the next index update."
+        );
+        return Ok(());
+    }
 
-        let req_url = format!("{address}/index-tracker/{index_timestamp}");
-        debug!("Sending request to '{req_url}'");
-        let r = Rekuest::new(&req_url)?.get()?;
-        let patch = String::from_utf8(r.body)
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))?;
-        debug!("Applying:\n\n {patch}");
+    info!("Getting {name} indexes..");
+    let options = RepositoryOptions::default();
+    let keyring = load_keyring(&ctx.core_db, Path::new(TRUSTED_KEYS_DIR))?;
+    let index_timestamp = get_index_timestamp(name)?;
+    let response = fetch(
+        &patch_url(name, address, index_timestamp),
+        &options.download_options(&ctx.config)?,
+    )?;
+    apply_repository_patch(
+        name,
+        index_timestamp,
+        &response.body,
+        None,
+        options.signature_level,
+        &keyring,
+    )?;
 
-        if !patch.is_empty() {
-            #[allow(clippy::disallowed_methods)]
-            index_db.execute(patch, SQL_NO_CALLBACK_FN)?;
-        }
+    Ok(())
+}
 
-        info!("{name} indexes successfully updated.");
+/// Brings `address` to the form it's stored in, so that the same repository
+/// is recognized however it's written. Schemes and hosts are lowercased, the
+/// default ports and trailing slashes are dropped, addresses without a scheme
+/// are http and absolute paths are `file://` addresses.
+fn normalize_repository_address(address: &str) -> Result<String, LpmError<RepositoryError>> {
+    let invalid = |reason: &str| {
+        RepositoryErrorKind::InvalidRepositoryAddress(address.to_owned(), reason.to_owned())
+            .to_lpm_err()
+    };
+    let trim_trailing_slashes = |path: &str| match path.trim_end_matches('/') {
+        "" => String::from("/"),
+        trimmed => trimmed.to_owned(),
+    };
+
+    let trimmed = address.trim();
+    if trimmed.starts_with('/') {
+        return Ok(format!("file://{}", trim_trailing_slashes(trimmed)));
     }
 
-    Ok(())
+    let (scheme, rest) = trimmed
+        .split_once("://")
+        .map_or((String::from("http"), trimmed), |(scheme, rest)| {
+            (scheme.to_ascii_lowercase(), rest)
+        });
+
+    match scheme.as_str() {
+        "http" | "https" => {
+            let default_port = if scheme == "http" { ":80" } else { ":443" };
+            let (host, path) = rest.split_once('/').unwrap_or((rest, ""));
+            let host = host.to_ascii_lowercase();
+            let host = host.strip_suffix(default_port).unwrap_or(&host);
+            if host.is_empty() || host.starts_with(':') {
+                return Err(invalid("the host is missing"));
+            }
+
+            match path.trim_end_matches('/') {
+                "" => Ok(format!("{scheme}://{host}")),
+                path => Ok(format!("{scheme}://{host}/{path}")),
+            }
+        }
+        "file" if rest.starts_with('/') => Ok(format!("file://{}", trim_trailing_slashes(rest))),
+        "file" => Err(invalid("local paths must be absolute")),
+        _ => Err(invalid(
+            "expected an 'http://', 'https://' or 'file://' address, or an absolute path",
+        )),
+    }
+}
+
+/// Makes sure that there is a repository at the normalized `address`.
+fn ensure_reachable(
+    address: &str,
+    options: &DownloadOptions,
+) -> Result<(), LpmError<RepositoryError>> {
+    let reason = match local_address_path(address) {
+        Some(path) if path.is_dir() => return Ok(()),
+        Some(path) => format!("'{}' is not a directory", path.display()),
+        None => match fetch(&format!("{address}/index-tracker/health"), options) {
+            Ok(response) if response.status_code < 400 => return Ok(()),
+            Ok(response) => format!("the server responded with {}", response.status_code),
+            Err(e) => e.to_string(),
+        },
+    };
+
+    Err(RepositoryErrorKind::UnreachableRepository {
+        address: address.to_owned(),
+        reason,
+    }
+    .to_lpm_err())
 }
 
 pub fn delete_repositories(
@@ -100,6 +215,127 @@ pub fn delete_repositories(
     Ok(())
 }
 
+/// Applies `key=value` options(e.g., `exclude=*-devel`) to the given repository.
+pub fn configure_repository(
+    ctx: Ctx,
+    name: &str,
+    args: &[String],
+) -> Result<(), LpmError<MainError>> {
+    if !is_repository_exists(&ctx.core_db, name)? {
+        return Err(RepositoryErrorKind::RepositoryNotFound(name.to_owned()).to_lpm_err())?;
+    }
+
+    if args.is_empty() {
+        panic!("At least 1 option must be provided.");
+    }
+
+    let mut options = get_repository_options(&ctx.core_db, name)?;
+    for arg in args {
+        apply_repository_option(&mut options, arg)?;
+    }
+    // Catches unusable certificates, or a certificate without its key, now
+    // instead of on the next download.
+    options.download_options(&ctx.config)?;
+
+    {
+        // TODO
+        // use colors
+        println!("\nRepository options to be applied on '{name}':");
+        args.iter().for_each(|arg| {
+            println!("  - {arg}");
+        });
+        println!();
+    }
+    ctx_confirmation_check!(ctx);
+
+    info!("Updating options of {name} repository..");
+    update_repository_options(&ctx.core_db, name, &options)?;
+
+    Ok(())
+}
+
+fn apply_repository_option(
+    options: &mut RepositoryOptions,
+    arg: &str,
+) -> Result<(), LpmError<RepositoryError>> {
+    let invalid_option =
+        || RepositoryErrorKind::InvalidRepositoryOption(arg.to_owned()).to_lpm_err();
+
+    let (key, value) = arg.split_once('=').ok_or_else(invalid_option)?;
+    let list: Vec<String> = value
+        .split(',')
+        .map(|t| t.trim().to_owned())
+        .filter(|t| !t.is_empty())
+        .collect();
+
+    match key {
+        "arch" => options.arch_filter = list.iter().map(|t| arch::normalize(t)).collect(),
+        "include" => options.include_pkgs = list,
+        "exclude" => options.exclude_pkgs = list,
+        "signature" => {
+            options.signature_level =
+                SignatureLevel::from_string_slice(value).ok_or_else(invalid_option)?
+        }
+        "bandwidth" => {
+            options.bandwidth_limit = if value.is_empty() {
+                None
+            } else {
+                Some(parse_byte_size(value).ok_or_else(invalid_option)?)
+            }
+        }
+        "mirrors" => {
+            options.mirrors = list
+                .iter()
+                .map(|t| normalize_repository_address(t).map_err(|_| invalid_option()))
+                .collect::<Result<_, _>>()?
+        }
+        "proxy" => {
+            options.proxy = if value.is_empty() {
+                None
+            } else {
+                Proxy::parse(value).map_err(|_| invalid_option())?;
+                Some(value.to_owned())
+            }
+        }
+        "ca" | "cert" | "key" => {
+            let path = if value.is_empty() {
+                None
+            } else if Path::new(value).is_file() {
+                Some(value.to_owned())
+            } else {
+                return Err(invalid_option());
+            };
+
+            match key {
+                "ca" => options.ca_bundle = path,
+                "cert" => options.client_cert = path,
+                _ => options.client_key = path,
+            }
+        }
+        _ => return Err(invalid_option()),
+    }
+
+    Ok(())
+}
+
+/// Returns false if the repository must be ignored on this system, logging the reason.
+pub(crate) fn is_repository_usable(name: &str, options: &RepositoryOptions) -> bool {
+    if !options.arch_filter.is_empty()
+        && !options
+            .arch_filter
+            .iter()
+            .any(|t| arch::is_supported_by_system(t))
+    {
+        debug!(
+            "Skipping {name} repository, it serves {:?} architectures only",
+            options.arch_filter
+        );
+        return false;
+    }
+
+    true
+}
+
 pub fn print_repositories(core_db: &Database) -> Result<(), LpmError<RepositoryError>> {
     info!("Getting repository list from the database..");
     let list = get_repositories(core_db)?;
@@ -114,14 +350,51 @@ pub fn print_repositories(core_db: &Database) -> Result<(), LpmError<RepositoryE
     println!("Registered repository list:");
     for item in list {
         println!("  {}: {}", item.0, item.1);
+
+        let options = get_repository_options(core_db, &item.0)?;
+        if !options.arch_filter.is_empty() {
+            println!("      arch = {}", options.arch_filter.join(","));
+        }
+        if !options.include_pkgs.is_empty() {
+            println!("      include = {}", options.include_pkgs.join(","));
+        }
+        if !options.exclude_pkgs.is_empty() {
+            println!("      exclude = {}", options.exclude_pkgs.join(","));
+        }
+        if options.signature_level != SignatureLevel::default() {
+            println!("      signature = {}", options.signature_level.as_str());
+        }
+        if let Some(bandwidth_limit) = options.bandwidth_limit {
+            println!("      bandwidth = {bandwidth_limit}");
+        }
+        if let Some(proxy) = &options.proxy {
+            println!("      proxy = {proxy}");
+        }
+        if !options.mirrors.is_empty() {
+            println!("      mirrors = {}", options.mirrors.join(","));
+        }
+        if let Some(ca_bundle) = &options.ca_bundle {
+            println!("      ca = {ca_bundle}");
+        }
+        if let Some(client_cert) = &options.client_cert {
+            println!("      cert = {client_cert}");
+        }
+        if let Some(client_key) = &options.client_key {
+            println!("      key = {client_key}");
+        }
     }
 
     Ok(())
 }
 
-pub fn get_and_apply_repository_patches(
-    core_db: &Database,
-) -> Result<(), LpmError<RepositoryError>> {
+pub fn get_and_apply_repository_patches(ctx: &Ctx) -> Result<(), LpmError<RepositoryError>> {
+    let core_db = &ctx.core_db;
+
+    if ctx.offline {
+        warning!("Offline mode is enabled, skipping repository index updates.");
+        return Ok(());
+    }
+
     info!("Getting repository list from the database..");
     let list = get_repositories(core_db)?;
 
@@ -130,43 +403,385 @@ pub fn get_and_apply_repository_patches(
         return Ok(());
     }
 
-    for (name, address) in &list {
-        let repository_index_db_path = Path::new(REPOSITORY_INDEX_DB_DIR).join(name);
-        let index_db = Database::open(Path::new(&repository_index_db_path))?;
+    let mut repositories = Vec::new();
+    for (name, address) in list {
+        let options = get_repository_options(core_db, &name)?;
+        if is_repository_usable(&name, &options) {
+            repositories.push((name, address, options));
+        }
+    }
+    let keyring = load_keyring(core_db, Path::new(TRUSTED_KEYS_DIR))?;
 
-        let index_db_file = fs::metadata(&repository_index_db_path)?;
-        let index_timestamp = if index_db_file.len() == 0 {
-            0
-        } else {
-            PkgIndex::latest_timestamp(&index_db)?
-        };
+    // The patches are fetched from this thread, as the requests may have to
+    // run in the process of the download user, which can't be started next
+    // to other threads. Only the number of connections to the servers is
+    // bounded.
+    let mut requests = Vec::with_capacity(repositories.len());
+    let mut index_timestamps = Vec::with_capacity(repositories.len());
+    for (name, address, options) in &repositories {
+        let index_timestamp = get_index_timestamp(name)?;
+        requests.push((
+            Request::Get(patch_url(name, address, index_timestamp)),
+            options.download_options(&ctx.config)?,
+        ));
+        index_timestamps.push(index_timestamp);
+    }
+    let mut responses = Vec::with_capacity(repositories.len());
+    for chunk in requests.chunks(ctx.config.parallel_index_updates.max(1)) {
+        responses.extend(send_requests(chunk)?);
+    }
+
+    // Each index has a database of its own.
+    let patches: Vec<_> = repositories
+        .iter()
+        .zip(index_timestamps)
+        .zip(responses)
+        .collect();
+    let results = map_parallel(
+        &patches,
+        ctx.config.parallel_index_updates,
+        |(((name, _, options), index_timestamp), response)| {
+            let response = response
+                .as_ref()
+                .map_err(|e| io::Error::new(e.kind(), e.to_string()))?;
+            apply_repository_patch(
+                name,
+                *index_timestamp,
+                &response.body,
+                options.synced_archs().as_deref(),
+                options.signature_level,
+                &keyring,
+            )
+        },
+    );
+
+    let mut first_error = None;
+    for ((name, ..), result) in repositories.iter().zip(results) {
+        if let Err(e) = result {
+            logger::error!("Index of '{name}' couldn't be updated: {:?}", e.error_type);
+            first_error.get_or_insert(e);
+        }
+    }
+
+    first_error.map_or(Ok(()), Err)
+}
+
+/// Sync timestamp of the index of `name`, 0 if it was never synced.
+fn get_index_timestamp(name: &str) -> Result<u32, LpmError<RepositoryError>> {
+    let repository_index_db_path = Path::new(REPOSITORY_INDEX_DB_DIR).join(name);
+    let index_db = Database::open(Path::new(&repository_index_db_path))?;
+
+    let index_db_file = fs::metadata(&repository_index_db_path)?;
+    if index_db_file.len() == 0 {
+        return Ok(0);
+    }
+
+    Ok(PkgIndex::latest_timestamp(&index_db)?)
+}
+
+/// Url of the patch that brings the index of `name` from `index_timestamp`
+/// to the latest version.
+fn patch_url(name: &str, address: &str, index_timestamp: u32) -> String {
+    let req_url = format!("{address}/index-tracker/{index_timestamp}");
+    debug!("Sending request to '{req_url}' for '{name}'");
+    req_url
+}
+
+/// Applies `patch`, fetched from `patch_url` with the same `index_timestamp`,
+/// to the index of `name`.
+fn apply_repository_patch(
+    name: &str,
+    index_timestamp: u32,
+    patch: &[u8],
+    synced_archs: Option<&[String]>,
+    signature_level: SignatureLevel,
+    keyring: &Keyring,
+) -> Result<(), LpmError<RepositoryError>> {
+    let repository_index_db_path = Path::new(REPOSITORY_INDEX_DB_DIR).join(name);
+    let index_db = Database::open(Path::new(&repository_index_db_path))?;
+
+    let patch = std::str::from_utf8(patch)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))?;
+    let (patch, signed_by) = verify_patch(name, patch, index_timestamp, signature_level, keyring)?;
+    debug!("Applying to '{name}':\n\n {patch}");
+
+    // The index stays signed only as long as every patch of it is.
+    let signing_key = match signed_by {
+        Some(key_id) if index_timestamp == 0 || PkgIndex::signing_key(&index_db)?.is_some() => {
+            Some(key_id)
+        }
+        _ => None,
+    };
+
+    // Read before the patch, which could drop the expiry date of the index.
+    let expiry_published = PkgIndex::has_published_expiry(&index_db)?
+        || PkgIndex::metadata_expires_at(&index_db)?.is_some();
+
+    if !patch.is_empty() {
+        #[allow(clippy::disallowed_methods)]
+        index_db.execute(patch.to_owned(), SQL_NO_CALLBACK_FN)?;
+    }
+    PkgIndex::set_signing_key(&index_db, signing_key.as_deref())?;
+    if expiry_published || PkgIndex::metadata_expires_at(&index_db)?.is_some() {
+        PkgIndex::set_expiry_published(&index_db)?;
+    }
+    upgrade_index_schema(name, &index_db)?;
+
+    if let Some(archs) = synced_archs {
+        PkgIndex::retain_archs(&index_db, archs)?;
+    }
+
+    info!("Index of '{name}' is successfully updated.");
+
+    Ok(())
+}
+
+/// Splits the signature line off `patch` and checks it as `signature_level`
+/// asks. Returns the SQL to apply and the id of the key that signed it.
+///
+/// Signed patches start with `PATCH_SIGNATURE_PREFIX`, followed by the
+/// signature of `index_timestamp`, a newline and the rest of the patch, so
+/// that a patch can't be replayed onto another version of the index.
+fn verify_patch<'a>(
+    name: &str,
+    patch: &'a str,
+    index_timestamp: u32,
+    signature_level: SignatureLevel,
+    keyring: &Keyring,
+) -> Result<(&'a str, Option<String>), LpmError<RepositoryError>> {
+    let invalid = |reason: String| {
+        RepositoryErrorKind::InvalidIndexSignature {
+            repository: name.to_owned(),
+            reason,
+        }
+        .to_lpm_err()
+    };
+
+    let (signature, sql) = match patch.strip_prefix(PATCH_SIGNATURE_PREFIX) {
+        Some(rest) => {
+            let (signature, sql) = rest.split_once('\n').unwrap_or((rest, ""));
+            (Some(signature), sql)
+        }
+        None => (None, patch),
+    };
+
+    match (signature, signature_level) {
+        (_, SignatureLevel::Never) => Ok((sql, None)),
+        (None, SignatureLevel::Required) => Err(invalid(String::from("the patch isn't signed"))),
+        (None, SignatureLevel::Optional) => Ok((sql, None)),
+        (Some(signature), _) => {
+            let message = format!("{index_timestamp}\n{sql}");
+            let key_id = keyring
+                .verify(signature, message.as_bytes())
+                .map_err(invalid)?;
+            debug!("Index patch of '{name}' is signed by '{key_id}'");
+
+            Ok((sql, Some(key_id)))
+        }
+    }
+}
+
+/// Whether packages can be resolved from the index of `name`, which they
+/// can't if the repository requires signatures but the index was synced
+/// without them.
+pub(crate) fn is_index_verified(
+    name: &str,
+    options: &RepositoryOptions,
+    index_db: &Database,
+) -> Result<bool, LpmError<RepositoryError>> {
+    if options.signature_level != SignatureLevel::Required
+        || PkgIndex::signing_key(index_db)?.is_some()
+    {
+        return Ok(true);
+    }
 
-        let req_url = format!("{address}/index-tracker/{index_timestamp}");
-        debug!("Sending request to '{req_url}'");
-        let r = Rekuest::new(&req_url)?.get()?;
-        let patch = String::from_utf8(r.body)
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))?;
-        debug!("Applying:\n\n {patch}");
+    warning!("Skipping {name} repository, it requires signatures but its index is not signed. Update the repository indexes.");
+    Ok(false)
+}
+
+/// Whether `index` comes from a repository that requires signatures and whose
+/// index was verified on every sync. The daemon lets local users install and
+/// update such packages without an administrator.
+pub(crate) fn is_index_trusted(
+    core_db: &Database,
+    index: &PkgIndex,
+) -> Result<bool, LpmError<RepositoryError>> {
+    let options = get_repository_options(core_db, &index.repository_name)?;
+    Ok(options.signature_level == SignatureLevel::Required && index.signing_key.is_some())
+}
+
+/// Fails if the transaction is limited to trusted repositories and any of
+/// `indexes` is not from one, which can happen if the indexes changed since
+/// the daemon authorized it.
+pub(crate) fn ensure_trusted_origin<'a>(
+    ctx: &Ctx,
+    indexes: impl IntoIterator<Item = &'a PkgIndex>,
+) -> Result<(), LpmError<RepositoryError>> {
+    if !ctx.trusted_repositories_only {
+        return Ok(());
+    }
+
+    for index in indexes {
+        if !is_index_trusted(&ctx.core_db, index)? {
+            return Err(RepositoryErrorKind::UntrustedRepository {
+                package: index.name.clone(),
+                repository: index.repository_name.clone(),
+            }
+            .to_lpm_err());
+        }
+    }
+
+    Ok(())
+}
 
-        if !patch.is_empty() {
-            #[allow(clippy::disallowed_methods)]
-            index_db.execute(patch, SQL_NO_CALLBACK_FN)?;
+/// Calls `f` on each of `items` from up to `jobs` threads, and returns the
+/// results in the order of `items`.
+fn map_parallel<T: Sync, R: Send>(items: &[T], jobs: usize, f: impl Fn(&T) -> R + Sync) -> Vec<R> {
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(Vec::with_capacity(items.len()));
+
+    thread::scope(|s| {
+        for _ in 0..jobs.clamp(1, items.len().max(1)) {
+            s.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some(item) = items.get(i) else {
+                    break;
+                };
+
+                let result = f(item);
+                results.lock().unwrap().push((i, result));
+            });
         }
+    });
+
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|(i, _)| *i);
+    results.into_iter().map(|(_, t)| t).collect()
+}
 
-        info!("Index of '{name}' is successfully updated.");
+/// Fails with a request to upgrade lpm if the index of `name` uses a newer
+/// schema than this lpm supports.
+fn ensure_supported_index_schema(
+    name: &str,
+    index_db: &Database,
+) -> Result<(), LpmError<RepositoryError>> {
+    let version = db::index_schema_version(index_db)?;
+    if version > db::INDEX_SCHEMA_VERSION {
+        return Err(RepositoryErrorKind::UnsupportedIndexSchema {
+            repository: name.to_owned(),
+            version,
+            supported: db::INDEX_SCHEMA_VERSION,
+        }
+        .to_lpm_err());
     }
 
     Ok(())
 }
 
-/// Finds most recent one when version is not specified
+/// Migrates the freshly patched index of `name` to the current schema, or
+/// rejects it if the server publishes a newer one.
+fn upgrade_index_schema(name: &str, index_db: &Database) -> Result<(), LpmError<RepositoryError>> {
+    ensure_supported_index_schema(name, index_db)?;
+    Ok(db::migrate_index(index_db)?)
+}
+
+/// Refuses to go on if the index of a repository that `indexes` were resolved
+/// from is stale as `is_metadata_stale` tells, so a frozen or replayed mirror
+/// can not keep clients on outdated packages. The other repositories are not
+/// checked.
+///
+/// The expiry date is part of the index, so it's covered by the signatures
+/// of the signed ones.
+pub(crate) fn ensure_fresh_metadata<'a>(
+    ctx: &Ctx,
+    indexes: impl IntoIterator<Item = &'a PkgIndex>,
+) -> Result<(), LpmError<RepositoryError>> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|t| t.as_secs() as i64)
+        .unwrap_or_default();
+
+    let names: BTreeSet<&str> = indexes
+        .into_iter()
+        .map(|index| index.repository_name.as_str())
+        .collect();
+    for name in names {
+        let options = get_repository_options(&ctx.core_db, name)?;
+        let repository_db_path = Path::new(REPOSITORY_INDEX_DB_DIR).join(name);
+        let index_db = Database::open(&repository_db_path)?;
+        ensure_supported_index_schema(name, &index_db)?;
+
+        let expires_at = PkgIndex::metadata_expires_at(&index_db)?;
+        let expiry_published = PkgIndex::has_published_expiry(&index_db)?;
+        if is_metadata_stale(expires_at, expiry_published, options.signature_level, now) {
+            if !ctx.config.allow_stale_metadata {
+                return Err(RepositoryErrorKind::StaleMetadata(name.to_owned()).to_lpm_err());
+            }
+
+            warning!("Index metadata of {name} repository has expired or has no expiry date, using it anyway as 'allow_stale_metadata' is set.");
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether an index that expires at `expires_at` is out of date at `now`.
+///
+/// An index without an expiry date can't be told apart from a frozen one, so
+/// it's only accepted from repositories that don't require signatures and
+/// never published one.
+fn is_metadata_stale(
+    expires_at: Option<i64>,
+    expiry_published: bool,
+    signature_level: SignatureLevel,
+    now: i64,
+) -> bool {
+    match expires_at {
+        Some(expires_at) => expires_at < now,
+        None => expiry_published || signature_level == SignatureLevel::Required,
+    }
+}
+
+/// Makes sure that all of the given packages can be installed without network
+/// access, either from the download cache or from local repositories.
+pub(crate) fn ensure_available_offline(
+    indexes: &[&PkgIndex],
+    output_dir: &str,
+) -> Result<(), LpmError<RepositoryError>> {
+    let missing: Vec<String> = indexes
+        .iter()
+        .filter(|index| {
+            let is_cached = index.pkg_output_path(output_dir).exists();
+            let is_local = local_address_path(&index.pkg_url()).map_or(false, |t| t.exists());
+
+            !is_cached && !is_local
+        })
+        .map(|index| format!("{} ({})", index.get_group_id(), index.pkg_url()))
+        .collect();
+
+    if !missing.is_empty() {
+        return Err(RepositoryErrorKind::MissingOfflineArtifacts(missing).to_lpm_err());
+    }
+
+    Ok(())
+}
+
+/// Finds most recent one when version is not specified, among the packages
+/// that can be installed on a `target_arch` system.
 pub(crate) fn find_pkg_index(
+    core_db: &Database,
     index_db_list: &[(String, String)],
     pkg_to_query: &PkgToQuery,
+    target_arch: &str,
 ) -> Result<PkgIndex, LpmError<RepositoryError>> {
     let mut most_recent_index = PkgIndex::default();
 
     for (name, address) in index_db_list {
+        let options = get_repository_options(core_db, name)?;
+        if !is_repository_usable(name, &options) || !options.is_pkg_allowed(&pkg_to_query.name) {
+            continue;
+        }
+
         let repository_db_path = Path::new(REPOSITORY_INDEX_DB_DIR).join(name);
         let db_file = fs::metadata(&repository_db_path)?;
         let db = Database::open(Path::new(&repository_db_path))?;
@@ -176,10 +791,18 @@ pub(crate) fn find_pkg_index(
             warning!("{name} repository is not initialized");
             continue;
         }
+        ensure_supported_index_schema(name, &db)?;
+        if !is_index_verified(name, &options, &db)? {
+            continue;
+        }
 
-        if let Some(index) =
-            PkgIndex::query_pkg_with_versions(&db, pkg_to_query, address.to_owned())?
-        {
+        if let Some(index) = PkgIndex::query_pkg_with_versions(
+            &db,
+            pkg_to_query,
+            &options.index_archs(target_arch),
+            name.to_owned(),
+            address.to_owned(),
+        )? {
             if index.version.compare(&most_recent_index.version) == std::cmp::Ordering::Greater {
                 most_recent_index = index
             };
@@ -192,3 +815,368 @@ pub(crate) fn find_pkg_index(
 
     Ok(most_recent_index)
 }
+
+/// Origin recorded for the packages installed or updated from `index`.
+pub(crate) fn index_origin(index: &PkgIndex) -> Result<PkgOrigin, LpmError<RepositoryError>> {
+    let index_db = Database::open(Path::new(REPOSITORY_INDEX_DB_DIR).join(&index.repository_name))?;
+
+    Ok(PkgOrigin {
+        repository: index.repository_name.clone(),
+        index_timestamp: PkgIndex::latest_timestamp(&index_db)?,
+        signing_key: index.signing_key.clone(),
+    })
+}
+
+/// Searches the usable repositories for packages whose name contains `pattern`,
+/// keeping the most recent version if more than one provides the same package.
+/// Only the packages that can be installed on a `target_arch` system are found.
+pub(crate) fn search_pkg_indexes(
+    core_db: &Database,
+    pattern: &str,
+    target_arch: &str,
+) -> Result<Vec<PkgIndex>, LpmError<RepositoryError>> {
+    let mut found: Vec<PkgIndex> = Vec::new();
+
+    for (name, address) in get_repositories(core_db)? {
+        let options = get_repository_options(core_db, &name)?;
+        if !is_repository_usable(&name, &options) {
+            continue;
+        }
+
+        let repository_db_path = Path::new(REPOSITORY_INDEX_DB_DIR).join(&name);
+        if fs::metadata(&repository_db_path)?.len() == 0 {
+            warning!("{name} repository is not initialized");
+            continue;
+        }
+
+        let db = Database::open(&repository_db_path)?;
+        ensure_supported_index_schema(&name, &db)?;
+        if !is_index_verified(&name, &options, &db)? {
+            continue;
+        }
+        let archs = options.index_archs(target_arch);
+        for index in PkgIndex::search(&db, pattern, &archs, &name, &address)? {
+            if !options.is_pkg_allowed(&index.name) {
+                continue;
+            }
+
+            match found.iter_mut().find(|t| t.name == index.name) {
+                Some(existing) => {
+                    if index.version.compare(&existing.version) == std::cmp::Ordering::Greater {
+                        *existing = index;
+                    }
+                }
+                None => found.push(index),
+            }
+        }
+    }
+
+    found.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(found)
+}
+
+/// Order of the packages listed by `lpm --repository --list-packages`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PackageSort {
+    Name,
+    /// Largest installed size first, packages without one last.
+    Size,
+}
+
+impl PackageSort {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "name" => Some(Self::Name),
+            "size" => Some(Self::Size),
+            _ => None,
+        }
+    }
+}
+
+const DEFAULT_PAGE_SIZE: usize = 50;
+
+fn parse_page_arg(flag: &str, value: Option<&str>, default: usize) -> Result<usize, io::Error> {
+    match value {
+        None => Ok(default),
+        Some(value) => value.parse().ok().filter(|t| *t > 0).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid '{flag}' value '{value}', expected a positive number."),
+            )
+        }),
+    }
+}
+
+/// Sorts `pkgs` by `sort` and returns the 1-based `page` of them.
+fn page_of_pkgs(
+    pkgs: &mut [PkgIndex],
+    sort: PackageSort,
+    page: usize,
+    page_size: usize,
+) -> &[PkgIndex] {
+    match sort {
+        PackageSort::Name => pkgs.sort_by(|a, b| a.name.cmp(&b.name)),
+        PackageSort::Size => pkgs.sort_by(|a, b| {
+            b.installed_size
+                .cmp(&a.installed_size)
+                .then_with(|| a.name.cmp(&b.name))
+        }),
+    }
+
+    let start = (page - 1).saturating_mul(page_size).min(pkgs.len());
+    let end = start.saturating_add(page_size).min(pkgs.len());
+    &pkgs[start..end]
+}
+
+/// Number of pages `total` packages take, at least one. Rounds up without
+/// adding to `total`, which `--page-size` could overflow.
+fn page_count(total: usize, page_size: usize) -> usize {
+    (total / page_size + usize::from(total % page_size != 0)).max(1)
+}
+
+/// Prints a page of the packages that the `name` repository offers for the
+/// target system, the most recent version of each, read from its index.
+pub fn print_repository_packages(
+    ctx: Ctx,
+    name: &str,
+    sort: Option<&str>,
+    page: Option<&str>,
+    page_size: Option<&str>,
+) -> Result<(), LpmError<MainError>> {
+    let sort = match sort {
+        None => PackageSort::Name,
+        Some(value) => PackageSort::parse(value).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid '--sort' value '{value}', expected 'name' or 'size'."),
+            )
+        })?,
+    };
+    let page = parse_page_arg("--page", page, 1)?;
+    let page_size = parse_page_arg("--page-size", page_size, DEFAULT_PAGE_SIZE)?;
+
+    if !is_repository_exists(&ctx.core_db, name)? {
+        return Err(RepositoryErrorKind::RepositoryNotFound(name.to_owned()).to_lpm_err())?;
+    }
+
+    let repository_db_path = Path::new(REPOSITORY_INDEX_DB_DIR).join(name);
+    if fs::metadata(&repository_db_path)?.len() == 0 {
+        warning!("{name} repository is not initialized");
+        return Ok(());
+    }
+
+    let db = Database::open(&repository_db_path)?;
+    ensure_supported_index_schema(name, &db)?;
+    let options = get_repository_options(&ctx.core_db, name)?;
+    let address = get_repositories(&ctx.core_db)?
+        .into_iter()
+        .find(|t| t.0 == name)
+        .map(|t| t.1)
+        .unwrap_or_default();
+
+    let mut pkgs: Vec<PkgIndex> = PkgIndex::search(
+        &db,
+        "",
+        &options.index_archs(ctx.target_arch()),
+        name,
+        &address,
+    )?
+    .into_iter()
+    .filter(|t| options.is_pkg_allowed(&t.name))
+    .collect();
+    let total = pkgs.len();
+    let page_count = page_count(total, page_size);
+
+    println!();
+    let pkgs = page_of_pkgs(&mut pkgs, sort, page, page_size);
+    if pkgs.is_empty() {
+        println!("No packages on page {page} of '{name}' repository ({total} packages).");
+        return Ok(());
+    }
+
+    println!("Packages of '{name}' repository (page {page} of {page_count}, {total} packages):");
+    let group_ids: Vec<String> = pkgs.iter().map(|t| t.get_group_id()).collect();
+    let width = group_ids.iter().map(|t| t.len()).max().unwrap_or(0);
+    for (pkg, group_id) in pkgs.iter().zip(&group_ids) {
+        let size = pkg
+            .installed_size
+            .map_or(String::from("-"), |t| format_byte_size(t.max(0) as u64));
+        println!(
+            "  {group_id:<width$}  {size:>10}  {}",
+            pkg.description.as_deref().unwrap_or_default()
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_page_of_pkgs() {
+        let pkg = |name: &str, installed_size| PkgIndex {
+            name: name.to_owned(),
+            installed_size,
+            ..Default::default()
+        };
+        let mut pkgs = vec![
+            pkg("htop", Some(200)),
+            pkg("bash", Some(1000)),
+            pkg("zlib", None),
+            pkg("curl", Some(200)),
+        ];
+        let names = |pkgs: &[PkgIndex]| pkgs.iter().map(|t| t.name.clone()).collect::<Vec<_>>();
+
+        let page = page_of_pkgs(&mut pkgs, PackageSort::Name, 1, 3);
+        assert_eq!(names(page), ["bash", "curl", "htop"]);
+        let page = page_of_pkgs(&mut pkgs, PackageSort::Name, 2, 3);
+        assert_eq!(names(page), ["zlib"]);
+        assert!(page_of_pkgs(&mut pkgs, PackageSort::Name, 3, 3).is_empty());
+
+        let page = page_of_pkgs(&mut pkgs, PackageSort::Size, 1, 10);
+        assert_eq!(names(page), ["bash", "curl", "htop", "zlib"]);
+        assert_eq!(
+            names(page_of_pkgs(&mut pkgs, PackageSort::Name, 1, usize::MAX)).len(),
+            4
+        );
+
+        assert_eq!(page_count(4, 3), 2);
+        assert_eq!(page_count(6, 3), 2);
+        assert_eq!(page_count(0, 50), 1);
+        assert_eq!(page_count(4, usize::MAX), 1);
+    }
+
+    #[test]
+    fn test_map_parallel() {
+        let items: Vec<u64> = (0..20).collect();
+        let running = AtomicUsize::new(0);
+        let most_running = AtomicUsize::new(0);
+
+        let results = map_parallel(&items, 3, |t| {
+            let now_running = running.fetch_add(1, Ordering::SeqCst) + 1;
+            most_running.fetch_max(now_running, Ordering::SeqCst);
+            thread::sleep(std::time::Duration::from_millis(2));
+            running.fetch_sub(1, Ordering::SeqCst);
+            t * 2
+        });
+
+        assert_eq!(results, items.iter().map(|t| t * 2).collect::<Vec<_>>());
+        assert!(most_running.load(Ordering::SeqCst) <= 3);
+        assert!(map_parallel(&[] as &[u64], 3, |t| *t).is_empty());
+    }
+
+    #[test]
+    fn test_is_metadata_stale() {
+        let now = 1_700_000_000;
+
+        // Unsigned and legacy repositories that never published an expiry.
+        assert!(!is_metadata_stale(None, false, SignatureLevel::Never, now));
+        assert!(!is_metadata_stale(
+            None,
+            false,
+            SignatureLevel::Optional,
+            now
+        ));
+
+        assert!(is_metadata_stale(
+            None,
+            false,
+            SignatureLevel::Required,
+            now
+        ));
+        assert!(is_metadata_stale(None, true, SignatureLevel::Optional, now));
+
+        assert!(!is_metadata_stale(
+            Some(now + 60),
+            true,
+            SignatureLevel::Required,
+            now
+        ));
+        assert!(is_metadata_stale(
+            Some(now - 60),
+            false,
+            SignatureLevel::Never,
+            now
+        ));
+    }
+
+    #[test]
+    fn test_normalize_repository_address() {
+        let normalized = |address| normalize_repository_address(address).ok();
+
+        assert_eq!(
+            normalized("HTTP://Example.ORG:80/lpm/"),
+            Some(String::from("http://example.org/lpm"))
+        );
+        assert_eq!(
+            normalized("example.org:6150"),
+            Some(String::from("http://example.org:6150"))
+        );
+        assert_eq!(
+            normalized("/srv/repo//"),
+            Some(String::from("file:///srv/repo"))
+        );
+        assert_eq!(normalized("file:///srv/repo/"), normalized("/srv/repo"));
+        assert_eq!(normalized("file:///"), Some(String::from("file:///")));
+        assert_eq!(
+            normalized("HTTPS://Example.ORG:443/lpm/"),
+            Some(String::from("https://example.org/lpm"))
+        );
+        assert_eq!(
+            normalized("https://example.org:80"),
+            Some(String::from("https://example.org:80"))
+        );
+
+        assert_eq!(normalized("ftp://example.org"), None);
+        assert_eq!(normalized("file://srv/repo"), None);
+        assert_eq!(normalized("http:///lpm"), None);
+    }
+
+    #[test]
+    fn test_verify_patch() {
+        let keyring = Keyring {
+            keys: [(
+                String::from("release-2024"),
+                crate::KeyState::Trusted(String::from(
+                    "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a",
+                )),
+            )]
+            .into(),
+        };
+        let patch = "-- signature: release-2024 4323ea6f048935e4bf9eea54c8bde9065e2e666399b79c51592ae59a5847bf976e0b1e943dd2e516e4352f38caa83f5ef8a8acb8d18c47ebeceaad429e7cc701\nDELETE FROM repository;";
+        let verify = |patch, index_timestamp, level| {
+            verify_patch("main", patch, index_timestamp, level, &keyring)
+                .map_err(|e| format!("{:?}", e.error_type))
+        };
+
+        for level in [SignatureLevel::Optional, SignatureLevel::Required] {
+            assert_eq!(
+                verify(patch, 0, level).unwrap(),
+                (
+                    "DELETE FROM repository;",
+                    Some(String::from("release-2024"))
+                )
+            );
+        }
+        assert_eq!(
+            verify(patch, 0, SignatureLevel::Never).unwrap(),
+            ("DELETE FROM repository;", None)
+        );
+
+        // Signed for another version of the index.
+        assert!(verify(patch, 1700000000, SignatureLevel::Optional)
+            .unwrap_err()
+            .contains("signature of key 'release-2024' doesn't match"));
+
+        let unsigned = "DELETE FROM repository;";
+        assert_eq!(
+            verify(unsigned, 0, SignatureLevel::Optional).unwrap(),
+            (unsigned, None)
+        );
+        assert!(verify(unsigned, 0, SignatureLevel::Required)
+            .unwrap_err()
+            .contains("the patch isn't signed"));
+    }
+}
@@ -0,0 +1,270 @@
+//! Journal of the file changes of an update transaction, so that a failure
+//! after them(a post-update script, the database commit) restores the files
+//! along with the database.
+//!
+//! Replaced and removed files are renamed to `.<name>.lpm-old` next to them
+//! instead of being dropped, which keeps them on the same filesystem. Files
+//! that are patched in place with a delta are copied aside instead. The
+//! retained files are dropped once the transaction commits. Directories
+//! created by the update are left in place either way.
+//!
+//! The `Backups` of the transaction are taken through the journal too, as
+//! they're recorded whether it commits or not.
+
+use crate::backup::Backups;
+
+use common::copy::{copy_file, set_attributes, set_ownership};
+use logger::{debug, info, warning};
+use std::{
+    collections::HashSet,
+    fs, io,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+enum FsChange {
+    /// Didn't exist before the transaction.
+    Added(PathBuf),
+    /// The previous version is kept at `retained`.
+    Replaced { path: PathBuf, retained: PathBuf },
+    /// Only the ownership, mode or modification time changed.
+    Attributes {
+        path: PathBuf,
+        metadata: fs::Metadata,
+    },
+}
+
+pub(crate) struct FsJournal {
+    backups: Backups,
+    changes: Vec<FsChange>,
+    /// Paths whose state before the transaction is recorded already, later
+    /// changes of the same transaction don't replace it.
+    recorded: HashSet<PathBuf>,
+}
+
+fn retained_path(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{name}.lpm-old"))
+}
+
+fn restore_attributes(path: &Path, metadata: &fs::Metadata) -> io::Result<()> {
+    set_ownership(
+        Path::new("/"),
+        path,
+        Some(&metadata.uid().to_string()),
+        Some(&metadata.gid().to_string()),
+    )?;
+    if metadata.file_type().is_symlink() {
+        return Ok(());
+    }
+
+    set_attributes(path, Some(metadata.mode() & 0o7777), Some(metadata.mtime()))
+}
+
+impl FsJournal {
+    pub(crate) fn new(backups: Backups) -> Self {
+        Self {
+            backups,
+            changes: Vec::new(),
+            recorded: HashSet::new(),
+        }
+    }
+
+    /// Stores the content of `path` in the backups before it's replaced or
+    /// removed.
+    pub(crate) fn backup(&mut self, path: &Path) -> io::Result<()> {
+        self.backups.save(path)
+    }
+
+    /// Moves `path` aside before it's replaced or removed, or records that it
+    /// didn't exist. Once `path` is recorded, it's just removed.
+    pub(crate) fn retain(&mut self, path: &Path) -> io::Result<()> {
+        let exists = match fs::symlink_metadata(path) {
+            Ok(_) => true,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => false,
+            Err(e) => return Err(e),
+        };
+
+        if !self.recorded.insert(path.to_owned()) {
+            if exists {
+                fs::remove_file(path)?;
+            }
+            return Ok(());
+        }
+
+        if !exists {
+            self.changes.push(FsChange::Added(path.to_owned()));
+            return Ok(());
+        }
+
+        let retained = retained_path(path);
+        fs::rename(path, &retained)?;
+        debug!("Retained {} as {}", path.display(), retained.display());
+        self.changes.push(FsChange::Replaced {
+            path: path.to_owned(),
+            retained,
+        });
+
+        Ok(())
+    }
+
+    /// Copies `path` aside before it's changed in place.
+    pub(crate) fn retain_copy(&mut self, path: &Path) -> io::Result<()> {
+        if self.recorded.contains(path) {
+            return Ok(());
+        }
+
+        let metadata = fs::symlink_metadata(path)?;
+        let retained = retained_path(path);
+        copy_file(path, &retained)?;
+        restore_attributes(&retained, &metadata)?;
+        debug!(
+            "Retained a copy of {} as {}",
+            path.display(),
+            retained.display()
+        );
+
+        self.recorded.insert(path.to_owned());
+        self.changes.push(FsChange::Replaced {
+            path: path.to_owned(),
+            retained,
+        });
+
+        Ok(())
+    }
+
+    /// Records the ownership, mode and modification time of `path` before
+    /// they are changed.
+    pub(crate) fn retain_attributes(&mut self, path: &Path) -> io::Result<()> {
+        if self.recorded.contains(path) {
+            return Ok(());
+        }
+
+        let metadata = fs::symlink_metadata(path)?;
+        self.recorded.insert(path.to_owned());
+        self.changes.push(FsChange::Attributes {
+            path: path.to_owned(),
+            metadata,
+        });
+
+        Ok(())
+    }
+
+    /// Drops the retained files if the transaction `committed`, restores them
+    /// otherwise, then records the backups. Failures are only warned about,
+    /// as the other files can still be handled.
+    pub(crate) fn finish(self, committed: bool) {
+        if committed {
+            drop_retained(self.changes);
+        } else {
+            restore(self.changes);
+        }
+
+        self.backups.finish();
+    }
+}
+
+fn drop_retained(changes: Vec<FsChange>) {
+    for change in changes {
+        if let FsChange::Replaced { retained, .. } = change {
+            if let Err(e) = fs::remove_file(&retained) {
+                warning!("Couldn't remove {}: {e}", retained.display());
+            }
+        }
+    }
+}
+
+/// Undoes `changes`, the most recent first.
+fn restore(changes: Vec<FsChange>) {
+    if !changes.is_empty() {
+        info!("Restoring the files changed by the failed transaction..");
+    }
+
+    for change in changes.into_iter().rev() {
+        let (path, result) = match change {
+            FsChange::Added(path) => {
+                let result = match fs::remove_file(&path) {
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+                    result => result,
+                };
+                (path, result)
+            }
+            FsChange::Replaced { path, retained } => {
+                let result = fs::rename(&retained, &path);
+                (path, result)
+            }
+            FsChange::Attributes { path, metadata } => {
+                let result = restore_attributes(&path, &metadata);
+                (path, result)
+            }
+        };
+
+        if let Err(e) = result {
+            warning!("Couldn't restore {}: {e}", path.display());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::testing::TestDir;
+
+    #[test]
+    fn test_fs_journal() {
+        let dir = TestDir::new("rollback");
+        let path = |name: &str| dir.join(name);
+        let read = |name: &str| fs::read_to_string(path(name)).ok();
+
+        let setup = || {
+            for name in ["replaced", "removed", "patched", "chmoded"] {
+                fs::write(path(name), name).unwrap();
+            }
+            set_attributes(&path("chmoded"), Some(0o644), None).unwrap();
+        };
+        let apply = |journal: &mut FsJournal| {
+            journal.retain(&path("replaced")).unwrap();
+            fs::write(path("replaced"), "new").unwrap();
+            // A second change of the same transaction keeps the first state.
+            journal.retain(&path("replaced")).unwrap();
+            fs::write(path("replaced"), "newer").unwrap();
+
+            journal.retain(&path("removed")).unwrap();
+
+            journal.retain(&path("added")).unwrap();
+            fs::write(path("added"), "added").unwrap();
+
+            journal.retain_copy(&path("patched")).unwrap();
+            fs::write(path("patched"), "in place").unwrap();
+
+            journal.retain_attributes(&path("chmoded")).unwrap();
+            set_attributes(&path("chmoded"), Some(0o600), None).unwrap();
+        };
+
+        setup();
+        let mut journal = FsJournal::new(Backups::new(0));
+        apply(&mut journal);
+        journal.finish(false);
+
+        assert_eq!(read("replaced").as_deref(), Some("replaced"));
+        assert_eq!(read("removed").as_deref(), Some("removed"));
+        assert_eq!(read("added"), None);
+        assert_eq!(read("patched").as_deref(), Some("patched"));
+        let mode = fs::metadata(path("chmoded")).unwrap().mode() & 0o7777;
+        assert_eq!(mode, 0o644);
+
+        let mut journal = FsJournal::new(Backups::new(0));
+        apply(&mut journal);
+        journal.finish(true);
+
+        assert_eq!(read("replaced").as_deref(), Some("newer"));
+        assert_eq!(read("removed"), None);
+        assert_eq!(read("added").as_deref(), Some("added"));
+        let mut names: Vec<String> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|t| t.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["added", "chmoded", "patched", "replaced"]);
+    }
+}
@@ -0,0 +1,585 @@
+//! `lpm --rpc-daemon`, which serves the operations of `Lpm` as JSON-RPC 2.0 on
+//! a Unix socket, one request or response per line.
+//!
+//! ```json
+//! {"jsonrpc": "2.0", "id": 1, "method": "install", "params": {"packages": ["htop"]}}
+//! ```
+//!
+//! Every request is authorized on its own against the uid of the connected
+//! peer: `list` and `search` are open to everyone, transactions are only
+//! allowed for root and the users in the `rpc_allowed_uids` config setting.
+//!
+//! The socket is only accessible to root, or also to the group in the
+//! `rpc_socket_gid` config setting. Connections are still served one at a
+//! time, so each request has to arrive within `REQUEST_TIMEOUT` and fit in
+//! `MAX_REQUEST_LEN` bytes, otherwise the connection is closed.
+
+use crate::{AvailablePackage, Changes, Ctx, Initiator, Lpm, PackageInfo, PackageKind};
+
+use ehandle::{lpm::LpmError, MainError};
+use json::{to_json_object, Json, JsonValue, Serialize};
+use std::{
+    ffi::CString,
+    fs,
+    io::{self, BufRead, BufReader, Read, Write},
+    os::{
+        raw::{c_char, c_int, c_void},
+        unix::{
+            ffi::OsStrExt,
+            fs::{FileTypeExt, PermissionsExt},
+            io::AsRawFd,
+            net::{UnixListener, UnixStream},
+        },
+    },
+    path::Path,
+    time::{Duration, Instant},
+};
+
+pub const DEFAULT_RPC_SOCKET_PATH: &str = "/run/lpm/rpc.sock";
+
+/// Time a client has to send each request and to read each response.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_REQUEST_LEN: usize = 64 * 1024;
+
+const PARSE_ERROR: i32 = -32700;
+const INVALID_REQUEST: i32 = -32600;
+const METHOD_NOT_FOUND: i32 = -32601;
+const INVALID_PARAMS: i32 = -32602;
+const OPERATION_FAILED: i32 = -32000;
+const NOT_AUTHORIZED: i32 = -32001;
+
+const SOL_SOCKET: c_int = 1;
+const SO_PEERCRED: c_int = 17;
+
+#[repr(C)]
+struct UCred {
+    pid: i32,
+    uid: u32,
+    gid: u32,
+}
+
+extern "C" {
+    fn getsockopt(
+        sockfd: c_int,
+        level: c_int,
+        optname: c_int,
+        optval: *mut c_void,
+        optlen: *mut u32,
+    ) -> c_int;
+    fn chown(path: *const c_char, owner: u32, group: u32) -> c_int;
+}
+
+#[derive(Debug, PartialEq)]
+struct RpcError {
+    code: i32,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i32, message: &str) -> Self {
+        Self {
+            code,
+            message: message.to_owned(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Request {
+    /// `None` for notifications, which get no response.
+    id: Option<JsonValue>,
+    method: String,
+    params: JsonValue,
+}
+
+impl Serialize for PackageInfo {
+    fn to_json(&self) -> String {
+        to_json_object(&[
+            ("name", self.name.to_json()),
+            ("version", self.version.to_json()),
+            ("arch", self.arch.to_json()),
+            ("slot", self.slot.to_json()),
+            ("kind", self.kind.map(|t| t.as_str().to_owned()).to_json()),
+            ("installed_size", self.installed_size.to_json()),
+            ("is_dependency", self.is_dependency.to_json()),
+        ])
+    }
+}
+
+impl Serialize for AvailablePackage {
+    fn to_json(&self) -> String {
+        to_json_object(&[
+            ("name", self.name.to_json()),
+            ("version", self.version.to_json()),
+            ("repository", self.repository.to_json()),
+            ("archive_size", self.archive_size.to_json()),
+            ("installed_size", self.installed_size.to_json()),
+            ("kind", self.kind.map(|t| t.as_str().to_owned()).to_json()),
+        ])
+    }
+}
+
+impl Serialize for Changes {
+    fn to_json(&self) -> String {
+        let updated: Vec<String> = self
+            .updated
+            .iter()
+            .map(|(from, to)| to_json_object(&[("from", from.to_json()), ("to", to.to_json())]))
+            .collect();
+
+        to_json_object(&[
+            ("installed", self.installed.to_json()),
+            ("updated", format!("[{}]", updated.join(","))),
+            ("deleted", self.deleted.to_json()),
+        ])
+    }
+}
+
+/// Serves the connections one at a time until the socket fails.
+pub fn run_rpc_daemon(ctx: Ctx, socket_path: &str) -> Result<(), LpmError<MainError>> {
+    let path = Path::new(socket_path);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    // Left over from a previous run, binding fails otherwise.
+    if matches!(fs::symlink_metadata(path), Ok(metadata) if metadata.file_type().is_socket()) {
+        fs::remove_file(path)?;
+    }
+
+    let listener = UnixListener::bind(path)?;
+    restrict_socket(path, ctx.config.rpc_socket_gid)?;
+    logger::info!("Serving JSON-RPC on '{socket_path}'.");
+
+    let allowed_uids = ctx.config.rpc_allowed_uids.clone();
+    let mut lpm = Lpm::from_ctx(ctx);
+
+    for stream in listener.incoming() {
+        if let Err(err) =
+            stream.and_then(|stream| serve_connection(&mut lpm, &allowed_uids, stream))
+        {
+            logger::warning!("JSON-RPC connection failed: {err}");
+        }
+    }
+
+    Ok(())
+}
+
+/// Leaves the socket to root, and to `gid` when there is one.
+fn restrict_socket(path: &Path, gid: Option<u32>) -> io::Result<()> {
+    let Some(gid) = gid else {
+        return fs::set_permissions(path, fs::Permissions::from_mode(0o600));
+    };
+
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    // SAFETY: `c_path` is a valid NUL terminated string that outlives the call.
+    #[allow(unsafe_code)]
+    if unsafe { chown(c_path.as_ptr(), 0, gid) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    fs::set_permissions(path, fs::Permissions::from_mode(0o660))
+}
+
+/// Stream that fails once `deadline` has passed, however slowly the peer
+/// keeps sending.
+struct DeadlineStream {
+    stream: UnixStream,
+    deadline: Instant,
+}
+
+impl Read for DeadlineStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "Timed out waiting for a request.",
+            ));
+        }
+
+        self.stream.set_read_timeout(Some(remaining))?;
+        self.stream.read(buf)
+    }
+}
+
+fn serve_connection(lpm: &mut Lpm, allowed_uids: &[u32], stream: UnixStream) -> io::Result<()> {
+    let uid = peer_uid(&stream)?;
+    lpm.set_initiator(Initiator::rpc_peer(uid));
+    let mut writer = stream.try_clone()?;
+    writer.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+
+    let mut reader = BufReader::new(DeadlineStream {
+        stream,
+        deadline: Instant::now(),
+    });
+
+    loop {
+        reader.get_mut().deadline = Instant::now() + REQUEST_TIMEOUT;
+        let line = match read_line(&mut reader, MAX_REQUEST_LEN) {
+            Ok(Some(line)) => line,
+            Ok(None) => break,
+            Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+                let response = response(
+                    &JsonValue::Null,
+                    Err(RpcError::new(INVALID_REQUEST, &err.to_string())),
+                );
+                writer.write_all(response.as_bytes())?;
+                writer.write_all(b"\n")?;
+                return Err(err);
+            }
+            Err(err) => return Err(err),
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let response = match parse_request(&line) {
+            Ok(request) => {
+                let result = if is_authorized(&request.method, uid, allowed_uids) {
+                    call_method(lpm, &request)
+                } else {
+                    Err(RpcError::new(
+                        NOT_AUTHORIZED,
+                        "Only root and the users in 'rpc_allowed_uids' can run transactions.",
+                    ))
+                };
+
+                match request.id {
+                    Some(id) => response(&id, result),
+                    None => continue,
+                }
+            }
+            Err(err) => response(&JsonValue::Null, Err(err)),
+        };
+
+        writer.write_all(response.as_bytes())?;
+        writer.write_all(b"\n")?;
+    }
+
+    Ok(())
+}
+
+/// Reads up to the next newline, or `None` at the end of the stream. Fails
+/// with `InvalidData` on lines longer than `max_len` or that aren't UTF-8,
+/// without buffering more than `max_len` bytes.
+fn read_line<R: BufRead>(reader: &mut R, max_len: usize) -> io::Result<Option<String>> {
+    let mut line = Vec::new();
+
+    loop {
+        let available = reader.fill_buf()?;
+        if available.is_empty() {
+            if line.is_empty() {
+                return Ok(None);
+            }
+            break;
+        }
+
+        let newline = available.iter().position(|t| *t == b'\n');
+        let end = newline.unwrap_or(available.len());
+        if line.len() + end > max_len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Request is longer than {max_len} bytes."),
+            ));
+        }
+
+        line.extend_from_slice(&available[..end]);
+        match newline {
+            Some(newline) => {
+                reader.consume(newline + 1);
+                break;
+            }
+            None => {
+                let consumed = available.len();
+                reader.consume(consumed);
+            }
+        }
+    }
+
+    String::from_utf8(line)
+        .map(Some)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Request is not valid UTF-8."))
+}
+
+fn peer_uid(stream: &UnixStream) -> io::Result<u32> {
+    let mut cred = UCred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut len = std::mem::size_of::<UCred>() as u32;
+
+    // SAFETY: `cred` and `len` outlive the call and `len` is the size of `cred`.
+    #[allow(unsafe_code)]
+    let result = unsafe {
+        getsockopt(
+            stream.as_raw_fd(),
+            SOL_SOCKET,
+            SO_PEERCRED,
+            &mut cred as *mut UCred as *mut c_void,
+            &mut len,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(cred.uid)
+}
+
+fn is_authorized(method: &str, uid: u32, allowed_uids: &[u32]) -> bool {
+    matches!(method, "list" | "search") || uid == 0 || allowed_uids.contains(&uid)
+}
+
+fn parse_request(line: &str) -> Result<Request, RpcError> {
+    let json = Json::new(line)
+        .parse()
+        .map_err(|err| RpcError::new(PARSE_ERROR, &err))?;
+
+    if !json.is_object() || json["jsonrpc"].to_string().as_deref() != Some("2.0") {
+        return Err(RpcError::new(
+            INVALID_REQUEST,
+            "Expected a JSON-RPC 2.0 request object.",
+        ));
+    }
+
+    let method = json["method"]
+        .to_string()
+        .ok_or_else(|| RpcError::new(INVALID_REQUEST, "Field 'method' is missing."))?;
+
+    let id = match &json["id"] {
+        JsonValue::Null => None,
+        id => Some(id.clone()),
+    };
+
+    Ok(Request {
+        id,
+        method,
+        params: json["params"].clone(),
+    })
+}
+
+/// Parameters can be passed by name or by position.
+fn param<'a>(params: &'a JsonValue, name: &str) -> &'a JsonValue {
+    match params {
+        JsonValue::Array(_) => &params[0],
+        _ => &params[name],
+    }
+}
+
+fn package_names(params: &JsonValue) -> Result<Vec<String>, RpcError> {
+    let error = || RpcError::new(INVALID_PARAMS, "Expected 'packages' as an array of names.");
+
+    // Positional parameters are the names themselves.
+    let names = match params {
+        JsonValue::Array(names) => names,
+        _ => match &params["packages"] {
+            JsonValue::Array(names) => names,
+            _ => return Err(error()),
+        },
+    };
+
+    names
+        .iter()
+        .map(|name| name.to_string().ok_or_else(error))
+        .collect()
+}
+
+/// Optional `kind` that `list` and `search` are filtered by, named only.
+fn kind_param(params: &JsonValue) -> Result<Option<PackageKind>, RpcError> {
+    let kind = match params {
+        JsonValue::Object(_) => &params["kind"],
+        _ => return Ok(None),
+    };
+    if *kind == JsonValue::Null {
+        return Ok(None);
+    }
+
+    let error = || {
+        let kinds = PackageKind::ALL.map(|t| t.as_str());
+        RpcError::new(
+            INVALID_PARAMS,
+            &format!("Expected 'kind' as one of: {}.", kinds.join(", ")),
+        )
+    };
+    kind.to_string()
+        .as_deref()
+        .and_then(PackageKind::parse)
+        .map(Some)
+        .ok_or_else(error)
+}
+
+fn call_method(lpm: &mut Lpm, request: &Request) -> Result<String, RpcError> {
+    let failed = |err: LpmError<MainError>| {
+        RpcError::new(OPERATION_FAILED, &format!("{:?}", err.error_type))
+    };
+
+    match request.method.as_str() {
+        "list" => {
+            let kind = kind_param(&request.params)?;
+            let mut pkgs = lpm.list().map_err(failed)?;
+            if kind.is_some() {
+                pkgs.retain(|t| t.kind == kind);
+            }
+            Ok(pkgs.to_json())
+        }
+
+        "search" => {
+            let pattern = param(&request.params, "pattern")
+                .to_string()
+                .ok_or_else(|| RpcError::new(INVALID_PARAMS, "Expected a 'pattern' string."))?;
+            let kind = kind_param(&request.params)?;
+            let mut pkgs = lpm.search(&pattern).map_err(failed)?;
+            if kind.is_some() {
+                pkgs.retain(|t| t.kind == kind);
+            }
+            Ok(pkgs.to_json())
+        }
+
+        "install" | "update" | "delete" => {
+            let names = package_names(&request.params)?;
+            let names: Vec<&str> = names.iter().map(String::as_str).collect();
+
+            let changes = match request.method.as_str() {
+                "install" => lpm.install(&names),
+                "update" => lpm.update(&names),
+                _ => lpm.delete(&names),
+            }
+            .map_err(failed)?;
+
+            Ok(changes.to_json())
+        }
+
+        method => Err(RpcError::new(
+            METHOD_NOT_FOUND,
+            &format!("Unknown method '{method}'."),
+        )),
+    }
+}
+
+fn response(id: &JsonValue, result: Result<String, RpcError>) -> String {
+    // The parser doesn't keep whether a value was quoted, so numeric ids are
+    // echoed back as numbers.
+    let id = match id {
+        JsonValue::Plain(id) if id.parse::<i64>().is_ok() => id.clone(),
+        JsonValue::Plain(id) => id.to_json(),
+        _ => String::from("null"),
+    };
+
+    match result {
+        Ok(result) => {
+            to_json_object(&[("jsonrpc", "2.0".to_json()), ("id", id), ("result", result)])
+        }
+        Err(err) => to_json_object(&[
+            ("jsonrpc", "2.0".to_json()),
+            ("id", id),
+            (
+                "error",
+                to_json_object(&[
+                    ("code", err.code.to_json()),
+                    ("message", err.message.to_json()),
+                ]),
+            ),
+        ]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_request() {
+        let request = parse_request(
+            r#"{"jsonrpc": "2.0", "id": 7, "method": "install", "params": {"packages": ["htop", "vim"]}}"#,
+        )
+        .unwrap();
+        assert_eq!(request.id, Some(JsonValue::Plain(String::from("7"))));
+        assert_eq!(request.method, "install");
+        assert_eq!(package_names(&request.params).unwrap(), vec!["htop", "vim"]);
+
+        let request =
+            parse_request(r#"{"jsonrpc": "2.0", "method": "delete", "params": ["htop"]}"#).unwrap();
+        assert_eq!(request.id, None);
+        assert_eq!(package_names(&request.params).unwrap(), vec!["htop"]);
+        assert_eq!(kind_param(&request.params).unwrap(), None);
+
+        let request = parse_request(
+            r#"{"jsonrpc": "2.0", "id": 2, "method": "list", "params": {"kind": "kernel"}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            kind_param(&request.params).unwrap(),
+            Some(PackageKind::Kernel)
+        );
+        let request = parse_request(
+            r#"{"jsonrpc": "2.0", "id": 3, "method": "search", "params": {"pattern": "", "kind": "game"}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            kind_param(&request.params).unwrap_err().code,
+            INVALID_PARAMS
+        );
+
+        assert_eq!(
+            parse_request(r#"{"id": 1, "method": "list"}"#)
+                .unwrap_err()
+                .code,
+            INVALID_REQUEST
+        );
+        assert_eq!(
+            parse_request(r#"{"jsonrpc": "2.0", "id": 1}"#)
+                .unwrap_err()
+                .code,
+            INVALID_REQUEST
+        );
+    }
+
+    #[test]
+    fn test_response() {
+        assert_eq!(
+            response(&JsonValue::Plain(String::from("1")), Ok(String::from("[]"))),
+            r#"{"jsonrpc":"2.0","id":1,"result":[]}"#
+        );
+        assert_eq!(
+            response(
+                &JsonValue::Plain(String::from("abc")),
+                Err(RpcError::new(METHOD_NOT_FOUND, "Unknown method 'x'."))
+            ),
+            r#"{"jsonrpc":"2.0","id":"abc","error":{"code":-32601,"message":"Unknown method 'x'."}}"#
+        );
+    }
+
+    #[test]
+    fn test_read_line() {
+        let mut reader = io::Cursor::new(b"{\"a\": 1}\n\nlast".to_vec());
+        assert_eq!(
+            read_line(&mut reader, 16).unwrap().as_deref(),
+            Some("{\"a\": 1}")
+        );
+        assert_eq!(read_line(&mut reader, 16).unwrap().as_deref(), Some(""));
+        assert_eq!(read_line(&mut reader, 16).unwrap().as_deref(), Some("last"));
+        assert_eq!(read_line(&mut reader, 16).unwrap(), None);
+
+        // Also across refills of the buffer.
+        let mut reader = BufReader::with_capacity(4, io::Cursor::new(vec![b'x'; 64]));
+        assert_eq!(
+            read_line(&mut reader, 16).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+
+        let mut reader = io::Cursor::new(vec![0xff, b'\n']);
+        assert_eq!(
+            read_line(&mut reader, 16).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn test_is_authorized() {
+        assert!(is_authorized("list", 1000, &[]));
+        assert!(is_authorized("install", 0, &[]));
+        assert!(is_authorized("install", 1000, &[1000]));
+        assert!(!is_authorized("delete", 1000, &[1001]));
+    }
+}
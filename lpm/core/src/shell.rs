@@ -0,0 +1,400 @@
+//! `lpm --shell`, a prompt for running several queries and staging a
+//! transaction before committing it.
+//!
+//! The database connection stays open for the whole session, and the package
+//! lists are loaded once and only reloaded after a commit or on `refresh`.
+
+use crate::{AvailablePackage, Changes, Ctx, Lpm, PackageInfo, PackageKind};
+
+use ehandle::{lpm::LpmError, MainError};
+use std::io::{self, BufRead, Write};
+
+const HELP: &str = "Commands:
+    search <PATTERN> [--kind <KIND>]  Search the repositories
+    list [--kind <KIND>]              List installed packages
+                                      (KIND: application, library, kernel, module, meta)
+    add <PACKAGES>                    Stage packages to install
+    update <PACKAGES>                 Stage installed packages to update
+    remove <PACKAGES>                 Stage packages to delete
+    unstage <PACKAGES>                Take packages out of the staged transaction
+    plan                              Show the staged transaction
+    commit                            Apply the staged transaction
+    clear                             Drop the staged transaction
+    refresh                           Reload the package lists
+    help                              Print help
+    exit, quit                        Leave the shell";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum StagedAction {
+    Install,
+    Update,
+    Delete,
+}
+
+impl StagedAction {
+    fn as_str(&self) -> &'static str {
+        match self {
+            StagedAction::Install => "install",
+            StagedAction::Update => "update",
+            StagedAction::Delete => "delete",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum ShellCommand<'a> {
+    Search(&'a str, Option<PackageKind>),
+    List(Option<PackageKind>),
+    Stage(StagedAction, Vec<&'a str>),
+    Unstage(Vec<&'a str>),
+    Plan,
+    Commit,
+    Clear,
+    Refresh,
+    Help,
+    Exit,
+    Empty,
+}
+
+impl<'a> ShellCommand<'a> {
+    fn parse(line: &'a str) -> Result<Self, String> {
+        let mut words = line.split_whitespace();
+        let Some(command) = words.next() else {
+            return Ok(Self::Empty);
+        };
+        let mut args: Vec<&str> = words.collect();
+
+        let kind = match args.iter().position(|t| *t == "--kind") {
+            Some(position) => {
+                let kind = args.get(position + 1).copied().unwrap_or_default();
+                args.drain(position..(position + 2).min(args.len()));
+                Some(PackageKind::parse(kind).ok_or_else(|| {
+                    format!(
+                        "Unknown kind '{kind}', expected one of: {}.",
+                        PackageKind::ALL.map(|t| t.as_str()).join(", ")
+                    )
+                })?)
+            }
+            None => None,
+        };
+        if kind.is_some() && !matches!(command, "search" | "list") {
+            return Err(format!("'{command}' can't be filtered by kind."));
+        }
+
+        let with_packages = |command: ShellCommand<'a>| {
+            if args.is_empty() {
+                Err(String::from("At least 1 package must be provided."))
+            } else {
+                Ok(command)
+            }
+        };
+
+        match command {
+            "search" => Ok(Self::Search(args.first().copied().unwrap_or(""), kind)),
+            "list" => Ok(Self::List(kind)),
+            "add" => with_packages(Self::Stage(StagedAction::Install, args.clone())),
+            "update" => with_packages(Self::Stage(StagedAction::Update, args.clone())),
+            "remove" => with_packages(Self::Stage(StagedAction::Delete, args.clone())),
+            "unstage" => with_packages(Self::Unstage(args.clone())),
+            "plan" => Ok(Self::Plan),
+            "commit" => Ok(Self::Commit),
+            "clear" => Ok(Self::Clear),
+            "refresh" => Ok(Self::Refresh),
+            "help" => Ok(Self::Help),
+            "exit" | "quit" => Ok(Self::Exit),
+            _ => Err(format!(
+                "Unknown command '{command}', see 'help' for the list."
+            )),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Session {
+    installed: Vec<PackageInfo>,
+    available: Vec<AvailablePackage>,
+    staged: Vec<(StagedAction, String)>,
+}
+
+impl Session {
+    fn load(&mut self, lpm: &Lpm) -> Result<(), LpmError<MainError>> {
+        self.installed = lpm.list()?;
+        self.available = lpm.search("")?;
+        Ok(())
+    }
+
+    fn installed(&self, name: &str) -> Option<&PackageInfo> {
+        self.installed.iter().find(|t| t.name == name)
+    }
+
+    fn available(&self, name: &str) -> Option<&AvailablePackage> {
+        self.available.iter().find(|t| t.name == name)
+    }
+
+    /// Stages `name`, replacing what was staged for it before.
+    fn stage(&mut self, action: StagedAction, name: &str) -> Result<(), String> {
+        match (action, self.installed(name), self.available(name)) {
+            (StagedAction::Install, Some(_), _) => {
+                return Err(format!("{name} is already installed."))
+            }
+            (StagedAction::Install, None, None) => {
+                return Err(format!("{name} is not found in the repositories."))
+            }
+            (StagedAction::Update | StagedAction::Delete, None, _) => {
+                return Err(format!("{name} is not installed."))
+            }
+            (StagedAction::Update, Some(_), None) => {
+                return Err(format!("{name} is not found in the repositories."))
+            }
+            _ => {}
+        }
+
+        self.unstage(name);
+        self.staged.push((action, name.to_owned()));
+
+        Ok(())
+    }
+
+    fn unstage(&mut self, name: &str) -> bool {
+        let count = self.staged.len();
+        self.staged.retain(|(_, staged)| staged != name);
+        self.staged.len() != count
+    }
+
+    fn staged(&self, action: StagedAction) -> Vec<&str> {
+        self.staged
+            .iter()
+            .filter(|(t, _)| *t == action)
+            .map(|(_, name)| name.as_str())
+            .collect()
+    }
+
+    fn print_plan(&self) {
+        if self.staged.is_empty() {
+            println!("Nothing is staged.");
+            return;
+        }
+
+        for (action, name) in &self.staged {
+            let from = self.installed(name).map(|t| t.version.as_str());
+            let to = self.available(name).map(|t| t.version.as_str());
+
+            let versions = match (action, from, to) {
+                (StagedAction::Install, _, Some(to)) => to.to_owned(),
+                (StagedAction::Update, Some(from), Some(to)) => format!("{from} -> {to}"),
+                (_, Some(from), _) => from.to_owned(),
+                _ => String::new(),
+            };
+            println!("  {:<8} {name} {versions}", action.as_str());
+        }
+    }
+
+    fn commit(&mut self, lpm: &mut Lpm) -> Result<Changes, LpmError<MainError>> {
+        let mut changes = Changes::default();
+
+        let to_install = self.staged(StagedAction::Install);
+        if !to_install.is_empty() {
+            changes
+                .installed
+                .extend(lpm.install(&to_install)?.installed);
+        }
+
+        let to_update = self.staged(StagedAction::Update);
+        if !to_update.is_empty() {
+            changes.updated.extend(lpm.update(&to_update)?.updated);
+        }
+
+        let to_delete = self.staged(StagedAction::Delete);
+        if !to_delete.is_empty() {
+            changes.deleted.extend(lpm.delete(&to_delete)?.deleted);
+        }
+
+        self.staged.clear();
+        Ok(changes)
+    }
+
+    fn run(&mut self, lpm: &mut Lpm, command: ShellCommand) -> Result<(), LpmError<MainError>> {
+        match command {
+            ShellCommand::Search(pattern, kind) => {
+                let found = self
+                    .available
+                    .iter()
+                    .filter(|t| t.name.contains(pattern) && (kind.is_none() || t.kind == kind));
+                for pkg in found {
+                    let installed = match self.installed(&pkg.name) {
+                        Some(installed) => format!(" (installed {})", installed.version),
+                        None => String::new(),
+                    };
+                    println!(
+                        "  {} {} [{}]{installed}",
+                        pkg.name, pkg.version, pkg.repository
+                    );
+                }
+            }
+            ShellCommand::List(kind) => {
+                let installed = self
+                    .installed
+                    .iter()
+                    .filter(|t| kind.is_none() || t.kind == kind);
+                for pkg in installed {
+                    println!("  {} {}", pkg.name, pkg.version);
+                }
+            }
+            ShellCommand::Stage(action, names) => {
+                for name in names {
+                    if let Err(e) = self.stage(action, name) {
+                        logger::warning!("{e}");
+                    }
+                }
+            }
+            ShellCommand::Unstage(names) => {
+                for name in names {
+                    if !self.unstage(name) {
+                        logger::warning!("{name} is not staged.");
+                    }
+                }
+            }
+            ShellCommand::Plan => self.print_plan(),
+            ShellCommand::Commit => {
+                if self.staged.is_empty() {
+                    println!("Nothing is staged.");
+                    return Ok(());
+                }
+
+                let result = self.commit(lpm);
+                // Whatever was applied before a failure is reflected too.
+                self.load(lpm)?;
+
+                let changes = result?;
+                println!(
+                    "{} installed, {} updated, {} deleted.",
+                    changes.installed.len(),
+                    changes.updated.len(),
+                    changes.deleted.len()
+                );
+            }
+            ShellCommand::Clear => self.staged.clear(),
+            ShellCommand::Refresh => self.load(lpm)?,
+            ShellCommand::Help => println!("{HELP}"),
+            ShellCommand::Exit | ShellCommand::Empty => {}
+        }
+
+        Ok(())
+    }
+}
+
+pub fn run_shell(ctx: Ctx) -> Result<(), LpmError<MainError>> {
+    let mut lpm = Lpm::from_ctx(ctx);
+    let mut session = Session::default();
+    session.load(&lpm)?;
+
+    println!("Type 'help' for the list of commands.");
+
+    let stdin = io::stdin();
+    let mut line = String::new();
+    loop {
+        if !session.staged.is_empty() {
+            print!("[{} staged] ", session.staged.len());
+        }
+        print!("lpm> ");
+        io::stdout().flush()?;
+
+        line.clear();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            println!();
+            break;
+        }
+
+        match ShellCommand::parse(&line) {
+            Ok(ShellCommand::Exit) => break,
+            Ok(command) => {
+                if let Err(e) = session.run(&mut lpm, command) {
+                    logger::error!("{:?}", e);
+                }
+            }
+            Err(e) => logger::warning!("{e}"),
+        }
+    }
+
+    if !session.staged.is_empty() {
+        logger::warning!("Leaving without committing the staged transaction.");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_shell_command() {
+        assert_eq!(
+            ShellCommand::parse("add htop  nano\n"),
+            Ok(ShellCommand::Stage(
+                StagedAction::Install,
+                vec!["htop", "nano"]
+            ))
+        );
+        assert_eq!(
+            ShellCommand::parse("search"),
+            Ok(ShellCommand::Search("", None))
+        );
+        assert_eq!(
+            ShellCommand::parse("search --kind kernel linux"),
+            Ok(ShellCommand::Search("linux", Some(PackageKind::Kernel)))
+        );
+        assert_eq!(
+            ShellCommand::parse("list --kind library"),
+            Ok(ShellCommand::List(Some(PackageKind::Library)))
+        );
+        assert!(ShellCommand::parse("list --kind game").is_err());
+        assert!(ShellCommand::parse("add htop --kind application").is_err());
+        assert_eq!(ShellCommand::parse("  \n"), Ok(ShellCommand::Empty));
+        assert!(ShellCommand::parse("remove").is_err());
+        assert!(ShellCommand::parse("install htop").is_err());
+    }
+
+    #[test]
+    fn test_stage() {
+        let mut session = Session {
+            installed: vec![PackageInfo {
+                name: String::from("htop"),
+                version: String::from("3.2.1"),
+                arch: String::from("amd64"),
+                slot: None,
+                kind: None,
+                installed_size: 0,
+                is_dependency: false,
+            }],
+            available: ["htop", "nano"]
+                .iter()
+                .map(|name| AvailablePackage {
+                    name: name.to_string(),
+                    version: String::from("1.0"),
+                    repository: String::from("main"),
+                    archive_size: None,
+                    installed_size: None,
+                    kind: None,
+                })
+                .collect(),
+            staged: Vec::new(),
+        };
+
+        assert!(session.stage(StagedAction::Install, "htop").is_err());
+        assert!(session.stage(StagedAction::Install, "vim").is_err());
+        assert!(session.stage(StagedAction::Delete, "nano").is_err());
+
+        session.stage(StagedAction::Install, "nano").unwrap();
+        session.stage(StagedAction::Update, "htop").unwrap();
+        // Staging again replaces the previous action.
+        session.stage(StagedAction::Delete, "htop").unwrap();
+
+        assert_eq!(session.staged(StagedAction::Install), vec!["nano"]);
+        assert!(session.staged(StagedAction::Update).is_empty());
+        assert_eq!(session.staged(StagedAction::Delete), vec!["htop"]);
+
+        assert!(session.unstage("nano"));
+        assert!(!session.unstage("nano"));
+    }
+}
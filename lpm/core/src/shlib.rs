@@ -0,0 +1,191 @@
+use common::{glob, pkg_path::PkgPath, Files};
+use ehandle::{lpm::LpmError, pkg::PackageErrorKind, ErrorCommons, MainError};
+use elf::Elf;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Searched after the directories of `ld.so.conf`, like the dynamic linker does.
+const DEFAULT_LIBRARY_DIRS: [&str; 4] = ["lib", "lib64", "usr/lib", "usr/lib64"];
+
+/// Nesting limit for the `include` lines of `ld.so.conf`.
+const MAX_INCLUDE_DEPTH: u8 = 8;
+
+/// Warns about the `DT_NEEDED` libraries of the installed ELF files that can't
+/// be found under `root`, or fails if `strict` is set.
+pub(crate) fn check_shared_libraries(
+    root: &Path,
+    files: &[&Files],
+    strict: bool,
+) -> Result<(), LpmError<MainError>> {
+    let library_dirs = library_dirs(root);
+
+    let mut missing = Vec::new();
+    for files in files {
+        missing.extend(find_missing_libraries(root, files, &library_dirs)?);
+    }
+
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    if strict {
+        return Err(PackageErrorKind::MissingSharedLibraries(missing).to_lpm_err())?;
+    }
+
+    for item in missing {
+        logger::warning!("Shared library could not be found: {item}");
+    }
+
+    Ok(())
+}
+
+fn find_missing_libraries(
+    root: &Path,
+    files: &Files,
+    library_dirs: &[PathBuf],
+) -> io::Result<Vec<String>> {
+    let mut missing = Vec::new();
+    for file in &files.0 {
+        let pkg_path = file.pkg_path()?;
+        let path = pkg_path.under(root);
+        match fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.is_file() => {}
+            Ok(_) => continue,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        }
+
+        let elf = match Elf::read(&path) {
+            Ok(Some(elf)) => elf,
+            Ok(None) => continue,
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                logger::debug!("Skipping '{}': {e}", path.display());
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+
+        let origin = path.parent().unwrap_or(root);
+        // `$ORIGIN/../lib` is common, it's left for the filesystem to resolve.
+        let search_dirs: Vec<PathBuf> = elf
+            .runpath
+            .iter()
+            .filter_map(|dir| match dir.strip_prefix("$ORIGIN") {
+                Some(rest) => Some(origin.join(rest.trim_start_matches('/'))),
+                None => PkgPath::new(dir).ok().map(|t| t.under(root)),
+            })
+            .chain(library_dirs.iter().cloned())
+            .collect();
+
+        for library in &elf.needed {
+            if !is_resolvable(root, library, &search_dirs) {
+                missing.push(format!("'{library}' needed by '{pkg_path}'"));
+            }
+        }
+    }
+
+    Ok(missing)
+}
+
+fn is_resolvable(root: &Path, library: &str, search_dirs: &[PathBuf]) -> bool {
+    if library.contains('/') {
+        return PkgPath::new(library).map_or(false, |t| t.under(root).exists());
+    }
+
+    search_dirs.iter().any(|dir| dir.join(library).exists())
+}
+
+/// Library directories of the system under `root`.
+fn library_dirs(root: &Path) -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    read_ld_so_conf(root, Path::new("/etc/ld.so.conf"), &mut dirs, 0);
+
+    dirs.extend(DEFAULT_LIBRARY_DIRS.iter().map(|dir| root.join(dir)));
+    dirs
+}
+
+fn read_ld_so_conf(root: &Path, conf: &Path, dirs: &mut Vec<PathBuf>, depth: u8) {
+    let root_relative = |path: &Path| root.join(path.strip_prefix("/").unwrap_or(path));
+
+    // Missing or unreadable configuration only means fewer places to look at.
+    let Ok(content) = fs::read_to_string(root_relative(conf)) else {
+        return;
+    };
+
+    for line in content.lines() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(pattern) = line.strip_prefix("include ") {
+            if depth >= MAX_INCLUDE_DEPTH {
+                continue;
+            }
+
+            let pattern = conf.parent().unwrap_or(Path::new("/")).join(pattern.trim());
+            let (Some(dir), Some(file_pattern)) =
+                (pattern.parent(), pattern.file_name().and_then(|t| t.to_str()))
+            else {
+                continue;
+            };
+
+            let Ok(entries) = fs::read_dir(root_relative(dir)) else {
+                continue;
+            };
+
+            let mut includes: Vec<PathBuf> = entries
+                .filter_map(Result::ok)
+                .filter(|entry| {
+                    entry
+                        .file_name()
+                        .to_str()
+                        .map_or(false, |name| glob::matches(file_pattern, name))
+                })
+                .map(|entry| dir.join(entry.file_name()))
+                .collect();
+            includes.sort();
+
+            for include in includes {
+                read_ld_so_conf(root, &include, dirs, depth + 1);
+            }
+        } else {
+            dirs.push(root_relative(Path::new(line)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::testing::TestDir;
+
+    #[test]
+    fn test_library_dirs_and_resolution() {
+        let root = TestDir::new("shlib");
+        fs::create_dir_all(root.join("etc/ld.so.conf.d")).unwrap();
+        fs::create_dir_all(root.join("opt/foo/lib")).unwrap();
+        fs::create_dir_all(root.join("usr/lib")).unwrap();
+
+        fs::write(
+            root.join("etc/ld.so.conf"),
+            "# comment\ninclude /etc/ld.so.conf.d/*.conf\n",
+        )
+        .unwrap();
+        fs::write(root.join("etc/ld.so.conf.d/foo.conf"), "/opt/foo/lib\n").unwrap();
+        fs::write(root.join("etc/ld.so.conf.d/ignored.txt"), "/opt/bar/lib\n").unwrap();
+        fs::write(root.join("opt/foo/lib/libfoo.so.1"), "").unwrap();
+        fs::write(root.join("usr/lib/libc.so.6"), "").unwrap();
+
+        let dirs = library_dirs(&root);
+        assert_eq!(dirs[0], root.join("opt/foo/lib"));
+        assert!(!dirs.contains(&root.join("opt/bar/lib")));
+
+        assert!(is_resolvable(&root, "libfoo.so.1", &dirs));
+        assert!(is_resolvable(&root, "libc.so.6", &dirs));
+        assert!(is_resolvable(&root, "/usr/lib/libc.so.6", &[]));
+        assert!(!is_resolvable(&root, "libbar.so.2", &dirs));
+    }
+}
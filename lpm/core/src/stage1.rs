@@ -1,177 +1,523 @@
-use common::pkg::{ScriptPhase, Stage1Script};
+use crate::{
+    chroot::ChrootMounts,
+    stage1_lua::{self, EMBEDDED_INTERPRETER},
+    Ctx,
+};
+
+use common::{
+    event::EventSink,
+    meta::{Meta, ScriptStruct},
+    pkg::{CustomPhase, PhaseOrder, PkgDataFromDb, ScriptPhase, Stage1Script, PHASES_MANIFEST},
+};
+use db::pkg::DbOpsForInstalledPkg;
 use ehandle::{lpm::LpmError, pkg::PackageErrorKind, ErrorCommons, MainError};
+use hash::sha256;
 use std::{
-    fs::File,
-    io::{self, Read},
-    path::Path,
-    process::Command,
+    ffi::OsStr,
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
 };
 
-pub const PKG_SCRIPTS_DIR: &str = "/var/lib/lpm/pkg";
+pub const PKG_SCRIPTS_DIR: &str = db::PKG_DATA_DIR;
+
+/// Phases whose scripts couldn't run when the package was installed, one per
+/// line, next to the `scripts` directory of the package.
+const PENDING_SCRIPTS_FILE: &str = "pending_scripts";
+
+/// Directory of the scripts and `meta.json` of the package of `meta` in
+/// `root`, see `db::pkg_data_dir_name`.
+pub(crate) fn pkg_lib_dir(root: &Path, meta: &Meta) -> PathBuf {
+    root.join(PKG_SCRIPTS_DIR.trim_start_matches('/'))
+        .join(db::pkg_data_dir_name(&meta.name, meta.slot.as_deref()))
+}
 
 pub(crate) trait Stage1Tasks {
+    /// Scripts are chrooted into `root` unless it is `/`, the `lpm-lua` ones
+    /// run in lpm on the files under `root`.
     fn execute_script(
         &self,
-        envs: Vec<(&str, &str)>,
+        root: &Path,
+        envs: Vec<(&str, &OsStr)>,
         caller_phase: ScriptPhase,
+        pkg_name: &str,
+        events: &dyn EventSink,
     ) -> Result<(), LpmError<MainError>>;
 }
 
 impl Stage1Tasks for Vec<Stage1Script> {
-    #[allow(unused_variables)]
+    /// Runs the script of `caller_phase` along with the ones of the custom
+    /// phases anchored to it, the `before` ones first and the `after` ones
+    /// last.
     fn execute_script(
         &self,
-        envs: Vec<(&str, &str)>,
+        root: &Path,
+        envs: Vec<(&str, &OsStr)>,
         caller_phase: ScriptPhase,
+        pkg_name: &str,
+        events: &dyn EventSink,
     ) -> Result<(), LpmError<MainError>> {
-        fn prepare_script(script: &Stage1Script) -> String {
-            format!(
-                r#"
-                set -e
-
-                {}
-                "#,
-                &script.contents
-            )
-        }
+        let anchored = |order: Option<PhaseOrder>| {
+            self.iter().filter(move |t| {
+                t.phase == caller_phase && t.custom_phase.as_ref().map(|t| t.order) == order
+            })
+        };
 
-        if let Some(script) = self.iter().find(|s| s.phase == caller_phase) {
-            let cmd = Command::new("bash");
-            let output = Command::new("bash")
-                .arg("-c")
-                .arg(prepare_script(script))
-                .envs(envs)
-                .output()?;
-
-            if !output.status.success() {
-                return Err(PackageErrorKind::FailedExecutingStage1Script {
-                    script_name: script.path.to_string_lossy().to_string(),
-                    output: String::from_utf8_lossy(&output.stderr).to_string(),
-                }
-                .to_lpm_err())?;
+        for script in anchored(Some(PhaseOrder::Before))
+            .chain(anchored(None).take(1))
+            .chain(anchored(Some(PhaseOrder::After)))
+        {
+            match &script.custom_phase {
+                Some(custom_phase) => events.custom_script_started(pkg_name, &custom_phase.name),
+                None => events.script_started(pkg_name, caller_phase),
             }
 
-            println!("{}", String::from_utf8_lossy(output.stdout.as_slice()));
+            run_script(script, root, envs.clone(), pkg_name)?;
         }
 
         Ok(())
     }
 }
 
-pub fn get_scripts(scripts_dir: &Path) -> Result<Vec<Stage1Script>, LpmError<io::Error>> {
-    let mut scripts = vec![];
+/// Interpreter of the scripts without a shebang.
+const DEFAULT_INTERPRETER: &str = "bash";
+/// Shebangs that `DEFAULT_INTERPRETER` runs as well.
+const SHELLS: &[&str] = &["bash", "sh"];
 
-    {
-        let path = scripts_dir.join("pre_install");
-        if let Ok(mut file) = File::open(&path) {
-            let mut contents = String::new();
-            file.read_to_string(&mut contents)?;
+/// Interpreter that the first line of a script asks for.
+#[derive(Debug, PartialEq)]
+struct Shebang<'a> {
+    /// `python3` for both `#!/usr/bin/python3` and `#!/usr/bin/env python3`.
+    name: &'a str,
+    /// Program and its arguments.
+    command: Vec<&'a str>,
+}
 
-            scripts.push(Stage1Script {
-                contents,
-                path,
-                phase: ScriptPhase::PreInstall,
-            });
+fn parse_shebang(contents: &str) -> Option<Shebang<'_>> {
+    let command: Vec<&str> = contents
+        .lines()
+        .next()?
+        .strip_prefix("#!")?
+        .split_whitespace()
+        .collect();
+
+    let name = command.first()?.rsplit('/').next()?;
+    let name = if name == "env" {
+        command[1..].iter().find(|t| !t.starts_with('-'))?
+    } else {
+        name
+    };
+
+    Some(Shebang { name, command })
+}
+
+/// Fails if a script of `pkg_name` needs an interpreter that isn't in
+/// `allowed`, so that none of them run on systems that might lack it. The
+/// embedded one is always there.
+pub(crate) fn check_interpreters(
+    pkg_name: &str,
+    scripts: &[Stage1Script],
+    allowed: &[String],
+) -> Result<(), LpmError<MainError>> {
+    for script in scripts {
+        let interpreter = parse_shebang(&script.contents).map_or(DEFAULT_INTERPRETER, |t| t.name);
+        if interpreter != EMBEDDED_INTERPRETER && !allowed.iter().any(|t| t == interpreter) {
+            return Err(PackageErrorKind::InterpreterNotAllowed {
+                package: pkg_name.to_owned(),
+                interpreter: interpreter.to_owned(),
+            }
+            .to_lpm_err())?;
         }
     }
 
-    {
-        let path = scripts_dir.join("post_install");
-        if let Ok(mut file) = File::open(&path) {
-            let mut contents = String::new();
-            file.read_to_string(&mut contents)?;
+    Ok(())
+}
 
-            scripts.push(Stage1Script {
-                contents,
-                path,
-                phase: ScriptPhase::PostInstall,
-            });
+/// Whether `script` is what its meta data was built with. Scripts are read
+/// once and run from memory, so checking them here covers whatever happened
+/// to the files after the package was validated.
+fn has_recorded_checksum(script: &Stage1Script) -> bool {
+    script.checksum.as_ref().map_or(true, |checksum| {
+        *checksum == hash::digest_to_hex_string(&sha256::digest(script.contents.as_bytes()))
+    })
+}
+
+fn run_script(
+    script: &Stage1Script,
+    root: &Path,
+    envs: Vec<(&str, &OsStr)>,
+    pkg_name: &str,
+) -> Result<(), LpmError<MainError>> {
+    if !has_recorded_checksum(script) {
+        return Err(PackageErrorKind::ScriptChecksumMismatch {
+            package: pkg_name.to_owned(),
+            script_name: script.path.to_string_lossy().to_string(),
         }
+        .to_lpm_err())?;
+    }
+
+    // Runs in this process, on the files of the root rather than in a chroot.
+    if parse_shebang(&script.contents).map_or(false, |t| t.name == EMBEDDED_INTERPRETER) {
+        let output = stage1_lua::run(script, root, &envs, pkg_name).map_err(|e| {
+            PackageErrorKind::FailedExecutingStage1Script {
+                script_name: script.path.to_string_lossy().to_string(),
+                output: e.to_string(),
+            }
+            .to_lpm_err()
+        })?;
+        println!("{output}");
+
+        return Ok(());
     }
 
-    {
-        let path = scripts_dir.join("pre_delete");
-        if let Ok(mut file) = File::open(&path) {
-            let mut contents = String::new();
-            file.read_to_string(&mut contents)?;
+    fn prepare_script(script: &Stage1Script) -> String {
+        format!(
+            r#"
+            set -e
 
-            scripts.push(Stage1Script {
-                contents,
-                path,
-                phase: ScriptPhase::PreDelete,
-            });
-        }
+            {}
+            "#,
+            &script.contents
+        )
     }
 
-    {
-        let path = scripts_dir.join("post_delete");
-        if let Ok(mut file) = File::open(&path) {
-            let mut contents = String::new();
-            file.read_to_string(&mut contents)?;
+    let chroot_mounts = if root == Path::new("/") {
+        None
+    } else {
+        Some(ChrootMounts::new(root)?)
+    };
 
-            scripts.push(Stage1Script {
-                contents,
-                path,
-                phase: ScriptPhase::PostDelete,
-            });
+    // Shell scripts run through bash with `set -e`, the others get their
+    // content on stdin.
+    let interpreter = parse_shebang(&script.contents).filter(|t| !SHELLS.contains(&t.name));
+    let (program, args) = match &interpreter {
+        Some(shebang) => {
+            let mut args: Vec<String> =
+                shebang.command[1..].iter().map(|t| t.to_string()).collect();
+            args.push(String::from("-"));
+            (shebang.command[0], args)
         }
-    }
+        None => (
+            DEFAULT_INTERPRETER,
+            vec![String::from("-c"), prepare_script(script)],
+        ),
+    };
 
-    {
-        let path = scripts_dir.join("pre_downgrade");
-        if let Ok(mut file) = File::open(&path) {
-            let mut contents = String::new();
-            file.read_to_string(&mut contents)?;
+    let mut command = match chroot_mounts {
+        None => Command::new(program),
+        // The interpreter of the root, which is `/` for the script.
+        Some(_) => {
+            let mut command = Command::new("chroot");
+            command.arg(root).arg(program);
+            command
+        }
+    };
+    command.args(args).envs(envs);
+    if chroot_mounts.is_some() {
+        command.env("LPM_ROOT", "/");
+    }
+    let output = match interpreter {
+        None => command.output()?,
+        Some(_) => {
+            let mut child = command
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()?;
+            // Dropped right after, so the interpreter sees the end of it.
+            child
+                .stdin
+                .take()
+                .unwrap()
+                .write_all(script.contents.as_bytes())?;
+            child.wait_with_output()?
+        }
+    };
+    drop(chroot_mounts);
 
-            scripts.push(Stage1Script {
-                contents,
-                path,
-                phase: ScriptPhase::PreDowngrade,
-            });
+    if !output.status.success() {
+        return Err(PackageErrorKind::FailedExecutingStage1Script {
+            script_name: script.path.to_string_lossy().to_string(),
+            output: String::from_utf8_lossy(&output.stderr).to_string(),
         }
+        .to_lpm_err())?;
+    }
+
+    println!("{}", String::from_utf8_lossy(output.stdout.as_slice()));
+
+    Ok(())
+}
+
+fn read_script(path: &Path) -> Result<Option<String>, LpmError<io::Error>> {
+    let Ok(mut file) = File::open(path) else {
+        return Ok(None);
+    };
+
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)?;
+
+    Ok(Some(contents))
+}
+
+/// Scripts of the built in phases in `scripts_dir`, and of the custom ones
+/// that its `PHASES_MANIFEST` declares, with the `checksums` of the meta data.
+///
+/// Unless `checksums` is `None` for a package that predates them, every
+/// script must have one and every checksum must have its script.
+pub fn get_scripts(
+    scripts_dir: &Path,
+    checksums: Option<&[ScriptStruct]>,
+) -> Result<Vec<Stage1Script>, LpmError<io::Error>> {
+    let mut scripts = read_scripts(scripts_dir)?;
+    let Some(checksums) = checksums else {
+        return Ok(scripts);
+    };
+
+    let invalid = |reason: String| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("'{}': {reason}", scripts_dir.display()),
+        )
+    };
+    for script in &mut scripts {
+        let name = script.path.file_name().unwrap().to_string_lossy();
+        let Some(recorded) = checksums.iter().find(|t| t.name == name) else {
+            return Err(invalid(format!(
+                "script '{name}' has no checksum in the meta data"
+            )))?;
+        };
+        script.checksum = Some(recorded.checksum.clone());
     }
 
-    {
-        let path = scripts_dir.join("post_downgrade");
-        if let Ok(mut file) = File::open(&path) {
-            let mut contents = String::new();
-            file.read_to_string(&mut contents)?;
+    if let Some(missing) = checksums.iter().find(|t| {
+        !scripts
+            .iter()
+            .any(|script| script.path.file_name().unwrap().to_string_lossy() == t.name)
+    }) {
+        return Err(invalid(format!("script '{}' is missing", missing.name)))?;
+    }
 
+    Ok(scripts)
+}
+
+/// Scripts of an installed package, with the checksums that the database
+/// recorded when it was installed. The `meta.json` kept next to the scripts
+/// isn't used, it's no more trustworthy than the scripts themselves.
+pub(crate) fn get_installed_scripts(meta: &Meta) -> Result<Vec<Stage1Script>, LpmError<io::Error>> {
+    let scripts_dir = pkg_lib_dir(Path::new("/"), meta).join("scripts");
+    get_scripts(&scripts_dir, meta.scripts.as_deref())
+}
+
+fn read_scripts(scripts_dir: &Path) -> Result<Vec<Stage1Script>, LpmError<io::Error>> {
+    let mut scripts = vec![];
+
+    for phase in ScriptPhase::ALL {
+        let path = scripts_dir.join(phase.as_str());
+        if let Some(contents) = read_script(&path)? {
             scripts.push(Stage1Script {
                 contents,
                 path,
-                phase: ScriptPhase::PostDowngrade,
+                phase,
+                custom_phase: None,
+                checksum: None,
             });
         }
     }
 
-    {
-        let path = scripts_dir.join("pre_upgrade");
-        if let Ok(mut file) = File::open(&path) {
-            let mut contents = String::new();
-            file.read_to_string(&mut contents)?;
+    let manifest_path = scripts_dir.join(PHASES_MANIFEST);
+    let manifest = match fs::read_to_string(&manifest_path) {
+        Ok(manifest) => manifest,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(scripts),
+        Err(e) => return Err(e)?,
+    };
 
-            scripts.push(Stage1Script {
-                contents,
-                path,
-                phase: ScriptPhase::PreUpgrade,
-            });
+    let invalid = |reason: String| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("'{}': {reason}", manifest_path.display()),
+        )
+    };
+    for custom_phase in CustomPhase::parse_manifest(&manifest).map_err(invalid)? {
+        let path = scripts_dir.join(&custom_phase.name);
+        let Some(contents) = read_script(&path)? else {
+            return Err(invalid(format!(
+                "script of phase '{}' is missing",
+                custom_phase.name
+            )))?;
+        };
+
+        scripts.push(Stage1Script {
+            contents,
+            path,
+            phase: custom_phase.anchor,
+            custom_phase: Some(custom_phase),
+            checksum: None,
+        });
+    }
+
+    Ok(scripts)
+}
+
+/// Records that the install scripts of the package of `meta` have to run on
+/// the system that `root` becomes, for packages whose architecture can't be
+/// emulated.
+pub(crate) fn defer_install_scripts(root: &Path, meta: &Meta) -> io::Result<()> {
+    let phases = [ScriptPhase::PreInstall, ScriptPhase::PostInstall];
+    write_pending_phases(&pkg_lib_dir(root, meta), &phases)
+}
+
+/// Removes the record once no phase is left.
+fn write_pending_phases(pkg_dir: &Path, phases: &[ScriptPhase]) -> io::Result<()> {
+    let path = pkg_dir.join(PENDING_SCRIPTS_FILE);
+    if phases.is_empty() {
+        return fs::remove_file(path);
+    }
+
+    let lines: Vec<&str> = phases.iter().map(ScriptPhase::as_str).collect();
+    fs::write(path, format!("{}\n", lines.join("\n")))
+}
+
+fn read_pending_phases(path: &Path) -> io::Result<Vec<ScriptPhase>> {
+    fs::read_to_string(path)?
+        .lines()
+        .filter(|t| !t.trim().is_empty())
+        .map(|t| {
+            ScriptPhase::parse(t.trim()).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("'{}': unknown phase '{t}'", path.display()),
+                )
+            })
+        })
+        .collect()
+}
+
+/// Runs the scripts that were deferred while installing into a root of a
+/// foreign architecture, once the system of that root is running. The
+/// extracted packages are gone by then, so `PKG_ROOT` is not set.
+pub fn run_pending_scripts(ctx: &Ctx) -> Result<(), LpmError<MainError>> {
+    let mut pkg_dirs = vec![];
+    for entry in fs::read_dir(PKG_SCRIPTS_DIR)? {
+        let path = entry?.path();
+        if path.join(PENDING_SCRIPTS_FILE).exists() {
+            pkg_dirs.push(path);
         }
     }
+    pkg_dirs.sort();
 
-    {
-        let path = scripts_dir.join("post_upgrade");
-        if let Ok(mut file) = File::open(&path) {
-            let mut contents = String::new();
-            file.read_to_string(&mut contents)?;
+    if pkg_dirs.is_empty() {
+        logger::info!("No pending scripts.");
+        return Ok(());
+    }
 
-            scripts.push(Stage1Script {
-                contents,
-                path,
-                phase: ScriptPhase::PostUpgrade,
-            });
+    for pkg_dir in pkg_dirs {
+        // `name@slot` is loaded as `name/slot`, see `pkg_lib_dir`.
+        let dir_name = pkg_dir.file_name().unwrap().to_string_lossy();
+        let mut phases = read_pending_phases(&pkg_dir.join(PENDING_SCRIPTS_FILE))?;
+        let pkg = PkgDataFromDb::load(&ctx.core_db, &dir_name.replacen('@', "/", 1))?;
+        let pkg_name = pkg.meta_fields.meta.name.clone();
+        let scripts = get_installed_scripts(&pkg.meta_fields.meta)?;
+
+        // Recorded after each phase, so a failing one is where a rerun starts.
+        while let Some(&phase) = phases.first() {
+            scripts.execute_script(
+                Path::new("/"),
+                vec![("LPM_ROOT", OsStr::new("/"))],
+                phase,
+                &pkg_name,
+                ctx.events.as_ref(),
+            )?;
+            phases.remove(0);
+            write_pending_phases(&pkg_dir, &phases)?;
         }
     }
 
-    Ok(scripts)
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::testing::TestDir;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_parse_shebang() {
+        assert_eq!(
+            parse_shebang("#!/usr/bin/env -S python3 -u\nprint(1)"),
+            Some(Shebang {
+                name: "python3",
+                command: vec!["/usr/bin/env", "-S", "python3", "-u"],
+            })
+        );
+        assert_eq!(parse_shebang("#!/bin/sh -e\n").map(|t| t.name), Some("sh"));
+        assert_eq!(parse_shebang("echo installed"), None);
+
+        let script = |contents: &str| Stage1Script {
+            contents: contents.to_owned(),
+            path: PathBuf::from("post_install"),
+            phase: ScriptPhase::PostInstall,
+            custom_phase: None,
+            checksum: None,
+        };
+        let allowed = [String::from("bash"), String::from("sh")];
+        let scripts = [
+            script("echo installed"),
+            script("#!/bin/sh\necho installed"),
+        ];
+        assert!(check_interpreters("hello", &scripts, &allowed).is_ok());
+
+        let scripts = [script("#!lpm-lua\nprint(pkg.name)")];
+        assert!(check_interpreters("hello", &scripts, &[]).is_ok());
+
+        let scripts = [script("#!/usr/bin/perl\nprint 1;")];
+        assert!(check_interpreters("hello", &scripts, &allowed).is_err());
+    }
+
+    #[test]
+    fn test_script_checksums() {
+        let dir = TestDir::new("stage1");
+        fs::write(dir.join("post_install"), "echo installed").unwrap();
+
+        let checksum = |name: &str, content: &str| ScriptStruct {
+            name: name.to_owned(),
+            checksum: hash::digest_to_hex_string(&sha256::digest(content.as_bytes())),
+        };
+
+        // Packages that predate checksums run their scripts unchecked.
+        let scripts = get_scripts(&dir, None).unwrap();
+        assert!(has_recorded_checksum(&scripts[0]));
+
+        // The others can't have scripts without checksums.
+        let error = get_scripts(&dir, Some(&[])).err().unwrap();
+        assert!(error
+            .error_type
+            .to_string()
+            .contains("'post_install' has no checksum"));
+
+        let mut scripts =
+            get_scripts(&dir, Some(&[checksum("post_install", "echo installed")])).unwrap();
+        assert!(has_recorded_checksum(&scripts[0]));
+        scripts[0].contents.push_str("; rm -rf /");
+        assert!(!has_recorded_checksum(&scripts[0]));
+
+        let error = get_scripts(&dir, Some(&[checksum("pre_install", "")]))
+            .err()
+            .unwrap();
+        assert!(error
+            .error_type
+            .to_string()
+            .contains("'post_install' has no checksum"));
+
+        let checksums = [
+            checksum("post_install", "echo installed"),
+            checksum("pre_delete", "echo deleting"),
+        ];
+        let error = get_scripts(&dir, Some(&checksums)).err().unwrap();
+        assert!(error
+            .error_type
+            .to_string()
+            .contains("'pre_delete' is missing"));
+    }
 }
@@ -0,0 +1,308 @@
+//! Runs the package scripts that start with `#!lpm-lua` inside lpm, so they
+//! need neither a shell nor a Lua installation on the root.
+//!
+//! Besides the standard library of the `lua` crate, scripts see:
+//!
+//! - `pkg`: `name`, `phase`, `root` and `env`, the variables that the other
+//!   scripts get from the environment.
+//! - `fs`: `read`, `write`, `append`, `exists`, `mkdir`, `remove`, `rename`,
+//!   `symlink`, `chmod` and `list`, with paths inside the root.
+
+use common::pkg::Stage1Script;
+use lua::{Args, Error, Lua, Table, Value};
+use std::{
+    ffi::{OsStr, OsString},
+    fs::{self, OpenOptions},
+    io::Write,
+    os::unix::fs::{symlink, PermissionsExt},
+    path::{Component, Path, PathBuf},
+    rc::Rc,
+};
+
+/// Shebang name of the scripts that run here.
+pub(crate) const EMBEDDED_INTERPRETER: &str = "lpm-lua";
+
+/// Runs `script`, returns what it printed.
+pub(crate) fn run(
+    script: &Stage1Script,
+    root: &Path,
+    envs: &[(&str, &OsStr)],
+    pkg_name: &str,
+) -> Result<String, Error> {
+    let mut lua = Lua::new();
+
+    let env = Table::new();
+    for (key, value) in envs {
+        env.set(*key, value.to_string_lossy().to_string())?;
+    }
+    let pkg = Table::new();
+    pkg.set("name", pkg_name)?;
+    pkg.set("phase", script.phase.as_str())?;
+    pkg.set("root", root.to_string_lossy().to_string())?;
+    pkg.set("env", env)?;
+    lua.set_global("pkg", pkg);
+    lua.set_global("fs", fs_table(Rc::new(root.to_owned()))?);
+
+    lua.exec(&script.contents)?;
+
+    Ok(lua.take_output())
+}
+
+/// Symlinks followed while resolving a single path, like `MAXSYMLINKS`.
+const MAX_SYMLINK_HOPS: usize = 40;
+
+/// `path` of the script as a path on the host, `..` is rejected so that
+/// nothing outside of `root` can be reached. Symlinks on the way are followed
+/// as if `root` was `/`, like `RESOLVE_IN_ROOT` does, so neither absolute
+/// targets nor `..` in them escape it. The last component is only followed
+/// with `follow_last`.
+fn resolve(root: &Path, path: &str, follow_last: bool) -> Result<PathBuf, Error> {
+    let mut pending = Vec::new();
+    for component in Path::new(path).components() {
+        match component {
+            Component::Normal(name) => pending.push(name.to_owned()),
+            Component::RootDir | Component::CurDir => {}
+            Component::ParentDir | Component::Prefix(_) => {
+                return Err(Error::new(format!("'{path}' must not contain '..'")));
+            }
+        }
+    }
+    pending.reverse();
+
+    let mut resolved = root.to_owned();
+    let mut hops = 0;
+    while let Some(name) = pending.pop() {
+        if name == ".." {
+            if resolved != root {
+                resolved.pop();
+            }
+            continue;
+        }
+
+        let candidate = resolved.join(&name);
+        let is_symlink = candidate
+            .symlink_metadata()
+            .map_or(false, |t| t.file_type().is_symlink());
+        if !is_symlink || (pending.is_empty() && !follow_last) {
+            resolved = candidate;
+            continue;
+        }
+
+        hops += 1;
+        if hops > MAX_SYMLINK_HOPS {
+            return Err(Error::new(format!(
+                "'{path}': too many levels of symbolic links"
+            )));
+        }
+        let target = fs::read_link(&candidate).map_err(|e| io_error(path, e))?;
+        if target.is_absolute() {
+            resolved = root.to_owned();
+        }
+        for component in target.components().rev() {
+            match component {
+                Component::Normal(name) => pending.push(name.to_owned()),
+                Component::ParentDir => pending.push(OsString::from("..")),
+                Component::RootDir | Component::CurDir | Component::Prefix(_) => {}
+            }
+        }
+    }
+
+    Ok(resolved)
+}
+
+/// Modes are octal strings like "755" or numbers, without the file type bits.
+fn parse_mode(value: &Value) -> Result<u32, Error> {
+    let mode = match value {
+        Value::Str(mode) => u32::from_str_radix(mode, 8).ok(),
+        _ => value
+            .to_number()
+            .filter(|t| t.fract() == 0.0 && *t >= 0.0)
+            .map(|t| t as u32),
+    };
+
+    mode.filter(|t| *t <= 0o7777).ok_or_else(|| {
+        Error::new(format!(
+            "invalid mode '{}'",
+            value.to_str().as_deref().unwrap_or(value.type_name())
+        ))
+    })
+}
+
+fn io_error(path: &str, error: std::io::Error) -> Error {
+    Error::new(format!("{path}: {error}"))
+}
+
+fn fs_table(root: Rc<PathBuf>) -> Result<Table, Error> {
+    type FsFunction = fn(&Path, &Args) -> Result<Vec<Value>, Error>;
+
+    let functions: [(&str, FsFunction); 10] = [
+        ("read", |root, args| {
+            let path = args.string(0)?;
+            let contents =
+                fs::read_to_string(resolve(root, &path, true)?).map_err(|e| io_error(&path, e))?;
+            Ok(vec![contents.into()])
+        }),
+        ("write", |root, args| {
+            let path = args.string(0)?;
+            fs::write(resolve(root, &path, true)?, args.string(1)?.as_bytes())
+                .map_err(|e| io_error(&path, e))?;
+            Ok(Vec::new())
+        }),
+        ("append", |root, args| {
+            let (path, contents) = (args.string(0)?, args.string(1)?);
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(resolve(root, &path, true)?)
+                .and_then(|mut file| file.write_all(contents.as_bytes()))
+                .map_err(|e| io_error(&path, e))?;
+            Ok(Vec::new())
+        }),
+        ("exists", |root, args| {
+            let path = resolve(root, &args.string(0)?, false)?;
+            Ok(vec![path.symlink_metadata().is_ok().into()])
+        }),
+        ("mkdir", |root, args| {
+            let path = args.string(0)?;
+            fs::create_dir_all(resolve(root, &path, true)?).map_err(|e| io_error(&path, e))?;
+            Ok(Vec::new())
+        }),
+        ("remove", |root, args| {
+            let path = args.string(0)?;
+            let resolved = resolve(root, &path, false)?;
+            match resolved.symlink_metadata() {
+                Ok(metadata) if metadata.is_dir() => fs::remove_dir(resolved),
+                _ => fs::remove_file(resolved),
+            }
+            .map_err(|e| io_error(&path, e))?;
+            Ok(Vec::new())
+        }),
+        ("rename", |root, args| {
+            let (from, to) = (args.string(0)?, args.string(1)?);
+            fs::rename(resolve(root, &from, false)?, resolve(root, &to, false)?)
+                .map_err(|e| io_error(&from, e))?;
+            Ok(Vec::new())
+        }),
+        // The target is kept as it is, like it would be for a script running
+        // in the root.
+        ("symlink", |root, args| {
+            let (target, path) = (args.string(0)?, args.string(1)?);
+            symlink(&*target, resolve(root, &path, false)?).map_err(|e| io_error(&path, e))?;
+            Ok(Vec::new())
+        }),
+        ("chmod", |root, args| {
+            let path = args.string(0)?;
+            let mode = parse_mode(&args.get(1))?;
+            fs::set_permissions(
+                resolve(root, &path, true)?,
+                fs::Permissions::from_mode(mode),
+            )
+            .map_err(|e| io_error(&path, e))?;
+            Ok(Vec::new())
+        }),
+        ("list", |root, args| {
+            let path = args.string(0)?;
+            let mut names = fs::read_dir(resolve(root, &path, true)?)
+                .and_then(|entries| {
+                    entries
+                        .map(|entry| Ok(entry?.file_name().to_string_lossy().to_string()))
+                        .collect::<std::io::Result<Vec<_>>>()
+                })
+                .map_err(|e| io_error(&path, e))?;
+            names.sort();
+            Ok(vec![
+                Table::from_list(names.into_iter().map(Value::from)).into()
+            ])
+        }),
+    ];
+
+    let table = Table::new();
+    for (name, f) in functions {
+        let root = root.clone();
+        table.set(name, Value::function(name, move |_, args| f(&root, &args)))?;
+    }
+
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::pkg::ScriptPhase;
+    use common::testing::TestDir;
+
+    #[test]
+    fn test_run() {
+        let root = TestDir::new("stage1-lua");
+        fs::create_dir_all(root.join("etc")).unwrap();
+
+        let script = |contents: &str| Stage1Script {
+            contents: contents.to_owned(),
+            path: PathBuf::from("post_install"),
+            phase: ScriptPhase::PostInstall,
+            custom_phase: None,
+            checksum: None,
+        };
+        let envs = [("PKG_VERSION", OsStr::new("1.2.0"))];
+
+        let output = run(
+            &script(
+                r#"#!lpm-lua
+                local conf = "/etc/" .. pkg.name .. ".conf"
+                fs.write(conf, "version=" .. pkg.env.PKG_VERSION .. "\n")
+                fs.append(conf, "phase=" .. pkg.phase .. "\n")
+                fs.chmod(conf, "600")
+                fs.mkdir("/var/lib/hello")
+                fs.symlink(conf, "/etc/hello.link")
+                print(fs.exists(conf), fs.exists("/missing"), table.concat(fs.list("/etc"), ","))
+                fs.remove("/etc/hello.link")
+                "#,
+            ),
+            &root,
+            &envs,
+            "hello",
+        )
+        .unwrap();
+
+        assert_eq!(output, "true\tfalse\thello.conf,hello.link\n");
+        assert_eq!(
+            fs::read_to_string(root.join("etc/hello.conf")).unwrap(),
+            "version=1.2.0\nphase=post_install\n"
+        );
+        let mode = fs::metadata(root.join("etc/hello.conf"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert!(root.join("var/lib/hello").is_dir());
+        assert!(!root.join("etc/hello.link").exists());
+
+        let error = run(&script("fs.read('/../etc/passwd')"), &root, &envs, "hello").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "line 1: '/../etc/passwd' must not contain '..'"
+        );
+
+        // Symlinks resolve inside the root, whether they were there before or
+        // the script made them.
+        symlink("/", root.join("etc/outside")).unwrap();
+        run(
+            &script(
+                r#"fs.write("/etc/outside/absolute", "")
+                fs.symlink("../../../../..", "/etc/up")
+                fs.write("/etc/up/relative", "")"#,
+            ),
+            &root,
+            &envs,
+            "hello",
+        )
+        .unwrap();
+        assert!(root.join("absolute").is_file());
+        assert!(root.join("relative").is_file());
+
+        for mode in ["'10000'", "'-1'", "-1", "4096", "1.5"] {
+            let source = format!("fs.chmod('/etc/hello.conf', {mode})");
+            assert!(run(&script(&source), &root, &envs, "hello").is_err());
+        }
+    }
+}
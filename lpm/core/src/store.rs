@@ -0,0 +1,315 @@
+//! Content-addressed store of the installed files, used when `content_store`
+//! is set in the config.
+//!
+//! Each installed file is a hardlink to an object in `STORE_DIR` of its root,
+//! so identical files of different packages or versions take space once. As
+//! the links share their attributes, these are part of the object name:
+//! `<algorithm>/<checksum>.<mode>.<uid>.<gid>[.<mtime>]`, the modification
+//! time only if the package records one.
+//!
+//! Config files are copied as usual, since they are meant to be edited.
+//! Objects that no installed file links to anymore are dropped after each
+//! transaction.
+
+use crate::validate::digest;
+
+use common::{
+    copy::{copy_file, set_attributes, set_ownership},
+    meta::FileStruct,
+};
+use logger::debug;
+use std::{
+    collections::HashMap,
+    fs, io,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+pub const STORE_DIR: &str = "/var/lib/lpm/store";
+/// `EXDEV`, hardlinks can't cross filesystems.
+const CROSS_DEVICE_LINK: i32 = 18;
+
+/// Packages are installed from several threads, which may store the same
+/// content at the same time.
+static NEXT_PARTIAL_ID: AtomicUsize = AtomicUsize::new(0);
+
+pub(crate) struct ContentStore {
+    root: PathBuf,
+    dir: PathBuf,
+}
+
+fn object_name(file: &FileStruct, metadata: &fs::Metadata) -> String {
+    let mut name = format!(
+        "{}.{:o}.{}.{}",
+        file.checksum.to_lowercase(),
+        metadata.mode() & 0o7777,
+        metadata.uid(),
+        metadata.gid()
+    );
+    if file.mtime.is_some() {
+        name.push_str(&format!(".{}", metadata.mtime()));
+    }
+
+    name
+}
+
+impl ContentStore {
+    /// Store of the installation root at `root`.
+    pub(crate) fn new(root: &Path) -> Self {
+        Self {
+            root: root.to_owned(),
+            dir: root.join(STORE_DIR.trim_start_matches('/')),
+        }
+    }
+
+    fn object_path(&self, file: &FileStruct, metadata: &fs::Metadata) -> PathBuf {
+        self.dir
+            .join(file.checksum_algorithm.to_lowercase())
+            .join(object_name(file, metadata))
+    }
+
+    /// Installs `source` as `destination`, a hardlink to the object of its
+    /// content and attributes, which is added to the store if it's not there
+    /// yet. Only regular files are stored, others are copied.
+    ///
+    /// `destination` is copied from the object if the store is on another
+    /// filesystem.
+    pub(crate) fn install(
+        &self,
+        source: &Path,
+        destination: &Path,
+        file: &FileStruct,
+    ) -> io::Result<u64> {
+        if fs::symlink_metadata(destination).is_ok() {
+            fs::remove_file(destination)?;
+        }
+
+        if !fs::symlink_metadata(source)?.is_file() {
+            return copy_file(source, destination);
+        }
+
+        let algorithm_dir = self.dir.join(file.checksum_algorithm.to_lowercase());
+        fs::create_dir_all(&algorithm_dir)?;
+
+        // Attributes are applied before the object gets its name, so that an
+        // interrupted install never leaves an object with the wrong ones.
+        let partial_path = algorithm_dir.join(format!(
+            "{}.partial-{}-{}",
+            file.checksum,
+            std::process::id(),
+            NEXT_PARTIAL_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+        let size = copy_file(source, &partial_path)?;
+        set_ownership(
+            &self.root,
+            &partial_path,
+            file.owner.as_deref(),
+            file.group.as_deref(),
+        )?;
+        set_attributes(&partial_path, file.mode, file.mtime)?;
+
+        let object_path = self.object_path(file, &fs::symlink_metadata(&partial_path)?);
+        match fs::hard_link(&partial_path, &object_path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                debug!("{} is already stored.", object_path.display())
+            }
+            Err(e) => {
+                fs::remove_file(&partial_path)?;
+                return Err(e);
+            }
+        }
+        fs::remove_file(&partial_path)?;
+
+        match fs::hard_link(&object_path, destination) {
+            Ok(()) => Ok(size),
+            Err(e) if e.raw_os_error() == Some(CROSS_DEVICE_LINK) => {
+                debug!(
+                    "{} is on another filesystem than the store, copying it.",
+                    destination.display()
+                );
+                copy_file(&object_path, destination)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Removes the objects that no installed file links to anymore, and the
+    /// leftovers of interrupted installs.
+    pub(crate) fn prune(&self) -> io::Result<usize> {
+        if !self.dir.exists() {
+            return Ok(0);
+        }
+
+        let mut removed = 0;
+        for algorithm_dir in fs::read_dir(&self.dir)? {
+            for object in fs::read_dir(algorithm_dir?.path())? {
+                let object = object?;
+                let is_partial = object.file_name().to_string_lossy().contains(".partial-");
+                if is_partial || object.metadata()?.nlink() == 1 {
+                    fs::remove_file(object.path())?;
+                    removed += 1;
+                }
+            }
+        }
+
+        Ok(removed)
+    }
+
+    /// Adds the name that the object of the installed `file` has with the
+    /// checksum of `rehashed`. Returns the old name if there is an object,
+    /// which is to be removed once the database refers to the new one.
+    pub(crate) fn relink(
+        &self,
+        file: &FileStruct,
+        rehashed: &FileStruct,
+    ) -> io::Result<Option<PathBuf>> {
+        let metadata = fs::symlink_metadata(file.pkg_path()?.under(&self.root))?;
+        let object_path = self.object_path(file, &metadata);
+        let is_linked = fs::metadata(&object_path).map_or(false, |t| {
+            (t.dev(), t.ino()) == (metadata.dev(), metadata.ino())
+        });
+        if !is_linked {
+            return Ok(None);
+        }
+
+        let rehashed_path = self.object_path(rehashed, &metadata);
+        fs::create_dir_all(rehashed_path.parent().unwrap())?;
+        match fs::hard_link(&object_path, &rehashed_path) {
+            // Another file with the same content got it already.
+            Err(e) if e.kind() != io::ErrorKind::AlreadyExists => return Err(e),
+            _ => {}
+        }
+
+        Ok(Some(object_path))
+    }
+
+    /// Problems of the installed `files`, which are the same as the packages
+    /// built them as long as they link to objects whose content matches
+    /// their names. Each object is read once, however many files link to it.
+    pub(crate) fn verify(&self, files: &[&FileStruct]) -> io::Result<Vec<String>> {
+        let mut problems = Vec::new();
+        let mut verified_objects: HashMap<PathBuf, bool> = HashMap::new();
+
+        for file in files {
+            let pkg_path = file.pkg_path()?;
+            let path = pkg_path.under(&self.root);
+            let metadata = match fs::symlink_metadata(&path) {
+                Ok(metadata) => metadata,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    problems.push(format!("{pkg_path} is missing."));
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            if !metadata.is_file() {
+                continue;
+            }
+
+            let object_path = self.object_path(file, &metadata);
+            let is_linked = fs::metadata(&object_path).map_or(false, |t| {
+                (t.dev(), t.ino()) == (metadata.dev(), metadata.ino())
+            });
+            if !is_linked {
+                problems.push(format!(
+                    "{pkg_path} isn't linked to the store, its content or attributes changed."
+                ));
+                continue;
+            }
+
+            let is_valid = match verified_objects.get(&object_path) {
+                Some(is_valid) => *is_valid,
+                None => {
+                    let checksum = digest(&file.checksum_algorithm, &fs::read(&object_path)?);
+                    let is_valid =
+                        checksum.map_or(false, |t| t.eq_ignore_ascii_case(&file.checksum));
+                    verified_objects.insert(object_path, is_valid);
+                    is_valid
+                }
+            };
+            if !is_valid {
+                problems.push(format!(
+                    "{pkg_path} doesn't match its {} checksum.",
+                    file.checksum_algorithm
+                ));
+            }
+        }
+
+        Ok(problems)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::testing::TestDir;
+
+    #[test]
+    fn test_content_store() {
+        let root = TestDir::new("store");
+        fs::create_dir_all(root.join("src")).unwrap();
+        let store = ContentStore::new(&root);
+
+        let source = root.join("src/htop");
+        fs::write(&source, b"htop").unwrap();
+        let file = |path: &str, mode| FileStruct {
+            path: path.to_owned(),
+            checksum_algorithm: String::from("sha256"),
+            checksum: digest("sha256", b"htop").unwrap(),
+            size: None,
+            mode: Some(mode),
+            mtime: Some(1700000000),
+            owner: None,
+            group: None,
+            class: None,
+        };
+        let (first, second, other_mode) = (
+            file("first", 0o755),
+            file("second", 0o755),
+            file("other_mode", 0o700),
+        );
+
+        for file in [&first, &second, &other_mode] {
+            store
+                .install(&source, &root.join(&file.path), file)
+                .unwrap();
+        }
+
+        let ino = |path: &str| fs::metadata(root.join(path)).unwrap().ino();
+        assert_eq!(ino("first"), ino("second"));
+        assert_ne!(ino("first"), ino("other_mode"));
+        assert!(store
+            .verify(&[&first, &second, &other_mode])
+            .unwrap()
+            .is_empty());
+
+        let rehashed = FileStruct {
+            checksum_algorithm: String::from("blake3"),
+            checksum: digest("blake3", b"htop").unwrap(),
+            ..other_mode.clone()
+        };
+        let old_object = store.relink(&other_mode, &rehashed).unwrap().unwrap();
+        fs::remove_file(old_object).unwrap();
+        assert!(store.verify(&[&rehashed]).unwrap().is_empty());
+
+        // Written through one of the links, so every link has the new content.
+        fs::write(root.join("first"), b"changed").unwrap();
+        assert_eq!(
+            store.verify(&[&first]).unwrap(),
+            ["/first isn't linked to the store, its content or attributes changed."]
+        );
+        set_attributes(&root.join("first"), None, first.mtime).unwrap();
+        assert_eq!(
+            store.verify(&[&first, &second]).unwrap(),
+            [
+                "/first doesn't match its sha256 checksum.",
+                "/second doesn't match its sha256 checksum."
+            ]
+        );
+
+        fs::remove_file(root.join("other_mode")).unwrap();
+        assert_eq!(store.prune().unwrap(), 1);
+        assert_eq!(store.prune().unwrap(), 0);
+    }
+}
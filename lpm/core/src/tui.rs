@@ -0,0 +1,348 @@
+//! `lpm --tui`, an interactive view of the installed and available packages.
+//!
+//! Packages are marked for installation or deletion, then applied together
+//! through `Lpm`, with the downloads reported as they progress.
+
+use crate::{Ctx, Event, Lpm};
+
+use common::event::EventSink;
+use ehandle::{lpm::LpmError, MainError};
+use std::{
+    collections::BTreeMap,
+    io::{self, Write},
+    sync::{Arc, Mutex},
+};
+use term::{
+    controller::TermController,
+    raw_mode::{read_key, Key, RawMode},
+};
+
+const ENTER_ALTERNATE_SCREEN: &str = "\x1B[?1049h\x1B[?25l";
+const LEAVE_ALTERNATE_SCREEN: &str = "\x1B[?25h\x1B[?1049l";
+/// Lines taken by the header and the footer.
+const RESERVED_LINES: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mark {
+    None,
+    Install,
+    Delete,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Row {
+    name: String,
+    installed_version: Option<String>,
+    available_version: Option<String>,
+    mark: Mark,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    rows: Vec<Row>,
+    filter: String,
+    searching: bool,
+    /// Position within the visible rows.
+    cursor: usize,
+    scroll: usize,
+}
+
+enum Action {
+    None,
+    Apply,
+    Quit,
+}
+
+impl State {
+    fn load(lpm: &Lpm) -> Result<Self, LpmError<MainError>> {
+        fn row<'a>(rows: &'a mut BTreeMap<String, Row>, name: &str) -> &'a mut Row {
+            rows.entry(name.to_owned()).or_insert_with(|| Row {
+                name: name.to_owned(),
+                installed_version: None,
+                available_version: None,
+                mark: Mark::None,
+            })
+        }
+
+        let mut rows = BTreeMap::new();
+        for pkg in lpm.list()? {
+            row(&mut rows, &pkg.name).installed_version = Some(pkg.version);
+        }
+        for pkg in lpm.search("")? {
+            row(&mut rows, &pkg.name).available_version = Some(pkg.version);
+        }
+
+        Ok(Self {
+            rows: rows.into_values().collect(),
+            ..Default::default()
+        })
+    }
+
+    fn visible_rows(&self) -> Vec<usize> {
+        (0..self.rows.len())
+            .filter(|&i| self.rows[i].name.contains(&self.filter))
+            .collect()
+    }
+
+    fn marked(&self, mark: Mark) -> Vec<&str> {
+        self.rows
+            .iter()
+            .filter(|t| t.mark == mark)
+            .map(|t| t.name.as_str())
+            .collect()
+    }
+
+    fn move_cursor(&mut self, by: isize) {
+        let count = self.visible_rows().len();
+        if count == 0 {
+            self.cursor = 0;
+            return;
+        }
+
+        self.cursor = self.cursor.saturating_add_signed(by).min(count - 1);
+    }
+
+    fn toggle_mark(&mut self) {
+        let Some(&i) = self.visible_rows().get(self.cursor) else {
+            return;
+        };
+
+        let row = &mut self.rows[i];
+        row.mark = match (row.mark, &row.installed_version) {
+            (Mark::None, Some(_)) => Mark::Delete,
+            (Mark::None, None) if row.available_version.is_some() => Mark::Install,
+            _ => Mark::None,
+        };
+    }
+
+    fn handle_key(&mut self, key: Key, page_size: usize) -> Action {
+        let page_size = page_size as isize;
+
+        match key {
+            Key::Interrupt => return Action::Quit,
+            Key::Up => self.move_cursor(-1),
+            Key::Down => self.move_cursor(1),
+            Key::PageUp => self.move_cursor(-page_size),
+            Key::PageDown => self.move_cursor(page_size),
+            Key::Enter | Key::Esc if self.searching => self.searching = false,
+            Key::Backspace if self.searching => {
+                self.filter.pop();
+                self.cursor = 0;
+            }
+            Key::Char(c) if self.searching => {
+                self.filter.push(c);
+                self.cursor = 0;
+            }
+            Key::Char('/') => self.searching = true,
+            Key::Char('k') => self.move_cursor(-1),
+            Key::Char('j') => self.move_cursor(1),
+            Key::Char(' ') => self.toggle_mark(),
+            Key::Char('a') => return Action::Apply,
+            Key::Char('q') => return Action::Quit,
+            _ => {}
+        }
+
+        Action::None
+    }
+
+    fn render(&mut self, out: &mut impl Write, lines: usize, columns: usize) -> io::Result<()> {
+        let page_size = lines.saturating_sub(RESERVED_LINES).max(1);
+        if self.cursor < self.scroll {
+            self.scroll = self.cursor;
+        } else if self.cursor >= self.scroll + page_size {
+            self.scroll = self.cursor + 1 - page_size;
+        }
+
+        let mut screen = String::from("\x1B[H\x1B[2J");
+        screen.push_str("[/] search  [space] mark  [a] apply  [q] quit\n");
+        let cursor = if self.searching { "_" } else { "" };
+        screen.push_str(&format!("Search: {}{cursor}\n\n", self.filter));
+
+        let visible_rows = self.visible_rows();
+        for (position, &i) in visible_rows
+            .iter()
+            .enumerate()
+            .skip(self.scroll)
+            .take(page_size)
+        {
+            let row = &self.rows[i];
+            let mark = match row.mark {
+                Mark::None => ' ',
+                Mark::Install => 'I',
+                Mark::Delete => 'D',
+            };
+            let version = |version: &Option<String>| version.as_deref().unwrap_or("-").to_owned();
+            let line: String = format!(
+                "[{mark}] {:<32} {:<16} {}",
+                row.name,
+                version(&row.installed_version),
+                version(&row.available_version)
+            )
+            .chars()
+            .take(columns)
+            .collect();
+
+            if position == self.cursor {
+                screen.push_str(&format!("\x1B[7m{line}\x1B[0m\n"));
+            } else {
+                screen.push_str(&format!("{line}\n"));
+            }
+        }
+
+        screen.push_str(&format!(
+            "\x1B[{lines};1H{} to install, {} to delete",
+            self.marked(Mark::Install).len(),
+            self.marked(Mark::Delete).len()
+        ));
+
+        out.write_all(screen.as_bytes())?;
+        out.flush()
+    }
+}
+
+/// Keeps the percentage of the running download on a single line.
+#[derive(Default)]
+struct ProgressEvents {
+    /// Last printed percentage of each download.
+    downloads: Mutex<BTreeMap<String, u64>>,
+}
+
+impl EventSink for ProgressEvents {
+    fn download_started(&self, file_name: &str) {
+        println!("Downloading {file_name}..");
+    }
+
+    fn download_progress(&self, file_name: &str, downloaded: u64, total: Option<u64>) {
+        let Some(total) = total.filter(|&t| t > 0) else {
+            return;
+        };
+
+        let percentage = downloaded * 100 / total;
+        let mut downloads = self.downloads.lock().unwrap();
+        if downloads.insert(file_name.to_owned(), percentage) != Some(percentage) {
+            print!("\r\x1B[K{file_name}: {percentage}%");
+            let _ = io::stdout().flush();
+        }
+    }
+
+    fn download_finished(&self, file_name: &str) {
+        self.downloads.lock().unwrap().remove(file_name);
+        println!("\r\x1B[K{file_name}: done");
+    }
+}
+
+fn apply(lpm: &mut Lpm, state: &State) -> Result<(), LpmError<MainError>> {
+    let (to_install, to_delete) = (state.marked(Mark::Install), state.marked(Mark::Delete));
+
+    if !to_install.is_empty() {
+        println!("Installing: {}", to_install.join(", "));
+        lpm.install(&to_install)?;
+    }
+    if !to_delete.is_empty() {
+        println!("Deleting: {}", to_delete.join(", "));
+        lpm.delete(&to_delete)?;
+    }
+
+    Ok(())
+}
+
+pub fn run_tui(ctx: Ctx) -> Result<(), LpmError<MainError>> {
+    let mut lpm = Lpm::from_ctx(ctx);
+    lpm.set_event_sink(Arc::new(ProgressEvents::default()));
+    lpm.set_event_callback(|event| match event {
+        Event::Installed(pkg) => println!("Installed {} {}", pkg.name, pkg.version),
+        Event::Updated { from, to } => {
+            println!("Updated {} {} -> {}", to.name, from.version, to.version)
+        }
+        Event::Deleted(pkg) => println!("Deleted {} {}", pkg.name, pkg.version),
+        Event::Started(_) | Event::Finished(_) => {}
+    });
+
+    let mut state = State::load(&lpm)?;
+    let (mut stdin, mut stdout) = (io::stdin(), io::stdout());
+
+    loop {
+        let action = {
+            let _raw_mode = RawMode::enable()?;
+            print!("{ENTER_ALTERNATE_SCREEN}");
+
+            let action = loop {
+                let term = TermController::new();
+                let lines = term.rows().max(RESERVED_LINES + 1);
+                state.render(&mut stdout, lines, term.columns().max(1))?;
+
+                match state.handle_key(read_key(&mut stdin)?, lines - RESERVED_LINES) {
+                    Action::None => continue,
+                    action => break action,
+                }
+            };
+
+            print!("{LEAVE_ALTERNATE_SCREEN}");
+            stdout.flush()?;
+            action
+        };
+
+        match action {
+            Action::Apply if state.rows.iter().any(|t| t.mark != Mark::None) => {
+                if let Err(e) = apply(&mut lpm, &state) {
+                    logger::error!("{:?}", e);
+                }
+
+                print!("\nPress Enter to go back to the package list.");
+                stdout.flush()?;
+                io::stdin().read_line(&mut String::new())?;
+
+                let filter = std::mem::take(&mut state.filter);
+                state = State::load(&lpm)?;
+                state.filter = filter;
+            }
+            Action::Apply | Action::None => {}
+            Action::Quit => return Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(name: &str, installed: Option<&str>, available: Option<&str>) -> Row {
+        Row {
+            name: name.to_owned(),
+            installed_version: installed.map(str::to_owned),
+            available_version: available.map(str::to_owned),
+            mark: Mark::None,
+        }
+    }
+
+    #[test]
+    fn test_search_and_mark() {
+        let mut state = State {
+            rows: vec![
+                row("htop", Some("3.2.1"), Some("3.2.2")),
+                row("nano", None, Some("7.2")),
+                row("ncurses", Some("6.4"), None),
+            ],
+            ..Default::default()
+        };
+
+        for key in [Key::Char('/'), Key::Char('n'), Key::Enter] {
+            state.handle_key(key, 10);
+        }
+        assert_eq!(state.visible_rows(), vec![1, 2]);
+
+        state.handle_key(Key::Char(' '), 10);
+        state.handle_key(Key::Down, 10);
+        state.handle_key(Key::Char(' '), 10);
+        // The cursor stays on the last row.
+        state.handle_key(Key::PageDown, 10);
+        assert_eq!(state.cursor, 1);
+
+        assert_eq!(state.marked(Mark::Install), vec!["nano"]);
+        assert_eq!(state.marked(Mark::Delete), vec!["ncurses"]);
+
+        state.handle_key(Key::Char(' '), 10);
+        assert!(state.marked(Mark::Delete).is_empty());
+        assert!(matches!(state.handle_key(Key::Char('q'), 10), Action::Quit));
+    }
+}
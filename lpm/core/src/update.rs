@@ -1,42 +1,85 @@
 use crate::{
+    alternatives::{alternative_links, refresh_alternatives},
+    backup::Backups,
     extract::get_pkg_tmp_output_path,
-    repository::find_pkg_index,
-    stage1::{get_scripts, Stage1Tasks, PKG_SCRIPTS_DIR},
-    validate::PkgValidateTasks,
+    filter::PathFilter,
+    in_transaction,
+    install::{create_directories, PkgInstallTasks},
+    maintainer::maintainer_changes,
+    plan::{confirm_plan, format_plan, Plan, PlanEntry},
+    protect::ProtectedPaths,
+    repository::{
+        ensure_available_offline, ensure_fresh_metadata, ensure_trusted_origin, find_pkg_index,
+        get_and_apply_repository_patches, index_origin,
+    },
+    rollback::FsJournal,
+    stage1::{check_interpreters, get_installed_scripts, pkg_lib_dir, Stage1Tasks},
+    store::ContentStore,
+    validate::{digest_file, verify_archive, PkgValidateTasks},
     Ctx, PkgExtractTasks,
 };
 
 use common::{
-    ctx_confirmation_check, download_file,
+    copy::{copy_file, reflink_file, set_attributes, set_ownership},
+    delta, download_file, download_files,
+    event::EventSink,
+    interrupt,
+    meta::{FileKind, FileStruct, Meta},
     pkg::{PkgDataFromDb, PkgDataFromFs, PkgToQuery, ScriptPhase},
-    Files,
+    stats,
+    version::VersionStruct,
+    Files, SYSTEM_ARCH,
 };
 use db::{
-    enable_core_db_wal1,
+    enable_core_db_wal1, get_package_alternatives,
     pkg::{DbOpsForBuildFile, DbOpsForInstalledPkg},
-    transaction_op, Transaction,
+    PkgIndex,
 };
 use ehandle::{lpm::LpmError, repository::RepositoryErrorKind, ErrorCommons, MainError};
 use logger::{debug, info, warning};
 use min_sqlite3_sys::prelude::Database;
 use std::{
+    ffi::OsString,
     fs::{self, create_dir_all, remove_file},
-    path::Path,
-    sync::Arc,
+    io,
+    os::unix::fs::{MetadataExt, PermissionsExt},
+    path::{Path, PathBuf},
+    sync::Mutex,
     thread,
 };
 
-trait PkgUpdateTasks {
+/// How the files of the updated packages are written.
+#[derive(Clone, Copy)]
+pub(crate) struct FileUpdateMode<'a> {
+    /// Files are hardlinked to the store if set, copied otherwise.
+    pub(crate) store: Option<&'a ContentStore>,
+    /// Hash the installed files whose checksum didn't change, rather than
+    /// trusting their size and modification time.
+    pub(crate) paranoid: bool,
+    /// Patterns of the `no_extract` config, skipped along with the paths
+    /// excluded when the package was installed.
+    pub(crate) no_extract: &'a [String],
+}
+
+pub(crate) trait PkgUpdateTasks {
     fn start_update_task(
         &mut self,
         core_db: &Database,
         to: &mut PkgDataFromFs,
+        protected: &ProtectedPaths,
+        mode: FileUpdateMode,
+        journal: &mut FsJournal,
+        events: &dyn EventSink,
     ) -> Result<(), LpmError<MainError>>;
 
     fn compare_and_update_files_on_fs(
         &mut self,
         pkg_path: &Path,
         new_files: Files,
+        to_meta: &Meta,
+        mode: FileUpdateMode,
+        journal: &mut FsJournal,
+        events: &dyn EventSink,
     ) -> Result<(), LpmError<MainError>>;
 }
 
@@ -45,6 +88,10 @@ impl PkgUpdateTasks for PkgDataFromDb {
         &mut self,
         core_db: &Database,
         to_pkg: &mut PkgDataFromFs,
+        protected: &ProtectedPaths,
+        mode: FileUpdateMode,
+        journal: &mut FsJournal,
+        events: &dyn EventSink,
     ) -> Result<(), LpmError<MainError>> {
         debug!("Comparing versions..");
 
@@ -59,7 +106,7 @@ impl PkgUpdateTasks for PkgDataFromDb {
                 (ScriptPhase::PreUpgrade, ScriptPhase::PostUpgrade)
             }
             std::cmp::Ordering::Greater => {
-                // TODO Ask for downgrading
+                // Callers already confirmed it through `is_downgrade_allowed`.
                 (ScriptPhase::PreDowngrade, ScriptPhase::PostDowngrade)
             }
             std::cmp::Ordering::Equal => {
@@ -71,33 +118,71 @@ impl PkgUpdateTasks for PkgDataFromDb {
             }
         };
 
-        let pkg_lib_dir = Path::new(PKG_SCRIPTS_DIR).join(&self.meta_fields.meta.name);
-        let scripts = get_scripts(&pkg_lib_dir.join("scripts"))?;
+        let pkg_lib_dir = pkg_lib_dir(Path::new("/"), &self.meta_fields.meta);
+        let scripts = get_installed_scripts(&self.meta_fields.meta)?;
 
-        to_pkg.start_validate_task()?;
-        let source_path = get_pkg_tmp_output_path(&to_pkg.path).join("program");
+        to_pkg.start_validate_task(SYSTEM_ARCH)?;
 
-        if let Err(err) = scripts.execute_script(vec![], pre_script) {
-            transaction_op(core_db, Transaction::Rollback)?;
-            return Err(err);
+        // The paths left out of the installed version stay out.
+        let mut filter = match db::pkg::get_path_filter(core_db, self.pkg_id)? {
+            Some(path_filter) => PathFilter::parse(&path_filter)?,
+            None => PathFilter::default(),
+        };
+        filter.add_patterns(mode.no_extract)?;
+        to_pkg.exclude_files(&filter)?;
+
+        for change in maintainer_changes(
+            &self.meta_fields.meta,
+            self.origin.as_ref(),
+            &to_pkg.meta_dir.meta,
+            to_pkg.origin.as_ref(),
+        ) {
+            warning!("{change} Review the update, it may not come from the same people.");
         }
 
+        // Both the files being replaced or removed and the new ones count.
+        protected.check(
+            &self.meta_fields.meta.name,
+            self.meta_fields
+                .files
+                .0
+                .iter()
+                .chain(&to_pkg.meta_dir.files.0)
+                .map(|file| file.path.as_str()),
+        )?;
+        let source_path = get_pkg_tmp_output_path(&to_pkg.path).join("program");
+
+        let pkg_name = self.meta_fields.meta.name.clone();
+        scripts.execute_script(Path::new("/"), vec![], pre_script, &pkg_name, events)?;
+
         info!("Applying package differences to the system..");
-        self.compare_and_update_files_on_fs(&source_path, to_pkg.meta_dir.files.clone())?;
+        self.compare_and_update_files_on_fs(
+            &source_path,
+            to_pkg.meta_dir.files.clone(),
+            &to_pkg.meta_dir.meta,
+            mode,
+            journal,
+            events,
+        )?;
+        to_pkg.meta_dir.meta.installed_size =
+            to_pkg.meta_dir.files.record_sizes(Path::new("/"))? as i64;
 
         info!("Syncing with package database..");
+        let mut links = get_package_alternatives(core_db, self.pkg_id)?;
         to_pkg.update_existing_pkg(core_db, self.pkg_id, to_pkg.meta_dir.meta.get_group_id())?;
 
-        if let Err(err) = scripts.execute_script(vec![], post_script) {
-            transaction_op(core_db, Transaction::Rollback)?;
-            return Err(err);
-        }
+        create_dir_all(&pkg_lib_dir)?;
+        fs::copy(
+            to_pkg.meta_dir.path.join("meta.json"),
+            pkg_lib_dir.join("meta.json"),
+        )?;
 
-        if let Err(err) = transaction_op(core_db, Transaction::Commit) {
-            transaction_op(core_db, Transaction::Rollback)?;
-            return Err(err)?;
-        };
-        info!("Update transaction completed.");
+        links.extend(alternative_links(&to_pkg.meta_dir.meta.alternatives));
+        links.sort();
+        links.dedup();
+        refresh_alternatives(Path::new("/"), core_db, &links)?;
+
+        scripts.execute_script(Path::new("/"), vec![], post_script, &pkg_name, events)?;
 
         Ok(())
     }
@@ -108,66 +193,296 @@ impl PkgUpdateTasks for PkgDataFromDb {
         &mut self,
         pkg_path: &Path,
         new_files: Files,
+        to_meta: &Meta,
+        mode: FileUpdateMode,
+        journal: &mut FsJournal,
+        events: &dyn EventSink,
     ) -> Result<(), LpmError<MainError>> {
+        let pkg_name = &self.meta_fields.meta.name;
         for file in new_files.0.iter() {
-            let file_index = self
-                .meta_fields
-                .files
-                .0
-                .iter()
-                .position(|f| f.path == "/".to_owned() + &file.path);
+            interrupt::check()?;
+
+            // Config files are meant to be edited, so they are never shared
+            // nor restored.
+            let is_config = file.kind(&to_meta.config_files) == FileKind::Config;
+            let store = mode.store.filter(|_| !is_config);
+
+            let path = file.pkg_path()?;
+            let destination_path = path.under(Path::new("/"));
+            let source_path = path.under(pkg_path);
+
+            let file_index = self.meta_fields.files.position(&file.path);
             if let Some(file_index) = file_index {
                 let found_file = &self.meta_fields.files.0[file_index];
 
                 // if both files are exactly the same
-                if found_file.checksum_algorithm == file.checksum_algorithm
+                let is_unchanged = found_file.checksum_algorithm == file.checksum_algorithm
                     && found_file.checksum == file.checksum
-                {
-                    debug!(
-                        "File /{} has same checksum in target package, ignoring it.",
-                        file.path
-                    );
+                    && (is_config
+                        || is_intact(&destination_path, found_file, file, mode.paranoid)?);
+                if is_unchanged {
+                    debug!("File {path} has same checksum in target package, ignoring it.");
                     self.meta_fields.files.0.remove(file_index);
+                    // Attributes are shared by the links, so a file whose
+                    // attributes changed links to another object.
+                    if let Some(store) = store {
+                        journal.retain(&destination_path)?;
+                        store.install(&source_path, &destination_path, file)?;
+                    }
+                    journal.retain_attributes(&destination_path)?;
+                    set_file_ownership(&destination_path, file)?;
+                    set_attributes(&destination_path, file.mode, file.mtime)?;
                     continue;
                 } else {
-                    debug!(
-                        "Updating /{} with the other version of it in the target package.",
-                        file.path
-                    );
-                    fs::remove_file(&found_file.path)?;
+                    debug!("Updating {path} with the other version of it in the target package.");
+                    journal.backup(&destination_path)?;
+                    let size = match store {
+                        Some(store) => {
+                            journal.retain(&destination_path)?;
+                            store.install(&source_path, &destination_path, file)?
+                        }
+                        None => update_file(&source_path, &destination_path, file, journal)?,
+                    };
                     self.meta_fields.files.0.remove(file_index);
-
-                    let destination_path = Path::new("/").join(&file.path);
-                    fs::copy(pkg_path.join(&file.path), destination_path)?;
+                    stats::record_installed_file(size);
+                    set_file_ownership(&destination_path, file)?;
+                    set_attributes(&destination_path, file.mode, file.mtime)?;
+                    events.file_installed(pkg_name, &destination_path);
                 }
             }
             // File is not included in the old pkg version
             else {
-                debug!("Adding /{} to the system.", file.path);
-                let destination_path = Path::new("/").join(&file.path);
+                debug!("Adding {path} to the system.");
                 // Ensure the target dir path
                 create_dir_all(destination_path.parent().unwrap())?;
-                fs::copy(pkg_path.join(&file.path), destination_path)?;
+                journal.retain(&destination_path)?;
+                let size = match store {
+                    Some(store) => store.install(&source_path, &destination_path, file)?,
+                    None => copy_file(&source_path, &destination_path)?,
+                };
+                stats::record_installed_file(size);
+                set_file_ownership(&destination_path, file)?;
+                set_attributes(&destination_path, file.mode, file.mtime)?;
+                events.file_installed(pkg_name, &destination_path);
             }
         }
 
         for file in self.meta_fields.files.0.iter() {
-            debug!(
-                "Removing {} since it's not needed in target package",
-                file.path
-            );
-            fs::remove_file(&file.path)?;
+            let path = file.pkg_path()?;
+            debug!("Removing {path} since it's not needed in target package");
+            journal.backup(&path.under(Path::new("/")))?;
+            journal.retain(&path.under(Path::new("/")))?;
+            stats::record_removed_file();
         }
 
+        create_directories(Path::new("/"), &to_meta.directories)?;
+
         Ok(())
     }
 }
 
-pub fn update_pkgs_from_repository(ctx: Ctx) -> Result<(), LpmError<MainError>> {
-    enable_core_db_wal1(&ctx.core_db)?;
+/// Whether the installed `path` of `installed` still has the content of
+/// `file`, which has the same checksum.
+///
+/// Unless `paranoid` is set, a file whose disk usage and modification time
+/// are the recorded ones is taken as intact without hashing it, as are files
+/// without any of them recorded.
+fn is_intact(
+    path: &Path,
+    installed: &FileStruct,
+    file: &FileStruct,
+    paranoid: bool,
+) -> io::Result<bool> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e),
+    };
+    if !metadata.is_file() {
+        return Ok(true);
+    }
+
+    if !paranoid {
+        let is_recorded = installed.size.is_some() || installed.mtime.is_some();
+        // Recorded by `Files::record_sizes`, in the same 512 byte units.
+        let size_matches = installed
+            .size
+            .map_or(true, |t| t == metadata.blocks() * 512);
+        let mtime_matches = installed.mtime.map_or(true, |t| t == metadata.mtime());
+        if !is_recorded || (size_matches && mtime_matches) {
+            return Ok(true);
+        }
+    }
+
+    let checksum = digest_file(&file.checksum_algorithm, path)?;
+    Ok(checksum.map_or(false, |t| t.eq_ignore_ascii_case(&file.checksum)))
+}
+
+/// Replaces the installed `destination` with `source`, which has the content
+/// of `file`, and returns its size. Large files are patched with a delta, so
+/// only the blocks that changed are written. Where `destination` can be
+/// reflinked, the patch goes into a copy that replaces it once its checksum
+/// is verified. Otherwise `destination` is patched in place after it's copied
+/// aside, and overwritten with `source` if the patch doesn't verify.
+///
+/// Executables and shared libraries are always copied as a whole, as running
+/// processes may have them mapped.
+///
+/// The previous version is retained in `journal` either way.
+fn update_file(
+    source: &Path,
+    destination: &Path,
+    file: &FileStruct,
+    journal: &mut FsJournal,
+) -> io::Result<u64> {
+    let is_patchable = |path: &Path| {
+        fs::symlink_metadata(path).map_or(false, |t| {
+            t.is_file() && t.len() >= delta::MIN_FILE_SIZE && t.permissions().mode() & 0o111 == 0
+        })
+    };
+    let is_library = destination
+        .file_name()
+        .map_or(false, |t| t.to_string_lossy().contains(".so"));
+
+    if !is_library && is_patchable(source) && is_patchable(destination) {
+        let size = fs::metadata(source)?.len();
+        let patched = patched_path(destination);
+        if reflink_file(destination, &patched)? {
+            match patch(source, &patched, destination, file) {
+                Ok(true) => {
+                    journal.retain(destination)?;
+                    fs::rename(&patched, destination)?;
+                    return Ok(size);
+                }
+                Ok(false) => fs::remove_file(&patched)?,
+                Err(e) => {
+                    let _ = fs::remove_file(&patched);
+                    return Err(e);
+                }
+            }
+        } else {
+            journal.retain_copy(destination)?;
+            if patch(source, destination, destination, file)? {
+                return Ok(size);
+            }
+        }
+    }
+
+    journal.retain(destination)?;
+    copy_file(source, destination)
+}
+
+/// Patches `target`, a copy of `destination` or `destination` itself, into
+/// `source`. `false` if the delta didn't pay off, `target` is untouched
+/// then, or if the result doesn't match the checksum of `file`.
+fn patch(source: &Path, target: &Path, destination: &Path, file: &FileStruct) -> io::Result<bool> {
+    let Some(written) = delta::update_in_place(target, source)? else {
+        return Ok(false);
+    };
+
+    let checksum = digest_file(&file.checksum_algorithm, target)?;
+    if !checksum.map_or(false, |t| t.eq_ignore_ascii_case(&file.checksum)) {
+        warning!(
+            "Patching {} didn't give the expected content, copying it instead.",
+            destination.display()
+        );
+        return Ok(false);
+    }
+
+    debug!(
+        "Patched {} with a delta, {written} bytes written.",
+        destination.display()
+    );
+    Ok(true)
+}
+
+/// Next to `path`, so it can be renamed over it.
+fn patched_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(".lpm-patched");
+    path.with_file_name(name)
+}
+
+fn set_file_ownership(path: &Path, file: &FileStruct) -> io::Result<()> {
+    set_ownership(
+        Path::new("/"),
+        path,
+        file.owner.as_deref(),
+        file.group.as_deref(),
+    )
+}
+
+/// Applies the downgrade policy of `ctx` if `new` is older than `current`,
+/// asking the user when there is no policy set.
+fn is_downgrade_allowed(
+    ctx: &Ctx,
+    name: &str,
+    current: &VersionStruct,
+    new: &VersionStruct,
+) -> Result<bool, LpmError<MainError>> {
+    if current.compare(new) != std::cmp::Ordering::Greater {
+        return Ok(true);
+    }
+
+    let (current, new) = (&current.readable_format, &new.readable_format);
+    match ctx.allow_downgrade {
+        Some(true) => {
+            warning!("Downgrading '{name}' from {current} to {new}.");
+            Ok(true)
+        }
+        Some(false) => {
+            warning!("Skipping '{name}', {new} is older than the installed {current}.");
+            Ok(false)
+        }
+        None => ctx.ask_for_confirmation(&format!(
+            "'{name}' will be downgraded from {current} to {new}, do you want to allow it?"
+        )),
+    }
+}
+
+/// Finds the version to update `pkg` to. The repository it came from is
+/// preferred as long as it offers a newer version, so that packages don't
+/// move between repositories only because another one has a newer build.
+fn find_update_index(
+    ctx: &Ctx,
+    index_db_list: &[(String, String)],
+    pkg: &PkgDataFromDb,
+    pkg_to_query: &PkgToQuery,
+) -> Result<PkgIndex, LpmError<MainError>> {
+    if let Some(origin) = &pkg.origin {
+        let same_origin: Vec<(String, String)> = index_db_list
+            .iter()
+            .filter(|t| t.0 == origin.repository)
+            .cloned()
+            .collect();
+
+        // Not finding the package there falls back to all the repositories.
+        if let Ok(index) =
+            find_pkg_index(&ctx.core_db, &same_origin, pkg_to_query, ctx.target_arch())
+        {
+            if pkg.meta_fields.meta.version.compare(&index.version) == std::cmp::Ordering::Less {
+                return Ok(index);
+            }
+        }
+    }
+
+    Ok(find_pkg_index(
+        &ctx.core_db,
+        index_db_list,
+        pkg_to_query,
+        ctx.target_arch(),
+    )?)
+}
 
+/// Installed packages that have a newer version in the repositories, along
+/// with the index of that version.
+pub(crate) fn find_available_updates(
+    ctx: &Ctx,
+) -> Result<(Vec<PkgDataFromDb>, Vec<PkgIndex>), LpmError<MainError>> {
     let pkgs = PkgDataFromDb::load_all_main_packages(&ctx.core_db)?;
     let mut old_pkgs = vec![];
+    let mut new_indexes = vec![];
 
     for pkg in pkgs {
         let pkg_to_query = PkgToQuery {
@@ -177,6 +492,8 @@ pub fn update_pkgs_from_repository(ctx: Ctx) -> Result<(), LpmError<MainError>>
             minor: None,
             patch: None,
             tag: None,
+            // Each slot is updated within itself.
+            slot: Some(pkg.meta_fields.meta.slot.clone().unwrap_or_default()),
         };
 
         let index_db_list = db::get_repositories(&ctx.core_db)?;
@@ -186,84 +503,174 @@ pub fn update_pkgs_from_repository(ctx: Ctx) -> Result<(), LpmError<MainError>>
             return Err(RepositoryErrorKind::PackageNotFound(pkg_to_query.name).to_lpm_err())?;
         }
 
-        let index = find_pkg_index(&index_db_list, &pkg_to_query)?;
+        let index = find_update_index(ctx, &index_db_list, &pkg, &pkg_to_query)?;
 
         if pkg.meta_fields.meta.version.compare(&index.version) == std::cmp::Ordering::Less {
             old_pkgs.push(pkg);
+            new_indexes.push(index);
         }
     }
 
+    Ok((old_pkgs, new_indexes))
+}
+
+/// Syncs the repository indexes(unless offline) and prints the available
+/// updates without applying them. Returns whether there are any.
+pub fn check_updates(ctx: &Ctx) -> Result<bool, LpmError<MainError>> {
+    get_and_apply_repository_patches(ctx)?;
+
+    let (old_pkgs, new_indexes) = find_available_updates(ctx)?;
+    ensure_fresh_metadata(ctx, &new_indexes)?;
     if old_pkgs.is_empty() {
         info!("All packages are already up to date.");
-        return Ok(());
+        return Ok(false);
     }
 
-    // TODO
-    // add new versions that will be installed
-    // package size is missing
-    // total installation size is missing
-    // use colors
-    println!("\nPackage list to be updated:");
-    for old_pkg in &old_pkgs {
-        println!("  - {}", old_pkg.group_id);
-    }
-    println!();
-    ctx_confirmation_check!(ctx);
+    let entries: Vec<PlanEntry> = old_pkgs
+        .iter()
+        .zip(&new_indexes)
+        .map(|(old_pkg, index)| PlanEntry::from_index(index, Some(&old_pkg.meta_fields.meta)))
+        .collect();
+    println!("\nAvailable updates:\n{}", format_plan(&entries));
 
-    let core_db = Arc::new(&ctx.core_db);
-    thread::scope(|s| -> Result<(), LpmError<MainError>> {
-        for mut old_pkg in old_pkgs {
-            let core_db = core_db.clone();
+    Ok(true)
+}
 
-            let index_db_list = db::get_repositories(&ctx.core_db)?;
+pub fn update_pkgs_from_repository(ctx: &Ctx) -> Result<(), LpmError<MainError>> {
+    enable_core_db_wal1(&ctx.core_db)?;
 
-            s.spawn(move || -> Result<(), LpmError<MainError>> {
-                let pkg_to_query = PkgToQuery {
-                    name: old_pkg.meta_fields.meta.name.clone(),
-                    condition: Default::default(),
-                    major: None,
-                    minor: None,
-                    patch: None,
-                    tag: None,
-                };
+    let (old_pkgs, new_indexes) = find_available_updates(ctx)?;
+    ensure_fresh_metadata(ctx, &new_indexes)?;
+    ensure_trusted_origin(ctx, &new_indexes)?;
+    if old_pkgs.is_empty() {
+        info!("All packages are already up to date.");
+        return Ok(());
+    }
 
-                if index_db_list.is_empty() {
-                    info!("No repository has been found within the database.");
-                    return Err(
-                        RepositoryErrorKind::PackageNotFound(pkg_to_query.name).to_lpm_err()
-                    )?;
-                }
+    apply_updates(ctx, old_pkgs, new_indexes)
+}
 
-                let index = find_pkg_index(&index_db_list, &pkg_to_query)?;
-                let pkg_path = index.pkg_output_path(super::EXTRACTION_OUTPUT_PATH);
+/// Replaces each of `old_pkgs` with the version of `new_indexes` at the same
+/// position, in a single transaction.
+pub(crate) fn apply_updates(
+    ctx: &Ctx,
+    old_pkgs: Vec<PkgDataFromDb>,
+    new_indexes: Vec<PkgIndex>,
+) -> Result<(), LpmError<MainError>> {
+    if ctx.offline {
+        let indexes: Vec<&PkgIndex> = new_indexes.iter().collect();
+        ensure_available_offline(&indexes, super::EXTRACTION_OUTPUT_PATH)?;
+    }
+
+    let plan = Plan {
+        operation: "update",
+        requested: Vec::new(),
+        entries: old_pkgs
+            .iter()
+            .zip(&new_indexes)
+            .map(|(old_pkg, index)| PlanEntry::from_index(index, Some(&old_pkg.meta_fields.meta)))
+            .collect(),
+    };
+    if !confirm_plan(ctx, "Package list to be updated:", &plan)? {
+        return Ok(());
+    }
+
+    let mut downloads = Vec::new();
+    for index in &new_indexes {
+        let options = db::get_repository_options(&ctx.core_db, &index.repository_name)?;
+        downloads.push((
+            index.pkg_urls(&options.mirrors),
+            index.pkg_output_path(super::EXTRACTION_OUTPUT_PATH),
+            options.download_options(&ctx.config)?,
+        ));
+    }
+    download_files(&downloads, ctx.events.as_ref())?;
 
-                download_file(&index.pkg_url(), &pkg_path)?;
-                let mut requested_pkg = PkgDataFromFs::start_extract_task(&pkg_path)?;
+    let (limits, events) = (&ctx.config.extraction_limits, ctx.events.as_ref());
+    let downloaded = Mutex::new(Vec::new());
+    thread::scope(|s| {
+        for (old_pkg, index) in old_pkgs.into_iter().zip(&new_indexes) {
+            let downloaded = &downloaded;
 
-                info!("Package update started for {}", pkg_to_query.name);
-                old_pkg.start_update_task(&core_db, &mut requested_pkg)?;
+            s.spawn(move || -> Result<(), LpmError<MainError>> {
+                let pkg_path = index.pkg_output_path(super::EXTRACTION_OUTPUT_PATH);
+                verify_archive(index, &pkg_path)?;
+                let mut requested_pkg =
+                    PkgDataFromFs::start_extract_task(&pkg_path, limits, events)?;
+                requested_pkg.origin = Some(index_origin(index)?);
+                downloaded.lock().unwrap().push((old_pkg, requested_pkg));
 
                 Ok(())
             });
         }
+    });
+
+    // Checked before any of the updates start.
+    let downloaded = downloaded.into_inner().unwrap();
+    for (_, requested_pkg) in &downloaded {
+        check_interpreters(
+            &requested_pkg.meta_dir.meta.name,
+            &requested_pkg.scripts,
+            &ctx.config.allowed_script_interpreters,
+        )?;
+    }
+
+    // Downloads and extractions run in parallel, but all of the updates share
+    // one transaction of the single database connection.
+    let protected = ProtectedPaths::new(&ctx.config.protected_paths)?;
+    let store = ctx.content_store();
+    let mut journal = FsJournal::new(Backups::new(ctx.config.keep_backups));
+    let result = in_transaction(&ctx.core_db, || {
+        for (mut old_pkg, mut requested_pkg) in downloaded {
+            info!(
+                "Package update started for {}",
+                old_pkg.meta_fields.meta.name
+            );
+            old_pkg.start_update_task(
+                &ctx.core_db,
+                &mut requested_pkg,
+                &protected,
+                FileUpdateMode {
+                    store: store.as_ref(),
+                    paranoid: ctx.paranoid,
+                    no_extract: &ctx.config.no_extract,
+                },
+                &mut journal,
+                events,
+            )?;
+        }
 
         Ok(())
-    })
+    });
+    journal.finish(result.is_ok());
+    result?;
+    info!("Update transaction completed.");
+
+    Ok(())
 }
 
-pub fn update_pkg_from_repository(ctx: Ctx, pkg_name: &str) -> Result<(), LpmError<MainError>> {
-    enable_core_db_wal1(&ctx.core_db)?;
+pub fn update_pkg_from_repository(ctx: &Ctx, pkg_name: &str) -> Result<(), LpmError<MainError>> {
+    update_pkg_to_version(ctx, pkg_name, None)
+}
 
+/// Installed `pkg_name` and the index of the version that
+/// `update_pkg_to_version` would update it to.
+pub(crate) fn find_pkg_update(
+    ctx: &Ctx,
+    pkg_name: &str,
+    version: Option<&PkgToQuery>,
+) -> Result<(PkgDataFromDb, PkgIndex), LpmError<MainError>> {
     // ensure the pkg exists
-    let mut old_pkg = PkgDataFromDb::load(&ctx.core_db, pkg_name)?;
+    let old_pkg = PkgDataFromDb::load(&ctx.core_db, pkg_name)?;
 
     let pkg_to_query = PkgToQuery {
-        name: pkg_name.to_owned(),
-        condition: Default::default(),
-        major: None,
-        minor: None,
-        patch: None,
-        tag: None,
+        name: old_pkg.meta_fields.meta.name.clone(),
+        condition: version.map(|t| t.condition).unwrap_or_default(),
+        major: version.and_then(|t| t.major),
+        minor: version.and_then(|t| t.minor),
+        patch: version.and_then(|t| t.patch),
+        tag: version.and_then(|t| t.tag.clone()),
+        slot: Some(old_pkg.meta_fields.meta.slot.clone().unwrap_or_default()),
     };
 
     let index_db_list = db::get_repositories(&ctx.core_db)?;
@@ -273,33 +680,98 @@ pub fn update_pkg_from_repository(ctx: Ctx, pkg_name: &str) -> Result<(), LpmErr
         return Err(RepositoryErrorKind::PackageNotFound(pkg_to_query.name).to_lpm_err())?;
     }
 
-    let index = find_pkg_index(&index_db_list, &pkg_to_query)?;
+    let index = find_update_index(ctx, &index_db_list, &old_pkg, &pkg_to_query)?;
+
+    Ok((old_pkg, index))
+}
+
+/// Updates `pkg_name` to the latest version that matches the version
+/// conditions of `version`, which may be older than the installed one.
+/// Without `version`, the latest one of the repositories is used.
+pub(crate) fn update_pkg_to_version(
+    ctx: &Ctx,
+    pkg_name: &str,
+    version: Option<&PkgToQuery>,
+) -> Result<(), LpmError<MainError>> {
+    enable_core_db_wal1(&ctx.core_db)?;
+
+    let (mut old_pkg, index) = find_pkg_update(ctx, pkg_name, version)?;
+    ensure_fresh_metadata(ctx, [&index])?;
+    ensure_trusted_origin(ctx, [&index])?;
 
     if old_pkg.meta_fields.meta.version.compare(&index.version) == std::cmp::Ordering::Equal {
         info!("{} is up to date", pkg_name);
         return Ok(());
     }
 
-    let pkg_path = index.pkg_output_path(super::EXTRACTION_OUTPUT_PATH);
+    if !is_downgrade_allowed(
+        ctx,
+        &old_pkg.meta_fields.meta.name,
+        &old_pkg.meta_fields.meta.version,
+        &index.version,
+    )? {
+        return Ok(());
+    }
 
-    {
-        // TODO
-        // package size is missing
-        // total installation size is missing
-        // use colors
-        println!("\nPackage list to be updated:");
-        println!("  - {}", index.get_group_id());
-        println!();
+    if ctx.offline {
+        ensure_available_offline(&[&index], super::EXTRACTION_OUTPUT_PATH)?;
     }
 
-    ctx_confirmation_check!(ctx);
+    let pkg_path = index.pkg_output_path(super::EXTRACTION_OUTPUT_PATH);
 
-    download_file(&index.pkg_url(), &pkg_path)?;
+    let plan = Plan {
+        operation: "update",
+        requested: vec![pkg_name.to_owned()],
+        entries: vec![PlanEntry::from_index(
+            &index,
+            Some(&old_pkg.meta_fields.meta),
+        )],
+    };
+    if !confirm_plan(ctx, "Package list to be updated:", &plan)? {
+        return Ok(());
+    }
 
-    let mut requested_pkg = PkgDataFromFs::start_extract_task(&pkg_path)?;
+    let options = db::get_repository_options(&ctx.core_db, &index.repository_name)?;
+    download_file(
+        &index.pkg_urls(&options.mirrors),
+        &pkg_path,
+        &options.download_options(&ctx.config)?,
+        ctx.events.as_ref(),
+    )?;
+    verify_archive(&index, &pkg_path)?;
+
+    let mut requested_pkg = PkgDataFromFs::start_extract_task(
+        &pkg_path,
+        &ctx.config.extraction_limits,
+        ctx.events.as_ref(),
+    )?;
+    requested_pkg.origin = Some(index_origin(&index)?);
+    check_interpreters(
+        &requested_pkg.meta_dir.meta.name,
+        &requested_pkg.scripts,
+        &ctx.config.allowed_script_interpreters,
+    )?;
 
     info!("Package update started for {}", pkg_name);
-    old_pkg.start_update_task(&ctx.core_db, &mut requested_pkg)?;
+    let protected = ProtectedPaths::new(&ctx.config.protected_paths)?;
+    let mut journal = FsJournal::new(Backups::new(ctx.config.keep_backups));
+    let result = in_transaction(&ctx.core_db, || {
+        old_pkg.start_update_task(
+            &ctx.core_db,
+            &mut requested_pkg,
+            &protected,
+            FileUpdateMode {
+                store: ctx.content_store().as_ref(),
+                paranoid: ctx.paranoid,
+                no_extract: &ctx.config.no_extract,
+            },
+            &mut journal,
+            ctx.events.as_ref(),
+        )
+    });
+    journal.finish(result.is_ok());
+    result?;
+    info!("Update transaction completed.");
 
     remove_file(pkg_path)?;
 
@@ -307,28 +779,152 @@ pub fn update_pkg_from_repository(ctx: Ctx, pkg_name: &str) -> Result<(), LpmErr
 }
 
 pub fn update_pkg_from_lod_file(
-    ctx: Ctx,
+    ctx: &Ctx,
     pkg_name: &str,
-    pkg_path: &str,
+    pkg_path: &Path,
 ) -> Result<(), LpmError<MainError>> {
     enable_core_db_wal1(&ctx.core_db)?;
 
     let mut old_pkg = PkgDataFromDb::load(&ctx.core_db, pkg_name)?;
-    let mut requested_pkg = PkgDataFromFs::start_extract_task(Path::new(pkg_path))?;
-
-    {
-        // TODO
-        // package size is missing
-        // total installation size is missing
-        // use colors
-        println!("\nPackage list to be updated:");
-        println!("  - {}", requested_pkg.meta_dir.meta.get_group_id());
-        println!();
+    let mut requested_pkg = PkgDataFromFs::start_extract_task(
+        pkg_path,
+        &ctx.config.extraction_limits,
+        ctx.events.as_ref(),
+    )?;
+    check_interpreters(
+        &requested_pkg.meta_dir.meta.name,
+        &requested_pkg.scripts,
+        &ctx.config.allowed_script_interpreters,
+    )?;
+
+    if !is_downgrade_allowed(
+        ctx,
+        &old_pkg.meta_fields.meta.name,
+        &old_pkg.meta_fields.meta.version,
+        &requested_pkg.meta_dir.meta.version,
+    )? {
+        return Ok(());
+    }
+
+    let plan = Plan {
+        operation: "update_local",
+        requested: vec![pkg_path.display().to_string()],
+        entries: vec![PlanEntry::from_meta(
+            &requested_pkg.meta_dir.meta,
+            Some(&old_pkg.meta_fields.meta),
+        )],
+    };
+    if !confirm_plan(ctx, "Package list to be updated:", &plan)? {
+        return Ok(());
     }
-    ctx_confirmation_check!(ctx);
 
     info!("Package update started for {}", pkg_name);
-    old_pkg.start_update_task(&ctx.core_db, &mut requested_pkg)?;
+    let protected = ProtectedPaths::new(&ctx.config.protected_paths)?;
+    let mut journal = FsJournal::new(Backups::new(ctx.config.keep_backups));
+    let result = in_transaction(&ctx.core_db, || {
+        old_pkg.start_update_task(
+            &ctx.core_db,
+            &mut requested_pkg,
+            &protected,
+            FileUpdateMode {
+                store: ctx.content_store().as_ref(),
+                paranoid: ctx.paranoid,
+                no_extract: &ctx.config.no_extract,
+            },
+            &mut journal,
+            ctx.events.as_ref(),
+        )
+    });
+    journal.finish(result.is_ok());
+    result?;
+    info!("Update transaction completed.");
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::validate::digest;
+    use common::testing::TestDir;
+
+    #[test]
+    fn test_is_intact() {
+        let root = TestDir::new("intact");
+        let path = root.join("htop");
+        fs::write(&path, b"htop").unwrap();
+        set_attributes(&path, None, Some(1700000000)).unwrap();
+
+        let mut files = Files(vec![FileStruct {
+            path: String::from("htop"),
+            checksum_algorithm: String::from("sha256"),
+            checksum: digest("sha256", b"htop").unwrap(),
+            size: None,
+            mode: None,
+            mtime: Some(1700000000),
+            owner: None,
+            group: None,
+            class: None,
+        }]);
+        files.record_sizes(&root).unwrap();
+        let file = files.0.remove(0);
+        assert!(is_intact(&path, &file, &file, false).unwrap());
+        assert!(is_intact(&path, &file, &file, true).unwrap());
+
+        // Same size and modification time, only hashing finds the change.
+        fs::write(&path, b"htoq").unwrap();
+        set_attributes(&path, None, Some(1700000000)).unwrap();
+        assert!(is_intact(&path, &file, &file, false).unwrap());
+        assert!(!is_intact(&path, &file, &file, true).unwrap());
+
+        fs::write(&path, b"changed").unwrap();
+        assert!(!is_intact(&path, &file, &file, false).unwrap());
+
+        fs::remove_file(&path).unwrap();
+        assert!(!is_intact(&path, &file, &file, false).unwrap());
+    }
+
+    #[test]
+    fn test_update_file() {
+        let dir = TestDir::new("update-file");
+        let (source, destination) = (dir.join("source"), dir.join("destination"));
+
+        let old = vec![b'a'; delta::MIN_FILE_SIZE as usize];
+        let mut new = old.clone();
+        new[delta::BLOCK_SIZE..delta::BLOCK_SIZE + 4].copy_from_slice(b"htop");
+        fs::write(&source, &new).unwrap();
+
+        let mut file = FileStruct {
+            path: String::from("usr/share/htop/data"),
+            checksum_algorithm: String::from("sha256"),
+            checksum: digest("sha256", &new).unwrap(),
+            size: None,
+            mode: None,
+            mtime: None,
+            owner: None,
+            group: None,
+            class: None,
+        };
+
+        // A mismatching patch falls back to a copy. Whether the file is
+        // patched in place depends on the filesystem of the temporary
+        // directory, the previous version is restored either way.
+        let wrong_checksum = digest("sha256", b"htop").unwrap();
+        for checksum in [file.checksum.clone(), wrong_checksum] {
+            file.checksum = checksum;
+            for committed in [true, false] {
+                fs::write(&destination, &old).unwrap();
+
+                let mut journal = FsJournal::new(Backups::new(0));
+                let size = update_file(&source, &destination, &file, &mut journal).unwrap();
+                assert_eq!(size, new.len() as u64);
+                assert_eq!(fs::read(&destination).unwrap(), new);
+                assert!(!patched_path(&destination).exists());
+
+                journal.finish(committed);
+                let expected = if committed { &new } else { &old };
+                assert_eq!(&fs::read(&destination).unwrap(), expected);
+            }
+        }
+    }
+}
@@ -1,24 +1,29 @@
-use crate::extract::get_pkg_tmp_output_path;
+use crate::{extract::get_pkg_tmp_output_path, lod::read_lod_meta};
 
-use common::meta::Files;
+use common::meta::{FileStruct, Files};
 use common::pkg::PkgDataFromFs;
-use common::{NO_ARCH, SYSTEM_ARCH};
+use common::{arch, NO_ARCH};
+use db::PkgIndex;
 use ehandle::lpm::LpmError;
 use ehandle::{
     pkg::{PackageError, PackageErrorKind},
     ErrorCommons, MainError,
 };
-use hash::{md5, sha256, sha512};
-use logger::debug;
+use hash::{blake3, md5, sha256, sha512};
+use logger::{debug, warning};
 use std::fmt;
 use std::path::Path;
-use std::{fs, io::Read};
+use std::{
+    fs,
+    io::{self, Read},
+};
 
 #[non_exhaustive]
 enum ChecksumKind {
     Md5,
     Sha256,
     Sha512,
+    Blake3,
 }
 
 impl fmt::Display for ChecksumKind {
@@ -27,6 +32,7 @@ impl fmt::Display for ChecksumKind {
             ChecksumKind::Md5 => write!(f, "md5"),
             ChecksumKind::Sha256 => write!(f, "sha256"),
             ChecksumKind::Sha512 => write!(f, "sha512"),
+            ChecksumKind::Blake3 => write!(f, "blake3"),
         }
     }
 }
@@ -37,18 +43,106 @@ impl ChecksumKind {
             "md5" => Ok(ChecksumKind::Md5),
             "sha256" => Ok(ChecksumKind::Sha256),
             "sha512" => Ok(ChecksumKind::Sha512),
+            "blake3" => Ok(ChecksumKind::Blake3),
             _ => Err(PackageErrorKind::UnsupportedChecksumAlgorithm(kind.to_string()).to_err()),
         }
     }
+
+    fn digest(&self, buffer: &[u8]) -> String {
+        match self {
+            ChecksumKind::Md5 => hash::digest_to_hex_string(&md5::digest(buffer)),
+            ChecksumKind::Sha256 => hash::digest_to_hex_string(&sha256::digest(buffer)),
+            ChecksumKind::Sha512 => hash::digest_to_hex_string(&sha512::digest(buffer)),
+            ChecksumKind::Blake3 => hash::digest_to_hex_string(&blake3::digest(buffer)),
+        }
+    }
+
+    fn hasher(&self) -> Hasher {
+        match self {
+            ChecksumKind::Md5 => Hasher::Md5(md5::Hasher::new()),
+            ChecksumKind::Sha256 => Hasher::Sha256(sha256::Hasher::new()),
+            ChecksumKind::Sha512 => Hasher::Sha512(sha512::Hasher::new()),
+            ChecksumKind::Blake3 => Hasher::Blake3(Box::new(blake3::Hasher::new())),
+        }
+    }
+}
+
+/// Same as `ChecksumKind::digest`, for input that is read in pieces.
+enum Hasher {
+    Md5(md5::Hasher),
+    Sha256(sha256::Hasher),
+    Sha512(sha512::Hasher),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl Hasher {
+    fn update(&mut self, input: &[u8]) {
+        match self {
+            Hasher::Md5(hasher) => hasher.update(input),
+            Hasher::Sha256(hasher) => hasher.update(input),
+            Hasher::Sha512(hasher) => hasher.update(input),
+            Hasher::Blake3(hasher) => hasher.update(input),
+        }
+    }
+
+    fn finalize(self) -> String {
+        match self {
+            Hasher::Md5(hasher) => hash::digest_to_hex_string(&hasher.finalize()),
+            Hasher::Sha256(hasher) => hash::digest_to_hex_string(&hasher.finalize()),
+            Hasher::Sha512(hasher) => hash::digest_to_hex_string(&hasher.finalize()),
+            Hasher::Blake3(hasher) => hash::digest_to_hex_string(&hasher.finalize()),
+        }
+    }
+}
+
+/// Hex digest of `buffer` with the checksum algorithm named `algorithm`,
+/// `None` if it's not supported.
+pub(crate) fn digest(algorithm: &str, buffer: &[u8]) -> Option<String> {
+    let kind = ChecksumKind::from_str(algorithm.to_lowercase().as_str()).ok()?;
+    Some(kind.digest(buffer))
+}
+
+/// Like `digest`, for the content of the file at `path`, which is read in
+/// pieces instead of being loaded whole.
+pub(crate) fn digest_file(algorithm: &str, path: &Path) -> io::Result<Option<String>> {
+    let Ok(kind) = ChecksumKind::from_str(algorithm.to_lowercase().as_str()) else {
+        return Ok(None);
+    };
+
+    let mut reader = fs::File::open(path)?;
+    let mut hasher = kind.hasher();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let read = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        hasher.update(&buffer[..read]);
+    }
+
+    Ok(Some(hasher.finalize()))
+}
+
+/// Fails if the checksum algorithm named `algorithm` isn't supported.
+pub(crate) fn check_algorithm(algorithm: &str) -> Result<(), LpmError<MainError>> {
+    if ChecksumKind::from_str(algorithm.to_lowercase().as_str()).is_err() {
+        return Err(
+            PackageErrorKind::UnsupportedChecksumAlgorithm(algorithm.to_owned()).to_lpm_err(),
+        )?;
+    }
+
+    Ok(())
 }
 
 pub(crate) trait PkgValidateTasks {
-    fn start_validate_task(&self) -> Result<(), LpmError<MainError>>;
+    fn start_validate_task(&self, target_arch: &str) -> Result<(), LpmError<MainError>>;
 }
 
 impl PkgValidateTasks for PkgDataFromFs {
-    fn start_validate_task(&self) -> Result<(), LpmError<MainError>> {
-        if self.meta_dir.meta.arch != NO_ARCH && self.meta_dir.meta.arch != SYSTEM_ARCH {
+    fn start_validate_task(&self, target_arch: &str) -> Result<(), LpmError<MainError>> {
+        if !arch::is_supported_by(target_arch, &self.meta_dir.meta.arch) {
             return Err(PackageErrorKind::UnsupportedPackageArchitecture(
                 self.meta_dir.meta.arch.clone(),
             )
@@ -56,48 +150,232 @@ impl PkgValidateTasks for PkgDataFromFs {
         }
 
         let pkg_output_path = get_pkg_tmp_output_path(&self.path);
-        check_program_checksums(&pkg_output_path, &self.meta_dir.files)
+        check_program_checksums(
+            &pkg_output_path,
+            &self.meta_dir.files,
+            &self.meta_dir.meta.arch,
+        )
     }
 }
 
-fn check_program_checksums(dir: &Path, files: &Files) -> Result<(), LpmError<MainError>> {
-    for file in &files.0 {
-        // Read file as byte-array
-        let f_path = dir.join("program").join(&file.path);
-        debug!("Reading {} in byte format", &f_path.display());
-        let mut f_reader = fs::File::open(&f_path)?;
-        let mut buffer = Vec::new();
-        f_reader.read_to_end(&mut buffer)?;
-
-        if let Ok(checksum_algorithm) =
-            ChecksumKind::from_str(file.checksum_algorithm.to_lowercase().as_str())
-        {
-            debug!(
-                "Checksum algorithm of {} is specified as {}",
-                &f_path.display(),
-                checksum_algorithm
-            );
-            // Generate hash with using same algorithm of pkg checksum
-            let file_hash = match checksum_algorithm {
-                ChecksumKind::Md5 => hash::digest_to_hex_string(&md5::digest(&buffer)),
-                ChecksumKind::Sha256 => hash::digest_to_hex_string(&sha256::digest(&buffer)),
-                ChecksumKind::Sha512 => hash::digest_to_hex_string(&sha512::digest(&buffer)),
-            };
-
-            debug!(
-                "Checking checksum value of {} if it's corrupted or not",
-                &f_path.display()
-            );
-            if file_hash.ne(&file.checksum) {
-                return Err(PackageErrorKind::InvalidPackageFiles.to_lpm_err())?;
+/// Describes why an ELF binary doesn't fit the declared architecture of the
+/// package, if it doesn't. Unknown machine types and `no-arch` packages are
+/// not checked.
+fn binary_arch_mismatch(path: &str, buffer: &[u8], declared: &str) -> Option<String> {
+    if !elf::is_elf(buffer) || arch::is_same(declared, NO_ARCH) {
+        return None;
+    }
+
+    let found = match elf::Elf::parse(buffer) {
+        Ok(elf) => arch::of_elf(&elf),
+        Err(_e) => {
+            debug!("Skipping architecture check of {path}: {_e}");
+            None
+        }
+    };
+
+    found
+        .filter(|found| !arch::is_supported_by(declared, found))
+        .map(|found| format!("built for '{found}', but the package is declared as '{declared}'"))
+}
+
+/// Checks the downloaded `.lod` archive against the size, checksum and
+/// installed size published in the repository index, before anything is
+/// extracted from it.
+///
+/// Invalid archives are removed so they don't poison the download cache.
+pub(crate) fn verify_archive(index: &PkgIndex, pkg_path: &Path) -> Result<(), LpmError<MainError>> {
+    let fail = |reason: String| -> Result<(), LpmError<MainError>> {
+        fs::remove_file(pkg_path)?;
+
+        Err(PackageErrorKind::ArchiveVerificationFailed {
+            package: index.get_group_id(),
+            reason,
+        }
+        .to_lpm_err())?
+    };
+
+    if let Some(expected_size) = index.archive_size {
+        let size = fs::metadata(pkg_path)?.len();
+        if size != expected_size as u64 {
+            return fail(format!(
+                "Expected {expected_size} bytes, but downloaded {size} bytes."
+            ));
+        }
+    }
+
+    match &index.archive_checksum {
+        Some(expected_checksum) => {
+            debug!("Verifying checksum of {}", pkg_path.display());
+            let buffer = fs::read(pkg_path)?;
+            let checksum = hash::digest_to_hex_string(&sha256::digest(&buffer));
+
+            if !checksum.eq_ignore_ascii_case(expected_checksum) {
+                return fail(format!(
+                    "Expected sha256 checksum '{expected_checksum}', but got '{checksum}'."
+                ));
             }
-        } else {
-            return Err(PackageErrorKind::UnsupportedChecksumAlgorithm(
-                file.checksum_algorithm.clone(),
-            )
-            .to_lpm_err())?;
+        }
+        // Archives are verified through the checksums of signed indexes.
+        None if index.signing_key.is_some() => {
+            return fail(String::from(
+                "The index is signed, but doesn't provide a checksum for the archive.",
+            ));
+        }
+        None => warning!(
+            "Index of '{}' doesn't provide a checksum for {}, the archive can't be verified.",
+            index.repository_name,
+            index.get_group_id()
+        ),
+    }
+
+    // Only the meta data is read here, so a mismatching archive is refused
+    // before anything gets extracted.
+    let meta = match read_lod_meta(pkg_path) {
+        Ok(lod) => lod.meta,
+        Err(e) => return fail(e.to_string()),
+    };
+
+    if meta.get_group_id() != index.get_group_id() {
+        return fail(format!("The archive contains '{}'.", meta.get_group_id()));
+    }
+
+    if let Some(expected_size) = index.installed_size {
+        if meta.installed_size != expected_size {
+            return fail(format!(
+                "Index declares an installed size of {expected_size} bytes, but the archive declares {} bytes.",
+                meta.installed_size
+            ));
         }
     }
 
     Ok(())
 }
+
+/// Checks every file of the package, and lists all of the invalid ones at
+/// once rather than stopping at the first.
+fn check_program_checksums(
+    dir: &Path,
+    files: &Files,
+    declared_arch: &str,
+) -> Result<(), LpmError<MainError>> {
+    let mut problems = Vec::new();
+
+    for file in &files.0 {
+        let pkg_path = file.pkg_path()?;
+        let f_path = pkg_path.under(&dir.join("program"));
+        if let Some(problem) = check_program_file(&f_path, file, declared_arch)? {
+            problems.push(format!("{pkg_path}: {problem}"));
+        }
+    }
+
+    if !problems.is_empty() {
+        return Err(PackageErrorKind::InvalidPackageFiles(problems).to_lpm_err())?;
+    }
+
+    Ok(())
+}
+
+/// What is wrong with `file`, `None` if it's valid.
+fn check_program_file(
+    f_path: &Path,
+    file: &FileStruct,
+    declared_arch: &str,
+) -> Result<Option<String>, LpmError<MainError>> {
+    let Ok(checksum_algorithm) =
+        ChecksumKind::from_str(file.checksum_algorithm.to_lowercase().as_str())
+    else {
+        return Ok(Some(format!(
+            "unsupported checksum algorithm '{}'",
+            file.checksum_algorithm
+        )));
+    };
+
+    // Read file as byte-array
+    debug!("Reading {} in byte format", &f_path.display());
+    let buffer = match fs::read(f_path) {
+        Ok(buffer) => buffer,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return Ok(Some(String::from("missing from the package")))
+        }
+        Err(e) => return Err(e)?,
+    };
+
+    debug!(
+        "Checksum algorithm of {} is specified as {}",
+        &f_path.display(),
+        checksum_algorithm
+    );
+    // Generate hash with using same algorithm of pkg checksum
+    let file_hash = checksum_algorithm.digest(&buffer);
+
+    debug!(
+        "Checking checksum value of {} if it's corrupted or not",
+        &f_path.display()
+    );
+    if file_hash.ne(&file.checksum) {
+        return Ok(Some(format!(
+            "{checksum_algorithm} checksum mismatch, expected {} but found {file_hash}",
+            file.checksum
+        )));
+    }
+
+    Ok(binary_arch_mismatch(&file.path, &buffer, declared_arch))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::testing::TestDir;
+
+    #[test]
+    fn test_check_program_checksums() {
+        let dir = TestDir::new("validate-test");
+        fs::create_dir_all(dir.join("program/usr/bin")).unwrap();
+        fs::write(dir.join("program/usr/bin/valid"), "valid").unwrap();
+        fs::write(dir.join("program/usr/bin/corrupt"), "corrupt").unwrap();
+
+        let file = |path: &str, checksum_algorithm: &str, content: &str| FileStruct {
+            path: path.to_owned(),
+            checksum_algorithm: checksum_algorithm.to_owned(),
+            checksum: hash::digest_to_hex_string(&sha256::digest(content.as_bytes())),
+            size: None,
+            mode: None,
+            mtime: None,
+            owner: None,
+            group: None,
+            class: None,
+        };
+        let files = Files(vec![
+            file("usr/bin/valid", "sha256", "valid"),
+            file("usr/bin/corrupt", "sha256", "valid"),
+            file("usr/bin/missing", "sha256", "missing"),
+            file("usr/bin/valid", "crc32", "valid"),
+        ]);
+
+        let error = check_program_checksums(&dir, &files, NO_ARCH).unwrap_err();
+        let error = format!("{:?}", error.error_type);
+        assert!(error.contains("/usr/bin/corrupt: sha256 checksum mismatch"));
+        assert!(error.contains("/usr/bin/missing: missing from the package"));
+        assert!(error.contains("/usr/bin/valid: unsupported checksum algorithm 'crc32'"));
+
+        assert!(check_program_checksums(&dir, &Files(files.0[..1].to_vec()), NO_ARCH).is_ok());
+    }
+
+    #[test]
+    fn test_digest_file() {
+        let dir = TestDir::new("digest-file");
+        let path = dir.join("file");
+        // Larger than the read buffer.
+        let content: Vec<u8> = (0..200_000).map(|t| (t % 251) as u8).collect();
+        fs::write(&path, &content).unwrap();
+
+        for algorithm in ["md5", "sha256", "SHA512", "blake3"] {
+            assert_eq!(
+                digest_file(algorithm, &path).unwrap(),
+                digest(algorithm, &content)
+            );
+        }
+        assert_eq!(digest_file("crc32", &path).unwrap(), None);
+    }
+}
@@ -0,0 +1,214 @@
+use common::meta::AlternativeStruct;
+use ehandle::{
+    db::SqlError, lpm::LpmError, simple_e_fmt, try_bind_val, try_execute_prepared, ErrorCommons,
+};
+use min_sqlite3_sys::prelude::*;
+use sql_builder::delete::*;
+use sql_builder::insert::Insert;
+use sql_builder::select::*;
+use sql_builder::Column;
+
+/// A provider of an alternative, as stored in the database.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alternative {
+    pub name: String,
+    pub link: String,
+    pub path: String,
+    pub priority: i64,
+    /// Name of the package that provides it.
+    pub package: String,
+}
+
+pub(crate) fn insert_alternatives(
+    core_db: &Database,
+    pkg_id: i64,
+    alternatives: &[AlternativeStruct],
+) -> Result<(), LpmError<SqlError>> {
+    const NAME_COL_PRE_ID: usize = 1;
+    const LINK_COL_PRE_ID: usize = 2;
+    const PATH_COL_PRE_ID: usize = 3;
+    const PRIORITY_COL_PRE_ID: usize = 4;
+    const PACKAGE_ID_COL_PRE_ID: usize = 5;
+
+    for alternative in alternatives {
+        let columns = vec![
+            Column::new(String::from("name"), NAME_COL_PRE_ID),
+            Column::new(String::from("link"), LINK_COL_PRE_ID),
+            Column::new(String::from("path"), PATH_COL_PRE_ID),
+            Column::new(String::from("priority"), PRIORITY_COL_PRE_ID),
+            Column::new(String::from("package_id"), PACKAGE_ID_COL_PRE_ID),
+        ];
+        let statement = Insert::new(Some(columns), String::from("alternatives")).to_string();
+
+        let mut sql = core_db.prepare(statement, super::SQL_NO_CALLBACK_FN)?;
+
+        try_bind_val!(sql, NAME_COL_PRE_ID, &*alternative.name);
+        try_bind_val!(sql, LINK_COL_PRE_ID, &*alternative.link);
+        try_bind_val!(sql, PATH_COL_PRE_ID, &*alternative.path);
+        try_bind_val!(sql, PRIORITY_COL_PRE_ID, alternative.priority);
+        try_bind_val!(sql, PACKAGE_ID_COL_PRE_ID, pkg_id);
+
+        try_execute_prepared!(
+            sql,
+            simple_e_fmt!("Could not insert to \"alternatives\" table.")
+        );
+    }
+
+    Ok(())
+}
+
+pub(crate) fn delete_alternatives(
+    core_db: &Database,
+    pkg_id: i64,
+) -> Result<(), LpmError<SqlError>> {
+    const PACKAGE_ID_COL_PRE_ID: usize = 1;
+
+    let statement = Delete::new(String::from("alternatives"))
+        .where_condition(Where::Equal(
+            PACKAGE_ID_COL_PRE_ID,
+            String::from("package_id"),
+        ))
+        .to_string();
+
+    let mut sql = core_db.prepare(statement, super::SQL_NO_CALLBACK_FN)?;
+    try_bind_val!(sql, PACKAGE_ID_COL_PRE_ID, pkg_id);
+
+    try_execute_prepared!(
+        sql,
+        simple_e_fmt!(
+            "Could not delete from 'alternatives' for package_id {}.",
+            pkg_id
+        )
+    );
+
+    Ok(())
+}
+
+/// Returns the providers of `name`, or of all alternatives if `name` is `None`,
+/// sorted by name and descending priority.
+pub fn get_alternatives(
+    core_db: &Database,
+    name: Option<&str>,
+) -> Result<Vec<Alternative>, LpmError<SqlError>> {
+    let mut statement = String::from(
+        "SELECT alternatives.name, link, path, priority, packages.name FROM alternatives
+         INNER JOIN packages ON packages.id = alternatives.package_id",
+    );
+    if name.is_some() {
+        statement.push_str(" WHERE alternatives.name = ?1");
+    }
+    statement.push_str(" ORDER BY alternatives.name, priority DESC, path;");
+
+    let mut sql = core_db.prepare(statement, super::SQL_NO_CALLBACK_FN)?;
+    if let Some(name) = name {
+        try_bind_val!(sql, 1, name);
+    }
+
+    let mut alternatives = vec![];
+    while let PreparedStatementStatus::FoundRow = sql.execute_prepared() {
+        alternatives.push(Alternative {
+            name: sql.get_data(0)?,
+            link: sql.get_data(1)?,
+            path: sql.get_data(2)?,
+            priority: sql.get_data(3)?,
+            package: sql.get_data(4)?,
+        });
+    }
+
+    Ok(alternatives)
+}
+
+/// Returns the alternatives provided by the package, as (name, link) pairs.
+pub fn get_package_alternatives(
+    core_db: &Database,
+    pkg_id: i64,
+) -> Result<Vec<(String, String)>, LpmError<SqlError>> {
+    const PACKAGE_ID_COL_PRE_ID: usize = 1;
+
+    let statement = Select::new(
+        Some(vec![String::from("name"), String::from("link")]),
+        String::from("alternatives"),
+    )
+    .where_condition(Where::Equal(
+        PACKAGE_ID_COL_PRE_ID,
+        String::from("package_id"),
+    ))
+    .to_string();
+
+    let mut sql = core_db.prepare(statement, super::SQL_NO_CALLBACK_FN)?;
+    try_bind_val!(sql, PACKAGE_ID_COL_PRE_ID, pkg_id);
+
+    let mut result = vec![];
+    while let PreparedStatementStatus::FoundRow = sql.execute_prepared() {
+        result.push((sql.get_data(0)?, sql.get_data(1)?));
+    }
+
+    Ok(result)
+}
+
+/// Returns the provider path selected by the user for `name`, if any.
+pub fn get_selected_alternative(
+    core_db: &Database,
+    name: &str,
+) -> Result<Option<String>, LpmError<SqlError>> {
+    const NAME_COL_PRE_ID: usize = 1;
+
+    let statement = Select::new(
+        Some(vec![String::from("path")]),
+        String::from("alternative_selections"),
+    )
+    .where_condition(Where::Equal(NAME_COL_PRE_ID, String::from("name")))
+    .to_string();
+
+    let mut sql = core_db.prepare(statement, super::SQL_NO_CALLBACK_FN)?;
+    try_bind_val!(sql, NAME_COL_PRE_ID, name);
+
+    if let PreparedStatementStatus::FoundRow = sql.execute_prepared() {
+        return Ok(Some(sql.get_data(0)?));
+    }
+
+    Ok(None)
+}
+
+/// Pins `name` to the provider at `path`, or back to the automatic
+/// (highest priority) selection if `path` is `None`.
+pub fn select_alternative(
+    core_db: &Database,
+    name: &str,
+    path: Option<&str>,
+) -> Result<(), LpmError<SqlError>> {
+    const NAME_COL_PRE_ID: usize = 1;
+    const PATH_COL_PRE_ID: usize = 2;
+
+    let statement = Delete::new(String::from("alternative_selections"))
+        .where_condition(Where::Equal(NAME_COL_PRE_ID, String::from("name")))
+        .to_string();
+    let mut sql = core_db.prepare(statement, super::SQL_NO_CALLBACK_FN)?;
+    try_bind_val!(sql, NAME_COL_PRE_ID, name);
+    try_execute_prepared!(
+        sql,
+        simple_e_fmt!(
+            "Could not delete from 'alternative_selections' for {}.",
+            name
+        )
+    );
+
+    if let Some(path) = path {
+        let columns = vec![
+            Column::new(String::from("name"), NAME_COL_PRE_ID),
+            Column::new(String::from("path"), PATH_COL_PRE_ID),
+        ];
+        let statement =
+            Insert::new(Some(columns), String::from("alternative_selections")).to_string();
+
+        let mut sql = core_db.prepare(statement, super::SQL_NO_CALLBACK_FN)?;
+        try_bind_val!(sql, NAME_COL_PRE_ID, name);
+        try_bind_val!(sql, PATH_COL_PRE_ID, path);
+        try_execute_prepared!(
+            sql,
+            simple_e_fmt!("Could not insert to \"alternative_selections\" table.")
+        );
+    }
+
+    Ok(())
+}
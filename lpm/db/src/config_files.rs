@@ -0,0 +1,134 @@
+use common::pkg_path::PkgPath;
+use ehandle::{
+    db::{SqlError, SqlErrorKind},
+    lpm::LpmError,
+    simple_e_fmt, try_bind_val, try_execute_prepared, ErrorCommons,
+};
+use min_sqlite3_sys::prelude::*;
+use sql_builder::delete::*;
+use sql_builder::insert::Insert;
+use sql_builder::Column;
+
+/// Records the config files of a package, `paths` being relative to the root.
+/// Files that were kept from a previous installation of the same package are
+/// owned by the new one from now on.
+pub(crate) fn insert_config_files(
+    core_db: &Database,
+    pkg_id: i64,
+    pkg_name: &str,
+    paths: &[String],
+) -> Result<(), LpmError<SqlError>> {
+    delete_retained_config_files(core_db, pkg_name)?;
+
+    const PACKAGE_NAME_COL_PRE_ID: usize = 1;
+    const ABSOLUTE_PATH_COL_PRE_ID: usize = 2;
+    const PACKAGE_ID_COL_PRE_ID: usize = 3;
+
+    for path in paths {
+        let columns = vec![
+            Column::new(String::from("package_name"), PACKAGE_NAME_COL_PRE_ID),
+            Column::new(String::from("absolute_path"), ABSOLUTE_PATH_COL_PRE_ID),
+            Column::new(String::from("package_id"), PACKAGE_ID_COL_PRE_ID),
+        ];
+        let statement = Insert::new(Some(columns), String::from("config_files")).to_string();
+
+        let mut sql = core_db.prepare(statement, super::SQL_NO_CALLBACK_FN)?;
+
+        try_bind_val!(sql, PACKAGE_NAME_COL_PRE_ID, pkg_name);
+        let path = PkgPath::new(path)
+            .map_err(|e| SqlErrorKind::InvalidPath(e.to_string()).to_lpm_err())?;
+        try_bind_val!(sql, ABSOLUTE_PATH_COL_PRE_ID, path.to_absolute());
+        try_bind_val!(sql, PACKAGE_ID_COL_PRE_ID, pkg_id);
+
+        try_execute_prepared!(
+            sql,
+            simple_e_fmt!("Could not insert to \"config_files\" table.")
+        );
+    }
+
+    Ok(())
+}
+
+pub(crate) fn delete_config_files(
+    core_db: &Database,
+    pkg_id: i64,
+) -> Result<(), LpmError<SqlError>> {
+    const PACKAGE_ID_COL_PRE_ID: usize = 1;
+
+    let statement = Delete::new(String::from("config_files"))
+        .where_condition(Where::Equal(
+            PACKAGE_ID_COL_PRE_ID,
+            String::from("package_id"),
+        ))
+        .to_string();
+
+    let mut sql = core_db.prepare(statement, super::SQL_NO_CALLBACK_FN)?;
+    try_bind_val!(sql, PACKAGE_ID_COL_PRE_ID, pkg_id);
+
+    try_execute_prepared!(
+        sql,
+        simple_e_fmt!(
+            "Could not delete from 'config_files' for package_id {}.",
+            pkg_id
+        )
+    );
+
+    Ok(())
+}
+
+/// Returns the absolute paths of the config files of the `pkg_id` package.
+pub fn get_package_config_files(
+    core_db: &Database,
+    pkg_id: i64,
+) -> Result<Vec<String>, LpmError<SqlError>> {
+    let statement = String::from("SELECT absolute_path FROM config_files WHERE package_id = ?1;");
+
+    let mut sql = core_db.prepare(statement, super::SQL_NO_CALLBACK_FN)?;
+    try_bind_val!(sql, 1, pkg_id);
+
+    let mut paths = vec![];
+    while let PreparedStatementStatus::FoundRow = sql.execute_prepared() {
+        paths.push(sql.get_data(0)?);
+    }
+
+    Ok(paths)
+}
+
+/// Returns the absolute paths of the config files that were kept on the
+/// system when `pkg_name` got deleted without purging.
+pub fn get_retained_config_files(
+    core_db: &Database,
+    pkg_name: &str,
+) -> Result<Vec<String>, LpmError<SqlError>> {
+    let statement = String::from(
+        "SELECT absolute_path FROM config_files WHERE package_name = ?1 AND package_id IS NULL;",
+    );
+
+    let mut sql = core_db.prepare(statement, super::SQL_NO_CALLBACK_FN)?;
+    try_bind_val!(sql, 1, pkg_name);
+
+    let mut paths = vec![];
+    while let PreparedStatementStatus::FoundRow = sql.execute_prepared() {
+        paths.push(sql.get_data(0)?);
+    }
+
+    Ok(paths)
+}
+
+pub fn delete_retained_config_files(
+    core_db: &Database,
+    pkg_name: &str,
+) -> Result<(), LpmError<SqlError>> {
+    let statement =
+        String::from("DELETE FROM config_files WHERE package_name = ?1 AND package_id IS NULL;");
+
+    let mut sql = core_db.prepare(statement, super::SQL_NO_CALLBACK_FN)?;
+    try_bind_val!(sql, 1, pkg_name);
+
+    try_execute_prepared!(
+        sql,
+        simple_e_fmt!("Could not delete retained config files of '{}'.", pkg_name)
+    );
+
+    Ok(())
+}
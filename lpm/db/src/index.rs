@@ -1,23 +1,60 @@
-use crate::SQL_NO_CALLBACK_FN;
+use crate::{
+    index_schema::{has_column, has_table},
+    SQL_NO_CALLBACK_FN,
+};
 
 use common::{
+    meta::PackageKind,
     pkg::PkgToQuery,
     version::{Condition, VersionStruct},
+    NO_ARCH,
 };
 use ehandle::{
     db::SqlError, lpm::LpmError, simple_e_fmt, try_bind_val, try_execute_prepared, ErrorCommons,
 };
 use min_sqlite3_sys::{prelude::*, statement::SqlStatement};
 use sql_builder::select::*;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 #[derive(Clone, Debug, Default)]
 pub struct PkgIndex {
     pub name: String,
+    pub repository_name: String,
     pub repository_address: String,
     pub version: VersionStruct,
+    /// sha256 checksum of the `.lod` archive, if the index provides it.
+    pub archive_checksum: Option<String>,
+    /// Size of the `.lod` archive in bytes, if the index provides it.
+    pub archive_size: Option<i64>,
+    /// Installed size in bytes, if the index provides it.
+    pub installed_size: Option<i64>,
+    /// Kind from the package meta, if the index provides it.
+    pub kind: Option<PackageKind>,
+    /// Description from the package meta, if the index provides it.
+    pub description: Option<String>,
+    /// Id of the trusted key that the index is signed with, if it is.
+    pub signing_key: Option<String>,
 }
 
+/// Columns that older indexes don't have.
+const OPTIONAL_COLUMNS: [&str; 5] = [
+    "archive_checksum",
+    "archive_size",
+    "installed_size",
+    "kind",
+    "description",
+];
+
+/// Metadata key of the newest `index_timestamp` synced, which the entries
+/// left in the index may be older than once the filtered ones are deleted.
+const SYNCED_TIMESTAMP_KEY: &str = "synced_timestamp";
+/// Metadata key of the id of the key that every sync of the index was
+/// verified with.
+const SIGNED_BY_KEY: &str = "signed_by";
+/// Metadata key that is set once the index has published an expiry date,
+/// so that an index which stops publishing one is still held to it.
+const EXPIRY_PUBLISHED_KEY: &str = "expiry_published";
+
 macro_rules! try_bind_val_if_some {
     ($sql: expr, $c_index: expr, $val: expr) => {
         if let Some(val) = $val {
@@ -52,12 +89,286 @@ impl PkgIndex {
         );
 
         let index: Option<u32> = sql.get_data(0)?;
-        Ok(index.unwrap_or(0))
+        let index = index.unwrap_or(0);
+
+        if !has_table(index_db, "metadata")? {
+            return Ok(index);
+        }
+
+        let statement = format!(
+            "SELECT CAST(value AS INTEGER) FROM metadata WHERE key = '{SYNCED_TIMESTAMP_KEY}';"
+        );
+        let mut sql = index_db.prepare(statement.clone(), SQL_NO_CALLBACK_FN)?;
+        let status = try_execute_prepared!(
+            sql,
+            simple_e_fmt!("Failed executing SQL statement `{}`.", statement)
+        );
+
+        if status != PreparedStatementStatus::FoundRow {
+            return Ok(index);
+        }
+
+        let synced: Option<u32> = sql.get_data(0)?;
+        Ok(index.max(synced.unwrap_or(0)))
+    }
+
+    /// Deletes the entries built for architectures other than `archs`. The
+    /// synced timestamp is kept so that they are not fetched again.
+    pub fn retain_archs(index_db: &Database, archs: &[String]) -> Result<(), LpmError<SqlError>> {
+        if !has_column(index_db, "arch")? {
+            return Ok(());
+        }
+
+        let timestamp = Self::latest_timestamp(index_db)?;
+        let statements = [
+            String::from(
+                "CREATE TABLE IF NOT EXISTS metadata (key TEXT NOT NULL UNIQUE, value TEXT);",
+            ),
+            format!("DELETE FROM metadata WHERE key = '{SYNCED_TIMESTAMP_KEY}';"),
+            format!(
+                "INSERT INTO metadata (key, value) VALUES ('{SYNCED_TIMESTAMP_KEY}', '{timestamp}');"
+            ),
+        ];
+
+        for statement in statements {
+            let mut sql = index_db.prepare(statement.clone(), SQL_NO_CALLBACK_FN)?;
+            try_execute_prepared!(
+                sql,
+                simple_e_fmt!("Failed executing SQL statement `{}`.", statement)
+            );
+        }
+
+        let statement = format!(
+            "DELETE FROM repository WHERE {} NOT IN ({});",
+            arch_expression(),
+            placeholders(1, archs.len())
+        );
+        let mut sql = index_db.prepare(statement.clone(), SQL_NO_CALLBACK_FN)?;
+        for (i, arch) in archs.iter().enumerate() {
+            try_bind_val!(sql, i + 1, arch.as_str());
+        }
+        try_execute_prepared!(
+            sql,
+            simple_e_fmt!("Failed executing SQL statement `{}`.", statement)
+        );
+
+        Ok(())
     }
 
+    /// Id of the key that each sync of the index was verified with, `None` if
+    /// any of them wasn't.
+    pub fn signing_key(index_db: &Database) -> Result<Option<String>, LpmError<SqlError>> {
+        if !has_table(index_db, "metadata")? {
+            return Ok(None);
+        }
+
+        let statement = format!("SELECT value FROM metadata WHERE key = '{SIGNED_BY_KEY}';");
+        let mut sql = index_db.prepare(statement.clone(), SQL_NO_CALLBACK_FN)?;
+        let status = try_execute_prepared!(
+            sql,
+            simple_e_fmt!("Failed executing SQL statement `{}`.", statement)
+        );
+
+        if status != PreparedStatementStatus::FoundRow {
+            return Ok(None);
+        }
+
+        Ok(sql.get_data(0)?)
+    }
+
+    /// Records the key that the index was verified with on the last sync.
+    /// The patches can write the metadata as well, so it's called after each
+    /// of them is applied.
+    pub fn set_signing_key(
+        index_db: &Database,
+        key_id: Option<&str>,
+    ) -> Result<(), LpmError<SqlError>> {
+        let mut statements = vec![
+            String::from(
+                "CREATE TABLE IF NOT EXISTS metadata (key TEXT NOT NULL UNIQUE, value TEXT);",
+            ),
+            format!("DELETE FROM metadata WHERE key = '{SIGNED_BY_KEY}';"),
+        ];
+        if key_id.is_some() {
+            statements.push(format!(
+                "INSERT INTO metadata (key, value) VALUES ('{SIGNED_BY_KEY}', ?1);"
+            ));
+        }
+
+        for statement in statements {
+            let mut sql = index_db.prepare(statement.clone(), SQL_NO_CALLBACK_FN)?;
+            if statement.contains("?1") {
+                try_bind_val!(sql, 1, key_id.unwrap());
+            }
+            try_execute_prepared!(
+                sql,
+                simple_e_fmt!("Failed executing SQL statement `{}`.", statement)
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Whether the index has ever published an expiry date, as recorded by
+    /// `set_expiry_published`.
+    pub fn has_published_expiry(index_db: &Database) -> Result<bool, LpmError<SqlError>> {
+        if !has_table(index_db, "metadata")? {
+            return Ok(false);
+        }
+
+        let statement = format!("SELECT 1 FROM metadata WHERE key = '{EXPIRY_PUBLISHED_KEY}';");
+        let mut sql = index_db.prepare(statement.clone(), SQL_NO_CALLBACK_FN)?;
+        let status = try_execute_prepared!(
+            sql,
+            simple_e_fmt!("Failed executing SQL statement `{}`.", statement)
+        );
+
+        Ok(status == PreparedStatementStatus::FoundRow)
+    }
+
+    /// Records that the index has published an expiry date. The patches can
+    /// write the metadata as well, so it's called after each of them is
+    /// applied.
+    pub fn set_expiry_published(index_db: &Database) -> Result<(), LpmError<SqlError>> {
+        let statements = [
+            String::from(
+                "CREATE TABLE IF NOT EXISTS metadata (key TEXT NOT NULL UNIQUE, value TEXT);",
+            ),
+            format!("INSERT OR IGNORE INTO metadata (key, value) VALUES ('{EXPIRY_PUBLISHED_KEY}', '1');"),
+        ];
+
+        for statement in statements {
+            let mut sql = index_db.prepare(statement.clone(), SQL_NO_CALLBACK_FN)?;
+            try_execute_prepared!(
+                sql,
+                simple_e_fmt!("Failed executing SQL statement `{}`.", statement)
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Returns the expiry date(unix timestamp) that the repository published
+    /// in the `metadata` table of its index, `None` if it doesn't publish one.
+    pub fn metadata_expires_at(index_db: &Database) -> Result<Option<i64>, LpmError<SqlError>> {
+        let statement = String::from(
+            "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = 'metadata';",
+        );
+
+        let mut sql = index_db.prepare(statement.clone(), SQL_NO_CALLBACK_FN)?;
+        try_execute_prepared!(
+            sql,
+            simple_e_fmt!("Failed executing SQL statement `{}`.", statement)
+        );
+
+        let count: i64 = sql.get_data(0)?;
+        if count == 0 {
+            return Ok(None);
+        }
+
+        const KEY_COL_PRE_ID: usize = 1;
+        let statement = Select::new(
+            Some(vec![String::from("CAST(value AS INTEGER)")]),
+            String::from("metadata"),
+        )
+        .where_condition(Where::Equal(KEY_COL_PRE_ID, String::from("key")))
+        .to_string();
+
+        let mut sql = index_db.prepare(statement.clone(), SQL_NO_CALLBACK_FN)?;
+        try_bind_val!(sql, KEY_COL_PRE_ID, "expires_at");
+
+        let status = try_execute_prepared!(
+            sql,
+            simple_e_fmt!("Failed executing SQL statement `{}`.", statement)
+        );
+
+        if status != PreparedStatementStatus::FoundRow {
+            return Ok(None);
+        }
+
+        Ok(sql.get_data(0)?)
+    }
+
+    /// Whether a version of `name` newer than `version` is marked as a security
+    /// fix in the `is_security` column, which older indexes don't have.
+    pub fn has_security_update(
+        index_db: &Database,
+        name: &str,
+        version: &VersionStruct,
+    ) -> Result<bool, LpmError<SqlError>> {
+        let statement = String::from(
+            "SELECT COUNT(*) FROM pragma_table_info('repository') WHERE name = 'is_security';",
+        );
+
+        let mut sql = index_db.prepare(statement.clone(), SQL_NO_CALLBACK_FN)?;
+        try_execute_prepared!(
+            sql,
+            simple_e_fmt!("Failed executing SQL statement `{}`.", statement)
+        );
+
+        let count: i64 = sql.get_data(0)?;
+        if count == 0 {
+            return Ok(false);
+        }
+
+        let statement = String::from(
+            "SELECT COUNT(*) FROM repository WHERE name = ?1 AND is_security = 1 \
+             AND (v_major > ?2 OR (v_major = ?2 AND (v_minor > ?3 OR (v_minor = ?3 AND v_patch > ?4))));",
+        );
+
+        let mut sql = index_db.prepare(statement.clone(), SQL_NO_CALLBACK_FN)?;
+        try_bind_val!(sql, 1, name);
+        try_bind_val!(sql, 2, version.major);
+        try_bind_val!(sql, 3, version.minor);
+        try_bind_val!(sql, 4, version.patch);
+        try_execute_prepared!(
+            sql,
+            simple_e_fmt!("Failed executing SQL statement `{}`.", statement)
+        );
+
+        let count: i64 = sql.get_data(0)?;
+        Ok(count > 0)
+    }
+
+    /// Returns `OPTIONAL_COLUMNS` in order, replacing the ones missing in the
+    /// index with `NULL`.
+    fn optional_columns(index_db: &Database) -> Result<Vec<String>, LpmError<SqlError>> {
+        let mut columns = Vec::with_capacity(OPTIONAL_COLUMNS.len());
+
+        for column in OPTIONAL_COLUMNS {
+            let statement = String::from(
+                "SELECT COUNT(*) FROM pragma_table_info('repository') WHERE name = ?1;",
+            );
+
+            let mut sql = index_db.prepare(statement.clone(), SQL_NO_CALLBACK_FN)?;
+            try_bind_val!(sql, 1, column);
+            try_execute_prepared!(
+                sql,
+                simple_e_fmt!("Failed executing SQL statement `{}`.", statement)
+            );
+
+            let count: i64 = sql.get_data(0)?;
+            if count == 0 {
+                columns.push(String::from("NULL"));
+            } else {
+                columns.push(column.to_owned());
+            }
+        }
+
+        Ok(columns)
+    }
+
+    /// Whether the queries of `index_db` can be restricted to `archs`, older
+    /// indexes have no `arch` column.
+    fn restricts_archs(index_db: &Database, archs: &[String]) -> Result<bool, LpmError<SqlError>> {
+        Ok(!archs.is_empty() && has_column(index_db, "arch")?)
+    }
+
+    /// Entries are restricted to `archs` unless it's empty.
     fn abstract_index_query(
         index_db: &Database,
         pkg_to_query: &PkgToQuery,
+        archs: &[String],
         columns: Vec<String>,
     ) -> Result<Option<SqlStatement>, LpmError<SqlError>> {
         fn get_where_condition(condition: &Condition, col_id: usize, col_name: &str) -> Where {
@@ -75,6 +386,8 @@ impl PkgIndex {
         const V_MINOR_COL_PRE_ID: usize = 3;
         const V_PATCH_COL_PRE_ID: usize = 4;
         const V_TAG_COL_PRE_ID: usize = 5;
+        const SLOT_COL_PRE_ID: usize = 6;
+        const ARCH_COL_PRE_ID: usize = 7;
 
         let mut sql_builder = Select::new(Some(columns), String::from("repository"))
             .where_condition(Where::Equal(NAME_COL_PRE_ID, String::from("name")));
@@ -111,6 +424,26 @@ impl PkgIndex {
             ));
         }
 
+        // Older indexes have no `slot` column, nor slot-aware packages.
+        let slot = match &pkg_to_query.slot {
+            Some(slot) if has_column(index_db, "slot")? => Some(slot.as_str()),
+            _ => None,
+        };
+        if slot.is_some() {
+            sql_builder = sql_builder.and_where(Where::Equal(
+                SLOT_COL_PRE_ID,
+                String::from("IFNULL(slot, '')"),
+            ));
+        }
+
+        let arch_ids: Vec<usize> = match Self::restricts_archs(index_db, archs)? {
+            true => (ARCH_COL_PRE_ID..ARCH_COL_PRE_ID + archs.len()).collect(),
+            false => Vec::new(),
+        };
+        if !arch_ids.is_empty() {
+            sql_builder = sql_builder.and_where(Where::In(arch_ids.clone(), arch_expression()));
+        }
+
         sql_builder = sql_builder
             .add_arg(SelectArg::OrderBy(vec![
                 OrderType::Desc(String::from("v_major")),
@@ -128,6 +461,10 @@ impl PkgIndex {
         try_bind_val_if_some!(sql, V_MINOR_COL_PRE_ID, pkg_to_query.minor);
         try_bind_val_if_some!(sql, V_PATCH_COL_PRE_ID, pkg_to_query.patch);
         try_bind_val_if_some!(sql, V_TAG_COL_PRE_ID, pkg_to_query.tag.as_deref());
+        try_bind_val_if_some!(sql, SLOT_COL_PRE_ID, slot);
+        for (id, arch) in arch_ids.into_iter().zip(archs) {
+            try_bind_val!(sql, id, arch.as_str());
+        }
 
         let status = try_execute_prepared!(
             sql,
@@ -144,17 +481,20 @@ impl PkgIndex {
     pub fn query_pkg_with_versions(
         index_db: &Database,
         pkg_to_query: &PkgToQuery,
+        archs: &[String],
+        repository_name: String,
         repository_address: String,
     ) -> Result<Option<Self>, LpmError<SqlError>> {
-        let columns = vec![
+        let mut columns = vec![
             String::from("v_major"),
             String::from("v_minor"),
             String::from("v_patch"),
             String::from("v_tag"),
             String::from("v_readable"),
         ];
+        columns.extend(Self::optional_columns(index_db)?);
 
-        let sql = Self::abstract_index_query(index_db, pkg_to_query, columns)?;
+        let sql = Self::abstract_index_query(index_db, pkg_to_query, archs, columns)?;
 
         if let Some(sql) = sql {
             let version = VersionStruct {
@@ -168,14 +508,147 @@ impl PkgIndex {
 
             Ok(Some(Self {
                 name: pkg_to_query.name.clone(),
+                repository_name,
                 repository_address,
                 version,
+                archive_checksum: sql.get_data(5)?,
+                archive_size: sql.get_data(6)?,
+                installed_size: sql.get_data(7)?,
+                kind: sql
+                    .get_data::<Option<String>>(8)?
+                    .as_deref()
+                    .and_then(PackageKind::parse),
+                description: sql.get_data(9)?,
+                signing_key: Self::signing_key(index_db)?,
             }))
         } else {
             Ok(None)
         }
     }
 
+    /// Returns the most recent version of each package whose name contains
+    /// `pattern` and is built for one of `archs`(any if empty), ordered by
+    /// name. An empty `pattern` matches every package.
+    pub fn search(
+        index_db: &Database,
+        pattern: &str,
+        archs: &[String],
+        repository_name: &str,
+        repository_address: &str,
+    ) -> Result<Vec<Self>, LpmError<SqlError>> {
+        let mut columns = vec![
+            String::from("name"),
+            String::from("v_major"),
+            String::from("v_minor"),
+            String::from("v_patch"),
+            String::from("v_tag"),
+            String::from("v_readable"),
+        ];
+        columns.extend(Self::optional_columns(index_db)?);
+
+        let arch_condition = match Self::restricts_archs(index_db, archs)? {
+            true => format!(
+                " AND {} IN ({})",
+                arch_expression(),
+                placeholders(2, archs.len())
+            ),
+            false => String::new(),
+        };
+
+        let statement = format!(
+            "SELECT {} FROM repository WHERE name LIKE ?1 ESCAPE '\\'{arch_condition} \
+             ORDER BY name, v_major DESC, v_minor DESC, v_patch DESC;",
+            columns.join(", ")
+        );
+
+        let escaped: String = pattern
+            .chars()
+            .flat_map(|c| match c {
+                '%' | '_' | '\\' => vec!['\\', c],
+                c => vec![c],
+            })
+            .collect();
+
+        let mut sql = index_db.prepare(statement, SQL_NO_CALLBACK_FN)?;
+        try_bind_val!(sql, 1, format!("%{escaped}%"));
+        if !arch_condition.is_empty() {
+            for (i, arch) in archs.iter().enumerate() {
+                try_bind_val!(sql, i + 2, arch.as_str());
+            }
+        }
+
+        let signing_key = Self::signing_key(index_db)?;
+        let mut pkgs: Vec<Self> = Vec::new();
+        while let PreparedStatementStatus::FoundRow = sql.execute_prepared() {
+            let name: String = sql.get_data(0)?;
+
+            // Older versions come after the most recent one.
+            if pkgs.last().map(|t| &t.name) == Some(&name) {
+                continue;
+            }
+
+            pkgs.push(Self {
+                name,
+                repository_name: repository_name.to_owned(),
+                repository_address: repository_address.to_owned(),
+                version: VersionStruct {
+                    major: sql.get_data(1)?,
+                    minor: sql.get_data(2)?,
+                    patch: sql.get_data(3)?,
+                    tag: sql.get_data(4)?,
+                    readable_format: sql.get_data(5)?,
+                    condition: Condition::default(),
+                },
+                archive_checksum: sql.get_data(6)?,
+                archive_size: sql.get_data(7)?,
+                installed_size: sql.get_data(8)?,
+                kind: sql
+                    .get_data::<Option<String>>(9)?
+                    .as_deref()
+                    .and_then(PackageKind::parse),
+                description: sql.get_data(10)?,
+                signing_key: signing_key.clone(),
+            });
+        }
+
+        Ok(pkgs)
+    }
+
+    /// Fills the archive checksum, archive size, installed size, kind and
+    /// description from the index entry of the exact version built for one of
+    /// `archs`, and the key that the index is signed with.
+    pub fn load_archive_info(
+        &mut self,
+        index_db: &Database,
+        archs: &[String],
+    ) -> Result<(), LpmError<SqlError>> {
+        let pkg_to_query = PkgToQuery {
+            name: self.name.clone(),
+            condition: Condition::Equal,
+            major: Some(self.version.major),
+            minor: Some(self.version.minor),
+            patch: Some(self.version.patch),
+            tag: self.version.tag.clone(),
+            slot: None,
+        };
+
+        let columns = Self::optional_columns(index_db)?;
+
+        if let Some(sql) = Self::abstract_index_query(index_db, &pkg_to_query, archs, columns)? {
+            self.archive_checksum = sql.get_data(0)?;
+            self.archive_size = sql.get_data(1)?;
+            self.installed_size = sql.get_data(2)?;
+            self.kind = sql
+                .get_data::<Option<String>>(3)?
+                .as_deref()
+                .and_then(PackageKind::parse);
+            self.description = sql.get_data(4)?;
+        }
+        self.signing_key = Self::signing_key(index_db)?;
+
+        Ok(())
+    }
+
     pub fn pkg_url(&self) -> String {
         format!(
             "{}/{}-{}.lod",
@@ -183,6 +656,20 @@ impl PkgIndex {
         )
     }
 
+    /// Urls of the package on each of `mirrors` and then on the repository
+    /// itself(unless it's one of them), in the order they should be tried.
+    pub fn pkg_urls(&self, mirrors: &[String]) -> Vec<String> {
+        let mut urls: Vec<String> = mirrors
+            .iter()
+            .map(|t| format!("{t}/{}", self.pkg_filename()))
+            .collect();
+        if !mirrors.contains(&self.repository_address) {
+            urls.push(self.pkg_url());
+        }
+
+        urls
+    }
+
     pub fn pkg_filename(&self) -> String {
         format!("{}-{}.lod", self.name, self.version.readable_format)
     }
@@ -191,17 +678,19 @@ impl PkgIndex {
         format!("{}@{}", self.name, self.version.readable_format)
     }
 
-    pub fn pkg_output_path(&self, output_dir: &str) -> PathBuf {
-        PathBuf::from(output_dir).join(self.pkg_filename())
+    pub fn pkg_output_path(&self, output_dir: impl AsRef<Path>) -> PathBuf {
+        output_dir.as_ref().join(self.pkg_filename())
     }
 
     pub fn get_mandatory_dependencies(
         index_db: &Database,
         pkg_to_query: &PkgToQuery,
+        archs: &[String],
     ) -> Result<Vec<String>, LpmError<SqlError>> {
         let sql = Self::abstract_index_query(
             index_db,
             pkg_to_query,
+            archs,
             vec![String::from("mandatory_dependencies")],
         )?;
 
@@ -223,3 +712,14 @@ impl PkgIndex {
         }
     }
 }
+
+/// Architecture of an entry, ones without it are taken as `no-arch`.
+fn arch_expression() -> String {
+    format!("IFNULL(LOWER(arch), '{NO_ARCH}')")
+}
+
+/// `?first, ?first+1, ...` for `count` values.
+fn placeholders(first: usize, count: usize) -> String {
+    let ids: Vec<String> = (first..first + count).map(|t| format!("?{t}")).collect();
+    ids.join(", ")
+}
@@ -0,0 +1,137 @@
+//! Schema versions of the repository indexes.
+//!
+//! Servers publish the schema of their index as `schema_version` in its
+//! `metadata` table, indexes without one are version 1. Older indexes are
+//! migrated locally up to `INDEX_SCHEMA_VERSION`, newer ones can't be read by
+//! this version of lpm.
+
+#![allow(clippy::disallowed_methods)]
+
+use crate::SQL_NO_CALLBACK_FN;
+
+use ehandle::{
+    db::{SqlError, SqlErrorKind},
+    lpm::LpmError,
+    simple_e_fmt, try_bind_val, try_execute_prepared, ErrorCommons,
+};
+use min_sqlite3_sys::prelude::*;
+
+/// Newest index schema that this lpm understands.
+pub const INDEX_SCHEMA_VERSION: u32 = 2;
+
+type IndexMigration = fn(&Database) -> Result<(), LpmError<SqlError>>;
+
+/// Migrations to each version after 1, in order.
+const INDEX_MIGRATIONS: [(u32, IndexMigration); 1] = [(2, add_optional_columns)];
+
+pub(crate) fn has_table(index_db: &Database, table: &str) -> Result<bool, LpmError<SqlError>> {
+    let statement =
+        String::from("SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = ?1;");
+
+    let mut sql = index_db.prepare(statement.clone(), SQL_NO_CALLBACK_FN)?;
+    try_bind_val!(sql, 1, table);
+    try_execute_prepared!(
+        sql,
+        simple_e_fmt!("Failed executing SQL statement `{}`.", statement)
+    );
+
+    Ok(sql.get_data::<i64>(0)? > 0)
+}
+
+pub(crate) fn has_column(index_db: &Database, column: &str) -> Result<bool, LpmError<SqlError>> {
+    let statement =
+        String::from("SELECT COUNT(*) FROM pragma_table_info('repository') WHERE name = ?1;");
+
+    let mut sql = index_db.prepare(statement.clone(), SQL_NO_CALLBACK_FN)?;
+    try_bind_val!(sql, 1, column);
+    try_execute_prepared!(
+        sql,
+        simple_e_fmt!("Failed executing SQL statement `{}`.", statement)
+    );
+
+    Ok(sql.get_data::<i64>(0)? > 0)
+}
+
+fn execute(index_db: &Database, statement: String) -> Result<(), LpmError<SqlError>> {
+    match index_db.execute(statement.clone(), SQL_NO_CALLBACK_FN)? {
+        SqlitePrimaryResult::Ok => Ok(()),
+        e => Err(SqlErrorKind::FailedExecuting(statement, e).to_lpm_err()),
+    }
+}
+
+/// Schema version of `index_db`, 1 for indexes that don't publish one.
+pub fn index_schema_version(index_db: &Database) -> Result<u32, LpmError<SqlError>> {
+    if !has_table(index_db, "metadata")? {
+        return Ok(1);
+    }
+
+    let statement =
+        String::from("SELECT CAST(value AS INTEGER) FROM metadata WHERE key = 'schema_version';");
+    let mut sql = index_db.prepare(statement.clone(), SQL_NO_CALLBACK_FN)?;
+    let status = try_execute_prepared!(
+        sql,
+        simple_e_fmt!("Failed executing SQL statement `{}`.", statement)
+    );
+
+    if status != PreparedStatementStatus::FoundRow {
+        return Ok(1);
+    }
+
+    let version: Option<i64> = sql.get_data(0)?;
+    Ok(version.map_or(1, |t| t.clamp(1, u32::MAX as i64) as u32))
+}
+
+/// Brings `index_db` from an older schema up to `INDEX_SCHEMA_VERSION`, the
+/// caller must reject newer ones. Indexes that are not synced yet are left
+/// alone.
+pub fn migrate_index(index_db: &Database) -> Result<(), LpmError<SqlError>> {
+    if !has_table(index_db, "repository")? {
+        return Ok(());
+    }
+
+    let version = index_schema_version(index_db)?;
+    for (target, migration) in INDEX_MIGRATIONS {
+        if target > version {
+            migration(index_db)?;
+            set_index_schema_version(index_db, target)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn set_index_schema_version(index_db: &Database, version: u32) -> Result<(), LpmError<SqlError>> {
+    execute(
+        index_db,
+        String::from("CREATE TABLE IF NOT EXISTS metadata (key TEXT NOT NULL UNIQUE, value TEXT);"),
+    )?;
+    execute(
+        index_db,
+        format!(
+            "DELETE FROM metadata WHERE key = 'schema_version';
+            INSERT INTO metadata (key, value) VALUES ('schema_version', '{version}');"
+        ),
+    )
+}
+
+/// Version 2 has the archive checks and the security flags, which versions 1
+/// indexes may have some of.
+fn add_optional_columns(index_db: &Database) -> Result<(), LpmError<SqlError>> {
+    let columns = [
+        ("archive_checksum", "TEXT"),
+        ("archive_size", "INTEGER"),
+        ("installed_size", "INTEGER"),
+        ("is_security", "INTEGER NOT NULL DEFAULT 0"),
+    ];
+
+    for (column, definition) in columns {
+        if !has_column(index_db, column)? {
+            execute(
+                index_db,
+                format!("ALTER TABLE repository ADD COLUMN {column} {definition};"),
+            )?;
+        }
+    }
+
+    Ok(())
+}
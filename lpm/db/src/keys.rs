@@ -0,0 +1,81 @@
+use ehandle::{
+    db::SqlError, lpm::LpmError, simple_e_fmt, try_bind_val, try_execute_prepared, ErrorCommons,
+};
+use min_sqlite3_sys::prelude::*;
+use sql_builder::delete::*;
+use sql_builder::insert::Insert;
+use sql_builder::select::*;
+use sql_builder::Column;
+
+/// Keys trusted or revoked in the database, the public key is `None` for the
+/// revoked ones.
+pub fn get_trusted_keys(
+    core_db: &Database,
+) -> Result<Vec<(String, Option<String>)>, LpmError<SqlError>> {
+    let statement = Select::new(
+        Some(vec![String::from("key_id"), String::from("public_key")]),
+        String::from("trusted_keys"),
+    )
+    .to_string();
+
+    let mut sql = core_db.prepare(statement, super::SQL_NO_CALLBACK_FN)?;
+
+    let mut result = vec![];
+    while let PreparedStatementStatus::FoundRow = sql.execute_prepared() {
+        result.push((sql.get_data(0)?, sql.get_data(1)?));
+    }
+
+    Ok(result)
+}
+
+/// Trusts `public_key` as `key_id`, replacing what was stored for it.
+pub fn trust_key(
+    core_db: &Database,
+    key_id: &str,
+    public_key: &str,
+) -> Result<PreparedStatementStatus, LpmError<SqlError>> {
+    replace_key(core_db, key_id, Some(public_key))
+}
+
+/// Revokes `key_id`, which wins over trusting it anywhere else.
+pub fn revoke_key(
+    core_db: &Database,
+    key_id: &str,
+) -> Result<PreparedStatementStatus, LpmError<SqlError>> {
+    replace_key(core_db, key_id, None)
+}
+
+fn replace_key(
+    core_db: &Database,
+    key_id: &str,
+    public_key: Option<&str>,
+) -> Result<PreparedStatementStatus, LpmError<SqlError>> {
+    const KEY_ID_COL_PRE_ID: usize = 1;
+    const PUBLIC_KEY_COL_PRE_ID: usize = 2;
+
+    let statement = Delete::new(String::from("trusted_keys"))
+        .where_condition(Where::Equal(KEY_ID_COL_PRE_ID, String::from("key_id")))
+        .to_string();
+    let mut sql = core_db.prepare(statement, super::SQL_NO_CALLBACK_FN)?;
+    try_bind_val!(sql, KEY_ID_COL_PRE_ID, key_id);
+    try_execute_prepared!(sql, simple_e_fmt!("Error on deleting key '{key_id}'"));
+
+    let columns = vec![
+        Column::new(String::from("key_id"), KEY_ID_COL_PRE_ID),
+        Column::new(String::from("public_key"), PUBLIC_KEY_COL_PRE_ID),
+    ];
+    let statement = Insert::new(Some(columns), String::from("trusted_keys")).to_string();
+    let mut sql = core_db.prepare(statement, super::SQL_NO_CALLBACK_FN)?;
+
+    try_bind_val!(sql, KEY_ID_COL_PRE_ID, key_id);
+    if let Some(public_key) = public_key {
+        try_bind_val!(sql, PUBLIC_KEY_COL_PRE_ID, public_key);
+    } else {
+        try_bind_val!(sql, PUBLIC_KEY_COL_PRE_ID, SQLITE_NULL);
+    }
+
+    logger::debug!("Storing key '{key_id}'");
+    let status = try_execute_prepared!(sql, simple_e_fmt!("Error on storing key '{key_id}'"));
+
+    Ok(status)
+}
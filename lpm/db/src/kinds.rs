@@ -0,0 +1,40 @@
+use common::meta::PackageKind;
+use ehandle::{db::SqlError, lpm::LpmError, try_bind_val, ErrorCommons};
+use min_sqlite3_sys::prelude::*;
+
+/// Id of `kind` in the `package_kinds` table, `None` if it's not registered.
+pub(crate) fn get_kind_id(
+    core_db: &Database,
+    kind: PackageKind,
+) -> Result<Option<i64>, LpmError<SqlError>> {
+    let statement = String::from("SELECT id FROM package_kinds WHERE name = ?1;");
+
+    let mut sql = core_db.prepare(statement, super::SQL_NO_CALLBACK_FN)?;
+    try_bind_val!(sql, 1, kind.as_str());
+
+    match sql.execute_prepared() {
+        PreparedStatementStatus::FoundRow => Ok(Some(sql.get_data(0)?)),
+        _ => Ok(None),
+    }
+}
+
+/// Kind of the `kind_id` row, `None` for the packages without a kind and for
+/// the kinds that this lpm doesn't know of.
+pub(crate) fn get_kind(
+    core_db: &Database,
+    kind_id: Option<i64>,
+) -> Result<Option<PackageKind>, LpmError<SqlError>> {
+    let Some(kind_id) = kind_id else {
+        return Ok(None);
+    };
+
+    let statement = String::from("SELECT name FROM package_kinds WHERE id = ?1;");
+
+    let mut sql = core_db.prepare(statement, super::SQL_NO_CALLBACK_FN)?;
+    try_bind_val!(sql, 1, kind_id);
+
+    match sql.execute_prepared() {
+        PreparedStatementStatus::FoundRow => Ok(PackageKind::parse(&sql.get_data::<String>(0)?)),
+        _ => Ok(None),
+    }
+}
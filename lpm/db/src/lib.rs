@@ -5,22 +5,78 @@ use ehandle::{
 };
 use min_sqlite3_sys::prelude::*;
 
+pub use alternatives::{
+    get_alternatives, get_package_alternatives, get_selected_alternative, select_alternative,
+    Alternative,
+};
+pub use config_files::{
+    delete_retained_config_files, get_package_config_files, get_retained_config_files,
+};
 pub use index::PkgIndex;
-pub use migrations::migrate_database_tables;
+pub use index_schema::{index_schema_version, migrate_index, INDEX_SCHEMA_VERSION};
+pub use keys::{get_trusted_keys, revoke_key, trust_key};
+pub use migrations::{
+    get_migration_status, migrate_database_tables, revert_migrations, MigrationStatus,
+};
 pub use module::{
-    delete_modules, get_dylib_path_by_name, get_modules, insert_module, is_module_exists,
+    delete_modules, get_dylib_path_by_name, get_module_summaries, get_modules, has_module_db_write,
+    insert_module, is_module_exists,
 };
+pub use relations::get_dependents;
 pub use repository::{
-    delete_repositories, get_repositories, insert_repository, is_repository_exists,
+    delete_repositories, get_repositories, get_repository_options, insert_repository,
+    is_repository_exists, update_repository_options, RepositoryOptions, SignatureLevel,
 };
 
 pub const REPOSITORY_INDEX_DB_DIR: &str = "/var/lib/lpm/db/repositories";
 pub const CORE_DB_PATH: &str = "/var/lib/lpm/db/core-db";
+/// Scripts and meta data of the installed packages, one directory per package.
+pub const PKG_DATA_DIR: &str = "/var/lib/lpm/pkg";
+
+/// Name of the directory of a package in `PKG_DATA_DIR`. Each slot of a
+/// slot-aware package has its own, e.g. `python3@3.11`, `@` not being allowed
+/// in package names.
+pub fn pkg_data_dir_name(name: &str, slot: Option<&str>) -> String {
+    match slot.filter(|t| !t.is_empty()) {
+        Some(slot) => format!("{name}@{slot}"),
+        None => name.to_owned(),
+    }
+}
 
 pub const SQL_NO_CALLBACK_FN: Option<
     Box<dyn FnOnce(min_sqlite3_sys::bindings::SqlitePrimaryResult, String)>,
 > = None::<Box<dyn FnOnce(SqlitePrimaryResult, String)>>;
 
+/// Runs SQLite's integrity and foreign key checks on `any_db`, returns the
+/// problems found.
+pub fn check_integrity(any_db: &Database) -> Result<Vec<String>, LpmError<SqlError>> {
+    let mut problems = vec![];
+
+    let mut sql = any_db.prepare(String::from("PRAGMA integrity_check;"), SQL_NO_CALLBACK_FN)?;
+    while let PreparedStatementStatus::FoundRow = sql.execute_prepared() {
+        let result: String = sql.get_data(0)?;
+        if result != "ok" {
+            problems.push(result);
+        }
+    }
+
+    let mut sql = any_db.prepare(
+        String::from("PRAGMA foreign_key_check;"),
+        SQL_NO_CALLBACK_FN,
+    )?;
+    while let PreparedStatementStatus::FoundRow = sql.execute_prepared() {
+        let table: String = sql.get_data(0)?;
+        let row_id: Option<i64> = sql.get_data(1)?;
+        let parent: String = sql.get_data(2)?;
+        problems.push(format!(
+            "Row {} of '{table}' references a missing '{parent}' entry.",
+            row_id.unwrap_or_default()
+        ));
+    }
+
+    Ok(problems)
+}
+
 #[allow(clippy::disallowed_methods)]
 pub fn enable_foreign_keys(any_db: &Database) -> Result<(), LpmError<SqlError>> {
     any_db.execute(
@@ -31,6 +87,20 @@ pub fn enable_foreign_keys(any_db: &Database) -> Result<(), LpmError<SqlError>>
     Ok(())
 }
 
+/// Pragmas of connections that only run queries, which must not touch the
+/// journal mode.
+#[allow(clippy::disallowed_methods)]
+pub fn enable_read_only_pragmas(core_db: &Database) -> Result<(), LpmError<SqlError>> {
+    core_db.execute(String::from("PRAGMA query_only = on;"), SQL_NO_CALLBACK_FN)?;
+
+    core_db.execute(
+        String::from("PRAGMA temp_storage = memory;"),
+        SQL_NO_CALLBACK_FN,
+    )?;
+
+    Ok(())
+}
+
 #[allow(clippy::disallowed_methods)]
 pub fn enable_core_db_pragmas(core_db: &Database) -> Result<(), LpmError<SqlError>> {
     core_db.execute(
@@ -114,8 +184,15 @@ pub fn get_current_datetime(any_db: &Database) -> Result<String, LpmError<SqlErr
     Ok(data)
 }
 
+mod alternatives;
+mod config_files;
 mod index;
+mod index_schema;
+mod keys;
+mod kinds;
 mod migrations;
 mod module;
 pub mod pkg;
+mod relations;
 mod repository;
+mod scripts;
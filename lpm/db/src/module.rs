@@ -11,13 +11,19 @@ pub fn insert_module(
     core_db: &Database,
     name: &str,
     dylib_path: &str,
+    db_write: bool,
+    summary: Option<&str>,
 ) -> Result<PreparedStatementStatus, LpmError<SqlError>> {
     const NAME_COL_PRE_ID: usize = 1;
     const DYLIB_PATH_COL_PRE_ID: usize = 2;
+    const DB_WRITE_COL_PRE_ID: usize = 3;
+    const SUMMARY_COL_PRE_ID: usize = 4;
 
     let module_columns = vec![
         Column::new(String::from("name"), NAME_COL_PRE_ID),
         Column::new(String::from("dylib_path"), DYLIB_PATH_COL_PRE_ID),
+        Column::new(String::from("db_write"), DB_WRITE_COL_PRE_ID),
+        Column::new(String::from("summary"), SUMMARY_COL_PRE_ID),
     ];
 
     let sql_builder = Insert::new(Some(module_columns), String::from("modules"));
@@ -28,8 +34,16 @@ pub fn insert_module(
 
     try_bind_val!(sql, NAME_COL_PRE_ID, name);
     try_bind_val!(sql, DYLIB_PATH_COL_PRE_ID, dylib_path);
+    try_bind_val!(sql, DB_WRITE_COL_PRE_ID, db_write as i64);
+    if let Some(summary) = summary {
+        try_bind_val!(sql, SUMMARY_COL_PRE_ID, summary);
+    } else {
+        try_bind_val!(sql, SUMMARY_COL_PRE_ID, SQLITE_NULL);
+    }
 
-    logger::debug!("Inserting module\n  name: {name}\n  dylib_path: {dylib_path}");
+    logger::debug!(
+        "Inserting module\n  name: {name}\n  dylib_path: {dylib_path}\n  db_write: {db_write}"
+    );
     let status = try_execute_prepared!(sql, simple_e_fmt!("Error on inserting module {name}"));
 
     Ok(status)
@@ -110,6 +124,28 @@ pub fn get_dylib_path_by_name(
     Ok(result)
 }
 
+/// Whether the module was added with write access to the core database.
+pub fn has_module_db_write(core_db: &Database, name: &str) -> Result<bool, LpmError<SqlError>> {
+    const NAME_COL_PRE_ID: usize = 1;
+    let statement = Select::new(
+        Some(vec![String::from("db_write")]),
+        String::from("modules"),
+    )
+    .where_condition(Where::Equal(NAME_COL_PRE_ID, String::from("name")))
+    .to_string();
+
+    let mut sql = core_db.prepare(statement.clone(), super::SQL_NO_CALLBACK_FN)?;
+
+    try_bind_val!(sql, NAME_COL_PRE_ID, name);
+
+    try_execute_prepared!(
+        sql,
+        simple_e_fmt!("Select db_write query failed. SQL:\n {}", statement)
+    );
+
+    Ok(sql.get_data::<i64>(0).unwrap_or(0) == 1)
+}
+
 pub fn get_modules(core_db: &Database) -> Result<Vec<(String, String)>, LpmError<SqlError>> {
     let select_statement = Select::new(None, String::from("modules")).to_string();
 
@@ -122,3 +158,23 @@ pub fn get_modules(core_db: &Database) -> Result<Vec<(String, String)>, LpmError
 
     Ok(result)
 }
+
+/// Names of the modules along with the summaries recorded when they were added.
+pub fn get_module_summaries(
+    core_db: &Database,
+) -> Result<Vec<(String, Option<String>)>, LpmError<SqlError>> {
+    let statement = Select::new(
+        Some(vec![String::from("name"), String::from("summary")]),
+        String::from("modules"),
+    )
+    .to_string();
+
+    let mut sql = core_db.prepare(statement, super::SQL_NO_CALLBACK_FN)?;
+
+    let mut result = vec![];
+    while let PreparedStatementStatus::FoundRow = sql.execute_prepared() {
+        result.push((sql.get_data(0)?, sql.get_data(1)?));
+    }
+
+    Ok(result)
+}
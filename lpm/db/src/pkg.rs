@@ -1,27 +1,44 @@
-use crate::{enable_foreign_keys, transaction_op, Transaction};
-
-use common::meta::FileStruct;
+use crate::alternatives::{delete_alternatives, insert_alternatives};
+use crate::config_files::{delete_config_files, get_package_config_files, insert_config_files};
+use crate::enable_foreign_keys;
+use crate::kinds::{get_kind, get_kind_id};
+use crate::relations::{delete_relations, insert_relations};
+use crate::scripts::{delete_scripts, get_package_scripts, insert_scripts};
+
+use common::arch;
+use common::meta::{FileKind, FileStruct};
 use common::pkg::MetaDir;
 use common::pkg::PkgDataFromDb;
 use common::pkg::PkgDataFromFs;
+use common::pkg::PkgOrigin;
 use common::version::Condition;
-use common::{meta::Meta, version::VersionStruct, Files};
+use common::{meta::Meta, version::VersionStruct, Files, NO_ARCH, SYSTEM_ARCH};
 use ehandle::{
-    db::SqlError,
+    db::{SqlError, SqlErrorKind},
     lpm::LpmError,
     pkg::{PackageError, PackageErrorKind},
     simple_e_fmt, try_bind_val, try_execute_prepared, ErrorCommons,
 };
 use logger::info;
 use min_sqlite3_sys::prelude::*;
+use min_sqlite3_sys::statement::SqlStatement;
 use sql_builder::delete::*;
 use sql_builder::insert::*;
 use sql_builder::select::*;
 use sql_builder::update::Update;
 use sql_builder::Column;
-use std::path::Path;
 use std::path::PathBuf;
 
+/// `essential`, `kind_id`, `repository`, `index_timestamp`, `maintainer` and
+/// `signing_key` were added by migrations, so they come after the timestamp
+/// columns when reading whole `packages` rows.
+const ESSENTIAL_COL_ID: usize = 13;
+const KIND_ID_COL_ID: usize = 14;
+const REPOSITORY_COL_ID: usize = 15;
+const INDEX_TIMESTAMP_COL_ID: usize = 16;
+const MAINTAINER_COL_ID: usize = 17;
+const SIGNING_KEY_COL_ID: usize = 18;
+
 pub trait DbOpsForInstalledPkg {
     const PKG_ID_COL_PRE_ID: usize = 0;
     const NAME_COL_PRE_ID: usize = 1;
@@ -32,6 +49,9 @@ pub trait DbOpsForInstalledPkg {
     const V_PATCH_COL_PRE_ID: usize = 6;
     const V_TAG_COL_PRE_ID: usize = 7;
     const V_READABLE_COL_PRE_ID: usize = 8;
+    const ARCH_COL_PRE_ID: usize = 9;
+    const SLOT_COL_PRE_ID: usize = 10;
+    const ESSENTIAL_COL_PRE_ID: usize = 11;
 
     fn load(core_db: &Database, name: &str) -> Result<Self, LpmError<PackageError>>
     where
@@ -42,6 +62,10 @@ pub trait DbOpsForInstalledPkg {
     where
         Self: Sized;
 
+    fn load_all_packages(core_db: &Database) -> Result<Vec<Self>, LpmError<PackageError>>
+    where
+        Self: Sized;
+
     fn delete_from_db(&self, core_db: &Database) -> Result<(), LpmError<PackageError>>;
 }
 
@@ -54,6 +78,12 @@ pub trait DbOpsForBuildFile {
     const V_PATCH_COL_PRE_ID: usize = 6;
     const V_TAG_COL_PRE_ID: usize = 7;
     const V_READABLE_COL_PRE_ID: usize = 8;
+    const ARCH_COL_PRE_ID: usize = 9;
+    const SLOT_COL_PRE_ID: usize = 10;
+    const ESSENTIAL_COL_PRE_ID: usize = 11;
+    const KIND_ID_COL_PRE_ID: usize = 12;
+    const MAINTAINER_COL_PRE_ID: usize = 13;
+    const SIGNING_KEY_COL_PRE_ID: usize = 14;
 
     fn insert_to_db(
         &self,
@@ -87,6 +117,12 @@ impl DbOpsForBuildFile for PkgDataFromFs {
             Column::new(String::from("v_patch"), Self::V_PATCH_COL_PRE_ID),
             Column::new(String::from("v_tag"), Self::V_TAG_COL_PRE_ID),
             Column::new(String::from("v_readable"), Self::V_READABLE_COL_PRE_ID),
+            Column::new(String::from("arch"), Self::ARCH_COL_PRE_ID),
+            Column::new(String::from("slot"), Self::SLOT_COL_PRE_ID),
+            Column::new(String::from("essential"), Self::ESSENTIAL_COL_PRE_ID),
+            Column::new(String::from("kind_id"), Self::KIND_ID_COL_PRE_ID),
+            Column::new(String::from("maintainer"), Self::MAINTAINER_COL_PRE_ID),
+            Column::new(String::from("signing_key"), Self::SIGNING_KEY_COL_PRE_ID),
         ];
 
         let statement = Insert::new(Some(package_columns), String::from("packages")).to_string();
@@ -131,6 +167,43 @@ impl DbOpsForBuildFile for PkgDataFromFs {
             &*self.meta_dir.meta.version.readable_format
         );
 
+        try_bind_val!(
+            sql,
+            Self::ARCH_COL_PRE_ID,
+            &*arch::normalize(&self.meta_dir.meta.arch)
+        );
+        try_bind_val!(
+            sql,
+            Self::SLOT_COL_PRE_ID,
+            self.meta_dir.meta.slot.as_deref().unwrap_or_default()
+        );
+        try_bind_val!(
+            sql,
+            Self::ESSENTIAL_COL_PRE_ID,
+            i64::from(self.meta_dir.meta.essential)
+        );
+
+        let kind_id = match self.meta_dir.meta.kind {
+            Some(kind) => get_kind_id(core_db, kind)?,
+            None => None,
+        };
+        if let Some(kind_id) = kind_id {
+            try_bind_val!(sql, Self::KIND_ID_COL_PRE_ID, kind_id);
+        } else {
+            try_bind_val!(sql, Self::KIND_ID_COL_PRE_ID, SQLITE_NULL);
+        }
+
+        if let Some(maintainer) = &self.meta_dir.meta.maintainer {
+            try_bind_val!(sql, Self::MAINTAINER_COL_PRE_ID, &**maintainer);
+        } else {
+            try_bind_val!(sql, Self::MAINTAINER_COL_PRE_ID, SQLITE_NULL);
+        }
+        if let Some(signing_key) = self.origin.as_ref().and_then(|t| t.signing_key.as_deref()) {
+            try_bind_val!(sql, Self::SIGNING_KEY_COL_PRE_ID, signing_key);
+        } else {
+            try_bind_val!(sql, Self::SIGNING_KEY_COL_PRE_ID, SQLITE_NULL);
+        }
+
         let sql_status = sql.execute_prepared();
         if PreparedStatementStatus::Done != sql_status {
             logger::error!(
@@ -145,11 +218,23 @@ impl DbOpsForBuildFile for PkgDataFromFs {
         }
 
         let pkg_id = super::get_last_insert_row_id(core_db)?;
-
-        match insert_files(core_db, pkg_id, &self.meta_dir.files) {
-            Ok(_) => Ok(pkg_id),
-            Err(err) => Err(err),
-        }
+        set_package_origin(core_db, pkg_id, self.origin.as_ref())?;
+
+        insert_files(core_db, pkg_id, &self.meta_dir.files)?;
+        insert_alternatives(core_db, pkg_id, &self.meta_dir.meta.alternatives)?;
+        insert_config_files(
+            core_db,
+            pkg_id,
+            &self.meta_dir.meta.name,
+            &self
+                .meta_dir
+                .files
+                .config_paths(&self.meta_dir.meta.config_files),
+        )?;
+        insert_relations(core_db, pkg_id, &self.meta_dir.meta)?;
+        insert_scripts(core_db, pkg_id, self.meta_dir.meta.scripts.as_deref())?;
+
+        Ok(pkg_id)
     }
 
     fn update_existing_pkg(
@@ -160,8 +245,6 @@ impl DbOpsForBuildFile for PkgDataFromFs {
     ) -> Result<(), LpmError<PackageError>> {
         enable_foreign_keys(core_db)?;
 
-        transaction_op(core_db, Transaction::Begin)?;
-
         let update_fields = vec![
             Column::new(String::from("group_id"), Self::GROUP_ID_COL_PRE_ID),
             Column::new(
@@ -173,15 +256,22 @@ impl DbOpsForBuildFile for PkgDataFromFs {
             Column::new(String::from("v_patch"), Self::V_PATCH_COL_PRE_ID),
             Column::new(String::from("v_tag"), Self::V_TAG_COL_PRE_ID),
             Column::new(String::from("v_readable"), Self::V_READABLE_COL_PRE_ID),
+            Column::new(String::from("arch"), Self::ARCH_COL_PRE_ID),
+            Column::new(String::from("slot"), Self::SLOT_COL_PRE_ID),
+            Column::new(String::from("essential"), Self::ESSENTIAL_COL_PRE_ID),
+            Column::new(String::from("kind_id"), Self::KIND_ID_COL_PRE_ID),
+            Column::new(String::from("maintainer"), Self::MAINTAINER_COL_PRE_ID),
+            Column::new(String::from("signing_key"), Self::SIGNING_KEY_COL_PRE_ID),
         ];
 
+        const PKG_ID_PRE_ID: usize = 15;
         let statement = Update::new(update_fields, String::from("packages"))
-            .where_condition(Where::Equal(Self::NAME_COL_PRE_ID, String::from("name")))
+            .where_condition(Where::Equal(PKG_ID_PRE_ID, String::from("id")))
             .to_string();
 
         let mut sql = core_db.prepare(statement, super::SQL_NO_CALLBACK_FN)?;
 
-        try_bind_val!(sql, Self::NAME_COL_PRE_ID, &*self.meta_dir.meta.name);
+        try_bind_val!(sql, PKG_ID_PRE_ID, pkg_id);
 
         // TODO
         // Update all of old group_ids to new one
@@ -221,29 +311,70 @@ impl DbOpsForBuildFile for PkgDataFromFs {
             &*self.meta_dir.meta.version.readable_format
         );
 
-        if PreparedStatementStatus::Done != sql.execute_prepared() {
-            transaction_op(core_db, Transaction::Rollback)?;
+        try_bind_val!(
+            sql,
+            Self::ARCH_COL_PRE_ID,
+            &*arch::normalize(&self.meta_dir.meta.arch)
+        );
+        try_bind_val!(
+            sql,
+            Self::SLOT_COL_PRE_ID,
+            self.meta_dir.meta.slot.as_deref().unwrap_or_default()
+        );
+        try_bind_val!(
+            sql,
+            Self::ESSENTIAL_COL_PRE_ID,
+            i64::from(self.meta_dir.meta.essential)
+        );
+
+        let kind_id = match self.meta_dir.meta.kind {
+            Some(kind) => get_kind_id(core_db, kind)?,
+            None => None,
+        };
+        if let Some(kind_id) = kind_id {
+            try_bind_val!(sql, Self::KIND_ID_COL_PRE_ID, kind_id);
+        } else {
+            try_bind_val!(sql, Self::KIND_ID_COL_PRE_ID, SQLITE_NULL);
+        }
+
+        if let Some(maintainer) = &self.meta_dir.meta.maintainer {
+            try_bind_val!(sql, Self::MAINTAINER_COL_PRE_ID, &**maintainer);
+        } else {
+            try_bind_val!(sql, Self::MAINTAINER_COL_PRE_ID, SQLITE_NULL);
+        }
+        if let Some(signing_key) = self.origin.as_ref().and_then(|t| t.signing_key.as_deref()) {
+            try_bind_val!(sql, Self::SIGNING_KEY_COL_PRE_ID, signing_key);
+        } else {
+            try_bind_val!(sql, Self::SIGNING_KEY_COL_PRE_ID, SQLITE_NULL);
+        }
 
+        if PreparedStatementStatus::Done != sql.execute_prepared() {
             return Err(
                 PackageErrorKind::InstallationFailed(self.meta_dir.meta.name.clone()).to_lpm_err(),
             );
         }
 
-        match delete_pkg_files(core_db, pkg_id) {
-            Ok(_) => (),
-            Err(err) => {
-                transaction_op(core_db, Transaction::Rollback)?;
-                return Err(err)?;
-            }
-        };
+        set_package_origin(core_db, pkg_id, self.origin.as_ref())?;
+        delete_pkg_files(core_db, pkg_id)?;
+        insert_files(core_db, pkg_id, &self.meta_dir.files)?;
+        delete_alternatives(core_db, pkg_id)?;
+        insert_alternatives(core_db, pkg_id, &self.meta_dir.meta.alternatives)?;
+        delete_config_files(core_db, pkg_id)?;
+        insert_config_files(
+            core_db,
+            pkg_id,
+            &self.meta_dir.meta.name,
+            &self
+                .meta_dir
+                .files
+                .config_paths(&self.meta_dir.meta.config_files),
+        )?;
+        delete_relations(core_db, pkg_id)?;
+        insert_relations(core_db, pkg_id, &self.meta_dir.meta)?;
+        delete_scripts(core_db, pkg_id)?;
+        insert_scripts(core_db, pkg_id, self.meta_dir.meta.scripts.as_deref())?;
 
-        match insert_files(core_db, pkg_id, &self.meta_dir.files) {
-            Ok(_) => Ok(()),
-            Err(err) => {
-                transaction_op(core_db, Transaction::Rollback)?;
-                Err(err)
-            }
-        }
+        Ok(())
     }
 }
 
@@ -251,11 +382,56 @@ impl DbOpsForInstalledPkg for PkgDataFromDb {
     fn load(core_db: &Database, name: &str) -> Result<Self, LpmError<PackageError>> {
         info!("Loading '{}' from database..", name);
 
-        let statement = Select::new(None, String::from("packages"))
-            .where_condition(Where::Equal(Self::NAME_COL_PRE_ID, String::from("name")))
-            .to_string();
+        // `name:arch` selects a specific architecture variant, otherwise the
+        // native (or arch independent) one is preferred over foreign ones.
+        let (pkg_name, pkg_arch) = match name.split_once(':') {
+            Some((pkg_name, pkg_arch)) => (pkg_name, Some(arch::normalize(pkg_arch))),
+            None => (name, None),
+        };
+
+        // `name/slot` selects an installation of a slot-aware package,
+        // otherwise the highest slot is used.
+        let (pkg_name, pkg_slot) = match pkg_name.split_once('/') {
+            Some((pkg_name, pkg_slot)) => (pkg_name, Some(pkg_slot)),
+            None => (pkg_name, None),
+        };
+
+        const SYSTEM_ARCH_PRE_ID: usize = 12;
+        const NO_ARCH_PRE_ID: usize = 13;
+
+        let mut select = Select::new(None, String::from("packages"))
+            .where_condition(Where::Equal(Self::NAME_COL_PRE_ID, String::from("name")));
+        if pkg_slot.is_some() {
+            select = select.and_where(Where::Equal(Self::SLOT_COL_PRE_ID, String::from("slot")));
+        }
+
+        let statement = if pkg_arch.is_some() {
+            select
+                .and_where(Where::Equal(Self::ARCH_COL_PRE_ID, String::from("arch")))
+                .to_string()
+        } else {
+            select
+                .add_arg(SelectArg::OrderBy(vec![
+                    OrderType::Desc(format!(
+                        "arch IN (?{SYSTEM_ARCH_PRE_ID}, ?{NO_ARCH_PRE_ID}, '')"
+                    )),
+                    OrderType::Desc(String::from("slot")),
+                ]))
+                .add_arg(SelectArg::Limit(1))
+                .to_string()
+        };
+
         let mut sql = core_db.prepare(statement, super::SQL_NO_CALLBACK_FN)?;
-        try_bind_val!(sql, Self::NAME_COL_PRE_ID, name);
+        try_bind_val!(sql, Self::NAME_COL_PRE_ID, pkg_name);
+        if let Some(pkg_arch) = &pkg_arch {
+            try_bind_val!(sql, Self::ARCH_COL_PRE_ID, &**pkg_arch);
+        } else {
+            try_bind_val!(sql, SYSTEM_ARCH_PRE_ID, SYSTEM_ARCH);
+            try_bind_val!(sql, NO_ARCH_PRE_ID, NO_ARCH);
+        }
+        if let Some(pkg_slot) = pkg_slot {
+            try_bind_val!(sql, Self::SLOT_COL_PRE_ID, pkg_slot);
+        }
         try_execute_prepared!(
             sql,
             simple_e_fmt!("Error SELECT query on 'packages' table.")
@@ -280,12 +456,28 @@ impl DbOpsForInstalledPkg for PkgDataFromDb {
 
         let meta = Meta {
             name: sql.get_data(Self::NAME_COL_PRE_ID)?,
-            arch: String::new(),
+            arch: sql.get_data(Self::ARCH_COL_PRE_ID)?,
+            slot: Some(sql.get_data::<String>(Self::SLOT_COL_PRE_ID)?).filter(|t| !t.is_empty()),
+            essential: sql.get_data::<i64>(ESSENTIAL_COL_ID)? != 0,
+            kind: get_kind(core_db, sql.get_data(KIND_ID_COL_ID)?)?,
+            maintainer: sql.get_data(MAINTAINER_COL_ID)?,
+            signing_key: sql.get_data(SIGNING_KEY_COL_ID)?,
             installed_size: sql.get_data(Self::INSTALLED_SIZE_COL_PRE_ID)?,
             version,
             dependencies: Vec::new(),
             suggestions: Vec::new(),
+            provides: Vec::new(),
+            conflicts: Vec::new(),
+            needs_sonames: Vec::new(),
+            provides_sonames: Vec::new(),
+            alternatives: Vec::new(),
+            config_files: get_package_config_files(core_db, id)?,
+            // Only needed while installing.
+            directories: Vec::new(),
+            scripts: get_package_scripts(core_db, id)?,
+            compression: None,
         };
+        let origin = get_origin(&sql)?;
 
         const PACKAGE_ID_COL_PRE_ID: usize = 1;
 
@@ -303,11 +495,25 @@ impl DbOpsForInstalledPkg for PkgDataFromDb {
         const PATH_COL_PRE_ID: usize = 2;
         const CHECKSUM_COL_PRE_ID: usize = 3;
         const CHECKSUM_ALGORITHM_COL_PRE_ID: usize = 4;
+        const SIZE_COL_PRE_ID: usize = 7;
+        const MODE_COL_PRE_ID: usize = 8;
+        const MTIME_COL_PRE_ID: usize = 9;
+        const CLASS_COL_PRE_ID: usize = 10;
         while let PreparedStatementStatus::FoundRow = sql.execute_prepared() {
+            let size: Option<i64> = sql.get_data(SIZE_COL_PRE_ID)?;
+            let mode: Option<i64> = sql.get_data(MODE_COL_PRE_ID)?;
+            let class: Option<String> = sql.get_data(CLASS_COL_PRE_ID)?;
             let file = FileStruct {
                 path: sql.get_data(PATH_COL_PRE_ID)?,
                 checksum_algorithm: sql.get_data(CHECKSUM_ALGORITHM_COL_PRE_ID)?,
                 checksum: sql.get_data(CHECKSUM_COL_PRE_ID)?,
+                size: size.map(|size| size as u64),
+                mode: mode.map(|mode| mode as u32),
+                mtime: sql.get_data(MTIME_COL_PRE_ID)?,
+                // Only needed while installing.
+                owner: None,
+                group: None,
+                class: class.as_deref().and_then(FileKind::parse),
             };
 
             files.push(file);
@@ -325,89 +531,43 @@ impl DbOpsForInstalledPkg for PkgDataFromDb {
             pkg_id: id,
             group_id,
             meta_fields,
+            origin,
         })
     }
 
     fn load_all_main_packages(core_db: &Database) -> Result<Vec<Self>, LpmError<PackageError>> {
         let statement =
             String::from("SELECT * FROM packages WHERE group_id = name || '@' || v_readable;");
-        let mut sql = core_db.prepare(statement, super::SQL_NO_CALLBACK_FN)?;
-
-        let mut pkgs = vec![];
-        while let PreparedStatementStatus::FoundRow = sql.execute_prepared() {
-            let id: i64 = sql.get_data(Self::PKG_ID_COL_PRE_ID).unwrap_or(0);
-
-            if id == 0 {
-                continue;
-            }
-
-            let group_id = sql.get_data(Self::GROUP_ID_COL_PRE_ID)?;
-
-            let version = VersionStruct {
-                major: sql.get_data(Self::V_MAJOR_COL_PRE_ID)?,
-                minor: sql.get_data(Self::V_MINOR_COL_PRE_ID)?,
-                patch: sql.get_data(Self::V_PATCH_COL_PRE_ID)?,
-                tag: sql.get_data(Self::V_TAG_COL_PRE_ID)?,
-                readable_format: sql.get_data(Self::V_READABLE_COL_PRE_ID)?,
-                condition: Condition::default(),
-            };
-
-            let meta = Meta {
-                name: sql.get_data(Self::NAME_COL_PRE_ID)?,
-                arch: String::new(),
-                installed_size: sql.get_data(Self::INSTALLED_SIZE_COL_PRE_ID)?,
-                version,
-                dependencies: Vec::new(),
-                suggestions: Vec::new(),
-            };
-
-            const PACKAGE_ID_COL_PRE_ID: usize = 1;
-
-            let files_statement = Select::new(None, String::from("files"))
-                .where_condition(Where::Equal(
-                    PACKAGE_ID_COL_PRE_ID,
-                    String::from("package_id"),
-                ))
-                .to_string();
-            let mut sql = core_db.prepare(files_statement, super::SQL_NO_CALLBACK_FN)?;
-            try_bind_val!(sql, PACKAGE_ID_COL_PRE_ID, id);
-
-            let mut files: Vec<FileStruct> = Vec::new();
-
-            const PATH_COL_PRE_ID: usize = 2;
-            const CHECKSUM_COL_PRE_ID: usize = 3;
-            const CHECKSUM_ALGORITHM_COL_PRE_ID: usize = 4;
-            while let PreparedStatementStatus::FoundRow = sql.execute_prepared() {
-                let file = FileStruct {
-                    path: sql.get_data(PATH_COL_PRE_ID)?,
-                    checksum_algorithm: sql.get_data(CHECKSUM_ALGORITHM_COL_PRE_ID)?,
-                    checksum: sql.get_data(CHECKSUM_COL_PRE_ID)?,
-                };
-
-                files.push(file);
-            }
-
-            let files = Files(files);
-            let meta_fields = MetaDir {
-                path: PathBuf::default(),
-                meta,
-                files,
-            };
-
-            pkgs.push(PkgDataFromDb {
-                pkg_id: id,
-                group_id,
-                meta_fields,
-            });
-        }
+        load_packages(
+            core_db,
+            core_db.prepare(statement, super::SQL_NO_CALLBACK_FN)?,
+        )
+    }
 
-        Ok(pkgs)
+    fn load_all_packages(core_db: &Database) -> Result<Vec<Self>, LpmError<PackageError>> {
+        let statement = String::from("SELECT * FROM packages;");
+        load_packages(
+            core_db,
+            core_db.prepare(statement, super::SQL_NO_CALLBACK_FN)?,
+        )
     }
 
     fn delete_from_db<'lpkg>(&self, core_db: &Database) -> Result<(), LpmError<PackageError>> {
         const GROUP_ID_COL_PRE_ID: usize = 1;
+        const ARCH_COL_PRE_ID: usize = 2;
+        const NO_ARCH_COL_PRE_ID: usize = 3;
+        const UNKNOWN_ARCH_COL_PRE_ID: usize = 4;
+        const SLOT_COL_PRE_ID: usize = 5;
+
+        // Variants of the same package for other architectures share the
+        // group id, keep them.
         let statement = Delete::new(String::from("packages"))
             .where_condition(Where::Equal(GROUP_ID_COL_PRE_ID, String::from("group_id")))
+            .and_where(Where::In(
+                vec![ARCH_COL_PRE_ID, NO_ARCH_COL_PRE_ID, UNKNOWN_ARCH_COL_PRE_ID],
+                String::from("arch"),
+            ))
+            .and_where(Where::Equal(SLOT_COL_PRE_ID, String::from("slot")))
             .to_string();
 
         let mut sql = core_db.prepare(statement, super::SQL_NO_CALLBACK_FN)?;
@@ -416,6 +576,18 @@ impl DbOpsForInstalledPkg for PkgDataFromDb {
             GROUP_ID_COL_PRE_ID,
             self.meta_fields.meta.get_group_id()
         );
+        try_bind_val!(
+            sql,
+            ARCH_COL_PRE_ID,
+            &*arch::normalize(&self.meta_fields.meta.arch)
+        );
+        try_bind_val!(sql, NO_ARCH_COL_PRE_ID, NO_ARCH);
+        try_bind_val!(sql, UNKNOWN_ARCH_COL_PRE_ID, "");
+        try_bind_val!(
+            sql,
+            SLOT_COL_PRE_ID,
+            self.meta_fields.meta.slot.as_deref().unwrap_or_default()
+        );
         try_execute_prepared!(
             sql,
             simple_e_fmt!(
@@ -428,6 +600,269 @@ impl DbOpsForInstalledPkg for PkgDataFromDb {
     }
 }
 
+/// Returns the `group_id` package along with the packages that were installed
+/// as its dependencies.
+pub fn load_group(
+    core_db: &Database,
+    group_id: &str,
+) -> Result<Vec<PkgDataFromDb>, LpmError<PackageError>> {
+    let statement = String::from("SELECT * FROM packages WHERE group_id = ?1;");
+
+    let sql = core_db.prepare(statement, super::SQL_NO_CALLBACK_FN)?;
+    try_bind_val!(sql, 1, group_id);
+
+    load_packages(core_db, sql)
+}
+
+fn get_origin(sql: &SqlStatement) -> Result<Option<PkgOrigin>, LpmError<PackageError>> {
+    let repository: Option<String> = sql.get_data(REPOSITORY_COL_ID)?;
+    let index_timestamp: Option<i64> = sql.get_data(INDEX_TIMESTAMP_COL_ID)?;
+
+    let signing_key: Option<String> = sql.get_data(SIGNING_KEY_COL_ID)?;
+
+    Ok(repository.map(|repository| PkgOrigin {
+        repository,
+        index_timestamp: index_timestamp.unwrap_or(0) as u32,
+        signing_key,
+    }))
+}
+
+/// Records the repository that the package `pkg_id` was installed or updated
+/// from, `None` for local `.lod` files.
+fn set_package_origin(
+    core_db: &Database,
+    pkg_id: i64,
+    origin: Option<&PkgOrigin>,
+) -> Result<(), LpmError<PackageError>> {
+    const REPOSITORY_COL_PRE_ID: usize = 1;
+    const INDEX_TIMESTAMP_COL_PRE_ID: usize = 2;
+    const PKG_ID_PRE_ID: usize = 3;
+
+    let statement = Update::new(
+        vec![
+            Column::new(String::from("repository"), REPOSITORY_COL_PRE_ID),
+            Column::new(String::from("index_timestamp"), INDEX_TIMESTAMP_COL_PRE_ID),
+        ],
+        String::from("packages"),
+    )
+    .where_condition(Where::Equal(PKG_ID_PRE_ID, String::from("id")))
+    .to_string();
+
+    let mut sql = core_db.prepare(statement.clone(), super::SQL_NO_CALLBACK_FN)?;
+    try_bind_val!(sql, PKG_ID_PRE_ID, pkg_id);
+    if let Some(origin) = origin {
+        try_bind_val!(sql, REPOSITORY_COL_PRE_ID, &*origin.repository);
+        try_bind_val!(
+            sql,
+            INDEX_TIMESTAMP_COL_PRE_ID,
+            i64::from(origin.index_timestamp)
+        );
+    } else {
+        try_bind_val!(sql, REPOSITORY_COL_PRE_ID, SQLITE_NULL);
+        try_bind_val!(sql, INDEX_TIMESTAMP_COL_PRE_ID, SQLITE_NULL);
+    }
+    try_execute_prepared!(
+        sql,
+        simple_e_fmt!("Failed executing SQL statement `{}`.", statement)
+    );
+
+    Ok(())
+}
+
+/// Records the paths that were excluded when installing the package `pkg_id`,
+/// `None` if all of them were installed.
+pub fn set_path_filter(
+    core_db: &Database,
+    pkg_id: i64,
+    path_filter: Option<&str>,
+) -> Result<(), LpmError<SqlError>> {
+    const PATH_FILTER_COL_PRE_ID: usize = 1;
+    const PKG_ID_PRE_ID: usize = 2;
+
+    let statement = Update::new(
+        vec![Column::new(
+            String::from("path_filter"),
+            PATH_FILTER_COL_PRE_ID,
+        )],
+        String::from("packages"),
+    )
+    .where_condition(Where::Equal(PKG_ID_PRE_ID, String::from("id")))
+    .to_string();
+
+    let mut sql = core_db.prepare(statement.clone(), super::SQL_NO_CALLBACK_FN)?;
+    try_bind_val!(sql, PKG_ID_PRE_ID, pkg_id);
+    if let Some(path_filter) = path_filter {
+        try_bind_val!(sql, PATH_FILTER_COL_PRE_ID, path_filter);
+    } else {
+        try_bind_val!(sql, PATH_FILTER_COL_PRE_ID, SQLITE_NULL);
+    }
+    try_execute_prepared!(
+        sql,
+        simple_e_fmt!("Failed executing SQL statement `{}`.", statement)
+    );
+
+    Ok(())
+}
+
+/// Moves the package `pkg_id` into the group `group_id`, e.g. a dependency
+/// that another package still needs once the one that pulled it in is gone.
+pub fn set_group_id(
+    core_db: &Database,
+    pkg_id: i64,
+    group_id: &str,
+) -> Result<(), LpmError<SqlError>> {
+    const GROUP_ID_COL_PRE_ID: usize = 1;
+    const PKG_ID_PRE_ID: usize = 2;
+
+    let statement = Update::new(
+        vec![Column::new(String::from("group_id"), GROUP_ID_COL_PRE_ID)],
+        String::from("packages"),
+    )
+    .where_condition(Where::Equal(PKG_ID_PRE_ID, String::from("id")))
+    .to_string();
+
+    let mut sql = core_db.prepare(statement.clone(), super::SQL_NO_CALLBACK_FN)?;
+    try_bind_val!(sql, GROUP_ID_COL_PRE_ID, group_id);
+    try_bind_val!(sql, PKG_ID_PRE_ID, pkg_id);
+    try_execute_prepared!(
+        sql,
+        simple_e_fmt!("Failed executing SQL statement `{}`.", statement)
+    );
+
+    Ok(())
+}
+
+/// Paths excluded when the package `pkg_id` was installed, as recorded by
+/// `set_path_filter`.
+pub fn get_path_filter(
+    core_db: &Database,
+    pkg_id: i64,
+) -> Result<Option<String>, LpmError<SqlError>> {
+    const PKG_ID_PRE_ID: usize = 1;
+
+    let statement = Select::new(
+        Some(vec![String::from("path_filter")]),
+        String::from("packages"),
+    )
+    .where_condition(Where::Equal(PKG_ID_PRE_ID, String::from("id")))
+    .to_string();
+
+    let mut sql = core_db.prepare(statement, super::SQL_NO_CALLBACK_FN)?;
+    try_bind_val!(sql, PKG_ID_PRE_ID, pkg_id);
+    if PreparedStatementStatus::FoundRow != sql.execute_prepared() {
+        return Ok(None);
+    }
+
+    Ok(sql.get_data(0)?)
+}
+
+fn load_packages(
+    core_db: &Database,
+    mut sql: SqlStatement,
+) -> Result<Vec<PkgDataFromDb>, LpmError<PackageError>> {
+    let mut pkgs = vec![];
+    while let PreparedStatementStatus::FoundRow = sql.execute_prepared() {
+        let id: i64 = sql.get_data(PkgDataFromDb::PKG_ID_COL_PRE_ID).unwrap_or(0);
+
+        if id == 0 {
+            continue;
+        }
+
+        let group_id = sql.get_data(PkgDataFromDb::GROUP_ID_COL_PRE_ID)?;
+
+        let version = VersionStruct {
+            major: sql.get_data(PkgDataFromDb::V_MAJOR_COL_PRE_ID)?,
+            minor: sql.get_data(PkgDataFromDb::V_MINOR_COL_PRE_ID)?,
+            patch: sql.get_data(PkgDataFromDb::V_PATCH_COL_PRE_ID)?,
+            tag: sql.get_data(PkgDataFromDb::V_TAG_COL_PRE_ID)?,
+            readable_format: sql.get_data(PkgDataFromDb::V_READABLE_COL_PRE_ID)?,
+            condition: Condition::default(),
+        };
+
+        let meta = Meta {
+            name: sql.get_data(PkgDataFromDb::NAME_COL_PRE_ID)?,
+            arch: sql.get_data(PkgDataFromDb::ARCH_COL_PRE_ID)?,
+            slot: Some(sql.get_data::<String>(PkgDataFromDb::SLOT_COL_PRE_ID)?)
+                .filter(|t| !t.is_empty()),
+            essential: sql.get_data::<i64>(ESSENTIAL_COL_ID)? != 0,
+            kind: get_kind(core_db, sql.get_data(KIND_ID_COL_ID)?)?,
+            maintainer: sql.get_data(MAINTAINER_COL_ID)?,
+            signing_key: sql.get_data(SIGNING_KEY_COL_ID)?,
+            installed_size: sql.get_data(PkgDataFromDb::INSTALLED_SIZE_COL_PRE_ID)?,
+            version,
+            dependencies: Vec::new(),
+            suggestions: Vec::new(),
+            provides: Vec::new(),
+            conflicts: Vec::new(),
+            needs_sonames: Vec::new(),
+            provides_sonames: Vec::new(),
+            alternatives: Vec::new(),
+            config_files: get_package_config_files(core_db, id)?,
+            // Only needed while installing.
+            directories: Vec::new(),
+            scripts: get_package_scripts(core_db, id)?,
+            compression: None,
+        };
+        let origin = get_origin(&sql)?;
+
+        const PACKAGE_ID_COL_PRE_ID: usize = 1;
+
+        let files_statement = Select::new(None, String::from("files"))
+            .where_condition(Where::Equal(
+                PACKAGE_ID_COL_PRE_ID,
+                String::from("package_id"),
+            ))
+            .to_string();
+        let mut sql = core_db.prepare(files_statement, super::SQL_NO_CALLBACK_FN)?;
+        try_bind_val!(sql, PACKAGE_ID_COL_PRE_ID, id);
+
+        let mut files: Vec<FileStruct> = Vec::new();
+
+        const PATH_COL_PRE_ID: usize = 2;
+        const CHECKSUM_COL_PRE_ID: usize = 3;
+        const CHECKSUM_ALGORITHM_COL_PRE_ID: usize = 4;
+        const SIZE_COL_PRE_ID: usize = 7;
+        const MODE_COL_PRE_ID: usize = 8;
+        const MTIME_COL_PRE_ID: usize = 9;
+        const CLASS_COL_PRE_ID: usize = 10;
+        while let PreparedStatementStatus::FoundRow = sql.execute_prepared() {
+            let size: Option<i64> = sql.get_data(SIZE_COL_PRE_ID)?;
+            let mode: Option<i64> = sql.get_data(MODE_COL_PRE_ID)?;
+            let class: Option<String> = sql.get_data(CLASS_COL_PRE_ID)?;
+            let file = FileStruct {
+                path: sql.get_data(PATH_COL_PRE_ID)?,
+                checksum_algorithm: sql.get_data(CHECKSUM_ALGORITHM_COL_PRE_ID)?,
+                checksum: sql.get_data(CHECKSUM_COL_PRE_ID)?,
+                size: size.map(|size| size as u64),
+                mode: mode.map(|mode| mode as u32),
+                mtime: sql.get_data(MTIME_COL_PRE_ID)?,
+                // Only needed while installing.
+                owner: None,
+                group: None,
+                class: class.as_deref().and_then(FileKind::parse),
+            };
+
+            files.push(file);
+        }
+
+        let files = Files(files);
+        let meta_fields = MetaDir {
+            path: PathBuf::default(),
+            meta,
+            files,
+        };
+
+        pkgs.push(PkgDataFromDb {
+            pkg_id: id,
+            group_id,
+            meta_fields,
+            origin,
+        });
+    }
+
+    Ok(pkgs)
+}
+
 fn delete_pkg_files(
     core_db: &Database,
     pkg_id: i64,
@@ -450,6 +885,12 @@ fn delete_pkg_files(
     Ok(status)
 }
 
+/// Rows per `INSERT` of `insert_files`, which keeps the statements far below
+/// the limit of SQLite on the number of parameters(32766).
+const FILES_PER_INSERT: usize = 500;
+
+/// Inserts `files` with a multi-row `INSERT` per `FILES_PER_INSERT` of them,
+/// instead of a statement per file.
 fn insert_files(
     core_db: &Database,
     pkg_id: i64,
@@ -457,15 +898,18 @@ fn insert_files(
 ) -> Result<(), LpmError<PackageError>> {
     let files = &files.0;
 
-    for file in files {
-        let file_path = Path::new(&file.path);
-
-        const NAME_COL_PRE_ID: usize = 1;
-        const ABSOLUTE_PATH_COL_PRE_ID: usize = 2;
-        const CHECKSUM_COL_PRE_ID: usize = 3;
-        const CHECKSUM_ALGORITHM_COL_PRE_ID: usize = 4;
-        const PACKAGE_ID_COL_PRE_ID: usize = 5;
-
+    const NAME_COL_PRE_ID: usize = 1;
+    const ABSOLUTE_PATH_COL_PRE_ID: usize = 2;
+    const CHECKSUM_COL_PRE_ID: usize = 3;
+    const CHECKSUM_ALGORITHM_COL_PRE_ID: usize = 4;
+    const PACKAGE_ID_COL_PRE_ID: usize = 5;
+    const SIZE_COL_PRE_ID: usize = 6;
+    const MODE_COL_PRE_ID: usize = 7;
+    const MTIME_COL_PRE_ID: usize = 8;
+    const CLASS_COL_PRE_ID: usize = 9;
+    const COLUMN_COUNT: usize = 9;
+
+    for chunk in files.chunks(FILES_PER_INSERT) {
         let file_columns = vec![
             Column::new(String::from("name"), NAME_COL_PRE_ID),
             Column::new(String::from("absolute_path"), ABSOLUTE_PATH_COL_PRE_ID),
@@ -475,24 +919,61 @@ fn insert_files(
                 CHECKSUM_ALGORITHM_COL_PRE_ID,
             ),
             Column::new(String::from("package_id"), PACKAGE_ID_COL_PRE_ID),
+            Column::new(String::from("size"), SIZE_COL_PRE_ID),
+            Column::new(String::from("mode"), MODE_COL_PRE_ID),
+            Column::new(String::from("mtime"), MTIME_COL_PRE_ID),
+            Column::new(String::from("class"), CLASS_COL_PRE_ID),
         ];
-        let statement = Insert::new(Some(file_columns), String::from("files")).to_string();
+        // Row `n` of the chunk uses the ids of the first one shifted by
+        // `n * COLUMN_COUNT`.
+        let mut insert = Insert::new(Some(file_columns), String::from("files"));
+        for row in 1..chunk.len() {
+            let offset = row * COLUMN_COUNT;
+            insert = insert.insert_another_row((1..=COLUMN_COUNT).map(|t| offset + t).collect());
+        }
 
-        let mut sql = core_db.prepare(statement, super::SQL_NO_CALLBACK_FN)?;
+        let mut sql = core_db.prepare(insert.to_string(), super::SQL_NO_CALLBACK_FN)?;
 
-        try_bind_val!(
-            sql,
-            NAME_COL_PRE_ID,
-            file_path.file_name().unwrap().to_str().unwrap()
-        );
-        try_bind_val!(sql, ABSOLUTE_PATH_COL_PRE_ID, format!("/{}", &file.path));
-        try_bind_val!(sql, CHECKSUM_COL_PRE_ID, &*file.checksum);
-        try_bind_val!(
-            sql,
-            CHECKSUM_ALGORITHM_COL_PRE_ID,
-            &*file.checksum_algorithm
-        );
-        try_bind_val!(sql, PACKAGE_ID_COL_PRE_ID, pkg_id);
+        for (row, file) in chunk.iter().enumerate() {
+            let offset = row * COLUMN_COUNT;
+            let file_path = file
+                .pkg_path()
+                .map_err(|e| SqlErrorKind::InvalidPath(e.to_string()).to_lpm_err())?;
+
+            try_bind_val!(sql, offset + NAME_COL_PRE_ID, file_path.file_name());
+            try_bind_val!(
+                sql,
+                offset + ABSOLUTE_PATH_COL_PRE_ID,
+                file_path.to_absolute()
+            );
+            try_bind_val!(sql, offset + CHECKSUM_COL_PRE_ID, &*file.checksum);
+            try_bind_val!(
+                sql,
+                offset + CHECKSUM_ALGORITHM_COL_PRE_ID,
+                &*file.checksum_algorithm
+            );
+            try_bind_val!(sql, offset + PACKAGE_ID_COL_PRE_ID, pkg_id);
+            if let Some(size) = file.size {
+                try_bind_val!(sql, offset + SIZE_COL_PRE_ID, size as i64);
+            } else {
+                try_bind_val!(sql, offset + SIZE_COL_PRE_ID, SQLITE_NULL);
+            }
+            if let Some(mode) = file.mode {
+                try_bind_val!(sql, offset + MODE_COL_PRE_ID, mode as i64);
+            } else {
+                try_bind_val!(sql, offset + MODE_COL_PRE_ID, SQLITE_NULL);
+            }
+            if let Some(mtime) = file.mtime {
+                try_bind_val!(sql, offset + MTIME_COL_PRE_ID, mtime);
+            } else {
+                try_bind_val!(sql, offset + MTIME_COL_PRE_ID, SQLITE_NULL);
+            }
+            if let Some(class) = file.class {
+                try_bind_val!(sql, offset + CLASS_COL_PRE_ID, class.as_str());
+            } else {
+                try_bind_val!(sql, offset + CLASS_COL_PRE_ID, SQLITE_NULL);
+            }
+        }
 
         try_execute_prepared!(sql, simple_e_fmt!("Could not insert to \"files\" table."));
     }
@@ -500,16 +981,91 @@ fn insert_files(
     Ok(())
 }
 
-pub fn is_package_exists(core_db: &Database, name: &str) -> Result<bool, LpmError<SqlError>> {
+/// Replaces the recorded checksum of the installed file at `absolute_path`,
+/// for when the files are re-hashed with another algorithm.
+pub fn update_file_checksum(
+    core_db: &Database,
+    absolute_path: &str,
+    checksum: &str,
+    checksum_algorithm: &str,
+) -> Result<(), LpmError<PackageError>> {
+    const CHECKSUM_COL_PRE_ID: usize = 1;
+    const CHECKSUM_ALGORITHM_COL_PRE_ID: usize = 2;
+    const ABSOLUTE_PATH_PRE_ID: usize = 3;
+
+    let statement = Update::new(
+        vec![
+            Column::new(String::from("checksum"), CHECKSUM_COL_PRE_ID),
+            Column::new(
+                String::from("checksum_algorithm"),
+                CHECKSUM_ALGORITHM_COL_PRE_ID,
+            ),
+        ],
+        String::from("files"),
+    )
+    .where_condition(Where::Equal(
+        ABSOLUTE_PATH_PRE_ID,
+        String::from("absolute_path"),
+    ))
+    .to_string();
+
+    let mut sql = core_db.prepare(statement.clone(), super::SQL_NO_CALLBACK_FN)?;
+    try_bind_val!(sql, CHECKSUM_COL_PRE_ID, checksum);
+    try_bind_val!(sql, CHECKSUM_ALGORITHM_COL_PRE_ID, checksum_algorithm);
+    try_bind_val!(sql, ABSOLUTE_PATH_PRE_ID, absolute_path);
+    try_execute_prepared!(
+        sql,
+        simple_e_fmt!("Failed executing SQL statement `{}`.", statement)
+    );
+
+    Ok(())
+}
+
+/// Checks if `name` is installed for any of the `archs`, or for any
+/// architecture if `archs` is empty.
+///
+/// Packages installed before arch tracking have an empty arch and always
+/// match. `slot` limits the check to a single slot, `Some("")` being the
+/// packages that are not slot-aware.
+pub fn is_package_exists(
+    core_db: &Database,
+    name: &str,
+    archs: &[&str],
+    slot: Option<&str>,
+) -> Result<bool, LpmError<SqlError>> {
     const NAME_COL_PRE_ID: usize = 1;
-    let exists_statement = Select::new(None, String::from("packages"))
-        .where_condition(Where::Equal(NAME_COL_PRE_ID, String::from("name")))
-        .exists()
-        .to_string();
+    const SLOT_COL_PRE_ID: usize = 2;
+    const UNKNOWN_ARCH_COL_PRE_ID: usize = 3;
+
+    let mut select = Select::new(None, String::from("packages"))
+        .where_condition(Where::Equal(NAME_COL_PRE_ID, String::from("name")));
+    if slot.is_some() {
+        select = select.and_where(Where::Equal(SLOT_COL_PRE_ID, String::from("slot")));
+    }
+
+    let arch_pre_ids: Vec<usize> = (0..archs.len())
+        .map(|i| UNKNOWN_ARCH_COL_PRE_ID + 1 + i)
+        .collect();
+    if !archs.is_empty() {
+        let mut pre_ids = vec![UNKNOWN_ARCH_COL_PRE_ID];
+        pre_ids.extend(&arch_pre_ids);
+        select = select.and_where(Where::In(pre_ids, String::from("arch")));
+    }
+
+    let exists_statement = select.exists().to_string();
 
     let mut sql = core_db.prepare(exists_statement.clone(), super::SQL_NO_CALLBACK_FN)?;
 
     try_bind_val!(sql, NAME_COL_PRE_ID, name);
+    if let Some(slot) = slot {
+        try_bind_val!(sql, SLOT_COL_PRE_ID, slot);
+    }
+    if !archs.is_empty() {
+        try_bind_val!(sql, UNKNOWN_ARCH_COL_PRE_ID, "");
+        for (pre_id, pkg_arch) in arch_pre_ids.iter().zip(archs) {
+            try_bind_val!(sql, *pre_id, &*arch::normalize(pkg_arch));
+        }
+    }
 
     try_execute_prepared!(
         sql,
@@ -520,3 +1076,24 @@ pub fn is_package_exists(core_db: &Database, name: &str) -> Result<bool, LpmErro
 
     Ok(result == 1)
 }
+
+/// Returns the group id of the package that owns `absolute_path`, if any.
+pub fn get_file_owner(
+    core_db: &Database,
+    absolute_path: &str,
+) -> Result<Option<String>, LpmError<SqlError>> {
+    let statement = String::from(
+        "SELECT packages.group_id FROM files
+         INNER JOIN packages ON packages.id = files.package_id
+         WHERE files.absolute_path = ?1;",
+    );
+
+    let mut sql = core_db.prepare(statement, super::SQL_NO_CALLBACK_FN)?;
+    try_bind_val!(sql, 1, absolute_path);
+
+    if let PreparedStatementStatus::FoundRow = sql.execute_prepared() {
+        return Ok(Some(sql.get_data(0)?));
+    }
+
+    Ok(None)
+}
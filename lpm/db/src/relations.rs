@@ -0,0 +1,234 @@
+use common::{meta::Meta, version::VersionStruct};
+use ehandle::{
+    db::SqlError, lpm::LpmError, simple_e_fmt, try_bind_val, try_execute_prepared, ErrorCommons,
+};
+use min_sqlite3_sys::prelude::*;
+use sql_builder::delete::*;
+use sql_builder::insert::Insert;
+use sql_builder::Column;
+use std::path::Path;
+
+/// Tables holding the package relations, they all share the same columns.
+const RELATION_TABLES: [&str; 3] = ["dependencies", "provides", "conflicts"];
+
+/// Records the dependencies, provides and conflicts of `meta`.
+pub(crate) fn insert_relations(
+    core_db: &Database,
+    pkg_id: i64,
+    meta: &Meta,
+) -> Result<(), LpmError<SqlError>> {
+    for dependency in &meta.dependencies {
+        insert_relation(
+            core_db,
+            "dependencies",
+            pkg_id,
+            &dependency.name,
+            Some(&dependency.version),
+        )?;
+    }
+
+    for provide in &meta.provides {
+        insert_relation(
+            core_db,
+            "provides",
+            pkg_id,
+            &provide.name,
+            provide.version.as_ref(),
+        )?;
+    }
+
+    for conflict in &meta.conflicts {
+        insert_relation(
+            core_db,
+            "conflicts",
+            pkg_id,
+            &conflict.name,
+            conflict.version.as_ref(),
+        )?;
+    }
+
+    Ok(())
+}
+
+/// `version` being `None` means any version of `name`.
+fn insert_relation(
+    core_db: &Database,
+    table: &str,
+    pkg_id: i64,
+    name: &str,
+    version: Option<&VersionStruct>,
+) -> Result<(), LpmError<SqlError>> {
+    const PACKAGE_ID_COL_PRE_ID: usize = 1;
+    const NAME_COL_PRE_ID: usize = 2;
+    const V_CONDITION_COL_PRE_ID: usize = 3;
+    const V_MAJOR_COL_PRE_ID: usize = 4;
+    const V_MINOR_COL_PRE_ID: usize = 5;
+    const V_PATCH_COL_PRE_ID: usize = 6;
+    const V_TAG_COL_PRE_ID: usize = 7;
+    const V_READABLE_COL_PRE_ID: usize = 8;
+
+    let columns = vec![
+        Column::new(String::from("package_id"), PACKAGE_ID_COL_PRE_ID),
+        Column::new(String::from("name"), NAME_COL_PRE_ID),
+        Column::new(String::from("v_condition"), V_CONDITION_COL_PRE_ID),
+        Column::new(String::from("v_major"), V_MAJOR_COL_PRE_ID),
+        Column::new(String::from("v_minor"), V_MINOR_COL_PRE_ID),
+        Column::new(String::from("v_patch"), V_PATCH_COL_PRE_ID),
+        Column::new(String::from("v_tag"), V_TAG_COL_PRE_ID),
+        Column::new(String::from("v_readable"), V_READABLE_COL_PRE_ID),
+    ];
+    let statement = Insert::new(Some(columns), table.to_owned()).to_string();
+
+    let mut sql = core_db.prepare(statement, super::SQL_NO_CALLBACK_FN)?;
+
+    try_bind_val!(sql, PACKAGE_ID_COL_PRE_ID, pkg_id);
+    try_bind_val!(sql, NAME_COL_PRE_ID, name);
+
+    match version {
+        Some(version) => {
+            try_bind_val!(
+                sql,
+                V_CONDITION_COL_PRE_ID,
+                version.condition.to_str_operator()
+            );
+            try_bind_val!(sql, V_MAJOR_COL_PRE_ID, version.major);
+            try_bind_val!(sql, V_MINOR_COL_PRE_ID, version.minor);
+            try_bind_val!(sql, V_PATCH_COL_PRE_ID, version.patch);
+            if let Some(vtag) = &version.tag {
+                try_bind_val!(sql, V_TAG_COL_PRE_ID, &**vtag);
+            } else {
+                try_bind_val!(sql, V_TAG_COL_PRE_ID, SQLITE_NULL);
+            }
+            try_bind_val!(sql, V_READABLE_COL_PRE_ID, &*version.readable_format);
+        }
+        None => {
+            for pre_id in V_CONDITION_COL_PRE_ID..=V_READABLE_COL_PRE_ID {
+                try_bind_val!(sql, pre_id, SQLITE_NULL);
+            }
+        }
+    }
+
+    try_execute_prepared!(
+        sql,
+        simple_e_fmt!("Could not insert to \"{}\" table.", table)
+    );
+
+    Ok(())
+}
+
+/// Packages that depend on the package `pkg_id`, by its name or by one of its
+/// provides, and that no other installed package satisfies. The packages of
+/// `excluded` count neither as dependents nor as satisfying them, e.g. when
+/// they are deleted along with it. Returns their ids and group ids.
+pub fn get_dependents(
+    core_db: &Database,
+    pkg_id: i64,
+    excluded: &[i64],
+) -> Result<Vec<(i64, String)>, LpmError<SqlError>> {
+    let excluded_pre_ids: Vec<String> =
+        (0..excluded.len()).map(|i| format!("?{}", i + 2)).collect();
+    let excluded_pre_ids = excluded_pre_ids.join(", ");
+
+    let statement = format!(
+        "SELECT DISTINCT packages.id, packages.group_id FROM dependencies
+         INNER JOIN packages ON packages.id = dependencies.package_id
+         WHERE packages.id != ?1 AND packages.id NOT IN ({excluded_pre_ids})
+         AND dependencies.name IN (
+             SELECT name FROM packages WHERE id = ?1
+             UNION SELECT name FROM provides WHERE package_id = ?1
+         )
+         AND NOT EXISTS (
+             SELECT 1 FROM packages AS other
+             WHERE other.id != ?1 AND other.id NOT IN ({excluded_pre_ids})
+             AND (other.name = dependencies.name OR other.id IN (
+                 SELECT package_id FROM provides WHERE provides.name = dependencies.name
+             ))
+         )
+         ORDER BY packages.id;"
+    );
+
+    let mut sql = core_db.prepare(statement, super::SQL_NO_CALLBACK_FN)?;
+    try_bind_val!(sql, 1, pkg_id);
+    for (i, id) in excluded.iter().enumerate() {
+        try_bind_val!(sql, i + 2, *id);
+    }
+
+    let mut dependents = vec![];
+    while let PreparedStatementStatus::FoundRow = sql.execute_prepared() {
+        dependents.push((sql.get_data(0)?, sql.get_data(1)?));
+    }
+
+    Ok(dependents)
+}
+
+pub(crate) fn delete_relations(core_db: &Database, pkg_id: i64) -> Result<(), LpmError<SqlError>> {
+    const PACKAGE_ID_COL_PRE_ID: usize = 1;
+
+    for table in RELATION_TABLES {
+        let statement = Delete::new(table.to_owned())
+            .where_condition(Where::Equal(
+                PACKAGE_ID_COL_PRE_ID,
+                String::from("package_id"),
+            ))
+            .to_string();
+
+        let mut sql = core_db.prepare(statement, super::SQL_NO_CALLBACK_FN)?;
+        try_bind_val!(sql, PACKAGE_ID_COL_PRE_ID, pkg_id);
+
+        try_execute_prepared!(
+            sql,
+            simple_e_fmt!(
+                "Could not delete from '{}' for package_id {}.",
+                table,
+                pkg_id
+            )
+        );
+    }
+
+    Ok(())
+}
+
+/// Fills the relation tables for the packages installed before they existed,
+/// using the `meta.json` kept in `PKG_DATA_DIR`. Packages without it are
+/// skipped.
+pub(crate) fn backfill_relations(core_db: &Database) -> Result<(), LpmError<SqlError>> {
+    let statement = String::from("SELECT id, name, slot FROM packages;");
+    let mut sql = core_db.prepare(statement, super::SQL_NO_CALLBACK_FN)?;
+
+    let mut pkgs: Vec<(i64, String, String)> = vec![];
+    while let PreparedStatementStatus::FoundRow = sql.execute_prepared() {
+        pkgs.push((sql.get_data(0)?, sql.get_data(1)?, sql.get_data(2)?));
+    }
+
+    for (pkg_id, pkg_name, pkg_slot) in pkgs {
+        let meta_path = Path::new(super::PKG_DATA_DIR)
+            .join(super::pkg_data_dir_name(&pkg_name, Some(&pkg_slot)))
+            .join("meta.json");
+
+        let meta = match read_meta(&meta_path) {
+            Some(meta) => meta,
+            None => {
+                logger::debug!("No meta data found for '{pkg_name}', skipping its relations.");
+                continue;
+            }
+        };
+
+        insert_relations(core_db, pkg_id, &meta)?;
+    }
+
+    Ok(())
+}
+
+pub(crate) fn read_meta(path: &Path) -> Option<Meta> {
+    if !path.exists() {
+        return None;
+    }
+
+    match Meta::read(path) {
+        Ok(meta) => Some(meta),
+        Err(error) => {
+            logger::warning!("Failed reading '{}': {error}", path.display());
+            None
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use common::{arch, config::Config, DownloadOptions, TlsConfig, NO_ARCH};
 use ehandle::{
     db::SqlError, lpm::LpmError, simple_e_fmt, try_bind_val, try_execute_prepared, ErrorCommons,
 };
@@ -5,7 +6,141 @@ use min_sqlite3_sys::prelude::*;
 use sql_builder::delete::*;
 use sql_builder::insert::Insert;
 use sql_builder::select::Select;
+use sql_builder::update::Update;
 use sql_builder::Column;
+use std::io;
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum SignatureLevel {
+    Never,
+    #[default]
+    Optional,
+    Required,
+}
+
+impl SignatureLevel {
+    pub fn from_string_slice(level: &str) -> Option<Self> {
+        match level {
+            "never" => Some(Self::Never),
+            "optional" => Some(Self::Optional),
+            "required" => Some(Self::Required),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Never => "never",
+            Self::Optional => "optional",
+            Self::Required => "required",
+        }
+    }
+}
+
+/// Settings stored per repository and honored during index sync and
+/// package resolution.
+#[derive(Clone, Debug, Default)]
+pub struct RepositoryOptions {
+    /// Architectures the repository serves, empty means any.
+    pub arch_filter: Vec<String>,
+    /// Package name globs allowed from the repository, empty means all.
+    pub include_pkgs: Vec<String>,
+    /// Package name globs never taken from the repository.
+    pub exclude_pkgs: Vec<String>,
+    pub signature_level: SignatureLevel,
+    /// Download speed cap in bytes per second.
+    pub bandwidth_limit: Option<u64>,
+    /// Proxy url, overrides the global `proxy` setting.
+    pub proxy: Option<String>,
+    /// Addresses serving the same packages, tried for downloads in this order
+    /// before the repository address.
+    pub mirrors: Vec<String>,
+    /// Extra CA bundle to trust for the repository.
+    pub ca_bundle: Option<String>,
+    /// Client certificate and key for mutual TLS.
+    pub client_cert: Option<String>,
+    pub client_key: Option<String>,
+}
+
+impl RepositoryOptions {
+    pub fn is_arch_allowed(&self, arch: &str) -> bool {
+        self.arch_filter.is_empty() || self.arch_filter.iter().any(|a| arch::is_same(a, arch))
+    }
+
+    /// Spellings of the architectures whose index entries can be installed
+    /// on a `target` system from the repository.
+    pub fn index_archs(&self, target: &str) -> Vec<String> {
+        arch::supported_archs(target)
+            .into_iter()
+            .filter(|t| t == NO_ARCH || self.is_arch_allowed(t))
+            .flat_map(|t| arch::spellings(&t))
+            .collect()
+    }
+
+    /// Spellings of the architectures whose index entries are kept on sync,
+    /// `None` if the repository serves any.
+    pub fn synced_archs(&self) -> Option<Vec<String>> {
+        if self.arch_filter.is_empty() {
+            return None;
+        }
+
+        let archs = self.arch_filter.iter().map(String::as_str).chain([NO_ARCH]);
+        Some(archs.flat_map(arch::spellings).collect())
+    }
+
+    pub fn is_pkg_allowed(&self, pkg_name: &str) -> bool {
+        (self.include_pkgs.is_empty() || common::glob::matches_any(&self.include_pkgs, pkg_name))
+            && !common::glob::matches_any(&self.exclude_pkgs, pkg_name)
+    }
+
+    /// Returns the stricter one of the repository and the global rate limits.
+    pub fn rate_limit(&self, global_limit: Option<u64>) -> Option<u64> {
+        match (self.bandwidth_limit, global_limit) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    /// Fails if the certificates of the repository can't be loaded.
+    pub fn download_options(&self, config: &Config) -> io::Result<DownloadOptions> {
+        Ok(DownloadOptions {
+            rate_limit: self.rate_limit(config.limit_rate),
+            proxy: self.proxy.clone().or_else(|| config.proxy.clone()),
+            tls: self.tls_config()?,
+            ..DownloadOptions::from_config(config)
+        })
+    }
+
+    /// Builds the TLS config for the certificates of the repository, `None`
+    /// if it doesn't set any.
+    fn tls_config(&self) -> io::Result<Option<TlsConfig>> {
+        let client_identity = match (&self.client_cert, &self.client_key) {
+            (Some(cert), Some(key)) => Some((Path::new(cert), Path::new(key))),
+            (None, None) => None,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Repository client certificate and key have to be set together.",
+                ))
+            }
+        };
+
+        if self.ca_bundle.is_none() && client_identity.is_none() {
+            return Ok(None);
+        }
+
+        TlsConfig::new(self.ca_bundle.as_deref().map(Path::new), client_identity).map(Some)
+    }
+}
+
+fn split_list(value: String) -> Vec<String> {
+    value
+        .split(',')
+        .map(|t| t.trim().to_owned())
+        .filter(|t| !t.is_empty())
+        .collect()
+}
 
 pub fn insert_repository(
     core_db: &Database,
@@ -105,3 +240,139 @@ pub fn get_repositories(core_db: &Database) -> Result<Vec<(String, String)>, Lpm
 
     Ok(result)
 }
+
+pub fn get_repository_options(
+    core_db: &Database,
+    name: &str,
+) -> Result<RepositoryOptions, LpmError<SqlError>> {
+    const NAME_COL_PRE_ID: usize = 1;
+    let statement = Select::new(
+        Some(vec![
+            String::from("arch_filter"),
+            String::from("include_pkgs"),
+            String::from("exclude_pkgs"),
+            String::from("signature_level"),
+            String::from("bandwidth_limit"),
+            String::from("proxy"),
+            String::from("mirrors"),
+            String::from("ca_bundle"),
+            String::from("client_cert"),
+            String::from("client_key"),
+        ]),
+        String::from("repositories"),
+    )
+    .where_condition(Where::Equal(NAME_COL_PRE_ID, String::from("name")))
+    .to_string();
+
+    let mut sql = core_db.prepare(statement.clone(), super::SQL_NO_CALLBACK_FN)?;
+
+    try_bind_val!(sql, NAME_COL_PRE_ID, name);
+
+    let status = try_execute_prepared!(
+        sql,
+        simple_e_fmt!(
+            "Select repository options query failed. SQL:\n {}",
+            statement
+        )
+    );
+
+    if status != PreparedStatementStatus::FoundRow {
+        return Ok(RepositoryOptions::default());
+    }
+
+    let signature_level: String = sql.get_data(3)?;
+    let bandwidth_limit: Option<i64> = sql.get_data(4)?;
+
+    Ok(RepositoryOptions {
+        arch_filter: split_list(sql.get_data(0)?),
+        include_pkgs: split_list(sql.get_data(1)?),
+        exclude_pkgs: split_list(sql.get_data(2)?),
+        signature_level: SignatureLevel::from_string_slice(&signature_level).unwrap_or_default(),
+        bandwidth_limit: bandwidth_limit.map(|t| t as u64),
+        proxy: sql.get_data(5)?,
+        mirrors: split_list(sql.get_data(6)?),
+        ca_bundle: sql.get_data(7)?,
+        client_cert: sql.get_data(8)?,
+        client_key: sql.get_data(9)?,
+    })
+}
+
+pub fn update_repository_options(
+    core_db: &Database,
+    name: &str,
+    options: &RepositoryOptions,
+) -> Result<PreparedStatementStatus, LpmError<SqlError>> {
+    const NAME_COL_PRE_ID: usize = 1;
+    const ARCH_FILTER_COL_PRE_ID: usize = 2;
+    const INCLUDE_PKGS_COL_PRE_ID: usize = 3;
+    const EXCLUDE_PKGS_COL_PRE_ID: usize = 4;
+    const SIGNATURE_LEVEL_COL_PRE_ID: usize = 5;
+    const BANDWIDTH_LIMIT_COL_PRE_ID: usize = 6;
+    const PROXY_COL_PRE_ID: usize = 7;
+    const MIRRORS_COL_PRE_ID: usize = 8;
+    const CA_BUNDLE_COL_PRE_ID: usize = 9;
+    const CLIENT_CERT_COL_PRE_ID: usize = 10;
+    const CLIENT_KEY_COL_PRE_ID: usize = 11;
+
+    let update_fields = vec![
+        Column::new(String::from("arch_filter"), ARCH_FILTER_COL_PRE_ID),
+        Column::new(String::from("include_pkgs"), INCLUDE_PKGS_COL_PRE_ID),
+        Column::new(String::from("exclude_pkgs"), EXCLUDE_PKGS_COL_PRE_ID),
+        Column::new(String::from("signature_level"), SIGNATURE_LEVEL_COL_PRE_ID),
+        Column::new(String::from("bandwidth_limit"), BANDWIDTH_LIMIT_COL_PRE_ID),
+        Column::new(String::from("proxy"), PROXY_COL_PRE_ID),
+        Column::new(String::from("mirrors"), MIRRORS_COL_PRE_ID),
+        Column::new(String::from("ca_bundle"), CA_BUNDLE_COL_PRE_ID),
+        Column::new(String::from("client_cert"), CLIENT_CERT_COL_PRE_ID),
+        Column::new(String::from("client_key"), CLIENT_KEY_COL_PRE_ID),
+    ];
+
+    let statement = Update::new(update_fields, String::from("repositories"))
+        .where_condition(Where::Equal(NAME_COL_PRE_ID, String::from("name")))
+        .to_string();
+
+    let mut sql = core_db.prepare(statement, super::SQL_NO_CALLBACK_FN)?;
+
+    try_bind_val!(sql, NAME_COL_PRE_ID, name);
+    try_bind_val!(sql, ARCH_FILTER_COL_PRE_ID, options.arch_filter.join(","));
+    try_bind_val!(sql, INCLUDE_PKGS_COL_PRE_ID, options.include_pkgs.join(","));
+    try_bind_val!(sql, EXCLUDE_PKGS_COL_PRE_ID, options.exclude_pkgs.join(","));
+    try_bind_val!(sql, MIRRORS_COL_PRE_ID, options.mirrors.join(","));
+    try_bind_val!(
+        sql,
+        SIGNATURE_LEVEL_COL_PRE_ID,
+        options.signature_level.as_str()
+    );
+
+    if let Some(bandwidth_limit) = options.bandwidth_limit {
+        try_bind_val!(sql, BANDWIDTH_LIMIT_COL_PRE_ID, bandwidth_limit as i64);
+    } else {
+        try_bind_val!(sql, BANDWIDTH_LIMIT_COL_PRE_ID, SQLITE_NULL);
+    }
+
+    if let Some(proxy) = &options.proxy {
+        try_bind_val!(sql, PROXY_COL_PRE_ID, proxy.as_str());
+    } else {
+        try_bind_val!(sql, PROXY_COL_PRE_ID, SQLITE_NULL);
+    }
+
+    for (pre_id, value) in [
+        (CA_BUNDLE_COL_PRE_ID, &options.ca_bundle),
+        (CLIENT_CERT_COL_PRE_ID, &options.client_cert),
+        (CLIENT_KEY_COL_PRE_ID, &options.client_key),
+    ] {
+        if let Some(value) = value {
+            try_bind_val!(sql, pre_id, value.as_str());
+        } else {
+            try_bind_val!(sql, pre_id, SQLITE_NULL);
+        }
+    }
+
+    logger::debug!("Updating options of repository '{name}'");
+    let status = try_execute_prepared!(
+        sql,
+        simple_e_fmt!("Error on updating options of repository {name}")
+    );
+
+    Ok(status)
+}
@@ -0,0 +1,155 @@
+use common::meta::ScriptStruct;
+use ehandle::{
+    db::SqlError, lpm::LpmError, simple_e_fmt, try_bind_val, try_execute_prepared, ErrorCommons,
+};
+use min_sqlite3_sys::prelude::*;
+use sql_builder::delete::*;
+use sql_builder::insert::Insert;
+use sql_builder::update::Update;
+use sql_builder::Column;
+use std::path::Path;
+
+/// Records the script checksums of a package, which its scripts are checked
+/// with from then on. `None` for the packages that predate them.
+pub(crate) fn insert_scripts(
+    core_db: &Database,
+    pkg_id: i64,
+    scripts: Option<&[ScriptStruct]>,
+) -> Result<(), LpmError<SqlError>> {
+    const HAS_SCRIPT_CHECKSUMS_COL_PRE_ID: usize = 1;
+    const PKG_ID_PRE_ID: usize = 2;
+
+    let statement = Update::new(
+        vec![Column::new(
+            String::from("has_script_checksums"),
+            HAS_SCRIPT_CHECKSUMS_COL_PRE_ID,
+        )],
+        String::from("packages"),
+    )
+    .where_condition(Where::Equal(PKG_ID_PRE_ID, String::from("id")))
+    .to_string();
+
+    let mut sql = core_db.prepare(statement, super::SQL_NO_CALLBACK_FN)?;
+    try_bind_val!(
+        sql,
+        HAS_SCRIPT_CHECKSUMS_COL_PRE_ID,
+        i64::from(scripts.is_some())
+    );
+    try_bind_val!(sql, PKG_ID_PRE_ID, pkg_id);
+    try_execute_prepared!(
+        sql,
+        simple_e_fmt!("Could not record the script checksums of package_id {pkg_id}.")
+    );
+
+    const NAME_COL_PRE_ID: usize = 1;
+    const CHECKSUM_COL_PRE_ID: usize = 2;
+    const PACKAGE_ID_COL_PRE_ID: usize = 3;
+
+    for script in scripts.unwrap_or_default() {
+        let columns = vec![
+            Column::new(String::from("name"), NAME_COL_PRE_ID),
+            Column::new(String::from("checksum"), CHECKSUM_COL_PRE_ID),
+            Column::new(String::from("package_id"), PACKAGE_ID_COL_PRE_ID),
+        ];
+        let statement = Insert::new(Some(columns), String::from("package_scripts")).to_string();
+
+        let mut sql = core_db.prepare(statement, super::SQL_NO_CALLBACK_FN)?;
+
+        try_bind_val!(sql, NAME_COL_PRE_ID, &*script.name);
+        try_bind_val!(sql, CHECKSUM_COL_PRE_ID, &*script.checksum);
+        try_bind_val!(sql, PACKAGE_ID_COL_PRE_ID, pkg_id);
+
+        try_execute_prepared!(
+            sql,
+            simple_e_fmt!("Could not insert to \"package_scripts\" table.")
+        );
+    }
+
+    Ok(())
+}
+
+pub(crate) fn delete_scripts(core_db: &Database, pkg_id: i64) -> Result<(), LpmError<SqlError>> {
+    const PACKAGE_ID_COL_PRE_ID: usize = 1;
+
+    let statement = Delete::new(String::from("package_scripts"))
+        .where_condition(Where::Equal(
+            PACKAGE_ID_COL_PRE_ID,
+            String::from("package_id"),
+        ))
+        .to_string();
+
+    let mut sql = core_db.prepare(statement, super::SQL_NO_CALLBACK_FN)?;
+    try_bind_val!(sql, PACKAGE_ID_COL_PRE_ID, pkg_id);
+
+    try_execute_prepared!(
+        sql,
+        simple_e_fmt!(
+            "Could not delete from 'package_scripts' for package_id {}.",
+            pkg_id
+        )
+    );
+
+    Ok(())
+}
+
+/// Script checksums that the `pkg_id` package was installed with, `None` if
+/// it predates them.
+pub(crate) fn get_package_scripts(
+    core_db: &Database,
+    pkg_id: i64,
+) -> Result<Option<Vec<ScriptStruct>>, LpmError<SqlError>> {
+    let statement = String::from("SELECT has_script_checksums FROM packages WHERE id = ?1;");
+
+    let mut sql = core_db.prepare(statement, super::SQL_NO_CALLBACK_FN)?;
+    try_bind_val!(sql, 1, pkg_id);
+    let has_script_checksums = match sql.execute_prepared() {
+        PreparedStatementStatus::FoundRow => sql.get_data::<i64>(0)? != 0,
+        _ => false,
+    };
+    if !has_script_checksums {
+        return Ok(None);
+    }
+
+    let statement =
+        String::from("SELECT name, checksum FROM package_scripts WHERE package_id = ?1;");
+
+    let mut sql = core_db.prepare(statement, super::SQL_NO_CALLBACK_FN)?;
+    try_bind_val!(sql, 1, pkg_id);
+
+    let mut scripts = vec![];
+    while let PreparedStatementStatus::FoundRow = sql.execute_prepared() {
+        scripts.push(ScriptStruct {
+            name: sql.get_data(0)?,
+            checksum: sql.get_data(1)?,
+        });
+    }
+
+    Ok(Some(scripts))
+}
+
+/// Fills `package_scripts` from the meta data of the installed packages, the
+/// last time that it's read for them.
+pub(crate) fn backfill_scripts(core_db: &Database) -> Result<(), LpmError<SqlError>> {
+    let statement = String::from("SELECT id, name, slot FROM packages;");
+    let mut sql = core_db.prepare(statement, super::SQL_NO_CALLBACK_FN)?;
+
+    let mut pkgs: Vec<(i64, String, String)> = vec![];
+    while let PreparedStatementStatus::FoundRow = sql.execute_prepared() {
+        pkgs.push((sql.get_data(0)?, sql.get_data(1)?, sql.get_data(2)?));
+    }
+
+    for (pkg_id, pkg_name, pkg_slot) in pkgs {
+        let meta_path = Path::new(super::PKG_DATA_DIR)
+            .join(super::pkg_data_dir_name(&pkg_name, Some(&pkg_slot)))
+            .join("meta.json");
+
+        match super::relations::read_meta(&meta_path) {
+            Some(meta) => insert_scripts(core_db, pkg_id, meta.scripts.as_deref())?,
+            None => {
+                logger::debug!("No meta data found for '{pkg_name}', skipping its scripts.")
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -61,6 +61,8 @@ macro_rules! try_execute {
 #[derive(Debug, Clone)]
 pub enum MigrationErrorKind {
     VersionCouldNotSet,
+    /// 1st arg: Name of the migration that was asked to be reverted
+    NotApplied(String),
 }
 
 #[non_exhaustive]
@@ -71,6 +73,10 @@ pub enum SqlErrorKind {
     FailedParameterBinding(usize, String, SqlitePrimaryResult),
     WrapperLibError(String, String),
     MigrationError(MigrationErrorKind),
+    /// 1st arg: Problems found by `lpm --db check`
+    CheckFailed(Vec<String>),
+    /// 1st arg: Package path that can't be stored, see `PkgPath::new`
+    InvalidPath(String),
 }
 
 #[derive(Debug)]
@@ -89,6 +95,8 @@ impl ErrorCommons for SqlErrorKind {
             Self::FailedParameterBinding(..) => "FailedParameterBinding",
             SqlErrorKind::WrapperLibError(..) => "WrapperLibError",
             SqlErrorKind::MigrationError(_) => "MigrationError",
+            SqlErrorKind::CheckFailed(_) => "CheckFailed",
+            SqlErrorKind::InvalidPath(_) => "InvalidPath",
         }
     }
 
@@ -128,6 +136,18 @@ impl ErrorCommons for SqlErrorKind {
                     error
                 ),
             },
+            SqlErrorKind::CheckFailed(ref problems) => Self::Error {
+                kind: self.as_str().to_owned(),
+                reason: format!(
+                    "Database check found {} problem(s):\n  - {}",
+                    problems.len(),
+                    problems.join("\n  - ")
+                ),
+            },
+            SqlErrorKind::InvalidPath(ref error) => Self::Error {
+                kind: self.as_str().to_owned(),
+                reason: error.clone(),
+            },
         }
     }
 
@@ -155,6 +175,8 @@ impl ErrorCommons for SqlErrorKind {
             }
             SqlErrorKind::WrapperLibError(_, _) => ResultCode::SqlError_WrapperLibError,
             SqlErrorKind::MigrationError(_) => ResultCode::SqlError_MigrationError,
+            SqlErrorKind::CheckFailed(_) => ResultCode::SqlError_CheckFailed,
+            SqlErrorKind::InvalidPath(_) => ResultCode::SqlError_InvalidPath,
         }
     }
 }
@@ -27,6 +27,18 @@ pub enum ResultCode {
     PackageError_FailedExecutingStage1Script = 110,
     PackageError_InvalidPackageName = 111,
     PackageError_DependencyOfAnotherPackage = 112,
+    PackageError_ArchiveVerificationFailed = 113,
+    PackageError_MissingSharedLibraries = 114,
+    PackageError_BinaryArchitectureMismatch = 115,
+    PackageError_UnknownAlternative = 116,
+    PackageError_FileConflict = 117,
+    PackageError_EssentialPackage = 118,
+    PackageError_ProtectedPaths = 119,
+    PackageError_PlanChanged = 120,
+    PackageError_UnsafeArchiveEntry = 121,
+    PackageError_ArchiveLimitExceeded = 122,
+    PackageError_ScriptChecksumMismatch = 123,
+    PackageError_InterpreterNotAllowed = 124,
 
     // 200-299 Module related errors
     ModuleError_DynamicLibraryNotFound = 200,
@@ -64,12 +76,23 @@ pub enum ResultCode {
     SqlError_WrapperLibError = 403,
     SqlError_MigrationError = 404,
     MinSqliteWrapperError = 405,
+    SqlError_CheckFailed = 406,
+    SqlError_InvalidPath = 407,
 
     // 500-599 Repository related errors
     RepositoryError_RepositoryNotFound = 500,
     RepositoryError_RepositoryAlreadyExists = 501,
     RepositoryError_Internal = 502,
     RepositoryError_PackageNotFound = 503,
+    RepositoryError_InvalidRepositoryOption = 504,
+    RepositoryError_MissingOfflineArtifacts = 505,
+    RepositoryError_StaleMetadata = 506,
+    RepositoryError_UnsupportedIndexSchema = 507,
+    RepositoryError_InvalidRepositoryAddress = 508,
+    RepositoryError_DuplicateRepositoryAddress = 509,
+    RepositoryError_UnreachableRepository = 510,
+    RepositoryError_InvalidIndexSignature = 511,
+    RepositoryError_UntrustedRepository = 512,
 
     // 900-999 ABI related errors
     Str_Utf8Error = 900,
@@ -87,6 +110,8 @@ impl ResultCode {
             "SqlError_FailedParameterBinding" => Self::SqlError_FailedParameterBinding,
             "SqlError_WrapperLibError" => Self::SqlError_WrapperLibError,
             "SqlError_MigrationError" => Self::SqlError_MigrationError,
+            "SqlError_CheckFailed" => Self::SqlError_CheckFailed,
+            "SqlError_InvalidPath" => Self::SqlError_InvalidPath,
 
             "ModuleError_Internal" => Self::ModuleError_Internal,
             "ModuleError_EntrypointFunctionNotFound" => {
@@ -111,9 +136,55 @@ impl ResultCode {
             "PackageError_FailedExecutingStage1Script" => {
                 Self::PackageError_FailedExecutingStage1Script
             }
+            "PackageError_InvalidPackageName" => Self::PackageError_InvalidPackageName,
+            "PackageError_DependencyOfAnotherPackage" => {
+                Self::PackageError_DependencyOfAnotherPackage
+            }
+            "PackageError_ArchiveVerificationFailed" => {
+                Self::PackageError_ArchiveVerificationFailed
+            }
+            "PackageError_MissingSharedLibraries" => Self::PackageError_MissingSharedLibraries,
+            "PackageError_BinaryArchitectureMismatch" => {
+                Self::PackageError_BinaryArchitectureMismatch
+            }
+            "PackageError_UnknownAlternative" => Self::PackageError_UnknownAlternative,
+            "PackageError_FileConflict" => Self::PackageError_FileConflict,
+            "PackageError_EssentialPackage" => Self::PackageError_EssentialPackage,
+            "PackageError_ProtectedPaths" => Self::PackageError_ProtectedPaths,
+            "PackageError_PlanChanged" => Self::PackageError_PlanChanged,
+            "PackageError_UnsafeArchiveEntry" => Self::PackageError_UnsafeArchiveEntry,
+            "PackageError_ArchiveLimitExceeded" => Self::PackageError_ArchiveLimitExceeded,
+            "PackageError_ScriptChecksumMismatch" => Self::PackageError_ScriptChecksumMismatch,
+            "PackageError_InterpreterNotAllowed" => Self::PackageError_InterpreterNotAllowed,
 
             "MinSqliteWrapperError" => Self::MinSqliteWrapperError,
 
+            "RepositoryError_RepositoryNotFound" => Self::RepositoryError_RepositoryNotFound,
+            "RepositoryError_RepositoryAlreadyExists" => {
+                Self::RepositoryError_RepositoryAlreadyExists
+            }
+            "RepositoryError_Internal" => Self::RepositoryError_Internal,
+            "RepositoryError_PackageNotFound" => Self::RepositoryError_PackageNotFound,
+            "RepositoryError_InvalidRepositoryOption" => {
+                Self::RepositoryError_InvalidRepositoryOption
+            }
+            "RepositoryError_MissingOfflineArtifacts" => {
+                Self::RepositoryError_MissingOfflineArtifacts
+            }
+            "RepositoryError_StaleMetadata" => Self::RepositoryError_StaleMetadata,
+            "RepositoryError_UnsupportedIndexSchema" => {
+                Self::RepositoryError_UnsupportedIndexSchema
+            }
+            "RepositoryError_InvalidRepositoryAddress" => {
+                Self::RepositoryError_InvalidRepositoryAddress
+            }
+            "RepositoryError_DuplicateRepositoryAddress" => {
+                Self::RepositoryError_DuplicateRepositoryAddress
+            }
+            "RepositoryError_UnreachableRepository" => Self::RepositoryError_UnreachableRepository,
+            "RepositoryError_InvalidIndexSignature" => Self::RepositoryError_InvalidIndexSignature,
+            "RepositoryError_UntrustedRepository" => Self::RepositoryError_UntrustedRepository,
+
             "IoError" => Self::IoError,
             "IoError_NotFound" => Self::IoError_NotFound,
             "IoError_PermissionDenied" => Self::IoError_PermissionDenied,
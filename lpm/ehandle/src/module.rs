@@ -43,7 +43,7 @@ impl ErrorCommons for ModuleErrorKind {
             ModuleErrorKind::EntrypointFunctionNotFound => Self::Error {
                 kind: self.as_str().to_owned(),
                 reason: String::from(
-                    "'lpm_entrypoint' function is not found in the dynamic library.",
+                    "Neither 'lpm_module_entrypoint' nor 'lpm_entrypoint' function is found in the dynamic library.",
                 ),
             },
             ModuleErrorKind::Internal(reason) => Self::Error {
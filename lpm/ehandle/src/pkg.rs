@@ -7,7 +7,8 @@ use min_sqlite3_sys::prelude::MinSqliteWrapperError;
 #[non_exhaustive]
 #[derive(Debug, Clone)]
 pub enum PackageErrorKind {
-    InvalidPackageFiles,
+    /// Problems of each invalid file, e.g. `/usr/bin/htop: missing from the package`.
+    InvalidPackageFiles(Vec<String>),
     UnsupportedPackageArchitecture(String),
     UnsupportedChecksumAlgorithm(String),
     InstallationFailed(String),
@@ -17,9 +18,53 @@ pub enum PackageErrorKind {
     DoesNotExists(String),
     UnrecognizedRepository(String),
     DbOperationFailed(String),
-    FailedExecutingStage1Script { script_name: String, output: String },
+    FailedExecutingStage1Script {
+        script_name: String,
+        output: String,
+    },
     InvalidPackageName(String),
-    DependencyOfAnotherPackage { package: String, depends_on: String },
+    DependencyOfAnotherPackage {
+        package: String,
+        depends_on: String,
+    },
+    ArchiveVerificationFailed {
+        package: String,
+        reason: String,
+    },
+    MissingSharedLibraries(Vec<String>),
+    BinaryArchitectureMismatch {
+        path: String,
+        found: String,
+        declared: String,
+    },
+    UnknownAlternative(String),
+    FileConflict {
+        path: String,
+        owner: String,
+    },
+    EssentialPackage(String),
+    ProtectedPaths {
+        package: String,
+        paths: Vec<String>,
+    },
+    PlanChanged(String),
+    UnsafeArchiveEntry {
+        package: String,
+        path: String,
+        reason: String,
+    },
+    ArchiveLimitExceeded {
+        package: String,
+        reason: String,
+    },
+    ScriptChecksumMismatch {
+        package: String,
+        script_name: String,
+    },
+    InterpreterNotAllowed {
+        package: String,
+        interpreter: String,
+    },
 }
 
 impl ErrorCommons for PackageErrorKind {
@@ -27,7 +72,7 @@ impl ErrorCommons for PackageErrorKind {
 
     fn as_str(&self) -> &str {
         match self {
-            Self::InvalidPackageFiles => "InvalidPackageFiles",
+            Self::InvalidPackageFiles(_) => "InvalidPackageFiles",
             Self::UnsupportedChecksumAlgorithm(_) => "UnsupportedChecksumAlgorithm",
             Self::UnsupportedPackageArchitecture(_) => "UnsupportedPackageArchitecture",
             Self::InstallationFailed(_) => "InstallationFailed",
@@ -40,15 +85,28 @@ impl ErrorCommons for PackageErrorKind {
             Self::FailedExecutingStage1Script { .. } => "FailedExecutingStage1Script",
             Self::InvalidPackageName(_) => "InvalidPackageName",
             Self::DependencyOfAnotherPackage { .. } => "DependencyOfAnotherPackage",
+            Self::ArchiveVerificationFailed { .. } => "ArchiveVerificationFailed",
+            Self::MissingSharedLibraries(_) => "MissingSharedLibraries",
+            Self::BinaryArchitectureMismatch { .. } => "BinaryArchitectureMismatch",
+            Self::UnknownAlternative(_) => "UnknownAlternative",
+            Self::FileConflict { .. } => "FileConflict",
+            Self::EssentialPackage(_) => "EssentialPackage",
+            Self::ProtectedPaths { .. } => "ProtectedPaths",
+            Self::PlanChanged(_) => "PlanChanged",
+            Self::UnsafeArchiveEntry { .. } => "UnsafeArchiveEntry",
+            Self::ArchiveLimitExceeded { .. } => "ArchiveLimitExceeded",
+            Self::ScriptChecksumMismatch { .. } => "ScriptChecksumMismatch",
+            Self::InterpreterNotAllowed { .. } => "InterpreterNotAllowed",
         }
     }
 
     fn to_err(&self) -> Self::Error {
         match self {
-            Self::InvalidPackageFiles => Self::Error {
+            Self::InvalidPackageFiles(ref problems) => Self::Error {
                 kind: self.as_str().to_owned(),
-                reason: String::from(
-                    "According to the checksum file, the package files are not valid.",
+                reason: format!(
+                    "According to the checksum file, the package files are not valid:\n  - {}",
+                    problems.join("\n  - ")
                 ),
             },
             Self::UnsupportedChecksumAlgorithm(ref algorithm) => Self::Error {
@@ -108,6 +166,54 @@ impl ErrorCommons for PackageErrorKind {
                 kind: self.as_str().to_owned(),
                 reason: format!("'{package}' is dependency of '{depends_on}' package.")
             },
+            Self::ArchiveVerificationFailed{ package, reason } => Self::Error {
+                kind: self.as_str().to_owned(),
+                reason: format!("Downloaded archive of '{package}' is either corrupted or tampered. {reason}")
+            },
+            Self::MissingSharedLibraries(ref libraries) => Self::Error {
+                kind: self.as_str().to_owned(),
+                reason: format!("Shared libraries could not be found on the system:\n  - {}", libraries.join("\n  - "))
+            },
+            Self::BinaryArchitectureMismatch{ path, found, declared } => Self::Error {
+                kind: self.as_str().to_owned(),
+                reason: format!("'{path}' is built for '{found}', but the package is declared as '{declared}'.")
+            },
+            Self::UnknownAlternative(ref alternative) => Self::Error {
+                kind: self.as_str().to_owned(),
+                reason: format!("No installed package provides the '{alternative}' alternative.")
+            },
+            Self::FileConflict{ path, owner } => Self::Error {
+                kind: self.as_str().to_owned(),
+                reason: format!("'{path}' is already owned by '{owner}'.")
+            },
+            Self::EssentialPackage(ref package) => Self::Error {
+                kind: self.as_str().to_owned(),
+                reason: format!("'{package}' is an essential package, use '--force-essential' to delete it anyway.")
+            },
+            Self::ProtectedPaths{ package, paths } => Self::Error {
+                kind: self.as_str().to_owned(),
+                reason: format!("'{package}' tries to modify protected paths:\n  - {}", paths.join("\n  - "))
+            },
+            Self::PlanChanged(ref plan_path) => Self::Error {
+                kind: self.as_str().to_owned(),
+                reason: format!("The resolved transaction differs from the approved plan '{plan_path}', create and approve a new one.")
+            },
+            Self::UnsafeArchiveEntry{ package, path, reason } => Self::Error {
+                kind: self.as_str().to_owned(),
+                reason: format!("Refusing to extract '{package}', '{path}' {reason}. The package might be malicious.")
+            },
+            Self::ArchiveLimitExceeded{ package, reason } => Self::Error {
+                kind: self.as_str().to_owned(),
+                reason: format!("Stopped extracting '{package}', {reason}. See 'extraction_limits' of the config.")
+            },
+            Self::ScriptChecksumMismatch{ package, script_name } => Self::Error {
+                kind: self.as_str().to_owned(),
+                reason: format!("Refusing to run the '{script_name}' script of '{package}', it doesn't match the checksum in its meta data. The script might have been tampered with.")
+            },
+            Self::InterpreterNotAllowed{ package, interpreter } => Self::Error {
+                kind: self.as_str().to_owned(),
+                reason: format!("Scripts of '{package}' need the '{interpreter}' interpreter, which is not in 'allowed_script_interpreters' of the config. Use '--allow-interpreter {interpreter}' to install it anyway.")
+            },
         }
     }
 
@@ -126,7 +232,9 @@ impl ErrorCommons for PackageErrorKind {
     #[cfg(feature = "sdk")]
     fn to_result_code(&self) -> ResultCode {
         match self {
-            PackageErrorKind::InvalidPackageFiles => ResultCode::PackageError_InvalidPackageFiles,
+            PackageErrorKind::InvalidPackageFiles(_) => {
+                ResultCode::PackageError_InvalidPackageFiles
+            }
             PackageErrorKind::UnsupportedPackageArchitecture(_) => {
                 ResultCode::PackageError_UnsupportedPackageArchitecture
             }
@@ -151,6 +259,32 @@ impl ErrorCommons for PackageErrorKind {
             PackageErrorKind::DependencyOfAnotherPackage { .. } => {
                 ResultCode::PackageError_DependencyOfAnotherPackage
             }
+            PackageErrorKind::ArchiveVerificationFailed { .. } => {
+                ResultCode::PackageError_ArchiveVerificationFailed
+            }
+            PackageErrorKind::MissingSharedLibraries(_) => {
+                ResultCode::PackageError_MissingSharedLibraries
+            }
+            PackageErrorKind::BinaryArchitectureMismatch { .. } => {
+                ResultCode::PackageError_BinaryArchitectureMismatch
+            }
+            PackageErrorKind::UnknownAlternative(_) => ResultCode::PackageError_UnknownAlternative,
+            PackageErrorKind::FileConflict { .. } => ResultCode::PackageError_FileConflict,
+            PackageErrorKind::EssentialPackage(_) => ResultCode::PackageError_EssentialPackage,
+            PackageErrorKind::ProtectedPaths { .. } => ResultCode::PackageError_ProtectedPaths,
+            PackageErrorKind::PlanChanged(_) => ResultCode::PackageError_PlanChanged,
+            PackageErrorKind::UnsafeArchiveEntry { .. } => {
+                ResultCode::PackageError_UnsafeArchiveEntry
+            }
+            PackageErrorKind::ArchiveLimitExceeded { .. } => {
+                ResultCode::PackageError_ArchiveLimitExceeded
+            }
+            PackageErrorKind::ScriptChecksumMismatch { .. } => {
+                ResultCode::PackageError_ScriptChecksumMismatch
+            }
+            PackageErrorKind::InterpreterNotAllowed { .. } => {
+                ResultCode::PackageError_InterpreterNotAllowed
+            }
         }
     }
 }
@@ -13,6 +13,31 @@ pub enum RepositoryErrorKind {
     RepositoryNotFound(String),
     RepositoryAlreadyExists(String),
     PackageNotFound(String),
+    InvalidRepositoryOption(String),
+    MissingOfflineArtifacts(Vec<String>),
+    StaleMetadata(String),
+    UnsupportedIndexSchema {
+        repository: String,
+        version: u32,
+        supported: u32,
+    },
+    InvalidRepositoryAddress(String, String),
+    DuplicateRepositoryAddress {
+        address: String,
+        repository: String,
+    },
+    UnreachableRepository {
+        address: String,
+        reason: String,
+    },
+    InvalidIndexSignature {
+        repository: String,
+        reason: String,
+    },
+    UntrustedRepository {
+        package: String,
+        repository: String,
+    },
     Internal(String),
 }
 
@@ -30,6 +55,15 @@ impl ErrorCommons for RepositoryErrorKind {
             Self::RepositoryNotFound(_) => "RepositoryNotFound",
             Self::RepositoryAlreadyExists(_) => "RepositoryAlreadyExists",
             Self::PackageNotFound(_) => "PackageNotFound",
+            Self::InvalidRepositoryOption(_) => "InvalidRepositoryOption",
+            Self::MissingOfflineArtifacts(_) => "MissingOfflineArtifacts",
+            Self::StaleMetadata(_) => "StaleMetadata",
+            Self::UnsupportedIndexSchema { .. } => "UnsupportedIndexSchema",
+            Self::InvalidRepositoryAddress(..) => "InvalidRepositoryAddress",
+            Self::DuplicateRepositoryAddress { .. } => "DuplicateRepositoryAddress",
+            Self::UnreachableRepository { .. } => "UnreachableRepository",
+            Self::InvalidIndexSignature { .. } => "InvalidIndexSignature",
+            Self::UntrustedRepository { .. } => "UntrustedRepository",
             Self::Internal(_) => "Internal",
         }
     }
@@ -48,6 +82,55 @@ impl ErrorCommons for RepositoryErrorKind {
                 kind: self.as_str().to_owned(),
                 reason: format!("Package '{pkg_name}' not found in the repository."),
             },
+            Self::InvalidRepositoryOption(option) => Self::Error {
+                kind: self.as_str().to_owned(),
+                reason: format!("'{option}' is not a valid repository option. Expected one of 'arch=<list>', 'include=<list>', 'exclude=<list>', 'signature=never|optional|required', 'bandwidth=<size>', 'proxy=<url>', 'mirrors=<list>', 'ca=<path>', 'cert=<path>' or 'key=<path>'."),
+            },
+            Self::MissingOfflineArtifacts(artifacts) => Self::Error {
+                kind: self.as_str().to_owned(),
+                reason: format!(
+                    "Offline mode is enabled and following artifacts are not available locally:\n  - {}",
+                    artifacts.join("\n  - ")
+                ),
+            },
+            Self::StaleMetadata(name) => Self::Error {
+                kind: self.as_str().to_owned(),
+                reason: format!("Index metadata of '{name}' repository has expired or has no expiry date. Update the repository indexes, or set 'allow_stale_metadata' in the config if the repository is known to be frozen."),
+            },
+            Self::UnsupportedIndexSchema {
+                repository,
+                version,
+                supported,
+            } => Self::Error {
+                kind: self.as_str().to_owned(),
+                reason: format!("Index of '{repository}' repository uses schema version {version}, but this lpm only supports up to version {supported}. Please upgrade lpm."),
+            },
+            Self::InvalidRepositoryAddress(address, reason) => Self::Error {
+                kind: self.as_str().to_owned(),
+                reason: format!("'{address}' is not a valid repository address, {reason}."),
+            },
+            Self::DuplicateRepositoryAddress {
+                address,
+                repository,
+            } => Self::Error {
+                kind: self.as_str().to_owned(),
+                reason: format!("'{address}' is already registered as '{repository}' repository."),
+            },
+            Self::UnreachableRepository { address, reason } => Self::Error {
+                kind: self.as_str().to_owned(),
+                reason: format!("Repository at '{address}' is not reachable, {reason}. Use '--skip-check' to add it anyway."),
+            },
+            Self::InvalidIndexSignature { repository, reason } => Self::Error {
+                kind: self.as_str().to_owned(),
+                reason: format!("Index of '{repository}' repository can't be verified, {reason}."),
+            },
+            Self::UntrustedRepository {
+                package,
+                repository,
+            } => Self::Error {
+                kind: self.as_str().to_owned(),
+                reason: format!("Package '{package}' comes from '{repository}' repository, which is not trusted. Only repositories with 'signature=required' and a signed index are."),
+            },
             Self::Internal(reason) => Self::Error {
                 kind: self.as_str().to_owned(),
                 reason: reason.to_owned(),
@@ -71,6 +154,21 @@ impl ErrorCommons for RepositoryErrorKind {
             Self::RepositoryNotFound(_) => ResultCode::RepositoryError_RepositoryNotFound,
             Self::RepositoryAlreadyExists(_) => ResultCode::RepositoryError_RepositoryAlreadyExists,
             Self::PackageNotFound(_) => ResultCode::RepositoryError_PackageNotFound,
+            Self::InvalidRepositoryOption(_) => ResultCode::RepositoryError_InvalidRepositoryOption,
+            Self::MissingOfflineArtifacts(_) => ResultCode::RepositoryError_MissingOfflineArtifacts,
+            Self::StaleMetadata(_) => ResultCode::RepositoryError_StaleMetadata,
+            Self::UnsupportedIndexSchema { .. } => {
+                ResultCode::RepositoryError_UnsupportedIndexSchema
+            }
+            Self::InvalidRepositoryAddress(..) => {
+                ResultCode::RepositoryError_InvalidRepositoryAddress
+            }
+            Self::DuplicateRepositoryAddress { .. } => {
+                ResultCode::RepositoryError_DuplicateRepositoryAddress
+            }
+            Self::UnreachableRepository { .. } => ResultCode::RepositoryError_UnreachableRepository,
+            Self::InvalidIndexSignature { .. } => ResultCode::RepositoryError_InvalidIndexSignature,
+            Self::UntrustedRepository { .. } => ResultCode::RepositoryError_UntrustedRepository,
             Self::Internal(_) => ResultCode::RepositoryError_Internal,
         }
     }
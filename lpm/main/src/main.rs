@@ -1,7 +1,10 @@
-use cli_parser::{CliParser, Command, ModuleSubcommand, RepositorySubcommand, UpdateSubcommand};
+use cli_parser::{
+    AlternativesSubcommand, AutoUpdateSubcommand, CliParser, Command, DbSubcommand,
+    ModuleSubcommand, RepositorySubcommand, UpdateSubcommand,
+};
 use common::some_or_error;
 use core::*;
-use std::{env, panic};
+use std::{cell::OnceCell, env, panic, path::Path};
 
 macro_rules! try_or_error {
     ($fn: expr) => {
@@ -16,6 +19,8 @@ macro_rules! try_or_error {
 }
 
 const LPM_VERSION: &str = env!("CARGO_PKG_VERSION");
+/// Exit code of `--check-updates` when there are updates to apply.
+const UPDATES_AVAILABLE_EXIT_CODE: i32 = 100;
 
 fn main() {
     panic::set_hook(Box::new(|info| logger::error!("{info}")));
@@ -27,10 +32,19 @@ fn main() {
 
     let args: Vec<String> = env::args().collect();
     let cli_parser = CliParser::parse_args(&args);
+    logger::set_verbose(cli_parser.verbose);
+    logger::set_logs_to_stderr(cli_parser.print_plan == Some("json"));
     let ctx = || try_or_error!(Ctx::new_from_cli_parser(&cli_parser));
+    // For the commands that only run queries, which work without root.
+    let read_only_ctx = || try_or_error!(Ctx::new_read_only_from_cli_parser(&cli_parser));
 
-    if cli_parser.commands.is_empty() {
+    let print_general_help = || {
         Command::Help.print_help();
+        try_or_error!(print_module_summaries());
+    };
+
+    if cli_parser.commands.is_empty() {
+        print_general_help();
     }
 
     let mut should_print_green_message = false;
@@ -46,42 +60,80 @@ fn main() {
                     command.print_help();
                 }
 
-                try_or_error!(install_package(ctx(), args));
+                try_or_error!(run_transaction(&mut ctx(), Operation::Install, |ctx| {
+                    install_package(ctx, args)
+                }));
             }
 
             Command::Update(pkg_name, subcommands) => {
                 should_print_green_message = true;
 
+                // All steps of the update share the same connection, which
+                // is opened on first use.
+                let update_ctx = OnceCell::new();
+                let ctx = || update_ctx.get_or_init(ctx);
+
                 if subcommands.is_empty() {
                     if let Some(pkg_name) = pkg_name {
-                        try_or_error!(update_pkg_from_repository(ctx(), pkg_name));
+                        try_or_error!(run_transaction(ctx(), Operation::Update, |ctx| {
+                            update_pkg_from_repository(ctx, pkg_name)
+                        }));
                     } else {
-                        try_or_error!(update_database_migrations());
-                        try_or_error!(get_and_apply_repository_patches(&core_db()));
-                        try_or_error!(update_pkgs_from_repository(ctx()));
+                        try_or_error!(update_database_migrations(&ctx().core_db));
+                        try_or_error!(get_and_apply_repository_patches(ctx()));
+                        try_or_error!(run_transaction(ctx(), Operation::Update, |ctx| {
+                            update_pkgs_from_repository(ctx)
+                        }));
                     }
                 }
 
                 for subcommand in subcommands {
                     match subcommand {
                         UpdateSubcommand::Local(lod_path) => {
-                            try_or_error!(update_pkg_from_lod_file(
-                                ctx(),
-                                pkg_name.expect("Package name is missing."),
-                                lod_path
-                            ))
+                            let pkg_name = pkg_name.expect("Package name is missing.");
+                            try_or_error!(run_transaction(ctx(), Operation::Update, |ctx| {
+                                update_pkg_from_lod_file(ctx, pkg_name, Path::new(lod_path))
+                            }));
                         }
                         UpdateSubcommand::Index => {
-                            try_or_error!(get_and_apply_repository_patches(&core_db()))
+                            try_or_error!(get_and_apply_repository_patches(ctx()))
+                        }
+                        UpdateSubcommand::Db => {
+                            try_or_error!(update_database_migrations(&ctx().core_db))
                         }
-                        UpdateSubcommand::Db => try_or_error!(update_database_migrations()),
                         UpdateSubcommand::Packages => {
-                            try_or_error!(update_pkgs_from_repository(ctx()))
+                            try_or_error!(run_transaction(ctx(), Operation::Update, |ctx| {
+                                update_pkgs_from_repository(ctx)
+                            }));
                         }
                         UpdateSubcommand::All => {
-                            try_or_error!(update_database_migrations());
-                            try_or_error!(get_and_apply_repository_patches(&core_db()));
-                            try_or_error!(update_pkgs_from_repository(ctx()));
+                            try_or_error!(update_database_migrations(&ctx().core_db));
+                            try_or_error!(get_and_apply_repository_patches(ctx()));
+                            try_or_error!(run_transaction(ctx(), Operation::Update, |ctx| {
+                                update_pkgs_from_repository(ctx)
+                            }));
+                        }
+
+                        UpdateSubcommand::Stage => {
+                            try_or_error!(update_database_migrations(&ctx().core_db));
+                            try_or_error!(get_and_apply_repository_patches(ctx()));
+                            try_or_error!(stage_updates(ctx()))
+                        }
+                        UpdateSubcommand::ApplyStaged => {
+                            try_or_error!(run_transaction(ctx(), Operation::Update, |ctx| {
+                                apply_staged_updates(ctx)
+                            }));
+                        }
+                        UpdateSubcommand::Ab => {
+                            try_or_error!(update_database_migrations(&ctx().core_db));
+                            try_or_error!(get_and_apply_repository_patches(ctx()));
+                            // Installations are redirected into the slot, so it
+                            // gets a context of its own.
+                            let ab_ctx = try_or_error!(Ctx::new_from_cli_parser(&cli_parser));
+                            try_or_error!(assemble_ab_update(ab_ctx));
+                        }
+                        UpdateSubcommand::AbConfirm => {
+                            try_or_error!(confirm_ab_boot(ctx()))
                         }
 
                         UpdateSubcommand::Help => {
@@ -104,7 +156,9 @@ fn main() {
                     command.print_help();
                 }
 
-                try_or_error!(delete_packages(ctx(), args));
+                try_or_error!(run_transaction(&ctx(), Operation::Delete, |ctx| {
+                    delete_packages(ctx, args)
+                }));
             }
 
             Command::Module(subcommand) => match subcommand {
@@ -112,13 +166,16 @@ fn main() {
                     try_or_error!(trigger_lpm_module(&core_db(), args.clone()))
                 }
 
-                ModuleSubcommand::Add(list) => {
+                ModuleSubcommand::Add {
+                    arguments,
+                    allow_db_write,
+                } => {
                     should_print_green_message = true;
                     let (module_name, dylib_path) = (
-                        some_or_error!(list.first(), "Module name is missing"),
-                        some_or_error!(list.get(1), "Dynamic library path is missing"),
+                        some_or_error!(arguments.first(), "Module name is missing"),
+                        some_or_error!(arguments.get(1), "Dynamic library path is missing"),
                     );
-                    try_or_error!(add_module(ctx(), module_name, dylib_path))
+                    try_or_error!(add_module(ctx(), module_name, dylib_path, *allow_db_write))
                 }
 
                 ModuleSubcommand::Delete(module_names) => {
@@ -128,22 +185,31 @@ fn main() {
                     try_or_error!(delete_modules(ctx(), &module_names))
                 }
 
-                ModuleSubcommand::Help => {
+                ModuleSubcommand::Help(None) => {
                     should_print_green_message = false;
                     command.print_help();
+                    try_or_error!(print_module_summaries());
                 }
 
-                ModuleSubcommand::List => try_or_error!(print_modules(ctx())),
+                ModuleSubcommand::Help(Some(module_name)) => {
+                    should_print_green_message = false;
+                    try_or_error!(print_module_help(&core_db(), module_name));
+                }
+
+                ModuleSubcommand::List => try_or_error!(print_modules(read_only_ctx())),
             },
 
             Command::Repository(subcommand) => match subcommand {
-                RepositorySubcommand::Add(args) => {
+                RepositorySubcommand::Add {
+                    arguments,
+                    skip_check,
+                } => {
                     should_print_green_message = true;
                     let (name, address) = (
-                        some_or_error!(args.first(), "Repository name is missing"),
-                        some_or_error!(args.get(1), "Repository address is missing"),
+                        some_or_error!(arguments.first(), "Repository name is missing"),
+                        some_or_error!(arguments.get(1), "Repository address is missing"),
                     );
-                    try_or_error!(add_repository(ctx(), name, address));
+                    try_or_error!(add_repository(ctx(), name, address, *skip_check));
                 }
 
                 RepositorySubcommand::Delete(repository_names) => {
@@ -153,8 +219,55 @@ fn main() {
                     try_or_error!(delete_repositories(ctx(), &repository_names))
                 }
 
+                RepositorySubcommand::Configure(args) => {
+                    should_print_green_message = true;
+                    let name = some_or_error!(args.first(), "Repository name is missing");
+                    let options: Vec<String> = args.iter().skip(1).map(|t| t.to_string()).collect();
+                    try_or_error!(configure_repository(ctx(), name, &options))
+                }
+
+                RepositorySubcommand::RankMirrors(repository_names) => {
+                    should_print_green_message = true;
+                    let repository_names: Vec<String> =
+                        repository_names.iter().map(|t| t.to_string()).collect();
+                    try_or_error!(rank_mirrors(ctx(), &repository_names))
+                }
+
                 RepositorySubcommand::List => {
-                    try_or_error!(print_repositories(&core_db()))
+                    try_or_error!(print_repositories(&read_only_ctx().core_db))
+                }
+
+                RepositorySubcommand::ListPackages {
+                    repository,
+                    sort,
+                    page,
+                    page_size,
+                } => {
+                    let name = some_or_error!(repository, "Repository name is missing");
+                    try_or_error!(print_repository_packages(
+                        read_only_ctx(),
+                        name,
+                        *sort,
+                        *page,
+                        *page_size
+                    ))
+                }
+
+                RepositorySubcommand::Keys => {
+                    try_or_error!(print_trusted_keys(&read_only_ctx().core_db))
+                }
+
+                RepositorySubcommand::Trust { key_id, public_key } => {
+                    should_print_green_message = true;
+                    let key_id = some_or_error!(key_id, "Key id is missing");
+                    let public_key = some_or_error!(public_key, "Public key is missing");
+                    try_or_error!(trust_signing_key(ctx(), key_id, public_key))
+                }
+
+                RepositorySubcommand::Revoke(key_id) => {
+                    should_print_green_message = true;
+                    let key_id = some_or_error!(key_id, "Key id is missing");
+                    try_or_error!(revoke_signing_key(ctx(), key_id))
                 }
 
                 RepositorySubcommand::Help => {
@@ -167,9 +280,140 @@ fn main() {
                 }
             },
 
+            Command::Db(subcommand) => match subcommand {
+                DbSubcommand::Check {
+                    verify_size,
+                    verify_store,
+                } => {
+                    try_or_error!(check_database(ctx(), *verify_size, *verify_store))
+                }
+
+                DbSubcommand::Status => try_or_error!(print_migration_status(ctx())),
+
+                DbSubcommand::Maintainers => try_or_error!(print_maintainers(read_only_ctx())),
+
+                DbSubcommand::RunPendingScripts => {
+                    should_print_green_message = true;
+                    try_or_error!(run_pending_scripts(&ctx()))
+                }
+
+                DbSubcommand::Rehash { to } => {
+                    try_or_error!(rehash_installed_files(ctx(), to))
+                }
+
+                DbSubcommand::Revert(name) => {
+                    try_or_error!(revert_database_migrations(ctx(), name))
+                }
+
+                DbSubcommand::Help => {
+                    command.print_help();
+                }
+
+                DbSubcommand::None => {
+                    panic!("Invalid command on 'lpm --db'.");
+                }
+            },
+
+            Command::DiskUsage(args) => {
+                if args.print_help {
+                    command.print_help();
+                }
+
+                if let Some(pkg_name) = args.package {
+                    try_or_error!(print_disk_usage(read_only_ctx(), pkg_name, args.stat));
+                }
+            }
+
+            Command::Alternatives(subcommand) => match subcommand {
+                AlternativesSubcommand::List(name) => {
+                    try_or_error!(print_alternatives(read_only_ctx(), *name))
+                }
+
+                AlternativesSubcommand::Set(name, path) => {
+                    should_print_green_message = true;
+                    try_or_error!(set_alternative(ctx(), name, Some(path)))
+                }
+
+                AlternativesSubcommand::Auto(name) => {
+                    should_print_green_message = true;
+                    try_or_error!(set_alternative(ctx(), name, None))
+                }
+
+                AlternativesSubcommand::Help => {
+                    command.print_help();
+                }
+
+                AlternativesSubcommand::None => {
+                    panic!("Invalid command on 'lpm --alternatives'.");
+                }
+            },
+
+            Command::Daemon => try_or_error!(run_daemon(ctx())),
+
+            Command::RpcDaemon(socket_path) => try_or_error!(run_rpc_daemon(
+                ctx(),
+                socket_path.unwrap_or(DEFAULT_RPC_SOCKET_PATH)
+            )),
+
+            Command::Inspect(path) => try_or_error!(print_package_info(path)),
+
+            Command::Info(name) => {
+                try_or_error!(print_installed_package_info(read_only_ctx(), name))
+            }
+
+            Command::CheckUpdates => {
+                if try_or_error!(check_updates(&ctx())) {
+                    std::process::exit(UPDATES_AVAILABLE_EXIT_CODE);
+                }
+            }
+
+            Command::Doctor => {
+                if try_or_error!(run_doctor(&ctx())) {
+                    std::process::exit(1);
+                }
+            }
+
+            Command::AutoUpdate(subcommand) => match subcommand {
+                AutoUpdateSubcommand::Run => try_or_error!(run_auto_update(ctx())),
+
+                AutoUpdateSubcommand::InstallUnits(unit_dir) => {
+                    try_or_error!(install_auto_update_units(*unit_dir))
+                }
+
+                AutoUpdateSubcommand::Help => {
+                    command.print_help();
+                }
+
+                AutoUpdateSubcommand::None => {
+                    panic!("Invalid command on 'lpm --auto-update'.");
+                }
+            },
+
+            Command::Tui => try_or_error!(run_tui(ctx())),
+
+            Command::Shell => try_or_error!(run_shell(ctx())),
+
+            Command::ApplyPlan(path) => {
+                should_print_green_message = true;
+                try_or_error!(apply_plan(&mut ctx(), path));
+            }
+
+            Command::DiffManifest { path, apply } => {
+                let differs = match apply {
+                    true => {
+                        should_print_green_message = true;
+                        try_or_error!(diff_manifest(&mut ctx(), path, true))
+                    }
+                    false => try_or_error!(diff_manifest(&mut read_only_ctx(), path, false)),
+                };
+                if differs {
+                    std::process::exit(1);
+                }
+            }
+
             Command::Help => {
                 should_print_green_message = false;
-                command.print_help();
+                print_general_help();
             }
 
             Command::Version => {
@@ -177,7 +421,7 @@ fn main() {
             }
         });
 
-    if should_print_green_message {
+    if should_print_green_message && cli_parser.print_plan.is_none() {
         logger::success!("Operation successfully completed.");
     }
 }
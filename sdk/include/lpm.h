@@ -0,0 +1,75 @@
+/* C interface of lpm, implemented by `liblpm_sdk.so`. */
+
+#ifndef LPM_H
+#define LPM_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+/* Values of `ResultCode` in `lpm/ehandle/src/lib.rs`, 0 means success. */
+typedef uint16_t lpm_result_code;
+
+/* Handle holding the database connection, see `lpm_open`. */
+typedef struct Lpm Lpm;
+
+typedef struct {
+    char *name;
+    char *version;
+    char *arch;
+    /* NULL for packages that are not slot-aware. */
+    char *slot;
+    int64_t installed_size;
+    bool is_dependency;
+} LpmPackage;
+
+typedef struct {
+    char *name;
+    char *version;
+    char *repository;
+    /* -1 if the index doesn't provide it. */
+    int64_t archive_size;
+    /* -1 if the index doesn't provide it. */
+    int64_t installed_size;
+} LpmAvailablePackage;
+
+typedef struct {
+    LpmPackage *items;
+    size_t len;
+} LpmPackageList;
+
+typedef struct {
+    LpmAvailablePackage *items;
+    size_t len;
+} LpmAvailablePackageList;
+
+/* Returns NULL if the package database can't be opened. */
+Lpm *lpm_open(void);
+void lpm_close(Lpm *lpm);
+
+/* Queries, the lists have to be freed with the matching function. */
+lpm_result_code lpm_list_packages(Lpm *lpm, LpmPackageList **out);
+void lpm_free_package_list(LpmPackageList *list);
+
+lpm_result_code lpm_search(Lpm *lpm, const char *pattern, LpmAvailablePackageList **out);
+void lpm_free_available_package_list(LpmAvailablePackageList *list);
+
+/* Transactions, confirmation prompts are always accepted. */
+lpm_result_code lpm_install(Lpm *lpm, const char *const *pkg_names, size_t num_packages);
+/* Updates all of the installed packages if `num_packages` is 0. */
+lpm_result_code lpm_update(Lpm *lpm, const char *const *pkg_names, size_t num_packages);
+/* Keeps the dependencies that were installed along with the packages. */
+lpm_result_code lpm_delete(Lpm *lpm, const char *const *pkg_names, size_t num_packages);
+
+/* Functions that open their own connection for a single operation. */
+lpm_result_code install_lod_file(const char *pkg_path);
+lpm_result_code update_pkg_from_lod_file(const char *pkg_name, const char *pkg_path);
+lpm_result_code delete_packages(const char *const *pkg_names, size_t num_packages);
+
+void success_log(const char *msg);
+void info_log(const char *msg);
+void warning_log(const char *msg);
+void error_log(const char *msg);
+void debug_log(const char *msg);
+
+#endif
@@ -0,0 +1,250 @@
+//! C ABI of `core::Lpm`; see `include/lpm.h` for the declarations.
+//!
+//! Unlike the functions in `high_level`, these share one handle (and so one
+//! database connection) across calls, and return the queried data instead of
+//! only logging it.
+
+use core::{AvailablePackage, Lpm, PackageInfo};
+use ehandle::ResultCode;
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use std::ptr;
+
+#[repr(C)]
+pub struct LpmPackage {
+    name: *mut c_char,
+    version: *mut c_char,
+    arch: *mut c_char,
+    /// NULL for packages that are not slot-aware.
+    slot: *mut c_char,
+    installed_size: i64,
+    is_dependency: bool,
+}
+
+#[repr(C)]
+pub struct LpmAvailablePackage {
+    name: *mut c_char,
+    version: *mut c_char,
+    repository: *mut c_char,
+    /// -1 if the index doesn't provide it.
+    archive_size: i64,
+    /// -1 if the index doesn't provide it.
+    installed_size: i64,
+}
+
+/// Array returned by the query functions, freed with the matching
+/// `lpm_free_*` function.
+#[repr(C)]
+pub struct LpmList<T> {
+    items: *mut T,
+    len: usize,
+}
+
+fn to_c_string(value: &str) -> *mut c_char {
+    // Values come from the database, which can't store interior NULs anyway.
+    CString::new(value).unwrap_or_default().into_raw()
+}
+
+unsafe fn free_c_string(value: *mut c_char) {
+    if !value.is_null() {
+        drop(CString::from_raw(value));
+    }
+}
+
+fn into_c_list<T>(items: Vec<T>) -> *mut LpmList<T> {
+    let mut items = items.into_boxed_slice();
+    let list = LpmList {
+        items: items.as_mut_ptr(),
+        len: items.len(),
+    };
+    std::mem::forget(items);
+
+    Box::into_raw(Box::new(list))
+}
+
+unsafe fn from_c_list<T>(list: *mut LpmList<T>) -> Vec<T> {
+    let list = Box::from_raw(list);
+    Vec::from_raw_parts(list.items, list.len, list.len)
+}
+
+unsafe fn str_array<'a>(
+    values: *const *const c_char,
+    len: usize,
+) -> Result<Vec<&'a str>, ResultCode> {
+    (0..len)
+        .map(|i| {
+            CStr::from_ptr(*values.add(i)).to_str().map_err(|e| {
+                logger::error!("{}", e);
+                ResultCode::Str_Utf8Error
+            })
+        })
+        .collect()
+}
+
+macro_rules! try_or_return {
+    ($result: expr) => {
+        match $result {
+            Ok(val) => val,
+            Err(err) => {
+                logger::error!("{:?}", err);
+                return err.result_code;
+            }
+        }
+    };
+}
+
+/// Opens a handle on the package database of the running system, NULL on failure.
+#[no_mangle]
+extern "C" fn lpm_open() -> *mut Lpm {
+    match Lpm::new() {
+        Ok(lpm) => Box::into_raw(Box::new(lpm)),
+        Err(err) => {
+            logger::error!("{:?}", err);
+            ptr::null_mut()
+        }
+    }
+}
+
+#[no_mangle]
+extern "C" fn lpm_close(lpm: *mut Lpm) {
+    if !lpm.is_null() {
+        drop(unsafe { Box::from_raw(lpm) });
+    }
+}
+
+/// Stores the installed packages in `out`.
+#[no_mangle]
+extern "C" fn lpm_list_packages(lpm: *mut Lpm, out: *mut *mut LpmList<LpmPackage>) -> ResultCode {
+    let lpm = unsafe { &*lpm };
+    let pkgs = try_or_return!(lpm.list());
+
+    let items = pkgs
+        .iter()
+        .map(|pkg: &PackageInfo| LpmPackage {
+            name: to_c_string(&pkg.name),
+            version: to_c_string(&pkg.version),
+            arch: to_c_string(&pkg.arch),
+            slot: pkg.slot.as_deref().map_or(ptr::null_mut(), to_c_string),
+            installed_size: pkg.installed_size,
+            is_dependency: pkg.is_dependency,
+        })
+        .collect();
+
+    unsafe { *out = into_c_list(items) };
+    ResultCode::Ok
+}
+
+#[no_mangle]
+extern "C" fn lpm_free_package_list(list: *mut LpmList<LpmPackage>) {
+    if list.is_null() {
+        return;
+    }
+
+    for pkg in unsafe { from_c_list(list) } {
+        unsafe {
+            free_c_string(pkg.name);
+            free_c_string(pkg.version);
+            free_c_string(pkg.arch);
+            free_c_string(pkg.slot);
+        }
+    }
+}
+
+/// Stores the repository packages whose name contains `pattern` in `out`.
+#[no_mangle]
+extern "C" fn lpm_search(
+    lpm: *mut Lpm,
+    pattern: *const c_char,
+    out: *mut *mut LpmList<LpmAvailablePackage>,
+) -> ResultCode {
+    let lpm = unsafe { &*lpm };
+    let pattern = match unsafe { CStr::from_ptr(pattern) }.to_str() {
+        Ok(val) => val,
+        Err(err) => {
+            logger::error!("{}", err);
+            return ResultCode::Str_Utf8Error;
+        }
+    };
+    let pkgs = try_or_return!(lpm.search(pattern));
+
+    let items = pkgs
+        .iter()
+        .map(|pkg: &AvailablePackage| LpmAvailablePackage {
+            name: to_c_string(&pkg.name),
+            version: to_c_string(&pkg.version),
+            repository: to_c_string(&pkg.repository),
+            archive_size: pkg.archive_size.unwrap_or(-1),
+            installed_size: pkg.installed_size.unwrap_or(-1),
+        })
+        .collect();
+
+    unsafe { *out = into_c_list(items) };
+    ResultCode::Ok
+}
+
+#[no_mangle]
+extern "C" fn lpm_free_available_package_list(list: *mut LpmList<LpmAvailablePackage>) {
+    if list.is_null() {
+        return;
+    }
+
+    for pkg in unsafe { from_c_list(list) } {
+        unsafe {
+            free_c_string(pkg.name);
+            free_c_string(pkg.version);
+            free_c_string(pkg.repository);
+        }
+    }
+}
+
+/// Installs `num_packages` packages from the repositories in one transaction.
+#[no_mangle]
+extern "C" fn lpm_install(
+    lpm: *mut Lpm,
+    pkg_names: *const *const c_char,
+    num_packages: usize,
+) -> ResultCode {
+    let lpm = unsafe { &mut *lpm };
+    let pkg_names = match unsafe { str_array(pkg_names, num_packages) } {
+        Ok(t) => t,
+        Err(result_code) => return result_code,
+    };
+
+    try_or_return!(lpm.install(&pkg_names));
+    ResultCode::Ok
+}
+
+/// Updates `num_packages` packages, all of the installed ones if it's 0.
+#[no_mangle]
+extern "C" fn lpm_update(
+    lpm: *mut Lpm,
+    pkg_names: *const *const c_char,
+    num_packages: usize,
+) -> ResultCode {
+    let lpm = unsafe { &mut *lpm };
+    let pkg_names = match unsafe { str_array(pkg_names, num_packages) } {
+        Ok(t) => t,
+        Err(result_code) => return result_code,
+    };
+
+    try_or_return!(lpm.update(&pkg_names));
+    ResultCode::Ok
+}
+
+/// Deletes `num_packages` packages in one transaction, keeping the dependencies
+/// that were installed along with them.
+#[no_mangle]
+extern "C" fn lpm_delete(
+    lpm: *mut Lpm,
+    pkg_names: *const *const c_char,
+    num_packages: usize,
+) -> ResultCode {
+    let lpm = unsafe { &mut *lpm };
+    let pkg_names = match unsafe { str_array(pkg_names, num_packages) } {
+        Ok(t) => t,
+        Err(result_code) => return result_code,
+    };
+
+    try_or_return!(lpm.delete(&pkg_names));
+    ResultCode::Ok
+}
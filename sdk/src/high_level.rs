@@ -1,6 +1,11 @@
 use cli_parser::{DeleteArgs, InstallArgs};
 use ehandle::ResultCode;
-use std::{collections::HashSet, ffi::CStr};
+use std::{
+    collections::HashSet,
+    ffi::{CStr, OsStr},
+    os::unix::ffi::OsStrExt,
+    path::Path,
+};
 
 #[no_mangle]
 extern "C" fn install_lod_file(pkg_path: *const std::os::raw::c_char) -> ResultCode {
@@ -14,7 +19,7 @@ extern "C" fn install_lod_file(pkg_path: *const std::os::raw::c_char) -> ResultC
         }
     };
 
-    let ctx = match core::Ctx::new() {
+    let mut ctx = match core::Ctx::new() {
         Ok(t) => t,
         Err(err) => {
             logger::error!("{:?}", err);
@@ -23,11 +28,19 @@ extern "C" fn install_lod_file(pkg_path: *const std::os::raw::c_char) -> ResultC
     };
 
     if let Err(err) = core::install_package(
-        ctx,
+        &mut ctx,
         &InstallArgs {
             packages: HashSet::from([pkg_path]),
             from_local_package: true,
             print_help: false,
+            root: None,
+            target_arch: None,
+            from_file: None,
+            exclude: Vec::new(),
+            no_docs: false,
+            select_locales: None,
+            strict: false,
+            allow_interpreters: Vec::new(),
         },
     ) {
         logger::error!("{:?}", err);
@@ -52,15 +65,10 @@ extern "C" fn update_pkg_from_lod_file(
         }
     };
 
-    let pkg_path = unsafe {
-        match CStr::from_ptr(pkg_path).to_str() {
-            Ok(val) => val,
-            Err(err) => {
-                logger::error!("{}", err);
-                return ResultCode::Str_Utf8Error;
-            }
-        }
-    };
+    // Paths don't have to be valid UTF-8.
+    let pkg_path = Path::new(OsStr::from_bytes(
+        unsafe { CStr::from_ptr(pkg_path) }.to_bytes(),
+    ));
 
     let ctx = match core::Ctx::new() {
         Ok(t) => t,
@@ -70,7 +78,7 @@ extern "C" fn update_pkg_from_lod_file(
         }
     };
 
-    if let Err(err) = core::update_pkg_from_lod_file(ctx, pkg_name, pkg_path) {
+    if let Err(err) = core::update_pkg_from_lod_file(&ctx, pkg_name, pkg_path) {
         logger::error!("{:?}", err);
         return err.result_code;
     }
@@ -110,10 +118,15 @@ extern "C" fn delete_packages(
     };
 
     if let Err(err) = core::delete_packages(
-        ctx,
+        &ctx,
         &DeleteArgs {
             packages: pkg_names,
             print_help: false,
+            from_file: None,
+            force_essential: false,
+            recursive: false,
+            cascade: false,
+            purge: false,
         },
     ) {
         logger::error!("{:?}", err);
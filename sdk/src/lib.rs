@@ -1,4 +1,5 @@
 #![allow(unsafe_code)]
 
+mod api;
 mod high_level;
 mod log;